@@ -0,0 +1,56 @@
+//! Embeds build provenance for `--version --verbose` and `jwt-check
+//! provenance`. Nothing time- or path-dependent is recorded, so two builds of
+//! the same commit with the same toolchain embed the same values.
+
+use std::path::Path;
+use std::process::Command;
+
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let out = Command::new(program).args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+fn main() {
+    let commit = output("git", &["rev-parse", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    let dirty = output("git", &["status", "--porcelain", "--untracked-files=no"]).map_or(
+        "unknown",
+        |status| if status.is_empty() { "false" } else { "true" },
+    );
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = output(&rustc, &["-V"]).unwrap_or_else(|| "unknown".to_string());
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(name, _)| {
+            name.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+
+    let env = |name: &str, value: &str| println!("cargo:rustc-env=JWT_CHECK_{}={}", name, value);
+    env("GIT_COMMIT", &commit);
+    env("GIT_DIRTY", dirty);
+    env("RUSTC_VERSION", &rustc_version);
+    env("TARGET", &std::env::var("TARGET").unwrap_or_default());
+    env("PROFILE", &std::env::var("PROFILE").unwrap_or_default());
+    env("FEATURES", &features.join(","));
+    env(
+        "BUILDER_ID",
+        &std::env::var("JWT_CHECK_BUILDER_ID").unwrap_or_else(|_| "unknown".to_string()),
+    );
+
+    println!("cargo:rerun-if-env-changed=JWT_CHECK_BUILDER_ID");
+    println!("cargo:rerun-if-env-changed=JWT_CHECK_RELEASE_KEY");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    if let Some(reference) = std::fs::read_to_string(".git/HEAD")
+        .ok()
+        .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_string()))
+    {
+        if Path::new(".git").join(&reference).exists() {
+            println!("cargo:rerun-if-changed=.git/{}", reference);
+        }
+    }
+}
@@ -0,0 +1,446 @@
+//! `jwt-check agility`: which signature algorithms and key sizes an
+//! issuer's JWKS offers, measured against a best-practice profile, for
+//! periodic posture reviews.
+//!
+//! Each key is described (type, size or curve, the algorithms it can sign
+//! with) and flagged where it falls short: RSA under 2048 bits, ES256K
+//! outside systems that expect it, symmetric or private key material in a
+//! published set, keys that may sign with any algorithm of their type, and,
+//! under the `fapi2` profile, algorithms FAPI 2.0 doesn't allow.
+
+use crate::audit::Severity;
+use crate::JWTError;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// RSA moduli shorter than this are flagged.
+pub const MIN_RSA_BITS: usize = 2048;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    /// RFC 8725's current best practice.
+    Baseline,
+    /// FAPI 2.0 Security Profile, 5.4: PS256, ES256 or EdDSA only.
+    Fapi2,
+}
+
+impl Profile {
+    pub const KINDS: [(&'static str, Profile); 2] =
+        [("baseline", Profile::Baseline), ("fapi2", Profile::Fapi2)];
+
+    fn allows(self, alg: &str) -> bool {
+        match self {
+            Profile::Baseline => true,
+            Profile::Fapi2 => ["PS256", "ES256", "EdDSA"].contains(&alg),
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = JWTError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Profile::KINDS
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, profile)| *profile)
+            .ok_or_else(|| {
+                JWTError::InvalidArgumentError(
+                    format!(
+                        "unknown agility profile `{}`; expected baseline or fapi2",
+                        s
+                    )
+                    .into(),
+                )
+            })
+    }
+}
+
+/// One key of the set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Key {
+    pub kid: Option<String>,
+    pub kty: String,
+    /// `2048 bits`, `P-256`, ...
+    pub strength: Option<String>,
+    /// What the key can sign with: its `alg`, or every algorithm its type
+    /// allows when it doesn't pin one.
+    pub algorithms: Vec<String>,
+    pub findings: Vec<(Severity, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub profile: Profile,
+    pub keys: Vec<Key>,
+    /// Findings about the set as a whole.
+    pub findings: Vec<(Severity, String)>,
+}
+
+impl Report {
+    /// Every algorithm some key can sign with.
+    pub fn algorithms(&self) -> BTreeSet<&str> {
+        self.keys
+            .iter()
+            .flat_map(|key| key.algorithms.iter().map(String::as_str))
+            .collect()
+    }
+
+    pub fn high(&self) -> usize {
+        self.findings
+            .iter()
+            .chain(self.keys.iter().flat_map(|key| key.findings.iter()))
+            .filter(|(severity, _)| *severity == Severity::High)
+            .count()
+    }
+}
+
+fn bits(encoded: &str) -> Option<usize> {
+    let bytes = base64::decode_config(encoded, base64::URL_SAFE_NO_PAD).ok()?;
+    let first = bytes.iter().position(|b| *b != 0)?;
+    let lead = bytes.get(first)?.leading_zeros() as usize;
+    Some((bytes.len() - first) * 8 - lead)
+}
+
+/// The algorithms a key of this type and curve could sign with.
+fn algorithms_for(kty: &str, crv: Option<&str>) -> Vec<&'static str> {
+    match (kty, crv) {
+        ("RSA", _) => vec!["PS256", "PS384", "PS512", "RS256", "RS384", "RS512"],
+        ("EC", Some("P-256")) => vec!["ES256"],
+        ("EC", Some("P-384")) => vec!["ES384"],
+        ("EC", Some("P-521")) => vec!["ES512"],
+        ("EC", Some("secp256k1")) => vec!["ES256K"],
+        ("OKP", Some("Ed25519")) | ("OKP", Some("Ed448")) => vec!["EdDSA"],
+        ("oct", _) => vec!["HS256", "HS384", "HS512"],
+        _ => Vec::new(),
+    }
+}
+
+fn check_key(jwk: &Value, profile: Profile, expect_es256k: bool) -> Key {
+    let text = |name: &str| jwk.get(name).and_then(Value::as_str);
+    let kty = text("kty").unwrap_or_default().to_string();
+    let crv = text("crv");
+    let mut findings = Vec::new();
+    let strength = match kty.as_str() {
+        "RSA" => match text("n").and_then(bits) {
+            Some(size) => {
+                if size < MIN_RSA_BITS {
+                    findings.push((
+                        Severity::High,
+                        format!(
+                            "RSA-{} is deprecated; use at least {} bits (NIST SP 800-131A)",
+                            size, MIN_RSA_BITS
+                        ),
+                    ));
+                }
+                if text("e").is_some_and(|e| e != "AQAB") {
+                    findings.push((Severity::Low, "RSA exponent is not 65537".to_string()));
+                }
+                Some(format!("{} bits", size))
+            }
+            None => {
+                findings.push((Severity::High, "RSA key has no valid `n`".to_string()));
+                None
+            }
+        },
+        "oct" => {
+            findings.push((
+                Severity::High,
+                "symmetric key published in a JWKS: anyone who fetches it can mint tokens"
+                    .to_string(),
+            ));
+            text("k")
+                .and_then(|k| base64::decode_config(k, base64::URL_SAFE_NO_PAD).ok())
+                .map(|k| format!("{} bits", k.len() * 8))
+        }
+        _ => crv.map(str::to_string),
+    };
+    if kty == "EC" && crv == Some("secp256k1") && !expect_es256k {
+        findings.push((
+            Severity::Medium,
+            "secp256k1 (ES256K) is unexpected outside blockchain-adjacent systems, and few verifiers support it".to_string(),
+        ));
+    }
+    let private: Vec<&str> = ["d", "p", "q", "dp", "dq", "qi"]
+        .iter()
+        .copied()
+        .filter(|name| jwk.get(*name).is_some())
+        .collect();
+    if !private.is_empty() && kty != "oct" {
+        findings.push((
+            Severity::High,
+            format!("private key material published: {}", private.join(", ")),
+        ));
+    }
+    let kid = text("kid").map(str::to_string);
+    if text("use") == Some("enc") {
+        return Key {
+            kid,
+            kty,
+            strength,
+            algorithms: Vec::new(),
+            findings,
+        };
+    }
+    let possible = algorithms_for(&kty, crv);
+    if possible.is_empty() {
+        findings.push((
+            Severity::Medium,
+            format!(
+                "unknown key type {}{}",
+                if kty.is_empty() { "(none)" } else { &kty },
+                crv.map(|crv| format!(" {}", crv)).unwrap_or_default()
+            ),
+        ));
+    }
+    let algorithms: Vec<String> = match text("alg") {
+        Some(alg) => {
+            if !possible.is_empty() && !possible.contains(&alg) {
+                findings.push((
+                    Severity::High,
+                    format!("alg {} does not fit a {} key", alg, kty),
+                ));
+            }
+            vec![alg.to_string()]
+        }
+        None => {
+            if possible.len() > 1 {
+                findings.push((
+                    Severity::Low,
+                    format!(
+                        "no alg: the key may be used with any of {}",
+                        possible.join(", ")
+                    ),
+                ));
+            }
+            possible.iter().map(|alg| alg.to_string()).collect()
+        }
+    };
+    let disallowed: Vec<&str> = algorithms
+        .iter()
+        .map(String::as_str)
+        .filter(|alg| !profile.allows(alg))
+        .collect();
+    if !disallowed.is_empty() {
+        findings.push((
+            Severity::High,
+            format!("{} not allowed by the fapi2 profile", disallowed.join(", ")),
+        ));
+    }
+    Key {
+        kid,
+        kty,
+        strength,
+        algorithms,
+        findings,
+    }
+}
+
+/// Reviews `set`, a JWKS document.
+pub fn review(set: &Value, profile: Profile, expect_es256k: bool) -> Result<Report, JWTError> {
+    let jwks = set
+        .get("keys")
+        .and_then(Value::as_array)
+        .ok_or_else(|| JWTError::KeyError("JWKS has no `keys` array".into()))?;
+    let keys: Vec<Key> = jwks
+        .iter()
+        .map(|jwk| check_key(jwk, profile, expect_es256k))
+        .collect();
+    let mut findings = Vec::new();
+    if keys.is_empty() {
+        findings.push((Severity::High, "the JWKS has no keys".to_string()));
+    }
+    if keys.len() > 1 && keys.iter().any(|key| key.kid.is_none()) {
+        findings.push((
+            Severity::Low,
+            "keys without a kid: verifiers must try each key in turn".to_string(),
+        ));
+    }
+    let mut seen = BTreeSet::new();
+    for kid in keys.iter().filter_map(|key| key.kid.as_deref()) {
+        if !seen.insert(kid) {
+            findings.push((
+                Severity::Medium,
+                format!("kid {} is used by more than one key", kid),
+            ));
+        }
+    }
+    Ok(Report {
+        profile,
+        keys,
+        findings,
+    })
+}
+
+pub fn render_text(report: &Report) -> String {
+    let algorithms: Vec<&str> = report.algorithms().into_iter().collect();
+    let mut out = format!(
+        "{} key(s); algorithms: {}",
+        report.keys.len(),
+        if algorithms.is_empty() {
+            "none".to_string()
+        } else {
+            algorithms.join(", ")
+        }
+    );
+    for (severity, message) in &report.findings {
+        out.push_str(&format!("\n  [{}] {}", severity, message));
+    }
+    for key in &report.keys {
+        out.push_str(&format!(
+            "\nkid {}: {}{}{}",
+            key.kid.as_deref().unwrap_or("(none)"),
+            key.kty,
+            key.strength
+                .as_ref()
+                .map(|strength| format!(" {}", strength))
+                .unwrap_or_default(),
+            if key.algorithms.is_empty() {
+                ", not for signatures".to_string()
+            } else {
+                format!(", {}", key.algorithms.join(" "))
+            }
+        ));
+        for (severity, message) in &key.findings {
+            out.push_str(&format!("\n  [{}] {}", severity, message));
+        }
+    }
+    out
+}
+
+pub fn to_json(report: &Report) -> Value {
+    let findings = |findings: &[(Severity, String)]| -> Vec<Value> {
+        findings
+            .iter()
+            .map(|(severity, message)| {
+                serde_json::json!({"severity": severity.to_string(), "message": message})
+            })
+            .collect()
+    };
+    serde_json::json!({
+        "profile": Profile::KINDS
+            .iter()
+            .find(|(_, profile)| *profile == report.profile)
+            .map(|(name, _)| *name),
+        "algorithms": report.algorithms(),
+        "findings": findings(&report.findings),
+        "keys": report.keys.iter().map(|key| serde_json::json!({
+            "kid": key.kid,
+            "kty": key.kty,
+            "strength": key.strength,
+            "algorithms": key.algorithms,
+            "findings": findings(&key.findings),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A base64url modulus of `bits` bits.
+    fn modulus(bits: usize) -> String {
+        base64::encode_config(vec![0xff; bits / 8], base64::URL_SAFE_NO_PAD)
+    }
+
+    #[test]
+    fn review_test() {
+        let set = json!({"keys": [
+            {"kty": "RSA", "kid": "old", "alg": "RS256", "n": modulus(1024), "e": "AQAB"},
+            {"kty": "RSA", "kid": "new", "alg": "PS256", "n": modulus(2048), "e": "AQAB"},
+            {"kty": "EC", "kid": "ec", "crv": "P-256", "x": "AA", "y": "AA"},
+            {"kty": "EC", "kid": "k1", "crv": "secp256k1", "x": "AA", "y": "AA"},
+            {"kty": "RSA", "kid": "enc", "use": "enc", "n": modulus(2048), "e": "AQAB"},
+        ]});
+        let report = review(&set, Profile::Baseline, false).unwrap();
+        assert_eq!(
+            report.algorithms().into_iter().collect::<Vec<_>>(),
+            ["ES256", "ES256K", "PS256", "RS256"]
+        );
+        assert_eq!(report.keys[0].strength.as_deref(), Some("1024 bits"));
+        assert_eq!(
+            report.keys[0].findings,
+            [(
+                Severity::High,
+                "RSA-1024 is deprecated; use at least 2048 bits (NIST SP 800-131A)".to_string()
+            )]
+        );
+        assert!(report.keys[1].findings.is_empty());
+        assert!(report.keys[2].findings.is_empty());
+        assert_eq!(report.keys[3].findings[0].0, Severity::Medium);
+        assert!(report.keys[4].algorithms.is_empty());
+        assert!(report.keys[4].findings.is_empty());
+        assert_eq!(report.high(), 1);
+        assert_eq!(
+            review(&set, Profile::Baseline, true).unwrap().keys[3].findings,
+            []
+        );
+
+        let fapi = review(&set, Profile::Fapi2, false).unwrap();
+        assert_eq!(fapi.high(), 3);
+        assert_eq!(
+            fapi.keys[0].findings[1].1,
+            "RS256 not allowed by the fapi2 profile"
+        );
+
+        let text = render_text(&report);
+        assert!(text.starts_with(
+            "5 key(s); algorithms: ES256, ES256K, PS256, RS256\n\
+             kid old: RSA 1024 bits, RS256\n  [high] RSA-1024 is deprecated"
+        ));
+        assert!(text.contains("kid enc: RSA 2048 bits, not for signatures"));
+        assert_eq!(to_json(&fapi)["profile"], "fapi2");
+        assert_eq!(
+            to_json(&report)["keys"][3]["findings"][0]["severity"],
+            "medium"
+        );
+
+        // what must never be in a published set
+        let leaky = json!({"keys": [
+            {"kty": "oct", "k": "c2VjcmV0"},
+            {"kty": "RSA", "alg": "ES256", "n": modulus(2048), "e": "Aw", "d": "AA", "p": "AA"},
+            {"kty": "RSA", "kid": "x", "n": "", "e": "AQAB"},
+            {"kty": "OKP", "kid": "x", "crv": "X25519"},
+        ]});
+        let report = review(&leaky, Profile::Baseline, false).unwrap();
+        let all: Vec<&str> = report
+            .findings
+            .iter()
+            .chain(report.keys.iter().flat_map(|key| key.findings.iter()))
+            .map(|(_, message)| message.as_str())
+            .collect();
+        assert_eq!(
+            all,
+            [
+                "keys without a kid: verifiers must try each key in turn",
+                "kid x is used by more than one key",
+                "symmetric key published in a JWKS: anyone who fetches it can mint tokens",
+                "no alg: the key may be used with any of HS256, HS384, HS512",
+                "RSA exponent is not 65537",
+                "private key material published: d, p",
+                "alg ES256 does not fit a RSA key",
+                "RSA key has no valid `n`",
+                "no alg: the key may be used with any of PS256, PS384, PS512, RS256, RS384, RS512",
+                "unknown key type OKP X25519",
+            ]
+        );
+        assert_eq!(report.keys[0].strength.as_deref(), Some("48 bits"));
+        assert_eq!(
+            review(&json!({"keys": []}), Profile::Baseline, false)
+                .unwrap()
+                .high(),
+            1
+        );
+        assert!(matches!(
+            review(&json!({}), Profile::Baseline, false),
+            Err(JWTError::KeyError(e)) if e == "JWKS has no `keys` array"
+        ));
+        assert!(matches!(
+            "fapi1".parse::<Profile>(),
+            Err(JWTError::InvalidArgumentError(e))
+                if e == "unknown agility profile `fapi1`; expected baseline or fapi2"
+        ));
+    }
+}
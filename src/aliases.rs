@@ -0,0 +1,171 @@
+//! `--aliases`: friendly names for vendor claim URIs, such as `roles` for
+//! `http://schemas.microsoft.com/ws/2008/06/identity/claims/role`.
+//!
+//! Aliasing renames top-level payload claims before anything else looks at
+//! them, so the friendly name is what the output shows and what `--claim`,
+//! `--map`, `--routes` and `--claim-decrypt` match. (URI claim names cannot
+//! be written as dot paths at all, since they contain dots.) A claim is not
+//! renamed onto a name the payload already has with a different value.
+
+use crate::JWTError;
+use serde_json::Value;
+
+/// The WS-Federation and Azure AD claim URIs, under the short names their
+/// JWT equivalents use.
+pub const BUILTIN: &[(&str, &str)] = &[
+    (
+        "name",
+        "http://schemas.xmlsoap.org/ws/2005/05/identity/claims/name",
+    ),
+    (
+        "email",
+        "http://schemas.xmlsoap.org/ws/2005/05/identity/claims/emailaddress",
+    ),
+    (
+        "given_name",
+        "http://schemas.xmlsoap.org/ws/2005/05/identity/claims/givenname",
+    ),
+    (
+        "family_name",
+        "http://schemas.xmlsoap.org/ws/2005/05/identity/claims/surname",
+    ),
+    (
+        "upn",
+        "http://schemas.xmlsoap.org/ws/2005/05/identity/claims/upn",
+    ),
+    (
+        "nameid",
+        "http://schemas.xmlsoap.org/ws/2005/05/identity/claims/nameidentifier",
+    ),
+    (
+        "roles",
+        "http://schemas.microsoft.com/ws/2008/06/identity/claims/role",
+    ),
+    (
+        "groups",
+        "http://schemas.microsoft.com/ws/2008/06/identity/claims/groups",
+    ),
+    (
+        "oid",
+        "http://schemas.microsoft.com/identity/claims/objectidentifier",
+    ),
+    (
+        "tid",
+        "http://schemas.microsoft.com/identity/claims/tenantid",
+    ),
+    ("scp", "http://schemas.microsoft.com/identity/claims/scope"),
+    (
+        "idp",
+        "http://schemas.microsoft.com/identity/claims/identityprovider",
+    ),
+    (
+        "amr",
+        "http://schemas.microsoft.com/claims/authnmethodsreferences",
+    ),
+];
+
+/// Friendly names and the claim names they stand for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aliases {
+    pairs: Vec<(String, String)>,
+}
+
+impl Aliases {
+    pub fn builtin() -> Aliases {
+        Aliases {
+            pairs: BUILTIN
+                .iter()
+                .map(|(friendly, original)| (friendly.to_string(), original.to_string()))
+                .collect(),
+        }
+    }
+
+    /// Reads a YAML mapping of friendly names to claim names.
+    pub fn from_config(config: &Value) -> Result<Aliases, JWTError> {
+        let object = config.as_object().ok_or_else(|| {
+            JWTError::ConfigError("aliases must be a mapping of friendly names to claims".into())
+        })?;
+        let pairs = object
+            .iter()
+            .map(|(friendly, original)| match original {
+                Value::String(original) => Ok((friendly.clone(), original.clone())),
+                _ => Err(JWTError::ConfigError(
+                    format!("alias `{}` must name a claim as a string", friendly).into(),
+                )),
+            })
+            .collect::<Result<_, JWTError>>()?;
+        Ok(Aliases { pairs })
+    }
+
+    /// Renames the aliased claims in `payload`, returning a warning for each
+    /// one left alone because its friendly name holds something else.
+    pub fn apply(&self, payload: &mut Value) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let object = match payload.as_object_mut() {
+            Some(object) => object,
+            None => return warnings,
+        };
+        for (friendly, original) in &self.pairs {
+            let value = match object.get(original) {
+                Some(value) => value,
+                None => continue,
+            };
+            match object.get(friendly) {
+                Some(existing) if existing != value => {
+                    warnings.push(format!(
+                        "{} not shown as {}: the token already has a different {}",
+                        original, friendly, friendly
+                    ));
+                    continue;
+                }
+                _ => (),
+            }
+            if let Some(value) = object.remove(original) {
+                object.insert(friendly.clone(), value);
+            }
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn renames_aliased_claims_test() {
+        let role = "http://schemas.microsoft.com/ws/2008/06/identity/claims/role";
+        let name = "http://schemas.xmlsoap.org/ws/2005/05/identity/claims/name";
+        let mut payload = json!({role: ["admin"], name: "alice", "name": "Alice", "sub": "1"});
+        let warnings = Aliases::builtin().apply(&mut payload);
+        assert_eq!(
+            payload,
+            json!({"roles": ["admin"], name: "alice", "name": "Alice", "sub": "1"})
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with(name));
+
+        let custom = crate::yaml::parse("tier: urn:acme:claims:tier\n").unwrap();
+        let mut payload = json!({"urn:acme:claims:tier": "gold"});
+        assert!(Aliases::from_config(&custom)
+            .unwrap()
+            .apply(&mut payload)
+            .is_empty());
+        assert_eq!(payload, json!({"tier": "gold"}));
+        assert!(Aliases::from_config(&json!(["tier"])).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_config_test() {
+        let error_of = |config: Value| error_message!(ConfigError, Aliases::from_config(&config));
+        assert_eq!(
+            error_of(json!(["tier"])),
+            "aliases must be a mapping of friendly names to claims"
+        );
+        assert_eq!(
+            error_of(json!({"tier": ["urn:acme:claims:tier"]})),
+            "alias `tier` must name a claim as a string"
+        );
+    }
+}
@@ -0,0 +1,307 @@
+//! Outliers among a batch's tokens, flagged alongside the `--stats`
+//! histogram.
+//!
+//! Each token is compared with the other tokens of its issuer, once the
+//! issuer has at least [`MIN_TOKENS`] of them: a lifetime (`exp - iat`) more
+//! than [`LIFETIME_FACTOR`] times longer or shorter than the issuer's
+//! median, an `alg` or `aud` that at most [`RARE`] of the issuer's tokens
+//! share, and claims that are rare for the issuer, or missing when nearly
+//! all of its tokens have them.
+
+use crate::humanize_seconds;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Fewer tokens than this make no norm to deviate from.
+pub const MIN_TOKENS: usize = 10;
+pub const LIFETIME_FACTOR: i64 = 4;
+/// The share of an issuer's tokens, at most, for a value to be rare.
+pub const RARE: f64 = 0.05;
+
+/// One token's departure from its issuer's norm.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    pub line: usize,
+    pub issuer: String,
+    /// `lifetime`, `alg`, `aud` or `claims`.
+    pub kind: &'static str,
+    pub message: String,
+}
+
+/// One decoded token of the batch, timestamps already in seconds.
+pub struct Sample<'a> {
+    pub line: usize,
+    pub header: &'a Value,
+    pub payload: &'a Value,
+}
+
+fn text(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::String(text) => Some(text.clone()),
+        Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+fn lifetime(payload: &Value) -> Option<i64> {
+    let claim = |name: &str| payload.get(name).and_then(Value::as_i64);
+    claim("exp")?.checked_sub(claim("iat")?)
+}
+
+/// The values in `counts` that at most [`RARE`] of `total` tokens share.
+fn rare(counts: &BTreeMap<String, usize>, total: usize) -> BTreeSet<String> {
+    counts
+        .iter()
+        .filter(|(_, count)| (**count as f64) <= RARE * total as f64)
+        .map(|(value, _)| value.clone())
+        .collect()
+}
+
+fn tally(values: impl Iterator<Item = Option<String>>) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for value in values.flatten() {
+        *counts.entry(value).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Every anomaly in `samples`, by line.
+pub fn detect(samples: &[Sample]) -> Vec<Anomaly> {
+    let mut by_issuer: BTreeMap<String, Vec<&Sample>> = BTreeMap::new();
+    for sample in samples {
+        let issuer = text(sample.payload.get("iss")).unwrap_or_else(|| "(no iss)".to_string());
+        by_issuer.entry(issuer).or_default().push(sample);
+    }
+    let mut anomalies = Vec::new();
+    for (issuer, tokens) in by_issuer {
+        let total = tokens.len();
+        if total < MIN_TOKENS {
+            continue;
+        }
+        let mut flag = |line: usize, kind: &'static str, message: String| {
+            anomalies.push(Anomaly {
+                line,
+                issuer: issuer.clone(),
+                kind,
+                message,
+            })
+        };
+
+        let mut lifetimes: Vec<i64> = tokens.iter().filter_map(|t| lifetime(t.payload)).collect();
+        lifetimes.sort_unstable();
+        let median = lifetimes
+            .get(lifetimes.len() / 2)
+            .copied()
+            .filter(|m| *m > 0);
+        if let Some(median) = median.filter(|_| lifetimes.len() >= MIN_TOKENS) {
+            for token in &tokens {
+                match lifetime(token.payload) {
+                    Some(life)
+                        if life > median.saturating_mul(LIFETIME_FACTOR)
+                            || life.saturating_mul(LIFETIME_FACTOR) < median =>
+                    {
+                        flag(
+                            token.line,
+                            "lifetime",
+                            format!(
+                                "lives {}, the issuer's median is {}",
+                                humanize_seconds(life),
+                                humanize_seconds(median)
+                            ),
+                        )
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let algs = tally(tokens.iter().map(|t| text(t.header.get("alg"))));
+        let rare_algs = rare(&algs, total);
+        let audiences = |token: &Sample| -> Vec<String> {
+            match token.payload.get("aud") {
+                Some(Value::Array(auds)) => auds.iter().filter_map(|aud| text(Some(aud))).collect(),
+                aud => text(aud).into_iter().collect(),
+            }
+        };
+        let auds = tally(
+            tokens
+                .iter()
+                .flat_map(|t| audiences(t).into_iter().map(Some)),
+        );
+        let rare_auds = rare(&auds, total);
+        let names = |token: &Sample| -> Vec<String> {
+            token
+                .payload
+                .as_object()
+                .map(|claims| claims.keys().cloned().collect())
+                .unwrap_or_default()
+        };
+        let claims = tally(tokens.iter().flat_map(|t| names(t).into_iter().map(Some)));
+        let rare_claims = rare(&claims, total);
+        let usual_claims: BTreeSet<&String> = claims
+            .iter()
+            .filter(|(_, count)| (**count as f64) >= (1.0 - RARE) * total as f64)
+            .map(|(name, _)| name)
+            .collect();
+
+        for token in &tokens {
+            if let Some(alg) = text(token.header.get("alg")).filter(|alg| rare_algs.contains(alg)) {
+                let usual = algs
+                    .iter()
+                    .max_by_key(|(_, count)| **count)
+                    .map(|(alg, _)| alg);
+                flag(
+                    token.line,
+                    "alg",
+                    format!(
+                        "alg {} is rare for the issuer, which mostly uses {}",
+                        alg,
+                        usual.map_or("nothing else", String::as_str)
+                    ),
+                );
+            }
+            for aud in audiences(token)
+                .iter()
+                .filter(|aud| rare_auds.contains(*aud))
+            {
+                flag(
+                    token.line,
+                    "aud",
+                    format!("aud {} is rare for the issuer", aud),
+                );
+            }
+            let present = names(token);
+            let unusual: Vec<&str> = present
+                .iter()
+                .filter(|name| rare_claims.contains(*name))
+                .map(String::as_str)
+                .collect();
+            let missing: Vec<&str> = usual_claims
+                .iter()
+                .filter(|name| !present.contains(name))
+                .map(|name| name.as_str())
+                .collect();
+            if !unusual.is_empty() {
+                flag(
+                    token.line,
+                    "claims",
+                    format!(
+                        "has claim(s) the issuer's tokens rarely carry: {}",
+                        unusual.join(", ")
+                    ),
+                );
+            }
+            if !missing.is_empty() {
+                flag(
+                    token.line,
+                    "claims",
+                    format!(
+                        "lacks claim(s) the issuer's tokens nearly all carry: {}",
+                        missing.join(", ")
+                    ),
+                );
+            }
+        }
+    }
+    anomalies.sort_by_key(|anomaly| anomaly.line);
+    anomalies
+}
+
+pub fn render_text(anomalies: &[Anomaly]) -> String {
+    let mut out = format!("anomalies: {}", anomalies.len());
+    for anomaly in anomalies {
+        out.push_str(&format!(
+            "\nline {}: {}: {}",
+            anomaly.line, anomaly.issuer, anomaly.message
+        ));
+    }
+    out
+}
+
+pub fn to_json(anomalies: &[Anomaly]) -> Value {
+    Value::Array(
+        anomalies
+            .iter()
+            .map(|anomaly| {
+                serde_json::json!({
+                    "line": anomaly.line,
+                    "iss": anomaly.issuer,
+                    "kind": anomaly.kind,
+                    "message": anomaly.message,
+                })
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn detect_test() {
+        let header = json!({"alg": "RS256"});
+        let odd_header = json!({"alg": "HS256"});
+        let mut payloads: Vec<Value> = (0..20)
+            .map(|i| json!({"iss": "https://idp", "sub": format!("user-{}", i), "aud": "api", "iat": 1_000, "exp": 4_600}))
+            .collect();
+        // a token that lives 30 days
+        payloads[3]["exp"] = json!(1_000 + 30 * 86_400);
+        // one for an audience nobody else has, with an extra claim
+        payloads[5]["aud"] = json!(["api", "admin"]);
+        payloads[5]["debug"] = json!(true);
+        // one without a subject
+        payloads[7].as_object_mut().unwrap().remove("sub");
+        // a small issuer: too few tokens for a norm
+        payloads.push(json!({"iss": "https://small", "iat": 0, "exp": 99_999_999}));
+        let samples: Vec<Sample> = payloads
+            .iter()
+            .enumerate()
+            .map(|(index, payload)| Sample {
+                line: index + 1,
+                header: if index == 9 { &odd_header } else { &header },
+                payload,
+            })
+            .collect();
+        let anomalies = detect(&samples);
+        let found: Vec<(usize, &str, &str)> = anomalies
+            .iter()
+            .map(|a| (a.line, a.kind, a.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    4,
+                    "lifetime",
+                    "lives 30 days, the issuer's median is 1 hour"
+                ),
+                (6, "aud", "aud admin is rare for the issuer"),
+                (
+                    6,
+                    "claims",
+                    "has claim(s) the issuer's tokens rarely carry: debug"
+                ),
+                (
+                    8,
+                    "claims",
+                    "lacks claim(s) the issuer's tokens nearly all carry: sub"
+                ),
+                (
+                    10,
+                    "alg",
+                    "alg HS256 is rare for the issuer, which mostly uses RS256"
+                ),
+            ]
+        );
+        assert!(render_text(&anomalies).starts_with(
+            "anomalies: 5\nline 4: https://idp: lives 30 days, the issuer's median is 1 hour\n"
+        ));
+        assert_eq!(to_json(&anomalies)[4]["kind"], "alg");
+
+        // a uniform corpus has none
+        assert!(detect(&samples[10..20]).is_empty());
+        assert_eq!(render_text(&[]), "anomalies: 0");
+    }
+}
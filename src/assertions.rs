@@ -0,0 +1,441 @@
+//! `assert`: one-line claims assertions for shell-based integration tests.
+//!
+//! Each assertion is one argument:
+//!
+//! - `PATH == VALUE` and `PATH != VALUE`; an array claim equals a value it
+//!   holds;
+//! - `PATH < NUMBER`, and `<=`, `>`, `>=`;
+//! - `PATH within DURATION`: a time claim at most that far from now, either
+//!   way (`exp within 1h`, `iat within 30s`);
+//! - `PATH starts with VALUE`, `PATH ends with VALUE`;
+//! - `has PATH`, `lacks PATH`;
+//! - `has PATH VALUE`: the claim holds the value, as an array member or a
+//!   word of a space-separated string such as `scope`.
+//!
+//! Paths are those of `--claim`. A value is JSON (`"api://orders"`, `42`,
+//! `true`), or a bare word taken as a string. Every assertion is checked,
+//! and each failure says what the claim actually was.
+
+use crate::{claim, format_timestamp, humanize_seconds, parse_duration, JWTError, JWToken};
+use serde_json::Value;
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Test {
+    Equals(Value),
+    NotEquals(Value),
+    Compare(&'static str, f64),
+    Within(i64),
+    StartsWith(String),
+    EndsWith(String),
+    Present,
+    Absent,
+    Holds(Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assertion {
+    /// As written, for the report.
+    pub text: String,
+    pub path: String,
+    pub test: Test,
+}
+
+/// The outcome of one assertion; `detail` says why it failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub assertion: Assertion,
+    pub passed: bool,
+    pub detail: Option<String>,
+}
+
+/// Splits on whitespace, keeping double-quoted strings whole (quotes
+/// included, so they still read as JSON).
+pub(crate) fn words(text: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        if c == '"' {
+            word.push(c);
+            chars.next();
+            let mut closed = false;
+            while let Some(c) = chars.next() {
+                word.push(c);
+                if c == '\\' {
+                    if let Some(escaped) = chars.next() {
+                        word.push(escaped);
+                    }
+                } else if c == '"' {
+                    closed = true;
+                    break;
+                }
+            }
+            if !closed {
+                return Err("unterminated string".to_string());
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+        }
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// A value as written: JSON, or else a bare string.
+fn value(word: &str) -> Result<Value, String> {
+    match serde_json::from_str(word) {
+        Ok(value) => Ok(value),
+        Err(_) if word.starts_with('"') => Err(format!("{} is not a valid JSON string", word)),
+        Err(_) => Ok(Value::String(word.to_string())),
+    }
+}
+
+fn text_value(word: &str) -> Result<String, String> {
+    match value(word)? {
+        Value::String(text) => Ok(text),
+        other => Ok(other.to_string()),
+    }
+}
+
+impl std::str::FromStr for Assertion {
+    type Err = JWTError;
+
+    fn from_str(text: &str) -> Result<Assertion, JWTError> {
+        let invalid = |why: String| {
+            JWTError::InvalidArgumentError(
+                format!("cannot read assertion `{}`: {}", text, why).into(),
+            )
+        };
+        let words = words(text).map_err(invalid)?;
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        let (path, test) = match words.as_slice() {
+            ["has", path] => (*path, Test::Present),
+            ["lacks", path] => (*path, Test::Absent),
+            ["has", path, needle] => (*path, Test::Holds(value(needle).map_err(invalid)?)),
+            [path, "==", operand] => (*path, Test::Equals(value(operand).map_err(invalid)?)),
+            [path, "!=", operand] => (*path, Test::NotEquals(value(operand).map_err(invalid)?)),
+            [path, op @ ("<" | "<=" | ">" | ">="), number] => {
+                let number = number
+                    .parse::<f64>()
+                    .map_err(|_| invalid(format!("`{}` needs a number", op)))?;
+                let op = match *op {
+                    "<" => "<",
+                    "<=" => "<=",
+                    ">" => ">",
+                    _ => ">=",
+                };
+                (*path, Test::Compare(op, number))
+            }
+            [path, "within", duration] => (
+                *path,
+                Test::Within(parse_duration(duration).map_err(|e| match e {
+                    JWTError::InvalidArgumentError(why) => invalid(why.into()),
+                    e => e,
+                })?),
+            ),
+            [path, "starts", "with", prefix] => (
+                *path,
+                Test::StartsWith(text_value(prefix).map_err(invalid)?),
+            ),
+            [path, "ends", "with", suffix] => {
+                (*path, Test::EndsWith(text_value(suffix).map_err(invalid)?))
+            }
+            _ => {
+                return Err(invalid(
+                    "expected `PATH == VALUE`, `!=`, `<`, `<=`, `>`, `>=`, `PATH within DURATION`, `PATH starts with VALUE`, `PATH ends with VALUE`, `has PATH [VALUE]` or `lacks PATH`"
+                        .to_string(),
+                ))
+            }
+        };
+        Ok(Assertion {
+            text: text.to_string(),
+            path: path.to_string(),
+            test,
+        })
+    }
+}
+
+/// Conditions joined by `and`, as `--where` and script rules take them.
+pub fn conjunction(text: &str) -> Result<Vec<Assertion>, JWTError> {
+    let words = words(text).map_err(|why| {
+        JWTError::InvalidArgumentError(format!("cannot read `{}`: {}", text, why).into())
+    })?;
+    words
+        .split(|word| word == "and")
+        .map(|condition| condition.join(" ").parse::<Assertion>())
+        .collect()
+}
+
+/// Whether every one of `conditions` holds for `token`.
+pub fn all_hold(conditions: &[Assertion], token: &JWToken, now: i64) -> bool {
+    conditions
+        .iter()
+        .all(|condition| condition.check(token, now).passed)
+}
+
+/// A claim value for a failure line, with strings quoted.
+fn shown(value: &Value) -> String {
+    let text = value.to_string();
+    if text.len() > 80 {
+        format!("{}...", text.get(..77).unwrap_or_default())
+    } else {
+        text
+    }
+}
+
+impl Assertion {
+    /// Checks the assertion against `token`, whose time claims are in
+    /// seconds.
+    pub fn check(&self, token: &JWToken, now: i64) -> Outcome {
+        let actual = claim(token, &self.path);
+        let path = &self.path;
+        let result = match (&self.test, actual) {
+            (Test::Present, Some(_)) => Ok(()),
+            (Test::Present, None) => Err(format!("{} is absent", path)),
+            (Test::Absent, None) => Ok(()),
+            (Test::Absent, Some(actual)) => Err(format!("{} is {}", path, shown(actual))),
+            (_, None) => Err(format!("{} is absent", path)),
+            (Test::Equals(expected), Some(actual)) => {
+                if actual == expected
+                    || actual
+                        .as_array()
+                        .is_some_and(|items| items.contains(expected))
+                {
+                    Ok(())
+                } else {
+                    Err(format!("{} is {}", path, shown(actual)))
+                }
+            }
+            (Test::NotEquals(unexpected), Some(actual)) => {
+                if actual == unexpected
+                    || actual
+                        .as_array()
+                        .is_some_and(|items| items.contains(unexpected))
+                {
+                    Err(format!("{} is {}", path, shown(actual)))
+                } else {
+                    Ok(())
+                }
+            }
+            (Test::Compare(op, bound), Some(actual)) => match actual.as_f64() {
+                Some(number) => {
+                    let holds = match *op {
+                        "<" => number < *bound,
+                        "<=" => number <= *bound,
+                        ">" => number > *bound,
+                        _ => number >= *bound,
+                    };
+                    if holds {
+                        Ok(())
+                    } else {
+                        Err(format!("{} is {}", path, shown(actual)))
+                    }
+                }
+                None => Err(format!("{} is {}, not a number", path, shown(actual))),
+            },
+            (Test::Within(window), Some(actual)) => match actual.as_i64() {
+                Some(at) if at.abs_diff(now) <= window.unsigned_abs() => Ok(()),
+                Some(at) => Err(format!(
+                    "{} is {}, {} {}",
+                    path,
+                    format_timestamp(at),
+                    humanize_seconds(i64::try_from(at.abs_diff(now)).unwrap_or(i64::MAX)),
+                    if at > now { "from now" } else { "ago" }
+                )),
+                None => Err(format!("{} is {}, not a NumericDate", path, shown(actual))),
+            },
+            (Test::StartsWith(prefix), Some(actual)) => match actual.as_str() {
+                Some(text) if text.starts_with(prefix.as_str()) => Ok(()),
+                _ => Err(format!("{} is {}", path, shown(actual))),
+            },
+            (Test::EndsWith(suffix), Some(actual)) => match actual.as_str() {
+                Some(text) if text.ends_with(suffix.as_str()) => Ok(()),
+                _ => Err(format!("{} is {}", path, shown(actual))),
+            },
+            (Test::Holds(needle), Some(actual)) => {
+                let holds = match (actual, needle) {
+                    (Value::Array(items), needle) => items.contains(needle),
+                    (Value::String(text), Value::String(needle)) => {
+                        text.split(' ').any(|word| word == needle)
+                    }
+                    (actual, needle) => actual == needle,
+                };
+                if holds {
+                    Ok(())
+                } else {
+                    Err(format!("{} is {}", path, shown(actual)))
+                }
+            }
+        };
+        Outcome {
+            assertion: self.clone(),
+            passed: result.is_ok(),
+            detail: result.err(),
+        }
+    }
+}
+
+/// One `ok`/`FAIL` line per outcome, failures followed by what was found,
+/// and a summary. `quiet` leaves out the assertions that passed.
+pub fn render_text(outcomes: &[Outcome], quiet: bool) -> String {
+    let mut out = String::new();
+    for outcome in outcomes {
+        if outcome.passed {
+            if !quiet {
+                out.push_str(&format!("ok    {}\n", outcome.assertion.text));
+            }
+            continue;
+        }
+        out.push_str(&format!("FAIL  {}\n", outcome.assertion.text));
+        if let Some(detail) = &outcome.detail {
+            out.push_str(&format!("      {}\n", detail));
+        }
+    }
+    let passed = outcomes.iter().filter(|o| o.passed).count();
+    out.push_str(&format!("assert: {} of {} passed", passed, outcomes.len()));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn check(token: &JWToken, text: &str) -> Outcome {
+        text.parse::<Assertion>().unwrap().check(token, NOW)
+    }
+
+    #[test]
+    fn assertions_test() {
+        let token = JWToken {
+            header: json!({"alg": "ES256", "kid": "k1"}),
+            payload: json!({"aud": ["api://orders", "api://billing"], "exp": NOW + 1_800,
+                            "iat": NOW - 10, "scope": "orders:read orders:write",
+                            "sub": "user-42", "level": 3}),
+            signature: Vec::new(),
+        };
+        for passing in [
+            "aud == \"api://orders\"",
+            "exp within 1h",
+            "iat within 30s",
+            "has scope \"orders:read\"",
+            "has scope orders:write",
+            "header.alg == ES256",
+            "sub starts with user-",
+            "level >= 3",
+            "level != 4",
+            "lacks email",
+            "has sub",
+        ] {
+            let outcome = check(&token, passing);
+            assert!(outcome.passed, "{}: {:?}", passing, outcome.detail);
+        }
+        let failures: Vec<Option<String>> = [
+            "exp within 10m",
+            "has scope \"orders:delete\"",
+            "aud == api://shipping",
+            "email == \"a@example.com\"",
+            "level < 2",
+        ]
+        .iter()
+        .map(|text| check(&token, text).detail)
+        .collect();
+        assert_eq!(
+            failures,
+            [
+                Some("exp is 2023-11-14T22:43:20Z, 30 minutes from now".to_string()),
+                Some("scope is \"orders:read orders:write\"".to_string()),
+                Some("aud is [\"api://orders\",\"api://billing\"]".to_string()),
+                Some("email is absent".to_string()),
+                Some("level is 3".to_string()),
+            ]
+        );
+        let outcomes = [check(&token, "has sub"), check(&token, "level < 2")];
+        assert_eq!(
+            render_text(&outcomes, false),
+            "ok    has sub\nFAIL  level < 2\n      level is 3\nassert: 1 of 2 passed"
+        );
+        assert!(render_text(&outcomes, true).starts_with("FAIL"));
+        let both = conjunction("payload.sub == \"user-42\" and has scope orders:read").unwrap();
+        assert_eq!(both.len(), 2);
+        assert!(all_hold(&both, &token, NOW));
+        assert!(!all_hold(
+            &conjunction("sub == user-42 and lacks sub").unwrap(),
+            &token,
+            NOW
+        ));
+        assert!(conjunction("sub == user-42 and").is_err());
+        assert!(conjunction("sub == \"user").is_err());
+        let ancient = JWToken {
+            payload: json!({"exp": i64::MIN}),
+            ..token
+        };
+        assert!(!check(&ancient, "exp within 1h").passed);
+        assert!("aud is api".parse::<Assertion>().is_err());
+        assert!("has scope \"open".parse::<Assertion>().is_err());
+    }
+
+    #[test]
+    fn rejects_bad_assertions_test() {
+        let error_of = |text: &str| error_message!(InvalidArgumentError, text.parse::<Assertion>());
+        assert_eq!(
+            error_of("has scope \"open"),
+            "cannot read assertion `has scope \"open`: unterminated string"
+        );
+        assert_eq!(
+            error_of("level < three"),
+            "cannot read assertion `level < three`: `<` needs a number"
+        );
+        assert_eq!(
+            error_of("exp within soon"),
+            "cannot read assertion `exp within soon`: expected a duration like 30s, 5m or 1h, got `soon`"
+        );
+        let expected = "expected `PATH == VALUE`, `!=`, `<`, `<=`, `>`, `>=`, \
+                        `PATH within DURATION`, `PATH starts with VALUE`, \
+                        `PATH ends with VALUE`, `has PATH [VALUE]` or `lacks PATH`";
+        assert_eq!(
+            error_of("aud is api"),
+            format!("cannot read assertion `aud is api`: {}", expected)
+        );
+        assert_eq!(
+            error_of(r#"sub == "a\q""#),
+            r#"cannot read assertion `sub == "a\q"`: "a\q" is not a valid JSON string"#
+        );
+        assert!(matches!(
+            conjunction("sub == user-42 and"),
+            Err(JWTError::InvalidArgumentError(e))
+                if e == format!("cannot read assertion ``: {}", expected)
+        ));
+        assert!(matches!(
+            conjunction("sub == \"user"),
+            Err(JWTError::InvalidArgumentError(e)) if e == "cannot read `sub == \"user`: unterminated string"
+        ));
+
+        let token = JWToken {
+            header: json!({}),
+            payload: json!({"level": "high", "exp": "tomorrow"}),
+            signature: Vec::new(),
+        };
+        assert_eq!(
+            check(&token, "level > 2").detail.unwrap(),
+            "level is \"high\", not a number"
+        );
+        assert_eq!(
+            check(&token, "exp within 1h").detail.unwrap(),
+            "exp is \"tomorrow\", not a NumericDate"
+        );
+    }
+}
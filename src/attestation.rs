@@ -0,0 +1,746 @@
+//! `jwt-check attestation`: mobile device attestations that get pasted in
+//! as if they were JWTs, decoded far enough to read.
+//!
+//! - Apple App Attest attestation objects and assertions are CBOR, not JWTs.
+//!   The authenticator data is unpacked (RP ID hash, flags, counter,
+//!   environment, credential) with the `x5c` certificates and the leaf's
+//!   nonce extension. A key ID that isn't the SHA-256 of the leaf's key is
+//!   reported.
+//! - Google Play Integrity tokens are a JWE (`A256KW`, `A256GCM`) around an
+//!   ES256 JWS. With the decryption key from the Play Console the verdict is
+//!   shown, and with the verification key its signature is checked.
+//!
+//! Nothing here says whether a device is genuine: Apple's root, the
+//! receipt and the nonce's relation to the challenge are not checked.
+
+use crate::crypto::Hash;
+use crate::jwe::{self, DecryptionKey};
+use crate::keys::{der_expect, parse_public_key_pem, TAG_OCTET_STRING, TAG_SEQUENCE};
+use crate::x509::Certificate;
+use crate::{format_timestamp, parse, ErrorMessage, JWTError};
+use serde_json::{json, Map, Value};
+use std::convert::TryFrom;
+
+/// The App Attest nonce extension of the leaf certificate.
+pub const OID_APP_ATTEST_NONCE: &str = "1.2.840.113635.100.8.2";
+
+/// How deeply CBOR arrays and maps may nest.
+const MAX_DEPTH: usize = 32;
+
+fn attestation_error(message: impl Into<ErrorMessage>) -> JWTError {
+    JWTError::AttestationError(message.into())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A decoded CBOR (RFC 8949) data item.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cbor {
+    Int(i128),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    Tag(u64, Box<Cbor>),
+    Bool(bool),
+    Null,
+    Undefined,
+    Float(f64),
+}
+
+impl Cbor {
+    /// The value under the text key `key` of a map.
+    pub fn get(&self, key: &str) -> Option<&Cbor> {
+        match self {
+            Cbor::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| matches!(k, Cbor::Text(text) if text == key))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Cbor::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// The JSON rendering: byte strings in diagnostic notation (`h'..'`),
+    /// map keys as their JSON text when they aren't strings.
+    pub fn to_json(&self) -> Value {
+        match self {
+            Cbor::Int(n) => serde_json::from_str(&n.to_string()).unwrap_or(Value::Null),
+            Cbor::Bytes(bytes) => Value::String(format!("h'{}'", hex(bytes))),
+            Cbor::Text(text) => Value::String(text.clone()),
+            Cbor::Array(items) => Value::Array(items.iter().map(Cbor::to_json).collect()),
+            Cbor::Map(entries) => {
+                let mut map = Map::new();
+                for (key, value) in entries {
+                    let key = match key.to_json() {
+                        Value::String(text) => text,
+                        other => other.to_string(),
+                    };
+                    map.insert(key, value.to_json());
+                }
+                Value::Object(map)
+            }
+            Cbor::Tag(tag, value) => json!({"tag": tag, "value": value.to_json()}),
+            Cbor::Bool(flag) => Value::Bool(*flag),
+            Cbor::Null | Cbor::Undefined => Value::Null,
+            Cbor::Float(x) => json!(x),
+        }
+    }
+}
+
+/// Decodes one CBOR item, returning it and what follows it. Indefinite
+/// lengths are refused; App Attest doesn't use them.
+pub fn cbor_decode(input: &[u8]) -> Result<(Cbor, &[u8]), JWTError> {
+    decode_item(input, 0)
+}
+
+fn take(input: &[u8], n: u64) -> Result<(&[u8], &[u8]), JWTError> {
+    let n = usize::try_from(n).map_err(|_| attestation_error("truncated CBOR"))?;
+    if input.len() < n {
+        return Err(attestation_error("truncated CBOR"));
+    }
+    Ok(input.split_at(n))
+}
+
+fn argument(info: u8, input: &[u8]) -> Result<(u64, &[u8]), JWTError> {
+    let size = match info {
+        0..=23 => return Ok((u64::from(info), input)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        31 => return Err(attestation_error("indefinite-length CBOR is not supported")),
+        _ => return Err(attestation_error("malformed CBOR")),
+    };
+    let (bytes, rest) = take(input, size)?;
+    let value = bytes
+        .iter()
+        .fold(0u64, |value, byte| value << 8 | u64::from(*byte));
+    Ok((value, rest))
+}
+
+fn decode_item(input: &[u8], depth: usize) -> Result<(Cbor, &[u8]), JWTError> {
+    if depth > MAX_DEPTH {
+        return Err(attestation_error("CBOR nested too deeply"));
+    }
+    let (first, rest) = input
+        .split_first()
+        .ok_or_else(|| attestation_error("truncated CBOR"))?;
+    let (major, info) = (first >> 5, first & 0x1f);
+    if major == 7 {
+        return decode_simple(info, rest);
+    }
+    let (n, mut rest) = argument(info, rest)?;
+    let item = match major {
+        0 => Cbor::Int(i128::from(n)),
+        1 => Cbor::Int(-1 - i128::from(n)),
+        2 => {
+            let (bytes, after) = take(rest, n)?;
+            rest = after;
+            Cbor::Bytes(bytes.to_vec())
+        }
+        3 => {
+            let (bytes, after) = take(rest, n)?;
+            rest = after;
+            let text = std::str::from_utf8(bytes)
+                .map_err(|_| attestation_error("CBOR text string is not UTF-8"))?;
+            Cbor::Text(text.to_string())
+        }
+        4 => {
+            let mut items = Vec::new();
+            for _ in 0..n {
+                let (item, after) = decode_item(rest, depth + 1)?;
+                items.push(item);
+                rest = after;
+            }
+            Cbor::Array(items)
+        }
+        5 => {
+            let mut entries = Vec::new();
+            for _ in 0..n {
+                let (key, after) = decode_item(rest, depth + 1)?;
+                let (value, after) = decode_item(after, depth + 1)?;
+                entries.push((key, value));
+                rest = after;
+            }
+            Cbor::Map(entries)
+        }
+        _ => {
+            let (value, after) = decode_item(rest, depth + 1)?;
+            rest = after;
+            Cbor::Tag(n, Box::new(value))
+        }
+    };
+    Ok((item, rest))
+}
+
+fn decode_simple(info: u8, input: &[u8]) -> Result<(Cbor, &[u8]), JWTError> {
+    let float = |size: u64| -> Result<(f64, &[u8]), JWTError> {
+        let (bytes, rest) = take(input, size)?;
+        let bits = bytes
+            .iter()
+            .fold(0u64, |value, byte| value << 8 | u64::from(*byte));
+        let value = match size {
+            2 => half_to_f64(bits as u16),
+            4 => f64::from(f32::from_bits(bits as u32)),
+            _ => f64::from_bits(bits),
+        };
+        Ok((value, rest))
+    };
+    match info {
+        20 => Ok((Cbor::Bool(false), input)),
+        21 => Ok((Cbor::Bool(true), input)),
+        22 => Ok((Cbor::Null, input)),
+        23 => Ok((Cbor::Undefined, input)),
+        25 => float(2).map(|(x, rest)| (Cbor::Float(x), rest)),
+        26 => float(4).map(|(x, rest)| (Cbor::Float(x), rest)),
+        27 => float(8).map(|(x, rest)| (Cbor::Float(x), rest)),
+        _ => Err(attestation_error(format!(
+            "unsupported CBOR simple value {}",
+            info
+        ))),
+    }
+}
+
+/// An IEEE 754 half-precision float (RFC 8949 Appendix D).
+fn half_to_f64(half: u16) -> f64 {
+    let exponent = i32::from(half >> 10 & 0x1f);
+    let mantissa = f64::from(half & 0x3ff);
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent - 25),
+    };
+    if half & 0x8000 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+/// Authenticator data flags (WebAuthn section 6.1), by name.
+const FLAGS: [(u8, &str); 5] = [
+    (0x01, "UP"),
+    (0x04, "UV"),
+    (0x08, "BE"),
+    (0x40, "AT"),
+    (0x80, "ED"),
+];
+
+/// The parts of App Attest authenticator data: RP ID hash, flags, counter
+/// and, when the `AT` flag is set, the attested credential.
+fn authenticator_data(data: &[u8]) -> Result<(Value, Option<Vec<u8>>), JWTError> {
+    let truncated = || attestation_error("truncated authenticator data");
+    let (rp_id_hash, rest) = take(data, 32).map_err(|_| truncated())?;
+    let (flags, rest) = rest.split_first().ok_or_else(truncated)?;
+    let (counter, rest) = take(rest, 4).map_err(|_| truncated())?;
+    let mut parsed = Map::new();
+    parsed.insert("rp_id_hash".to_string(), json!(hex(rp_id_hash)));
+    let names: Vec<&str> = FLAGS
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    parsed.insert("flags".to_string(), json!(names));
+    let counter = counter
+        .iter()
+        .fold(0u32, |value, byte| value << 8 | u32::from(*byte));
+    parsed.insert("counter".to_string(), json!(counter));
+    if flags & 0x40 == 0 {
+        return Ok((Value::Object(parsed), None));
+    }
+    let (aaguid, rest) = take(rest, 16).map_err(|_| truncated())?;
+    let (length, rest) = take(rest, 2).map_err(|_| truncated())?;
+    let length = u64::from(u16::from_be_bytes([
+        length.first().copied().unwrap_or(0),
+        length.get(1).copied().unwrap_or(0),
+    ]));
+    let (credential_id, rest) = take(rest, length).map_err(|_| truncated())?;
+    let (public_key, _) = cbor_decode(rest)?;
+    let environment = match aaguid {
+        b"appattestdevelop" => "development".to_string(),
+        b"appattest\0\0\0\0\0\0\0" => "production".to_string(),
+        other => format!("unknown (aaguid {})", hex(other)),
+    };
+    parsed.insert("environment".to_string(), json!(environment));
+    parsed.insert("credential_id".to_string(), json!(hex(credential_id)));
+    parsed.insert("public_key".to_string(), public_key.to_json());
+    Ok((Value::Object(parsed), Some(credential_id.to_vec())))
+}
+
+/// The nonce of an App Attest leaf certificate: `SEQUENCE { [1] OCTET
+/// STRING }`.
+fn certificate_nonce(certificate: &Certificate) -> Result<Option<Vec<u8>>, JWTError> {
+    let value = certificate
+        .other_extensions
+        .iter()
+        .find(|(oid, _)| oid == OID_APP_ATTEST_NONCE)
+        .map(|(_, value)| value);
+    let value = match value {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let (sequence, _) = der_expect(value, TAG_SEQUENCE)?;
+    let (tagged, _) = der_expect(sequence, 0xa1)?;
+    let (nonce, _) = der_expect(tagged, TAG_OCTET_STRING)?;
+    Ok(Some(nonce.to_vec()))
+}
+
+fn certificate_json(certificate: &Certificate) -> Result<Value, JWTError> {
+    let mut parsed = Map::new();
+    parsed.insert("subject".to_string(), json!(certificate.name()));
+    parsed.insert("issuer".to_string(), json!(certificate.issuer_name()));
+    parsed.insert(
+        "not_before".to_string(),
+        json!(format_timestamp(certificate.not_before)),
+    );
+    parsed.insert(
+        "not_after".to_string(),
+        json!(format_timestamp(certificate.not_after)),
+    );
+    if let Some(nonce) = certificate_nonce(certificate)? {
+        parsed.insert("nonce".to_string(), json!(hex(&nonce)));
+    }
+    Ok(Value::Object(parsed))
+}
+
+/// An App Attest attestation object: `fmt`, `attStmt` (`x5c`, `receipt`)
+/// and `authData`.
+fn app_attest(object: &Cbor) -> Result<Value, JWTError> {
+    let statement = object
+        .get("attStmt")
+        .ok_or_else(|| attestation_error("the attestation object has no attStmt"))?;
+    let auth_data = object
+        .get("authData")
+        .and_then(Cbor::bytes)
+        .ok_or_else(|| attestation_error("the attestation object has no authData bytes"))?;
+    let (authenticator, credential_id) = authenticator_data(auth_data)?;
+    let chain = match statement.get("x5c") {
+        Some(Cbor::Array(certificates)) => certificates
+            .iter()
+            .map(|der| {
+                let der = der
+                    .bytes()
+                    .ok_or_else(|| attestation_error("an x5c entry is not a byte string"))?;
+                Certificate::from_der(der)
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => return Err(attestation_error("attStmt has no x5c certificates")),
+    };
+    let mut problems = Vec::new();
+    if let (Some(leaf), Some(id)) = (chain.first(), &credential_id) {
+        if Hash::Sha256.digest(&leaf.public_key_bits()?) != *id {
+            problems.push(
+                "the key ID is not the SHA-256 of the leaf certificate's public key".to_string(),
+            );
+        }
+    }
+    for pair in chain.windows(2) {
+        if let [certificate, issuer] = pair {
+            if let Err(e) = certificate.issued_by(issuer) {
+                problems.push(e.to_string());
+            }
+        }
+    }
+    Ok(json!({
+        "format": "apple-appattest",
+        "authenticator_data": authenticator,
+        "certificates": chain.iter().map(certificate_json).collect::<Result<Vec<_>, _>>()?,
+        "receipt_bytes": statement.get("receipt").and_then(Cbor::bytes).map_or(0, <[u8]>::len),
+        "problems": problems,
+    }))
+}
+
+/// An App Attest assertion: `signature` and `authenticatorData`.
+fn app_attest_assertion(object: &Cbor) -> Result<Value, JWTError> {
+    let signature = object.get("signature").and_then(Cbor::bytes);
+    let auth_data = object.get("authenticatorData").and_then(Cbor::bytes);
+    match (signature, auth_data) {
+        (Some(signature), Some(auth_data)) => Ok(json!({
+            "format": "apple-appattest-assertion",
+            "authenticator_data": authenticator_data(auth_data)?.0,
+            "signature_bytes": signature.len(),
+        })),
+        _ => Err(attestation_error(
+            "an assertion needs signature and authenticatorData byte strings",
+        )),
+    }
+}
+
+/// What kind of attestation `input` holds, if it's one of these.
+fn kind(object: &Cbor) -> Option<&'static str> {
+    if object.get("attStmt").is_some() && object.get("authData").is_some() {
+        Some("attestation")
+    } else if object.get("authenticatorData").is_some() {
+        Some("assertion")
+    } else {
+        None
+    }
+}
+
+/// The bytes of base64, base64url or binary input.
+fn input_bytes(input: &[u8]) -> Vec<u8> {
+    let text = match std::str::from_utf8(input) {
+        Ok(text) => text.trim(),
+        Err(_) => return input.to_vec(),
+    };
+    [base64::STANDARD, base64::URL_SAFE, base64::URL_SAFE_NO_PAD]
+        .iter()
+        .find_map(|config| base64::decode_config(text, *config).ok())
+        .unwrap_or_else(|| input.to_vec())
+}
+
+/// The keys a Play Integrity token is opened and checked with, as the Play
+/// Console shows them: base64 of the AES key and of the DER public key.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayKeys {
+    pub decryption_key: Option<String>,
+    pub verification_key: Option<String>,
+}
+
+fn play_integrity(raw: &str, keys: &PlayKeys) -> Result<Value, JWTError> {
+    let jwe = jwe::parse(raw)?;
+    let decryption_key = match &keys.decryption_key {
+        Some(key) => base64::decode(key.trim())?,
+        None => {
+            return Ok(json!({
+                "format": "play-integrity",
+                "header": jwe.header,
+                "verdict": "encrypted; pass --decryption-key with the Play Console's decryption key",
+            }))
+        }
+    };
+    let plaintext = jwe.decrypt(&DecryptionKey::Symmetric(decryption_key))?;
+    let jws = std::str::from_utf8(&plaintext)?;
+    let token = parse(jws)?;
+    let signature = match &keys.verification_key {
+        Some(key) => {
+            let pem = format!(
+                "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+                key.trim()
+            );
+            let algorithm = crate::verify::verify(jws, &token, &parse_public_key_pem(&pem)?)?;
+            json!(format!("valid {}", algorithm))
+        }
+        None => {
+            json!("not checked; pass --verification-key with the Play Console's verification key")
+        }
+    };
+    Ok(json!({
+        "format": "play-integrity",
+        "header": jwe.header,
+        "jws_header": token.header,
+        "verdict": token.payload,
+        "signature": signature,
+    }))
+}
+
+/// Decodes an App Attest attestation or assertion (base64 or binary CBOR)
+/// or a Play Integrity token.
+pub fn decode(input: &[u8], keys: &PlayKeys) -> Result<Value, JWTError> {
+    if let Ok(text) = std::str::from_utf8(input) {
+        if jwe::is_compact(text.trim()) {
+            return play_integrity(text.trim(), keys);
+        }
+    }
+    let bytes = input_bytes(input);
+    let (object, rest) = cbor_decode(&bytes)?;
+    if !rest.is_empty() {
+        return Err(attestation_error(format!(
+            "{} bytes follow the CBOR item",
+            rest.len()
+        )));
+    }
+    match kind(&object) {
+        Some("attestation") => app_attest(&object),
+        Some(_) => app_attest_assertion(&object),
+        None => Ok(json!({"format": "cbor", "cbor": object.to_json()})),
+    }
+}
+
+/// For a token that isn't a JWT: the error to report instead if it is an
+/// App Attest object, so the user is sent to `jwt-check attestation`.
+pub fn hint(raw: &str) -> Option<JWTError> {
+    if raw.contains('.') {
+        return None;
+    }
+    let bytes = input_bytes(raw.as_bytes());
+    let (object, _) = cbor_decode(&bytes).ok()?;
+    kind(&object).map(|kind| {
+        attestation_error(format!(
+            "this is an App Attest {}, not a JWT; decode it with `jwt-check attestation`",
+            kind
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An App Attest attestation object built like Apple's, with a test CA
+    /// in place of Apple's: a development key, its leaf and the CA.
+    const ATTESTATION: &str = "o2NmbXRvYXBwbGUtYXBwYXR0ZXN0Z2F0dFN0bXSiY3g1Y4JZAWIwggFeMIIBBaADAgECAgECMAoGCCqGSM49BAMCMCIxIDAeBgNVBAMMF1Rlc3QgQXBwIEF0dGVzdGF0aW9uIENBMB4XDTI0MDEwMTAwMDAwMFoXDTM0MDEwMTAwMDAwMFowFzEVMBMGA1UEAwwMM2I3YTQ0ZGIyZjBmMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEb7lJ15tksUcangnHaFC8y9iR/kdt8ID4YaULHT6JyaX5FmpXeQJBpLpQ3jVjNeDMUEdG8xQ0O7lGNJeE/PyrSqM3MDUwMwYJKoZIhvdjZAgCBCYwJKEiBCBcl94JE4Phy7GkuUwoLq4eV7Mtp8utqcd3lZOQ8Xp2XDAKBggqhkjOPQQDAgNHADBEAiA/xk+bXTcQsSK2Ax1Nxcro/lWChA6/7hs7Q7A/X3nvNAIgMBXobziE6d4chmGVPP/Fbda1+IcSHC3A+6j4NSstWxBZAUwwggFIMIHvoAMCAQICAQEwCgYIKoZIzj0EAwIwIjEgMB4GA1UEAwwXVGVzdCBBcHAgQXR0ZXN0YXRpb24gQ0EwHhcNMjQwMTAxMDAwMDAwWhcNMzQwMTAxMDAwMDAwWjAiMSAwHgYDVQQDDBdUZXN0IEFwcCBBdHRlc3RhdGlvbiBDQTBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABGXeFf7LfsPN+1cPqHq3HOHxjonVl2sHBXzbv/X/hbdgZ9hQmLaQO62CvrDJiPefHylenBKFxYCxmYNhIO0bv36jFjAUMBIGA1UdEwEB/wQIMAYBAf8CAQAwCgYIKoZIzj0EAwIDSAAwRQIga8BIIsZh/cv6R7vNvOQgcIwmZm9XKazBXHFlZxdJVh4CIQDGIrkr3fabSL+MwuWU1VV/Ij2uhgICUJ5PnzKMSeSEEWdyZWNlaXB0STCAcmVjZWlwdGhhdXRoRGF0YVikKS/CLP+TLMes9vX7+cx94tgvyKerB0wNGYaAyGl460BBAAAAAGFwcGF0dGVzdGRldmVsb3AAIDdUWaBXZzEmmuK9Qvl4ahJAKcG//7dFliEqTHOe9nkgpQECAyYgASFYIG+5SdebZLFHGp4Jx2hQvMvYkf5HbfCA+GGlCx0+icmlIlgg+RZqV3kCQaS6UN41YzXgzFBHRvMUNDu5RjSXhPz8q0o=";
+    const NONCE: &str = "5c97de091383e1cbb1a4b94c282eae1e57b32da7cbada9c777959390f17a765c";
+    const RP_ID_HASH: &str = "292fc22cff932cc7acf6f5fbf9cc7de2d82fc8a7ab074c0d198680c86978eb40";
+    const KEY_ID: &str = "375459a0576731269ae2bd42f9786a124029c1bfffb74596212a4c739ef67920";
+    const ASSERTION: &str = "omlzaWduYXR1cmVYRzBFAiAhSxWWdfebArhFvXCnfgX+1Swfb/9UCalgVZf43XD+owIhAKBNrCETX32HYWxayF3cqG31IbZ31H+CNMU3rA1VPU7McWF1dGhlbnRpY2F0b3JEYXRhWCUpL8Is/5Msx6z29fv5zH3i2C/Ip6sHTA0ZhoDIaXjrQAAAAAAH";
+    /// A Play Integrity token: `A256KW` and `A256GCM` around an ES256 verdict.
+    const PLAY_INTEGRITY: &str = "eyJhbGciOiJBMjU2S1ciLCJlbmMiOiJBMjU2R0NNIn0.AmEV0Ay2rOltniwHTvePRkqhilMrxozs-1EXEJkQ3t2SS26RQjM8Dw.AAECAwQFBgcICQoL.-tJyJN8KooDSetSOw-DBg6d-d6kDZqlvvkixTWK2Ga6AZIuCHXNVpvGymaQgij8CrRqH91O5MUyk60XoqAgutrFY7uoB7zsSoano5QPCQK2hZF-bYua9maw8VBZ9ae6Ris56de_t3lSZgQAyXe-DBqvizNAc094Eqez5BYxFTArn_JHl4i5kF862uWOf85iR_NOX-9gfFRBdpJABNkj6PhChjW6m_7vDFB9lZ54hyvKxv01bbpT934BRV67bGM7ZyjLsQy-dTQurKgY6YICJLt1cdaB-LVe-83iw7j_ASoZJgorGlkM6Qbvc0i-eVRt1xygpTSpvmgDKGXjXrRf8AjEPfJFcu_lNUCsoUgJEffCfhEdSr8X720NpkMYEAuTuMcr6MfzIa_EO2HuLDkTDVGTnGCwfV3QFn2vtRmDypcD8gVI0CNMhaRc_-j8YJWMMQAGH0DO219ZEtzuLmWlvTExV9gjztMz6urX58htMNvgMTzkqgEOu-_wSYYZRWj2qsb5NpL6UIBbup-s4WMqz4xACj94QPi9RgASb7MLt_SlxEac6IvlgfZbMeg-rkT9cIS-pJx2oh26WIi5TfUe1SAqArmAVXBUbLOs4ri8vv2xPyegFEYyoaYhw89k3tOiO-uUjaLqUDpljcn8ZGbhMC3zdR5EjLUtHiSfPa0TEALPXvUteCqNYdc1JrRu58rDqkjxn1UPg6VzOKUAjPkrHYXNYubv-N0QtE5RDq0bWO8Q__G5E7Cbn_kKdNw.7UGBeQL8AZLy4TjihW9iWw";
+    const PLAY_DECRYPTION_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+    const PLAY_VERIFICATION_KEY: &str = "MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEjID7mUbhGfkK6vgcB8VTrf/7CXb+1wqVL0Kvcpt0FeyWzItqMIbq3z+cry+iqVTsiUE1qUMgGZAzQ1UVIfWEqg==";
+
+    #[test]
+    fn cbor_test() {
+        // RFC 8949 Appendix A
+        for (hex, expected) in [
+            ("0a", json!(10)),
+            ("1903e8", json!(1000)),
+            ("3903e7", json!(-1000)),
+            ("1bffffffffffffffff", json!(18446744073709551615u64)),
+            ("f93e00", json!(1.5)),
+            ("f4", json!(false)),
+            ("f6", Value::Null),
+            ("4401020304", json!("h'01020304'")),
+            ("6449455446", json!("IETF")),
+            ("8301820203820405", json!([1, [2, 3], [4, 5]])),
+            ("a201020304", json!({"1": 2, "3": 4})),
+            ("a26161016162820203", json!({"a": 1, "b": [2, 3]})),
+            ("c11a514b67b0", json!({"tag": 1, "value": 1363896240})),
+        ] {
+            let bytes: Vec<u8> = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect();
+            let (item, rest) = cbor_decode(&bytes).unwrap();
+            assert_eq!((item.to_json(), rest.len()), (expected, 0), "{}", hex);
+        }
+        assert!(matches!(
+            cbor_decode(&[0x44, 1, 2]),
+            Err(JWTError::AttestationError(e)) if e == "truncated CBOR"
+        ));
+        assert!(matches!(
+            cbor_decode(&[0x5f, 0x41, 1, 0xff]),
+            Err(JWTError::AttestationError(e)) if e == "indefinite-length CBOR is not supported"
+        ));
+        assert!(matches!(
+            cbor_decode(&[0x62, 0xff, 0xfe]),
+            Err(JWTError::AttestationError(e)) if e == "CBOR text string is not UTF-8"
+        ));
+        assert!(matches!(
+            cbor_decode(&[0x81; 40]),
+            Err(JWTError::AttestationError(e)) if e == "CBOR nested too deeply"
+        ));
+        assert!(matches!(
+            cbor_decode(&[0x1c]),
+            Err(JWTError::AttestationError(e)) if e == "malformed CBOR"
+        ));
+        for (simple, info) in [(0xf8, 24), (0xfc, 28), (0xff, 31)] {
+            assert!(matches!(
+                cbor_decode(&[simple, 0]),
+                Err(JWTError::AttestationError(e))
+                    if e == format!("unsupported CBOR simple value {}", info)
+            ));
+        }
+    }
+
+    #[test]
+    fn app_attest_test() {
+        let decoded = decode(ATTESTATION.as_bytes(), &PlayKeys::default()).unwrap();
+        assert_eq!(decoded["format"], "apple-appattest");
+        let authenticator = &decoded["authenticator_data"];
+        assert_eq!(authenticator["rp_id_hash"], RP_ID_HASH);
+        assert_eq!(authenticator["flags"], json!(["UP", "AT"]));
+        assert_eq!(authenticator["counter"], 0);
+        assert_eq!(authenticator["environment"], "development");
+        assert_eq!(authenticator["credential_id"], KEY_ID);
+        assert_eq!(authenticator["public_key"]["1"], 2);
+        assert_eq!(authenticator["public_key"]["3"], -7);
+        let certificates = decoded["certificates"].as_array().unwrap();
+        assert_eq!(certificates.len(), 2);
+        assert_eq!(certificates[0]["issuer"], "Test App Attestation CA");
+        assert_eq!(certificates[0]["nonce"], NONCE);
+        assert_eq!(certificates[0]["not_after"], format_timestamp(2019686400));
+        assert!(certificates[1].get("nonce").is_none());
+        assert_eq!(decoded["receipt_bytes"], 9);
+        assert_eq!(decoded["problems"], json!([]));
+
+        // the same object as binary CBOR
+        let binary = base64::decode(ATTESTATION).unwrap();
+        assert_eq!(decode(&binary, &PlayKeys::default()).unwrap(), decoded);
+        assert!(matches!(
+            decode(&binary[..binary.len() - 1], &PlayKeys::default()),
+            Err(JWTError::AttestationError(_))
+        ));
+        let mut trailing = binary;
+        trailing.push(0);
+        assert!(matches!(
+            decode(&trailing, &PlayKeys::default()),
+            Err(JWTError::AttestationError(e)) if e == "1 bytes follow the CBOR item"
+        ));
+    }
+
+    #[test]
+    fn app_attest_problems_test() {
+        let (object, _) = cbor_decode(&base64::decode(ATTESTATION).unwrap()).unwrap();
+        // the object with `key` replaced by `value`, or without it
+        let replaced = |key: &str, value: Option<Cbor>| {
+            let mut entries = match object.clone() {
+                Cbor::Map(entries) => entries,
+                other => panic!("expected a map, got {:?}", other),
+            };
+            entries.retain(|(k, _)| *k != Cbor::Text(key.to_string()));
+            entries.extend(value.map(|value| (Cbor::Text(key.to_string()), value)));
+            app_attest(&Cbor::Map(entries))
+        };
+
+        // another key ID, and the certificates in the wrong order
+        let mut auth_data = object.get("authData").unwrap().bytes().unwrap().to_vec();
+        auth_data[60] ^= 1;
+        let mut chain = match object.get("attStmt").unwrap().get("x5c") {
+            Some(Cbor::Array(chain)) => chain.clone(),
+            other => panic!("expected an array, got {:?}", other),
+        };
+        chain.reverse();
+        let mut entries = match object.clone() {
+            Cbor::Map(entries) => entries,
+            other => panic!("expected a map, got {:?}", other),
+        };
+        for (key, value) in entries.iter_mut() {
+            if *key == Cbor::Text("authData".into()) {
+                *value = Cbor::Bytes(auth_data.clone());
+            } else if *key == Cbor::Text("attStmt".into()) {
+                *value = Cbor::Map(vec![(Cbor::Text("x5c".into()), Cbor::Array(chain.clone()))]);
+            }
+        }
+        let problems = app_attest(&Cbor::Map(entries)).unwrap()["problems"].clone();
+        assert_eq!(problems.as_array().unwrap().len(), 2);
+        assert!(problems[0]
+            .as_str()
+            .unwrap()
+            .starts_with("the key ID is not"));
+        assert!(problems[1].as_str().unwrap().contains("was not issued by"));
+
+        assert!(matches!(
+            replaced("attStmt", None),
+            Err(JWTError::AttestationError(e)) if e == "the attestation object has no attStmt"
+        ));
+        assert!(matches!(
+            replaced("authData", None),
+            Err(JWTError::AttestationError(e))
+                if e == "the attestation object has no authData bytes"
+        ));
+        assert!(matches!(
+            replaced("authData", Some(Cbor::Bytes(vec![0; 36]))),
+            Err(JWTError::AttestationError(e)) if e == "truncated authenticator data"
+        ));
+        assert!(matches!(
+            replaced("attStmt", Some(Cbor::Map(Vec::new()))),
+            Err(JWTError::AttestationError(e)) if e == "attStmt has no x5c certificates"
+        ));
+        assert!(matches!(
+            replaced(
+                "attStmt",
+                Some(Cbor::Map(vec![(
+                    Cbor::Text("x5c".into()),
+                    Cbor::Array(vec![Cbor::Int(1)])
+                )]))
+            ),
+            Err(JWTError::AttestationError(e)) if e == "an x5c entry is not a byte string"
+        ));
+    }
+
+    #[test]
+    fn assertion_test() {
+        let decoded = decode(ASSERTION.as_bytes(), &PlayKeys::default()).unwrap();
+        assert_eq!(decoded["format"], "apple-appattest-assertion");
+        assert_eq!(decoded["authenticator_data"]["counter"], 7);
+        assert_eq!(decoded["authenticator_data"]["flags"], json!([]));
+        assert!(decoded["authenticator_data"].get("environment").is_none());
+        assert!(decoded["signature_bytes"].as_u64().unwrap() >= 70);
+        // a map of neither shape is shown as plain CBOR
+        let other = decode(b"oWFhAQ==", &PlayKeys::default()).unwrap();
+        assert_eq!(other, json!({"format": "cbor", "cbor": {"a": 1}}));
+        // a signature without authenticator data
+        assert!(matches!(
+            decode(
+                b"omlzaWduYXR1cmVBAHFhdXRoZW50aWNhdG9yRGF0YQE=",
+                &PlayKeys::default()
+            ),
+            Err(JWTError::AttestationError(e))
+                if e == "an assertion needs signature and authenticatorData byte strings"
+        ));
+    }
+
+    #[test]
+    fn play_integrity_test() {
+        let sealed = decode(PLAY_INTEGRITY.as_bytes(), &PlayKeys::default()).unwrap();
+        assert_eq!(sealed["header"], json!({"alg": "A256KW", "enc": "A256GCM"}));
+        assert!(sealed["verdict"].as_str().unwrap().starts_with("encrypted"));
+
+        let keys = PlayKeys {
+            decryption_key: Some(PLAY_DECRYPTION_KEY.to_string()),
+            verification_key: None,
+        };
+        let opened = decode(PLAY_INTEGRITY.as_bytes(), &keys).unwrap();
+        assert_eq!(opened["jws_header"], json!({"alg": "ES256"}));
+        assert_eq!(
+            opened["verdict"]["appIntegrity"]["appRecognitionVerdict"],
+            "PLAY_RECOGNIZED"
+        );
+        assert!(opened["signature"]
+            .as_str()
+            .unwrap()
+            .starts_with("not checked"));
+
+        let keys = PlayKeys {
+            verification_key: Some(PLAY_VERIFICATION_KEY.to_string()),
+            ..keys
+        };
+        let checked = decode(PLAY_INTEGRITY.as_bytes(), &keys).unwrap();
+        assert_eq!(checked["signature"], "valid ES256");
+
+        let wrong = PlayKeys {
+            decryption_key: Some(base64::encode([0u8; 32])),
+            verification_key: None,
+        };
+        assert!(matches!(
+            decode(PLAY_INTEGRITY.as_bytes(), &wrong),
+            Err(JWTError::DecryptionError(_))
+        ));
+        let short = PlayKeys {
+            decryption_key: Some(base64::encode([0u8; 16])),
+            verification_key: None,
+        };
+        assert!(matches!(
+            decode(PLAY_INTEGRITY.as_bytes(), &short),
+            Err(JWTError::KeyError(_))
+        ));
+    }
+
+    #[test]
+    fn hint_test() {
+        for (raw, kind) in [(ATTESTATION, "attestation"), (ASSERTION, "assertion")] {
+            assert!(matches!(
+                Err::<(), _>(hint(raw).unwrap()),
+                Err(JWTError::AttestationError(e))
+                    if e == format!(
+                        "this is an App Attest {}, not a JWT; decode it with `jwt-check attestation`",
+                        kind
+                    )
+            ));
+        }
+        assert!(hint(crate::SAMPLE_TOKEN).is_none());
+        assert!(hint("not base64 at all").is_none());
+        assert!(hint("oWFhAQ==").is_none());
+    }
+}
@@ -0,0 +1,745 @@
+//! `--audit`: known weaknesses in a decoded token, each with a severity.
+//!
+//! Some findings come with a `Fix`, a change to the header or claims that
+//! resolves them; `apply_fixes` makes those changes, for `--fix` to sign
+//! the result again.
+
+use crate::crypto::{constant_time_eq, hmac};
+use crate::verify::{signing_input, token_algorithm};
+use crate::{humanize_seconds, numeric_claim, JWToken, TokenKind};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Lifetimes above this are reported no matter what kind of token it is.
+pub const MAX_SANE_LIFETIME: i64 = 365 * 86_400;
+
+/// Secrets that turn up in tutorials, samples and default configs.
+const WEAK_SECRETS: &[&str] = &[
+    "",
+    "secret",
+    "Secret",
+    "SECRET",
+    "secretkey",
+    "secret-key",
+    "secret_key",
+    "mysecret",
+    "supersecret",
+    "topsecret",
+    "s3cr3t",
+    "shhhhh",
+    "password",
+    "Password",
+    "password1",
+    "passw0rd",
+    "123456",
+    "12345678",
+    "qwerty",
+    "letmein",
+    "changeme",
+    "change-me",
+    "changeit",
+    "default",
+    "admin",
+    "test",
+    "testing",
+    "dev",
+    "development",
+    "example",
+    "hello",
+    "key",
+    "jwt",
+    "jwt-secret",
+    "jwt_secret",
+    "jwtsecret",
+    "token",
+    "your-256-bit-secret",
+    "your-384-bit-secret",
+    "your-512-bit-secret",
+    "your_jwt_secret",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A section of an RFC that explains a finding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reference {
+    pub rfc: u16,
+    pub section: &'static str,
+}
+
+impl Reference {
+    pub fn url(&self) -> String {
+        format!(
+            "https://www.rfc-editor.org/rfc/rfc{}#section-{}",
+            self.rfc, self.section
+        )
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RFC {} section {}", self.rfc, self.section)
+    }
+}
+
+const fn rfc(rfc: u16, section: &'static str) -> Option<Reference> {
+    Some(Reference { rfc, section })
+}
+
+/// A change to the token that resolves a finding.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fix {
+    Header(&'static str, Value),
+    Claim(&'static str, Value),
+    /// Sets `kid`, to a value only the caller knows.
+    KeyId,
+}
+
+/// What a finding is about. The ids are stable: dashboards that track
+/// findings across runs, such as SARIF consumers, key on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    AlgNone,
+    MissingAlg,
+    AlgorithmConfusion,
+    WellKnownSecret,
+    WeakSecret,
+    ShortSecret,
+    NoExpiry,
+    LongLifetime,
+    EmbeddedKey,
+    KeySetUrl,
+    CertificateUrl,
+    EmbeddedChain,
+    DuplicateKey,
+    MissingTyp,
+    MissingKid,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 15] = [
+        Rule::AlgNone,
+        Rule::MissingAlg,
+        Rule::AlgorithmConfusion,
+        Rule::WellKnownSecret,
+        Rule::WeakSecret,
+        Rule::ShortSecret,
+        Rule::NoExpiry,
+        Rule::LongLifetime,
+        Rule::EmbeddedKey,
+        Rule::KeySetUrl,
+        Rule::CertificateUrl,
+        Rule::EmbeddedChain,
+        Rule::DuplicateKey,
+        Rule::MissingTyp,
+        Rule::MissingKid,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Rule::AlgNone => "alg-none",
+            Rule::MissingAlg => "missing-alg",
+            Rule::AlgorithmConfusion => "algorithm-confusion",
+            Rule::WellKnownSecret => "well-known-secret",
+            Rule::WeakSecret => "weak-secret",
+            Rule::ShortSecret => "short-secret",
+            Rule::NoExpiry => "no-expiry",
+            Rule::LongLifetime => "long-lifetime",
+            Rule::EmbeddedKey => "embedded-jwk",
+            Rule::KeySetUrl => "jku-header",
+            Rule::CertificateUrl => "x5u-header",
+            Rule::EmbeddedChain => "x5c-header",
+            Rule::DuplicateKey => "duplicate-key",
+            Rule::MissingTyp => "missing-typ",
+            Rule::MissingKid => "missing-kid",
+        }
+    }
+
+    /// One line saying what the rule looks for.
+    pub fn summary(self) -> &'static str {
+        match self {
+            Rule::AlgNone => "The token is unsigned (`alg: none`).",
+            Rule::MissingAlg => "The header has no `alg`.",
+            Rule::AlgorithmConfusion => "An HMAC-signed token where a public key is expected.",
+            Rule::WellKnownSecret => {
+                "The signature was made with a secret from samples or default configs."
+            }
+            Rule::WeakSecret => "The expected HMAC secret is one from samples or default configs.",
+            Rule::ShortSecret => "The HMAC secret is shorter than the hash output.",
+            Rule::NoExpiry => "The token has no `exp`, so it never expires.",
+            Rule::LongLifetime => "The token is valid for more than a year.",
+            Rule::EmbeddedKey => "The header embeds its own verification key (`jwk`).",
+            Rule::KeySetUrl => "The header points at a key set URL of its choosing (`jku`).",
+            Rule::CertificateUrl => {
+                "The header points at a certificate URL of its choosing (`x5u`)."
+            }
+            Rule::EmbeddedChain => "The header carries its own certificate chain (`x5c`).",
+            Rule::DuplicateKey => "A JSON object repeats a key, which parsers resolve differently.",
+            Rule::MissingTyp => "The header has no `typ`.",
+            Rule::MissingKid => "An asymmetrically signed token's header has no `kid`.",
+        }
+    }
+
+    pub fn severity(self) -> Severity {
+        match self {
+            Rule::AlgNone
+            | Rule::MissingAlg
+            | Rule::AlgorithmConfusion
+            | Rule::WellKnownSecret
+            | Rule::WeakSecret
+            | Rule::EmbeddedKey
+            | Rule::DuplicateKey => Severity::High,
+            Rule::ShortSecret
+            | Rule::NoExpiry
+            | Rule::LongLifetime
+            | Rule::KeySetUrl
+            | Rule::CertificateUrl => Severity::Medium,
+            Rule::EmbeddedChain | Rule::MissingTyp | Rule::MissingKid => Severity::Low,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub rule: Rule,
+    /// The rule's severity, which decides whether the finding fails the run.
+    pub severity: Severity,
+    pub message: String,
+    pub reference: Option<Reference>,
+    pub fix: Option<Fix>,
+}
+
+// As in the `--audit --out` document, with its keys sorted: the rule by its
+// id and the reference by its URL. The fix is left out, since applying it
+// is `--fix`'s job.
+impl Serialize for Finding {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Finding", 4)?;
+        state.serialize_field("message", &self.message)?;
+        state.serialize_field("reference", &self.reference.map(|r| r.url()))?;
+        state.serialize_field("rule", self.rule.id())?;
+        state.serialize_field("severity", &self.severity)?;
+        state.end()
+    }
+}
+
+/// What the caller knows beyond the token itself.
+pub struct AuditOptions<'a> {
+    /// The `--secret` the token is meant to be signed with.
+    pub secret: Option<&'a [u8]>,
+    /// Whether a public key or JWKS was supplied, so an HMAC `alg` is
+    /// unexpected.
+    pub expect_asymmetric: bool,
+}
+
+/// The `--audit --out` document, as `jwt-check schema audit` describes it.
+pub fn document(token: &JWToken, findings: &[Finding]) -> Value {
+    serde_json::json!({
+        "header": token.header,
+        "payload": token.payload,
+        "findings": findings,
+    })
+}
+
+/// Audits `token`, whose compact form is `raw`. `payload` is the payload
+/// with timestamps normalized to seconds.
+pub fn audit(
+    raw: &str,
+    token: &JWToken,
+    payload: &Value,
+    now: i64,
+    options: &AuditOptions,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut report =
+        |rule: Rule, message: String, reference: Option<Reference>, fix: Option<Fix>| {
+            findings.push(Finding {
+                rule,
+                severity: rule.severity(),
+                message,
+                reference,
+                fix,
+            })
+        };
+
+    let alg = token.header.get("alg").and_then(Value::as_str);
+    match alg {
+        Some(alg) if alg.eq_ignore_ascii_case("none") => report(
+            Rule::AlgNone,
+            "alg is `none`: the token is unsigned and anyone can forge it".to_string(),
+            rfc(8725, "3.1"),
+            None,
+        ),
+        None => report(
+            Rule::MissingAlg,
+            "header has no alg".to_string(),
+            rfc(7515, "4.1.1"),
+            None,
+        ),
+        _ => (),
+    }
+    if let Ok(alg) = token_algorithm(token) {
+        if alg.is_symmetric() {
+            if options.expect_asymmetric {
+                report(
+                    Rule::AlgorithmConfusion,
+                    format!(
+                        "{} is an HMAC algorithm but a public key was supplied; \
+                         a verifier that accepts it may be open to algorithm confusion",
+                        alg
+                    ),
+                    rfc(8725, "2.1"),
+                    None,
+                );
+            }
+            let message = signing_input(raw).as_bytes();
+            let guessed = WEAK_SECRETS.iter().find(|secret| {
+                constant_time_eq(
+                    &hmac(alg.hash(), secret.as_bytes(), message),
+                    &token.signature,
+                )
+            });
+            if let Some(secret) = guessed {
+                report(
+                    Rule::WellKnownSecret,
+                    format!("signature was made with the well-known secret `{}`", secret),
+                    rfc(8725, "3.5"),
+                    None,
+                );
+            }
+            if let Some(secret) = options.secret {
+                if guessed.is_none() && WEAK_SECRETS.iter().any(|w| w.as_bytes() == secret) {
+                    report(
+                        Rule::WeakSecret,
+                        "--secret is a well-known secret from samples and default configs"
+                            .to_string(),
+                        rfc(8725, "3.5"),
+                        None,
+                    );
+                }
+                let wanted = alg.hash().output_len();
+                if secret.len() < wanted {
+                    report(
+                        Rule::ShortSecret,
+                        format!(
+                            "--secret is {} bytes; {} needs at least {}",
+                            secret.len(),
+                            alg,
+                            wanted
+                        ),
+                        rfc(7518, "3.2"),
+                        None,
+                    );
+                }
+            }
+        }
+    }
+
+    // expiry fixes use the lifetime recommended for the kind of token
+    let issued = numeric_claim(payload, "iat").unwrap_or(now);
+    let lifetime = TokenKind::infer(&token.header, payload)
+        .map_or(MAX_SANE_LIFETIME, TokenKind::recommended_max_lifetime);
+    let expiry = issued
+        .checked_add(lifetime)
+        .map(|exp| Fix::Claim("exp", Value::from(exp)));
+    match numeric_claim(payload, "exp") {
+        None => report(
+            Rule::NoExpiry,
+            "no exp claim: the token never expires".to_string(),
+            rfc(7519, "4.1.4"),
+            expiry,
+        ),
+        Some(exp) => match exp.checked_sub(issued) {
+            Some(lifetime) if lifetime > MAX_SANE_LIFETIME => report(
+                Rule::LongLifetime,
+                format!("token is valid for {}", humanize_seconds(lifetime)),
+                rfc(7519, "4.1.4"),
+                expiry,
+            ),
+            // too far apart for 64 bits: as good as never expiring
+            None if exp > issued => report(
+                Rule::LongLifetime,
+                "token's lifetime is unbounded: exp - iat overflows 64 bits".to_string(),
+                rfc(7519, "4.1.4"),
+                expiry,
+            ),
+            _ => {}
+        },
+    }
+
+    let embedded = [
+        (
+            "jwk",
+            Rule::EmbeddedKey,
+            "embeds its own verification key",
+            "4.1.3",
+        ),
+        (
+            "jku",
+            Rule::KeySetUrl,
+            "points at a key set URL of its choosing",
+            "4.1.2",
+        ),
+        (
+            "x5u",
+            Rule::CertificateUrl,
+            "points at a certificate URL of its choosing",
+            "4.1.5",
+        ),
+        (
+            "x5c",
+            Rule::EmbeddedChain,
+            "carries its own certificate chain",
+            "4.1.6",
+        ),
+    ];
+    for (name, rule, what, section) in embedded.iter() {
+        if token.header.get(*name).is_some() {
+            report(
+                *rule,
+                format!(
+                    "header has `{}`: the token {}; never trust it without pinning or chain validation",
+                    name, what
+                ),
+                rfc(7515, section),
+                None,
+            );
+        }
+    }
+
+    let mut parts = raw.split('.');
+    for (section, reference) in [("header", rfc(7515, "4")), ("payload", rfc(7519, "4"))] {
+        let json = parts
+            .next()
+            .and_then(|part| base64::decode_config(part, base64::URL_SAFE).ok())
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .unwrap_or_default();
+        for key in duplicate_keys(&json) {
+            report(
+                Rule::DuplicateKey,
+                format!(
+                    "{} repeats the key `{}`; parsers disagree on which value wins",
+                    section, key
+                ),
+                reference,
+                None,
+            );
+        }
+    }
+
+    if token.header.get("typ").is_none() {
+        report(
+            Rule::MissingTyp,
+            "header has no typ: explicit typing keeps a token of one kind from passing as another"
+                .to_string(),
+            rfc(8725, "3.11"),
+            Some(Fix::Header("typ", Value::from("JWT"))),
+        );
+    }
+    let asymmetric = token_algorithm(token).is_ok_and(|alg| !alg.is_symmetric());
+    if asymmetric && token.header.get("kid").is_none() {
+        report(
+            Rule::MissingKid,
+            "header has no kid: verifiers must try every key the issuer publishes".to_string(),
+            rfc(7515, "4.1.4"),
+            Some(Fix::KeyId),
+        );
+    }
+    findings
+}
+
+/// `token` with the fixes of `findings` made, and one `path: old -> new`
+/// line per change. `kid` is what `Fix::KeyId` sets; without it that fix
+/// is left out.
+pub fn apply_fixes(
+    token: &JWToken,
+    findings: &[Finding],
+    kid: Option<&str>,
+) -> (JWToken, Vec<String>) {
+    let mut fixed = token.clone();
+    let mut changes = Vec::new();
+    for fix in findings.iter().filter_map(|f| f.fix.as_ref()) {
+        let (section, part, name, value) = match fix {
+            Fix::Header(name, value) => ("header", &mut fixed.header, *name, value.clone()),
+            Fix::Claim(name, value) => ("payload", &mut fixed.payload, *name, value.clone()),
+            Fix::KeyId => match kid {
+                Some(kid) => ("header", &mut fixed.header, "kid", Value::from(kid)),
+                None => continue,
+            },
+        };
+        if let Some(object) = part.as_object_mut() {
+            let old = object.insert(name.to_string(), value.clone());
+            changes.push(format!(
+                "{}.{}: {} -> {}",
+                section,
+                name,
+                old.map_or("(absent)".to_string(), |old| old.to_string()),
+                value
+            ));
+        }
+    }
+    (fixed, changes)
+}
+
+/// Object keys that appear more than once in the same object. `Value`
+/// keeps only the last one, so this works on the JSON text.
+fn duplicate_keys(json: &str) -> Vec<String> {
+    // one entry per open object (`Some`: keys seen, expecting a key) or array
+    let mut stack: Vec<Option<(HashSet<String>, bool)>> = Vec::new();
+    let mut duplicates = Vec::new();
+    let mut chars = json.char_indices();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '{' => stack.push(Some((HashSet::new(), true))),
+            '[' => stack.push(None),
+            '}' | ']' => {
+                stack.pop();
+            }
+            ',' => {
+                if let Some(Some((_, expect_key))) = stack.last_mut() {
+                    *expect_key = true;
+                }
+            }
+            '"' => {
+                let mut escaped = false;
+                let mut end = json.len();
+                for (at, c) in chars.by_ref() {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => {
+                            end = at + 1;
+                            break;
+                        }
+                        _ => (),
+                    }
+                }
+                if let Some(Some((seen, expect_key))) = stack.last_mut() {
+                    if *expect_key {
+                        *expect_key = false;
+                        let key = json
+                            .get(start..end)
+                            .and_then(|literal| serde_json::from_str::<String>(literal).ok())
+                            .unwrap_or_default();
+                        if !seen.insert(key.clone()) && !duplicates.contains(&key) {
+                            duplicates.push(key);
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    duplicates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, SAMPLE_TOKEN};
+
+    fn b64(json: &str) -> String {
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn run(raw: &str, options: &AuditOptions) -> Vec<(Severity, String)> {
+        let token = parse(raw).unwrap();
+        audit(raw, &token, &token.payload, 1_600_000_000, options)
+            .into_iter()
+            .map(|f| (f.severity, f.message))
+            .collect()
+    }
+
+    #[test]
+    fn audit_test() {
+        let none = AuditOptions {
+            secret: None,
+            expect_asymmetric: false,
+        };
+        // the jwt.io sample: well-known secret, no exp
+        let findings = run(SAMPLE_TOKEN, &none);
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].0, Severity::High);
+        assert!(findings[0].1.contains("`your-256-bit-secret`"));
+        assert!(findings[1].1.starts_with("no exp claim"));
+
+        let weak = AuditOptions {
+            secret: Some(b"short"),
+            expect_asymmetric: true,
+        };
+        let findings = run(SAMPLE_TOKEN, &weak);
+        assert!(findings[0].1.contains("algorithm confusion"));
+        assert!(findings[2]
+            .1
+            .starts_with("--secret is 5 bytes; HS256 needs at least 32"));
+
+        let raw = format!(
+            "{}.{}.",
+            b64(r#"{"alg":"none","jku":"https://evil.example/jwks","jwk":{}}"#),
+            b64(r#"{"sub":"a","exp":1900000000,"iat":1,"n":{"x":1,"x":2},"sub":"b"}"#)
+        );
+        let findings = run(&raw, &none);
+        let severities: Vec<Severity> = findings.iter().map(|f| f.0).collect();
+        assert_eq!(
+            severities,
+            vec![
+                Severity::High,
+                Severity::Medium,
+                Severity::High,
+                Severity::Medium,
+                Severity::High,
+                Severity::High,
+                Severity::Low,
+            ]
+        );
+        assert!(findings[6].1.starts_with("header has no typ"));
+        assert!(findings[1].1.starts_with("token is valid for"));
+        assert!(findings[4].1.contains("`x`"));
+        assert!(findings[5].1.contains("`sub`"));
+
+        let token = parse(SAMPLE_TOKEN).unwrap();
+        let reference = audit(SAMPLE_TOKEN, &token, &token.payload, 0, &none)[0]
+            .reference
+            .unwrap();
+        assert_eq!(reference.to_string(), "RFC 8725 section 3.5");
+        assert_eq!(
+            reference.url(),
+            "https://www.rfc-editor.org/rfc/rfc8725#section-3.5"
+        );
+
+        assert!(duplicate_keys(r#"{"a":[{"a":1},{"a":2}],"b":"a,\"a"}"#).is_empty());
+    }
+
+    #[test]
+    fn rules_test() {
+        let none = AuditOptions {
+            secret: None,
+            expect_asymmetric: false,
+        };
+        let token = parse(SAMPLE_TOKEN).unwrap();
+        let rules: Vec<&str> = audit(SAMPLE_TOKEN, &token, &token.payload, 0, &none)
+            .iter()
+            .map(|f| f.rule.id())
+            .collect();
+        assert_eq!(rules, ["well-known-secret", "no-expiry"]);
+        let findings = audit(SAMPLE_TOKEN, &token, &token.payload, 0, &none);
+        let json = serde_json::to_value(&findings[0]).unwrap();
+        assert_eq!(
+            (&json["rule"], &json["severity"], &json["reference"]),
+            (
+                &Value::from("well-known-secret"),
+                &Value::from("high"),
+                &Value::from("https://www.rfc-editor.org/rfc/rfc8725#section-3.5")
+            )
+        );
+        assert_eq!(json["message"], findings[0].message);
+        let mut ids: Vec<&str> = Rule::ALL.iter().map(|rule| rule.id()).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), Rule::ALL.len());
+        assert!(Rule::ALL.iter().all(|rule| rule.summary().ends_with('.')));
+    }
+
+    #[test]
+    fn unbounded_lifetime_test() {
+        let none = AuditOptions {
+            secret: None,
+            expect_asymmetric: false,
+        };
+        let raw = format!(
+            "{}.{}.",
+            b64(r#"{"alg":"HS256","typ":"JWT"}"#),
+            b64(r#"{"iat":-9223372036854775807,"exp":9223372036854775807}"#)
+        );
+        let findings = run(&raw, &none);
+        assert_eq!(
+            findings,
+            vec![(
+                Severity::Medium,
+                "token's lifetime is unbounded: exp - iat overflows 64 bits".to_string()
+            )]
+        );
+        // exp long before iat is no lifetime at all, and a fix can't go past i64::MAX
+        let raw = format!(
+            "{}.{}.",
+            b64(r#"{"alg":"HS256","typ":"JWT"}"#),
+            b64(r#"{"iat":9223372036854775807,"exp":-9223372036854775807}"#)
+        );
+        assert!(run(&raw, &none).is_empty());
+        let raw = format!(
+            "{}.{}.",
+            b64(r#"{"alg":"HS256","typ":"JWT"}"#),
+            b64(r#"{"iat":9223372036854775807}"#)
+        );
+        let token = parse(&raw).unwrap();
+        let findings = audit(&raw, &token, &token.payload, 0, &none);
+        assert!(findings[0].message.starts_with("no exp claim"));
+        assert_eq!(findings[0].fix, None);
+    }
+
+    #[test]
+    fn apply_fixes_test() {
+        let raw = format!(
+            "{}.{}.",
+            b64(r#"{"alg":"RS256"}"#),
+            b64(r#"{"sub":"a","scope":"read","iat":1000,"exp":900000000}"#)
+        );
+        let token = parse(&raw).unwrap();
+        let options = AuditOptions {
+            secret: None,
+            expect_asymmetric: true,
+        };
+        let findings = audit(&raw, &token, &token.payload, 2000, &options);
+        let fixes: Vec<&Fix> = findings.iter().filter_map(|f| f.fix.as_ref()).collect();
+        assert_eq!(
+            fixes,
+            vec![
+                // an access token, so an hour
+                &Fix::Claim("exp", Value::from(4600)),
+                &Fix::Header("typ", Value::from("JWT")),
+                &Fix::KeyId,
+            ]
+        );
+        let (fixed, changes) = apply_fixes(&token, &findings, Some("2024-06"));
+        assert_eq!(
+            changes,
+            vec![
+                "payload.exp: 900000000 -> 4600",
+                "header.typ: (absent) -> \"JWT\"",
+                "header.kid: (absent) -> \"2024-06\"",
+            ]
+        );
+        assert_eq!(fixed.header["kid"], "2024-06");
+        assert_eq!(fixed.payload["sub"], "a");
+        let fixed = apply_fixes(
+            &fixed,
+            &audit(&raw, &fixed, &fixed.payload, 2000, &options),
+            None,
+        )
+        .0;
+        assert!(audit(&raw, &fixed, &fixed.payload, 2000, &options)
+            .iter()
+            .all(|f| f.fix.is_none()));
+        assert_eq!(apply_fixes(&token, &findings, None).1.len(), 2);
+    }
+}
@@ -0,0 +1,631 @@
+//! `--batch`: checks many tokens, one per line, across all cores, and
+//! reports each as one NDJSON record followed by a summary record.
+
+use crate::assertions::{self, Assertion};
+use crate::canary::{self, Canary};
+use crate::syslog::{self, Priority};
+use crate::{
+    extract_token, normalize_timestamps, parse, parse_duration, parse_time, validate, ClaimChecks,
+    Freshness, JWTError, JWToken, TimeUnit,
+};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How one token in the batch fared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Valid,
+    Invalid,
+    Expired,
+}
+
+impl Status {
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Valid => "valid",
+            Status::Invalid => "invalid",
+            Status::Expired => "expired",
+        }
+    }
+}
+
+/// The lines of `input` that aren't blank, numbered from 1.
+pub fn lines(input: &str) -> Vec<(usize, &str)> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty())
+        .collect()
+}
+
+/// The token on each line, its time claims in seconds, for the views that
+/// look at the batch as a whole (`--stats`, `--sessions`, `--graph`).
+pub fn decode(lines: &[(usize, &str)], unit: TimeUnit) -> Vec<(usize, Result<JWToken, JWTError>)> {
+    lines
+        .iter()
+        .map(|(line, text)| {
+            let token = parse(extract_token(text)).map(|token| JWToken {
+                payload: normalize_timestamps(&token.payload, unit).0,
+                ..token
+            });
+            (*line, token)
+        })
+        .collect()
+}
+
+/// `--where`: each argument's conditions, joined by `and`.
+pub fn where_conditions<'a>(
+    texts: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<Assertion>, JWTError> {
+    let mut conditions = Vec::new();
+    for text in texts {
+        conditions.extend(assertions::conjunction(text)?);
+    }
+    Ok(conditions)
+}
+
+/// `--issued-after`, `--issued-before` and `--expires-within`, which narrow
+/// a batch to a window of `iat` or `exp`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Window<'a> {
+    pub issued_after: Option<&'a str>,
+    pub issued_before: Option<&'a str>,
+    pub expires_within: Option<&'a str>,
+}
+
+impl Window<'_> {
+    /// The window as `--where` conditions on `iat` and `exp`; a token
+    /// without the claim is left out.
+    pub fn conditions(&self, now: i64) -> Result<Vec<Assertion>, JWTError> {
+        let mut conditions = Vec::new();
+        if let Some(after) = self.issued_after {
+            conditions.push(format!("iat >= {}", parse_time(after, now)?));
+        }
+        if let Some(before) = self.issued_before {
+            conditions.push(format!("iat < {}", parse_time(before, now)?));
+        }
+        if let Some(within) = self.expires_within {
+            let within = parse_duration(within)?;
+            conditions.push(format!("exp >= {}", now));
+            conditions.push(format!("exp <= {}", now.saturating_add(within)));
+        }
+        where_conditions(conditions.iter().map(String::as_str))
+    }
+}
+
+/// Whether the token on a line holds to `conditions`. A line that isn't a
+/// token holds to none.
+pub fn selected(conditions: &[Assertion], text: &str, unit: TimeUnit, now: i64) -> bool {
+    match parse(extract_token(text)) {
+        Ok(token) => {
+            let (payload, _) = normalize_timestamps(&token.payload, unit);
+            let token = JWToken { payload, ..token };
+            assertions::all_hold(conditions, &token, now)
+        }
+        Err(_) => false,
+    }
+}
+
+/// What checks the signature of a batch line's token: given the raw token,
+/// the token and its time claims in seconds, it answers what is wrong.
+pub type Verifier<'a> = dyn Fn(&str, &JWToken, &Value) -> Vec<String> + Sync + 'a;
+
+/// Decodes and checks one batch line: its signature with `verify`, its
+/// claims with `checks`. Expiry alone makes a token `expired`; any other
+/// failure makes it `invalid`.
+pub fn record(
+    line: usize,
+    text: &str,
+    verify: Option<&Verifier>,
+    checks: Option<&ClaimChecks>,
+    unit: TimeUnit,
+    now: i64,
+) -> (Value, Status) {
+    let raw = extract_token(text);
+    let token = match parse(raw) {
+        Ok(token) => token,
+        Err(e) => {
+            let record = serde_json::json!({
+                "line": line,
+                "header": null,
+                "payload": null,
+                "status": Status::Invalid.name(),
+                "error": e.to_string(),
+            });
+            return (record, Status::Invalid);
+        }
+    };
+    let (timestamps, _) = normalize_timestamps(&token.payload, unit);
+    let mut errors = match verify {
+        Some(verify) => verify(raw, &token, &timestamps),
+        None => Vec::new(),
+    };
+    let mut expired = None;
+    match checks {
+        Some(checks) => {
+            for failure in validate(&timestamps, now, checks) {
+                if failure.starts_with("expired") {
+                    expired = Some(failure);
+                } else {
+                    errors.push(format!("claims: {}", failure));
+                }
+            }
+        }
+        None => {
+            if Freshness::classify(&timestamps, now, 0) == Freshness::Expired {
+                expired = Some("expired".to_string());
+            }
+        }
+    }
+    let status = if !errors.is_empty() {
+        Status::Invalid
+    } else if expired.is_some() {
+        Status::Expired
+    } else {
+        Status::Valid
+    };
+    if let Some(expired) = expired {
+        errors.push(format!("claims: {}", expired));
+    }
+    let record = serde_json::json!({
+        "line": line,
+        "header": token.header,
+        "payload": token.payload,
+        "status": status.name(),
+        "error": if errors.is_empty() { Value::Null } else { Value::String(errors.join("; ")) },
+    });
+    (record, status)
+}
+
+/// How `run` goes through a batch.
+#[derive(Default)]
+pub struct Options<'a> {
+    /// Threads checking tokens.
+    pub workers: usize,
+    /// How often records written so far are flushed; zero flushes each one.
+    pub flush_interval: Duration,
+    /// `--canary`: a second verifier asked about every line.
+    pub canary: Option<&'a Canary>,
+    /// Whether each failed token gets a syslog line of its own.
+    pub logged: bool,
+}
+
+/// How a batch went.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Outcome {
+    /// Each checked token's status, in input order.
+    pub statuses: Vec<Status>,
+    /// The lines left unchecked by a cancelled batch.
+    pub unchecked: usize,
+    /// The tokens the canary disagreed about.
+    pub divergent: usize,
+}
+
+impl Outcome {
+    /// How many tokens weren't valid.
+    pub fn failed(&self) -> usize {
+        self.statuses
+            .iter()
+            .filter(|s| **s != Status::Valid)
+            .count()
+    }
+}
+
+/// Checks every line with `check`, writing a record for each to `out` as it
+/// finishes and the summary record last. With a canary, each record gets a
+/// `canary` field and each divergence an error on syslog. Once `cancelled`
+/// says so, the lines not yet taken are left unchecked.
+pub fn run<W: Write + Send>(
+    lines: &[(usize, &str)],
+    check: impl Fn(usize, &str) -> (Value, Status) + Sync,
+    options: &Options,
+    out: W,
+    cancelled: &(dyn Fn() -> bool + Sync),
+) -> Result<(Outcome, W), JWTError> {
+    let out = Mutex::new(std::io::BufWriter::new(out));
+    let lock = || {
+        out.lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    };
+    let interval = options.flush_interval;
+    let mut outcome = Outcome::default();
+    let (done, finished) = mpsc::channel::<()>();
+    std::thread::scope(|scope| {
+        if !interval.is_zero() {
+            scope.spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(interval) {
+                    let _ = lock().flush();
+                }
+            });
+        }
+        let streamed = stream(
+            lines,
+            options.workers,
+            |(line, text)| {
+                let (mut record, status) = check(*line, text);
+                let mut divergence = None;
+                if let (Some(canary), Some(fields)) = (options.canary, record.as_object_mut()) {
+                    let (field, diverges) = canary::compare(status, canary.ask(text));
+                    if diverges {
+                        divergence = Some(format!(
+                            "canary: line {}: {} here, {}",
+                            line,
+                            status.name(),
+                            canary::says(&field)
+                        ));
+                    }
+                    fields.insert("canary".to_string(), field);
+                }
+                (record, status, divergence)
+            },
+            |(record, status, divergence)| {
+                outcome.statuses.push(status);
+                if options.logged && status != Status::Valid {
+                    let priority = match status {
+                        Status::Expired => Priority::Notice,
+                        _ => Priority::Warning,
+                    };
+                    let line = format!(
+                        "batch: line {}: {}: {}",
+                        record.get("line").unwrap_or(&Value::Null),
+                        status.name(),
+                        record.get("error").and_then(Value::as_str).unwrap_or("-")
+                    );
+                    syslog::log(priority, &line);
+                }
+                if let Some(divergence) = divergence {
+                    outcome.divergent += 1;
+                    syslog::log(Priority::Err, &divergence);
+                }
+                let mut out = lock();
+                writeln!(out, "{}", record)?;
+                if interval.is_zero() {
+                    out.flush()?;
+                }
+                Ok::<(), std::io::Error>(())
+            },
+            cancelled,
+        );
+        drop(done);
+        streamed
+    })?;
+    outcome.unchecked = lines.len().saturating_sub(outcome.statuses.len());
+    let mut summary = summary(&outcome.statuses, outcome.unchecked);
+    if let (Some(_), Some(fields)) = (
+        options.canary,
+        summary.get_mut("summary").and_then(Value::as_object_mut),
+    ) {
+        fields.insert("divergent".to_string(), outcome.divergent.into());
+    }
+    let mut out = out
+        .into_inner()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    writeln!(out, "{}", summary)?;
+    let out = out
+        .into_inner()
+        .map_err(|e| JWTError::from(e.into_error()))?;
+    Ok((outcome, out))
+}
+
+/// Runs `check` over every item on `workers` threads and hands each result
+/// to `emit` in input order, as soon as it and every result before it are
+/// done. Workers take the next unclaimed item, so one slow token doesn't
+/// hold up a whole chunk. Stops early with the error when `emit` fails.
+/// Once `cancelled` says so, no more items are taken, and those already
+/// taken are finished and emitted.
+pub fn stream<T, R, F, E>(
+    items: &[T],
+    workers: usize,
+    check: F,
+    mut emit: impl FnMut(R) -> Result<(), E>,
+    cancelled: &(dyn Fn() -> bool + Sync),
+) -> Result<(), E>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        let handles: Vec<_> = (0..workers.clamp(1, items.len().max(1)))
+            .map(|_| {
+                let (sender, next, stop, check) = (sender.clone(), &next, &stop, &check);
+                scope.spawn(move || {
+                    while !stop.load(Ordering::Relaxed) && !cancelled() {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let item = match items.get(index) {
+                            Some(item) => item,
+                            None => return,
+                        };
+                        if sender.send((index, check(item))).is_err() {
+                            return;
+                        }
+                    }
+                })
+            })
+            .collect();
+        drop(sender);
+        // results that finished ahead of an earlier one still running
+        let mut pending = BTreeMap::new();
+        let mut due = 0;
+        for (index, result) in receiver {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&due) {
+                due += 1;
+                if let Err(e) = emit(result) {
+                    stop.store(true, Ordering::Relaxed);
+                    return Err(e);
+                }
+            }
+        }
+        // only left over when a worker panicked; its item is skipped
+        for result in pending.into_values() {
+            emit(result)?;
+        }
+        for handle in handles {
+            let _ = handle.join();
+        }
+        Ok(())
+    })
+}
+
+/// The closing record: how many tokens ended up in each status, and how
+/// many an interrupted batch left `unchecked`.
+pub fn summary(statuses: &[Status], unchecked: usize) -> Value {
+    let count = |status: Status| statuses.iter().filter(|s| **s == status).count();
+    let mut summary = serde_json::json!({
+        "total": statuses.len(),
+        "valid": count(Status::Valid),
+        "invalid": count(Status::Invalid),
+        "expired": count(Status::Expired),
+    });
+    if let (Some(summary), true) = (summary.as_object_mut(), unchecked > 0) {
+        summary.insert("unchecked".to_string(), unchecked.into());
+    }
+    serde_json::json!({ "summary": summary })
+}
+
+/// `--fail-threshold`: how many failed tokens a batch tolerates before it
+/// exits non-zero. Without one, any failure fails the batch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    /// At most this many failed tokens (`3`).
+    Count(usize),
+    /// At most this fraction of the batch (`0.01` or `1%`).
+    Fraction(f64),
+}
+
+impl FromStr for Threshold {
+    type Err = JWTError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            JWTError::InvalidArgumentError(format!(
+                "`{}` is not a fail threshold; expected a count (3), a fraction (0.01) or a percentage (1%)",
+                s
+            ).into())
+        };
+        let fraction = match s.strip_suffix('%') {
+            Some(percent) => percent.parse::<f64>().map_err(|_| invalid())? / 100.0,
+            None if s.contains('.') => s.parse::<f64>().map_err(|_| invalid())?,
+            None => return s.parse().map(Threshold::Count).map_err(|_| invalid()),
+        };
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(invalid());
+        }
+        Ok(Threshold::Fraction(fraction))
+    }
+}
+
+impl Threshold {
+    /// Whether `failed` tokens out of `total` are more than tolerated.
+    pub fn exceeded(self, failed: usize, total: usize) -> bool {
+        match self {
+            Threshold::Count(count) => failed > count,
+            Threshold::Fraction(fraction) => failed as f64 > fraction * total as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SAMPLE_TOKEN;
+
+    #[test]
+    fn stream_keeps_input_order_test() {
+        let items: Vec<u64> = (0..500).collect();
+        let mut doubled = Vec::new();
+        stream(
+            &items,
+            8,
+            |n| {
+                if n % 7 == 0 {
+                    std::thread::sleep(std::time::Duration::from_micros(50));
+                }
+                n * 2
+            },
+            |n| {
+                doubled.push(n);
+                Ok::<(), ()>(())
+            },
+            &|| false,
+        )
+        .unwrap();
+        assert_eq!(doubled, items.iter().map(|n| n * 2).collect::<Vec<_>>());
+        let mut seen = 0;
+        assert_eq!(
+            stream(
+                &items,
+                4,
+                |n| *n,
+                |_| {
+                    seen += 1;
+                    if seen == 3 {
+                        Err("closed")
+                    } else {
+                        Ok(())
+                    }
+                },
+                &|| false,
+            ),
+            Err("closed")
+        );
+        assert_eq!(seen, 3);
+        assert_eq!(
+            stream(&Vec::<u64>::new(), 4, |n| *n, |_| Err(()), &|| false),
+            Ok(())
+        );
+        // cancelled partway: whatever was taken is finished, in order
+        let checked = AtomicUsize::new(0);
+        let mut emitted = Vec::new();
+        stream(
+            &items,
+            4,
+            |n| {
+                checked.fetch_add(1, Ordering::SeqCst);
+                *n
+            },
+            |n| {
+                emitted.push(n);
+                Ok::<(), ()>(())
+            },
+            &|| checked.load(Ordering::SeqCst) >= 10,
+        )
+        .unwrap();
+        assert!(emitted.len() >= 10 && emitted.len() < items.len());
+        assert_eq!(emitted, (0..emitted.len() as u64).collect::<Vec<_>>());
+        let statuses = [
+            Status::Valid,
+            Status::Expired,
+            Status::Invalid,
+            Status::Valid,
+        ];
+        assert_eq!(
+            summary(&statuses, 0),
+            serde_json::json!({"summary": {"total": 4, "valid": 2, "invalid": 1, "expired": 1}})
+        );
+        assert_eq!(summary(&statuses, 6)["summary"]["unchecked"], 6);
+    }
+
+    #[test]
+    fn where_test() {
+        let conditions = where_conditions([
+            "payload.sub == \"1234567890\" and has name",
+            "iat < 1600000000",
+        ])
+        .unwrap();
+        assert_eq!(conditions.len(), 3);
+        let now = 1_700_000_000;
+        assert!(selected(&conditions, SAMPLE_TOKEN, TimeUnit::Auto, now));
+        let bearer = format!("Authorization: Bearer {}", SAMPLE_TOKEN);
+        assert!(selected(&conditions, &bearer, TimeUnit::Auto, now));
+        // with --assume-ms the conditions see iat in seconds too
+        assert!(selected(&conditions, SAMPLE_TOKEN, TimeUnit::Millis, now));
+        let other = assertions::conjunction("sub == user-123").unwrap();
+        assert!(!selected(&other, SAMPLE_TOKEN, TimeUnit::Auto, now));
+        assert!(!selected(&conditions, "not a token", TimeUnit::Auto, now));
+        assert!(where_conditions(["sub is 5"]).is_err());
+
+        let window = Window {
+            issued_after: Some("2018-01-01"),
+            issued_before: Some("2018-01-19T00:00:00Z"),
+            ..Window::default()
+        };
+        let window = window.conditions(now).unwrap();
+        assert!(selected(&window, SAMPLE_TOKEN, TimeUnit::Auto, now));
+        let window = Window {
+            issued_after: Some("24h"),
+            ..Window::default()
+        };
+        assert!(!selected(
+            &window.conditions(now).unwrap(),
+            SAMPLE_TOKEN,
+            TimeUnit::Auto,
+            now
+        ));
+        // SAMPLE_TOKEN has no exp
+        let window = Window {
+            expires_within: Some("24h"),
+            ..Window::default()
+        };
+        let window = window.conditions(now).unwrap();
+        assert_eq!(window.len(), 2);
+        assert!(!selected(&window, SAMPLE_TOKEN, TimeUnit::Auto, now));
+        for bad in [
+            Window {
+                issued_after: Some("2018-13-01"),
+                ..Window::default()
+            },
+            Window {
+                expires_within: Some("soon"),
+                ..Window::default()
+            },
+        ] {
+            assert!(bad.conditions(now).is_err());
+        }
+    }
+
+    #[test]
+    fn run_test() {
+        let input = format!("{}\n\nnot a token\n{}\n", SAMPLE_TOKEN, SAMPLE_TOKEN);
+        let lines = lines(&input);
+        assert_eq!(
+            lines.iter().map(|(line, _)| *line).collect::<Vec<_>>(),
+            [1, 3, 4]
+        );
+        let forged: &Verifier = &|_, token, _| match token.payload.get("name") {
+            Some(_) => vec!["signature: forged".to_string()],
+            None => Vec::new(),
+        };
+        let (forged, status) = record(1, SAMPLE_TOKEN, Some(forged), None, TimeUnit::Auto, 0);
+        assert_eq!(status, Status::Invalid);
+        assert_eq!(forged["error"], "signature: forged");
+        let options = Options {
+            workers: 2,
+            ..Options::default()
+        };
+        let check = |line, text: &str| record(line, text, None, None, TimeUnit::Auto, 0);
+        let (outcome, out) = run(&lines, check, &options, Vec::new(), &|| false).unwrap();
+        assert_eq!(
+            outcome.statuses,
+            [Status::Valid, Status::Invalid, Status::Valid]
+        );
+        assert_eq!(outcome.failed(), 1);
+        let out = String::from_utf8(out).unwrap();
+        let records: Vec<Value> = out
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[1]["line"], 3);
+        assert_eq!(records[3], summary(&outcome.statuses, 0));
+        let (outcome, _) = run(&lines, check, &options, Vec::new(), &|| true).unwrap();
+        assert_eq!(outcome.unchecked, 3);
+    }
+
+    #[test]
+    fn fail_threshold_test() {
+        let count: Threshold = "3".parse().unwrap();
+        assert!(!count.exceeded(3, 10));
+        assert!(count.exceeded(4, 10));
+        let fraction: Threshold = "0.01".parse().unwrap();
+        assert!(!fraction.exceeded(10, 1000));
+        assert!(fraction.exceeded(11, 1000));
+        assert_eq!("1%".parse::<Threshold>().unwrap(), fraction);
+        assert!(!"0".parse::<Threshold>().unwrap().exceeded(0, 5));
+        assert!("0".parse::<Threshold>().unwrap().exceeded(1, 5));
+        for bad in ["", "-1", "1.5", "150%", "lots"] {
+            assert!(bad.parse::<Threshold>().is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Opt-in on-disk cache of successful signature checks, for `--cache`.
+//!
+//! An entry is keyed by the SHA-256 of the token and the key's RFC 7638
+//! thumbprint, so a rotated key never hits an old entry. Entries carry the
+//! token's `exp` and are discarded once it passes. Only successes are
+//! cached. Anyone who can write the cache directory can forge a "verified"
+//! result, so it is created private to the user, and an existing one that
+//! group or others can write is refused.
+
+use crate::crypto::Hash;
+use crate::keys::VerifyingKey;
+use crate::verify::{self, Algorithm};
+use crate::{unix_now, JWTError, JWToken};
+use std::path::{Path, PathBuf};
+
+pub struct VerifyCache {
+    dir: PathBuf,
+}
+
+impl VerifyCache {
+    /// Uses `dir`, or `$XDG_CACHE_HOME/jwt-check`, or `~/.cache/jwt-check`.
+    pub fn open(dir: Option<&str>) -> Result<VerifyCache, JWTError> {
+        let dir = match dir {
+            Some(dir) => PathBuf::from(dir),
+            None => match (std::env::var_os("XDG_CACHE_HOME"), std::env::var_os("HOME")) {
+                (Some(cache), _) => PathBuf::from(cache).join("jwt-check"),
+                (None, Some(home)) => PathBuf::from(home).join(".cache").join("jwt-check"),
+                (None, None) => {
+                    return Err(JWTError::InvalidArgumentError(
+                        "--cache needs --cache-dir when neither XDG_CACHE_HOME nor HOME is set"
+                            .to_string()
+                            .into(),
+                    ))
+                }
+            },
+        };
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(&dir)?;
+        // the mode only applies to a directory created just now
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if std::fs::metadata(&dir)?.permissions().mode() & 0o022 != 0 {
+                return Err(JWTError::InvalidArgumentError(
+                    format!(
+                        "--cache: {} is writable by group or others, who could plant \"verified\" entries; chmod 700 it or pick another --cache-dir",
+                        dir.display()
+                    )
+                    .into(),
+                ));
+            }
+        }
+        Ok(VerifyCache { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn entry(&self, raw: &str, key: &VerifyingKey) -> PathBuf {
+        let mut material = raw.as_bytes().to_vec();
+        material.push(b'\n');
+        material.extend_from_slice(key.thumbprint().as_bytes());
+        let name: String = Hash::Sha256
+            .digest(&material)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        self.dir.join(name)
+    }
+
+    /// The algorithm a previous run verified this token and key with, if the
+    /// entry exists and the token has not expired since.
+    pub fn lookup(&self, raw: &str, key: &VerifyingKey, now: i64) -> Option<Algorithm> {
+        let path = self.entry(raw, key);
+        let text = std::fs::read_to_string(&path).ok()?;
+        let mut fields = text.split_whitespace();
+        if fields.next() != Some("v1") {
+            return None;
+        }
+        let alg = fields.next()?.parse().ok()?;
+        match fields.next()? {
+            "-" => Some(alg),
+            exp => match exp.parse::<i64>() {
+                Ok(exp) if now < exp => Some(alg),
+                _ => {
+                    // expired or unreadable: drop it so the directory doesn't grow forever
+                    let _ = std::fs::remove_file(&path);
+                    None
+                }
+            },
+        }
+    }
+
+    pub fn store(
+        &self,
+        raw: &str,
+        key: &VerifyingKey,
+        alg: Algorithm,
+        exp: Option<i64>,
+    ) -> Result<(), JWTError> {
+        let exp = exp.map_or_else(|| "-".to_string(), |exp| exp.to_string());
+        std::fs::write(self.entry(raw, key), format!("v1 {} {}\n", alg, exp))?;
+        Ok(())
+    }
+}
+
+/// Tries each candidate in turn; the first key that verifies wins. With a
+/// cache, a previous success for the same token and key skips the check.
+pub fn verify_with_any<'a>(
+    raw: &str,
+    token: &JWToken,
+    keys: &'a [VerifyingKey],
+    cache: Option<&VerifyCache>,
+    exp: Option<i64>,
+) -> Result<(Algorithm, &'a VerifyingKey, bool), JWTError> {
+    if let Some(cache) = cache {
+        let now = unix_now();
+        for key in keys {
+            if let Some(alg) = cache.lookup(raw, key, now) {
+                return Ok((alg, key, true));
+            }
+        }
+    }
+    let mut last_error = JWTError::InvalidSignatureError;
+    for key in keys {
+        match verify::verify(raw, token, key) {
+            Ok(alg) => {
+                if let Some(cache) = cache {
+                    cache.store(raw, key, alg, exp)?;
+                }
+                return Ok((alg, key, false));
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_respects_exp_and_key_test() {
+        let dir = std::env::temp_dir().join(format!("jwt-check-cache-test-{}", std::process::id()));
+        let cache = VerifyCache::open(dir.to_str()).unwrap();
+        let key = VerifyingKey::Hmac(b"secret".to_vec());
+        let rotated = VerifyingKey::Hmac(b"rotated".to_vec());
+        assert_eq!(cache.lookup("a.b.c", &key, 0), None);
+        cache
+            .store("a.b.c", &key, Algorithm::HS256, Some(1000))
+            .unwrap();
+        assert_eq!(cache.lookup("a.b.c", &key, 999), Some(Algorithm::HS256));
+        assert_eq!(cache.lookup("a.b.c", &rotated, 999), None);
+        assert_eq!(cache.lookup("a.b.d", &key, 999), None);
+        assert_eq!(cache.lookup("a.b.c", &key, 1000), None);
+        // the expired entry was removed
+        assert_eq!(cache.lookup("a.b.c", &key, 0), None);
+        cache.store("a.b.c", &key, Algorithm::HS256, None).unwrap();
+        assert_eq!(
+            cache.lookup("a.b.c", &key, i64::MAX),
+            Some(Algorithm::HS256)
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn verify_with_any_test() {
+        let dir = std::env::temp_dir().join(format!("jwt-check-cache-any-{}", std::process::id()));
+        let cache = VerifyCache::open(dir.to_str()).unwrap();
+        let token = crate::parse(crate::SAMPLE_TOKEN).unwrap();
+        let keys = [
+            VerifyingKey::Hmac(b"wrong".to_vec()),
+            VerifyingKey::Hmac(b"your-256-bit-secret".to_vec()),
+        ];
+        let verified = |cache| {
+            verify_with_any(crate::SAMPLE_TOKEN, &token, &keys, cache, None)
+                .map(|(alg, key, cached)| (alg, std::ptr::eq(key, &keys[1]), cached))
+        };
+        assert_eq!(verified(None).unwrap(), (Algorithm::HS256, true, false));
+        assert_eq!(
+            verified(Some(&cache)).unwrap(),
+            (Algorithm::HS256, true, false)
+        );
+        assert_eq!(
+            verified(Some(&cache)).unwrap(),
+            (Algorithm::HS256, true, true)
+        );
+        assert!(matches!(
+            verify_with_any(crate::SAMPLE_TOKEN, &token, &keys[..1], None, None),
+            Err(JWTError::InvalidSignatureError)
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_shared_directory_test() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir =
+            std::env::temp_dir().join(format!("jwt-check-cache-shared-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for mode in [0o770, 0o707, 0o777] {
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(mode)).unwrap();
+            assert!(matches!(
+                VerifyCache::open(dir.to_str()),
+                Err(JWTError::InvalidArgumentError(e)) if e == format!(
+                    "--cache: {} is writable by group or others, who could plant \"verified\" entries; chmod 700 it or pick another --cache-dir",
+                    dir.display()
+                )
+            ));
+        }
+        // readable by others is fine; only writing plants entries
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(VerifyCache::open(dir.to_str()).is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
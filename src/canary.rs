@@ -0,0 +1,182 @@
+//! `--canary`: runs a second verifier over the same `--batch`, such as the
+//! implementation being migrated from, and reports every token the two
+//! disagree on.
+//!
+//! The command is started once, through `sh -c`, and sent each batch line
+//! on its stdin. For each line it answers one line on stdout: its verdict,
+//! `valid`, `invalid` or `expired`, optionally followed by a reason. Every
+//! record gets a `canary` field with that verdict and whether it agrees.
+
+use crate::batch::Status;
+use crate::{ErrorMessage, JWTError};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Mutex;
+
+type Pipes = (ChildStdin, BufReader<ChildStdout>);
+
+/// The other verifier, answering one line at a time.
+pub struct Canary {
+    child: Child,
+    pipes: Mutex<Option<Pipes>>,
+}
+
+/// What the other verifier said about one line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    pub verdict: String,
+    pub reason: Option<String>,
+}
+
+impl Verdict {
+    /// Reads an answer line: the verdict word, then the reason, if any.
+    pub fn parse(line: &str) -> Verdict {
+        let line = line.trim();
+        let (verdict, reason) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        Verdict {
+            verdict: verdict.to_lowercase(),
+            reason: Some(reason.trim().to_string()).filter(|reason| !reason.is_empty()),
+        }
+    }
+}
+
+impl Canary {
+    pub fn spawn(command: &str) -> Result<Canary, JWTError> {
+        let mut child = Command::new("sh")
+            .args(["-c", command])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                JWTError::InvalidArgumentError(ErrorMessage::caused_by(
+                    format!("cannot run --canary {}: {}", command, e),
+                    e,
+                ))
+            })?;
+        let pipes = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => Some((stdin, BufReader::new(stdout))),
+            _ => None,
+        };
+        Ok(Canary {
+            child,
+            pipes: Mutex::new(pipes),
+        })
+    }
+
+    /// Sends one batch line and reads the answer. Lines go one at a time,
+    /// so the answers can't get out of step with the workers.
+    pub fn ask(&self, line: &str) -> Result<Verdict, String> {
+        let mut pipes = self
+            .pipes
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let (stdin, stdout) = pipes
+            .as_mut()
+            .ok_or_else(|| "it is not running".to_string())?;
+        if let Err(e) = writeln!(stdin, "{}", line).and_then(|_| stdin.flush()) {
+            *pipes = None;
+            return Err(format!("cannot send the line: {}", e));
+        }
+        let mut answer = String::new();
+        match stdout.read_line(&mut answer) {
+            Ok(0) => {
+                // it exited, and every later line would fail the same way
+                *pipes = None;
+                Err("it exited without answering".to_string())
+            }
+            Ok(_) => Ok(Verdict::parse(&answer)),
+            Err(e) => Err(format!("cannot read the answer: {}", e)),
+        }
+    }
+}
+
+impl Drop for Canary {
+    fn drop(&mut self) {
+        // closing stdin tells it the batch is over
+        if let Ok(pipes) = self.pipes.get_mut() {
+            pipes.take();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+/// The `canary` field of a record whose status is `ours`, and whether the
+/// two verdicts diverge. A canary that failed to answer diverges too.
+pub fn compare(ours: Status, theirs: Result<Verdict, String>) -> (Value, bool) {
+    match theirs {
+        Ok(theirs) => {
+            let agrees = theirs.verdict == ours.name();
+            let mut field = json!({"verdict": theirs.verdict, "agrees": agrees});
+            if let (Some(reason), Some(field)) = (theirs.reason, field.as_object_mut()) {
+                field.insert("reason".to_string(), reason.into());
+            }
+            (field, !agrees)
+        }
+        Err(e) => (json!({"verdict": null, "agrees": false, "error": e}), true),
+    }
+}
+
+/// How a record's `canary` field reads in the divergence log.
+pub fn says(field: &Value) -> String {
+    let text = |key: &str| field.get(key).and_then(Value::as_str);
+    match (text("verdict"), text("reason")) {
+        (Some(verdict), Some(reason)) => format!("{} from the canary ({})", verdict, reason),
+        (Some(verdict), None) => format!("{} from the canary", verdict),
+        _ => format!(
+            "no verdict from the canary ({})",
+            text("error").unwrap_or_default()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canary_test() {
+        assert_eq!(
+            Verdict::parse("INVALID  bad signature\n"),
+            Verdict {
+                verdict: "invalid".to_string(),
+                reason: Some("bad signature".to_string()),
+            }
+        );
+        let canary = Canary::spawn(
+            "while read -r line; do case $line in *x*) echo invalid no x allowed;; *) echo valid;; esac; done",
+        )
+        .unwrap();
+        let (field, diverges) = compare(Status::Valid, canary.ask("abc"));
+        assert_eq!(
+            (field, diverges),
+            (json!({"verdict": "valid", "agrees": true}), false)
+        );
+        let (field, diverges) = compare(Status::Valid, canary.ask("xyz"));
+        assert!(diverges);
+        assert_eq!(field["reason"], "no x allowed");
+        assert!(!compare(Status::Invalid, canary.ask("xyz")).1);
+
+        let gone = Canary::spawn("read -r line; exit 0").unwrap();
+        let (field, diverges) = compare(Status::Valid, gone.ask("abc"));
+        assert!(diverges);
+        assert_eq!(field["error"], "it exited without answering");
+        assert_eq!(gone.ask("abc").unwrap_err(), "it is not running");
+    }
+
+    #[test]
+    fn says_test() {
+        assert_eq!(
+            says(&json!({"verdict": "invalid", "agrees": false, "reason": "bad kid"})),
+            "invalid from the canary (bad kid)"
+        );
+        assert_eq!(
+            says(&json!({"verdict": "expired", "agrees": false})),
+            "expired from the canary"
+        );
+        assert_eq!(
+            says(&json!({"verdict": null, "agrees": false, "error": "it is not running"})),
+            "no verdict from the canary (it is not running)"
+        );
+    }
+}
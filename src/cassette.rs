@@ -0,0 +1,130 @@
+//! `--record DIR` and `--replay DIR`: the documents a run fetches (JWKS,
+//! discovery, OCSP), kept as fixtures so CI runs of verification tests are
+//! hermetic and fast.
+//!
+//! Each fetched URL is one file in the directory, named by the SHA-256 of
+//! the URL, holding the URL and the response body. A UTF-8 body is kept as
+//! text, so a recorded JWKS can be read and edited; any other body is kept
+//! as base64. Replaying never touches the network, and a URL that was never
+//! recorded is an error. Reports sent to `--out` are not recorded.
+
+use crate::crypto::Hash;
+use crate::JWTError;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Record,
+    Replay,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cassette {
+    pub dir: PathBuf,
+    pub mode: Mode,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Cassette {
+    pub fn new(dir: &Path, mode: Mode) -> Cassette {
+        Cassette {
+            dir: dir.to_path_buf(),
+            mode,
+        }
+    }
+
+    /// The file `url` is kept in.
+    pub fn path(&self, url: &str) -> PathBuf {
+        self.dir.join(format!(
+            "{}.json",
+            hex(&Hash::Sha256.digest(url.as_bytes()))
+        ))
+    }
+
+    /// The recorded body for `url`.
+    pub fn load(&self, url: &str) -> Result<Vec<u8>, JWTError> {
+        let path = self.path(url);
+        let text = std::fs::read_to_string(&path).map_err(|_| {
+            JWTError::HttpError(
+                format!(
+                    "{}: not in the cassette at {}; record it first with --record",
+                    url,
+                    self.dir.display()
+                )
+                .into(),
+            )
+        })?;
+        let entry: Value = serde_json::from_str(&text)?;
+        if entry.get("url").and_then(Value::as_str) != Some(url) {
+            return Err(JWTError::HttpError(
+                format!("{}: {} was recorded for another URL", url, path.display()).into(),
+            ));
+        }
+        match (entry.get("body"), entry.get("body_base64")) {
+            (Some(Value::String(body)), _) => Ok(body.clone().into_bytes()),
+            (_, Some(Value::String(body))) => Ok(base64::decode(body)?),
+            _ => Err(JWTError::HttpError(
+                format!("{}: {} has no body", url, path.display()).into(),
+            )),
+        }
+    }
+
+    /// Records `body` as the response for `url`.
+    pub fn store(&self, url: &str, body: &[u8]) -> Result<(), JWTError> {
+        std::fs::create_dir_all(&self.dir)?;
+        let entry = match std::str::from_utf8(body) {
+            Ok(text) => serde_json::json!({"url": url, "body": text}),
+            Err(_) => serde_json::json!({"url": url, "body_base64": base64::encode(body)}),
+        };
+        std::fs::write(self.path(url), serde_json::to_string_pretty(&entry)? + "\n")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_test() {
+        let dir = std::env::temp_dir().join(format!("jwt-check-cassette-{}", std::process::id()));
+        let recorder = Cassette::new(&dir, Mode::Record);
+        let player = Cassette::new(&dir, Mode::Replay);
+        let jwks = "http://idp.example/jwks";
+        let ocsp = "http://ocsp.example/abc";
+        recorder.store(jwks, br#"{"keys": []}"#).unwrap();
+        recorder.store(ocsp, &[0x30, 0x80, 0xff]).unwrap();
+        assert_eq!(player.load(jwks).unwrap(), br#"{"keys": []}"#);
+        assert_eq!(player.load(ocsp).unwrap(), [0x30, 0x80, 0xff]);
+        let recorded = std::fs::read_to_string(player.path(jwks)).unwrap();
+        assert!(recorded.contains(r#""body": "{\"keys\": []}""#));
+
+        assert!(matches!(
+            player.load("http://idp.example/other"),
+            Err(JWTError::HttpError(e)) if e == format!(
+                "http://idp.example/other: not in the cassette at {}; record it first with --record",
+                dir.display()
+            )
+        ));
+        // a file moved into another URL's place is refused
+        std::fs::copy(player.path(jwks), player.path("http://idp.example/moved")).unwrap();
+        assert!(matches!(
+            player.load("http://idp.example/moved"),
+            Err(JWTError::HttpError(e)) if e == format!(
+                "http://idp.example/moved: {} was recorded for another URL",
+                player.path("http://idp.example/moved").display()
+            )
+        ));
+        std::fs::write(player.path(ocsp), r#"{"url": "http://ocsp.example/abc"}"#).unwrap();
+        assert!(matches!(
+            player.load(ocsp),
+            Err(JWTError::HttpError(e))
+                if e == format!("{}: {} has no body", ocsp, player.path(ocsp).display())
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,310 @@
+//! `--chain`: the tokens nested inside a token, as a tree.
+//!
+//! Three things nest: a JWS inside a JWE, JWTs carried as claim values
+//! (request objects, `id_token_hint`, and the like), and RFC 8693 `act`
+//! claims, where each actor may itself carry an `act`. The walk stops at a
+//! depth limit, and a node that repeats one of its ancestors (the same
+//! actor, or a token with the same `jti`) is marked as a cycle and not
+//! followed.
+
+use crate::{jwe, parse, JWTError, JWToken};
+use serde_json::Value;
+
+/// How deep `--chain` goes unless told otherwise.
+pub const DEFAULT_MAX_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeKind {
+    Jwe,
+    Token,
+    Actor,
+}
+
+impl NodeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            NodeKind::Jwe => "jwe",
+            NodeKind::Token => "token",
+            NodeKind::Actor => "actor",
+        }
+    }
+}
+
+/// One link in the chain: where it was found (`act.act`, `request`), a
+/// short description, and what it contains.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub path: String,
+    pub label: String,
+    /// Set when the node repeats an ancestor; its contents are not walked.
+    pub cycle: bool,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    /// Paths of the nodes marked as cycles, depth first.
+    pub fn cycles(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        if self.cycle {
+            out.push(self.path.clone());
+        }
+        for child in &self.children {
+            out.extend(child.cycles());
+        }
+        out
+    }
+}
+
+fn describe(claims: &Value, names: &[&str]) -> String {
+    let parts: Vec<String> = names
+        .iter()
+        .filter_map(|name| {
+            let value = claims.get(*name)?;
+            Some(match value.as_str() {
+                Some(text) => format!("{}={}", name, text),
+                None => format!("{}={}", name, value),
+            })
+        })
+        .collect();
+    parts.join(" ")
+}
+
+/// What makes a node the same as an ancestor.
+fn identity(kind: NodeKind, claims: &Value) -> Option<String> {
+    match kind {
+        NodeKind::Actor => Some(format!("actor {}", describe(claims, &["iss", "sub"]))),
+        NodeKind::Token => claims.get("jti").map(|jti| format!("token jti={}", jti)),
+        NodeKind::Jwe => None,
+    }
+}
+
+struct Walk {
+    max_depth: usize,
+    ancestors: Vec<String>,
+}
+
+impl Walk {
+    fn check_depth(&self, depth: usize, path: &str) -> Result<(), JWTError> {
+        if depth > self.max_depth {
+            return Err(JWTError::ChainError(
+                format!(
+                    "{} is nested more than {} levels deep",
+                    path, self.max_depth
+                )
+                .into(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn token(&mut self, token: &JWToken, path: String, depth: usize) -> Result<Node, JWTError> {
+        self.check_depth(depth, &path)?;
+        let mut label = describe(&token.header, &["alg"]);
+        let claims = describe(&token.payload, &["iss", "sub", "jti"]);
+        if !claims.is_empty() {
+            label = format!("{} {}", label, claims);
+        }
+        self.node(NodeKind::Token, &token.payload, path, label, depth)
+    }
+
+    fn node(
+        &mut self,
+        kind: NodeKind,
+        claims: &Value,
+        path: String,
+        label: String,
+        depth: usize,
+    ) -> Result<Node, JWTError> {
+        let mut node = Node {
+            kind,
+            path,
+            label,
+            cycle: false,
+            children: Vec::new(),
+        };
+        let identity = identity(kind, claims);
+        if let Some(identity) = &identity {
+            if self.ancestors.contains(identity) {
+                node.cycle = true;
+                return Ok(node);
+            }
+            self.ancestors.push(identity.clone());
+        }
+        if let Some(act) = claims.get("act") {
+            let path = format!("{}.act", node.path);
+            self.check_depth(depth + 1, &path)?;
+            let label = describe(act, &["iss", "sub"]);
+            node.children
+                .push(self.node(NodeKind::Actor, act, path, label, depth + 1)?);
+        }
+        if kind == NodeKind::Token {
+            self.embedded(claims, &node.path.clone(), depth, &mut node.children)?;
+        }
+        if identity.is_some() {
+            self.ancestors.pop();
+        }
+        Ok(node)
+    }
+
+    /// JWTs in string claims, at any depth of the claims' JSON.
+    fn embedded(
+        &mut self,
+        value: &Value,
+        path: &str,
+        depth: usize,
+        out: &mut Vec<Node>,
+    ) -> Result<(), JWTError> {
+        match value {
+            Value::Object(object) => {
+                for (name, value) in object {
+                    if name != "act" {
+                        self.embedded(value, &format!("{}.{}", path, name), depth, out)?;
+                    }
+                }
+            }
+            Value::Array(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    self.embedded(item, &format!("{}[{}]", path, index), depth, out)?;
+                }
+            }
+            Value::String(text) => {
+                if let Ok(token) = parse(text) {
+                    out.push(self.token(&token, path.to_string(), depth + 1)?);
+                } else if jwe::is_compact(text) {
+                    if let Ok(jwe) = jwe::parse(text) {
+                        self.check_depth(depth + 1, path)?;
+                        let label = describe(&jwe.header, &["alg", "enc"]);
+                        out.push(Node {
+                            kind: NodeKind::Jwe,
+                            path: path.to_string(),
+                            label: format!("{} (encrypted)", label),
+                            cycle: false,
+                            children: Vec::new(),
+                        });
+                    }
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// The chain below `token`. `outer` is the header of the JWE it was
+/// decrypted from, which becomes the root. Fails when anything is nested
+/// deeper than `max_depth`.
+pub fn walk(token: &JWToken, outer: Option<&Value>, max_depth: usize) -> Result<Node, JWTError> {
+    let mut walk = Walk {
+        max_depth,
+        ancestors: Vec::new(),
+    };
+    match outer {
+        Some(header) => {
+            let inner = walk.token(token, "$.payload".to_string(), 1)?;
+            Ok(Node {
+                kind: NodeKind::Jwe,
+                path: "$".to_string(),
+                label: describe(header, &["alg", "enc"]),
+                cycle: false,
+                children: vec![inner],
+            })
+        }
+        None => walk.token(token, "$".to_string(), 0),
+    }
+}
+
+/// The tree drawn with box-drawing characters, one node per line.
+pub fn render_tree(root: &Node) -> String {
+    fn line(node: &Node) -> String {
+        let mut text = format!("{} {}", node.kind.name(), node.label);
+        if node.path != "$" {
+            text = format!("{} [{}]", text, node.path);
+        }
+        if node.cycle {
+            text.push_str(" (cycle: repeats an ancestor)");
+        }
+        text
+    }
+    fn children(node: &Node, prefix: &str, out: &mut String) {
+        let count = node.children.len();
+        for (index, child) in node.children.iter().enumerate() {
+            let last = index + 1 == count;
+            out.push_str(&format!(
+                "\n{}{}{}",
+                prefix,
+                if last { "└─ " } else { "├─ " },
+                line(child)
+            ));
+            let deeper = format!("{}{}", prefix, if last { "   " } else { "│  " });
+            children(child, &deeper, out);
+        }
+    }
+    let mut out = line(root);
+    children(root, "", &mut out);
+    out
+}
+
+/// The tree as nested JSON objects.
+pub fn to_json(node: &Node) -> Value {
+    serde_json::json!({
+        "kind": node.kind.name(),
+        "path": node.path,
+        "label": node.label,
+        "cycle": node.cycle,
+        "children": node.children.iter().map(to_json).collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn token(payload: Value) -> JWToken {
+        JWToken {
+            header: json!({"alg": "HS256"}),
+            payload,
+            signature: Vec::new(),
+        }
+    }
+
+    fn compact(payload: &Value) -> String {
+        let b64 = |v: &Value| base64::encode_config(v.to_string(), base64::URL_SAFE_NO_PAD);
+        format!("{}.{}.c2ln", b64(&json!({"alg": "RS256"})), b64(payload))
+    }
+
+    #[test]
+    fn walks_act_chains_and_embedded_tokens_test() {
+        let request = compact(&json!({"iss": "client", "jti": "r1"}));
+        let root = token(json!({
+            "sub": "alice",
+            "jti": "t1",
+            "act": {"sub": "svc-a", "act": {"sub": "svc-b", "act": {"sub": "svc-a"}}},
+            "request": request,
+        }));
+        let tree = walk(&root, None, DEFAULT_MAX_DEPTH).unwrap();
+        assert_eq!(
+            render_tree(&tree),
+            "token alg=HS256 sub=alice jti=t1\n\
+             ├─ actor sub=svc-a [$.act]\n\
+             │  └─ actor sub=svc-b [$.act.act]\n\
+             │     └─ actor sub=svc-a [$.act.act.act] (cycle: repeats an ancestor)\n\
+             └─ token alg=RS256 iss=client jti=r1 [$.request]"
+        );
+        assert_eq!(tree.cycles(), vec!["$.act.act.act"]);
+
+        // a token embedding a copy of itself by jti
+        let inner = compact(&json!({"jti": "t1"}));
+        let looped = token(json!({"jti": "t1", "hint": [inner]}));
+        assert_eq!(walk(&looped, None, 8).unwrap().cycles(), vec!["$.hint[0]"]);
+
+        assert!(matches!(
+            walk(&root, None, 2),
+            Err(JWTError::ChainError(e)) if e.starts_with("$.act.act.act is nested more than 2")
+        ));
+        let wrapped = walk(&root, Some(&json!({"alg": "dir", "enc": "A256GCM"})), 8).unwrap();
+        assert_eq!(wrapped.kind, NodeKind::Jwe);
+        assert_eq!(wrapped.children[0].path, "$.payload");
+    }
+}
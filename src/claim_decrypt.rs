@@ -0,0 +1,327 @@
+//! `--claim-decrypt`: claim values that an issuer encrypted on its own,
+//! inside an otherwise readable token, decrypted for display.
+//!
+//! A rule is `CLAIM=KEYREF:SCHEME`. `CLAIM` is a dot path or JSON pointer
+//! into the payload, as `--claim` takes. `KEYREF` is `env:NAME`,
+//! `file:PATH` or a bare path, holding a 32-byte key as raw bytes, hex or
+//! base64. `SCHEME` names one of the [`Scheme`]s; supporting another one
+//! means adding a variant and its `open`.
+//!
+//! The claim value is base64 or base64url of the scheme's sealed form, and
+//! is replaced by an object whose `$decrypted` member names the scheme, with
+//! the plaintext as `value` (parsed when it is JSON) or as `hex` when it is
+//! not text.
+
+use crate::{crypto, JWTError};
+use serde_json::{Map, Value};
+use std::convert::TryInto;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheme {
+    /// libsodium `crypto_secretbox_easy` (XSalsa20-Poly1305), sealed as
+    /// nonce, tag, ciphertext.
+    Secretbox,
+    /// libsodium `crypto_aead_xchacha20poly1305_ietf` with no associated
+    /// data, sealed as nonce, ciphertext, tag.
+    XChaCha20Poly1305,
+}
+
+impl FromStr for Scheme {
+    type Err = JWTError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "secretbox" => Ok(Scheme::Secretbox),
+            "xchacha20poly1305" => Ok(Scheme::XChaCha20Poly1305),
+            _ => Err(JWTError::InvalidArgumentError(
+                format!(
+                    "unknown claim encryption scheme `{}`; expected secretbox or xchacha20poly1305",
+                    s
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+impl Scheme {
+    pub fn name(self) -> &'static str {
+        match self {
+            Scheme::Secretbox => "secretbox",
+            Scheme::XChaCha20Poly1305 => "xchacha20poly1305",
+        }
+    }
+
+    /// The plaintext, or `None` if `sealed` is malformed or fails
+    /// authentication.
+    pub fn open(self, key: &[u8; 32], sealed: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Scheme::Secretbox => crypto::secretbox_open(key, sealed),
+            Scheme::XChaCha20Poly1305 => crypto::xchacha20poly1305_open(key, sealed),
+        }
+    }
+}
+
+/// Where a rule's key comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyRef {
+    Env(String),
+    File(PathBuf),
+}
+
+impl KeyRef {
+    pub fn load(&self) -> Result<[u8; 32], JWTError> {
+        let material = match self {
+            KeyRef::Env(name) => std::env::var(name)
+                .map_err(|_| {
+                    JWTError::KeyError(format!("environment variable {} is not set", name).into())
+                })?
+                .into_bytes(),
+            KeyRef::File(path) => std::fs::read(path)?,
+        };
+        key_material(&material).ok_or_else(|| {
+            JWTError::KeyError(
+                format!("{} does not hold a 32-byte key (raw, hex or base64)", self).into(),
+            )
+        })
+    }
+}
+
+impl std::fmt::Display for KeyRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            KeyRef::Env(name) => write!(f, "env:{}", name),
+            KeyRef::File(path) => write!(f, "file:{}", path.display()),
+        }
+    }
+}
+
+/// One `--claim-decrypt` rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub claim: String,
+    pub key: KeyRef,
+    pub scheme: Scheme,
+}
+
+impl FromStr for Rule {
+    type Err = JWTError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            JWTError::InvalidArgumentError(
+                format!(
+                    "`{}` is not CLAIM=KEYREF:SCHEME (for example data=env:CLAIM_KEY:secretbox)",
+                    s
+                )
+                .into(),
+            )
+        };
+        let (claim, rest) = s.split_once('=').ok_or_else(invalid)?;
+        // the scheme comes last, so a key path may itself contain colons
+        let (key, scheme) = rest.rsplit_once(':').ok_or_else(invalid)?;
+        if claim.is_empty() || key.is_empty() {
+            return Err(invalid());
+        }
+        let key = match (key.strip_prefix("env:"), key.strip_prefix("file:")) {
+            (Some(name), _) => KeyRef::Env(name.to_string()),
+            (None, Some(path)) => KeyRef::File(PathBuf::from(path)),
+            (None, None) => KeyRef::File(PathBuf::from(key)),
+        };
+        Ok(Rule {
+            claim: claim.to_string(),
+            key,
+            scheme: scheme.parse()?,
+        })
+    }
+}
+
+/// A 32-byte key from a file or variable: the bytes themselves, 64 hex
+/// digits, or base64 / base64url, ignoring surrounding whitespace.
+pub fn key_material(material: &[u8]) -> Option<[u8; 32]> {
+    if let Ok(key) = material.try_into() {
+        return Some(key);
+    }
+    let text = std::str::from_utf8(material).ok()?.trim();
+    let decoded = if text.len() == 64 && text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        (0..64)
+            .step_by(2)
+            .map(|at| {
+                text.get(at..at + 2)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()?
+    } else {
+        decode_base64(text)?
+    };
+    decoded.try_into().ok()
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let unpadded = text.trim_end_matches('=');
+    base64::decode_config(unpadded, base64::URL_SAFE_NO_PAD)
+        .or_else(|_| base64::decode_config(unpadded, base64::STANDARD_NO_PAD))
+        .ok()
+}
+
+fn pointer(path: &str) -> String {
+    if path.starts_with('/') {
+        return path.to_string();
+    }
+    path.split('.')
+        .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+/// Decrypts `rule.claim` in `payload` in place with `key`.
+pub fn apply(payload: &mut Value, rule: &Rule, key: &[u8; 32]) -> Result<(), JWTError> {
+    let value = payload
+        .pointer_mut(&pointer(&rule.claim))
+        .ok_or_else(|| JWTError::ClaimNotFoundError(rule.claim.clone().into()))?;
+    let sealed = value.as_str().and_then(decode_base64).ok_or_else(|| {
+        JWTError::InvalidArgumentError(
+            format!("claim `{}` is not a base64 string to decrypt", rule.claim).into(),
+        )
+    })?;
+    let plaintext = rule.scheme.open(key, &sealed).ok_or_else(|| {
+        JWTError::DecryptionError(
+            format!(
+                "claim `{}` did not open as {} with key {}",
+                rule.claim,
+                rule.scheme.name(),
+                rule.key
+            )
+            .into(),
+        )
+    })?;
+    let mut object = Map::new();
+    object.insert(
+        "$decrypted".to_string(),
+        Value::String(rule.scheme.name().to_string()),
+    );
+    match String::from_utf8(plaintext) {
+        Ok(text) => {
+            let parsed = serde_json::from_str(&text).unwrap_or(Value::String(text));
+            object.insert("value".to_string(), parsed);
+        }
+        Err(e) => {
+            let hex = e.as_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+            object.insert("hex".to_string(), Value::String(hex));
+        }
+    }
+    *value = Value::Object(object);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn decrypts_claims_test() {
+        let rule: Rule = "profile.data=file:C:\\keys\\claim.key:xchacha20poly1305"
+            .parse()
+            .unwrap();
+        assert_eq!(rule.claim, "profile.data");
+        assert_eq!(rule.key, KeyRef::File(PathBuf::from("C:\\keys\\claim.key")));
+        assert_eq!(rule.scheme, Scheme::XChaCha20Poly1305);
+        assert_eq!(
+            "ssn=env:K:secretbox".parse::<Rule>().unwrap().key,
+            KeyRef::Env("K".to_string())
+        );
+        assert!("ssn=env:K:aes".parse::<Rule>().is_err());
+        assert!("ssn".parse::<Rule>().is_err());
+
+        let key: Vec<u8> = (0..32).collect();
+        let hex: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(key_material(&key).unwrap().to_vec(), key);
+        assert_eq!(
+            key_material(format!("{}\n", hex).as_bytes())
+                .unwrap()
+                .to_vec(),
+            key
+        );
+        assert_eq!(
+            key_material(base64::encode(&key).as_bytes())
+                .unwrap()
+                .to_vec(),
+            key
+        );
+        assert_eq!(key_material(b"short"), None);
+
+        // libsodium crypto_secretbox_easy of `gold` with key 00..1f
+        let sealed = "ZGVmZ2hpamtsbW5vcHFyc3R1dnd4eXp7y1yXhTh0H6Ok3ehupB2k_WXW9a0";
+        let mut payload = json!({"sub": "alice", "profile": {"tier": sealed}});
+        let rule: Rule = "profile.tier=key.bin:secretbox".parse().unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        apply(&mut payload, &rule, &key).unwrap();
+        assert_eq!(
+            payload["profile"]["tier"],
+            json!({"$decrypted": "secretbox", "value": "gold"})
+        );
+        // crypto_aead_xchacha20poly1305_ietf_encrypt of a JSON object, in
+        // standard base64
+        let mut payload = json!({"pii": "ZGVmZ2hpamtsbW5vcHFyc3R1dnd4eXp7B1GKw5EMs6C3B6USllqQ+GqJYlOZ0z7T2n+7G9sjBM5rExpK0Q=="});
+        let rule: Rule = "pii=env:K:xchacha20poly1305".parse().unwrap();
+        apply(&mut payload, &rule, &key).unwrap();
+        assert_eq!(payload["pii"]["value"], json!({"ssn": "078-05-1120"}));
+        let mut payload = json!({"tier": sealed});
+        let wrong = [7u8; 32];
+        let rule: Rule = "/tier=key.bin:secretbox".parse().unwrap();
+        assert!(matches!(
+            apply(&mut payload, &rule, &wrong),
+            Err(JWTError::DecryptionError(_))
+        ));
+        let rule: Rule = "missing=key.bin:secretbox".parse().unwrap();
+        assert!(matches!(
+            apply(&mut payload, &rule, &key),
+            Err(JWTError::ClaimNotFoundError(_))
+        ));
+    }
+
+    #[test]
+    fn refused_rules_test() {
+        let argument_error_of = |s: &str| error_message!(InvalidArgumentError, s.parse::<Rule>());
+        assert_eq!(
+            argument_error_of("ssn=env:K:aes"),
+            "unknown claim encryption scheme `aes`; expected secretbox or xchacha20poly1305"
+        );
+        for bad in ["ssn", "ssn=secretbox", "=env:K:secretbox", "ssn=:secretbox"] {
+            assert_eq!(
+                argument_error_of(bad),
+                format!(
+                    "`{}` is not CLAIM=KEYREF:SCHEME (for example data=env:CLAIM_KEY:secretbox)",
+                    bad
+                )
+            );
+        }
+
+        let key_error_of = |key: KeyRef| error_message!(KeyError, key.load());
+        assert_eq!(
+            key_error_of(KeyRef::Env("JWT_CHECK_TEST_UNSET_CLAIM_KEY".to_string())),
+            "environment variable JWT_CHECK_TEST_UNSET_CLAIM_KEY is not set"
+        );
+        std::env::set_var("JWT_CHECK_TEST_SHORT_CLAIM_KEY", "short");
+        assert_eq!(
+            key_error_of(KeyRef::Env("JWT_CHECK_TEST_SHORT_CLAIM_KEY".to_string())),
+            "env:JWT_CHECK_TEST_SHORT_CLAIM_KEY does not hold a 32-byte key (raw, hex or base64)"
+        );
+
+        let key = [7u8; 32];
+        let rule: Rule = "tier=env:K:secretbox".parse().unwrap();
+        for value in [json!(42), json!("not base64!")] {
+            assert!(matches!(
+                apply(&mut json!({"tier": value}), &rule, &key),
+                Err(JWTError::InvalidArgumentError(e)) if e == "claim `tier` is not a base64 string to decrypt"
+            ));
+        }
+        let sealed = "ZGVmZ2hpamtsbW5vcHFyc3R1dnd4eXp7y1yXhTh0H6Ok3ehupB2k_WXW9a0";
+        assert!(matches!(
+            apply(&mut json!({"tier": sealed}), &rule, &key),
+            Err(JWTError::DecryptionError(e)) if e == "claim `tier` did not open as secretbox with key env:K"
+        ));
+    }
+}
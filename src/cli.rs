@@ -0,0 +1,2608 @@
+//! Command line definition.
+//!
+//! The clap definitions and the example table below are the only place
+//! commands are described; `--help`, `--help-long`, `examples` and the man
+//! page are all rendered from them.
+
+use crate::{JWTError, Normalization};
+use clap::{App, AppSettings, Arg, SubCommand};
+
+/// Copy-pasteable invocations, keyed by subcommand (`""` for the decoder itself).
+const EXAMPLES: &[(&str, &str, &str)] = &[
+    ("", "Decode a token", "jwt-check -t eyJhbGciOi..."),
+    (
+        "",
+        "Decode a token saved from an HTTP dump; `Authorization: Bearer` is stripped",
+        "jwt-check --file header.txt",
+    ),
+    (
+        "",
+        "Decode the session cookie from a request copied with devtools' Copy as cURL",
+        "jwt-check --file copied-request.txt --cookie session",
+    ),
+    (
+        "",
+        "See which claims the token and the userinfo endpoint disagree on",
+        "jwt-check -t eyJhbGciOi... --userinfo userinfo.json",
+    ),
+    (
+        "",
+        "Show who is acting for whom in a token-exchange token",
+        "jwt-check -t eyJhbGciOi... --chain",
+    ),
+    (
+        "",
+        "Draw which issuers, subjects, sessions and actors a set of tokens involve",
+        "jwt-check --batch tokens.txt --graph dot | dot -Tsvg > tokens.svg",
+    ),
+    (
+        "",
+        "Follow each session through its logins and refreshes, flagging refreshes after expiry",
+        "jwt-check --batch tokens.txt --sessions",
+    ),
+    (
+        "",
+        "Expand an id_token_hint or base64 `data` claim in place",
+        "jwt-check -t eyJhbGciOi... --decode-nested",
+    ),
+    (
+        "agility",
+        "Review which algorithms and key sizes an issuer's JWKS offers, under FAPI 2.0",
+        "jwt-check agility --issuer http://127.0.0.1:8080 --profile fapi2",
+    ),
+    (
+        "",
+        "Attach an HTML report of a token's audit findings to a ticket",
+        "jwt-check -t eyJhbGciOi... --audit --report audit.html",
+    ),
+    (
+        "",
+        "Show where the IP addresses in a token's claims are, from a local GeoIP database",
+        "jwt-check -t eyJhbGciOi... --geoip GeoLite2-City.mmdb",
+    ),
+    (
+        "",
+        "Decrypt a claim an internal service sealed with libsodium secretbox",
+        "jwt-check -t eyJhbGciOi... --claim-decrypt pii=env:PII_KEY:secretbox",
+    ),
+    (
+        "",
+        "See who a token is for, in the same fields whichever IdP issued it",
+        "jwt-check -t eyJhbGciOi... --identity builtin",
+    ),
+    (
+        "",
+        "Post a one-line verdict to a team's chat webhook",
+        "jwt-check --token eyJ... --verify --jwks-file keys.json --summary-line --post-to https://hooks.slack.com/services/T000/B000/XXXX",
+    ),
+    (
+        "ttl",
+        "Show the token's remaining lifetime, e.g. in a shell prompt's $(...)",
+        "jwt-check ttl --file token.txt --format human",
+    ),
+    (
+        "",
+        "Fetch the JWKS on a site that mandates DNS-over-HTTPS",
+        "jwt-check --token eyJ... --verify --jwks-url https://idp.example/jwks --doh-url https://cloudflare-dns.com/dns-query",
+    ),
+    (
+        "",
+        "Record the JWKS a verification test fetches, then replay it hermetically in CI",
+        "jwt-check --token eyJ... --verify --jwks-url https://idp.example/jwks --replay fixtures/",
+    ),
+    (
+        "",
+        "Fail fast when the IdP is slow, rather than hang a deploy script",
+        "jwt-check --token eyJ... --verify --jwks-url https://idp.example/jwks --timeout 2s",
+    ),
+    (
+        "",
+        "Reach a staging IdP that only split-horizon DNS knows",
+        "jwt-check --token eyJ... --verify --jwks-url https://idp.staging.internal/jwks --resolve idp.staging.internal:443:10.20.0.5",
+    ),
+    (
+        "",
+        "Keep a script written against jwt-cli's JSON output working, with validation on top",
+        "jwt-check -t eyJhbGciOi... --compat jwt-cli --format json --validate",
+    ),
+    (
+        "",
+        "Find the Keycloak mapper behind each claim",
+        "jwt-check -t eyJhbGciOi... --idp-config realm-export.json",
+    ),
+    (
+        "",
+        "Fix what the audit finds in a token from a minting service under development",
+        "jwt-check -t eyJhbGciOi... --audit --fix --resign-with dev-key.pem --fix-kid dev-2024",
+    ),
+    (
+        "",
+        "Run a team's own Rhai script after the built-in checks",
+        "jwt-check --token eyJ... --verify --jwks-file keys.json --script orders.rhai",
+    ),
+    (
+        "",
+        "Compare verdicts with the legacy verifier on a sample of real traffic",
+        "jwt-check --batch sample.txt --verify --jwks-file keys.json --validate --canary ./legacy-verify.sh",
+    ),
+    (
+        "",
+        "Run a verification sidecar for two trust domains, picked by path or header",
+        "jwt-check serve --config policies.yaml --listen 127.0.0.1:8080",
+    ),
+    (
+        "",
+        "Assert on a token's claims in an integration test",
+        "jwt-check assert -t \"$TOKEN\" 'aud == \"api://orders\"' 'exp within 1h' 'has scope \"orders:read\"'",
+    ),
+    (
+        "",
+        "Audit a day's tokens and store the results in S3-compatible storage",
+        "jwt-check --batch tokens.txt --verify --jwks-file jwks.json --out s3://audit/2026-10-14.ndjson",
+    ),
+    (
+        "",
+        "Nightly: check freshly minted staging tokens against RFC 9068 and our policy",
+        "jwt-check compliance staging-tokens.txt --profile rfc9068 --policy minting-policy.yaml",
+    ),
+    (
+        "",
+        "Check an access token against the RFC 9068 JWT access token profile",
+        "jwt-check -t eyJhbGciOi... --profile rfc9068 --verify --jwks-url https://as.example/jwks",
+    ),
+    (
+        "",
+        "Check a license against the revocation list and every key it may be signed with",
+        "jwt-check --file license.jwt --profile license --revoked-jti revoked.txt --verify --key signing-2025.pem --previous-key signing-2023.pem",
+    ),
+    (
+        "",
+        "Lint a registration request and check its signatures before sending it",
+        "jwt-check dcr --file registration.jwt --trust-dir ./keystore",
+    ),
+    (
+        "",
+        "Verify a DCR request and its software statement against a directory mirror",
+        "jwt-check -t eyJhbGciOi... --verify --trust-dir ./keystore",
+    ),
+    (
+        "",
+        "Verify a verifiable credential JWT from a did:web issuer",
+        "jwt-check -t eyJhbGciOi... --verify --did --did-document did.json",
+    ),
+    (
+        "",
+        "Pre-check a request object against FAPI 2.0 before certification",
+        "jwt-check --file request.jwt --profile fapi2 --verify --key client-public.pem",
+    ),
+    (
+        "",
+        "Find out why a Sign in with Apple token is rejected",
+        "jwt-check -t eyJraWQiOi... --preset sign-in-with-apple --client-id com.example.app --verify --jwks-file apple-keys.json",
+    ),
+    (
+        "",
+        "Check a Supabase user token against the project's JWT secret",
+        "jwt-check -t eyJhbGciOi... --preset supabase --project abcdefghij --verify --secret-file jwt-secret.txt",
+    ),
+    (
+        "",
+        "Diff an ID token's claims against what the UserInfo endpoint returns",
+        "jwt-check -t eyJhbGciOi... --userinfo-url http://127.0.0.1:8080/userinfo --access-token eyJhbGciOi...",
+    ),
+    (
+        "",
+        "Check an ID token against the authorization request and callback it came from",
+        "jwt-check -t eyJhbGciOi... --auth-request request.txt --callback 'https://client.example/cb?code=Splx...&state=af0i...'",
+    ),
+    (
+        "",
+        "Verify against the token's x5c chain and reject revoked certificates",
+        "jwt-check -t eyJhbGciOi... --verify --x5c-roots roots.pem --ocsp --crl issuing-ca.crl",
+    ),
+    (
+        "",
+        "Verify against the token's x5c chain, requiring a clientAuth certificate",
+        "jwt-check -t eyJhbGciOi... --verify --x5c-roots roots.pem --cert-eku clientAuth",
+    ),
+    (
+        "",
+        "Cross-check signing and verification with OpenSSL for every algorithm",
+        "jwt-check interop",
+    ),
+    (
+        "",
+        "Check a captured Stripe delivery against the endpoint's signing secret",
+        "jwt-check webhook verify --preset stripe --headers headers.txt --secret-file whsec.txt body.json",
+    ),
+    (
+        "",
+        "Mint a token with an encrypted signing key, the passphrase coming from CI",
+        "jwt-check encode --alg ES256 --key signing-encrypted.pem --passphrase-env SIGNING_KEY_PASSPHRASE --claim sub=ci",
+    ),
+    (
+        "",
+        "Turn a captured (redacted) token into a fixture: drop PII, extend it and re-sign with a test secret",
+        "jwt-check pipe -t \"$CAPTURED\" --drop-claim email --set exp=+1h --resign hs256:env:FIXTURE_SECRET",
+    ),
+    (
+        "",
+        "Sign with a key kept encrypted with age, decrypting it only in memory",
+        "jwt-check encode --alg ES256 --key signing.pem.age --age-identity ~/.config/age/keys.txt --claim sub=ci",
+    ),
+    (
+        "",
+        "Sign with the SSH key already loaded in ssh-agent, and check it with the .pub file",
+        "jwt-check encode --alg ES256 --ssh-agent ~/.ssh/id_ecdsa.pub --claim sub=dev > dev.jwt && jwt-check --file dev.jwt --verify --key ~/.ssh/id_ecdsa.pub",
+    ),
+    (
+        "",
+        "Sign a release artifact with a detached JWS, then check it",
+        "jwt-check jws sign --alg ES256 --key release.pem --detached app.tar.gz > app.tar.gz.jws && jwt-check jws verify --key release-public.pem app.tar.gz app.tar.gz.jws",
+    ),
+    (
+        "",
+        "Read the verdict of a Play Integrity token with the Play Console's keys",
+        "jwt-check attestation --decryption-key \"$PLAY_DECRYPTION_KEY\" --verification-key \"$PLAY_VERIFICATION_KEY\" eyJhbGciOiJBMjU2S1ci...",
+    ),
+    (
+        "",
+        "Check this build against the RFC example tokens",
+        "jwt-check conformance",
+    ),
+    (
+        "",
+        "Show the signing input and digest behind a token, to compare with another library",
+        "jwt-check encode '{\"sub\":\"1\"}' --secret s3cret --explain",
+    ),
+    (
+        "",
+        "See which base64 chunk of the token holds which part of the JSON",
+        "jwt-check -t eyJhbGciOi... --annotated",
+    ),
+    (
+        "",
+        "Show WS-Federation claim URIs as roles, email and so on, and query them",
+        "jwt-check -t eyJhbGciOi... --aliases builtin --claim roles",
+    ),
+    (
+        "",
+        "Decode a legacy token with a standard-base64 alphabet and a metadata segment",
+        "jwt-check -t eyJ2IjoyfQ.eyJhbGciOi... --dialect legacy-dialect.yaml",
+    ),
+    (
+        "",
+        "Check which gateway route a token would take, and why",
+        "jwt-check -t eyJhbGciOi... --routes gateway-routes.yaml",
+    ),
+    (
+        "",
+        "Show claims as a table with readable dates",
+        "jwt-check -t eyJhbGciOi... --format table --humanize-dates",
+    ),
+    (
+        "",
+        "Pipe the decoded token to jq",
+        "jwt-check -t eyJhbGciOi... --format json | jq .payload",
+    ),
+    (
+        "",
+        "Pin the JSON shape a script was written against",
+        "jwt-check -t eyJhbGciOi... --format json --output-version 2",
+    ),
+    (
+        "",
+        "Verify an HS256 token with a shared secret",
+        "jwt-check -t eyJhbGciOi... --verify --secret-file secret.txt",
+    ),
+    (
+        "",
+        "Verify an RS256 or ES256 token with a PEM public key",
+        "jwt-check -t eyJhbGciOi... --verify --key public.pem",
+    ),
+    (
+        "",
+        "Verify against an identity provider's key set, matching the token's kid",
+        "jwt-check -t eyJhbGciOi... --verify --jwks-file jwks.json",
+    ),
+    (
+        "",
+        "Skip repeated RSA checks when a script verifies the same token per request",
+        "jwt-check -t eyJhbGciOi... --verify --key public.pem --cache",
+    ),
+    (
+        "",
+        "Check expiry and the expected issuer and audience, allowing 30s of clock skew",
+        "jwt-check -t eyJhbGciOi... --validate --iss https://issuer.example --aud api --leeway 30s",
+    ),
+    (
+        "",
+        "Require a token meant for both the orders and billing APIs",
+        "jwt-check -t eyJhbGciOi... --validate --aud https://orders.example --aud https://billing.example --aud-match all",
+    ),
+    (
+        "",
+        "Accept an issuer URL that differs only by a trailing slash or its host's case",
+        "jwt-check -t eyJhbGciOi... --validate --iss https://issuer.example --normalize trailing-slash --normalize scheme-case",
+    ),
+    (
+        "",
+        "Warn when fewer than 10 minutes of lifetime remain",
+        "jwt-check -t eyJhbGciOi... --expiring-threshold 10m",
+    ),
+    (
+        "",
+        "Check a token against a golden snapshot, ignoring volatile claims",
+        "jwt-check -t eyJhbGciOi... --expect golden.json --expect-ignore iat,exp,jti",
+    ),
+    (
+        "",
+        "Decrypt a JWE with an RSA private key and decode the token inside",
+        "jwt-check -t eyJhbGciOiJSU0EtT0FFUCIs... --key private.pem",
+    ),
+    (
+        "",
+        "Print one claim as plain text for a script",
+        "jwt-check -t eyJhbGciOi... --claim realm_access.roles.0 --format raw",
+    ),
+    (
+        "",
+        "Decode a token copied out of a packet capture as hex",
+        "jwt-check --input-encoding hex --file token.hex",
+    ),
+    (
+        "",
+        "Audit a token for known weaknesses in a CI pipeline",
+        "jwt-check -t eyJhbGciOi... --audit --secret \"$JWT_SECRET\"",
+    ),
+    (
+        "",
+        "Validate a file of tokens, one per line, as NDJSON",
+        "jwt-check --batch tokens.txt --validate --verify --jwks-file jwks.json",
+    ),
+    (
+        "",
+        "Follow a long batch run live, one record at a time",
+        "jwt-check --batch tokens.txt --validate --unbuffered | jq -c 'select(.status != \"valid\")'",
+    ),
+    (
+        "",
+        "Look for issuance spikes per issuer and subject in an hour of tokens",
+        "jwt-check --batch tokens.txt --stats --stats-bucket 1m",
+    ),
+    (
+        "",
+        "Audit a token dump in CI, tolerating up to 1% bad tokens",
+        "jwt-check --batch tokens.txt --validate --fail-threshold 1%",
+    ),
+    (
+        "",
+        "Decode an untrusted token with no network and a read-only filesystem",
+        "jwt-check --file suspicious.txt --offline --sandbox",
+    ),
+    (
+        "",
+        "Reshape claims with a YAML mapping",
+        "jwt-check -t eyJhbGciOi... --map mapping.yaml",
+    ),
+    (
+        "daemon",
+        "Keep JWKS documents warm for later --jwks-url runs",
+        "jwt-check daemon --jwks-ttl 10m &",
+    ),
+    (
+        "wizard",
+        "Build a verification command interactively",
+        "jwt-check wizard",
+    ),
+    (
+        "bench",
+        "Measure decode throughput, and RS256 and ES256 verification",
+        "jwt-check bench --iterations 10k --alg RS256,ES256",
+    ),
+    (
+        "encode",
+        "Mint an HS256 test token that expires in an hour",
+        "jwt-check encode --claim sub=alice --claim admin=true --secret s3cret --exp 1h",
+    ),
+    (
+        "encode",
+        "Sign claims from a file with an RSA private key",
+        "jwt-check encode --claims-file claims.json --alg RS256 --key private.pem --kid k1",
+    ),
+    (
+        "encode",
+        "Feed a service a token every 5 seconds, each expiring as the next is minted, from an issuer 2 seconds ahead",
+        "jwt-check encode --secret s3cret --every 5s --skew 2s --pipe ./consumer",
+    ),
+    (
+        "capabilities",
+        "List what this build supports as JSON",
+        "jwt-check capabilities --json",
+    ),
+    ("man", "Install the man page", "jwt-check man > jwt-check.1"),
+    (
+        "mock-idp",
+        "Stand in for the identity provider in a service's integration tests",
+        "jwt-check mock-idp --port 9000 --claim aud=api://orders",
+    ),
+    (
+        "scan",
+        "List the tokens in a capture's HTTP traffic, decrypting HTTPS with the browser's key log",
+        "jwt-check scan --pcap capture.pcapng --keylog sslkeys.log",
+    ),
+    (
+        "schema",
+        "Check batch output against its published schema",
+        "jwt-check schema batch --check results.ndjson",
+    ),
+    (
+        "stream",
+        "Audit the tokens on an auth event stream as they arrive",
+        "jwt-check stream --kafka-brokers kafka:9092 --topic auth-logs --field token",
+    ),
+    (
+        "provenance",
+        "Record where this binary came from",
+        "jwt-check provenance > jwt-check.intoto.json",
+    ),
+    (
+        "history",
+        "Find the tokens from an earlier debugging session that a given user sent",
+        "jwt-check history search alice@example.com",
+    ),
+    (
+        "history",
+        "See whether an IdP changed its algorithm, token lifetime or claims",
+        "jwt-check history stats --iss https://login.example.com",
+    ),
+    (
+        "self-update",
+        "Update a jwt-check on a machine with no package manager from an internal mirror",
+        "jwt-check self-update --from http://mirror.internal/jwt-check --release-key release.pub",
+    ),
+    (
+        "glossary",
+        "Document the claims seen in a batch run, with redacted examples",
+        "jwt-check glossary --from batch-results.ndjson --out glossary.md",
+    ),
+];
+
+const NAME: &str = env!("CARGO_PKG_NAME");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What `--version` prints.
+pub fn version_line() -> String {
+    format!("{} {}", NAME, VERSION)
+}
+
+/// `--history-file`, shared by `--save-history` and the `history` commands.
+fn history_file_arg() -> Arg<'static, 'static> {
+    Arg::with_name("history-file")
+        .long("history-file")
+        .value_name("FILE")
+        .help("the encrypted history [default: $XDG_DATA_HOME/jwt-check/history]")
+        .takes_value(true)
+}
+
+pub fn app() -> App<'static, 'static> {
+    App::new(NAME)
+        .version(VERSION)
+        .author("Kevin K. <kbknapp@gmail.com>")
+        .about("Decodes JWT tokens")
+        .arg(
+            Arg::with_name("token")
+                .short("t")
+                .long("token")
+                .value_name("TOKEN")
+                .help("token to decode; read from --file or stdin when omitted")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("file")
+                .long("file")
+                .value_name("PATH")
+                .help("read the token from a file")
+                .takes_value(true)
+                .conflicts_with("token"),
+        )
+        .arg(
+            Arg::with_name("batch")
+                .long("batch")
+                .value_name("FILE")
+                .help("check one token per line of FILE (- for stdin) in parallel, printing NDJSON and a summary")
+                .takes_value(true)
+                .conflicts_with_all(&["token", "file", "map", "expect"]),
+        )
+        .arg(
+            Arg::with_name("where")
+                .long("where")
+                .value_name("CONDITION")
+                .help("with --batch, only check the tokens for which CONDITION holds, in the language of `assert`, e.g. 'payload.sub == \"user-123\"'; conditions can be joined with `and` (repeatable: all must hold)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("batch"),
+        )
+        .arg(
+            Arg::with_name("issued-after")
+                .long("issued-after")
+                .value_name("TIME")
+                .help("with --batch, only check the tokens issued (iat) at or after TIME: 2024-05-01T09:30:00Z, 2024-05-01, seconds since the epoch, or 24h for a day ago")
+                .takes_value(true)
+                .requires("batch"),
+        )
+        .arg(
+            Arg::with_name("issued-before")
+                .long("issued-before")
+                .value_name("TIME")
+                .help("with --batch, only check the tokens issued (iat) before TIME, written as for --issued-after")
+                .takes_value(true)
+                .requires("batch"),
+        )
+        .arg(
+            Arg::with_name("expires-within")
+                .long("expires-within")
+                .value_name("DURATION")
+                .help("with --batch, only check the tokens that haven't expired yet but will within DURATION, e.g. 24h")
+                .takes_value(true)
+                .requires("batch"),
+        )
+        .arg(
+            Arg::with_name("flush-interval")
+                .long("flush-interval")
+                .value_name("DURATION")
+                .help("with --batch, how often records written so far are flushed to stdout [default: 1s]")
+                .takes_value(true)
+                .requires("batch"),
+        )
+        .arg(
+            Arg::with_name("unbuffered")
+                .long("unbuffered")
+                .help("with --batch, flush stdout after every record")
+                .conflicts_with("flush-interval")
+                .requires("batch"),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .help("with --batch, print an issuance histogram by iat per issuer and subject, flagging spikes, then the tokens that stand out from their issuer's (lifetime, alg, aud, claims), instead of NDJSON (JSON with --format json)")
+                .conflicts_with("graph")
+                .requires("batch"),
+        )
+        .arg(
+            Arg::with_name("stats-bucket")
+                .long("stats-bucket")
+                .value_name("DURATION")
+                .help("width of the --stats time buckets, like 5m or 1h (default: picked from the span of iat)")
+                .takes_value(true)
+                .requires("stats"),
+        )
+        .arg(
+            Arg::with_name("fail-threshold")
+                .long("fail-threshold")
+                .value_name("LIMIT")
+                .help("with --batch, exit zero unless more tokens fail than LIMIT: a count (3), a fraction (0.01) or a percentage (1%)")
+                .takes_value(true)
+                .requires("batch"),
+        )
+        .arg(
+            Arg::with_name("canary")
+                .long("canary")
+                .value_name("COMMAND")
+                .help("with --batch, also send each line to COMMAND, another verifier answering one `valid`, `invalid` or `expired` line per token, and report where the verdicts diverge")
+                .takes_value(true)
+                .conflicts_with_all(&["stats", "graph", "sessions"])
+                .requires("batch"),
+        )
+        .arg(
+            Arg::with_name("log-sink")
+                .long("log-sink")
+                .value_name("SINK")
+                .help("with --batch, also log each token that fails, at warning priority (notice when only expired), to stderr, syslog (/dev/log) or journald")
+                .takes_value(true)
+                .possible_values(&["stderr", "syslog", "journald"])
+                .requires("batch"),
+        )
+        .arg(
+            Arg::with_name("out")
+                .long("out")
+                .value_name("URL")
+                .help("deliver the --batch records or --audit findings to a path, file://, http[s]:// (POST) or s3://bucket/key instead of stdout")
+                .takes_value(true)
+                .conflicts_with_all(&["unbuffered", "stats", "graph", "sessions"]),
+        )
+        .arg(
+            Arg::with_name("out-retries")
+                .long("out-retries")
+                .value_name("COUNT")
+                .help("how many times to retry a failed --out delivery, with exponential backoff [default: 3]")
+                .takes_value(true)
+                .requires("out"),
+        )
+        .arg(
+            Arg::with_name("sarif")
+                .long("sarif")
+                .value_name("URL")
+                .help("deliver the --audit findings as a SARIF 2.1.0 log to a path, file://, http[s]:// (POST) or s3://bucket/key")
+                .takes_value(true)
+                .requires("audit"),
+        )
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .value_name("URL")
+                .help("deliver a self-contained report for people, of the --audit findings or the --batch --stats, to a path, file://, http[s]:// (POST) or s3://bucket/key")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("report-format")
+                .long("report-format")
+                .value_name("FORMAT")
+                .help("write the --report as html or md [default: html for a .html destination, md otherwise]")
+                .takes_value(true)
+                .possible_values(&["html", "md"])
+                .requires("report"),
+        )
+        .arg(
+            Arg::with_name("junit")
+                .long("junit")
+                .value_name("URL")
+                .help("deliver a JUnit XML report of the checks run, one test case per check, to a path, file://, http[s]:// (POST) or s3://bucket/key")
+                .takes_value(true)
+                .conflicts_with("batch"),
+        )
+        .arg(
+            Arg::with_name("sessions")
+                .long("sessions")
+                .help("with --batch, group the tokens into sessions by sid, or sub without one, and print each as a timeline of logins, refreshes and reissues with its anomalies, instead of NDJSON (JSON with --format json)")
+                .conflicts_with_all(&["stats", "graph"])
+                .requires("batch"),
+        )
+        .arg(
+            Arg::with_name("graph")
+                .long("graph")
+                .value_name("FORMAT")
+                .help("with --batch, print how the tokens relate (issuer, subject, session, actor) as a graph instead of NDJSON")
+                .takes_value(true)
+                .possible_values(&["dot", "mermaid"])
+                .requires("batch"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("how to print the decoded token")
+                .takes_value(true)
+                .possible_values(&["pretty", "json", "raw", "table"])
+                .default_value("pretty"),
+        )
+        .arg(
+            Arg::with_name("output-version")
+                .long("output-version")
+                .value_name("VERSION")
+                .help("shape of the --format json document; pin it in scripts")
+                .takes_value(true)
+                .possible_values(&["1", "2"])
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("no-color")
+                .long("no-color")
+                .help("never color output (also off when NO_COLOR is set or stdout is not a terminal)"),
+        )
+        .arg(
+            Arg::with_name("humanize-dates")
+                .long("humanize-dates")
+                .help("show exp, nbf and iat as RFC 3339 dates"),
+        )
+        .arg(
+            Arg::with_name("decode-nested")
+                .long("decode-nested")
+                .help("expand claims holding JWTs, stringified JSON, base64 JSON or URL-encoded text, marking each with `$decoded`"),
+        )
+        .arg(
+            Arg::with_name("geoip")
+                .long("geoip")
+                .value_name("FILE")
+                .help("look up the IP addresses in claims in a local MaxMind DB file (GeoLite2-City, GeoLite2-ASN, ...) and show each with its country, city and network, marked with `$geoip`")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("claim-decrypt")
+                .long("claim-decrypt")
+                .value_name("CLAIM=KEYREF:SCHEME")
+                .help("decrypt a claim sealed with libsodium before showing it; KEYREF is env:NAME or a key file (32 bytes raw, hex or base64), SCHEME is secretbox or xchacha20poly1305 (repeatable)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with("batch"),
+        )
+        .arg(
+            Arg::with_name("signature-encoding")
+                .long("signature-encoding")
+                .value_name("ENCODING")
+                .help("how to print the signature bytes")
+                .takes_value(true)
+                .possible_values(&["base64", "hex"])
+                .default_value("base64"),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .help("verify the signature; exits non-zero when it does not match"),
+        )
+        .arg(
+            Arg::with_name("secret")
+                .long("secret")
+                .value_name("SECRET")
+                .help("shared secret for HS256/HS384/HS512, or the key of a `dir` JWE")
+                .takes_value(true)
+                .conflicts_with_all(&["secret-file", "key"]),
+        )
+        .arg(
+            Arg::with_name("secret-file")
+                .long("secret-file")
+                .value_name("FILE")
+                .help("read the shared secret from a file (one trailing newline is ignored, except for JWE keys)")
+                .takes_value(true)
+                .conflicts_with("key"),
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .value_name("PEM")
+                .help("PEM public key or certificate for RS*, PS*, ES256, ES384, ES256K and EdDSA (or a hex secp256k1 point, multibase multikey, did:key or CESR key), or an RSA private key to decrypt a JWE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("age-identity")
+                .long("age-identity")
+                .value_name("FILE")
+                .help("age identity file (age-keygen output) for a --secret-file or JWE --key encrypted with age; OpenPGP files go through gpg instead")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("previous-key")
+                .long("previous-key")
+                .value_name("PEM")
+                .help("an earlier key of the signer's, tried after --key; repeat for each key rotated out, as long-lived licenses need")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("key"),
+        )
+        .arg(
+            Arg::with_name("jwks-file")
+                .long("jwks-file")
+                .value_name("FILE")
+                .help("JSON Web Key Set; the key is chosen by the token's kid and alg")
+                .takes_value(true)
+                .conflicts_with_all(&["secret", "secret-file", "key", "jwks-url"]),
+        )
+        .arg(
+            Arg::with_name("jwks-url")
+                .long("jwks-url")
+                .value_name("URL")
+                .help("fetch the JSON Web Key Set from a file://, https:// or http:// URL; plain http:// warns unless the host is loopback")
+                .takes_value(true)
+                .conflicts_with_all(&["secret", "secret-file", "key"]),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .value_name("DURATION")
+                .help("give up on each JWKS, OCSP or report fetch after DURATION (e.g. 2s), name lookups included, instead of waiting up to ten seconds for each step")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("DIR")
+                .help("keep every JWKS, discovery or OCSP document this run fetches in DIR, for --replay")
+                .takes_value(true)
+                .conflicts_with("replay"),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .value_name("DIR")
+                .help("answer every fetch from the documents --record kept in DIR, without touching the network")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("doh-url")
+                .long("doh-url")
+                .value_name("URL")
+                .help("resolve the hosts of JWKS, discovery and other fetches through the RFC 8484 DNS-over-HTTPS resolver at URL, over https:// (or http:// on loopback), instead of the system's")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("resolve")
+                .long("resolve")
+                .value_name("HOST:PORT:ADDRESS")
+                .help("connect to HOST at ADDRESS instead of looking it up, for hosts only split-horizon DNS knows; as for curl, or HOST:ADDRESS for any port, with an IPv6 ADDRESS bracketed (repeatable)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("x5c-roots")
+                .long("x5c-roots")
+                .value_name("PEM")
+                .help("verify with the token's x5c certificate chain, trusting the root certificates in PEM")
+                .takes_value(true)
+                .conflicts_with_all(&["secret", "secret-file", "key", "jwks-file", "jwks-url"]),
+        )
+        .arg(
+            Arg::with_name("trust-dir")
+                .long("trust-dir")
+                .value_name("DIR")
+                .help("verify an Open Banking software statement, or a token signed with the keys one names, against a local mirror of the UK or Brazil directory keystore")
+                .takes_value(true)
+                .conflicts_with_all(&["secret", "secret-file", "key", "jwks-file", "jwks-url", "x5c-roots"]),
+        )
+        .arg(
+            Arg::with_name("software-statement")
+                .long("software-statement")
+                .value_name("FILE")
+                .help("the software statement whose software signed the token, when it isn't in the token's claims")
+                .takes_value(true)
+                .requires("trust-dir"),
+        )
+        .arg(
+            Arg::with_name("did")
+                .long("did")
+                .help("verify with the key of the token's DID issuer: a did:key, or a did:web whose DID document is fetched, or given as --did-document")
+                .conflicts_with_all(&["secret", "secret-file", "key", "jwks-file", "jwks-url", "x5c-roots", "trust-dir"]),
+        )
+        .arg(
+            Arg::with_name("did-document")
+                .long("did-document")
+                .value_name("FILE")
+                .help("the did:web issuer's DID document, downloaded from its did.json URL, in place of fetching it")
+                .takes_value(true)
+                .requires("did"),
+        )
+        .arg(
+            Arg::with_name("crl")
+                .long("crl")
+                .value_name("FILE")
+                .help("check the x5c certificates against this CRL, in PEM or DER (repeatable)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("x5c-roots"),
+        )
+        .arg(
+            Arg::with_name("ocsp-response")
+                .long("ocsp-response")
+                .value_name("FILE")
+                .help("a stapled DER OCSP response for the x5c certificates, used when --ocsp can't get one (repeatable)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("x5c-roots"),
+        )
+        .arg(
+            Arg::with_name("ocsp")
+                .long("ocsp")
+                .help("ask each x5c certificate's OCSP responder (http:// only) for its revocation status")
+                .requires("x5c-roots")
+                .conflicts_with("offline"),
+        )
+        .arg(
+            Arg::with_name("cert-eku")
+                .long("cert-eku")
+                .value_name("NAME|OID")
+                .help("require this extendedKeyUsage (e.g. clientAuth) in a certificate-backed key")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("offline")
+                .long("offline")
+                .help("never use the network; on Linux a seccomp filter blocks sockets for the whole run")
+                .conflicts_with_all(&["jwks-url", "out", "sarif", "junit", "post-to", "doh-url", "ocsp", "userinfo-url"]),
+        )
+        .arg(
+            Arg::with_name("sandbox")
+                .long("sandbox")
+                .help("make the filesystem read-only for this run (Linux Landlock), except the --cache and --save-history directories, the --record directory and the local --out, --report, --junit and --sarif files"),
+        )
+        .arg(
+            Arg::with_name("no-daemon")
+                .long("no-daemon")
+                .help("fetch --jwks-url directly even when a jwt-check daemon is running")
+                .requires("jwks-url"),
+        )
+        .arg(
+            Arg::with_name("cache")
+                .long("cache")
+                .help("remember successful --verify results on disk until the token expires"),
+        )
+        .arg(
+            Arg::with_name("cache-dir")
+                .long("cache-dir")
+                .value_name("DIR")
+                .help("where --cache keeps entries [default: $XDG_CACHE_HOME/jwt-check]")
+                .takes_value(true)
+                .requires("cache"),
+        )
+        .arg(
+            Arg::with_name("save-history")
+                .long("save-history")
+                .help("record the token's fingerprint, header and claims in the encrypted history, for `jwt-check history`; the passphrase is JWT_CHECK_HISTORY_PASSPHRASE or asked for"),
+        )
+        .arg(
+            Arg::with_name("history-raw")
+                .long("history-raw")
+                .help("keep the raw token in the history too")
+                .requires("save-history"),
+        )
+        .arg(history_file_arg().requires("save-history"))
+        .arg(
+            Arg::with_name("audit")
+                .long("audit")
+                .help("report known weaknesses (alg none, guessable secrets, embedded keys, ...); exits non-zero on high-severity findings"),
+        )
+        .arg(
+            Arg::with_name("fix")
+                .long("fix")
+                .help("with --audit, also print the token with its fixable findings fixed (typ, kid, exp) and signed again with --resign-with, after one `path: old -> new` line per change")
+                .requires_all(&["audit", "resign-with"])
+                .conflicts_with("batch"),
+        )
+        .arg(
+            Arg::with_name("resign-with")
+                .long("resign-with")
+                .value_name("FILE")
+                .help("the key --fix signs with: a PEM private key, or a file holding an HMAC secret")
+                .takes_value(true)
+                .requires("fix"),
+        )
+        .arg(
+            Arg::with_name("fix-kid")
+                .long("fix-kid")
+                .value_name("KID")
+                .help("the kid --fix sets when the header has none")
+                .takes_value(true)
+                .requires("fix"),
+        )
+        .arg(
+            Arg::with_name("validate")
+                .long("validate")
+                .help("check exp, nbf and iat, plus --iss/--aud/--sub when given; exits non-zero on failure"),
+        )
+        .arg(
+            Arg::with_name("leeway")
+                .long("leeway")
+                .value_name("DURATION")
+                .help("clock skew allowed by --validate, e.g. 30s or 2m [default: 0]")
+                .takes_value(true)
+                .requires("validate"),
+        )
+        .arg(
+            Arg::with_name("iss")
+                .long("iss")
+                .value_name("ISSUER")
+                .help("issuer the token's iss claim must equal")
+                .takes_value(true)
+                .requires("validate"),
+        )
+        .arg(
+            Arg::with_name("aud")
+                .long("aud")
+                .value_name("AUDIENCE")
+                .help("audience the token's aud claim (a string or an array) must contain; repeat for several, matched per --aud-match")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("validate"),
+        )
+        .arg(
+            Arg::with_name("aud-match")
+                .long("aud-match")
+                .value_name("MODE")
+                .help("with several --aud: aud must contain any of them, all of them, or exactly them [default: any]")
+                .takes_value(true)
+                .possible_values(&["any", "all", "exact"])
+                .requires("aud"),
+        )
+        .arg(
+            Arg::with_name("normalize")
+                .long("normalize")
+                .value_name("RULE")
+                .help("URL difference --iss and --aud overlook: a trailing slash, scheme or host case, or percent-encoding; repeat for several [default: compare exactly]")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(&Normalization::RULES)
+                .requires("validate"),
+        )
+        .arg(
+            Arg::with_name("sub")
+                .long("sub")
+                .value_name("SUBJECT")
+                .help("subject the token's sub claim must equal")
+                .takes_value(true)
+                .requires("validate"),
+        )
+        .arg(
+            Arg::with_name("expiring-threshold")
+                .long("expiring-threshold")
+                .value_name("DURATION")
+                .help("remaining lifetime below which a token counts as expiring soon (e.g. 90s, 5m, 1h)")
+                .takes_value(true)
+                .default_value("5m"),
+        )
+        .arg(
+            Arg::with_name("expect")
+                .long("expect")
+                .value_name("FILE")
+                .help("compare header, payload and freshness against a golden JSON snapshot")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("expect-ignore")
+                .long("expect-ignore")
+                .value_name("FIELDS")
+                .help("comma separated claims or paths (e.g. payload.jti) skipped by --expect")
+                .takes_value(true)
+                .default_value("iat,exp,nbf,jti"),
+        )
+        .arg(
+            Arg::with_name("input-encoding")
+                .long("input-encoding")
+                .value_name("ENCODING")
+                .help("how the input spells the token: text, hex (e.g. from a packet capture) or binary (raw bytes from a dump)")
+                .takes_value(true)
+                .possible_values(&["text", "hex", "binary"])
+                .conflicts_with("batch"),
+        )
+        .arg(
+            Arg::with_name("cookie")
+                .long("cookie")
+                .value_name("NAME")
+                .help("when the input is a request copied from devtools, decode this cookie instead of the bearer token")
+                .takes_value(true)
+                .conflicts_with("batch"),
+        )
+        .arg(
+            Arg::with_name("claim")
+                .long("claim")
+                .value_name("PATH")
+                .help("print only this value: a dot path (realm_access.roles), a JSON pointer (/realm_access/roles/0) or header.<path>; exits non-zero when it is missing, or when --verify or --validate fails first")
+                .takes_value(true)
+                .conflicts_with_all(&["map", "batch"]),
+        )
+        .arg(
+            Arg::with_name("chain")
+                .long("chain")
+                .help("print the tokens nested in this one (JWE contents, JWTs in claims, act delegation chains) as a tree; exits non-zero on a cycle")
+                .conflicts_with_all(&["map", "claim", "claim-sources", "batch"]),
+        )
+        .arg(
+            Arg::with_name("max-chain-depth")
+                .long("max-chain-depth")
+                .value_name("N")
+                .help("how deep --chain follows nested tokens and actors before failing")
+                .takes_value(true)
+                .default_value("8"),
+        )
+        .arg(
+            Arg::with_name("claim-sources")
+                .long("claim-sources")
+                .help("print every claim with the layer it came from (payload, JWS header, JWE header, introspection, userinfo), highest precedence first, marking conflicts")
+                .conflicts_with_all(&["map", "claim", "batch"]),
+        )
+        .arg(
+            Arg::with_name("userinfo")
+                .long("userinfo")
+                .value_name("FILE")
+                .help("a saved UserInfo response to include as a claim source; implies --claim-sources")
+                .takes_value(true)
+                .conflicts_with_all(&["map", "claim", "batch"]),
+        )
+        .arg(
+            Arg::with_name("userinfo-url")
+                .long("userinfo-url")
+                .value_name("URL")
+                .help("fetch the UserInfo response with --access-token, over https:// or http:// to a loopback host, and diff it against the token's claims; implies --claim-sources")
+                .takes_value(true)
+                .requires("access-token")
+                .conflicts_with_all(&["userinfo", "map", "claim", "batch"]),
+        )
+        .arg(
+            Arg::with_name("access-token")
+                .long("access-token")
+                .value_name("TOKEN")
+                .help("the access token to present to --userinfo-url")
+                .takes_value(true)
+                .requires("userinfo-url"),
+        )
+        .arg(
+            Arg::with_name("introspection")
+                .long("introspection")
+                .value_name("FILE")
+                .help("a saved token introspection (RFC 7662) response to include as a claim source; implies --claim-sources")
+                .takes_value(true)
+                .conflicts_with_all(&["map", "claim", "batch"]),
+        )
+        .arg(
+            Arg::with_name("routes")
+                .long("routes")
+                .value_name("FILE")
+                .help("dry-run a gateway's claim-based routing: print which route in the YAML config FILE the token selects and why each route does or does not match; exits non-zero when none does")
+                .takes_value(true)
+                .conflicts_with_all(&["map", "claim", "chain", "claim-sources", "batch"]),
+        )
+        .arg(
+            Arg::with_name("summary-line")
+                .long("summary-line")
+                .help("print one line instead of the decoded token: the verdict of the checks, sub, iss and expiry, for pasting into chat or a PR comment; the other reports go to stderr")
+                .conflicts_with_all(&["map", "claim", "routes", "identity", "chain", "claim-sources", "batch"]),
+        )
+        .arg(
+            Arg::with_name("compat")
+                .long("compat")
+                .value_name("TOOL")
+                .help("print the decoded token as jwt-cli's `jwt decode` or step's `step crypto jwt inspect` do, for scripts that parse their output; --format json selects `jwt decode --json`; the other reports go to stderr")
+                .takes_value(true)
+                .possible_values(&["jwt-cli", "stepjwt"])
+                .conflicts_with_all(&["map", "claim", "routes", "identity", "chain", "claim-sources", "batch", "summary-line", "annotated"]),
+        )
+        .arg(
+            Arg::with_name("post-to")
+                .long("post-to")
+                .value_name("URL")
+                .help("also POST the --summary-line to the chat webhook URL, as a Slack-style {\"text\": ...} JSON body")
+                .takes_value(true)
+                .requires("summary-line"),
+        )
+        .arg(
+            Arg::with_name("identity")
+                .long("identity")
+                .value_name("FILE")
+                .help("print the token's identity (id, username, email, name, tenant) read from the claims its issuer's vendor uses; FILE is YAML adding vendors or overriding the presets' mappings, or `builtin` for the presets alone")
+                .takes_value(true)
+                .conflicts_with_all(&["map", "claim", "routes", "chain", "claim-sources", "batch"]),
+        )
+        .arg(
+            Arg::with_name("idp-config")
+                .long("idp-config")
+                .value_name("FILE")
+                .help("trace each claim to the mapper, client scope, action or rule that adds it, from a Keycloak realm export or an Auth0 tenant export (JSON or YAML); fails when a claim or scope has no source in effect")
+                .takes_value(true)
+                .conflicts_with_all(&["map", "claim", "routes", "identity", "chain", "claim-sources", "batch", "summary-line", "compat"]),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .value_name("PROFILE")
+                .help("check the token against a security profile's requirements and report each one; fails when any is not met")
+                .takes_value(true)
+                .possible_values(&jwt_check::profile::PRESETS)
+                .conflicts_with("batch"),
+        )
+        .arg(
+            Arg::with_name("revoked-jti")
+                .long("revoked-jti")
+                .value_name("FILE")
+                .help("the revocation list --profile license checks the jti against: one jti per line, # for comments")
+                .takes_value(true)
+                .requires("profile"),
+        )
+        .arg(
+            Arg::with_name("preset")
+                .long("preset")
+                .value_name("PRESET")
+                .help("check the token against an identity provider's issuers, algorithms and audience rules, and print its known quirks; with --verify and no key, fetch its JWKS")
+                .takes_value(true)
+                .possible_values(&jwt_check::presets::PRESETS)
+                .conflicts_with("batch"),
+        )
+        .arg(
+            Arg::with_name("client-id")
+                .long("client-id")
+                .value_name("ID")
+                .help("the app's client ID that --preset expects as aud: a bundle or Services ID for Apple, an OAuth client ID for Google")
+                .takes_value(true)
+                .requires("preset"),
+        )
+        .arg(
+            Arg::with_name("project")
+                .long("project")
+                .value_name("ID")
+                .help("the Firebase project ID or Supabase project ref that --preset derives iss and aud from")
+                .takes_value(true)
+                .requires("preset"),
+        )
+        .arg(
+            Arg::with_name("auth-request")
+                .long("auth-request")
+                .value_name("FILE|URL")
+                .help("the authorization request the token answers, as its URL, query string or JSON parameters: check nonce, aud, s_hash, PKCE and redirect_uri against it")
+                .takes_value(true)
+                .conflicts_with("batch"),
+        )
+        .arg(
+            Arg::with_name("callback")
+                .long("callback")
+                .value_name("URL")
+                .help("the redirect the user agent came back with: check its state, code (via c_hash), iss and redirect URI too")
+                .takes_value(true)
+                .requires("auth-request"),
+        )
+        .arg(
+            Arg::with_name("script")
+                .long("script")
+                .value_name("FILE")
+                .help("run the Rhai script FILE against the token, after the other checks; it can `warn`, `deny` the token, or `allow` one that failed only --validate")
+                .takes_value(true)
+                .conflicts_with("batch"),
+        )
+        .arg(
+            Arg::with_name("token-kind")
+                .long("token-kind")
+                .value_name("KIND")
+                .help("which --profile fapi2 requirements apply, instead of guessing from typ and the claims")
+                .takes_value(true)
+                .possible_values(&["request-object", "access-token", "client-assertion", "dpop-proof", "id-token"])
+                .requires("profile"),
+        )
+        .arg(
+            Arg::with_name("annotated")
+                .long("annotated")
+                .help("show the token above the decoded output, each base64 group over the bytes it encodes")
+                .conflicts_with_all(&["batch", "dialect"]),
+        )
+        .arg(
+            Arg::with_name("aliases")
+                .long("aliases")
+                .value_name("FILE")
+                .help("rename vendor claim URIs to friendly names, for output and for --claim, --map, --routes and --claim-decrypt; FILE is a YAML mapping of name: claim, or `builtin` for the WS-Federation and Azure AD URIs")
+                .takes_value(true)
+                .conflicts_with("batch"),
+        )
+        .arg(
+            Arg::with_name("dialect")
+                .long("dialect")
+                .value_name("FILE")
+                .help("read a non-standard token format described by the YAML FILE: separator, base64 alphabets, and which segments are the header, payload, signature or JSON metadata")
+                .takes_value(true)
+                .conflicts_with_all(&["verify", "batch"]),
+        )
+        .arg(
+            Arg::with_name("map")
+                .long("map")
+                .value_name("FILE")
+                .help("reshape the decoded claims with a YAML mapping and print only the result")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("version")
+                .short("V")
+                .long("version")
+                .help("print version information; with --verbose, also the commit, toolchain and features it was built from"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("print diagnostics to stderr, with tokens and secrets redacted"),
+        )
+        .arg(
+            Arg::with_name("unsafe-log-full-values")
+                .long("unsafe-log-full-values")
+                .help("stop redacting tokens and secrets in verbose output"),
+        )
+        .arg(
+            Arg::with_name("assume-ms")
+                .long("assume-ms")
+                .conflicts_with("assume-s")
+                .help("read exp, nbf and iat as milliseconds"),
+        )
+        .arg(
+            Arg::with_name("assume-s")
+                .long("assume-s")
+                .help("read exp, nbf and iat as seconds, even when they look like milliseconds"),
+        )
+        .arg(
+            Arg::with_name("stringify-numbers")
+                .long("stringify-numbers")
+                .help("print numbers that would lose precision as doubles (e.g. snowflake IDs) as exact strings"),
+        )
+        .arg(
+            Arg::with_name("ascii")
+                .long("ascii")
+                .help("escape non-ASCII characters in the output as \\uXXXX"),
+        )
+        .arg(
+            Arg::with_name("help-long")
+                .long("help-long")
+                .help("print help for every subcommand, with examples"),
+        )
+        .setting(AppSettings::SubcommandsNegateReqs)
+        // handled by our own --version, so that --verbose can extend it
+        .setting(AppSettings::DisableVersion)
+        .subcommands(subcommands())
+}
+
+fn subcommands() -> Vec<App<'static, 'static>> {
+    vec![
+        SubCommand::with_name("agility")
+            .about("Reviews which signature algorithms and key sizes an issuer's JWKS offers, against a best-practice profile")
+            .arg(
+                Arg::with_name("jwks-file")
+                    .long("jwks-file")
+                    .value_name("FILE")
+                    .help("the JWKS to review")
+                    .takes_value(true)
+                    .required_unless_one(&["jwks-url", "issuer"])
+                    .conflicts_with_all(&["jwks-url", "issuer"]),
+            )
+            .arg(
+                Arg::with_name("jwks-url")
+                    .long("jwks-url")
+                    .value_name("URL")
+                    .help("fetch the JWKS to review from a file://, https:// or http:// URL")
+                    .takes_value(true)
+                    .conflicts_with("issuer"),
+            )
+            .arg(
+                Arg::with_name("issuer")
+                    .long("issuer")
+                    .value_name("URL")
+                    .help("review the JWKS at the jwks_uri of this issuer's /.well-known/openid-configuration")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("profile")
+                    .long("profile")
+                    .value_name("PROFILE")
+                    .help("baseline (RFC 8725) or fapi2 (PS256, ES256 and EdDSA only) [default: baseline]")
+                    .takes_value(true)
+                    .possible_values(&["baseline", "fapi2"]),
+            )
+            .arg(
+                Arg::with_name("expect-es256k")
+                    .long("expect-es256k")
+                    .help("don't flag secp256k1 keys, for systems that use ES256K on purpose"),
+            )
+            .arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .help("print the review as JSON"),
+            ),
+        SubCommand::with_name("assert")
+            .about("Checks claims assertions against a token, for shell-based integration tests")
+            .arg(
+                Arg::with_name("token")
+                    .short("t")
+                    .long("token")
+                    .value_name("TOKEN")
+                    .help("the token to check")
+                    .takes_value(true)
+                    .required_unless("file")
+                    .conflicts_with("file"),
+            )
+            .arg(
+                Arg::with_name("file")
+                    .long("file")
+                    .value_name("FILE")
+                    .help("read the token from a file, or `-` for stdin")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("assertions")
+                    .value_name("ASSERTION")
+                    .help("e.g. 'aud == \"api://orders\"', 'exp within 1h', 'has scope \"orders:read\"'")
+                    .multiple(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("quiet")
+                    .short("q")
+                    .long("quiet")
+                    .help("print only the assertions that fail"),
+            )
+            .arg(
+                Arg::with_name("junit")
+                    .long("junit")
+                    .value_name("URL")
+                    .help("also deliver the outcomes as a JUnit XML report to a path, file://, http[s]:// (POST) or s3://bucket/key")
+                    .takes_value(true),
+            ),
+        SubCommand::with_name("bench")
+            .about("Measures token decoding and verification throughput on this machine")
+            .arg(
+                Arg::with_name("iterations")
+                    .short("n")
+                    .long("iterations")
+                    .value_name("COUNT")
+                    .help("number of decode iterations, k/m suffixes allowed (e.g. 100k)")
+                    .takes_value(true)
+                    .default_value("100k"),
+            )
+            .arg(
+                Arg::with_name("token")
+                    .short("t")
+                    .long("token")
+                    .value_name("TOKEN")
+                    .help("token to decode, defaults to a built-in HS256 sample")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("alg")
+                    .long("alg")
+                    .value_name("ALG")
+                    .help("also time verifying a token signed with ALG and a built-in key; repeatable, comma-separated, or `all`")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .use_delimiter(true),
+            ),
+        SubCommand::with_name("capabilities")
+            .about("Lists the algorithms, formats and features supported by this build")
+            .arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .help("print machine-readable JSON"),
+            ),
+        SubCommand::with_name("compliance")
+            .about("Checks sample tokens from an issuer against a profile and policy, and lists what to fix")
+            .arg(
+                Arg::with_name("samples")
+                    .value_name("FILE")
+                    .help("sample tokens, one per line, or `-` for stdin")
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("profile")
+                    .long("profile")
+                    .value_name("PROFILE")
+                    .help("check the samples against this --profile")
+                    .takes_value(true)
+                    .possible_values(&jwt_check::profile::PRESETS)
+                    .required_unless("policy"),
+            )
+            .arg(
+                Arg::with_name("policy")
+                    .long("policy")
+                    .value_name("FILE")
+                    .help("check the samples against an internal minting policy in YAML")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .help("print the remediation list as JSON"),
+            ),
+        SubCommand::with_name("conformance")
+            .about("Checks the parser and verifier against the RFC 7515 and RFC 7520 examples")
+            .arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .help("print the results as JSON"),
+            ),
+        SubCommand::with_name("daemon")
+            .about("Stays resident and serves cached JWKS documents to --jwks-url runs")
+            .arg(
+                Arg::with_name("socket")
+                    .long("socket")
+                    .value_name("PATH")
+                    .help("Unix socket to listen on [default: $XDG_RUNTIME_DIR/jwt-check.sock]")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("jwks-ttl")
+                    .long("jwks-ttl")
+                    .value_name("DURATION")
+                    .help("how long a fetched JWKS is reused, e.g. 30s or 10m")
+                    .takes_value(true)
+                    .default_value("5m"),
+            )
+            .arg(
+                Arg::with_name("max-stale")
+                    .long("max-stale")
+                    .value_name("DURATION")
+                    .help("past --jwks-ttl, how much longer a JWKS is served while it is refreshed in the background")
+                    .takes_value(true)
+                    .default_value("1h"),
+            )
+            .arg(
+                Arg::with_name("user")
+                    .long("user")
+                    .value_name("NAME")
+                    .help("when started as root, switch to this user once the socket is bound")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("timeout")
+                    .long("timeout")
+                    .value_name("DURATION")
+                    .help("give up on each JWKS, OCSP or report fetch after DURATION (e.g. 2s), name lookups included, instead of waiting up to ten seconds for each step")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("doh-url")
+                    .long("doh-url")
+                    .value_name("URL")
+                    .help("resolve the hosts of JWKS, discovery and other fetches through the RFC 8484 DNS-over-HTTPS resolver at URL, over https:// (or http:// on loopback), instead of the system's")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("resolve")
+                    .long("resolve")
+                    .value_name("HOST:PORT:ADDRESS")
+                    .help("connect to HOST at ADDRESS instead of looking it up, for hosts only split-horizon DNS knows; as for curl, or HOST:ADDRESS for any port, with an IPv6 ADDRESS bracketed (repeatable)")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            ),
+        SubCommand::with_name("dcr")
+            .about("Lints an Open Banking software statement or Dynamic Client Registration request")
+            .arg(
+                Arg::with_name("input")
+                    .value_name("TOKEN|JSON")
+                    .help("the software statement, signed registration request or JSON registration body")
+                    .required_unless("file")
+                    .conflicts_with("file"),
+            )
+            .arg(
+                Arg::with_name("file")
+                    .long("file")
+                    .value_name("FILE")
+                    .help("read the input from a file, or `-` for stdin")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("trust-dir")
+                    .long("trust-dir")
+                    .value_name("DIR")
+                    .help("check the signatures against this mirror of the directory keystore, as for --verify")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .help("print the problems as JSON"),
+            ),
+        SubCommand::with_name("attestation")
+            .about("Decodes an App Attest attestation or assertion, or a Play Integrity token")
+            .arg(
+                Arg::with_name("input")
+                    .value_name("ATTESTATION")
+                    .help("the base64 attestation object or assertion, or the Play Integrity token")
+                    .required_unless("file")
+                    .conflicts_with("file"),
+            )
+            .arg(
+                Arg::with_name("file")
+                    .long("file")
+                    .value_name("FILE")
+                    .help("read the input from a file (base64 or binary CBOR), or `-` for stdin")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("decryption-key")
+                    .long("decryption-key")
+                    .value_name("BASE64")
+                    .help("the Play Console's decryption key, to read a Play Integrity verdict")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("verification-key")
+                    .long("verification-key")
+                    .value_name("BASE64")
+                    .help("the Play Console's verification key, to check the verdict's signature")
+                    .requires("decryption-key")
+                    .takes_value(true),
+            ),
+        SubCommand::with_name("encode")
+            .about("Signs claims into a compact JWT, for testing")
+            .arg(
+                Arg::with_name("claims")
+                    .value_name("JSON")
+                    .help("claims as a JSON object")
+                    .conflicts_with("claims-file"),
+            )
+            .arg(
+                Arg::with_name("claims-file")
+                    .long("claims-file")
+                    .value_name("FILE")
+                    .help("read the claims JSON from a file, or `-` for stdin")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("from-token")
+                    .long("from-token")
+                    .value_name("TOKEN")
+                    .help("start from the claims of an existing token; repeat to merge several")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .conflicts_with_all(&["claims", "claims-file"]),
+            )
+            .arg(
+                Arg::with_name("prefer")
+                    .long("prefer")
+                    .value_name("STRATEGY")
+                    .help("which --from-token wins a claim they disagree on: the latest or earliest issued, the first or last given, or error [default: error]")
+                    .takes_value(true)
+                    .possible_values(&["latest", "earliest", "first", "last", "error"])
+                    .requires("from-token"),
+            )
+            .arg(
+                Arg::with_name("claim")
+                    .long("claim")
+                    .value_name("KEY=VALUE")
+                    .help("set one claim; VALUE is read as JSON when it parses, else as a string (repeatable)")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("alg")
+                    .long("alg")
+                    .value_name("ALG")
+                    .help("signing algorithm")
+                    .takes_value(true)
+                    .possible_values(&[
+                        "HS256", "HS384", "HS512", "RS256", "RS384", "RS512", "PS256", "PS384",
+                        "PS512", "ES256", "ES384", "ES256K", "EdDSA",
+                    ])
+                    .default_value("HS256"),
+            )
+            .arg(
+                Arg::with_name("secret")
+                    .long("secret")
+                    .value_name("SECRET")
+                    .help("shared secret for HS256/HS384/HS512")
+                    .takes_value(true)
+                    .conflicts_with_all(&["secret-file", "key"]),
+            )
+            .arg(
+                Arg::with_name("secret-file")
+                    .long("secret-file")
+                    .value_name("FILE")
+                    .help("read the shared secret from a file (one trailing newline is ignored)")
+                    .takes_value(true)
+                    .conflicts_with("key"),
+            )
+            .arg(
+                Arg::with_name("key")
+                    .long("key")
+                    .value_name("PEM")
+                    .help("PEM private key (PKCS#8, PKCS#1 or SEC 1) for RS*, PS*, ES256, ES384, ES256K and EdDSA, or a hex secp256k1 scalar; an encrypted PKCS#8 key asks for its passphrase")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("passphrase-env")
+                    .long("passphrase-env")
+                    .value_name("VAR")
+                    .help("read the passphrase of an encrypted --key from this environment variable instead of the terminal")
+                    .takes_value(true)
+                    .requires("key"),
+            )
+            .arg(
+                Arg::with_name("age-identity")
+                    .long("age-identity")
+                    .value_name("FILE")
+                    .help("decrypt a --key or --secret-file encrypted with age in memory, with this identity file (age-keygen output); OpenPGP files are decrypted by gpg")
+                    .takes_value(true)
+                    .conflicts_with_all(&["secret", "ssh-agent"]),
+            )
+            .arg(
+                Arg::with_name("ssh-agent")
+                    .long("ssh-agent")
+                    .value_name("PUB")
+                    .help("sign with the ssh-agent key (at SSH_AUTH_SOCK) whose OpenSSH public key is this file: RS256 or RS512 for RSA, ES256 or ES384 for ECDSA, EdDSA for Ed25519")
+                    .takes_value(true)
+                    .conflicts_with_all(&["secret", "secret-file", "key"]),
+            )
+            .arg(
+                Arg::with_name("explain")
+                    .long("explain")
+                    .help("print the signing input, its digest, how the key was used and the signature bytes to stderr"),
+            )
+            .arg(
+                Arg::with_name("kid")
+                    .long("kid")
+                    .value_name("KID")
+                    .help("key ID to put in the header")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("exp")
+                    .long("exp")
+                    .value_name("DURATION")
+                    .help("set exp this far from now, e.g. 30m or 1h")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("no-iat")
+                    .long("no-iat")
+                    .help("don't add an iat claim when the claims lack one"),
+            )
+            .arg(
+                Arg::with_name("every")
+                    .long("every")
+                    .value_name("DURATION")
+                    .help("keep minting, one token per window of DURATION counted from the epoch, its iat the window's start and its exp the window's end")
+                    .takes_value(true)
+                    .conflicts_with_all(&["exp", "no-iat"]),
+            )
+            .arg(
+                Arg::with_name("count")
+                    .long("count")
+                    .value_name("COUNT")
+                    .help("with --every, stop after COUNT tokens")
+                    .takes_value(true)
+                    .requires("every"),
+            )
+            .arg(
+                Arg::with_name("skew")
+                    .long("skew")
+                    .value_name("DURATION")
+                    .help("with --every, mint as if the issuer's clock were DURATION ahead, or behind with a leading `-`")
+                    .takes_value(true)
+                    .allow_hyphen_values(true)
+                    .requires("every"),
+            )
+            .arg(
+                Arg::with_name("pipe")
+                    .long("pipe")
+                    .value_name("COMMAND")
+                    .help("with --every, write each token as a line to the stdin of COMMAND, run once through `sh -c`")
+                    .takes_value(true)
+                    .requires("every")
+                    .conflicts_with("to"),
+            )
+            .arg(
+                Arg::with_name("to")
+                    .long("to")
+                    .value_name("URL")
+                    .help("with --every, deliver each token to a path (rewritten each time), file://, http:// (POST) or s3://bucket/key")
+                    .takes_value(true)
+                    .requires("every"),
+            ),
+        SubCommand::with_name("examples").about("Prints example invocations for common tasks"),
+        SubCommand::with_name("glossary")
+            .about("Documents every claim seen in --batch output as a Markdown table")
+            .arg(
+                Arg::with_name("from")
+                    .long("from")
+                    .value_name("FILE")
+                    .help("NDJSON written by --batch, or `-` for stdin")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("out")
+                    .long("out")
+                    .value_name("FILE")
+                    .help("write the Markdown to FILE instead of stdout")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("unredacted")
+                    .long("unredacted")
+                    .help("show example values in full rather than as their length"),
+            ),
+        SubCommand::with_name("interop")
+            .about("Signs and verifies with every algorithm against OpenSSL, and prints the matrix")
+            .arg(
+                Arg::with_name("openssl")
+                    .long("openssl")
+                    .value_name("PATH")
+                    .help("the openssl binary to test against [default: openssl]")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .help("print the matrix as JSON"),
+            ),
+        SubCommand::with_name("jws")
+            .about("Signs and verifies arbitrary files as compact JWS")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("sign")
+                    .about("Prints a JWS over FILE's bytes")
+                    .arg(
+                        Arg::with_name("file")
+                            .value_name("FILE")
+                            .help("the file to sign")
+                            .required(true),
+                    )
+                    .arg(
+                        Arg::with_name("alg")
+                            .long("alg")
+                            .value_name("ALG")
+                            .help("signing algorithm")
+                            .takes_value(true)
+                            .possible_values(&[
+                                "HS256", "HS384", "HS512", "RS256", "RS384", "RS512", "PS256",
+                                "PS384", "PS512", "ES256", "ES384", "ES256K", "EdDSA",
+                            ])
+                            .default_value("HS256"),
+                    )
+                    .arg(
+                        Arg::with_name("secret")
+                            .long("secret")
+                            .value_name("SECRET")
+                            .help("shared secret for HS256/HS384/HS512")
+                            .takes_value(true)
+                            .conflicts_with_all(&["secret-file", "key"]),
+                    )
+                    .arg(
+                        Arg::with_name("secret-file")
+                            .long("secret-file")
+                            .value_name("FILE")
+                            .help("read the shared secret from a file (one trailing newline is ignored)")
+                            .takes_value(true)
+                            .conflicts_with("key"),
+                    )
+                    .arg(
+                        Arg::with_name("key")
+                            .long("key")
+                            .value_name("PEM")
+                            .help("PEM private key for RS*, PS*, ES256, ES384, ES256K and EdDSA, as for encode")
+                            .takes_value(true),
+                    )
+                    .arg(
+                        Arg::with_name("passphrase-env")
+                            .long("passphrase-env")
+                            .value_name("VAR")
+                            .help("the environment variable holding an encrypted --key's passphrase, as for encode")
+                            .takes_value(true)
+                            .requires("key"),
+                    )
+                    .arg(
+                        Arg::with_name("age-identity")
+                            .long("age-identity")
+                            .value_name("FILE")
+                            .help("the identity file for a --key or --secret-file encrypted with age, as for encode")
+                            .takes_value(true)
+                            .conflicts_with_all(&["secret", "ssh-agent"]),
+                    )
+                    .arg(
+                        Arg::with_name("ssh-agent")
+                            .long("ssh-agent")
+                            .value_name("PUB")
+                            .help("sign with the ssh-agent key whose OpenSSH public key this is, as for encode")
+                            .takes_value(true)
+                            .conflicts_with_all(&["secret", "secret-file", "key"]),
+                    )
+                    .arg(
+                        Arg::with_name("kid")
+                            .long("kid")
+                            .value_name("KID")
+                            .help("key ID to put in the header")
+                            .takes_value(true),
+                    )
+                    .arg(
+                        Arg::with_name("detached")
+                            .long("detached")
+                            .help("leave the payload out of the JWS (RFC 7515 Appendix F); the file is given again to verify it"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("verify")
+                    .about("Checks that SIG is a valid JWS over FILE's bytes")
+                    .arg(
+                        Arg::with_name("file")
+                            .value_name("FILE")
+                            .help("the signed file")
+                            .required(true),
+                    )
+                    .arg(
+                        Arg::with_name("sig")
+                            .value_name("SIG")
+                            .help("the file holding the compact JWS, attached or detached")
+                            .required(true),
+                    )
+                    .arg(
+                        Arg::with_name("secret")
+                            .long("secret")
+                            .value_name("SECRET")
+                            .help("shared secret for HS256/HS384/HS512")
+                            .takes_value(true)
+                            .conflicts_with_all(&["secret-file", "key", "jwks-file"]),
+                    )
+                    .arg(
+                        Arg::with_name("secret-file")
+                            .long("secret-file")
+                            .value_name("FILE")
+                            .help("read the shared secret from a file (one trailing newline is ignored)")
+                            .takes_value(true)
+                            .conflicts_with_all(&["key", "jwks-file"]),
+                    )
+                    .arg(
+                        Arg::with_name("key")
+                            .long("key")
+                            .value_name("PEM")
+                            .help("public key, as for --verify --key")
+                            .takes_value(true)
+                            .conflicts_with("jwks-file"),
+                    )
+                    .arg(
+                        Arg::with_name("jwks-file")
+                            .long("jwks-file")
+                            .value_name("FILE")
+                            .help("JSON Web Key Set; the key is chosen by the JWS header's kid and alg")
+                            .takes_value(true),
+                    ),
+            ),
+        SubCommand::with_name("man").about("Prints the man page in roff format"),
+        SubCommand::with_name("mock-idp")
+            .about("Runs a throwaway OIDC identity provider that mints tokens, for integration tests")
+            .arg(
+                Arg::with_name("port")
+                    .long("port")
+                    .value_name("PORT")
+                    .help("port to listen on, on 127.0.0.1")
+                    .takes_value(true)
+                    .default_value("9000"),
+            )
+            .arg(
+                Arg::with_name("issuer")
+                    .long("issuer")
+                    .value_name("URL")
+                    .help("the iss and base URL to advertise [default: http://127.0.0.1:PORT]")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("claim")
+                    .long("claim")
+                    .value_name("KEY=VALUE")
+                    .help("add a claim to every token; VALUE is read as JSON when it parses, else as a string (repeatable)")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("lifetime")
+                    .long("lifetime")
+                    .value_name("DURATION")
+                    .help("how long minted tokens are valid")
+                    .takes_value(true)
+                    .default_value("1h"),
+            ),
+        SubCommand::with_name("pipe")
+            .about("Decodes a token, edits its claims and re-signs it, to make a fixture from a captured token")
+            .arg(
+                Arg::with_name("token")
+                    .short("t")
+                    .long("token")
+                    .value_name("TOKEN")
+                    .help("the token to start from")
+                    .takes_value(true)
+                    .required_unless("file")
+                    .conflicts_with("file"),
+            )
+            .arg(
+                Arg::with_name("file")
+                    .long("file")
+                    .value_name("FILE")
+                    .help("read the token from a file, or `-` for stdin")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("drop-claim")
+                    .long("drop-claim")
+                    .value_name("NAME")
+                    .help("remove a claim (repeatable); edits apply in the order given")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("set")
+                    .long("set")
+                    .value_name("KEY=VALUE")
+                    .help("set a claim (repeatable): +DURATION or -DURATION is that long from now in epoch seconds, e.g. exp=+1h; otherwise VALUE is JSON when it parses, else a string")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .allow_hyphen_values(true),
+            )
+            .arg(
+                Arg::with_name("resign")
+                    .long("resign")
+                    .value_name("ALG:SOURCE")
+                    .help("the algorithm and key to re-sign with: ALG:env:VAR or ALG:file:PATH, the secret or PEM private key, e.g. hs256:env:SECRET")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("kid")
+                    .long("kid")
+                    .value_name("KID")
+                    .help("key ID for the new header; the captured kid is dropped otherwise")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("age-identity")
+                    .long("age-identity")
+                    .value_name("FILE")
+                    .help("the identity file for an ALG:file: key encrypted with age, as for encode")
+                    .takes_value(true),
+            ),
+        SubCommand::with_name("self-update")
+            .about("Replaces this binary with a newer release, verified against the release key")
+            .arg(
+                Arg::with_name("from")
+                    .long("from")
+                    .value_name("URL|DIR")
+                    .help("the release mirror or directory holding manifest.jws and the binaries")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("release-key")
+                    .long("release-key")
+                    .value_name("FILE")
+                    .help("PEM public key the manifest must be signed with [default: the key built in with JWT_CHECK_RELEASE_KEY]")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("check")
+                    .long("check")
+                    .help("only report whether a newer release is available"),
+            ),
+        SubCommand::with_name("history")
+            .about("Lists, shows, searches and summarizes the tokens recorded with --save-history")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("list")
+                    .about("Prints one line per recorded token: number, time, alg, iss, sub and fingerprint")
+                    .arg(
+                        Arg::with_name("iss")
+                            .long("iss")
+                            .value_name("ISSUER")
+                            .help("only tokens from this issuer")
+                            .takes_value(true),
+                    )
+                    .arg(history_file_arg()),
+            )
+            .subcommand(
+                SubCommand::with_name("show")
+                    .about("Prints one recorded token as JSON")
+                    .arg(
+                        Arg::with_name("id")
+                            .value_name("ID")
+                            .help("its number in `history list`, or the start of its fingerprint")
+                            .required(true),
+                    )
+                    .arg(history_file_arg()),
+            )
+            .subcommand(
+                SubCommand::with_name("search")
+                    .about("Lists the recorded tokens whose header or claims contain TEXT, ignoring case")
+                    .arg(
+                        Arg::with_name("text")
+                            .value_name("TEXT")
+                            .help("the text to look for")
+                            .required(true),
+                    )
+                    .arg(history_file_arg()),
+            )
+            .subcommand(
+                SubCommand::with_name("stats")
+                    .about("Shows how an issuer's tokens changed over time: alg, kid and lifetime changes, and claims that came or went")
+                    .arg(
+                        Arg::with_name("iss")
+                            .long("iss")
+                            .value_name("ISSUER")
+                            .help("the issuer whose tokens to summarize")
+                            .takes_value(true)
+                            .required(true),
+                    )
+                    .arg(
+                        Arg::with_name("json")
+                            .long("json")
+                            .help("print the summary as JSON"),
+                    )
+                    .arg(history_file_arg()),
+            ),
+        SubCommand::with_name("provenance")
+            .about("Prints SLSA provenance for this binary as an in-toto statement"),
+        SubCommand::with_name("scan")
+            .about("Finds JWTs in the HTTP traffic of a packet capture")
+            .arg(
+                Arg::with_name("pcap")
+                    .long("pcap")
+                    .value_name("FILE")
+                    .help("pcap or pcapng capture to read")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("keylog")
+                    .long("keylog")
+                    .value_name("FILE")
+                    .help("TLS key log for decrypting HTTPS connections; defaults to $SSLKEYLOGFILE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("sarif")
+                    .long("sarif")
+                    .value_name("URL")
+                    .help("also audit every token found and deliver the findings as a SARIF 2.1.0 log to a path, file://, http[s]:// (POST) or s3://bucket/key")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("where")
+                    .long("where")
+                    .value_name("CONDITION")
+                    .help("only list the JWTs for which CONDITION holds, as for --batch --where (repeatable: all must hold)")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            ),
+        SubCommand::with_name("schema")
+            .about("Prints the JSON Schema of a JSON output, or checks a document against it")
+            .arg(
+                Arg::with_name("kind")
+                    .value_name("KIND")
+                    .help("decode (--format json), verification (serve's answers), audit (--audit --out) or batch (a --batch record)")
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("output-version")
+                    .long("output-version")
+                    .value_name("N")
+                    .help("the schema version; decode has 1 and 2, as --output-version does")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::with_name("check")
+                    .long("check")
+                    .value_name("FILE")
+                    .help("check the document in FILE, or `-` for stdin, instead of printing the schema; for batch, each line")
+                    .takes_value(true),
+            ),
+        SubCommand::with_name("self-test")
+            .about("Runs a corpus of tricky tokens through the parser, to check a build on an unusual platform")
+            .arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .help("print the results as JSON"),
+            ),
+        SubCommand::with_name("serve")
+            .about("Answers token verification requests over HTTP, with a policy per trust domain")
+            .arg(
+                Arg::with_name("config")
+                    .long("config")
+                    .value_name("FILE")
+                    .help("YAML file listing the policies and the path or header each is picked by")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("listen")
+                    .long("listen")
+                    .value_name("ADDR")
+                    .help("address and port to listen on")
+                    .takes_value(true)
+                    .default_value("127.0.0.1:8080"),
+            )
+            .arg(
+                Arg::with_name("drain-timeout")
+                    .long("drain-timeout")
+                    .value_name("DURATION")
+                    .help("on SIGTERM, how long requests in flight get to finish")
+                    .takes_value(true)
+                    .default_value("10s"),
+            )
+            .arg(
+                Arg::with_name("max-request-size")
+                    .long("max-request-size")
+                    .value_name("SIZE")
+                    .help("refuse larger requests with 413, headers and body together")
+                    .takes_value(true)
+                    .default_value("1M"),
+            )
+            .arg(
+                Arg::with_name("memory-limit")
+                    .long("memory-limit")
+                    .value_name("SIZE")
+                    .help("request memory held at once before new requests get 503")
+                    .takes_value(true)
+                    .default_value("64M"),
+            )
+            .arg(
+                Arg::with_name("max-connections")
+                    .long("max-connections")
+                    .value_name("COUNT")
+                    .help("connections served at once before new ones get 503")
+                    .takes_value(true)
+                    .default_value("256"),
+            )
+            .arg(
+                Arg::with_name("request-timeout")
+                    .long("request-timeout")
+                    .value_name("DURATION")
+                    .help("answer 408 to a client that has not sent its whole request within DURATION of connecting")
+                    .takes_value(true)
+                    .default_value("10s"),
+            )
+            .arg(
+                Arg::with_name("timeout")
+                    .long("timeout")
+                    .value_name("DURATION")
+                    .help("give up on each JWKS, OCSP or report fetch after DURATION (e.g. 2s), name lookups included, instead of waiting up to ten seconds for each step")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("doh-url")
+                    .long("doh-url")
+                    .value_name("URL")
+                    .help("resolve the hosts of JWKS, discovery and other fetches through the RFC 8484 DNS-over-HTTPS resolver at URL, over https:// (or http:// on loopback), instead of the system's")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("resolve")
+                    .long("resolve")
+                    .value_name("HOST:PORT:ADDRESS")
+                    .help("connect to HOST at ADDRESS instead of looking it up, for hosts only split-horizon DNS knows; as for curl, or HOST:ADDRESS for any port, with an IPv6 ADDRESS bracketed (repeatable)")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("otlp-endpoint")
+                    .long("otlp-endpoint")
+                    .value_name("URL")
+                    .help("export an OpenTelemetry trace of each request, with spans for decoding, claim validation, key resolution and the signature check, to the OTLP/HTTP collector at URL (http[s]://; /v1/traces is added) [env: OTEL_EXPORTER_OTLP_ENDPOINT]")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("otlp-service")
+                    .long("otlp-service")
+                    .value_name("NAME")
+                    .help("the service.name the traces carry [env: OTEL_SERVICE_NAME, default: jwt-check]")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("log-sink")
+                    .long("log-sink")
+                    .value_name("SINK")
+                    .help("write the access log, reloads and failures to stderr, syslog (/dev/log) or journald, with 4xx answers and script findings at warning priority and failures at err")
+                    .takes_value(true)
+                    .possible_values(&["stderr", "syslog", "journald"])
+                    .default_value("stderr"),
+            )
+            .arg(
+                Arg::with_name("user")
+                    .long("user")
+                    .value_name("NAME")
+                    .help("when started as root, switch to this user once the port is bound")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("sandbox")
+                    .long("sandbox")
+                    .help("once the port is bound, make the filesystem read-only (Linux Landlock); policy reloads still read"),
+            )
+            .arg(
+                Arg::with_name("offline")
+                    .long("offline")
+                    .help("once the port is bound, block new sockets (Linux seccomp); needs policies with a `jwks` file, not a `jwks_url`")
+                    .conflicts_with_all(&["doh-url", "otlp-endpoint"]),
+            )
+            .arg(
+                Arg::with_name("verbose")
+                    .short("v")
+                    .long("verbose")
+                    .help("log the policies as they are loaded"),
+            ),
+        SubCommand::with_name("stream")
+            .about("Audits the tokens in a Kafka topic as messages arrive")
+            .arg(
+                Arg::with_name("kafka-brokers")
+                    .long("kafka-brokers")
+                    .value_name("HOST:PORT")
+                    .help("brokers to bootstrap from, comma-separated")
+                    .takes_value(true)
+                    .use_delimiter(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("topic")
+                    .long("topic")
+                    .value_name("TOPIC")
+                    .help("the topic to read, every partition of it")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("field")
+                    .long("field")
+                    .value_name("PATH")
+                    .help("where the token is in a JSON message, as a dot path such as `token` or `auth.header` [default: the whole message]")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("from-beginning")
+                    .long("from-beginning")
+                    .help("read the messages already in the topic too, not only new ones"),
+            )
+            .arg(
+                Arg::with_name("all")
+                    .long("all")
+                    .help("print a record for every token, not only those with findings"),
+            )
+            .arg(
+                Arg::with_name("to-topic")
+                    .long("to-topic")
+                    .value_name("TOPIC")
+                    .help("write the records to partition 0 of TOPIC instead of stdout")
+                    .takes_value(true),
+            ),
+        SubCommand::with_name("ttl")
+            .about("Prints only the token's remaining lifetime, negative once expired, for shell prompts and status lines")
+            .arg(
+                Arg::with_name("token")
+                    .short("t")
+                    .long("token")
+                    .value_name("TOKEN")
+                    .help("the token to read")
+                    .takes_value(true)
+                    .required_unless("file")
+                    .conflicts_with("file"),
+            )
+            .arg(
+                Arg::with_name("file")
+                    .long("file")
+                    .value_name("FILE")
+                    .help("read the token from a file, or `-` for stdin")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .value_name("FORMAT")
+                    .help("`seconds` (the default) or `human`, as in 1h59m")
+                    .takes_value(true)
+                    .possible_values(&["seconds", "human"]),
+            ),
+        SubCommand::with_name("webhook")
+            .about("Checks sample webhook deliveries the way their senders sign them")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("verify")
+                    .about("Checks the signature on a webhook body, and fails unless it is valid")
+                    .arg(
+                        Arg::with_name("body")
+                            .value_name("BODY")
+                            .help("the raw request body, or `-` for stdin")
+                            .required(true),
+                    )
+                    .arg(
+                        Arg::with_name("preset")
+                            .long("preset")
+                            .value_name("PRESET")
+                            .help("how the sender signs: github (X-Hub-Signature-256), stripe (Stripe-Signature), slack (X-Slack-Signature) or jws (a detached JWS in X-JWS-Signature)")
+                            .takes_value(true)
+                            .possible_values(&jwt_check::webhook::PRESETS)
+                            .required(true),
+                    )
+                    .arg(
+                        Arg::with_name("signature")
+                            .long("signature")
+                            .value_name("VALUE")
+                            .help("the signature header's value")
+                            .takes_value(true)
+                            .required_unless("headers")
+                            .conflicts_with("headers"),
+                    )
+                    .arg(
+                        Arg::with_name("timestamp")
+                            .long("timestamp")
+                            .value_name("UNIX")
+                            .help("with --preset slack, the X-Slack-Request-Timestamp value")
+                            .takes_value(true)
+                            .conflicts_with("headers"),
+                    )
+                    .arg(
+                        Arg::with_name("headers")
+                            .long("headers")
+                            .value_name("FILE")
+                            .help("read the signature (and Slack's timestamp) from the delivery's HTTP headers, one `Name: value` per line, as `curl -D` saves them")
+                            .takes_value(true),
+                    )
+                    .arg(
+                        Arg::with_name("secret")
+                            .long("secret")
+                            .value_name("SECRET")
+                            .help("the webhook signing secret")
+                            .takes_value(true)
+                            .conflicts_with_all(&["secret-file", "key"]),
+                    )
+                    .arg(
+                        Arg::with_name("secret-file")
+                            .long("secret-file")
+                            .value_name("FILE")
+                            .help("read the signing secret from a file (one trailing newline is ignored)")
+                            .takes_value(true)
+                            .conflicts_with("key"),
+                    )
+                    .arg(
+                        Arg::with_name("key")
+                            .long("key")
+                            .value_name("PEM")
+                            .help("with --preset jws, the sender's public key, as for --verify --key")
+                            .takes_value(true),
+                    )
+                    .arg(
+                        Arg::with_name("tolerance")
+                            .long("tolerance")
+                            .value_name("DURATION")
+                            .help("how far a signed timestamp may be from now [default: 5m]")
+                            .takes_value(true),
+                    ),
+            ),
+        SubCommand::with_name("wizard")
+            .about("Asks a few questions, then prints and runs the equivalent command"),
+    ]
+}
+
+/// Examples for one subcommand (`Some("")` for the decoder), or all of them.
+pub fn examples_text(command: Option<&str>) -> String {
+    let mut out = String::new();
+    for (name, description, invocation) in EXAMPLES {
+        if command.is_none_or(|c| c == *name) {
+            out.push_str(&format!("  # {}\n  {}\n\n", description, invocation));
+        }
+    }
+    out
+}
+
+fn help_text(mut app: App<'static, 'static>) -> Result<String, JWTError> {
+    let mut buffer = Vec::new();
+    app.write_long_help(&mut buffer)
+        .map_err(|e| JWTError::InvalidArgumentError(e.to_string().into()))?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+/// Top-level help followed by every subcommand's help, each with its examples.
+pub fn long_help() -> Result<String, JWTError> {
+    let mut out = help_text(app().bin_name("jwt-check"))?;
+    out.push_str("\n\nEXAMPLES:\n");
+    out.push_str(&examples_text(Some("")));
+    for command in subcommands() {
+        let name = command.get_name().to_string();
+        out.push_str(&format!("\n{}\n", "-".repeat(60)));
+        out.push_str(&help_text(command.bin_name(format!("jwt-check {}", name)))?);
+        let examples = examples_text(Some(&name));
+        if !examples.is_empty() {
+            out.push_str("\n\nEXAMPLES:\n");
+            out.push_str(&examples);
+        }
+    }
+    Ok(out)
+}
+
+pub fn man_page() -> Result<String, JWTError> {
+    let mut out = String::from(
+        ".TH JWT-CHECK 1\n.SH NAME\njwt-check \\- decode and inspect JSON Web Tokens\n",
+    );
+    out.push_str(".SH DESCRIPTION\n.nf\n");
+    out.push_str(&roff_escape(&help_text(app().bin_name("jwt-check"))?));
+    out.push_str("\n.fi\n");
+    for command in subcommands() {
+        let name = command.get_name().to_string();
+        out.push_str(&format!(".SH COMMAND: {}\n.nf\n", name.to_uppercase()));
+        out.push_str(&roff_escape(&help_text(
+            command.bin_name(format!("jwt-check {}", name)),
+        )?));
+        out.push_str("\n.fi\n");
+    }
+    out.push_str(".SH EXAMPLES\n");
+    for (_, description, invocation) in EXAMPLES {
+        out.push_str(&format!(
+            ".TP\n{}\n.B {}\n",
+            roff_escape(description),
+            roff_escape(invocation)
+        ));
+    }
+    Ok(out)
+}
+
+fn roff_escape(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e");
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}", line)
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_example_names_a_known_command() {
+        let names: Vec<String> = subcommands()
+            .iter()
+            .map(|c| c.get_name().to_string())
+            .collect();
+        for (command, _, invocation) in EXAMPLES {
+            assert!(command.is_empty() || names.iter().any(|n| n == command));
+            assert!(invocation.starts_with("jwt-check"));
+        }
+        assert!(man_page().unwrap().contains(".SH COMMAND: BENCH"));
+    }
+
+    #[test]
+    fn version_test() {
+        // the same version provenance and self-update report
+        assert_eq!(
+            version_line(),
+            format!("jwt-check {}", env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(app().p.meta.version, Some(env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn offline_conflicts_test() {
+        let token = "eyJhbGciOiJIUzI1NiJ9.e30.c2ln";
+        for network in [
+            &["--jwks-url", "http://idp.example/jwks"][..],
+            &[
+                "--batch",
+                "tokens.txt",
+                "--out",
+                "http://sink.example/records",
+            ],
+            &["--post-to", "http://hooks.example/verdict"],
+            &["--doh-url", "https://dns.example/dns-query"],
+            &["--x5c-roots", "roots.pem", "--ocsp"],
+            &[
+                "--userinfo-url",
+                "http://idp.example/userinfo",
+                "--access-token",
+                "at",
+            ],
+        ] {
+            let mut args = vec!["jwt-check", "--offline"];
+            if network.first() != Some(&"--batch") {
+                args.extend(["-t", token]);
+            }
+            args.extend(network);
+            let error = app().get_matches_from_safe(&args).unwrap_err();
+            assert_eq!(
+                error.kind,
+                clap::ErrorKind::ArgumentConflict,
+                "{:?} was accepted",
+                args
+            );
+        }
+        assert!(app()
+            .get_matches_from_safe(["jwt-check", "--offline", "-t", token])
+            .is_ok());
+    }
+
+    #[test]
+    fn every_example_parses() {
+        for (_, _, invocation) in EXAMPLES {
+            let command = invocation.split(['|', '>', '&']).next().unwrap();
+            let args = command.split_whitespace();
+            assert!(
+                app().get_matches_from_safe(args).is_ok(),
+                "`{}` does not parse",
+                command
+            );
+        }
+    }
+}
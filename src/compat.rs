@@ -0,0 +1,150 @@
+//! `--compat`: the decoded token printed as another tool prints it, so
+//! scripts that parse that tool's output keep working after switching, and
+//! gain this one's checks.
+//!
+//! - `jwt-cli` is `jwt decode` from jwt-cli: a "Token header" and a "Token
+//!   claims" section, each a dashed heading over indented JSON. With
+//!   `--format json` it is `jwt decode --json`, one `{header, payload}`
+//!   document.
+//! - `stepjwt` is `step crypto jwt inspect --insecure`: one `{header,
+//!   payload, signature}` document, with `<`, `>`, `&` and the line
+//!   separators escaped as Go's JSON encoder does.
+//!
+//! Only the decoded token goes to stdout; freshness, warnings and the rest
+//! go to stderr, as for `--format json`.
+
+use crate::output::Format;
+use crate::{JWTError, JWToken};
+use serde_json::json;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compat {
+    JwtCli,
+    StepJwt,
+}
+
+impl FromStr for Compat {
+    type Err = JWTError;
+
+    fn from_str(name: &str) -> Result<Compat, JWTError> {
+        match name {
+            "jwt-cli" => Ok(Compat::JwtCli),
+            "stepjwt" => Ok(Compat::StepJwt),
+            _ => Err(JWTError::InvalidArgumentError(
+                format!("unknown --compat `{}`; expected jwt-cli or stepjwt", name).into(),
+            )),
+        }
+    }
+}
+
+/// `\u` escapes for what Go's `json.Marshal` escapes by default. They can
+/// only occur inside strings, so replacing them in the text is safe.
+fn go_escape(json: &str) -> String {
+    json.replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026")
+        .replace('\u{2028}', "\\u2028")
+        .replace('\u{2029}', "\\u2029")
+}
+
+/// `token` as `compat` prints it; `format` is `pretty` or `json`.
+pub fn render(token: &JWToken, compat: Compat, format: Format) -> Result<String, JWTError> {
+    match (compat, format) {
+        (Compat::JwtCli, Format::Pretty) => Ok(format!(
+            "\nToken header\n------------\n{}\n\nToken claims\n------------\n{}",
+            serde_json::to_string_pretty(&token.header)?,
+            serde_json::to_string_pretty(&token.payload)?
+        )),
+        (Compat::JwtCli, Format::Json) => Ok(serde_json::to_string_pretty(&json!({
+            "header": token.header,
+            "payload": token.payload,
+        }))?),
+        (Compat::StepJwt, Format::Pretty) | (Compat::StepJwt, Format::Json) => {
+            let document = json!({
+                "header": token.header,
+                "payload": token.payload,
+                "signature": base64::encode_config(&token.signature, base64::URL_SAFE_NO_PAD),
+            });
+            Ok(go_escape(&serde_json::to_string_pretty(&document)?))
+        }
+        _ => Err(JWTError::InvalidArgumentError(
+            "--compat only goes with --format pretty or json".into(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, SAMPLE_TOKEN};
+
+    #[test]
+    fn compat_formats_test() {
+        let token = parse(SAMPLE_TOKEN).unwrap();
+        assert_eq!(
+            render(&token, Compat::JwtCli, Format::Pretty).unwrap(),
+            "
+Token header
+------------
+{
+  \"alg\": \"HS256\",
+  \"typ\": \"JWT\"
+}
+
+Token claims
+------------
+{
+  \"iat\": 1516239022,
+  \"name\": \"John Doe\",
+  \"sub\": \"1234567890\"
+}"
+        );
+        assert_eq!(
+            render(&token, Compat::JwtCli, Format::Json).unwrap(),
+            "{
+  \"header\": {
+    \"alg\": \"HS256\",
+    \"typ\": \"JWT\"
+  },
+  \"payload\": {
+    \"iat\": 1516239022,
+    \"name\": \"John Doe\",
+    \"sub\": \"1234567890\"
+  }
+}"
+        );
+        let mut token = token;
+        token.payload["name"] = json!("<Tom & Jerry>");
+        assert_eq!(
+            render(&token, Compat::StepJwt, Format::Pretty).unwrap(),
+            "{
+  \"header\": {
+    \"alg\": \"HS256\",
+    \"typ\": \"JWT\"
+  },
+  \"payload\": {
+    \"iat\": 1516239022,
+    \"name\": \"\\u003cTom \\u0026 Jerry\\u003e\",
+    \"sub\": \"1234567890\"
+  },
+  \"signature\": \"SflKxwRJSMeKKF2QT4fwpMeJf36POk6yJV_adQssw5c\"
+}"
+        );
+        for (compat, format) in [
+            (Compat::StepJwt, Format::Table),
+            (Compat::JwtCli, Format::Raw),
+        ] {
+            assert!(matches!(
+                render(&token, compat, format),
+                Err(JWTError::InvalidArgumentError(e))
+                    if e == "--compat only goes with --format pretty or json"
+            ));
+        }
+        assert!(matches!(
+            "jwt.io".parse::<Compat>(),
+            Err(JWTError::InvalidArgumentError(e))
+                if e == "unknown --compat `jwt.io`; expected jwt-cli or stepjwt"
+        ));
+    }
+}
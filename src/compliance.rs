@@ -0,0 +1,495 @@
+//! `compliance`: checks sample tokens freshly minted by an issuer we run
+//! against a profile and an internal policy, and turns every failure into
+//! a remediation list for the issuer's owners. It is meant for a nightly CI
+//! job against staging, so the command fails when any sample does.
+//!
+//! The profiles are those of `--profile`. A policy is a YAML file:
+//!
+//! ```yaml
+//! name: acme-issuers        # shown in the report, default `policy`
+//! issuer: https://id.acme.example
+//! algorithms: [ES256, PS256]
+//! typ: at+jwt
+//! kid: true                 # every token names its key
+//! required: [iss, sub, aud, exp, iat, jti]
+//! forbidden: [email, phone_number]
+//! max_lifetime: 1h
+//! ```
+//!
+//! Every key is optional; each one present is a requirement of its own.
+
+use crate::profile::{requirement, Profile, Requirement};
+use crate::{
+    extract_token, humanize_seconds, normalize_timestamps, numeric_claim, parse, parse_duration,
+    JWTError, JWToken, TimeUnit,
+};
+use serde_json::{json, Value};
+
+/// How many distinct failure details a remediation lists.
+pub const MAX_DETAILS: usize = 3;
+
+/// An internal minting policy.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Policy {
+    pub name: String,
+    pub issuer: Option<String>,
+    pub algorithms: Vec<String>,
+    pub typ: Option<String>,
+    pub kid: bool,
+    pub required: Vec<String>,
+    pub forbidden: Vec<String>,
+    pub max_lifetime: Option<i64>,
+}
+
+impl Policy {
+    /// Reads a parsed policy file.
+    pub fn from_config(config: &Value) -> Result<Policy, JWTError> {
+        let invalid = |what: String| JWTError::ConfigError(format!("policy: {}", what).into());
+        if !config.is_object() {
+            return Err(invalid("must be a YAML mapping".to_string()));
+        }
+        let text = |key: &str| match config.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(invalid(format!("`{}` must be a string", key))),
+        };
+        let list = |key: &str| match config.get(key) {
+            None => Ok(Vec::new()),
+            Some(Value::Array(items)) => items
+                .iter()
+                .map(|item| {
+                    item.as_str()
+                        .map(str::to_string)
+                        .ok_or_else(|| invalid(format!("`{}` must be a list of strings", key)))
+                })
+                .collect(),
+            Some(_) => Err(invalid(format!("`{}` must be a list", key))),
+        };
+        let max_lifetime = match config.get("max_lifetime") {
+            None => None,
+            Some(Value::String(value)) => Some(parse_duration(value)?),
+            Some(value) => Some(value.as_i64().ok_or_else(|| {
+                invalid("`max_lifetime` must be a duration such as 1h".to_string())
+            })?),
+        };
+        Ok(Policy {
+            name: text("name")?.unwrap_or_else(|| "policy".to_string()),
+            issuer: text("issuer")?,
+            algorithms: list("algorithms")?,
+            typ: text("typ")?,
+            kid: config.get("kid").and_then(Value::as_bool).unwrap_or(false),
+            required: list("required")?,
+            forbidden: list("forbidden")?,
+            max_lifetime,
+        })
+    }
+
+    /// Checks `token`; `payload` has its timestamps normalized to seconds.
+    pub fn check(&self, token: &JWToken, payload: &Value) -> Vec<Requirement> {
+        let source = format!("policy {}", self.name);
+        let mut requirements = Vec::new();
+        let mut push = |key: &str, description: &str, result: Result<String, String>| {
+            requirements.push(requirement(&source, key, description, result));
+        };
+        let header = |name: &str| token.header.get(name).and_then(Value::as_str);
+        if let Some(issuer) = &self.issuer {
+            push(
+                "issuer",
+                &format!("iss is {}", issuer),
+                match payload.get("iss").and_then(Value::as_str) {
+                    Some(iss) if iss == issuer => Ok(format!("iss is {}", iss)),
+                    Some(iss) => Err(format!("iss is {}", iss)),
+                    None => Err("no iss".to_string()),
+                },
+            );
+        }
+        if !self.algorithms.is_empty() {
+            push(
+                "algorithms",
+                &format!("signed with {}", self.algorithms.join(", ")),
+                match header("alg") {
+                    Some(alg) if self.algorithms.iter().any(|a| a == alg) => {
+                        Ok(format!("alg is {}", alg))
+                    }
+                    Some(alg) => Err(format!("alg is {}", alg)),
+                    None => Err("no alg".to_string()),
+                },
+            );
+        }
+        if let Some(typ) = &self.typ {
+            push(
+                "typ",
+                &format!("typ is {}", typ),
+                match header("typ") {
+                    Some(found) if found.eq_ignore_ascii_case(typ) => {
+                        Ok(format!("typ is {}", found))
+                    }
+                    Some(found) => Err(format!("typ is {}", found)),
+                    None => Err("no typ".to_string()),
+                },
+            );
+        }
+        if self.kid {
+            push(
+                "kid",
+                "names its key with kid",
+                match header("kid") {
+                    Some(kid) => Ok(format!("kid is {}", kid)),
+                    None => Err("no kid: verifiers have to try every key".to_string()),
+                },
+            );
+        }
+        if !self.required.is_empty() {
+            let missing: Vec<&str> = self
+                .required
+                .iter()
+                .map(String::as_str)
+                .filter(|claim| payload.get(claim).is_none())
+                .collect();
+            push(
+                "required",
+                "required claims",
+                if missing.is_empty() {
+                    Ok(format!("has {}", self.required.join(", ")))
+                } else {
+                    Err(format!("missing {}", missing.join(", ")))
+                },
+            );
+        }
+        if !self.forbidden.is_empty() {
+            let present: Vec<&str> = self
+                .forbidden
+                .iter()
+                .map(String::as_str)
+                .filter(|claim| payload.get(claim).is_some())
+                .collect();
+            push(
+                "forbidden",
+                "no forbidden claims",
+                if present.is_empty() {
+                    Ok("none present".to_string())
+                } else {
+                    Err(format!("has {}", present.join(", ")))
+                },
+            );
+        }
+        if let Some(max) = self.max_lifetime {
+            push(
+                "max_lifetime",
+                &format!("lifetime at most {}", humanize_seconds(max)),
+                match (numeric_claim(payload, "iat"), numeric_claim(payload, "exp")) {
+                    (Some(iat), Some(exp)) => match exp.checked_sub(iat) {
+                        Some(lifetime) if lifetime <= max => {
+                            Ok(format!("valid for {}", humanize_seconds(lifetime)))
+                        }
+                        Some(lifetime) => Err(format!("valid for {}", humanize_seconds(lifetime))),
+                        None => Err("exp - iat overflows 64 bits".to_string()),
+                    },
+                    (_, None) => Err("no exp: the token never expires".to_string()),
+                    (None, _) => Err("no iat to measure the lifetime from".to_string()),
+                },
+            );
+        }
+        requirements
+    }
+}
+
+/// What the samples are checked against.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Check {
+    Profile(Profile),
+    Policy(Policy),
+}
+
+/// One requirement some samples failed, and the lines they are on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Remediation {
+    pub source: String,
+    pub description: String,
+    pub lines: Vec<usize>,
+    /// Up to [`MAX_DETAILS`] distinct reasons, most common first.
+    pub details: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub samples: usize,
+    pub failing: usize,
+    pub remediations: Vec<Remediation>,
+}
+
+/// Checks each token in `input`, one per line, against `checks`.
+pub fn check_samples(input: &str, checks: &[Check]) -> Report {
+    let mut samples = 0;
+    let mut failing = Vec::new();
+    // keyed by source and description, in the order first seen
+    let mut failures: Vec<(Remediation, Vec<(String, usize)>)> = Vec::new();
+    let mut record = |line: usize, source: &str, description: &str, detail: &str| {
+        let at = failures
+            .iter()
+            .position(|(r, _)| r.source == source && r.description == description)
+            .unwrap_or_else(|| {
+                failures.push((
+                    Remediation {
+                        source: source.to_string(),
+                        description: description.to_string(),
+                        lines: Vec::new(),
+                        details: Vec::new(),
+                    },
+                    Vec::new(),
+                ));
+                failures.len() - 1
+            });
+        if let Some((remediation, details)) = failures.get_mut(at) {
+            remediation.lines.push(line);
+            match details.iter_mut().find(|(d, _)| d == detail) {
+                Some((_, count)) => *count += 1,
+                None => details.push((detail.to_string(), 1)),
+            }
+        }
+    };
+    for (index, text) in input.lines().enumerate() {
+        if text.trim().is_empty() || text.trim_start().starts_with('#') {
+            continue;
+        }
+        let line = index + 1;
+        samples += 1;
+        let token = match parse(extract_token(text)) {
+            Ok(token) => token,
+            Err(e) => {
+                record(line, "RFC 7519", "decodes as a JWT", &e.to_string());
+                failing.push(line);
+                continue;
+            }
+        };
+        let (payload, _) = normalize_timestamps(&token.payload, TimeUnit::Auto);
+        let mut failed = false;
+        for check in checks {
+            let requirements = match check {
+                Check::Profile(profile) => profile.check(&token, &payload, None).1,
+                Check::Policy(policy) => policy.check(&token, &payload),
+            };
+            for r in requirements.iter().filter(|r| !r.passed) {
+                record(line, &r.source, &r.description, &r.detail);
+                failed = true;
+            }
+        }
+        if failed {
+            failing.push(line);
+        }
+    }
+    let mut remediations: Vec<Remediation> = failures
+        .into_iter()
+        .map(|(mut remediation, mut details)| {
+            details.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+            remediation.details = details
+                .into_iter()
+                .take(MAX_DETAILS)
+                .map(|(detail, _)| detail)
+                .collect();
+            remediation
+        })
+        .collect();
+    remediations.sort_by_key(|r| std::cmp::Reverse(r.lines.len()));
+    Report {
+        samples,
+        failing: failing.len(),
+        remediations,
+    }
+}
+
+fn line_list(lines: &[usize]) -> String {
+    let shown: Vec<String> = lines.iter().take(10).map(usize::to_string).collect();
+    if lines.len() > shown.len() {
+        format!("{}, ...", shown.join(", "))
+    } else {
+        shown.join(", ")
+    }
+}
+
+pub fn render_text(report: &Report) -> String {
+    let mut out = String::new();
+    for (i, remediation) in report.remediations.iter().enumerate() {
+        out.push_str(&format!(
+            "{}. {}: {}\n   {} sample(s), line(s) {}\n",
+            i + 1,
+            remediation.source,
+            remediation.description,
+            remediation.lines.len(),
+            line_list(&remediation.lines)
+        ));
+        for detail in &remediation.details {
+            out.push_str(&format!("   - {}\n", detail));
+        }
+    }
+    out.push_str(&format!(
+        "compliance: {} of {} sample(s) compliant, {} requirement(s) to fix",
+        report.samples - report.failing,
+        report.samples,
+        report.remediations.len()
+    ));
+    out
+}
+
+pub fn to_json(report: &Report) -> Value {
+    json!({
+        "samples": report.samples,
+        "failing": report.failing,
+        "remediations": report.remediations.iter().map(|remediation| json!({
+            "source": remediation.source,
+            "description": remediation.description,
+            "lines": remediation.lines,
+            "details": remediation.details,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::SigningKey;
+    use crate::sign::sign;
+
+    #[test]
+    fn remediation_list_test() {
+        let policy = Policy::from_config(
+            &crate::yaml::parse(
+                "name: acme\nissuer: https://id.acme.example\nkid: true\nforbidden: [email]\nmax_lifetime: 1h\n",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(policy.max_lifetime, Some(3_600));
+        let mint = |header: Value, claims: Value| {
+            sign(&header, &claims, &SigningKey::Hmac(b"secret".to_vec())).unwrap()
+        };
+        let good = json!({"iss": "https://id.acme.example", "sub": "a", "aud": "api",
+                          "client_id": "c", "iat": 1_000, "exp": 1_300, "jti": "1"});
+        let mut leaky = good.clone();
+        leaky["email"] = json!("a@example.com");
+        leaky["exp"] = json!(90_000);
+        let samples = [
+            mint(
+                json!({"alg": "HS256", "typ": "at+jwt", "kid": "k1"}),
+                good.clone(),
+            ),
+            mint(json!({"alg": "HS256", "typ": "at+jwt"}), good),
+            "# not a sample".to_string(),
+            mint(json!({"alg": "HS256", "typ": "at+jwt", "kid": "k1"}), leaky),
+            "not-a-token".to_string(),
+        ]
+        .join("\n");
+        let report = check_samples(
+            &samples,
+            &[Check::Profile(Profile::Rfc9068), Check::Policy(policy)],
+        );
+        assert_eq!((report.samples, report.failing), (4, 3));
+        let summary: Vec<(&str, &[usize])> = report
+            .remediations
+            .iter()
+            .map(|r| (r.description.as_str(), r.lines.as_slice()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("names its key with kid", &[2][..]),
+                ("no forbidden claims", &[4][..]),
+                ("lifetime at most 1 hour", &[4][..]),
+                ("decodes as a JWT", &[5][..]),
+            ]
+        );
+        assert_eq!(report.remediations[1].details, ["has email"]);
+        assert_eq!(report.remediations[2].details, ["valid for 1 day"]);
+        assert!(render_text(&report)
+            .ends_with("compliance: 1 of 4 sample(s) compliant, 4 requirement(s) to fix"));
+
+        let policy =
+            Policy::from_config(&crate::yaml::parse("name: acme\nmax_lifetime: 1h\n").unwrap())
+                .unwrap();
+        let token = crate::parse(mint(json!({"alg": "HS256"}), json!({}))).unwrap();
+        let extreme = json!({"iat": -9_223_372_036_854_775_807_i64, "exp": i64::MAX});
+        let requirements = policy.check(&token, &extreme);
+        assert!(!requirements[0].passed);
+        assert_eq!(requirements[0].detail, "exp - iat overflows 64 bits");
+    }
+
+    #[test]
+    fn policy_errors_test() {
+        let config_error_of = |text: &str| {
+            error_message!(
+                ConfigError,
+                Policy::from_config(&crate::yaml::parse(text).unwrap())
+            )
+        };
+        assert_eq!(
+            config_error_of("- ES256\n"),
+            "policy: must be a YAML mapping"
+        );
+        assert_eq!(
+            config_error_of("typ: [at+jwt]\n"),
+            "policy: `typ` must be a string"
+        );
+        assert_eq!(
+            config_error_of("required: iss\n"),
+            "policy: `required` must be a list"
+        );
+        assert_eq!(
+            config_error_of("forbidden: [1]\n"),
+            "policy: `forbidden` must be a list of strings"
+        );
+        assert_eq!(
+            config_error_of("max_lifetime: [1h]\n"),
+            "policy: `max_lifetime` must be a duration such as 1h"
+        );
+        assert!(matches!(
+            Policy::from_config(&json!({"max_lifetime": "1 fortnight"})),
+            Err(JWTError::InvalidArgumentError(_))
+        ));
+
+        let policy = Policy::from_config(
+            &crate::yaml::parse(
+                "issuer: https://id.acme.example\nalgorithms: [ES256]\ntyp: at+jwt\nkid: true\nrequired: [sub, jti]\nmax_lifetime: 1h\n",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let details = |header: Value, payload: Value| {
+            let token = JWToken {
+                header,
+                payload,
+                signature: Vec::new(),
+            };
+            policy
+                .check(&token, &token.payload)
+                .into_iter()
+                .filter(|r| !r.passed)
+                .map(|r| r.detail)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            details(
+                json!({"alg": "RS256", "typ": "JWT"}),
+                json!({"iss": "https://evil.example", "sub": "a", "exp": 1_000})
+            ),
+            [
+                "iss is https://evil.example",
+                "alg is RS256",
+                "typ is JWT",
+                "no kid: verifiers have to try every key",
+                "missing jti",
+                "no iat to measure the lifetime from"
+            ]
+        );
+        assert_eq!(
+            details(json!({}), json!({"iat": 1_000})),
+            [
+                "no iss",
+                "no alg",
+                "no typ",
+                "no kid: verifiers have to try every key",
+                "missing sub, jti",
+                "no exp: the token never expires"
+            ]
+        );
+    }
+}
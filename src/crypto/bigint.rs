@@ -0,0 +1,408 @@
+//! Arbitrary-size unsigned integers and Montgomery modular arithmetic,
+//! just enough for RSA and the NIST prime curves.
+// Limb indices stay below the operands' lengths, which every loop is bounded by.
+#![allow(clippy::indexing_slicing)]
+
+use std::cmp::Ordering;
+
+/// Unsigned integer stored as little-endian 64-bit limbs without trailing
+/// zero limbs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigUint {
+    limbs: Vec<u64>,
+}
+
+impl BigUint {
+    pub fn zero() -> BigUint {
+        BigUint { limbs: Vec::new() }
+    }
+
+    pub fn from_u64(value: u64) -> BigUint {
+        BigUint::from_limbs(vec![value])
+    }
+
+    fn from_limbs(mut limbs: Vec<u64>) -> BigUint {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        BigUint { limbs }
+    }
+
+    pub fn from_be_bytes(bytes: &[u8]) -> BigUint {
+        let limbs = bytes
+            .rchunks(8)
+            .map(|chunk| chunk.iter().fold(0u64, |acc, b| (acc << 8) | u64::from(*b)))
+            .collect();
+        BigUint::from_limbs(limbs)
+    }
+
+    /// Parses a hex constant; non-hex characters are skipped so constants can
+    /// be grouped with spaces or underscores.
+    pub fn from_hex(hex: &str) -> BigUint {
+        let nibbles: Vec<u8> = hex
+            .chars()
+            .filter_map(|c| c.to_digit(16))
+            .map(|d| d as u8)
+            .collect();
+        let bytes: Vec<u8> = nibbles
+            .rchunks(2)
+            .rev()
+            .map(|pair| pair.iter().fold(0u8, |acc, n| (acc << 4) | n))
+            .collect();
+        BigUint::from_be_bytes(&bytes)
+    }
+
+    /// Big-endian bytes, left-padded with zeros to at least `len` bytes.
+    pub fn to_be_bytes(&self, len: usize) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self
+            .limbs
+            .iter()
+            .rev()
+            .flat_map(|limb| limb.to_be_bytes())
+            .skip_while(|b| *b == 0)
+            .collect();
+        if bytes.len() < len {
+            let mut padded = vec![0; len - bytes.len()];
+            padded.append(&mut bytes);
+            bytes = padded;
+        }
+        bytes
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn bits(&self) -> usize {
+        match self.limbs.last() {
+            Some(top) => self.limbs.len() * 64 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    pub fn bit(&self, index: usize) -> bool {
+        self.limbs
+            .get(index / 64)
+            .is_some_and(|limb| (limb >> (index % 64)) & 1 == 1)
+    }
+
+    pub fn add(&self, other: &BigUint) -> BigUint {
+        let len = self.limbs.len().max(other.limbs.len());
+        let mut out = Vec::with_capacity(len + 1);
+        let mut carry = 0u64;
+        for i in 0..len {
+            let a = self.limbs.get(i).copied().unwrap_or(0);
+            let b = other.limbs.get(i).copied().unwrap_or(0);
+            let (sum, c1) = a.overflowing_add(b);
+            let (sum, c2) = sum.overflowing_add(carry);
+            out.push(sum);
+            carry = u64::from(c1 || c2);
+        }
+        out.push(carry);
+        BigUint::from_limbs(out)
+    }
+
+    /// `self - other`; the caller guarantees `self >= other`.
+    pub fn sub(&self, other: &BigUint) -> BigUint {
+        let mut out = self.limbs.clone();
+        sub_in_place(&mut out, &other.limbs);
+        BigUint::from_limbs(out)
+    }
+
+    /// Schoolbook product, used to cross-check the Montgomery arithmetic.
+    #[cfg(test)]
+    pub fn mul(&self, other: &BigUint) -> BigUint {
+        let mut out = vec![0u64; self.limbs.len() + other.limbs.len()];
+        for (i, a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u128;
+            for (j, b) in other.limbs.iter().enumerate() {
+                let t = u128::from(*a) * u128::from(*b) + u128::from(out[i + j]) + carry;
+                out[i + j] = t as u64;
+                carry = t >> 64;
+            }
+            out[i + other.limbs.len()] = carry as u64;
+        }
+        BigUint::from_limbs(out)
+    }
+
+    /// `self mod modulus` by binary long division; the modulus must be non-zero.
+    pub fn rem(&self, modulus: &BigUint) -> BigUint {
+        if self < modulus {
+            return self.clone();
+        }
+        let mut remainder = BigUint::zero();
+        for i in (0..self.bits()).rev() {
+            remainder = remainder.shl1();
+            if self.bit(i) {
+                remainder = remainder.add(&BigUint::from_u64(1));
+            }
+            if &remainder >= modulus {
+                remainder = remainder.sub(modulus);
+            }
+        }
+        remainder
+    }
+
+    fn shl1(&self) -> BigUint {
+        let mut out = Vec::with_capacity(self.limbs.len() + 1);
+        let mut carry = 0;
+        for limb in &self.limbs {
+            out.push((limb << 1) | carry);
+            carry = limb >> 63;
+        }
+        out.push(carry);
+        BigUint::from_limbs(out)
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &BigUint) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &BigUint) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Subtracts `b` from `a` in place, returning the final borrow.
+fn sub_in_place(a: &mut [u64], b: &[u64]) -> bool {
+    let mut borrow = false;
+    for (i, limb) in a.iter_mut().enumerate() {
+        let rhs = b.get(i).copied().unwrap_or(0);
+        let (diff, b1) = limb.overflowing_sub(rhs);
+        let (diff, b2) = diff.overflowing_sub(u64::from(borrow));
+        *limb = diff;
+        borrow = b1 || b2;
+    }
+    borrow
+}
+
+fn add_in_place(a: &mut [u64], b: &[u64]) -> bool {
+    let mut carry = false;
+    for (i, limb) in a.iter_mut().enumerate() {
+        let rhs = b.get(i).copied().unwrap_or(0);
+        let (sum, c1) = limb.overflowing_add(rhs);
+        let (sum, c2) = sum.overflowing_add(u64::from(carry));
+        *limb = sum;
+        carry = c1 || c2;
+    }
+    carry
+}
+
+fn less_than(a: &[u64], b: &[u64]) -> bool {
+    a.iter().rev().cmp(b.iter().rev()) == Ordering::Less
+}
+
+/// A residue modulo a [`Modulus`], as a fixed number of limbs.
+pub type Residue = Vec<u64>;
+
+/// An odd modulus with precomputed Montgomery constants.
+///
+/// Residues passed to `mul`, `square`, `pow_mont` and `inv` are in
+/// Montgomery form (`to_mont`); `add`, `sub` and `neg` work in either form.
+#[derive(Clone, Debug)]
+pub struct Modulus {
+    value: BigUint,
+    m: Vec<u64>,
+    m0inv: u64,
+    r2: Residue,
+    one: Residue,
+}
+
+impl Modulus {
+    /// Returns `None` for even or zero moduli, which Montgomery form cannot handle.
+    pub fn new(value: &BigUint) -> Option<Modulus> {
+        let m0 = *value.limbs.first()?;
+        if m0 & 1 == 0 {
+            return None;
+        }
+        let n = value.limbs.len();
+        // Newton iteration for m0^-1 mod 2^64; each step doubles the correct bits
+        let mut inv = m0;
+        for _ in 0..5 {
+            inv = inv.wrapping_mul(2u64.wrapping_sub(m0.wrapping_mul(inv)));
+        }
+        let mut r = vec![0u64; n];
+        r.push(1);
+        let one = BigUint::from_limbs(r).rem(value);
+        let mut r2 = vec![0u64; 2 * n];
+        r2.push(1);
+        let r2 = BigUint::from_limbs(r2).rem(value);
+        let modulus = Modulus {
+            value: value.clone(),
+            m: value.limbs.clone(),
+            m0inv: inv.wrapping_neg(),
+            r2: Vec::new(),
+            one: Vec::new(),
+        };
+        Some(Modulus {
+            r2: modulus.fit(&r2),
+            one: modulus.fit(&one),
+            ..modulus
+        })
+    }
+
+    pub fn value(&self) -> &BigUint {
+        &self.value
+    }
+
+    pub fn byte_len(&self) -> usize {
+        self.value.bits().div_ceil(8)
+    }
+
+    fn fit(&self, x: &BigUint) -> Residue {
+        let mut limbs = x.limbs.clone();
+        limbs.resize(self.m.len(), 0);
+        limbs
+    }
+
+    /// Montgomery multiplication (CIOS): `a * b / R mod m`.
+    pub fn mul(&self, a: &[u64], b: &[u64]) -> Residue {
+        let n = self.m.len();
+        let mut t = vec![0u64; n + 2];
+        for &bi in b.iter().take(n) {
+            let mut carry = 0u128;
+            for j in 0..n {
+                let sum = u128::from(t[j]) + u128::from(a[j]) * u128::from(bi) + carry;
+                t[j] = sum as u64;
+                carry = sum >> 64;
+            }
+            let sum = u128::from(t[n]) + carry;
+            t[n] = sum as u64;
+            t[n + 1] = (sum >> 64) as u64;
+
+            let q = t[0].wrapping_mul(self.m0inv);
+            let mut carry = (u128::from(t[0]) + u128::from(q) * u128::from(self.m[0])) >> 64;
+            for j in 1..n {
+                let sum = u128::from(t[j]) + u128::from(q) * u128::from(self.m[j]) + carry;
+                t[j - 1] = sum as u64;
+                carry = sum >> 64;
+            }
+            let sum = u128::from(t[n]) + carry;
+            t[n - 1] = sum as u64;
+            t[n] = t[n + 1] + (sum >> 64) as u64;
+        }
+        let overflow = t[n] != 0;
+        t.truncate(n);
+        if overflow || !less_than(&t, &self.m) {
+            sub_in_place(&mut t, &self.m);
+        }
+        t
+    }
+
+    pub fn square(&self, a: &[u64]) -> Residue {
+        self.mul(a, a)
+    }
+
+    pub fn add(&self, a: &[u64], b: &[u64]) -> Residue {
+        let mut out = a.to_vec();
+        let carry = add_in_place(&mut out, b);
+        if carry || !less_than(&out, &self.m) {
+            sub_in_place(&mut out, &self.m);
+        }
+        out
+    }
+
+    pub fn sub(&self, a: &[u64], b: &[u64]) -> Residue {
+        let mut out = a.to_vec();
+        if sub_in_place(&mut out, b) {
+            add_in_place(&mut out, &self.m);
+        }
+        out
+    }
+
+    pub fn zero(&self) -> Residue {
+        vec![0; self.m.len()]
+    }
+
+    /// One in Montgomery form.
+    pub fn one(&self) -> Residue {
+        self.one.clone()
+    }
+
+    pub fn is_zero(&self, a: &[u64]) -> bool {
+        a.iter().all(|limb| *limb == 0)
+    }
+
+    pub fn to_mont(&self, x: &BigUint) -> Residue {
+        self.mul(&self.fit(&x.rem(&self.value)), &self.r2)
+    }
+
+    pub fn to_int(&self, a: &[u64]) -> BigUint {
+        let mut one = self.zero();
+        one[0] = 1;
+        BigUint::from_limbs(self.mul(a, &one))
+    }
+
+    pub fn pow_mont(&self, base: &[u64], exponent: &BigUint) -> Residue {
+        let mut result = self.one();
+        for i in (0..exponent.bits()).rev() {
+            result = self.square(&result);
+            if exponent.bit(i) {
+                result = self.mul(&result, base);
+            }
+        }
+        result
+    }
+
+    /// `base^exponent mod m` on plain integers.
+    pub fn pow(&self, base: &BigUint, exponent: &BigUint) -> BigUint {
+        self.to_int(&self.pow_mont(&self.to_mont(base), exponent))
+    }
+
+    /// Inverse by Fermat's little theorem; only meaningful for prime moduli.
+    pub fn inv(&self, a: &[u64]) -> Residue {
+        let exponent = self.value.sub(&BigUint::from_u64(2));
+        self.pow_mont(a, &exponent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic() {
+        let a = BigUint::from_hex("ffffffffffffffffffffffffffffffff");
+        let b = BigUint::from_u64(1);
+        assert_eq!(
+            a.add(&b),
+            BigUint::from_hex("1 00000000000000000000000000000000")
+        );
+        assert_eq!(a.add(&b).sub(&b), a);
+        assert_eq!(
+            a.mul(&a),
+            BigUint::from_hex("fffffffffffffffffffffffffffffffe00000000000000000000000000000001")
+        );
+        assert_eq!(
+            BigUint::from_u64(1000).rem(&BigUint::from_u64(7)),
+            BigUint::from_u64(6)
+        );
+        assert_eq!(a.to_be_bytes(18)[..3], [0, 0, 0xff]);
+        assert_eq!(BigUint::from_be_bytes(&a.to_be_bytes(0)), a);
+    }
+
+    #[test]
+    fn montgomery_pow_and_inverse() {
+        // the P-256 field prime
+        let p =
+            BigUint::from_hex("ffffffff00000001000000000000000000000000ffffffffffffffffffffffff");
+        let m = Modulus::new(&p).unwrap();
+        let x = BigUint::from_u64(123_456_789);
+        let inv = m.inv(&m.to_mont(&x));
+        assert_eq!(m.to_int(&m.mul(&inv, &m.to_mont(&x))), BigUint::from_u64(1));
+        assert_eq!(
+            Modulus::new(&BigUint::from_u64(1_000_000_007))
+                .unwrap()
+                .pow(&BigUint::from_u64(2), &BigUint::from_u64(1_000_000_006)),
+            BigUint::from_u64(1)
+        );
+        assert!(Modulus::new(&BigUint::from_u64(10)).is_none());
+    }
+}
@@ -0,0 +1,519 @@
+//! ECDSA over the NIST P-256 and P-384 curves (FIPS 186-4) and secp256k1
+//! (SEC 2), with deterministic nonces for signing (RFC 6979).
+//!
+//! The point arithmetic below verifies signatures and checks that public
+//! points lie on their curve; none of it sees a private scalar. Signing goes through the `p256`, `p384` and `k256` crates, whose scalar
+//! arithmetic is constant time.
+
+use super::bigint::{BigUint, Modulus, Residue};
+use super::Hash;
+use k256::ecdsa::signature::hazmat::PrehashSigner;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    P256,
+    P384,
+    Secp256k1,
+}
+
+impl Curve {
+    /// Field element and scalar size in bytes.
+    pub fn byte_len(self) -> usize {
+        match self {
+            Curve::P256 | Curve::Secp256k1 => 32,
+            Curve::P384 => 48,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Curve::P256 => "P-256",
+            Curve::P384 => "P-384",
+            Curve::Secp256k1 => "secp256k1",
+        }
+    }
+
+    /// Whether the curve's `a` coefficient is zero rather than -3.
+    fn a_is_zero(self) -> bool {
+        self == Curve::Secp256k1
+    }
+}
+
+/// Curve constants with the field modulus `p` and group order `n`.
+pub struct Params {
+    pub curve: Curve,
+    pub p: Modulus,
+    pub n: Modulus,
+    b: Residue,
+    g: Point,
+}
+
+impl Params {
+    // both moduli are odd primes, so `Modulus::new` cannot fail here
+    #[allow(clippy::unwrap_used, clippy::expect_used)]
+    pub fn new(curve: Curve) -> Params {
+        let (p, n, b, gx, gy) = match curve {
+            Curve::P256 => (
+                "ffffffff00000001000000000000000000000000ffffffffffffffffffffffff",
+                "ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551",
+                "5ac635d8aa3a93e7b3ebbd55769886bc651d06b0cc53b0f63bce3c3e27d2604b",
+                "6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296",
+                "4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5",
+            ),
+            Curve::P384 => (
+                "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffeffffffff0000000000000000ffffffff",
+                "ffffffffffffffffffffffffffffffffffffffffffffffffc7634d81f4372ddf581a0db248b0a77aecec196accc52973",
+                "b3312fa7e23ee7e4988e056be3f82d19181d9c6efe8141120314088f5013875ac656398d8a2ed19d2a85c8edd3ec2aef",
+                "aa87ca22be8b05378eb1c71ef320ad746e1d3b628ba79b9859f741e082542a385502f25dbf55296c3a545e3872760ab7",
+                "3617de4a96262c6f5d9e98bf9292dc29f8f41dbd289a147ce9da3113b5f0b8c00a60b1ce1d7e819d7a431d7c90ea0e5f",
+            ),
+            Curve::Secp256k1 => (
+                "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f",
+                "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141",
+                "07",
+                "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+                "483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8",
+            ),
+        };
+        let p = Modulus::new(&BigUint::from_hex(p)).expect("curve prime is odd");
+        let n = Modulus::new(&BigUint::from_hex(n)).expect("group order is odd");
+        let g = Point {
+            x: p.to_mont(&BigUint::from_hex(gx)),
+            y: p.to_mont(&BigUint::from_hex(gy)),
+            z: p.one(),
+        };
+        Params {
+            curve,
+            b: p.to_mont(&BigUint::from_hex(b)),
+            p,
+            n,
+            g,
+        }
+    }
+
+    fn small(&self, a: &[u64], times: usize) -> Residue {
+        let mut out = a.to_vec();
+        for _ in 1..times {
+            out = self.p.add(&out, a);
+        }
+        out
+    }
+
+    /// x^3 + ax + b, in Montgomery form.
+    fn rhs(&self, x: &[u64]) -> Residue {
+        let f = &self.p;
+        let cube = f.mul(&f.square(x), x);
+        if self.curve.a_is_zero() {
+            f.add(&cube, &self.b)
+        } else {
+            f.add(&f.sub(&cube, &self.small(x, 3)), &self.b)
+        }
+    }
+
+    /// Whether affine (x, y), as plain integers, satisfies y^2 = x^3 + ax + b.
+    pub fn on_curve(&self, x: &BigUint, y: &BigUint) -> bool {
+        if x >= self.p.value() || y >= self.p.value() {
+            return false;
+        }
+        let f = &self.p;
+        let (x, y) = (f.to_mont(x), f.to_mont(y));
+        f.square(&y) == self.rhs(&x)
+    }
+
+    /// The y with parity `odd` for a compressed point's x (SEC 1 section
+    /// 2.3.4). Every supported prime is 3 mod 4, so the square root is
+    /// `rhs^((p+1)/4)`.
+    pub fn decompress(&self, x: &BigUint, odd: bool) -> Option<BigUint> {
+        if x >= self.p.value() {
+            return None;
+        }
+        let f = &self.p;
+        let rhs = f.to_int(&self.rhs(&f.to_mont(x)));
+        let mut exponent = f.value().add(&BigUint::from_u64(1));
+        for _ in 0..2 {
+            exponent = halve(&exponent);
+        }
+        let y = f.pow(&rhs, &exponent);
+        if !self.on_curve(x, &y) {
+            return None;
+        }
+        if y.bit(0) == odd || y.is_zero() {
+            Some(y)
+        } else {
+            Some(f.value().sub(&y))
+        }
+    }
+
+    pub fn point(&self, x: &BigUint, y: &BigUint) -> Point {
+        Point {
+            x: self.p.to_mont(x),
+            y: self.p.to_mont(y),
+            z: self.p.one(),
+        }
+    }
+
+    pub fn generator(&self) -> Point {
+        self.g.clone()
+    }
+
+    fn double(&self, q: &Point) -> Point {
+        let f = &self.p;
+        if f.is_zero(&q.z) || f.is_zero(&q.y) {
+            return self.infinity();
+        }
+        if self.curve.a_is_zero() {
+            // dbl-2009-l
+            let a = f.square(&q.x);
+            let b = f.square(&q.y);
+            let c = f.square(&b);
+            let d = self.small(&f.sub(&f.sub(&f.square(&f.add(&q.x, &b)), &a), &c), 2);
+            let e = self.small(&a, 3);
+            let x = f.sub(&f.square(&e), &self.small(&d, 2));
+            let y = f.sub(&f.mul(&e, &f.sub(&d, &x)), &self.small(&c, 8));
+            let z = self.small(&f.mul(&q.y, &q.z), 2);
+            return Point { x, y, z };
+        }
+        let delta = f.square(&q.z);
+        let gamma = f.square(&q.y);
+        let beta = f.mul(&q.x, &gamma);
+        let alpha = self.small(&f.mul(&f.sub(&q.x, &delta), &f.add(&q.x, &delta)), 3);
+        let x = f.sub(&f.square(&alpha), &self.small(&beta, 8));
+        let z = f.sub(&f.sub(&f.square(&f.add(&q.y, &q.z)), &gamma), &delta);
+        let y = f.sub(
+            &f.mul(&alpha, &f.sub(&self.small(&beta, 4), &x)),
+            &self.small(&f.square(&gamma), 8),
+        );
+        Point { x, y, z }
+    }
+
+    pub fn add(&self, a: &Point, b: &Point) -> Point {
+        let f = &self.p;
+        if f.is_zero(&a.z) {
+            return b.clone();
+        }
+        if f.is_zero(&b.z) {
+            return a.clone();
+        }
+        let z1z1 = f.square(&a.z);
+        let z2z2 = f.square(&b.z);
+        let u1 = f.mul(&a.x, &z2z2);
+        let u2 = f.mul(&b.x, &z1z1);
+        let s1 = f.mul(&f.mul(&a.y, &b.z), &z2z2);
+        let s2 = f.mul(&f.mul(&b.y, &a.z), &z1z1);
+        let h = f.sub(&u2, &u1);
+        let r = self.small(&f.sub(&s2, &s1), 2);
+        if f.is_zero(&h) {
+            return if f.is_zero(&r) {
+                self.double(a)
+            } else {
+                self.infinity()
+            };
+        }
+        let i = f.square(&self.small(&h, 2));
+        let j = f.mul(&h, &i);
+        let v = f.mul(&u1, &i);
+        let x = f.sub(&f.sub(&f.square(&r), &j), &self.small(&v, 2));
+        let y = f.sub(&f.mul(&r, &f.sub(&v, &x)), &self.small(&f.mul(&s1, &j), 2));
+        let z = f.mul(
+            &f.sub(&f.sub(&f.square(&f.add(&a.z, &b.z)), &z1z1), &z2z2),
+            &h,
+        );
+        Point { x, y, z }
+    }
+
+    /// Double-and-add; not constant time.
+    pub fn multiply(&self, point: &Point, scalar: &BigUint) -> Point {
+        let mut result = self.infinity();
+        for i in (0..scalar.bits()).rev() {
+            result = self.double(&result);
+            if scalar.bit(i) {
+                result = self.add(&result, point);
+            }
+        }
+        result
+    }
+
+    fn infinity(&self) -> Point {
+        Point {
+            x: self.p.one(),
+            y: self.p.one(),
+            z: self.p.zero(),
+        }
+    }
+
+    /// Affine coordinates as plain integers, or `None` for the point at infinity.
+    pub fn to_affine(&self, q: &Point) -> Option<(BigUint, BigUint)> {
+        let f = &self.p;
+        if f.is_zero(&q.z) {
+            return None;
+        }
+        let zinv = f.inv(&q.z);
+        let zinv2 = f.square(&zinv);
+        let x = f.mul(&q.x, &zinv2);
+        let y = f.mul(&f.mul(&q.y, &zinv2), &zinv);
+        Some((f.to_int(&x), f.to_int(&y)))
+    }
+
+    /// The message hash as an integer, truncated to the order's byte length.
+    pub fn hash_to_scalar(&self, digest: &[u8]) -> BigUint {
+        let len = self.curve.byte_len().min(digest.len());
+        BigUint::from_be_bytes(digest.get(..len).unwrap_or(digest))
+    }
+}
+
+/// `value / 2`, rounding down.
+fn halve(value: &BigUint) -> BigUint {
+    let bytes = value.to_be_bytes(0);
+    let mut carry = 0u8;
+    let halved: Vec<u8> = bytes
+        .iter()
+        .map(|byte| {
+            let out = (byte >> 1) | (carry << 7);
+            carry = byte & 1;
+            out
+        })
+        .collect();
+    BigUint::from_be_bytes(&halved)
+}
+
+/// A point in Jacobian coordinates, each in Montgomery form.
+#[derive(Clone, Debug)]
+pub struct Point {
+    x: Residue,
+    y: Residue,
+    z: Residue,
+}
+
+#[derive(Debug, Clone)]
+pub struct EcPublicKey {
+    pub curve: Curve,
+    x: BigUint,
+    y: BigUint,
+}
+
+impl EcPublicKey {
+    /// Returns `None` unless (x, y) is a point on the curve.
+    pub fn new(curve: Curve, x: BigUint, y: BigUint) -> Option<EcPublicKey> {
+        if !Params::new(curve).on_curve(&x, &y) {
+            return None;
+        }
+        Some(EcPublicKey { curve, x, y })
+    }
+
+    /// A point from its x coordinate and the parity of y.
+    pub fn from_compressed(curve: Curve, x: BigUint, odd: bool) -> Option<EcPublicKey> {
+        let y = Params::new(curve).decompress(&x, odd)?;
+        EcPublicKey::new(curve, x, y)
+    }
+
+    /// Fixed-length big-endian affine coordinates.
+    pub fn coordinates(&self) -> (Vec<u8>, Vec<u8>) {
+        let len = self.curve.byte_len();
+        (self.x.to_be_bytes(len), self.y.to_be_bytes(len))
+    }
+}
+
+/// The scalar of an EC private key, held by the curve's own crate so that
+/// signing runs in constant time.
+#[derive(Debug, Clone)]
+enum Secret {
+    P256(p256::ecdsa::SigningKey),
+    P384(p384::ecdsa::SigningKey),
+    Secp256k1(k256::ecdsa::SigningKey),
+}
+
+/// An EC private scalar.
+#[derive(Debug, Clone)]
+pub struct EcPrivateKey {
+    pub curve: Curve,
+    secret: Secret,
+}
+
+impl EcPrivateKey {
+    /// Returns `None` unless `0 < d < n`.
+    pub fn new(curve: Curve, d: BigUint) -> Option<EcPrivateKey> {
+        if d.bits() > 8 * curve.byte_len() {
+            return None;
+        }
+        let bytes = d.to_be_bytes(curve.byte_len());
+        let secret = match curve {
+            Curve::P256 => Secret::P256(p256::ecdsa::SigningKey::from_slice(&bytes).ok()?),
+            Curve::P384 => Secret::P384(p384::ecdsa::SigningKey::from_slice(&bytes).ok()?),
+            Curve::Secp256k1 => {
+                Secret::Secp256k1(k256::ecdsa::SigningKey::from_slice(&bytes).ok()?)
+            }
+        };
+        Some(EcPrivateKey { curve, secret })
+    }
+
+    /// `d·G`, the key that verifies its signatures.
+    pub fn public_key(&self) -> Option<EcPublicKey> {
+        // SEC 1 uncompressed: 0x04, then x and y
+        let point = match &self.secret {
+            Secret::P256(key) => key.verifying_key().to_encoded_point(false).to_bytes(),
+            Secret::P384(key) => key.verifying_key().to_encoded_point(false).to_bytes(),
+            Secret::Secp256k1(key) => key.verifying_key().to_encoded_point(false).to_bytes(),
+        };
+        let (x, y) = point.get(1..)?.split_at_checked(self.curve.byte_len())?;
+        EcPublicKey::new(
+            self.curve,
+            BigUint::from_be_bytes(x),
+            BigUint::from_be_bytes(y),
+        )
+    }
+}
+
+/// Signs `message` as a JWS ECDSA signature: `r || s`, each half the
+/// curve's byte length. The nonce is derived from the key and digest
+/// (RFC 6979), so signing needs no random source.
+pub fn sign(key: &EcPrivateKey, hash: Hash, message: &[u8]) -> Vec<u8> {
+    let digest = hash.digest(message);
+    let signature = match &key.secret {
+        Secret::P256(key) => PrehashSigner::<p256::ecdsa::Signature>::sign_prehash(key, &digest)
+            .map(|signature| signature.to_bytes().to_vec()),
+        Secret::P384(key) => PrehashSigner::<p384::ecdsa::Signature>::sign_prehash(key, &digest)
+            .map(|signature| signature.to_bytes().to_vec()),
+        Secret::Secp256k1(key) => {
+            PrehashSigner::<k256::ecdsa::Signature>::sign_prehash(key, &digest)
+                .map(|signature| signature.to_bytes().to_vec())
+        }
+    };
+    // only a digest shorter than half the scalar fails, and no JWA hash is
+    signature.unwrap_or_default()
+}
+
+/// Verifies a JWS ECDSA signature: the raw `r || s` concatenation, each
+/// half exactly the curve's byte length (RFC 7518 section 3.4).
+pub fn verify(key: &EcPublicKey, hash: Hash, message: &[u8], signature: &[u8]) -> bool {
+    let params = Params::new(key.curve);
+    let len = key.curve.byte_len();
+    let (r, s) = match signature.split_at_checked(len) {
+        Some((r, s)) if s.len() == len => (r, s),
+        _ => return false,
+    };
+    let (r, s) = (BigUint::from_be_bytes(r), BigUint::from_be_bytes(s));
+    let order = params.n.value();
+    if r.is_zero() || s.is_zero() || &r >= order || &s >= order {
+        return false;
+    }
+    let n = &params.n;
+    let e = params.hash_to_scalar(&hash.digest(message));
+    let w = n.inv(&n.to_mont(&s));
+    let u1 = n.to_int(&n.mul(&n.to_mont(&e), &w));
+    let u2 = n.to_int(&n.mul(&n.to_mont(&r), &w));
+    let q = params.point(&key.x, &key.y);
+    let sum = params.add(
+        &params.multiply(&params.generator(), &u1),
+        &params.multiply(&q, &u2),
+    );
+    match params.to_affine(&sum) {
+        Some((x, _)) => x.rem(order) == r,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generator_is_on_curve_and_has_order_n() {
+        for curve in [Curve::P256, Curve::P384, Curve::Secp256k1].iter() {
+            let params = Params::new(*curve);
+            let (gx, gy) = params.to_affine(&params.generator()).unwrap();
+            assert!(params.on_curve(&gx, &gy));
+            let n = params.n.value().clone();
+            assert!(params
+                .to_affine(&params.multiply(&params.generator(), &n))
+                .is_none());
+            let two_g = params.multiply(&params.generator(), &BigUint::from_u64(2));
+            let g_plus_g = params.add(&params.generator(), &params.generator());
+            assert_eq!(params.to_affine(&two_g), params.to_affine(&g_plus_g));
+        }
+    }
+
+    #[test]
+    fn rfc6979_p256_sha256_sample() {
+        let d =
+            BigUint::from_hex("c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721");
+        let key = EcPrivateKey::new(Curve::P256, d.clone()).unwrap();
+        let signature = sign(&key, Hash::Sha256, b"sample");
+        let expected = BigUint::from_hex(
+            "efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716\
+             f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8",
+        );
+        assert_eq!(signature, expected.to_be_bytes(64));
+        let params = Params::new(Curve::P256);
+        let (x, y) = params
+            .to_affine(&params.multiply(&params.generator(), &d))
+            .unwrap();
+        let public = EcPublicKey::new(Curve::P256, x, y).unwrap();
+        assert_eq!(
+            key.public_key().unwrap().coordinates(),
+            public.coordinates()
+        );
+        assert!(verify(&public, Hash::Sha256, b"sample", &signature));
+        assert!(!verify(&public, Hash::Sha256, b"test", &signature));
+    }
+
+    #[test]
+    fn p384_test() {
+        let key = EcPrivateKey::new(Curve::P384, BigUint::from_u64(7)).unwrap();
+        let public = key.public_key().unwrap();
+        let signature = sign(&key, Hash::Sha384, b"sample");
+        assert_eq!(signature.len(), 96);
+        assert!(verify(&public, Hash::Sha384, b"sample", &signature));
+        // d must be below the order
+        let n = Params::new(Curve::P384).n.value().clone();
+        assert!(EcPrivateKey::new(Curve::P384, n).is_none());
+        assert!(EcPrivateKey::new(Curve::P384, BigUint::zero()).is_none());
+    }
+
+    #[test]
+    fn secp256k1_test() {
+        // the private key 1 signs for the generator itself
+        let key = EcPrivateKey::new(Curve::Secp256k1, BigUint::from_u64(1)).unwrap();
+        let public = key.public_key().unwrap();
+        let (x, y) = public.coordinates();
+        assert_eq!(
+            BigUint::from_be_bytes(&x),
+            BigUint::from_hex("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+        );
+        // 3G, checked against the doubling formula for a = 0
+        let params = Params::new(Curve::Secp256k1);
+        let (x3, _) = params
+            .to_affine(&params.multiply(&params.generator(), &BigUint::from_u64(3)))
+            .unwrap();
+        assert_eq!(
+            x3,
+            BigUint::from_hex("f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9")
+        );
+        let signature = sign(&key, Hash::Sha256, b"sample");
+        assert!(verify(&public, Hash::Sha256, b"sample", &signature));
+        assert!(!verify(&public, Hash::Sha256, b"test", &signature));
+
+        // decompression recovers y, for either parity
+        let gy = BigUint::from_be_bytes(&y);
+        let odd = gy.bit(0);
+        let x = BigUint::from_be_bytes(&x);
+        assert_eq!(params.decompress(&x, odd), Some(gy.clone()));
+        assert_eq!(params.decompress(&x, !odd), Some(params.p.value().sub(&gy)));
+        assert!(EcPublicKey::from_compressed(Curve::Secp256k1, x, odd).is_some());
+        // x = 5 has no point: 5^3 + 7 = 132 is not a square mod p
+        assert!(params.decompress(&BigUint::from_u64(5), false).is_none());
+        // nor does an x past the field
+        assert!(params.decompress(params.p.value(), false).is_none());
+        // and a P-256 point is not on secp256k1
+        let p256 = EcPrivateKey::new(Curve::P256, BigUint::from_u64(1))
+            .unwrap()
+            .public_key()
+            .unwrap();
+        let (px, py) = p256.coordinates();
+        assert!(EcPublicKey::new(
+            Curve::Secp256k1,
+            BigUint::from_be_bytes(&px),
+            BigUint::from_be_bytes(&py)
+        )
+        .is_none());
+    }
+}
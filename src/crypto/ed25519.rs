@@ -0,0 +1,113 @@
+//! Ed25519 signatures (RFC 8032), the JOSE `EdDSA` algorithm (RFC 8037),
+//! through the `ed25519-dalek` crate. Verification is strict: signatures
+//! with a non-canonical `S` and keys of small order are refused.
+
+use std::convert::TryInto;
+
+/// An Ed25519 public key: the 32-byte encoding of a curve point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ed25519PublicKey(ed25519_dalek::VerifyingKey);
+
+impl Ed25519PublicKey {
+    /// Returns `None` unless `bytes` is 32 bytes that decode to a point,
+    /// and not one of small order.
+    pub fn new(bytes: &[u8]) -> Option<Ed25519PublicKey> {
+        let bytes: &[u8; 32] = bytes.try_into().ok()?;
+        ed25519_dalek::VerifyingKey::from_bytes(bytes)
+            .ok()
+            .filter(|key| !key.is_weak())
+            .map(Ed25519PublicKey)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+}
+
+/// An Ed25519 private key, held as its 32-byte seed.
+#[derive(Debug, Clone)]
+pub struct Ed25519PrivateKey(ed25519_dalek::SigningKey);
+
+impl Ed25519PrivateKey {
+    /// Returns `None` unless `seed` is 32 bytes.
+    pub fn new(seed: &[u8]) -> Option<Ed25519PrivateKey> {
+        let seed: &[u8; 32] = seed.try_into().ok()?;
+        Some(Ed25519PrivateKey(ed25519_dalek::SigningKey::from_bytes(
+            seed,
+        )))
+    }
+
+    /// The key that verifies its signatures.
+    pub fn public_key(&self) -> Ed25519PublicKey {
+        Ed25519PublicKey(self.0.verifying_key())
+    }
+}
+
+/// The 64-byte signature `R || S` over `message`. Ed25519 hashes the
+/// message itself, with SHA-512, and needs no random source.
+pub fn sign(key: &Ed25519PrivateKey, message: &[u8]) -> Vec<u8> {
+    use ed25519_dalek::Signer;
+    key.0.sign(message).to_bytes().to_vec()
+}
+
+pub fn verify(key: &Ed25519PublicKey, message: &[u8], signature: &[u8]) -> bool {
+    ed25519_dalek::Signature::from_slice(signature)
+        .is_ok_and(|signature| key.0.verify_strict(message, &signature).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// RFC 8032 section 7.1, test 2: a one-byte message.
+    #[test]
+    fn rfc8032_test_2() {
+        let key = Ed25519PrivateKey::new(&unhex(
+            "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+        ))
+        .unwrap();
+        let public = key.public_key();
+        assert_eq!(
+            public.to_bytes().to_vec(),
+            unhex("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c")
+        );
+        let signature = sign(&key, &[0x72]);
+        assert_eq!(
+            signature,
+            unhex(
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+                 085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"
+            )
+        );
+        assert!(verify(&public, &[0x72], &signature));
+        assert!(!verify(&public, &[0x73], &signature));
+        assert!(!verify(&public, &[0x72], &signature[..63]));
+        // S + L: the same signature with a non-canonical S
+        let mut malleated = signature.clone();
+        let order = unhex("edd3f55c1a631258d69cf7a2def9de1400000000000000000000000000000010");
+        let mut carry = 0u16;
+        for (s, l) in malleated[32..].iter_mut().zip(order) {
+            let sum = u16::from(*s) + u16::from(l) + carry;
+            *s = sum as u8;
+            carry = sum >> 8;
+        }
+        assert!(!verify(&public, &[0x72], &malleated));
+    }
+
+    #[test]
+    fn key_test() {
+        assert!(Ed25519PrivateKey::new(&[0; 31]).is_none());
+        assert!(Ed25519PublicKey::new(&[0; 33]).is_none());
+        // the identity point has order 1
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        assert!(Ed25519PublicKey::new(&identity).is_none());
+    }
+}
@@ -0,0 +1,347 @@
+//! Primitives for signature verification, JWE decryption, encrypted keys,
+//! the history store and `--claim-decrypt`.
+//!
+//! RSA and ECDSA verification, which only touch public values, are
+//! hand-rolled in the submodules. Everything that handles a secret goes
+//! through the RustCrypto and dalek crates, wrapped here in the byte layouts
+//! the callers read. The limb arithmetic in `bigint`, whose indices are
+//! bounded by fixed sizes, is the only code that allows
+//! `clippy::indexing_slicing`.
+
+pub mod bigint;
+pub mod ecdsa;
+pub mod ed25519;
+pub mod rsa;
+
+use sha2::Digest;
+
+/// The SHA-2 variant selected by a JOSE algorithm's digit suffix, or SHA-1
+/// for plain RSA-OAEP.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hash {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Hash {
+    pub fn name(self) -> &'static str {
+        match self {
+            Hash::Sha1 => "SHA-1",
+            Hash::Sha256 => "SHA-256",
+            Hash::Sha384 => "SHA-384",
+            Hash::Sha512 => "SHA-512",
+        }
+    }
+
+    /// `message` hashed by the `sha1` and `sha2` crates, which signing and
+    /// RSA decryption use too.
+    pub fn digest(self, message: &[u8]) -> Vec<u8> {
+        match self {
+            Hash::Sha1 => sha1::Sha1::digest(message).to_vec(),
+            Hash::Sha256 => sha2::Sha256::digest(message).to_vec(),
+            Hash::Sha384 => sha2::Sha384::digest(message).to_vec(),
+            Hash::Sha512 => sha2::Sha512::digest(message).to_vec(),
+        }
+    }
+
+    pub fn output_len(self) -> usize {
+        match self {
+            Hash::Sha1 => 20,
+            Hash::Sha256 => 32,
+            Hash::Sha384 => 48,
+            Hash::Sha512 => 64,
+        }
+    }
+
+    pub fn block_len(self) -> usize {
+        match self {
+            Hash::Sha1 | Hash::Sha256 => 64,
+            Hash::Sha384 | Hash::Sha512 => 128,
+        }
+    }
+}
+
+/// HMAC (RFC 2104), through the `hmac` crate.
+pub fn hmac(hash: Hash, key: &[u8], message: &[u8]) -> Vec<u8> {
+    use hmac::{Hmac, Mac};
+    fn mac<M: Mac + hmac::digest::KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+        // HMAC takes keys of any length, so this never fails
+        match <M as Mac>::new_from_slice(key) {
+            Ok(mut mac) => {
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Err(_) => Vec::new(),
+        }
+    }
+    match hash {
+        Hash::Sha1 => mac::<Hmac<sha1::Sha1>>(key, message),
+        Hash::Sha256 => mac::<Hmac<sha2::Sha256>>(key, message),
+        Hash::Sha384 => mac::<Hmac<sha2::Sha384>>(key, message),
+        Hash::Sha512 => mac::<Hmac<sha2::Sha512>>(key, message),
+    }
+}
+
+/// PBKDF2 (RFC 8018 section 5.2) with HMAC over `hash`, deriving `len`
+/// bytes.
+pub fn pbkdf2(hash: Hash, password: &[u8], salt: &[u8], iterations: u32, len: usize) -> Vec<u8> {
+    let mut derived = Vec::with_capacity(len);
+    let mut index: u32 = 1;
+    while derived.len() < len {
+        let mut message = salt.to_vec();
+        message.extend_from_slice(&index.to_be_bytes());
+        let mut u = hmac(hash, password, &message);
+        let mut block = u.clone();
+        for _ in 1..iterations {
+            u = hmac(hash, password, &u);
+            block.iter_mut().zip(&u).for_each(|(b, x)| *b ^= x);
+        }
+        derived.extend_from_slice(&block);
+        index += 1;
+    }
+    derived.truncate(len);
+    derived
+}
+
+/// HKDF (RFC 5869): extract with `salt`, then expand to `len` bytes of
+/// output keyed by `info`.
+pub fn hkdf(hash: Hash, ikm: &[u8], salt: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+    let prk = hmac(hash, salt, ikm);
+    let mut okm = Vec::with_capacity(len + hash.output_len());
+    let mut block = Vec::new();
+    let mut counter = 1u8;
+    while okm.len() < len {
+        let mut message = block;
+        message.extend_from_slice(info);
+        message.push(counter);
+        block = hmac(hash, &prk, &message);
+        okm.extend_from_slice(&block);
+        counter = counter.wrapping_add(1);
+    }
+    okm.truncate(len);
+    okm
+}
+
+/// AES-CBC with PKCS#7 padding, through the `aes` and `cbc` crates. Returns
+/// `None` if the key or IV has the wrong size or the padding is bad.
+pub fn cbc_decrypt(key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+    use cbc::cipher::block_padding::Pkcs7;
+    use cbc::cipher::{BlockDecryptMut, KeyIvInit};
+    fn decrypt<C: BlockDecryptMut + KeyIvInit>(
+        key: &[u8],
+        iv: &[u8],
+        ciphertext: &[u8],
+    ) -> Option<Vec<u8>> {
+        C::new_from_slices(key, iv)
+            .ok()?
+            .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+            .ok()
+    }
+    match key.len() {
+        16 => decrypt::<cbc::Decryptor<::aes::Aes128>>(key, iv, ciphertext),
+        24 => decrypt::<cbc::Decryptor<::aes::Aes192>>(key, iv, ciphertext),
+        32 => decrypt::<cbc::Decryptor<::aes::Aes256>>(key, iv, ciphertext),
+        _ => None,
+    }
+}
+
+/// AES-GCM decryption with a 96-bit IV and a 128-bit tag, through the
+/// `aes-gcm` crate. Returns `None` if the key, IV or tag has the wrong size
+/// or the tag does not match.
+pub fn gcm_decrypt(
+    key: &[u8],
+    iv: &[u8],
+    aad: &[u8],
+    ciphertext: &[u8],
+    tag: &[u8],
+) -> Option<Vec<u8>> {
+    use aes_gcm::aead::{Aead, KeyInit, Payload};
+    use aes_gcm::AesGcm;
+    fn decrypt<C: Aead + KeyInit>(key: &[u8], iv: &[u8], payload: Payload) -> Option<Vec<u8>> {
+        C::new_from_slice(key)
+            .ok()?
+            .decrypt(iv.into(), payload)
+            .ok()
+    }
+    if iv.len() != 12 || tag.len() != 16 {
+        return None;
+    }
+    let sealed = [ciphertext, tag].concat();
+    let payload = Payload { msg: &sealed, aad };
+    match key.len() {
+        16 => decrypt::<aes_gcm::Aes128Gcm>(key, iv, payload),
+        24 => decrypt::<AesGcm<::aes::Aes192, aes_gcm::aead::consts::U12>>(key, iv, payload),
+        32 => decrypt::<aes_gcm::Aes256Gcm>(key, iv, payload),
+        _ => None,
+    }
+}
+
+/// AES Key Wrap unwrapping (RFC 3394), through the `aes-kw` crate. Returns
+/// `None` if the key or the wrapped key has the wrong size or the integrity
+/// check fails.
+pub fn key_unwrap(kek: &[u8], wrapped: &[u8]) -> Option<Vec<u8>> {
+    use std::convert::TryFrom;
+    if wrapped.len() < 24 {
+        return None;
+    }
+    match kek.len() {
+        16 => aes_kw::KekAes128::try_from(kek)
+            .ok()?
+            .unwrap_vec(wrapped)
+            .ok(),
+        24 => aes_kw::KekAes192::try_from(kek)
+            .ok()?
+            .unwrap_vec(wrapped)
+            .ok(),
+        32 => aes_kw::KekAes256::try_from(kek)
+            .ok()?
+            .unwrap_vec(wrapped)
+            .ok(),
+        _ => None,
+    }
+}
+
+/// Opens ChaCha20-Poly1305 (RFC 8439) with no associated data, through the
+/// `chacha20poly1305` crate: the ciphertext followed by the 16-byte tag.
+/// Returns `None` if the input is too short or the tag does not match.
+pub fn chacha20poly1305_open(key: &[u8; 32], nonce: &[u8; 12], sealed: &[u8]) -> Option<Vec<u8>> {
+    use chacha20poly1305::aead::{Aead, KeyInit};
+    chacha20poly1305::ChaCha20Poly1305::new(key[..].into())
+        .decrypt(nonce[..].into(), sealed)
+        .ok()
+}
+
+/// Seals `plaintext` with XChaCha20-Poly1305 and no associated data, through
+/// the `chacha20poly1305` crate, in the layout [`xchacha20poly1305_open`]
+/// reads: nonce, ciphertext, tag. The 24-byte nonce is long enough to be
+/// chosen at random.
+pub fn xchacha20poly1305_seal(key: &[u8; 32], nonce: &[u8; 24], plaintext: &[u8]) -> Vec<u8> {
+    use chacha20poly1305::aead::{Aead, KeyInit};
+    let cipher = chacha20poly1305::XChaCha20Poly1305::new(key[..].into());
+    // encrypting into a Vec only fails past the AEAD's 256 GiB message limit
+    let sealed = cipher
+        .encrypt(nonce[..].into(), plaintext)
+        .unwrap_or_default();
+    [&nonce[..], &sealed].concat()
+}
+
+/// Opens XChaCha20-Poly1305 with no associated data, through the
+/// `chacha20poly1305` crate: a 24-byte nonce, the ciphertext, then the
+/// 16-byte tag. Returns `None` if the input is too short or the tag does not
+/// match.
+pub fn xchacha20poly1305_open(key: &[u8; 32], sealed: &[u8]) -> Option<Vec<u8>> {
+    use chacha20poly1305::aead::{Aead, KeyInit};
+    if sealed.len() < 40 {
+        return None;
+    }
+    let (nonce, rest) = sealed.split_at(24);
+    chacha20poly1305::XChaCha20Poly1305::new(key[..].into())
+        .decrypt(nonce.into(), rest)
+        .ok()
+}
+
+/// Opens a secretbox (XSalsa20-Poly1305), through the `crypto_secretbox`
+/// crate: a 24-byte nonce, then the 16-byte Poly1305 tag, then the
+/// ciphertext (libsodium's `crypto_secretbox_easy` output with the nonce
+/// prepended). Returns `None` if the box is too short or the tag does not
+/// match.
+pub fn secretbox_open(key: &[u8; 32], sealed: &[u8]) -> Option<Vec<u8>> {
+    use crypto_secretbox::aead::{Aead, KeyInit};
+    let (nonce, rest) = sealed.split_at_checked(24)?;
+    crypto_secretbox::XSalsa20Poly1305::new(key[..].into())
+        .decrypt(nonce.into(), rest)
+        .ok()
+}
+
+/// scrypt (RFC 7914) with cost N = 2^`log_n`, r = 8 and p = 1, deriving a
+/// 32-byte key through the `scrypt` crate. Uses 2^`log_n` KiB of memory.
+/// Returns `None` if `log_n` is out of the crate's range.
+pub fn scrypt(password: &[u8], salt: &[u8], log_n: u8) -> Option<[u8; 32]> {
+    let params = scrypt::Params::new(log_n, 8, 1, 32).ok()?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(password, salt, &params, &mut key).ok()?;
+    Some(key)
+}
+
+/// Compares two byte strings without an early exit on the first mismatch.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_rfc4231_case_2() {
+        let mac = hmac(Hash::Sha256, b"Jefe", b"what do ya want for nothing?");
+        let hex: String = mac.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(
+            hex,
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hmac(Hash::Sha512, b"Jefe", b"what do ya want for nothing?").len(),
+            64
+        );
+        assert!(constant_time_eq(&mac, &mac.clone()));
+        assert!(!constant_time_eq(&mac, &mac[1..]));
+    }
+
+    #[test]
+    fn pbkdf2_test() {
+        let hex =
+            |bytes: Vec<u8>| -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() };
+        // RFC 6070
+        assert_eq!(
+            hex(pbkdf2(Hash::Sha1, b"password", b"salt", 1, 20)),
+            "0c60c80f961f0e71f3a9b524af6012062fe037a6"
+        );
+        assert_eq!(
+            hex(pbkdf2(Hash::Sha1, b"password", b"salt", 4096, 20)),
+            "4b007901b765489abead49d926f721d065a429c1"
+        );
+        assert_eq!(
+            hex(pbkdf2(
+                Hash::Sha1,
+                b"passwordPASSWORDpassword",
+                b"saltSALTsaltSALTsaltSALTsaltSALTsalt",
+                4096,
+                25
+            )),
+            "3d2eec4fe41c849b80c8d83662c0e44a8b291a964cf2f07038"
+        );
+        // RFC 7914 section 11, two blocks of SHA-256
+        assert_eq!(
+            hex(pbkdf2(Hash::Sha256, b"passwd", b"salt", 1, 64)),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+             49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+        );
+    }
+
+    #[test]
+    fn hkdf_rfc5869_case_1() {
+        let unhex = |hex: &str| -> Vec<u8> {
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect()
+        };
+        assert_eq!(
+            hkdf(
+                Hash::Sha256,
+                &[0x0b; 22],
+                &unhex("000102030405060708090a0b0c"),
+                &unhex("f0f1f2f3f4f5f6f7f8f9"),
+                42
+            ),
+            unhex(
+                "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf\
+                 34007208d5b887185865"
+            )
+        );
+        assert_eq!(hkdf(Hash::Sha256, b"ikm", b"", b"", 0), Vec::<u8>::new());
+    }
+}
@@ -0,0 +1,425 @@
+//! RSASSA-PKCS1-v1_5 and RSASSA-PSS signatures, and RSAES-OAEP decryption
+//! (RFC 8017).
+//!
+//! Verifying is a single exponentiation by the public exponent, done on
+//! `bigint` here before the padding is checked. Signing and decryption use the private exponent, and go through the
+//! `rsa` crate, which blinds the exponentiation and decodes OAEP padding in
+//! constant time.
+
+use super::bigint::{BigUint, Modulus};
+use super::{constant_time_eq, Hash};
+use ::rsa::traits::PublicKeyParts;
+use ::rsa::{Oaep, Pkcs1v15Sign, Pss};
+use rand_core::OsRng;
+
+#[derive(Debug, Clone)]
+pub struct RsaPublicKey {
+    modulus: Modulus,
+    exponent: BigUint,
+}
+
+impl RsaPublicKey {
+    /// Returns `None` unless `n` is odd and `e` is an odd exponent above 1.
+    pub fn new(n: BigUint, e: BigUint) -> Option<RsaPublicKey> {
+        if e <= BigUint::from_u64(1) || !e.bit(0) {
+            return None;
+        }
+        Some(RsaPublicKey {
+            modulus: Modulus::new(&n)?,
+            exponent: e,
+        })
+    }
+
+    pub fn bits(&self) -> usize {
+        self.modulus.value().bits()
+    }
+
+    /// Minimal big-endian `(n, e)`, as a JWK carries them.
+    pub fn components(&self) -> (Vec<u8>, Vec<u8>) {
+        let minimal = |v: &BigUint| v.to_be_bytes(v.bits().div_ceil(8));
+        (minimal(self.modulus.value()), minimal(&self.exponent))
+    }
+
+    /// RSAVP1: the encoded message recovered from a signature, or `None` when
+    /// the signature is not an integer in range of the modulus length.
+    fn open(&self, signature: &[u8]) -> Option<Vec<u8>> {
+        let k = self.modulus.byte_len();
+        if signature.len() != k {
+            return None;
+        }
+        let s = BigUint::from_be_bytes(signature);
+        if &s >= self.modulus.value() {
+            return None;
+        }
+        Some(self.modulus.pow(&s, &self.exponent).to_be_bytes(k))
+    }
+}
+
+/// An RSA private key for decryption and signing.
+#[derive(Debug, Clone)]
+pub struct RsaPrivateKey {
+    // boxed, since the crate keeps its CRT values alongside
+    key: Box<::rsa::RsaPrivateKey>,
+}
+
+impl RsaPrivateKey {
+    /// A key from its modulus and exponents, and its primes when the key
+    /// format carries them; without them they are recovered from `d`.
+    /// Returns `None` unless the parts make a consistent key.
+    pub fn new(n: BigUint, e: BigUint, d: BigUint, primes: &[BigUint]) -> Option<RsaPrivateKey> {
+        if n.is_zero() || d.is_zero() {
+            return None;
+        }
+        let int = |v: &BigUint| ::rsa::BigUint::from_bytes_be(&v.to_be_bytes(v.bits().div_ceil(8)));
+        let primes = primes.iter().map(int).collect();
+        ::rsa::RsaPrivateKey::from_components(int(&n), int(&e), int(&d), primes)
+            .ok()
+            .map(|key| RsaPrivateKey { key: Box::new(key) })
+    }
+
+    pub fn bits(&self) -> usize {
+        self.key.n().bits()
+    }
+}
+
+/// RSAES-OAEP decryption with an empty label and MGF1 over the same hash.
+/// Returns `None` on any decoding failure, without saying which check failed,
+/// and takes as long whichever check it was.
+pub fn decrypt_oaep(key: &RsaPrivateKey, hash: Hash, ciphertext: &[u8]) -> Option<Vec<u8>> {
+    let padding = match hash {
+        Hash::Sha1 => Oaep::new::<sha1::Sha1>(),
+        Hash::Sha256 => Oaep::new::<sha2::Sha256>(),
+        Hash::Sha384 => Oaep::new::<sha2::Sha384>(),
+        Hash::Sha512 => Oaep::new::<sha2::Sha512>(),
+    };
+    key.key
+        .decrypt_blinded(&mut OsRng, padding, ciphertext)
+        .ok()
+}
+
+fn digest_info_prefix(hash: Hash) -> &'static [u8] {
+    match hash {
+        Hash::Sha1 => &[
+            0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04,
+            0x14,
+        ],
+        Hash::Sha256 => &[
+            0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+            0x01, 0x05, 0x00, 0x04, 0x20,
+        ],
+        Hash::Sha384 => &[
+            0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+            0x02, 0x05, 0x00, 0x04, 0x30,
+        ],
+        Hash::Sha512 => &[
+            0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+            0x03, 0x05, 0x00, 0x04, 0x40,
+        ],
+    }
+}
+
+/// EMSA-PKCS1-v1_5 encoding of `message` for a modulus of `k` bytes.
+pub fn pkcs1v15_encode(hash: Hash, message: &[u8], k: usize) -> Option<Vec<u8>> {
+    let prefix = digest_info_prefix(hash);
+    let t_len = prefix.len() + hash.output_len();
+    if k < t_len + 11 {
+        return None;
+    }
+    let mut em = vec![0x00, 0x01];
+    em.resize(k - t_len - 1, 0xff);
+    em.push(0x00);
+    em.extend_from_slice(prefix);
+    em.extend_from_slice(&hash.digest(message));
+    Some(em)
+}
+
+/// Verifies by re-encoding the expected message and comparing, rather than
+/// parsing the recovered padding.
+pub fn verify_pkcs1v15(key: &RsaPublicKey, hash: Hash, message: &[u8], signature: &[u8]) -> bool {
+    let em = match key.open(signature) {
+        Some(em) => em,
+        None => return false,
+    };
+    match pkcs1v15_encode(hash, message, em.len()) {
+        Some(expected) => constant_time_eq(&em, &expected),
+        None => false,
+    }
+}
+
+/// RSASSA-PKCS1-v1_5 signature, or `None` when the modulus is too short for
+/// the hash.
+pub fn sign_pkcs1v15(key: &RsaPrivateKey, hash: Hash, message: &[u8]) -> Option<Vec<u8>> {
+    let padding = Pkcs1v15Sign {
+        hash_len: Some(hash.output_len()),
+        prefix: digest_info_prefix(hash).into(),
+    };
+    key.key
+        .sign_with_rng(&mut OsRng, padding, &hash.digest(message))
+        .ok()
+}
+
+/// MGF1 mask generation with the given hash.
+pub fn mgf1(hash: Hash, seed: &[u8], len: usize) -> Vec<u8> {
+    let mut mask = Vec::with_capacity(len + hash.output_len());
+    let mut counter = 0u32;
+    while mask.len() < len {
+        let mut block = seed.to_vec();
+        block.extend_from_slice(&counter.to_be_bytes());
+        mask.extend_from_slice(&hash.digest(&block));
+        counter += 1;
+    }
+    mask.truncate(len);
+    mask
+}
+
+/// RSASSA-PSS signature with MGF1 over the same hash and a fresh random
+/// salt as long as the hash output, as JWA wants. `None` when the modulus
+/// is too short for the hash.
+pub fn sign_pss(key: &RsaPrivateKey, hash: Hash, message: &[u8]) -> Option<Vec<u8>> {
+    let salt_len = hash.output_len();
+    let padding = match hash {
+        Hash::Sha1 => Pss::new_with_salt::<sha1::Sha1>(salt_len),
+        Hash::Sha256 => Pss::new_with_salt::<sha2::Sha256>(salt_len),
+        Hash::Sha384 => Pss::new_with_salt::<sha2::Sha384>(salt_len),
+        Hash::Sha512 => Pss::new_with_salt::<sha2::Sha512>(salt_len),
+    };
+    key.key
+        .sign_with_rng(&mut OsRng, padding, &hash.digest(message))
+        .ok()
+}
+
+/// EMSA-PSS verification with MGF1 over the same hash and a salt as long as
+/// the hash output, as JWA (RFC 7518 section 3.5) requires.
+pub fn verify_pss(key: &RsaPublicKey, hash: Hash, message: &[u8], signature: &[u8]) -> bool {
+    pss_matches(key, hash, message, signature).unwrap_or(false)
+}
+
+/// The checks of `verify_pss`, with `None` for a recovered block too short
+/// for or inconsistent with its own layout.
+fn pss_matches(key: &RsaPublicKey, hash: Hash, message: &[u8], signature: &[u8]) -> Option<bool> {
+    let em_bits = key.bits().checked_sub(1)?;
+    let em_len = em_bits.div_ceil(8);
+    let em = key.open(signature)?;
+    // the recovered block has the modulus length; EM is its low em_len bytes
+    let (leading, em) = em.split_at_checked(em.len().checked_sub(em_len)?)?;
+    let h_len = hash.output_len();
+    let s_len = h_len;
+    let padding_len = em_len.checked_sub(h_len + s_len + 2)?;
+    if leading.iter().any(|b| *b != 0) || em.last() != Some(&0xbc) {
+        return Some(false);
+    }
+    let (masked_db, rest) = em.split_at_checked(em_len - h_len - 1)?;
+    let h = rest.get(..h_len)?;
+    let unused_bits = 8 * em_len - em_bits;
+    if unused_bits > 0 && *masked_db.first()? >> (8 - unused_bits) != 0 {
+        return Some(false);
+    }
+    let mut db: Vec<u8> = masked_db
+        .iter()
+        .zip(mgf1(hash, h, masked_db.len()))
+        .map(|(a, b)| a ^ b)
+        .collect();
+    *db.first_mut()? &= 0xff >> unused_bits;
+    let (padding, rest) = db.split_at_checked(padding_len)?;
+    let (separator, salt) = rest.split_first()?;
+    if padding.iter().any(|b| *b != 0) || *separator != 0x01 {
+        return Some(false);
+    }
+    let mut m_prime = vec![0u8; 8];
+    m_prime.extend_from_slice(&hash.digest(message));
+    m_prime.extend_from_slice(salt);
+    Some(constant_time_eq(&hash.digest(&m_prime), h))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 1024-bit key (e = 65537) and signatures and ciphertexts OpenSSL made
+    // with it over MESSAGE and PLAINTEXT: PSS with SHA-256 and salts of 32,
+    // 0, 20 and 94 (the most that fits) bytes, PKCS #1 v1.5 with SHA-256,
+    // and OAEP with SHA-1 and with SHA-256.
+    const N: &str = "b27509fd2b3cc16c9b7580db852d9916425d9f354c54c17e8d79f4c7a51434ee\
+                  db0e8c5694d0b936e1fdf356c3327d39d2b9bbc83007c0a3f0d406464995f1b4\
+                  af8f002d82188eb0700c1311cae67532c08d7aa08baa0d3765a4c1c281dfbffa\
+                  34a961775ecb217abd6245d10ef1af7332cba648c69657317ad1e0ceb3bfa1dd";
+    const D: &str = "449e9c68ace77fb8b34e4b6e1d979af5e42efbb912d0c45655303fdf44aab06f\
+                  e7eb802b0aadf78a12fc0345a969109ddb596fcb5a2ca0f668f41e3588b60f6a\
+                  3661df6f4b2e0612a6845a732c9f5ad76d8f5a98f4bf1f86f9d4f9d9b8aff90f\
+                  7b94719ac838a362dcb3487b6b7f35ea30a87fa07b90f0035b5917cb00be221";
+    const PSS32: &str = "86b3252fed88976e5f0b02bdb1255adc1277cbe5eebfe328a41f8f2dd0e86f0e\
+                  8452c765f56392efb690f754125cb1380633408a562c869bacef2159bc892e1e\
+                  1181626174037fd3870120d517e001a7d5e07cfa3ebf78ef42f2d28298c10853\
+                  97c035011854a814a210aa5d609e5137d9b082e056de268b21e0ebc8736e2400";
+    const PSS0: &str = "75077dfadf5c4e8775465dd91a11d4bbd2fea45e8e4961d1d37a86a4b89b8147\
+                  70ffb7fc713d6d0f62c051f69f52ed03b289c565bec30cd0bb08b83e7f6b6052\
+                  1eb7377c646c180c9fe39f1934be9732cdceaf101067f996933c3bfd64c0545c\
+                  c309308c0f0371e1ac02dfbcbc6240b2278ba5a83943a698aec1496ab3f2e922";
+    const PSS20: &str = "567b2b079952be3493d727b5ab35365bd46ca977c3fa99494555f72813ec9981\
+                  159ac4dca8e0c9693512bcfbbd70ce854028252ea260a59d6904d4badbc2f04f\
+                  21237aea313fae649a17dc3948a7f1d3133d3025532c2689fab0f5b924af96bb\
+                  1847866882460a5b33360d184867d62cbed88a3a4ca92ad8fb609c334cabad5c";
+    const PSS94: &str = "54626e75aed2afbc421f74df803c969e004467a642f0cb37fb836c2a433ed694\
+                  c2c81afd3c05a1361d8df0c5d1e7992bf1e322b4deb4499994225bd93be7665c\
+                  b9c19af2241a715696fa14361ae255f20baaf1ba58dd552060dca283422fd8fb\
+                  7e20978954cbe6735f9e19cafcee2d5d1c1199efb3ac84ca0a5ac7c707dac2f1";
+    const V15: &str = "481a34bfaef3228707bf2fa7750b2076dec714324b490edfbe02c71deff282e2\
+                  75edded4a79b562e58b3c24be7cbeb24ba7a5218bc1d4ab562d881ca0a024609\
+                  d9110eec9c08a16a8a950726b8410bc51387dc7b8f5b947c33b91448b4b23bd9\
+                  31ffc6a3f2402834a507960f16aeb2f939d8ec85e4046c8685d6520688379df8";
+    const OAEP: &str = "aefb7ef57a64f724c1481c610337be1d36ccb6eeda712feac4fee8675c0dba2e\
+                  c5a10c78eb4856b429e204b8dea55d3622b308835af095b17d00f8ee69e1ae01\
+                  8cc2b0ab394ae1fc73609743fda22f8bebee849b41d07d57d91be81670ac2391\
+                  0c4bedfc839d56d535c1b8c07e32a73995dacfe0029736db1e371fb08f4acd27";
+    const OAEP256: &str = "6263d0fa34942585419c2762d34bba95cfaf7d982f5ae5336d4411cba453e9c7\
+                  d12bdc31f66c35ac83f3955e5ab90120ad4e0c130eecfd53975a9e99c3dcc3de\
+                  237b9954a84599662f6c35f893355c10f4d9b66a59ae83c37589669ecd10cb6b\
+                  bc42056b0a2c5210cedfb845a1b534ed4371683b7c79737c6146c69927ef8789";
+    const MESSAGE: &[u8] = b"jwt-check test message";
+    const PLAINTEXT: &[u8] = b"content key 16by";
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        BigUint::from_hex(hex).to_be_bytes(128)
+    }
+
+    fn public() -> RsaPublicKey {
+        RsaPublicKey::new(BigUint::from_hex(N), BigUint::from_u64(65_537)).unwrap()
+    }
+
+    fn private() -> RsaPrivateKey {
+        let e = BigUint::from_u64(65_537);
+        RsaPrivateKey::new(BigUint::from_hex(N), e, BigUint::from_hex(D), &[]).unwrap()
+    }
+
+    /// RSASP1 of a crafted block, unpadded, to forge signatures with.
+    fn seal(em: &[u8]) -> Vec<u8> {
+        let modulus = Modulus::new(&BigUint::from_hex(N)).unwrap();
+        modulus
+            .pow(&BigUint::from_be_bytes(em), &BigUint::from_hex(D))
+            .to_be_bytes(128)
+    }
+
+    /// Signatures that are out of range of the modulus, whatever they hold.
+    fn out_of_range() -> Vec<Vec<u8>> {
+        let n = bytes(N);
+        let mut above = n.clone();
+        above[127] += 2;
+        vec![n, above, vec![0xff; 128]]
+    }
+
+    #[test]
+    fn key_test() {
+        let n = BigUint::from_hex(N);
+        assert_eq!(public().bits(), 1024);
+        assert!(RsaPublicKey::new(n.clone(), BigUint::from_u64(1)).is_none());
+        assert!(RsaPublicKey::new(n.clone(), BigUint::from_u64(65_536)).is_none());
+        assert!(RsaPublicKey::new(n.add(&BigUint::from_u64(1)), BigUint::from_u64(3)).is_none());
+        let e = BigUint::from_u64(65_537);
+        assert!(RsaPrivateKey::new(n.clone(), e.clone(), BigUint::zero(), &[]).is_none());
+        // a private exponent that doesn't belong to the modulus
+        let d = BigUint::from_hex(D).add(&BigUint::from_u64(2));
+        assert!(RsaPrivateKey::new(n, e, d, &[]).is_none());
+        assert_eq!(private().bits(), 1024);
+        let (n, e) = public().components();
+        assert_eq!((n, e), (bytes(N), vec![0x01, 0x00, 0x01]));
+    }
+
+    #[test]
+    fn pss_test() {
+        let key = public();
+        assert!(verify_pss(&key, Hash::Sha256, MESSAGE, &bytes(PSS32)));
+        assert!(!verify_pss(
+            &key,
+            Hash::Sha256,
+            b"another message",
+            &bytes(PSS32)
+        ));
+        assert!(!verify_pss(&key, Hash::Sha384, MESSAGE, &bytes(PSS32)));
+        // JWA fixes the salt at the hash length; shorter or longer is refused
+        for salted in [PSS0, PSS20, PSS94] {
+            assert!(!verify_pss(&key, Hash::Sha256, MESSAGE, &bytes(salted)));
+        }
+        let signature = bytes(PSS32);
+        assert!(!verify_pss(&key, Hash::Sha256, MESSAGE, &signature[1..]));
+        let mut padded = vec![0];
+        padded.extend_from_slice(&signature);
+        assert!(!verify_pss(&key, Hash::Sha256, MESSAGE, &padded));
+        assert!(!verify_pss(&key, Hash::Sha256, MESSAGE, &[]));
+        for signature in out_of_range() {
+            assert!(!verify_pss(&key, Hash::Sha256, MESSAGE, &signature));
+        }
+
+        let signed = sign_pss(&private(), Hash::Sha256, MESSAGE).unwrap();
+        assert!(verify_pss(&key, Hash::Sha256, MESSAGE, &signed));
+        // the salt is random, so no two signatures are the same
+        assert_ne!(sign_pss(&private(), Hash::Sha256, MESSAGE).unwrap(), signed);
+        // 128 bytes of EM cannot hold a SHA-512 hash and as long a salt
+        assert!(sign_pss(&private(), Hash::Sha512, MESSAGE).is_none());
+        // a recovered EM with a bad trailer, or with the bit above emBits set
+        let em = key.open(&signed).unwrap();
+        let mut trailer = em.clone();
+        trailer[127] = 0xbb;
+        let mut top_bit = em;
+        top_bit[0] |= 0x80;
+        for em in [trailer, top_bit] {
+            assert!(!verify_pss(&key, Hash::Sha256, MESSAGE, &seal(&em)));
+        }
+    }
+
+    #[test]
+    fn pkcs1v15_test() {
+        let key = public();
+        // deterministic, so signing reproduces OpenSSL's signature exactly
+        assert_eq!(
+            sign_pkcs1v15(&private(), Hash::Sha256, MESSAGE).unwrap(),
+            bytes(V15)
+        );
+        assert!(verify_pkcs1v15(&key, Hash::Sha256, MESSAGE, &bytes(V15)));
+        assert!(!verify_pkcs1v15(&key, Hash::Sha512, MESSAGE, &bytes(V15)));
+        assert!(!verify_pkcs1v15(
+            &key,
+            Hash::Sha256,
+            b"another message",
+            &bytes(V15)
+        ));
+        assert!(!verify_pkcs1v15(
+            &key,
+            Hash::Sha256,
+            MESSAGE,
+            &bytes(V15)[1..]
+        ));
+        assert!(!verify_pkcs1v15(&key, Hash::Sha256, MESSAGE, &bytes(PSS32)));
+        for signature in out_of_range() {
+            assert!(!verify_pkcs1v15(&key, Hash::Sha256, MESSAGE, &signature));
+        }
+        // SHA-512's DigestInfo is 83 bytes, and needs 11 more of padding
+        assert!(pkcs1v15_encode(Hash::Sha512, MESSAGE, 93).is_none());
+        assert_eq!(
+            pkcs1v15_encode(Hash::Sha512, MESSAGE, 94).unwrap().len(),
+            94
+        );
+    }
+
+    #[test]
+    fn oaep_test() {
+        let key = private();
+        assert_eq!(
+            decrypt_oaep(&key, Hash::Sha1, &bytes(OAEP)).unwrap(),
+            PLAINTEXT
+        );
+        assert_eq!(
+            decrypt_oaep(&key, Hash::Sha256, &bytes(OAEP256)).unwrap(),
+            PLAINTEXT
+        );
+        assert!(decrypt_oaep(&key, Hash::Sha256, &bytes(OAEP)).is_none());
+        let mut flipped = bytes(OAEP);
+        flipped[64] ^= 1;
+        assert!(decrypt_oaep(&key, Hash::Sha1, &flipped).is_none());
+        assert!(decrypt_oaep(&key, Hash::Sha1, &bytes(OAEP)[1..]).is_none());
+        for ciphertext in out_of_range() {
+            assert!(decrypt_oaep(&key, Hash::Sha1, &ciphertext).is_none());
+        }
+        // SHA-512 needs 2 * 64 + 2 bytes, more than the modulus has
+        assert!(decrypt_oaep(&key, Hash::Sha512, &bytes(OAEP)).is_none());
+    }
+}
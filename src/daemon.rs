@@ -0,0 +1,364 @@
+//! `jwt-check daemon`: a resident process that keeps fetched JWKS documents
+//! warm, so scripted runs don't pay for a network round trip each time.
+//!
+//! The protocol is one request per connection over a Unix socket. The client
+//! sends `GET <url>\n`. The daemon answers `OK <length> <age>\n<body>`, the
+//! age being how many seconds ago the body was fetched, or
+//! `ERR <message>\n`. `--jwks-url` uses a running daemon automatically and
+//! falls back to fetching directly when none answers.
+//!
+//! Entries are stale-while-revalidate. Within `--jwks-ttl` an entry is
+//! served as is. Past it, but within `--max-stale` more, it is still served
+//! at once while a single background fetch refreshes it, so a verification
+//! never waits for keys that have merely expired. Only an entry older than
+//! that, or a URL not yet fetched, makes the request wait for the network.
+//! A failed refresh keeps the stale entry, and the next request retries.
+
+use crate::JWTError;
+use jwt_check::http;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Largest body the client accepts from the daemon.
+const MAX_BODY: usize = 4 * 1024 * 1024;
+
+/// A fetched JWKS body, and whether a background refresh is under way.
+struct Entry {
+    fetched: Instant,
+    body: Vec<u8>,
+    refreshing: bool,
+}
+
+/// Fetched JWKS bodies by URL.
+type Entries = HashMap<String, Entry>;
+type Cache = Arc<Mutex<Entries>>;
+
+/// How long an entry is fresh, and how much longer it may be served stale
+/// while it is refreshed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Freshness {
+    pub ttl: Duration,
+    pub max_stale: Duration,
+}
+
+/// A JWKS body from the daemon, and how long ago it was fetched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fetched {
+    pub body: Vec<u8>,
+    pub age: Duration,
+}
+
+/// `--socket`, else `$JWT_CHECK_SOCKET`, else `$XDG_RUNTIME_DIR/jwt-check.sock`.
+/// The runtime directory is private to the user; there is no shared `/tmp`
+/// fallback, since a socket there could be planted by another user to serve
+/// forged keys.
+pub fn socket_path(explicit: Option<&str>) -> Option<PathBuf> {
+    if let Some(path) = explicit {
+        return Some(PathBuf::from(path));
+    }
+    if let Some(path) = std::env::var_os("JWT_CHECK_SOCKET") {
+        return Some(PathBuf::from(path));
+    }
+    std::env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join("jwt-check.sock"))
+}
+
+/// Binds the daemon's socket, replacing one left behind by a dead daemon.
+pub fn bind(path: &Path) -> Result<UnixListener, JWTError> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(JWTError::InvalidArgumentError(
+                format!("a daemon is already listening on {}", path.display()).into(),
+            ));
+        }
+        // left behind by a daemon that didn't shut down cleanly
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Serves JWKS requests, fetched with `client`, until the process is
+/// killed.
+pub fn serve(
+    listener: UnixListener,
+    freshness: Freshness,
+    client: http::Client,
+) -> Result<(), JWTError> {
+    let cache: Cache = Arc::default();
+    let client = Arc::new(client);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept failed: {}", e);
+                continue;
+            }
+        };
+        let (cache, client) = (Arc::clone(&cache), Arc::clone(&client));
+        std::thread::spawn(move || {
+            if let Err(e) = handle(stream, &cache, &client, freshness) {
+                eprintln!("request failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle(
+    stream: UnixStream,
+    cache: &Cache,
+    client: &Arc<http::Client>,
+    freshness: Freshness,
+) -> Result<(), JWTError> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut writer = &stream;
+    let url = match line.trim_end().strip_prefix("GET ") {
+        Some(url) => url.to_string(),
+        None => {
+            writeln!(writer, "ERR expected `GET <url>`")?;
+            return Ok(());
+        }
+    };
+    match cached_fetch(cache, client, &url, freshness) {
+        Ok(fetched) => {
+            writeln!(
+                writer,
+                "OK {} {}",
+                fetched.body.len(),
+                fetched.age.as_secs()
+            )?;
+            writer.write_all(&fetched.body)?;
+        }
+        Err(e) => writeln!(writer, "ERR {}", e.to_string().replace('\n', " "))?,
+    }
+    Ok(())
+}
+
+fn lock(cache: &Cache) -> Result<MutexGuard<'_, Entries>, JWTError> {
+    cache
+        .lock()
+        .map_err(|_| JWTError::InvalidArgumentError("daemon cache lock poisoned".into()))
+}
+
+fn store(cache: &Cache, url: &str, body: Vec<u8>) -> Result<(), JWTError> {
+    let entry = Entry {
+        fetched: Instant::now(),
+        body,
+        refreshing: false,
+    };
+    lock(cache)?.insert(url.to_string(), entry);
+    Ok(())
+}
+
+/// Fetches `url` in the background to replace a stale entry.
+fn refresh(cache: &Cache, client: &Arc<http::Client>, url: &str) {
+    let (cache, client, url) = (Arc::clone(cache), Arc::clone(client), url.to_string());
+    std::thread::spawn(move || {
+        let refreshed = client.get(&url).and_then(|body| store(&cache, &url, body));
+        if let Err(e) = refreshed {
+            eprintln!("refreshing {} failed: {}", url, e);
+            if let Ok(mut entries) = lock(&cache) {
+                if let Some(entry) = entries.get_mut(&url) {
+                    entry.refreshing = false;
+                }
+            }
+        }
+    });
+}
+
+fn cached_fetch(
+    cache: &Cache,
+    client: &Arc<http::Client>,
+    url: &str,
+    freshness: Freshness,
+) -> Result<Fetched, JWTError> {
+    {
+        let mut entries = lock(cache)?;
+        if let Some(entry) = entries.get_mut(url) {
+            let age = entry.fetched.elapsed();
+            if age < freshness.ttl + freshness.max_stale {
+                if age >= freshness.ttl && !entry.refreshing {
+                    entry.refreshing = true;
+                    refresh(cache, client, url);
+                }
+                let body = entry.body.clone();
+                return Ok(Fetched { body, age });
+            }
+        }
+    }
+    // fetch without holding the lock so one slow issuer doesn't stall the rest
+    let body = client.get(url)?;
+    store(cache, url, body.clone())?;
+    Ok(Fetched {
+        body,
+        age: Duration::ZERO,
+    })
+}
+
+/// Whether the socket belongs to the same user as the directory it is in.
+fn trusted(path: &Path) -> bool {
+    let parent = path.parent().unwrap_or_else(|| Path::new("."));
+    match (std::fs::metadata(path), std::fs::metadata(parent)) {
+        (Ok(socket), Ok(dir)) => socket.uid() == dir.uid(),
+        _ => false,
+    }
+}
+
+/// Asks a running daemon for `url`. `None` means no usable daemon, and the
+/// caller should fetch directly.
+pub fn fetch(path: &Path, url: &str) -> Option<Result<Fetched, JWTError>> {
+    if !trusted(path) {
+        return None;
+    }
+    let mut stream = UnixStream::connect(path).ok()?;
+    Some(request(&mut stream, url))
+}
+
+fn request(stream: &mut UnixStream, url: &str) -> Result<Fetched, JWTError> {
+    writeln!(stream, "GET {}", url)?;
+    let mut reader = BufReader::new(stream);
+    let mut status = String::new();
+    reader.read_line(&mut status)?;
+    let status = status.trim_end();
+    if let Some(message) = status.strip_prefix("ERR ") {
+        return Err(JWTError::HttpError(format!("daemon: {}", message).into()));
+    }
+    let unexpected = || JWTError::HttpError(format!("daemon sent `{}`", status).into());
+    let mut fields = status
+        .strip_prefix("OK ")
+        .ok_or_else(unexpected)?
+        .split(' ');
+    let length = fields
+        .next()
+        .and_then(|n| n.parse::<usize>().ok())
+        .filter(|n| *n <= MAX_BODY)
+        .ok_or_else(unexpected)?;
+    // a daemon from before ages were sent leaves it out
+    let age = match fields.next() {
+        Some(age) => Duration::from_secs(age.parse().map_err(|_| unexpected())?),
+        None => Duration::ZERO,
+    };
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    Ok(Fetched { body, age })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn serves_jwks_from_cache_test() {
+        // an HTTP server that answers exactly once
+        let http = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/jwks", http.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = http.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n{\"keys\":[]}")
+                .unwrap();
+        });
+        // a directory of our own, as XDG_RUNTIME_DIR would be
+        let dir = std::env::temp_dir().join(format!("jwt-check-daemon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("jwt-check.sock");
+        let _ = std::fs::remove_file(&socket);
+        let path = socket.clone();
+        let listener = bind(&path).unwrap();
+        // every entry is stale at once, but may be served for a minute
+        let freshness = Freshness {
+            ttl: Duration::ZERO,
+            max_stale: Duration::from_secs(60),
+        };
+        std::thread::spawn(move || serve(listener, freshness, http::Client::default()));
+        for _ in 0..100 {
+            if socket.exists() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        // the second answer is the stale entry, although its background
+        // refresh fails once the server has gone
+        for _ in 0..3 {
+            let fetched = fetch(&socket, &url).unwrap().unwrap();
+            assert_eq!(fetched.body, b"{\"keys\":[]}");
+            assert!(fetched.age < Duration::from_secs(60));
+        }
+        assert!(matches!(
+            fetch(&socket, "ftp://idp.example/jwks"),
+            Some(Err(JWTError::HttpError(e))) if e == "daemon: HTTP error: ftp://idp.example/jwks: expected an http:// or https:// URL"
+        ));
+        assert!(fetch(Path::new("/nonexistent/jwt-check.sock"), &url).is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refusals_test() {
+        let dir =
+            std::env::temp_dir().join(format!("jwt-check-daemon-refusals-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("jwt-check.sock");
+        let _ = std::fs::remove_file(&socket);
+        let listener = bind(&socket).unwrap();
+        assert!(matches!(
+            bind(&socket),
+            Err(JWTError::InvalidArgumentError(e))
+                if e == format!("a daemon is already listening on {}", socket.display())
+        ));
+
+        // a daemon answering each request with the next of these lines
+        let answers = ["HELLO", "OK lots", "OK 2 soon", "ERR upstream gone"];
+        let server = std::thread::spawn(move || {
+            // the connection that found the socket in use
+            drop(listener.accept().unwrap());
+            for answer in answers {
+                let (stream, _) = listener.accept().unwrap();
+                let mut line = String::new();
+                BufReader::new(&stream).read_line(&mut line).unwrap();
+                writeln!(&stream, "{}", answer).unwrap();
+            }
+        });
+        for error in [
+            "daemon sent `HELLO`",
+            "daemon sent `OK lots`",
+            "daemon sent `OK 2 soon`",
+            "daemon: upstream gone",
+        ] {
+            let mut stream = UnixStream::connect(&socket).unwrap();
+            assert!(matches!(
+                request(&mut stream, "http://idp/jwks"),
+                Err(JWTError::HttpError(e)) if e == error
+            ));
+        }
+        server.join().unwrap();
+
+        // a client that doesn't speak the protocol
+        let listener = {
+            std::fs::remove_file(&socket).unwrap();
+            bind(&socket).unwrap()
+        };
+        let freshness = Freshness {
+            ttl: Duration::ZERO,
+            max_stale: Duration::ZERO,
+        };
+        std::thread::spawn(move || serve(listener, freshness, http::Client::default()));
+        let stream = UnixStream::connect(&socket).unwrap();
+        writeln!(&stream, "PUT http://idp/jwks").unwrap();
+        let mut answer = String::new();
+        BufReader::new(&stream).read_line(&mut answer).unwrap();
+        assert_eq!(answer, "ERR expected `GET <url>`\n");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
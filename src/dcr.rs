@@ -0,0 +1,623 @@
+//! `dcr`: lints Open Banking software statements and Dynamic Client
+//! Registration requests before they're sent, so a registration isn't
+//! rejected over a missing claim or a redirect URI with a trailing slash.
+//!
+//! The input is a software statement (SSA), a signed registration request
+//! carrying one in `software_statement` (UK Open Banking DCR), or the plain
+//! JSON registration body Brazil's Open Finance DCR takes. Checked are:
+//!
+//! - the metadata RFC 7591 and the UK and Brazil DCR specifications require,
+//!   and that each field has the JSON type they give it;
+//! - that redirect URIs are absolute `https` URIs without a fragment or
+//!   wildcard, and that each one is among the SSA's
+//!   `software_redirect_uris`;
+//! - that the request's `iss` is the SSA's `software_id`, and that it and
+//!   the SSA are within their lifetimes;
+//! - with a `--trust-dir` ([`crate::trust`]), the signatures: the SSA's
+//!   against the directory keys, and the request's against the keys of the
+//!   software the SSA describes.
+
+use crate::trust::Directory;
+use crate::verify::verify;
+use crate::{format_timestamp, numeric_claim, parse, JWTError, JWToken};
+use serde_json::{json, Value};
+use std::fmt;
+
+/// How far in the future an `iat` may be, for clock skew.
+pub const IAT_LEEWAY: i64 = 60;
+
+const SSA_REQUIRED: [&str; 7] = [
+    "iss",
+    "iat",
+    "jti",
+    "org_id",
+    "software_id",
+    "software_redirect_uris",
+    "software_roles",
+];
+
+const REQUEST_REQUIRED: [&str; 4] = [
+    "redirect_uris",
+    "token_endpoint_auth_method",
+    "grant_types",
+    "software_statement",
+];
+
+/// What a signed request needs on top of [`REQUEST_REQUIRED`].
+const SIGNED_REQUEST_REQUIRED: [&str; 5] = ["iss", "aud", "iat", "exp", "jti"];
+
+const ARRAY_FIELDS: [&str; 5] = [
+    "redirect_uris",
+    "grant_types",
+    "response_types",
+    "software_redirect_uris",
+    "software_roles",
+];
+
+const AUTH_METHODS: [&str; 6] = [
+    "private_key_jwt",
+    "tls_client_auth",
+    "self_signed_tls_client_auth",
+    "client_secret_jwt",
+    "client_secret_basic",
+    "client_secret_post",
+];
+
+const GRANT_TYPES: [&str; 5] = [
+    "authorization_code",
+    "client_credentials",
+    "refresh_token",
+    "implicit",
+    "urn:openid:params:grant-type:ciba",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Warning => write!(f, "warning"),
+            Level::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// One problem, with what to do about it in `message`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub level: Level,
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Document {
+    SoftwareStatement,
+    SignedRequest,
+    Request,
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Document::SoftwareStatement => write!(f, "software statement"),
+            Document::SignedRequest => write!(f, "signed registration request"),
+            Document::Request => write!(f, "registration request"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Lint {
+    pub document: Document,
+    pub problems: Vec<Problem>,
+    /// How the signatures were checked, one line per step; empty without a
+    /// trust directory.
+    pub trust: Vec<String>,
+}
+
+impl Lint {
+    pub fn errors(&self) -> usize {
+        self.problems
+            .iter()
+            .filter(|p| p.level == Level::Error)
+            .count()
+    }
+}
+
+struct Problems {
+    prefix: &'static str,
+    list: Vec<Problem>,
+}
+
+impl Problems {
+    fn push(&mut self, level: Level, field: &str, message: String) {
+        self.list.push(Problem {
+            level,
+            field: format!("{}{}", self.prefix, field),
+            message,
+        });
+    }
+
+    fn error(&mut self, field: &str, message: String) {
+        self.push(Level::Error, field, message);
+    }
+
+    fn warning(&mut self, field: &str, message: String) {
+        self.push(Level::Warning, field, message);
+    }
+}
+
+fn trust_message(e: JWTError) -> String {
+    match e {
+        JWTError::TrustError(message) => String::from(message),
+        other => other.to_string(),
+    }
+}
+
+/// Lints `input`: a compact JWT, or a JSON registration body.
+pub fn lint(input: &str, now: i64, directory: Option<&Directory>) -> Result<Lint, JWTError> {
+    let input = input.trim();
+    let mut problems = Problems {
+        prefix: "",
+        list: Vec::new(),
+    };
+    let mut trust = Vec::new();
+    if input.starts_with('{') {
+        let payload: Value = serde_json::from_str(input)?;
+        let ssa = request(&payload, None, now, &mut problems);
+        if let (Some(directory), Some(raw)) = (directory, ssa) {
+            match directory.verify_statement(raw) {
+                Ok(_) => {
+                    trust.push("software statement verified with the directory keys".to_string())
+                }
+                Err(e) => problems.error("software_statement", trust_message(e)),
+            }
+        }
+        return Ok(Lint {
+            document: Document::Request,
+            problems: problems.list,
+            trust,
+        });
+    }
+    let token = parse(input)?;
+    if token.payload.get("software_statement").is_none() {
+        software_statement(&token.payload, now, &mut problems);
+        if let Some(directory) = directory {
+            match directory.verify_statement(input) {
+                Ok(_) => trust.push("signature verified with the directory keys".to_string()),
+                Err(e) => problems.error("signature", trust_message(e)),
+            }
+        }
+        return Ok(Lint {
+            document: Document::SoftwareStatement,
+            problems: problems.list,
+            trust,
+        });
+    }
+    request(&token.payload, Some(&token), now, &mut problems);
+    if let Some(directory) = directory {
+        match directory.resolve(&token, None) {
+            Ok(resolution) => {
+                trust.extend(resolution.steps);
+                if resolution
+                    .keys
+                    .iter()
+                    .any(|key| verify(input, &token, key).is_ok())
+                {
+                    trust.push("signature verified with the software's keys".to_string());
+                } else {
+                    problems.error(
+                        "signature",
+                        "does not verify with the keys of the software the statement describes; sign with a key in its JWKS, and set that key's kid"
+                            .to_string(),
+                    );
+                }
+            }
+            Err(e) => problems.error("signature", trust_message(e)),
+        }
+    }
+    Ok(Lint {
+        document: Document::SignedRequest,
+        problems: problems.list,
+        trust,
+    })
+}
+
+fn required(payload: &Value, fields: &[&str], problems: &mut Problems) {
+    for field in fields {
+        if payload.get(field).is_none() {
+            problems.error(field, "is missing; it is required".to_string());
+        }
+    }
+}
+
+fn arrays(payload: &Value, problems: &mut Problems) {
+    for field in ARRAY_FIELDS {
+        match payload.get(field) {
+            Some(Value::Array(_)) | None => {}
+            Some(Value::String(value)) => problems.error(
+                field,
+                format!("must be a JSON array; write [\"{}\"]", value),
+            ),
+            Some(_) => problems.error(field, "must be a JSON array of strings".to_string()),
+        }
+    }
+}
+
+fn strings<'a>(payload: &'a Value, field: &str) -> Vec<&'a str> {
+    payload
+        .get(field)
+        .and_then(Value::as_array)
+        .map(|values| values.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+/// What is wrong with a redirect URI, as registered.
+fn redirect_uri_problem(uri: &str) -> Option<String> {
+    if uri.trim() != uri || uri.contains(char::is_whitespace) {
+        return Some("contains whitespace; remove it".to_string());
+    }
+    let rest = match uri.strip_prefix("https://") {
+        Some(rest) => rest,
+        None if uri.starts_with("http://") => {
+            return Some("is http; redirect URIs must use https".to_string())
+        }
+        None => return Some("is not an absolute https:// URI".to_string()),
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if host.is_empty() {
+        return Some("has no host".to_string());
+    }
+    if uri.contains('*') {
+        return Some("contains a wildcard; register each URI exactly".to_string());
+    }
+    if uri.contains('#') {
+        return Some("has a fragment, which RFC 6749 forbids; remove it".to_string());
+    }
+    None
+}
+
+fn redirect_uris(payload: &Value, field: &str, problems: &mut Problems) {
+    let uris = strings(payload, field);
+    if payload.get(field).is_some_and(Value::is_array) && uris.is_empty() {
+        problems.error(field, "is empty; register at least one URI".to_string());
+    }
+    for (i, uri) in uris.iter().enumerate() {
+        if let Some(problem) = redirect_uri_problem(uri) {
+            problems.error(
+                &format!("{}[{}]", field, i),
+                format!("`{}` {}", uri, problem),
+            );
+        } else if uri.contains("://localhost") || uri.contains("://127.0.0.1") {
+            problems.warning(
+                &format!("{}[{}]", field, i),
+                format!(
+                    "`{}` is a loopback URI, which production directories reject",
+                    uri
+                ),
+            );
+        }
+    }
+}
+
+/// `iat` not in the future and `exp`, when present, not past.
+fn lifetime(payload: &Value, now: i64, problems: &mut Problems) {
+    let iat = numeric_claim(payload, "iat");
+    let exp = numeric_claim(payload, "exp");
+    if let Some(iat) = iat.filter(|iat| *iat > now + IAT_LEEWAY) {
+        problems.error(
+            "iat",
+            format!(
+                "is {}, in the future; check the signer's clock",
+                format_timestamp(iat)
+            ),
+        );
+    }
+    if let Some(exp) = exp {
+        if exp <= now {
+            problems.error(
+                "exp",
+                format!("expired at {}; sign a fresh one", format_timestamp(exp)),
+            );
+        }
+        if iat.is_some_and(|iat| exp <= iat) {
+            problems.error("exp", "is not after iat".to_string());
+        }
+    }
+    for field in ["iat", "exp"] {
+        if payload.get(field).is_some() && numeric_claim(payload, field).is_none() {
+            problems.error(field, "must be a NumericDate, in seconds".to_string());
+        }
+    }
+}
+
+fn software_statement(payload: &Value, now: i64, problems: &mut Problems) {
+    required(payload, &SSA_REQUIRED, problems);
+    arrays(payload, problems);
+    if payload.get("software_jwks_endpoint").is_none() && payload.get("software_jwks_uri").is_none()
+    {
+        problems.error(
+            "software_jwks_endpoint",
+            "is missing; the directory needs the software's JWKS URI (software_jwks_uri in Brazil)"
+                .to_string(),
+        );
+    }
+    if payload.get("software_client_name").is_none() {
+        problems.warning(
+            "software_client_name",
+            "is missing; banks show it to users when they authorize".to_string(),
+        );
+    }
+    redirect_uris(payload, "software_redirect_uris", problems);
+    lifetime(payload, now, problems);
+}
+
+/// Lints a registration request, and the SSA in it; returns the SSA.
+fn request<'a>(
+    payload: &'a Value,
+    token: Option<&JWToken>,
+    now: i64,
+    problems: &mut Problems,
+) -> Option<&'a str> {
+    required(payload, &REQUEST_REQUIRED, problems);
+    arrays(payload, problems);
+    if token.is_some() {
+        required(payload, &SIGNED_REQUEST_REQUIRED, problems);
+        lifetime(payload, now, problems);
+    }
+    redirect_uris(payload, "redirect_uris", problems);
+    let method = payload
+        .get("token_endpoint_auth_method")
+        .and_then(Value::as_str);
+    match method {
+        Some(method) if !AUTH_METHODS.contains(&method) => problems.error(
+            "token_endpoint_auth_method",
+            format!(
+                "`{}` is not a client authentication method; use private_key_jwt or tls_client_auth",
+                method
+            ),
+        ),
+        Some(method) if method.starts_with("client_secret") => problems.warning(
+            "token_endpoint_auth_method",
+            format!(
+                "`{}` is a shared secret, which FAPI doesn't allow; use private_key_jwt or tls_client_auth",
+                method
+            ),
+        ),
+        Some("private_key_jwt") if payload.get("token_endpoint_auth_signing_alg").is_none() => {
+            problems.error(
+                "token_endpoint_auth_signing_alg",
+                "is missing; private_key_jwt needs it, e.g. PS256".to_string(),
+            )
+        }
+        Some("tls_client_auth") if payload.get("tls_client_auth_subject_dn").is_none() => problems
+            .error(
+                "tls_client_auth_subject_dn",
+                "is missing; tls_client_auth needs the transport certificate's subject DN"
+                    .to_string(),
+            ),
+        _ => {}
+    }
+    let grants = strings(payload, "grant_types");
+    for grant in &grants {
+        if !GRANT_TYPES.contains(grant) {
+            problems.warning(
+                "grant_types",
+                format!("`{}` is not a grant type the directories know", grant),
+            );
+        }
+    }
+    let responses = strings(payload, "response_types");
+    if grants.contains(&"authorization_code")
+        && !responses
+            .iter()
+            .any(|r| r.split(' ').any(|part| part == "code"))
+    {
+        problems.error(
+            "response_types",
+            "must include `code` (or `code id_token`) with the authorization_code grant"
+                .to_string(),
+        );
+    }
+    if payload.get("scope").is_some_and(|scope| !scope.is_string()) {
+        problems.error(
+            "scope",
+            "must be a space-separated string, not a list".to_string(),
+        );
+    }
+
+    let raw = payload.get("software_statement").and_then(Value::as_str)?;
+    let ssa = match parse(raw) {
+        Ok(ssa) => ssa.payload,
+        Err(e) => {
+            problems.error(
+                "software_statement",
+                format!("is not a JWT ({}); paste the SSA as issued", e),
+            );
+            return None;
+        }
+    };
+    let mut nested = Problems {
+        prefix: "software_statement.",
+        list: Vec::new(),
+    };
+    software_statement(&ssa, now, &mut nested);
+    problems.list.append(&mut nested.list);
+    let allowed = strings(&ssa, "software_redirect_uris");
+    for (i, uri) in strings(payload, "redirect_uris").iter().enumerate() {
+        if allowed.is_empty() || allowed.contains(uri) {
+            continue;
+        }
+        let bare = uri.trim_end_matches('/');
+        let near = allowed.iter().find(|a| a.trim_end_matches('/') == bare);
+        problems.error(
+            &format!("redirect_uris[{}]", i),
+            match near {
+                Some(near) => format!("`{}` is not in the SSA; it has `{}`", uri, near),
+                None => format!(
+                    "`{}` is not among the SSA's software_redirect_uris; add it in the directory and issue a new SSA",
+                    uri
+                ),
+            },
+        );
+    }
+    let software_id = ssa.get("software_id").and_then(Value::as_str);
+    let iss = payload.get("iss").and_then(Value::as_str);
+    if let (Some(_), Some(software_id)) = (token, software_id) {
+        if iss.is_some_and(|iss| iss != software_id) {
+            problems.error(
+                "iss",
+                format!("must be the SSA's software_id, `{}`", software_id),
+            );
+        }
+    }
+    Some(raw)
+}
+
+pub fn render_text(lint: &Lint) -> String {
+    let mut out = format!("{}\n", lint.document);
+    let mut problems: Vec<&Problem> = lint.problems.iter().collect();
+    problems.sort_by_key(|p| std::cmp::Reverse(p.level));
+    for problem in problems {
+        out.push_str(&format!(
+            "{:7} {}: {}\n",
+            problem.level.to_string(),
+            problem.field,
+            problem.message
+        ));
+    }
+    for step in &lint.trust {
+        out.push_str(&format!("trust: {}\n", step));
+    }
+    if lint.trust.is_empty() && !lint.problems.iter().any(|p| p.field == "signature") {
+        out.push_str("signature: not checked; pass --trust-dir to check it\n");
+    }
+    let errors = lint.errors();
+    out.push_str(&format!(
+        "dcr: {} error(s), {} warning(s)",
+        errors,
+        lint.problems.len() - errors
+    ));
+    out
+}
+
+pub fn to_json(lint: &Lint) -> Value {
+    let errors = lint.errors();
+    json!({
+        "document": lint.document.to_string(),
+        "errors": errors,
+        "warnings": lint.problems.len() - errors,
+        "problems": lint.problems.iter().map(|problem| json!({
+            "level": problem.level.to_string(),
+            "field": problem.field,
+            "message": problem.message,
+        })).collect::<Vec<_>>(),
+        "trust": lint.trust,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::SigningKey;
+    use crate::sign::sign;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn signed(claims: Value) -> String {
+        sign(
+            &json!({"alg": "HS256", "kid": "k"}),
+            &claims,
+            &SigningKey::Hmac(b"secret".to_vec()),
+        )
+        .unwrap()
+    }
+
+    fn fields(lint: &Lint) -> Vec<&str> {
+        lint.problems.iter().map(|p| p.field.as_str()).collect()
+    }
+
+    #[test]
+    fn lints_registration_requests_test() {
+        let ssa = signed(json!({
+            "iss": "OpenBanking Ltd", "iat": NOW - 60, "jti": "1",
+            "org_id": "org", "software_id": "sw", "software_client_name": "App",
+            "software_roles": ["AISP"],
+            "software_jwks_endpoint": "https://keystore.example/org/sw.jwks",
+            "software_redirect_uris": ["https://app.example/cb"],
+        }));
+        let lint = lint(&ssa, NOW, None).unwrap();
+        assert_eq!(lint.document, Document::SoftwareStatement);
+        assert!(lint.problems.is_empty(), "{:?}", lint.problems);
+
+        let good = json!({
+            "iss": "sw", "aud": "https://bank.example", "iat": NOW, "exp": NOW + 300,
+            "jti": "2", "redirect_uris": ["https://app.example/cb"],
+            "token_endpoint_auth_method": "private_key_jwt",
+            "token_endpoint_auth_signing_alg": "PS256",
+            "grant_types": ["authorization_code", "refresh_token"],
+            "response_types": ["code id_token"], "software_statement": ssa,
+        });
+        let lint = super::lint(&signed(good.clone()), NOW, None).unwrap();
+        assert_eq!(lint.document, Document::SignedRequest);
+        assert!(lint.problems.is_empty(), "{:?}", lint.problems);
+        assert!(render_text(&lint).contains("signature: not checked"));
+
+        let mut bad = good;
+        bad["iss"] = json!("someone-else");
+        bad["redirect_uris"] = json!(["https://app.example/cb/", "http://app.example/x#y"]);
+        bad["grant_types"] = json!("authorization_code");
+        bad["exp"] = json!(NOW - 1);
+        bad.as_object_mut()
+            .unwrap()
+            .remove("token_endpoint_auth_signing_alg");
+        let lint = super::lint(&signed(bad.clone()), NOW, None).unwrap();
+        assert_eq!(
+            fields(&lint),
+            [
+                "grant_types",
+                "exp",
+                "exp",
+                "redirect_uris[1]",
+                "token_endpoint_auth_signing_alg",
+                "redirect_uris[0]",
+                "redirect_uris[1]",
+                "iss"
+            ]
+        );
+        assert_eq!(
+            lint.problems[5].message,
+            "`https://app.example/cb/` is not in the SSA; it has `https://app.example/cb`"
+        );
+        assert!(lint.problems[1].message.starts_with("expired at"));
+
+        // Brazil's plain JSON body: no iss/exp, but the SSA is still linted
+        let mut body = json!({
+            "redirect_uris": ["https://app.example/cb"],
+            "token_endpoint_auth_method": "tls_client_auth",
+            "tls_client_auth_subject_dn": "CN=app",
+            "grant_types": ["client_credentials"],
+            "software_statement": signed(json!({"org_id": "org", "software_id": "sw"})),
+        });
+        let lint = super::lint(&body.to_string(), NOW, None).unwrap();
+        assert_eq!(lint.document, Document::Request);
+        assert_eq!(lint.errors(), 6);
+        assert!(fields(&lint).contains(&"software_statement.software_redirect_uris"));
+        body["software_statement"] = json!("not a jwt");
+        let lint = super::lint(&body.to_string(), NOW, None).unwrap();
+        assert_eq!(fields(&lint), ["software_statement"]);
+        assert_eq!(lint.problems[0].level, Level::Error);
+        assert_eq!(
+            lint.problems[0].message,
+            format!(
+                "is not a JWT ({}); paste the SSA as issued",
+                parse("not a jwt").unwrap_err()
+            )
+        );
+    }
+}
@@ -0,0 +1,469 @@
+//! Requests copied out of browser devtools: "Copy as cURL" for bash and for
+//! cmd, and "Copy as fetch", in the forms Chrome and Firefox write them.
+//!
+//! Only the URL and headers are kept; `-b`/`--cookie` values become a
+//! `Cookie` header.
+
+use crate::{jwe, parse, JWTError};
+use serde_json::Value;
+
+/// A request copied from devtools.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CopiedRequest {
+    /// `curl` or `fetch`, for messages.
+    pub kind: &'static str,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+}
+
+/// A JWT in a copied request and where it was: `authorization header` or
+/// `cookie <name>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoundToken {
+    pub location: String,
+    pub token: String,
+}
+
+fn snippet_error(message: &str) -> JWTError {
+    JWTError::InvalidArgumentError(format!("copied request: {}", message).into())
+}
+
+fn is_jwt(value: &str) -> bool {
+    match value.split('.').count() {
+        3 => parse(value).is_ok(),
+        5 => jwe::parse(value).is_ok(),
+        _ => false,
+    }
+}
+
+impl CopiedRequest {
+    /// Every JWT in the request: the bearer token first, then cookies in
+    /// the order they were sent.
+    pub fn tokens(&self) -> Vec<FoundToken> {
+        let mut found = Vec::new();
+        for (name, value) in &self.headers {
+            if name.eq_ignore_ascii_case("authorization") {
+                let token = crate::extract_token(value);
+                if is_jwt(token) {
+                    found.insert(
+                        0,
+                        FoundToken {
+                            location: "authorization header".to_string(),
+                            token: token.to_string(),
+                        },
+                    );
+                }
+            } else if name.eq_ignore_ascii_case("cookie") {
+                for pair in value.split(';') {
+                    let (cookie, value) = match pair.split_once('=') {
+                        Some(split) => split,
+                        None => continue,
+                    };
+                    let value = value.trim().trim_matches('"');
+                    if is_jwt(value) {
+                        found.push(FoundToken {
+                            location: format!("cookie {}", cookie.trim()),
+                            token: value.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Parses `input` when it is a copied `curl` or `fetch` call. `None` means
+/// it isn't one and should be read as a token.
+pub fn parse_copied(input: &str) -> Option<Result<CopiedRequest, JWTError>> {
+    let text = input.trim_start();
+    if text.starts_with("curl ") || text.starts_with("curl.exe ") {
+        let words = if text.contains("^\"") {
+            cmd_words(text)
+        } else {
+            bash_words(text)
+        };
+        return Some(words.and_then(|words| curl_request(&words)));
+    }
+    let call = text
+        .strip_prefix("await ")
+        .unwrap_or(text)
+        .strip_prefix("fetch(")?;
+    Some(fetch_request(call))
+}
+
+/// Splits a bash command line the way the shell would, for the quoting
+/// devtools uses: `'single'`, `$'ANSI-C'`, `"double"`, backslash escapes and
+/// `\` line continuations.
+fn bash_words(text: &str) -> Result<Vec<String>, JWTError> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => (),
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => (),
+            },
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(snippet_error("unterminated ' quote")),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\\') => word.push(ansi_c_escape(&mut chars)?),
+                        Some(c) => word.push(c),
+                        None => return Err(snippet_error("unterminated $' quote")),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => (),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(snippet_error("unterminated \" quote")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(snippet_error("unterminated \" quote")),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+fn hex_digits(
+    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    max: usize,
+) -> Result<char, JWTError> {
+    let mut digits = String::new();
+    while digits.len() < max {
+        match chars.peek() {
+            Some(c) if c.is_ascii_hexdigit() => digits.push(*c),
+            _ => break,
+        }
+        chars.next();
+    }
+    u32::from_str_radix(&digits, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| snippet_error("bad escape in $' quote"))
+}
+
+fn ansi_c_escape(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<char, JWTError> {
+    Ok(match chars.next() {
+        Some('n') => '\n',
+        Some('r') => '\r',
+        Some('t') => '\t',
+        Some('x') => hex_digits(chars, 2)?,
+        Some('u') => hex_digits(chars, 4)?,
+        Some('U') => hex_digits(chars, 8)?,
+        Some(c) => c,
+        None => return Err(snippet_error("unterminated $' quote")),
+    })
+}
+
+/// Splits a cmd.exe command line: `^` escapes the next character (a `^` at
+/// the end of a line continues it), then the C runtime splits words on
+/// `"` quotes, with `\"` for a literal quote.
+fn cmd_words(text: &str) -> Result<Vec<String>, JWTError> {
+    let mut line = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '^' => match chars.next() {
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                Some('\n') => (),
+                Some(c) => line.push(c),
+                None => (),
+            },
+            c => line.push(c),
+        }
+    }
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'"') => {
+                chars.next();
+                word.get_or_insert_with(String::new).push('"');
+            }
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quoted {
+        return Err(snippet_error("unterminated \" quote"));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// curl options whose value is not a header, and so is skipped.
+const CURL_VALUE_OPTIONS: &[&str] = &[
+    "-X",
+    "--request",
+    "-d",
+    "--data",
+    "--data-raw",
+    "--data-binary",
+    "--data-urlencode",
+    "-F",
+    "--form",
+    "-u",
+    "--user",
+    "-A",
+    "--user-agent",
+    "-e",
+    "--referer",
+    "-o",
+    "--output",
+];
+
+fn curl_request(words: &[String]) -> Result<CopiedRequest, JWTError> {
+    let mut url = None;
+    let mut headers = Vec::new();
+    let mut args = words.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let arg = arg.as_str();
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| snippet_error(&format!("{} needs a value", arg)))
+        };
+        match arg {
+            "-H" | "--header" => {
+                let header = value()?;
+                if let Some((name, value)) = header.split_once(':') {
+                    headers.push((name.trim().to_string(), value.trim().to_string()));
+                }
+            }
+            "-b" | "--cookie" => headers.push(("Cookie".to_string(), value()?)),
+            "--url" => url = Some(value()?),
+            _ if CURL_VALUE_OPTIONS.contains(&arg) => {
+                value()?;
+            }
+            _ if arg.starts_with('-') => (),
+            _ => {
+                url.get_or_insert_with(|| arg.to_string());
+            }
+        }
+    }
+    Ok(CopiedRequest {
+        kind: "curl",
+        url: url.ok_or_else(|| snippet_error("curl command has no URL"))?,
+        headers,
+    })
+}
+
+/// The length of the JavaScript string literal at the start of `text`.
+fn string_literal_len(text: &str) -> Option<usize> {
+    let quote = text
+        .chars()
+        .next()
+        .filter(|c| matches!(c, '"' | '\'' | '`'))?;
+    let mut escaped = false;
+    for (at, c) in text.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == quote => return Some(at + 1),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// The length of the balanced `{ ... }` at the start of `text`.
+fn object_len(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut at = 0;
+    while let Some(c) = text.get(at..)?.chars().next() {
+        match c {
+            '"' | '\'' | '`' => {
+                at += string_literal_len(text.get(at..)?)?;
+                continue;
+            }
+            '{' => depth += 1,
+            '}' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some(at + 1);
+                }
+            }
+            _ => (),
+        }
+        at += c.len_utf8();
+    }
+    None
+}
+
+/// The value of a JavaScript string literal. Double-quoted ones are JSON;
+/// the others are rewritten as JSON first.
+fn string_value(literal: &str) -> Result<String, JWTError> {
+    if literal.starts_with('"') {
+        return Ok(serde_json::from_str(literal)?);
+    }
+    let inner = literal
+        .get(1..literal.len().saturating_sub(1))
+        .unwrap_or_default();
+    let json = format!("\"{}\"", inner.replace("\\'", "'").replace('"', "\\\""));
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// `fetch("url", { "headers": { ... }, ... })`, after the `fetch(`. The
+/// options object is JSON in both browsers' output.
+fn fetch_request(call: &str) -> Result<CopiedRequest, JWTError> {
+    let call = call.trim_start();
+    let len = string_literal_len(call).ok_or_else(|| snippet_error("fetch has no URL"))?;
+    let url = string_value(call.get(..len).unwrap_or_default())?;
+    let rest = call.get(len..).unwrap_or_default().trim_start();
+    let mut headers = Vec::new();
+    if let Some(options) = rest.strip_prefix(',').map(str::trim_start) {
+        let len = object_len(options).ok_or_else(|| snippet_error("unterminated fetch options"))?;
+        let options: Value = serde_json::from_str(options.get(..len).unwrap_or_default())?;
+        if let Some(map) = options.get("headers").and_then(Value::as_object) {
+            for (name, value) in map {
+                if let Some(value) = value.as_str() {
+                    headers.push((name.clone(), value.to_string()));
+                }
+            }
+        }
+    }
+    Ok(CopiedRequest {
+        kind: "fetch",
+        url,
+        headers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SAMPLE_TOKEN;
+
+    fn copied(snippet: &str) -> CopiedRequest {
+        parse_copied(snippet).unwrap().unwrap()
+    }
+
+    #[test]
+    fn parses_devtools_snippets_test() {
+        let t = SAMPLE_TOKEN;
+        let bash = format!(
+            "curl 'https://api.example/me' \\\n  -H 'accept: */*' \\\n  \
+             -H 'authorization: Bearer {t}' \\\n  -b 'theme=dark; id={t}' \\\n  \
+             --data-raw $'{{\"note\":\"it\\'s\"}}' \\\n  --compressed",
+        );
+        let request = copied(&bash);
+        assert_eq!(request.url, "https://api.example/me");
+        let tokens = request.tokens();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].location, "authorization header");
+        assert_eq!(tokens[1].location, "cookie id");
+        assert!(tokens.iter().all(|f| f.token == t));
+
+        let cmd = format!(
+            "curl ^\"https://api.example/me?a=1^&b=2^\" ^\n  -H ^\"authorization: Bearer {t}^\" ^\n  \
+             --data-raw ^\"^{{\\^\"a\\^\":1^}}^\"",
+        );
+        let request = copied(&cmd);
+        assert_eq!(request.url, "https://api.example/me?a=1&b=2");
+        assert_eq!(request.tokens()[0].token, t);
+
+        let fetch = format!(
+            "fetch(\"https://api.example/me\", {{\n  \"headers\": {{\n    \"accept\": \"*/*\",\n    \
+             \"authorization\": \"Bearer {t}\",\n    \"cookie\": \"id={t}\"\n  }},\n  \
+             \"body\": \"{{\\\"x\\\":\\\"}}\\\"}}\",\n  \"method\": \"POST\"\n}});",
+        );
+        let request = copied(&fetch);
+        assert_eq!(request.kind, "fetch");
+        assert_eq!(request.tokens().len(), 2);
+
+        let firefox = format!(
+            "await fetch(\"https://api.example/me\", {{ \"credentials\": \"include\", \
+             \"headers\": {{ \"Authorization\": \"Bearer {t}\" }}, \"mode\": \"cors\" }});",
+        );
+        assert_eq!(
+            copied(&firefox).tokens()[0].location,
+            "authorization header"
+        );
+
+        assert!(parse_copied(t).is_none());
+        assert!(parse_copied("curl 'https://a.example").unwrap().is_err());
+    }
+
+    #[test]
+    fn malformed_snippets_test() {
+        let error_of =
+            |snippet: &str| error_message!(InvalidArgumentError, parse_copied(snippet).unwrap());
+        for (snippet, error) in [
+            ("curl 'https://a.example", "unterminated ' quote"),
+            ("curl $'https://a.example", "unterminated $' quote"),
+            ("curl $'https://a.example\\", "unterminated $' quote"),
+            ("curl $'\\xzz' https://a.example", "bad escape in $' quote"),
+            (
+                "curl $'\\ud800' https://a.example",
+                "bad escape in $' quote",
+            ),
+            ("curl \"https://a.example", "unterminated \" quote"),
+            ("curl \"https://a.example\\", "unterminated \" quote"),
+            ("curl ^\"https://a.example", "unterminated \" quote"),
+            ("curl https://a.example -H", "-H needs a value"),
+            ("curl -H 'accept: */*'", "curl command has no URL"),
+            ("fetch(url)", "fetch has no URL"),
+            (
+                "fetch(\"https://a.example\", {\"headers\": {",
+                "unterminated fetch options",
+            ),
+        ] {
+            assert_eq!(
+                error_of(snippet),
+                format!("copied request: {}", error),
+                "{}",
+                snippet
+            );
+        }
+    }
+}
@@ -0,0 +1,321 @@
+//! `--dialect`: tokens that are JWT-like but not JWTs, such as legacy
+//! formats with a different base64 alphabet or an extra metadata segment.
+//!
+//! A dialect is a YAML file:
+//!
+//! ```yaml
+//! separator: "."          # the default
+//! alphabet: url           # url (the default), standard, or 64 characters
+//! segments:
+//!   - name: header
+//!     json: true
+//!   - name: payload
+//!     json: true
+//!   - name: signature
+//!   - name: meta
+//!     json: true
+//!     alphabet: standard
+//! ```
+//!
+//! Segments named `header` and `payload` become the token's header and
+//! payload and must be JSON; `signature`, if present, is its signature
+//! bytes. Any other segment is returned alongside, parsed when it is marked
+//! `json`, otherwise as text (or hex when it is not UTF-8). Padding is
+//! optional in every segment.
+
+use crate::{JWTError, JWToken};
+use serde_json::Value;
+
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// A base64 alphabet, as the standard character each byte stands for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alphabet {
+    to_standard: Vec<(char, u8)>,
+}
+
+impl Alphabet {
+    fn named(name: &str) -> Result<Alphabet, JWTError> {
+        let chars: Vec<char> = match name {
+            "url" => URL.iter().map(|b| *b as char).collect(),
+            "standard" => STANDARD.iter().map(|b| *b as char).collect(),
+            custom => custom.chars().collect(),
+        };
+        if chars.len() != 64 {
+            return Err(JWTError::ConfigError(
+                format!(
+                "`{}` is not a base64 alphabet; expected url, standard or 64 distinct characters",
+                name
+            )
+                .into(),
+            ));
+        }
+        let mut to_standard: Vec<(char, u8)> = Vec::with_capacity(64);
+        for (c, standard) in chars.into_iter().zip(STANDARD.iter()) {
+            if c == '=' || to_standard.iter().any(|(seen, _)| *seen == c) {
+                return Err(JWTError::ConfigError(
+                    format!(
+                        "base64 alphabet repeats `{}` or uses the padding character",
+                        c
+                    )
+                    .into(),
+                ));
+            }
+            to_standard.push((c, *standard));
+        }
+        Ok(Alphabet { to_standard })
+    }
+
+    fn contains(&self, c: char) -> bool {
+        self.to_standard.iter().any(|(known, _)| *known == c)
+    }
+
+    fn decode(&self, segment: &str) -> Result<Vec<u8>, JWTError> {
+        let standard = segment
+            .trim_end_matches('=')
+            .chars()
+            .map(|c| {
+                self.to_standard
+                    .iter()
+                    .find(|(known, _)| *known == c)
+                    .map(|(_, standard)| *standard)
+                    .ok_or_else(|| {
+                        JWTError::InvalidArgumentError(
+                            format!("`{}` is not in the dialect's base64 alphabet", c).into(),
+                        )
+                    })
+            })
+            .collect::<Result<Vec<u8>, JWTError>>()?;
+        Ok(base64::decode_config(standard, base64::STANDARD_NO_PAD)?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub name: String,
+    pub json: bool,
+    alphabet: Alphabet,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dialect {
+    pub separator: char,
+    pub segments: Vec<Segment>,
+}
+
+impl Dialect {
+    /// Reads a parsed dialect file.
+    pub fn from_config(config: &Value) -> Result<Dialect, JWTError> {
+        let invalid = |what: String| JWTError::ConfigError(format!("dialect: {}", what).into());
+        let text = |value: &Value, what: &str| {
+            value
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| invalid(format!("`{}` must be a string", what)))
+        };
+        let separator = match config.get("separator") {
+            None => '.',
+            Some(value) => {
+                let separator = text(value, "separator")?;
+                let mut chars = separator.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => return Err(invalid("`separator` must be one character".to_string())),
+                }
+            }
+        };
+        let alphabet = match config.get("alphabet") {
+            None => Alphabet::named("url")?,
+            Some(value) => Alphabet::named(&text(value, "alphabet")?)?,
+        };
+        let segments = config
+            .get("segments")
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid("needs a `segments` list".to_string()))?
+            .iter()
+            .map(|segment| {
+                let name = text(segment.get("name").unwrap_or(&Value::Null), "name")?;
+                let alphabet = match segment.get("alphabet") {
+                    None => alphabet.clone(),
+                    Some(value) => Alphabet::named(&text(value, "alphabet")?)?,
+                };
+                if alphabet.contains(separator) {
+                    return Err(invalid(format!(
+                        "segment `{}`: the separator `{}` is in its alphabet",
+                        name, separator
+                    )));
+                }
+                Ok(Segment {
+                    json: segment
+                        .get("json")
+                        .and_then(Value::as_bool)
+                        .unwrap_or(false),
+                    name,
+                    alphabet,
+                })
+            })
+            .collect::<Result<Vec<Segment>, JWTError>>()?;
+        for required in ["header", "payload"] {
+            if !segments.iter().any(|s| s.name == required && s.json) {
+                return Err(invalid(format!(
+                    "needs a `{}` segment marked `json: true`",
+                    required
+                )));
+            }
+        }
+        Ok(Dialect {
+            separator,
+            segments,
+        })
+    }
+
+    /// Splits and decodes `raw`, returning the token and the segments that
+    /// are not its header, payload or signature, in order.
+    pub fn parse(&self, raw: &str) -> Result<(JWToken, Vec<(String, Value)>), JWTError> {
+        let parts: Vec<&str> = raw.split(self.separator).collect();
+        if parts.len() < self.segments.len() {
+            return Err(JWTError::MissingPartError);
+        }
+        if parts.len() > self.segments.len() {
+            return Err(JWTError::UnknownPartError);
+        }
+        let mut token = JWToken {
+            header: Value::Null,
+            payload: Value::Null,
+            signature: Vec::new(),
+        };
+        let mut extra = Vec::new();
+        for (segment, part) in self.segments.iter().zip(parts) {
+            let bytes = segment.alphabet.decode(part)?;
+            let value = if segment.json {
+                serde_json::from_str(std::str::from_utf8(&bytes)?)?
+            } else {
+                match std::str::from_utf8(&bytes) {
+                    Ok(text) => Value::String(text.to_string()),
+                    Err(_) => Value::String(bytes.iter().map(|b| format!("{:02x}", b)).collect()),
+                }
+            };
+            match segment.name.as_str() {
+                "header" => token.header = value,
+                "payload" => token.payload = value,
+                "signature" => token.signature = bytes,
+                name => extra.push((name.to_string(), value)),
+            }
+        }
+        Ok((token, extra))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_custom_dialects_test() {
+        let config = crate::yaml::parse(
+            "separator: \"~\"\n\
+             alphabet: standard\n\
+             segments:\n\
+             \x20 - name: meta\n\
+             \x20   json: true\n\
+             \x20 - name: header\n\
+             \x20   json: true\n\
+             \x20 - name: payload\n\
+             \x20   json: true\n\
+             \x20 - name: signature\n",
+        )
+        .unwrap();
+        let dialect = Dialect::from_config(&config).unwrap();
+        let b64 = |text: &str| base64::encode(text);
+        let raw = format!(
+            "{}~{}~{}~{}",
+            b64(r#"{"v":2}"#),
+            b64(r#"{"alg":"HS256"}"#),
+            b64(r#"{"sub":"~~~"}"#),
+            b64("\u{fb}\u{ff}")
+        );
+        assert!(raw.contains('+') || raw.contains('/'));
+        let (token, extra) = dialect.parse(&raw).unwrap();
+        assert_eq!(token.header, json!({"alg": "HS256"}));
+        assert_eq!(token.payload, json!({"sub": "~~~"}));
+        assert_eq!(token.signature, "\u{fb}\u{ff}".as_bytes());
+        assert_eq!(extra, vec![("meta".to_string(), json!({"v": 2}))]);
+        assert!(matches!(
+            dialect.parse("a~b"),
+            Err(JWTError::MissingPartError)
+        ));
+
+        // a shuffled alphabet: each character stands for the standard one
+        let shuffled: String = STANDARD.iter().rev().map(|b| *b as char).collect();
+        let config = json!({"alphabet": shuffled, "segments": [
+            {"name": "header", "json": true}, {"name": "payload", "json": true}]});
+        let dialect = Dialect::from_config(&config).unwrap();
+        let translate = |text: &str| -> String {
+            base64::encode_config(text, base64::STANDARD_NO_PAD)
+                .bytes()
+                .map(|b| STANDARD[63 - STANDARD.iter().position(|s| *s == b).unwrap()] as char)
+                .collect()
+        };
+        let raw = format!("{}.{}", translate(r#"{"alg":"none"}"#), translate("{}"));
+        assert_eq!(
+            dialect.parse(&raw).unwrap().0.header,
+            json!({"alg": "none"})
+        );
+
+        for bad in [
+            json!({"segments": [{"name": "payload", "json": true}]}),
+            json!({"alphabet": "abc", "segments": []}),
+            json!({"separator": "-", "segments": [{"name": "header", "json": true}]}),
+        ] {
+            assert!(Dialect::from_config(&bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_dialects_test() {
+        let error_of = |config: Value| error_message!(ConfigError, Dialect::from_config(&config));
+        let both = json!([{"name": "header", "json": true}, {"name": "payload", "json": true}]);
+        assert_eq!(
+            error_of(json!({"alphabet": "abc", "segments": both})),
+            "`abc` is not a base64 alphabet; expected url, standard or 64 distinct characters"
+        );
+        let repeated: String = "a".repeat(64);
+        assert_eq!(
+            error_of(json!({"alphabet": repeated, "segments": both})),
+            "base64 alphabet repeats `a` or uses the padding character"
+        );
+        assert_eq!(
+            error_of(json!({"separator": 1, "segments": both})),
+            "dialect: `separator` must be a string"
+        );
+        assert_eq!(
+            error_of(json!({"separator": "::", "segments": both})),
+            "dialect: `separator` must be one character"
+        );
+        assert_eq!(error_of(json!({})), "dialect: needs a `segments` list");
+        assert_eq!(
+            error_of(json!({"segments": [{"json": true}]})),
+            "dialect: `name` must be a string"
+        );
+        assert_eq!(
+            error_of(json!({"separator": "-", "segments": both})),
+            "dialect: segment `header`: the separator `-` is in its alphabet"
+        );
+        assert_eq!(
+            error_of(json!({"segments": [{"name": "header", "json": true}, {"name": "payload"}]})),
+            "dialect: needs a `payload` segment marked `json: true`"
+        );
+
+        let dialect = Dialect::from_config(&json!({"segments": both})).unwrap();
+        assert!(matches!(
+            dialect.parse("e30.e30.e30"),
+            Err(JWTError::UnknownPartError)
+        ));
+        assert!(matches!(
+            dialect.parse("e30.e3+"),
+            Err(JWTError::InvalidArgumentError(e)) if e == "`+` is not in the dialect's base64 alphabet"
+        ));
+    }
+}
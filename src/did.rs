@@ -0,0 +1,369 @@
+//! `--did`: keys for tokens whose `iss` is a DID, as verifiable-credential
+//! JWTs have.
+//!
+//! A `did:key` carries its key in the identifier itself: a multibase
+//! (base58btc, `z`) multikey. A `did:web` names a DID document
+//! at `https://{host}/.well-known/did.json`, or `https://{host}/{path}/did.json`
+//! when the identifier has a path, which is fetched unless it is given as
+//! `--did-document`. Its verification methods are read from
+//! `publicKeyJwk` or `publicKeyMultibase`, limited to the `assertionMethod`
+//! ones when it lists any. A token's `kid`, such as `did:web:example.com#key-1`
+//! or `#key-1`, picks one method.
+
+use crate::http::Client;
+use crate::jwks::jwk_to_key;
+use crate::keys::VerifyingKey;
+use crate::multikey::multikey;
+use crate::{ErrorMessage, JWTError, JWToken};
+use serde_json::Value;
+
+/// The DID a token names as its issuer, and the keys in its document (or,
+/// for did:key, the key the DID itself is) that may have signed it.
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub did: String,
+    pub keys: Vec<VerifyingKey>,
+    /// Where the document came from and which methods were used or skipped,
+    /// printed as the `did:` lines.
+    pub steps: Vec<String>,
+}
+
+fn did_error(message: impl Into<ErrorMessage>) -> JWTError {
+    JWTError::DidError(message.into())
+}
+
+/// Where a `did:web` document is published.
+pub fn web_document_url(did: &str) -> Result<String, JWTError> {
+    let id = did
+        .strip_prefix("did:web:")
+        .filter(|id| !id.is_empty())
+        .ok_or_else(|| did_error(format!("`{}` is not a did:web", did)))?;
+    let mut parts = id.split(':');
+    let host = parts.next().unwrap_or_default().replace("%3A", ":");
+    let path: Vec<&str> = parts.collect();
+    if path.is_empty() {
+        Ok(format!("https://{}/.well-known/did.json", host))
+    } else {
+        Ok(format!("https://{}/{}/did.json", host, path.join("/")))
+    }
+}
+
+/// The verification methods of `document` a token may be signed with:
+/// `(id, method)`, ids made absolute.
+fn assertion_methods<'a>(did: &str, document: &'a Value) -> Vec<(String, &'a Value)> {
+    let absolute = |id: &str| {
+        if id.starts_with('#') {
+            format!("{}{}", did, id)
+        } else {
+            id.to_string()
+        }
+    };
+    let list = |name: &str| document.get(name).and_then(Value::as_array);
+    let declared: Vec<(String, &Value)> = list("verificationMethod")
+        .into_iter()
+        .flatten()
+        .filter_map(|method| Some((absolute(method.get("id")?.as_str()?), method)))
+        .collect();
+    let assertion = match list("assertionMethod") {
+        Some(assertion) => assertion,
+        None => return declared,
+    };
+    assertion
+        .iter()
+        .filter_map(|entry| match entry {
+            Value::String(id) => {
+                let id = absolute(id);
+                declared
+                    .iter()
+                    .find(|(declared, _)| *declared == id)
+                    .map(|(_, method)| (id, *method))
+            }
+            method => Some((absolute(method.get("id")?.as_str()?), method)),
+        })
+        .collect()
+}
+
+fn method_key(method: &Value) -> Result<VerifyingKey, JWTError> {
+    if let Some(jwk) = method.get("publicKeyJwk") {
+        return jwk_to_key(jwk);
+    }
+    match method.get("publicKeyMultibase").and_then(Value::as_str) {
+        Some(multibase) => multikey(multibase),
+        None => Err(did_error(
+            "no publicKeyJwk or publicKeyMultibase (other encodings are not supported)",
+        )),
+    }
+}
+
+#[cfg(feature = "net")]
+fn fetch_document(client: &Client, url: &str) -> Result<Value, JWTError> {
+    let body = client.get(url)?;
+    serde_json::from_slice(&body).map_err(|e| {
+        did_error(ErrorMessage::caused_by(
+            format!("{} is not a DID document: {}", url, e),
+            e,
+        ))
+    })
+}
+
+#[cfg(not(feature = "net"))]
+fn fetch_document(_: &Client, url: &str) -> Result<Value, JWTError> {
+    Err(did_error(format!(
+        "this build cannot fetch {} without the `net` feature; download it and pass it as --did-document",
+        url
+    )))
+}
+
+/// The keys for `token`'s DID issuer. `document` is the `did:web` document,
+/// fetched with `client` when it is `None`; a `did:key` doesn't need one.
+pub fn resolve(
+    token: &JWToken,
+    document: Option<&Value>,
+    client: &Client,
+) -> Result<Resolution, JWTError> {
+    let did = token
+        .payload
+        .get("iss")
+        .and_then(Value::as_str)
+        .ok_or_else(|| did_error("the token has no iss to resolve"))?
+        .to_string();
+    let kid = token.header.get("kid").and_then(Value::as_str);
+    if !did.starts_with("did:") || did.contains('#') {
+        return Err(did_error(format!("iss `{}` is not a DID", did)));
+    }
+    // a kid naming another DID's key can't be resolved from this one
+    if let Some(kid) = kid.filter(|kid| !kid.starts_with('#') && kid.starts_with("did:")) {
+        if kid.split('#').next() != Some(did.as_str()) {
+            return Err(did_error(format!(
+                "kid `{}` is not a key of the issuer {}",
+                kid, did
+            )));
+        }
+    }
+    let mut steps = Vec::new();
+    if let Some(id) = did.strip_prefix("did:key:") {
+        let key = multikey(id)?;
+        steps.push(format!("{} is a {}", did, key.describe()));
+        return Ok(Resolution {
+            did,
+            keys: vec![key],
+            steps,
+        });
+    }
+    if !did.starts_with("did:web:") {
+        let method_name = did.split(':').nth(1).unwrap_or_default();
+        return Err(did_error(format!(
+            "did:{} is not supported; expected did:key or did:web",
+            method_name
+        )));
+    }
+    let url = web_document_url(&did)?;
+    let fetched;
+    let document = match document {
+        Some(document) => document,
+        None => {
+            fetched = fetch_document(client, &url)?;
+            &fetched
+        }
+    };
+    if document.get("id").and_then(Value::as_str) != Some(did.as_str()) {
+        return Err(did_error(format!(
+            "the DID document is for {}, not {}",
+            document
+                .get("id")
+                .and_then(Value::as_str)
+                .unwrap_or("(no id)"),
+            did
+        )));
+    }
+    steps.push(format!("DID document for {} ({})", did, url));
+    let wanted = kid.map(|kid| {
+        if kid.starts_with('#') {
+            format!("{}{}", did, kid)
+        } else {
+            kid.to_string()
+        }
+    });
+    let mut keys = Vec::new();
+    for (id, method) in assertion_methods(&did, document) {
+        if wanted.as_ref().is_some_and(|wanted| *wanted != id) {
+            continue;
+        }
+        match method_key(method) {
+            Ok(key) => {
+                steps.push(format!("{}: {}", id, key.describe()));
+                keys.push(key);
+            }
+            Err(e) => steps.push(format!("{}: skipped, {}", id, e)),
+        }
+    }
+    if keys.is_empty() {
+        return Err(did_error(match wanted {
+            Some(kid) => format!("the DID document has no usable assertion key {}", kid),
+            None => "the DID document has no usable assertion key".to_string(),
+        }));
+    }
+    Ok(Resolution { did, keys, steps })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::parse_signing_key_pem;
+    use crate::parse;
+    use crate::sign::sign;
+    use crate::verify::verify;
+    use serde_json::json;
+
+    /// The secp256k1 key of the `keys` and `sign` tests, as a did:key.
+    const K1_DID: &str = "did:key:zQ3shvUUujektmecwVGbXZ2vbzm2rKKKeg9RqQRvHseBaD3rq";
+    const K1_SCALAR: &str = "79a8079d5d7ffd18773cc0cf9eb7a430982aa70c758171a88e041189410af9ae";
+
+    fn token(header: Value, iss: &str) -> (String, JWToken) {
+        let key = parse_signing_key_pem(K1_SCALAR).unwrap();
+        let raw = sign(&header, &json!({"iss": iss, "sub": "holder"}), &key).unwrap();
+        let token = parse(&raw).unwrap();
+        (raw, token)
+    }
+
+    #[test]
+    fn web_document_url_test() {
+        assert_eq!(
+            web_document_url("did:web:example.com").unwrap(),
+            "https://example.com/.well-known/did.json"
+        );
+        assert_eq!(
+            web_document_url("did:web:localhost%3A8443:user:alice").unwrap(),
+            "https://localhost:8443/user/alice/did.json"
+        );
+        for bad in ["did:key:z", "did:web:", "did:webby:example.com"] {
+            assert!(matches!(
+                web_document_url(bad),
+                Err(JWTError::DidError(e)) if e == format!("`{}` is not a did:web", bad)
+            ));
+        }
+    }
+
+    #[test]
+    fn did_key_test() {
+        let (raw, token) = token(
+            json!({"alg": "ES256K", "kid": format!("{}#k", K1_DID)}),
+            K1_DID,
+        );
+        let resolution = resolve(&token, None, &Client::default()).unwrap();
+        assert_eq!(
+            resolution.steps,
+            [format!("{} is a EC secp256k1 public key", K1_DID)]
+        );
+        verify(&raw, &token, &resolution.keys[0]).unwrap();
+
+        let (_, token) =
+            token_with_kid("did:key:zDnaerDaTF5BXEavCrfRZEk316dpbLsfPDZ3WJ5hRTPFU2169#k");
+        assert!(matches!(
+            resolve(&token, None, &Client::default()),
+            Err(JWTError::DidError(e))
+                if e == format!(
+                    "kid `did:key:zDnaerDaTF5BXEavCrfRZEk316dpbLsfPDZ3WJ5hRTPFU2169#k` is not a key of the issuer {}",
+                    K1_DID
+                )
+        ));
+        let anonymous = JWToken {
+            payload: json!({"sub": "holder"}),
+            ..token.clone()
+        };
+        assert!(matches!(
+            resolve(&anonymous, None, &Client::default()),
+            Err(JWTError::DidError(e)) if e == "the token has no iss to resolve"
+        ));
+        let (_, token) = self::token(json!({"alg": "ES256K"}), "https://idp.example");
+        assert!(matches!(
+            resolve(&token, None, &Client::default()),
+            Err(JWTError::DidError(e)) if e == "iss `https://idp.example` is not a DID"
+        ));
+        let (_, token) = self::token(json!({"alg": "ES256K"}), "did:ion:abc");
+        assert!(matches!(
+            resolve(&token, None, &Client::default()),
+            Err(JWTError::DidError(e))
+                if e == "did:ion is not supported; expected did:key or did:web"
+        ));
+    }
+
+    fn token_with_kid(kid: &str) -> (String, JWToken) {
+        token(json!({"alg": "ES256K", "kid": kid}), K1_DID)
+    }
+
+    #[test]
+    fn did_web_test() {
+        let did = "did:web:issuer.example";
+        let document = json!({
+            "id": did,
+            "verificationMethod": [
+                {"id": "#old", "type": "Multikey", "publicKeyMultibase": "zDnaerDaTF5BXEavCrfRZEk316dpbLsfPDZ3WJ5hRTPFU2169"},
+                {"id": format!("{}#ed", did), "type": "Multikey", "publicKeyMultibase": "z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK"},
+                {"id": format!("{}#auth", did), "type": "JsonWebKey2020", "publicKeyJwk": {"kty": "oct", "k": "c2VjcmV0"}},
+            ],
+            "assertionMethod": [
+                "#old",
+                format!("{}#ed", did),
+                {"id": "#k1", "type": "Multikey", "publicKeyMultibase": &K1_DID["did:key:".len()..]},
+                {"id": "#kex", "type": "JsonWebKey2020", "publicKeyJwk": {"kty": "OKP", "crv": "X25519", "x": "AA"}},
+            ],
+        });
+        let (raw, token) = token(json!({"alg": "ES256K", "kid": "#k1"}), did);
+        let resolution = resolve(&token, Some(&document), &Client::default()).unwrap();
+        assert_eq!(
+            resolution.steps,
+            [
+                "DID document for did:web:issuer.example (https://issuer.example/.well-known/did.json)",
+                "did:web:issuer.example#k1: EC secp256k1 public key",
+            ]
+        );
+        verify(&raw, &token, &resolution.keys[0]).unwrap();
+
+        // without a kid every assertion key is a candidate; #auth is not
+        // an assertion method
+        let (_, token) = self::token(json!({"alg": "ES256K"}), did);
+        let resolution = resolve(&token, Some(&document), &Client::default()).unwrap();
+        assert_eq!(resolution.keys.len(), 3);
+        assert_eq!(
+            resolution.steps[2..],
+            [
+                "did:web:issuer.example#ed: Ed25519 public key",
+                "did:web:issuer.example#k1: EC secp256k1 public key",
+                "did:web:issuer.example#kex: skipped, Key error: unsupported JWK curve `X25519`; expected Ed25519",
+            ]
+        );
+
+        let (_, token) = self::token(json!({"alg": "ES256K", "kid": "#auth"}), did);
+        assert!(matches!(
+            resolve(&token, Some(&document), &Client::default()),
+            Err(JWTError::DidError(e))
+                if e == "the DID document has no usable assertion key did:web:issuer.example#auth"
+        ));
+        // nothing listens on port 1
+        let (_, unreachable) = self::token(json!({"alg": "ES256K"}), "did:web:127.0.0.1%3A1");
+        let url = "https://127.0.0.1:1/.well-known/did.json";
+        #[cfg(feature = "net")]
+        assert!(matches!(
+            resolve(&unreachable, None, &Client::default()),
+            Err(JWTError::HttpError(e)) if e.starts_with(&format!("{}: ", url))
+        ));
+        #[cfg(not(feature = "net"))]
+        assert!(matches!(
+            resolve(&unreachable, None, &Client::default()),
+            Err(JWTError::DidError(e))
+                if e == format!("this build cannot fetch {} without the `net` feature; download it and pass it as --did-document", url)
+        ));
+        assert!(matches!(
+            method_key(&json!({"id": "#x", "publicKeyBase58": "abc"})),
+            Err(JWTError::DidError(e))
+                if e == "no publicKeyJwk or publicKeyMultibase (other encodings are not supported)"
+        ));
+        let other = json!({"id": "did:web:elsewhere.example"});
+        assert!(matches!(
+            resolve(&token, Some(&other), &Client::default()),
+            Err(JWTError::DidError(e))
+                if e == "the DID document is for did:web:elsewhere.example, not did:web:issuer.example"
+        ));
+    }
+}
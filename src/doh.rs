@@ -0,0 +1,165 @@
+//! `--doh-url`: DNS messages in the RFC 8484 (DNS-over-HTTPS) format, for
+//! sites where tools must not use the system resolver.
+//!
+//! Only what resolving a host needs: an `A` or `AAAA` query, and the
+//! addresses in the answer. CNAMEs are followed by the resolver, which
+//! puts the records they lead to in the same answer.
+
+use crate::JWTError;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+fn doh_error(message: String) -> JWTError {
+    JWTError::HttpError(message.into())
+}
+
+/// The query message for `host`'s records of `qtype`. The ID is 0, as RFC
+/// 8484 recommends, so the GET request is cacheable.
+pub fn query(host: &str, qtype: u16) -> Result<Vec<u8>, JWTError> {
+    // ID, flags with recursion desired, one question
+    let mut message = vec![0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(doh_error(format!("{}: not a valid host name", host)));
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&qtype.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(message)
+}
+
+/// The `GET` URL for `message` at the resolver `doh_url`.
+pub fn query_url(doh_url: &str, message: &[u8]) -> String {
+    let separator = if doh_url.contains('?') { '&' } else { '?' };
+    format!(
+        "{}{}dns={}",
+        doh_url,
+        separator,
+        base64::encode_config(message, base64::URL_SAFE_NO_PAD)
+    )
+}
+
+fn u16_at(message: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([
+        *message.get(at)?,
+        *message.get(at + 1)?,
+    ]))
+}
+
+/// Where the name starting at `at` ends: after its zero label, or after a
+/// compression pointer, which ends a name wherever it points.
+fn skip_name(message: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let length = *message.get(at)?;
+        match length {
+            0 => return Some(at + 1),
+            _ if length & 0xC0 == 0xC0 => return Some(at + 2),
+            _ => at += 1 + length as usize,
+        }
+    }
+}
+
+/// The `A` and `AAAA` addresses of a response message.
+pub fn addresses(message: &[u8]) -> Result<Vec<IpAddr>, JWTError> {
+    let malformed = || doh_error("malformed DNS response".to_string());
+    let flags = u16_at(message, 2).ok_or_else(malformed)?;
+    match flags & 0x000F {
+        0 => {}
+        3 => return Err(doh_error("no such host (NXDOMAIN)".to_string())),
+        rcode => return Err(doh_error(format!("DNS error, RCODE {}", rcode))),
+    }
+    let questions = u16_at(message, 4).ok_or_else(malformed)?;
+    let answers = u16_at(message, 6).ok_or_else(malformed)?;
+    let mut at = 12;
+    for _ in 0..questions {
+        at = skip_name(message, at).ok_or_else(malformed)? + 4;
+    }
+    let mut found = Vec::new();
+    for _ in 0..answers {
+        at = skip_name(message, at).ok_or_else(malformed)?;
+        let rtype = u16_at(message, at).ok_or_else(malformed)?;
+        let length = u16_at(message, at + 8).ok_or_else(malformed)? as usize;
+        at += 10;
+        let data = message.get(at..at + length).ok_or_else(malformed)?;
+        match (rtype, data.len()) {
+            (TYPE_A, 4) => {
+                let mut octets = [0u8; 4];
+                octets.copy_from_slice(data);
+                found.push(IpAddr::V4(Ipv4Addr::from(octets)));
+            }
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                found.push(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            _ => {}
+        }
+        at += length;
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doh_message_test() {
+        let message = query("idp.example", TYPE_A).unwrap();
+        assert_eq!(
+            message,
+            b"\0\0\x01\0\0\x01\0\0\0\0\0\0\x03idp\x07example\0\0\x01\0\x01".to_vec()
+        );
+        assert_eq!(
+            query_url("http://127.0.0.1:8053/dns-query", &message),
+            "http://127.0.0.1:8053/dns-query?dns=AAABAAABAAAAAAAAA2lkcAdleGFtcGxlAAABAAE"
+        );
+        let long = format!("{}.example", "a".repeat(64));
+        for bad in ["idp..example", "", long.as_str()] {
+            assert!(matches!(
+                query(bad, TYPE_A),
+                Err(JWTError::HttpError(e)) if e == format!("{}: not a valid host name", bad)
+            ));
+        }
+
+        // the question, a CNAME to a name compressed against it, and an A
+        let mut response = message.clone();
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = 2;
+        response.extend_from_slice(b"\xc0\x0c\0\x05\0\x01\0\0\0\x3c\0\x06\x03www\xc0\x10");
+        response.extend_from_slice(b"\x03www\xc0\x10\0\x01\0\x01\0\0\0\x3c\0\x04\xc0\x00\x02\x07");
+        assert_eq!(
+            addresses(&response).unwrap(),
+            vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7))]
+        );
+        let mut nxdomain = message.clone();
+        nxdomain[3] = 0x83;
+        assert!(matches!(
+            addresses(&nxdomain),
+            Err(JWTError::HttpError(e)) if e == "no such host (NXDOMAIN)"
+        ));
+        let mut servfail = message.clone();
+        servfail[3] = 0x82;
+        assert!(matches!(
+            addresses(&servfail),
+            Err(JWTError::HttpError(e)) if e == "DNS error, RCODE 2"
+        ));
+        for truncated in [
+            &response[..response.len() - 2],
+            &response[..3],
+            &message[..20],
+        ] {
+            assert!(matches!(
+                addresses(truncated),
+                Err(JWTError::HttpError(e)) if e == "malformed DNS response"
+            ));
+        }
+    }
+}
@@ -0,0 +1,689 @@
+//! `--auth-request`: correlates a returned ID token with the authorization
+//! request that asked for it, since flow-level bugs (a dropped `nonce`, a
+//! `state` swapped between tabs, a request object that disagrees with its
+//! query) only show when the artifacts are read together.
+//!
+//! The request is an authorization URL, its query string, or a JSON object
+//! of its parameters. A `request` parameter holding a request object (RFC
+//! 9101) is read too, and its claims win over the query's. `--callback`
+//! adds the URL the user agent was redirected back to, with `state`,
+//! `code` and `iss` in its query or fragment.
+//!
+//! Checked are the `nonce` echo, `aud` against `client_id`, `s_hash` and
+//! `c_hash` against `state` and `code`, PKCE with `S256`, and that the
+//! callback arrived at the `redirect_uri` with the `state` sent.
+
+use crate::crypto::Hash;
+use crate::nested::percent_decode;
+use crate::profile::{requirement, Requirement};
+use crate::{audiences, parse, JWTError, JWToken};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+const OIDC: &str = "OpenID Connect Core";
+
+/// The parameters a request object and its query must agree on.
+const SHARED: [&str; 6] = [
+    "client_id",
+    "redirect_uri",
+    "response_type",
+    "scope",
+    "state",
+    "nonce",
+];
+
+fn decode_component(text: &str) -> String {
+    let text = text.replace('+', "%20");
+    percent_decode(&text).unwrap_or(text)
+}
+
+/// The parameters of a query string or fragment.
+fn query_params(query: &str) -> BTreeMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_component(name), decode_component(value))
+        })
+        .collect()
+}
+
+/// An authorization request, as sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthRequest {
+    pub params: BTreeMap<String, String>,
+    /// The claims of the `request` parameter's request object.
+    pub request_object: Option<Value>,
+}
+
+impl AuthRequest {
+    /// Reads an authorization URL, a query string or a JSON object.
+    pub fn parse(text: &str) -> Result<AuthRequest, JWTError> {
+        let text = text.trim();
+        let params = if text.starts_with('{') {
+            let object: serde_json::Map<String, Value> = serde_json::from_str(text)?;
+            object
+                .into_iter()
+                .map(|(name, value)| {
+                    let value = match value {
+                        Value::String(text) => text,
+                        other => other.to_string(),
+                    };
+                    (name, value)
+                })
+                .collect()
+        } else {
+            let query = text.split('#').next().unwrap_or_default();
+            let query = query.split_once('?').map_or(query, |(_, query)| query);
+            query_params(query)
+        };
+        if !params.contains_key("client_id") && !params.contains_key("request") {
+            return Err(JWTError::InvalidArgumentError(
+                "--auth-request has no client_id; expected an authorization URL, query string or JSON parameters"
+                    .to_string().into(),
+            ));
+        }
+        let request_object = match params.get("request") {
+            Some(jwt) => Some(parse(jwt)?.payload),
+            None => None,
+        };
+        Ok(AuthRequest {
+            params,
+            request_object,
+        })
+    }
+
+    /// A parameter, from the request object when it has one.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.request_object
+            .as_ref()
+            .and_then(|claims| claims.get(name))
+            .and_then(Value::as_str)
+            .or_else(|| self.params.get(name).map(String::as_str))
+    }
+}
+
+/// The redirect the user agent came back with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Callback {
+    /// The URL without its query and fragment.
+    pub base: String,
+    /// The query and fragment parameters.
+    pub params: BTreeMap<String, String>,
+}
+
+impl Callback {
+    pub fn parse(url: &str) -> Callback {
+        let url = url.trim();
+        let (rest, fragment) = url.split_once('#').unwrap_or((url, ""));
+        let (base, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let mut params = query_params(query);
+        params.extend(query_params(fragment));
+        Callback {
+            base: base.to_string(),
+            params,
+        }
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+}
+
+/// The left half of the hash of `value` the token's `alg` uses, base64url,
+/// as `at_hash`, `c_hash` and `s_hash` are made.
+pub fn half_hash(alg: &str, value: &str) -> Option<String> {
+    let hash = match alg {
+        "ES256K" => Hash::Sha256,
+        "EdDSA" => Hash::Sha512,
+        alg if alg.ends_with("256") => Hash::Sha256,
+        alg if alg.ends_with("384") => Hash::Sha384,
+        alg if alg.ends_with("512") => Hash::Sha512,
+        _ => return None,
+    };
+    let digest = hash.digest(value.as_bytes());
+    let half = digest.get(..digest.len() / 2)?;
+    Some(base64::encode_config(half, base64::URL_SAFE_NO_PAD))
+}
+
+/// Checks `claim`, a half hash, against the `value` it was made from.
+fn hash_matches(token: &JWToken, claim: &str, value: &str) -> Result<String, String> {
+    let alg = token
+        .header
+        .get("alg")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let expected =
+        half_hash(alg, value).ok_or_else(|| format!("no hash is defined for alg {}", alg))?;
+    match token.payload.get(claim).and_then(Value::as_str) {
+        Some(actual) if actual == expected => Ok(format!("{} is {}", claim, actual)),
+        Some(actual) => Err(format!("{} is {}, expected {}", claim, actual, expected)),
+        None => Err(format!("no {}", claim)),
+    }
+}
+
+/// Correlates `token`, an ID token, with the request and callback of its
+/// flow.
+pub fn correlate(
+    request: &AuthRequest,
+    callback: Option<&Callback>,
+    token: &JWToken,
+) -> Vec<Requirement> {
+    let payload = &token.payload;
+    let mut requirements = Vec::new();
+    if let Some(claims) = &request.request_object {
+        let disagreements: Vec<String> = SHARED
+            .iter()
+            .filter_map(|name| {
+                let inner = claims.get(name).and_then(Value::as_str)?;
+                let outer = request.params.get(*name)?;
+                Some(format!(
+                    "{} is {} in the request object, {} in the query",
+                    name, inner, outer
+                ))
+                .filter(|_| inner != outer)
+            })
+            .collect();
+        requirements.push(requirement(
+            "RFC 9101",
+            "5",
+            "the request object agrees with the query",
+            if disagreements.is_empty() {
+                Ok("no parameter differs".to_string())
+            } else {
+                Err(disagreements.join("; "))
+            },
+        ));
+    }
+    let token_nonce = payload.get("nonce").and_then(Value::as_str);
+    let response_type = request.param("response_type").unwrap_or_default();
+    requirements.push(requirement(
+        OIDC,
+        "3.1.3.7",
+        "nonce echoes the request's",
+        match (request.param("nonce"), token_nonce) {
+            (Some(sent), Some(returned)) if sent == returned => Ok(format!("nonce is {}", sent)),
+            (Some(sent), Some(returned)) => Err(format!(
+                "nonce is {}, the request sent {}: a replayed or cross-session token",
+                returned, sent
+            )),
+            (Some(sent), None) => Err(format!(
+                "the request sent nonce {}, the token has none",
+                sent
+            )),
+            (None, Some(returned)) => Err(format!(
+                "the token has nonce {}, the request sent none",
+                returned
+            )),
+            (None, None) if response_type.split(' ').any(|t| t == "id_token") => Err(format!(
+                "no nonce, which response_type {} requires",
+                response_type
+            )),
+            (None, None) => Ok("neither has a nonce".to_string()),
+        },
+    ));
+    requirements.push(requirement(
+        OIDC,
+        "3.1.3.7",
+        "aud includes the client_id",
+        match request.param("client_id") {
+            Some(client_id) if audiences(payload).contains(&client_id) => {
+                Ok(format!("aud includes {}", client_id))
+            }
+            Some(client_id) => Err(format!(
+                "aud is {}, not the requesting client {}",
+                payload
+                    .get("aud")
+                    .map_or("missing".to_string(), Value::to_string),
+                client_id
+            )),
+            None => Err("the request has no client_id".to_string()),
+        },
+    ));
+    if payload.get("s_hash").is_some() {
+        requirements.push(requirement(
+            "FAPI 1.0 Advanced",
+            "5.1",
+            "s_hash matches the request's state",
+            match request.param("state") {
+                Some(state) => hash_matches(token, "s_hash", state),
+                None => Err("the token has s_hash, but the request sent no state".to_string()),
+            },
+        ));
+    }
+    let code = callback.and_then(|callback| callback.param("code"));
+    if let (Some(code), Some(_)) = (code, payload.get("c_hash")) {
+        requirements.push(requirement(
+            OIDC,
+            "3.3.2.11",
+            "c_hash matches the returned code",
+            hash_matches(token, "c_hash", code),
+        ));
+    }
+    requirements.push(requirement(
+        "RFC 9700",
+        "2.1.1",
+        "PKCE with S256",
+        match (
+            request.param("code_challenge"),
+            request.param("code_challenge_method"),
+        ) {
+            (Some(_), Some("S256")) => Ok("code_challenge_method is S256".to_string()),
+            (Some(_), Some(method)) => Err(format!(
+                "code_challenge_method is {}: the challenge gives the verifier away",
+                method
+            )),
+            (Some(_), None) => Err("no code_challenge_method, which means plain".to_string()),
+            (None, _) => {
+                Err("no code_challenge: an intercepted code can be redeemed by anyone".to_string())
+            }
+        },
+    ));
+    let redirect_uri = request.param("redirect_uri");
+    requirements.push(requirement(
+        OIDC,
+        "3.1.2.1",
+        "redirect_uri present",
+        match redirect_uri {
+            Some(uri) => Ok(format!("redirect_uri is {}", uri)),
+            None => Err("no redirect_uri".to_string()),
+        },
+    ));
+    if let Some(callback) = callback {
+        requirements.extend(callback_requirements(request, callback, token));
+    }
+    requirements
+}
+
+fn callback_requirements(
+    request: &AuthRequest,
+    callback: &Callback,
+    token: &JWToken,
+) -> Vec<Requirement> {
+    let mut requirements = Vec::new();
+    if let Some(error) = callback.param("error") {
+        requirements.push(requirement(
+            "RFC 6749",
+            "4.1.2.1",
+            "the callback is not an error",
+            Err(match callback.param("error_description") {
+                Some(description) => format!("error {}: {}", error, description),
+                None => format!("error {}", error),
+            }),
+        ));
+    }
+    requirements.push(requirement(
+        "RFC 9700",
+        "4.1.3",
+        "the callback arrived at the redirect_uri",
+        match request.param("redirect_uri") {
+            Some(uri) if uri == callback.base => Ok(format!("both are {}", uri)),
+            Some(uri) => Err(format!(
+                "the callback is {}, the redirect_uri {}; redirect URIs must match exactly",
+                callback.base, uri
+            )),
+            None => Err("the request has no redirect_uri to compare".to_string()),
+        },
+    ));
+    requirements.push(requirement(
+        "RFC 6749",
+        "4.1.2",
+        "state returned unchanged",
+        match (request.param("state"), callback.param("state")) {
+            (Some(sent), Some(returned)) if sent == returned => Ok(format!("state is {}", sent)),
+            (Some(sent), Some(returned)) => Err(format!(
+                "state is {}, the request sent {}: this callback belongs to another request",
+                returned, sent
+            )),
+            (Some(sent), None) => Err(format!(
+                "the request sent state {}, the callback has none",
+                sent
+            )),
+            (None, _) => Err("the request sent no state, so CSRF goes unnoticed".to_string()),
+        },
+    ));
+    if let Some(iss) = callback.param("iss") {
+        requirements.push(requirement(
+            "RFC 9207",
+            "2.4",
+            "the callback's iss is the token's",
+            match token.payload.get("iss").and_then(Value::as_str) {
+                Some(token_iss) if token_iss == iss => Ok(format!("iss is {}", iss)),
+                Some(token_iss) => Err(format!(
+                    "the callback names {}, the token {}: a mix-up attack?",
+                    iss, token_iss
+                )),
+                None => Err("the token has no iss".to_string()),
+            },
+        ));
+    }
+    requirements
+}
+
+pub fn render_text(requirements: &[Requirement]) -> String {
+    let width = requirements
+        .iter()
+        .map(|r| r.source.len())
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    for requirement in requirements {
+        out.push_str(&format!(
+            "{} {:w$}  {}: {}\n",
+            if requirement.passed { "pass" } else { "FAIL" },
+            requirement.source,
+            requirement.description,
+            requirement.detail,
+            w = width
+        ));
+    }
+    let failed = requirements.iter().filter(|r| !r.passed).count();
+    out.push_str(&format!(
+        "flow: {} of {} check(s) met",
+        requirements.len() - failed,
+        requirements.len()
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const REQUEST: &str = "https://as.example/authorize?response_type=code&client_id=s6BhdRkqt3\
+        &redirect_uri=https%3A%2F%2Fclient.example%2Fcb&scope=openid+profile\
+        &state=af0ifjsldkj&nonce=n-0S6_WzA2Mj\
+        &code_challenge=E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM&code_challenge_method=S256";
+    const CODE: &str = "SplxlOBeZQQYbYS6WxSbIA";
+
+    fn id_token(payload: Value) -> JWToken {
+        JWToken {
+            header: json!({"alg": "RS256"}),
+            payload,
+            signature: Vec::new(),
+        }
+    }
+
+    fn good() -> JWToken {
+        id_token(
+            json!({"iss": "https://as.example", "sub": "alice", "aud": "s6BhdRkqt3",
+                        "nonce": "n-0S6_WzA2Mj", "s_hash": "bOhtX8F73IMjSPeVAqxyTQ",
+                        "c_hash": "o1uBp9eSe3DsmScN0jYriA"}),
+        )
+    }
+
+    fn failed(requirements: &[Requirement]) -> Vec<(&str, &str)> {
+        requirements
+            .iter()
+            .filter(|r| !r.passed)
+            .map(|r| (r.description.as_str(), r.detail.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn half_hash_test() {
+        assert_eq!(
+            half_hash("RS256", "af0ifjsldkj").unwrap(),
+            "bOhtX8F73IMjSPeVAqxyTQ"
+        );
+        assert_eq!(
+            half_hash("ES384", "af0ifjsldkj").unwrap(),
+            "JYYRngFO-VUh_eQBlkugwLQCrGnI_y1Q"
+        );
+        assert_eq!(
+            half_hash("EdDSA", "af0ifjsldkj").unwrap(),
+            "rWGxt4NU9kITOhSU3u71vN0xp-uunW35Qk4uEj9h2Y4"
+        );
+        assert_eq!(half_hash("ES256K", "x"), half_hash("HS256", "x"));
+        assert_eq!(half_hash("none", "x"), None);
+    }
+
+    #[test]
+    fn parse_test() {
+        let request = AuthRequest::parse(REQUEST).unwrap();
+        assert_eq!(
+            request.param("redirect_uri"),
+            Some("https://client.example/cb")
+        );
+        assert_eq!(request.param("scope"), Some("openid profile"));
+        let query = AuthRequest::parse("?client_id=c&state=s").unwrap();
+        assert_eq!(query.param("state"), Some("s"));
+        let json = AuthRequest::parse(r#"{"client_id": "c", "max_age": 300}"#).unwrap();
+        assert_eq!(json.param("max_age"), Some("300"));
+        assert!(matches!(
+            AuthRequest::parse("https://as.example/authorize?scope=openid"),
+            Err(JWTError::InvalidArgumentError(e)) if e == "--auth-request has no client_id; expected an authorization URL, query string or JSON parameters"
+        ));
+        assert!(AuthRequest::parse("client_id=c&request=not.a.jwt").is_err());
+
+        let callback = Callback::parse("https://client.example/cb?state=s#code=abc&iss=x");
+        assert_eq!(callback.base, "https://client.example/cb");
+        assert_eq!(callback.param("code"), Some("abc"));
+        assert_eq!(callback.param("state"), Some("s"));
+    }
+
+    #[test]
+    fn correlate_test() {
+        let request = AuthRequest::parse(REQUEST).unwrap();
+        let callback = Callback::parse(&format!(
+            "https://client.example/cb?code={}&state=af0ifjsldkj&iss=https%3A%2F%2Fas.example",
+            CODE
+        ));
+        let requirements = correlate(&request, Some(&callback), &good());
+        assert_eq!(failed(&requirements), []);
+        assert!(render_text(&requirements).ends_with("flow: 9 of 9 check(s) met"));
+        // without a callback, c_hash has no code to check against
+        assert_eq!(correlate(&request, None, &good()).len(), 5);
+
+        // another tab's callback, at a redirect_uri with a trailing slash
+        let other = Callback::parse(&format!(
+            "https://client.example/cb/?code={}&state=zzz&iss=https%3A%2F%2Fevil.example",
+            "another-code"
+        ));
+        let requirements = correlate(&request, Some(&other), &good());
+        let failures: Vec<&str> = failed(&requirements).iter().map(|(d, _)| *d).collect();
+        assert_eq!(
+            failures,
+            [
+                "c_hash matches the returned code",
+                "the callback arrived at the redirect_uri",
+                "state returned unchanged",
+                "the callback's iss is the token's"
+            ]
+        );
+
+        let mut payload = good().payload;
+        payload["nonce"] = json!("replayed");
+        payload["aud"] = json!(["other-client"]);
+        payload["s_hash"] = json!("AAAA");
+        let requirements = correlate(&request, None, &id_token(payload));
+        assert_eq!(
+            failed(&requirements),
+            [
+                (
+                    "nonce echoes the request's",
+                    "nonce is replayed, the request sent n-0S6_WzA2Mj: a replayed or cross-session token"
+                ),
+                (
+                    "aud includes the client_id",
+                    "aud is [\"other-client\"], not the requesting client s6BhdRkqt3"
+                ),
+                (
+                    "s_hash matches the request's state",
+                    "s_hash is AAAA, expected bOhtX8F73IMjSPeVAqxyTQ"
+                ),
+            ]
+        );
+
+        let implicit = AuthRequest::parse(
+            "client_id=s6BhdRkqt3&response_type=id_token&code_challenge=x&code_challenge_method=plain",
+        )
+        .unwrap();
+        let error = Callback::parse("cb#error=access_denied&error_description=user+cancelled");
+        let requirements = correlate(
+            &implicit,
+            Some(&error),
+            &id_token(json!({"aud": "s6BhdRkqt3"})),
+        );
+        assert_eq!(
+            failed(&requirements),
+            [
+                (
+                    "nonce echoes the request's",
+                    "no nonce, which response_type id_token requires"
+                ),
+                (
+                    "PKCE with S256",
+                    "code_challenge_method is plain: the challenge gives the verifier away"
+                ),
+                ("redirect_uri present", "no redirect_uri"),
+                (
+                    "the callback is not an error",
+                    "error access_denied: user cancelled"
+                ),
+                (
+                    "the callback arrived at the redirect_uri",
+                    "the request has no redirect_uri to compare"
+                ),
+                (
+                    "state returned unchanged",
+                    "the request sent no state, so CSRF goes unnoticed"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn request_object_test() {
+        let request_object = crate::sign::sign(
+            &json!({"alg": "HS256"}),
+            &json!({"client_id": "s6BhdRkqt3", "redirect_uri": "https://client.example/cb",
+                    "state": "af0ifjsldkj", "nonce": "n-0S6_WzA2Mj", "response_type": "code"}),
+            &crate::keys::SigningKey::Hmac(b"secret".to_vec()),
+        )
+        .unwrap();
+        let request = AuthRequest::parse(&format!(
+            "client_id=s6BhdRkqt3&redirect_uri=https%3A%2F%2Fclient.example%2Fother&request={}",
+            request_object
+        ))
+        .unwrap();
+        // the request object's redirect_uri is the one the AS uses
+        assert_eq!(
+            request.param("redirect_uri"),
+            Some("https://client.example/cb")
+        );
+        let requirements = correlate(&request, None, &good());
+        assert_eq!(
+            failed(&requirements),
+            [
+                (
+                    "the request object agrees with the query",
+                    "redirect_uri is https://client.example/cb in the request object, https://client.example/other in the query"
+                ),
+                (
+                    "PKCE with S256",
+                    "no code_challenge: an intercepted code can be redeemed by anyone"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn each_failure_test() {
+        let request = AuthRequest::parse(
+            "client_id=c&redirect_uri=https://client.example/cb&state=s1&nonce=n1&code_challenge=x",
+        )
+        .unwrap();
+        let callback =
+            Callback::parse("https://client.example/cb/?code=abc&iss=https://as.example");
+        let token = JWToken {
+            header: json!({"alg": "none"}),
+            payload: json!({"aud": "c", "s_hash": "x"}),
+            signature: Vec::new(),
+        };
+        assert_eq!(
+            failed(&correlate(&request, Some(&callback), &token)),
+            [
+                (
+                    "nonce echoes the request's",
+                    "the request sent nonce n1, the token has none"
+                ),
+                (
+                    "s_hash matches the request's state",
+                    "no hash is defined for alg none"
+                ),
+                (
+                    "PKCE with S256",
+                    "no code_challenge_method, which means plain"
+                ),
+                (
+                    "the callback arrived at the redirect_uri",
+                    "the callback is https://client.example/cb/, the redirect_uri https://client.example/cb; redirect URIs must match exactly"
+                ),
+                (
+                    "state returned unchanged",
+                    "the request sent state s1, the callback has none"
+                ),
+                ("the callback's iss is the token's", "the token has no iss"),
+            ]
+        );
+
+        let request_object = crate::sign::sign(
+            &json!({"alg": "HS256"}),
+            &json!({"response_type": "code"}),
+            &crate::keys::SigningKey::Hmac(b"secret".to_vec()),
+        )
+        .unwrap();
+        let request = AuthRequest::parse(&format!("request={}", request_object)).unwrap();
+        let callback = Callback::parse("https://client.example/cb?iss=https://evil.example");
+        let mut token = good();
+        token.payload["nonce"] = json!("n2");
+        assert_eq!(
+            failed(&correlate(&request, Some(&callback), &token)),
+            [
+                (
+                    "nonce echoes the request's",
+                    "the token has nonce n2, the request sent none"
+                ),
+                ("aud includes the client_id", "the request has no client_id"),
+                (
+                    "s_hash matches the request's state",
+                    "the token has s_hash, but the request sent no state"
+                ),
+                (
+                    "PKCE with S256",
+                    "no code_challenge: an intercepted code can be redeemed by anyone"
+                ),
+                ("redirect_uri present", "no redirect_uri"),
+                (
+                    "the callback arrived at the redirect_uri",
+                    "the request has no redirect_uri to compare"
+                ),
+                (
+                    "state returned unchanged",
+                    "the request sent no state, so CSRF goes unnoticed"
+                ),
+                (
+                    "the callback's iss is the token's",
+                    "the callback names https://evil.example, the token https://as.example: a mix-up attack?"
+                ),
+            ]
+        );
+
+        let request = AuthRequest::parse(REQUEST).unwrap();
+        let callback = Callback::parse(&format!(
+            "https://client.example/cb?code={}&state=zzz",
+            CODE
+        ));
+        let requirements = correlate(&request, Some(&callback), &good());
+        assert_eq!(
+            failed(&requirements),
+            [(
+                "state returned unchanged",
+                "state is zzz, the request sent af0ifjsldkj: this callback belongs to another request"
+            )]
+        );
+    }
+}
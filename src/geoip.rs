@@ -0,0 +1,626 @@
+//! `--geoip`: IP addresses in claims, looked up in a local MaxMind DB
+//! (`.mmdb`) file such as GeoLite2-City or GeoLite2-ASN, with no network
+//! calls.
+//!
+//! A string claim holding an IPv4 or IPv6 address (`ipaddr`, `client_ip`, or
+//! any other, at any depth) is replaced by an object whose `$geoip` member
+//! holds what the database knows: `country`, `city`, `location` and, from
+//! an ASN database, `asn` and `org`. `value` keeps the address. Addresses
+//! the database has nothing for are left alone.
+//!
+//! The reader follows the MaxMind DB 2.0 format: a binary search tree over
+//! the address bits, 24, 28 or 32-bit records, then the data section the
+//! leaves point into.
+
+use crate::JWTError;
+use serde_json::{Map, Value};
+use std::convert::TryFrom;
+use std::net::IpAddr;
+
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+/// The metadata is in the file's last 128KiB.
+const METADATA_MAX: usize = 128 * 1024;
+/// Bytes of zeroes between the search tree and the data section.
+const SEPARATOR: usize = 16;
+/// How deeply maps, arrays and pointers may nest before the file is taken
+/// as corrupt rather than followed.
+const MAX_DEPTH: usize = 32;
+
+fn geoip_error(message: &str) -> JWTError {
+    JWTError::GeoIpError(message.to_string().into())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A decoder over `data`, whose pointers are offsets from `base`.
+struct Decoder<'a> {
+    data: &'a [u8],
+    base: usize,
+}
+
+impl Decoder<'_> {
+    fn bytes(&self, at: usize, count: usize) -> Result<&[u8], JWTError> {
+        at.checked_add(count)
+            .and_then(|end| self.data.get(at..end))
+            .ok_or_else(|| geoip_error("data runs past the end of the file"))
+    }
+
+    fn unsigned(&self, at: usize, count: usize) -> Result<u128, JWTError> {
+        Ok(self
+            .bytes(at, count)?
+            .iter()
+            .fold(0u128, |n, b| (n << 8) | u128::from(*b)))
+    }
+
+    /// The value at `at`, and where the next one starts.
+    fn decode(&self, at: usize, depth: usize) -> Result<(Value, usize), JWTError> {
+        if depth > MAX_DEPTH {
+            return Err(geoip_error("data nests too deeply"));
+        }
+        let control = *self.bytes(at, 1)?.first().unwrap_or(&0);
+        let mut next = at + 1;
+        let mut kind = control >> 5;
+        if kind == 1 {
+            // a pointer: its size bits say how many bytes follow
+            let size = usize::from((control >> 3) & 3);
+            let low = u128::from(control & 7);
+            let (offset, length) = match size {
+                0 => ((low << 8) | self.unsigned(next, 1)?, 1),
+                1 => (((low << 16) | self.unsigned(next, 2)?) + 2_048, 2),
+                2 => (((low << 24) | self.unsigned(next, 3)?) + 526_336, 3),
+                _ => (self.unsigned(next, 4)?, 4),
+            };
+            let target = usize::try_from(offset)
+                .ok()
+                .and_then(|offset| offset.checked_add(self.base))
+                .ok_or_else(|| geoip_error("pointer out of range"))?;
+            let (value, _) = self.decode(target, depth + 1)?;
+            return Ok((value, next + length));
+        }
+        if kind == 0 {
+            kind = 7 + *self.bytes(next, 1)?.first().unwrap_or(&0);
+            next += 1;
+        }
+        let mut size = usize::from(control & 0x1f);
+        if size >= 29 {
+            let extra = size - 28;
+            let more = usize::try_from(self.unsigned(next, extra)?).unwrap_or(usize::MAX);
+            size = match extra {
+                1 => 29 + more,
+                2 => 285 + more,
+                _ => 65_821 + more,
+            };
+            next += extra;
+        }
+        let number = |text: String| serde_json::from_str::<Value>(&text).map_err(JWTError::from);
+        let value = match kind {
+            2 => Value::String(
+                std::str::from_utf8(self.bytes(next, size)?)
+                    .map_err(|_| geoip_error("string is not UTF-8"))?
+                    .to_string(),
+            ),
+            3 if size == 8 => {
+                let bits = u64::try_from(self.unsigned(next, 8)?).unwrap_or_default();
+                serde_json::Number::from_f64(f64::from_bits(bits))
+                    .map_or(Value::Null, Value::Number)
+            }
+            15 if size == 4 => {
+                let bits = u32::try_from(self.unsigned(next, 4)?).unwrap_or_default();
+                serde_json::Number::from_f64(f64::from(f32::from_bits(bits)))
+                    .map_or(Value::Null, Value::Number)
+            }
+            4 => Value::String(hex(self.bytes(next, size)?)),
+            5 | 6 | 9 | 10 if size <= 16 => number(self.unsigned(next, size)?.to_string())?,
+            8 if size <= 4 => {
+                // sign-extended from however many bytes there are
+                let raw = u32::try_from(self.unsigned(next, size)?).unwrap_or_default();
+                let shift = 32 - 8 * u32::try_from(size).unwrap_or(4);
+                let signed = if size == 0 {
+                    0
+                } else {
+                    ((raw << shift) as i32) >> shift
+                };
+                Value::from(signed)
+            }
+            7 => {
+                let mut map = Map::new();
+                for _ in 0..size {
+                    let (key, after) = self.decode(next, depth + 1)?;
+                    let key = match key {
+                        Value::String(key) => key,
+                        _ => return Err(geoip_error("map key is not a string")),
+                    };
+                    let (value, after) = self.decode(after, depth + 1)?;
+                    map.insert(key, value);
+                    next = after;
+                }
+                return Ok((Value::Object(map), next));
+            }
+            11 => {
+                // each element takes at least a byte, so a bogus size
+                // can't make a huge allocation
+                let mut array = Vec::with_capacity(size.min(self.data.len()));
+                for _ in 0..size {
+                    let (value, after) = self.decode(next, depth + 1)?;
+                    array.push(value);
+                    next = after;
+                }
+                return Ok((Value::Array(array), next));
+            }
+            14 => return Ok((Value::Bool(size != 0), next)),
+            _ => return Err(geoip_error(&format!("unsupported data type {}", kind))),
+        };
+        Ok((value, next + size))
+    }
+}
+
+/// An opened `.mmdb` file.
+#[derive(Debug, Clone)]
+pub struct Database {
+    bytes: Vec<u8>,
+    node_count: usize,
+    record_size: usize,
+    ip_version: u64,
+    /// Where, from the root, IPv4 addresses start in an IPv6 tree.
+    ipv4_start: usize,
+    /// `database_type` from the metadata, e.g. `GeoLite2-City`.
+    pub kind: String,
+}
+
+impl Database {
+    pub fn open(bytes: Vec<u8>) -> Result<Database, JWTError> {
+        let tail = bytes.len().saturating_sub(METADATA_MAX);
+        let start = bytes
+            .get(tail..)
+            .and_then(|window| {
+                window
+                    .windows(METADATA_MARKER.len())
+                    .rposition(|candidate| candidate == METADATA_MARKER)
+            })
+            .map(|at| tail + at + METADATA_MARKER.len())
+            .ok_or_else(|| geoip_error("not a MaxMind DB file: no metadata"))?;
+        let decoder = Decoder {
+            data: &bytes,
+            base: start,
+        };
+        let (metadata, _) = decoder.decode(start, 0)?;
+        let field = |name: &str| {
+            metadata
+                .get(name)
+                .and_then(Value::as_u64)
+                .ok_or_else(|| geoip_error(&format!("metadata has no {}", name)))
+        };
+        let node_count = usize::try_from(field("node_count")?).unwrap_or(usize::MAX);
+        let record_size = usize::try_from(field("record_size")?).unwrap_or_default();
+        let ip_version = field("ip_version")?;
+        if ![24, 28, 32].contains(&record_size) {
+            return Err(geoip_error(&format!(
+                "record size {} is not 24, 28 or 32",
+                record_size
+            )));
+        }
+        if ![4, 6].contains(&ip_version) {
+            return Err(geoip_error(&format!(
+                "ip_version {} is not 4 or 6",
+                ip_version
+            )));
+        }
+        if node_count
+            .checked_mul(record_size / 4)
+            .is_none_or(|tree| tree + SEPARATOR > start)
+        {
+            return Err(geoip_error("search tree runs past the metadata"));
+        }
+        let mut database = Database {
+            node_count,
+            record_size,
+            ip_version,
+            ipv4_start: 0,
+            kind: metadata
+                .get("database_type")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            bytes,
+        };
+        if ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= node_count {
+                    break;
+                }
+                node = database.record(node, 0)?;
+            }
+            database.ipv4_start = node;
+        }
+        Ok(database)
+    }
+
+    fn tree_size(&self) -> usize {
+        self.node_count * self.record_size / 4
+    }
+
+    /// The left (`bit` 0) or right record of `node`.
+    fn record(&self, node: usize, bit: u8) -> Result<usize, JWTError> {
+        let width = self.record_size / 4;
+        let at = node * width;
+        let bytes = self
+            .bytes
+            .get(at..at + width)
+            .ok_or_else(|| geoip_error("node out of range"))?;
+        let number = |bytes: &[u8]| bytes.iter().fold(0usize, |n, b| (n << 8) | usize::from(*b));
+        let half = width / 2;
+        Ok(match (self.record_size, bit) {
+            (28, 0) => {
+                let high = usize::from(bytes.get(3).copied().unwrap_or_default() >> 4);
+                (high << 24) | number(bytes.get(..3).unwrap_or_default())
+            }
+            (28, _) => {
+                let high = usize::from(bytes.get(3).copied().unwrap_or_default() & 0x0f);
+                (high << 24) | number(bytes.get(4..).unwrap_or_default())
+            }
+            (_, 0) => number(bytes.get(..half).unwrap_or_default()),
+            _ => number(bytes.get(half..).unwrap_or_default()),
+        })
+    }
+
+    /// The database's record for `ip`, if it has one.
+    pub fn lookup(&self, ip: IpAddr) -> Result<Option<Value>, JWTError> {
+        let (bits, mut node): (Vec<u8>, usize) = match (ip, self.ip_version) {
+            (IpAddr::V4(v4), 6) => (v4.octets().to_vec(), self.ipv4_start),
+            (IpAddr::V4(v4), _) => (v4.octets().to_vec(), 0),
+            (IpAddr::V6(v6), 6) => (v6.octets().to_vec(), 0),
+            (IpAddr::V6(v6), _) => match v6.to_ipv4_mapped() {
+                Some(v4) => (v4.octets().to_vec(), 0),
+                None => return Ok(None),
+            },
+        };
+        for bit in bits
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1))
+        {
+            if node >= self.node_count {
+                break;
+            }
+            node = self.record(node, bit)?;
+        }
+        if node <= self.node_count {
+            // node_count itself is the empty record
+            return Ok(None);
+        }
+        let base = self.tree_size() + SEPARATOR;
+        let at = (node - self.node_count)
+            .checked_add(self.tree_size())
+            .filter(|at| *at >= base)
+            .ok_or_else(|| geoip_error("record points into the search tree"))?;
+        let decoder = Decoder {
+            data: &self.bytes,
+            base,
+        };
+        Ok(Some(decoder.decode(at, 0)?.0))
+    }
+}
+
+/// The parts of a City, Country or ASN record worth showing next to a claim.
+pub fn summary(record: &Value) -> Value {
+    let mut summary = Map::new();
+    let country = record
+        .pointer("/country/iso_code")
+        .or_else(|| record.pointer("/registered_country/iso_code"));
+    let fields = [
+        ("country", country),
+        ("city", record.pointer("/city/names/en")),
+        ("asn", record.get("autonomous_system_number")),
+        ("org", record.get("autonomous_system_organization")),
+    ];
+    for (name, value) in fields {
+        if let Some(value) = value {
+            summary.insert(name.to_string(), value.clone());
+        }
+    }
+    if let (Some(latitude), Some(longitude)) = (
+        record.pointer("/location/latitude"),
+        record.pointer("/location/longitude"),
+    ) {
+        summary.insert(
+            "location".to_string(),
+            Value::Array(vec![latitude.clone(), longitude.clone()]),
+        );
+    }
+    if summary.is_empty() {
+        record.clone()
+    } else {
+        Value::Object(summary)
+    }
+}
+
+/// Replaces the address claims in `value` that `database` knows with
+/// `{"$geoip": summary, "value": address}`, returning how many it did.
+pub fn enrich(value: &mut Value, database: &Database) -> Result<usize, JWTError> {
+    match value {
+        Value::String(text) => match text.trim().parse::<IpAddr>() {
+            Ok(ip) => match database.lookup(ip)? {
+                Some(record) => {
+                    let mut object = Map::new();
+                    object.insert("$geoip".to_string(), summary(&record));
+                    object.insert("value".to_string(), Value::String(text.clone()));
+                    *value = Value::Object(object);
+                    Ok(1)
+                }
+                None => Ok(0),
+            },
+            Err(_) => Ok(0),
+        },
+        Value::Array(items) => items.iter_mut().map(|item| enrich(item, database)).sum(),
+        Value::Object(members) => members
+            .values_mut()
+            .map(|member| enrich(member, database))
+            .sum(),
+        _ => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A value in the data section format: maps, strings, unsigned
+    /// integers and doubles are enough for the tests.
+    fn encode(value: &Value) -> Vec<u8> {
+        let header = |kind: u8, size: usize| -> Vec<u8> {
+            assert!(size < 29);
+            match kind {
+                0..=7 => vec![(kind << 5) | size as u8],
+                _ => vec![size as u8, kind - 7],
+            }
+        };
+        match value {
+            Value::String(text) => {
+                let mut out = header(2, text.len());
+                out.extend(text.as_bytes());
+                out
+            }
+            Value::Number(n) if n.is_u64() => {
+                let bytes: Vec<u8> = n
+                    .as_u64()
+                    .unwrap()
+                    .to_be_bytes()
+                    .iter()
+                    .skip_while(|b| **b == 0)
+                    .copied()
+                    .collect();
+                let mut out = header(6, bytes.len());
+                out.extend(bytes);
+                out
+            }
+            Value::Number(n) => {
+                let mut out = header(3, 8);
+                out.extend(n.as_f64().unwrap().to_bits().to_be_bytes());
+                out
+            }
+            Value::Object(members) => {
+                let mut out = header(7, members.len());
+                for (key, value) in members {
+                    out.extend(encode(&Value::String(key.clone())));
+                    out.extend(encode(value));
+                }
+                out
+            }
+            other => panic!("not encodable in the tests: {}", other),
+        }
+    }
+
+    /// An IPv4 database of 24-bit records with `record` for the /24 of
+    /// `network`, and a pointer to it for the next /24 up.
+    fn database(network: [u8; 3], record: &Value, record_size: usize) -> Vec<u8> {
+        let bits: Vec<u8> = network
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1))
+            .collect();
+        let node_count = bits.len();
+        let data = encode(record);
+        // a pointer to offset 0 of the data section
+        let mut pointer_data = data.clone();
+        let pointer_at = pointer_data.len();
+        pointer_data.extend([0x20, 0x00]);
+        let leaf = node_count + SEPARATOR;
+        let mut tree = Vec::new();
+        for (node, bit) in bits.iter().enumerate() {
+            let (mut left, mut right) = (node_count, node_count);
+            let next = if node + 1 == node_count {
+                leaf
+            } else {
+                node + 1
+            };
+            if *bit == 0 {
+                left = next;
+                // the sibling /24 of the last bit points at the pointer
+                if node + 1 == node_count {
+                    right = leaf + pointer_at;
+                }
+            } else {
+                right = next;
+            }
+            match record_size {
+                24 => {
+                    tree.extend(&left.to_be_bytes()[5..]);
+                    tree.extend(&right.to_be_bytes()[5..]);
+                }
+                _ => {
+                    tree.extend(&(left as u32).to_be_bytes());
+                    tree.extend(&(right as u32).to_be_bytes());
+                }
+            }
+        }
+        let mut bytes = tree;
+        bytes.extend([0; SEPARATOR]);
+        bytes.extend(pointer_data);
+        bytes.extend(METADATA_MARKER);
+        bytes.extend(encode(&json!({
+            "node_count": node_count,
+            "record_size": record_size,
+            "ip_version": 4,
+            "database_type": "Test-City",
+        })));
+        bytes
+    }
+
+    #[test]
+    fn lookup_test() {
+        let record = json!({
+            "city": {"names": {"en": "Mountain View"}},
+            "country": {"iso_code": "US"},
+            "location": {"latitude": 37.386, "longitude": -122.0838},
+        });
+        for record_size in [24, 32] {
+            let database = Database::open(database([8, 8, 8], &record, record_size)).unwrap();
+            assert_eq!(database.kind, "Test-City");
+            let found = database.lookup("8.8.8.8".parse().unwrap()).unwrap();
+            assert_eq!(found.as_ref(), Some(&record));
+            // the /24 after it holds a pointer to the same record
+            assert_eq!(
+                database.lookup("8.8.9.200".parse().unwrap()).unwrap(),
+                Some(record.clone())
+            );
+            assert_eq!(database.lookup("9.9.9.9".parse().unwrap()).unwrap(), None);
+            assert_eq!(
+                database.lookup("::ffff:8.8.8.1".parse().unwrap()).unwrap(),
+                Some(record.clone())
+            );
+            assert_eq!(
+                database.lookup("2001:db8::1".parse().unwrap()).unwrap(),
+                None
+            );
+        }
+
+        let database = Database::open(database([8, 8, 8], &record, 24)).unwrap();
+        let mut payload = json!({
+            "sub": "alice",
+            "ipaddr": "8.8.8.8",
+            "ctx": {"hops": ["9.9.9.9", " 8.8.8.4"]},
+            "port": 8,
+        });
+        assert_eq!(enrich(&mut payload, &database).unwrap(), 2);
+        let expected = json!({
+            "country": "US",
+            "city": "Mountain View",
+            "location": [37.386, -122.0838],
+        });
+        assert_eq!(
+            payload["ipaddr"],
+            json!({"$geoip": expected, "value": "8.8.8.8"})
+        );
+        assert_eq!(payload["ctx"]["hops"][0], "9.9.9.9");
+        assert_eq!(payload["ctx"]["hops"][1]["$geoip"]["country"], "US");
+        assert_eq!(payload["sub"], "alice");
+
+        let asn =
+            json!({"autonomous_system_number": 15169, "autonomous_system_organization": "GOOGLE"});
+        assert_eq!(summary(&asn), json!({"asn": 15169, "org": "GOOGLE"}));
+        assert_eq!(summary(&json!({"custom": 1})), json!({"custom": 1}));
+    }
+
+    #[test]
+    fn corrupt_database_test() {
+        assert!(matches!(
+            Database::open(b"not a database".to_vec()),
+            Err(JWTError::GeoIpError(e)) if e == "not a MaxMind DB file: no metadata"
+        ));
+        let record = json!({"country": {"iso_code": "US"}});
+        let mut bytes = database([8, 8, 8], &record, 24);
+        let metadata = bytes
+            .windows(METADATA_MARKER.len())
+            .rposition(|window| window == METADATA_MARKER)
+            .unwrap()
+            + METADATA_MARKER.len();
+        let mut odd = bytes[..metadata].to_vec();
+        odd.extend(encode(
+            &json!({"node_count": 24, "record_size": 20, "ip_version": 4}),
+        ));
+        assert!(matches!(
+            Database::open(odd),
+            Err(JWTError::GeoIpError(e)) if e == "record size 20 is not 24, 28 or 32"
+        ));
+        let mut huge = bytes[..metadata].to_vec();
+        huge.extend(encode(
+            &json!({"node_count": 1_000_000, "record_size": 24, "ip_version": 4}),
+        ));
+        assert!(matches!(
+            Database::open(huge),
+            Err(JWTError::GeoIpError(e)) if e == "search tree runs past the metadata"
+        ));
+        for (metadata_fields, error) in [
+            (
+                json!({"record_size": 24, "ip_version": 4}),
+                "metadata has no node_count",
+            ),
+            (
+                json!({"node_count": 24, "record_size": 24, "ip_version": 5}),
+                "ip_version 5 is not 4 or 6",
+            ),
+        ] {
+            let mut bad = bytes[..metadata].to_vec();
+            bad.extend(encode(&metadata_fields));
+            assert!(matches!(
+                Database::open(bad),
+                Err(JWTError::GeoIpError(e)) if e == error
+            ));
+        }
+
+        // a record pointing past the end of the file
+        let data = 24 * 6 + SEPARATOR;
+        bytes[data] = (2 << 5) | 31;
+        let database = Database::open(bytes).unwrap();
+        assert!(matches!(
+            database.lookup("8.8.8.8".parse().unwrap()),
+            Err(JWTError::GeoIpError(e)) if e == "data runs past the end of the file"
+        ));
+        // a pointer to itself is cut off rather than followed forever
+        let looping = Decoder {
+            data: &[0x20, 0x00],
+            base: 0,
+        };
+        assert!(matches!(
+            looping.decode(0, 0),
+            Err(JWTError::GeoIpError(e)) if e == "data nests too deeply"
+        ));
+    }
+
+    #[test]
+    fn malformed_data_test() {
+        let error_of = |data: &[u8], base: usize| {
+            error_message!(GeoIpError, (Decoder { data, base }).decode(0, 0))
+        };
+        assert_eq!(
+            error_of(&[0x38, 0, 0, 0, 1], usize::MAX),
+            "pointer out of range"
+        );
+        assert_eq!(
+            error_of(&[0xe1, 0xa1, 0x01, 0x41, b'a'], 0),
+            "map key is not a string"
+        );
+        assert_eq!(error_of(&[0x00, 0x05], 0), "unsupported data type 12");
+        assert_eq!(error_of(&[0x41, 0xff], 0), "string is not UTF-8");
+
+        // trees whose records lead nowhere
+        let tree = |bytes: Vec<u8>, node_count: usize| Database {
+            bytes,
+            node_count,
+            record_size: 24,
+            ip_version: 4,
+            ipv4_start: 0,
+            kind: String::new(),
+        };
+        let lookup_error_of = |database: Database| {
+            error_message!(GeoIpError, database.lookup("10.0.0.1".parse().unwrap()))
+        };
+        assert_eq!(lookup_error_of(tree(vec![0; 3], 10)), "node out of range");
+        assert_eq!(
+            lookup_error_of(tree(vec![0, 0, 2, 0, 0, 2], 1)),
+            "record points into the search tree"
+        );
+    }
+}
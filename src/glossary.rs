@@ -0,0 +1,249 @@
+//! `jwt-check glossary`: every claim seen in a `--batch` run's NDJSON, as
+//! a Markdown table of how often it appears, its JSON types, a few example
+//! values and the issuers that send it.
+//!
+//! Example values are redacted unless asked otherwise: strings show only
+//! their length, or a prefix and suffix when long, as in `--verbose`;
+//! numbers show their digit count. `iss` and `aud` are kept, since issuers
+//! are listed anyway, and so are the NumericDate claims, whose values say
+//! little about anyone.
+
+use jwt_check::{ErrorMessage, JWTError};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+const EXAMPLES: usize = 3;
+const UNREDACTED: &[&str] = &["iss", "aud", "exp", "nbf", "iat", "auth_time"];
+
+/// What was seen of one claim.
+#[derive(Debug, Default)]
+pub struct Entry {
+    pub count: usize,
+    pub types: BTreeMap<&'static str, usize>,
+    pub examples: Vec<String>,
+    pub issuers: BTreeSet<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Glossary {
+    pub tokens: usize,
+    /// Records without a decoded payload, such as those of tokens that
+    /// didn't parse.
+    pub skipped: usize,
+    pub claims: BTreeMap<String, Entry>,
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// `value` as an example, hiding what could identify someone.
+pub fn redact(value: &Value) -> String {
+    match value {
+        Value::String(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            if chars.len() < 24 {
+                return format!("***({} bytes)", s.len());
+            }
+            let prefix: String = chars.iter().take(8).collect();
+            let suffix: String = chars.iter().skip(chars.len() - 4).collect();
+            format!("{}...{} ({} bytes)", prefix, suffix, s.len())
+        }
+        Value::Number(n) => {
+            let digits = n.to_string().chars().filter(char::is_ascii_digit).count();
+            format!("***({} digits)", digits)
+        }
+        Value::Array(items) => format!("[{} items]", items.len()),
+        // key names are what a glossary documents; values stay hidden
+        Value::Object(map) => {
+            let keys: Vec<&str> = map.keys().map(String::as_str).collect();
+            format!("{{{}}}", keys.join(", "))
+        }
+        Value::Null | Value::Bool(_) => value.to_string(),
+    }
+}
+
+impl Glossary {
+    /// Reads `--batch` output; the summary record and blank lines are
+    /// skipped, and any other line that isn't a JSON object is an error.
+    pub fn from_ndjson(text: &str, unredacted: bool) -> Result<Glossary, JWTError> {
+        let mut glossary = Glossary::default();
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: Value = serde_json::from_str(line).map_err(|e| {
+                JWTError::InvalidArgumentError(ErrorMessage::caused_by(
+                    format!("line {} is not JSON: {}", index + 1, e),
+                    e,
+                ))
+            })?;
+            let record = record.as_object().ok_or_else(|| {
+                JWTError::InvalidArgumentError(
+                    format!("line {} is not a batch record", index + 1).into(),
+                )
+            })?;
+            if record.contains_key("summary") {
+                continue;
+            }
+            match record.get("payload").and_then(Value::as_object) {
+                Some(payload) => glossary.add(payload, unredacted),
+                None => glossary.skipped += 1,
+            }
+        }
+        Ok(glossary)
+    }
+
+    fn add(&mut self, payload: &serde_json::Map<String, Value>, unredacted: bool) {
+        self.tokens += 1;
+        let issuer = match payload.get("iss") {
+            Some(Value::String(iss)) => iss.clone(),
+            Some(other) => other.to_string(),
+            None => "(none)".to_string(),
+        };
+        for (name, value) in payload {
+            let entry = self.claims.entry(name.clone()).or_default();
+            entry.count += 1;
+            *entry.types.entry(type_name(value)).or_default() += 1;
+            entry.issuers.insert(issuer.clone());
+            let example = if unredacted || UNREDACTED.contains(&name.as_str()) {
+                value.to_string()
+            } else {
+                redact(value)
+            };
+            if entry.examples.len() < EXAMPLES && !entry.examples.contains(&example) {
+                entry.examples.push(example);
+            }
+        }
+    }
+
+    /// The glossary as a Markdown document, most frequent claims first.
+    pub fn to_markdown(&self, source: &str) -> String {
+        let mut out = format!(
+            "# Claim glossary\n\n{} claims across {} tokens from `{}`",
+            self.claims.len(),
+            self.tokens,
+            source
+        );
+        if self.skipped > 0 {
+            out.push_str(&format!(
+                "; {} records had no decoded payload",
+                self.skipped
+            ));
+        }
+        out.push_str(
+            ".\n\n| Claim | Seen in | Types | Examples | Issuers |\n|---|---|---|---|---|\n",
+        );
+        let mut claims: Vec<(&String, &Entry)> = self.claims.iter().collect();
+        claims.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        for (name, entry) in claims {
+            let types: Vec<String> = entry
+                .types
+                .iter()
+                .map(|(name, count)| match entry.types.len() {
+                    1 => name.to_string(),
+                    _ => format!("{} ({})", name, count),
+                })
+                .collect();
+            let examples: Vec<String> = entry
+                .examples
+                .iter()
+                .map(|e| format!("`{}`", e.replace('`', "'")))
+                .collect();
+            let issuers: Vec<&str> = entry.issuers.iter().map(String::as_str).collect();
+            out.push_str(&format!(
+                "| `{}` | {} ({}%) | {} | {} | {} |\n",
+                cell(name),
+                entry.count,
+                entry.count * 100 / self.tokens.max(1),
+                types.join(", "),
+                cell(&examples.join(", ")),
+                cell(&issuers.join(", "))
+            ));
+        }
+        out
+    }
+}
+
+/// A table row's cells can't hold a bare `|`.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glossary_test() {
+        let ndjson = r#"{"line":1,"header":{"alg":"HS256"},"payload":{"iss":"https://a.example","sub":"alice","exp":1700000000,"roles":["admin"]},"status":"valid","error":null}
+{"line":2,"header":null,"payload":null,"status":"invalid","error":"invalid token"}
+
+{"line":3,"header":{"alg":"RS256"},"payload":{"iss":"https://b.example","sub":42,"exp":1700000001,"tenant":{"id":7,"name":"a|b"}},"status":"expired","error":"claims: expired"}
+{"line":4,"header":{"alg":"RS256"},"payload":{"sub":"bob","note":"a note that is long enough to show"},"status":"valid","error":null}
+{"summary":{"total":4,"valid":2,"invalid":1,"expired":1}}"#;
+        let glossary = Glossary::from_ndjson(ndjson, false).unwrap();
+        assert_eq!((glossary.tokens, glossary.skipped), (3, 1));
+        let sub = &glossary.claims["sub"];
+        assert_eq!(sub.count, 3);
+        assert_eq!(sub.types.get("string"), Some(&2));
+        assert_eq!(sub.types.get("integer"), Some(&1));
+        assert_eq!(
+            sub.examples,
+            ["***(5 bytes)", "***(2 digits)", "***(3 bytes)"]
+        );
+        assert_eq!(
+            sub.issuers.iter().collect::<Vec<_>>(),
+            ["(none)", "https://a.example", "https://b.example"]
+        );
+        assert_eq!(
+            glossary.claims["exp"].examples,
+            ["1700000000", "1700000001"]
+        );
+        assert_eq!(glossary.claims["tenant"].examples, ["{id, name}"]);
+        assert_eq!(
+            glossary.claims["note"].examples,
+            ["a note t...show (34 bytes)"]
+        );
+
+        let markdown = glossary.to_markdown("batch.ndjson");
+        assert!(markdown.starts_with(
+            "# Claim glossary\n\n6 claims across 3 tokens from `batch.ndjson`; 1 records had no decoded payload.\n"
+        ));
+        let rows: Vec<&str> = markdown.lines().skip(4).collect();
+        assert_eq!(
+            rows[2],
+            "| `sub` | 3 (100%) | integer (1), string (2) | `***(5 bytes)`, `***(2 digits)`, `***(3 bytes)` | (none), https://a.example, https://b.example |"
+        );
+        assert_eq!(
+            rows[3],
+            "| `exp` | 2 (66%) | integer | `1700000000`, `1700000001` | https://a.example, https://b.example |"
+        );
+
+        let unredacted = Glossary::from_ndjson(ndjson, true).unwrap();
+        assert_eq!(
+            unredacted.claims["tenant"].examples,
+            [r#"{"id":7,"name":"a|b"}"#]
+        );
+        assert!(unredacted
+            .to_markdown("-")
+            .contains(r#"`{"id":7,"name":"a\|b"}`"#));
+        assert!(matches!(
+            Glossary::from_ndjson("[1]", false),
+            Err(JWTError::InvalidArgumentError(e)) if e == "line 1 is not a batch record"
+        ));
+        assert!(matches!(
+            Glossary::from_ndjson("\n{\"payload\": {}}\n{", false),
+            Err(JWTError::InvalidArgumentError(e))
+                if e == "line 3 is not JSON: EOF while parsing an object at line 1 column 1"
+        ));
+    }
+}
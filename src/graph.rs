@@ -0,0 +1,222 @@
+//! `--graph`: how the tokens in a batch relate, as Graphviz DOT or Mermaid.
+//!
+//! Each token contributes the path issuer → subject → session (`sid`) →
+//! actor (`act.sub`, then any nested `act`). Subjects and sessions are
+//! scoped to their issuer, so the same `sub` from two issuers is two
+//! nodes. Edges are labelled with how many tokens share them.
+
+use crate::JWTError;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl FromStr for GraphFormat {
+    type Err = JWTError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            "mermaid" => Ok(GraphFormat::Mermaid),
+            _ => Err(JWTError::InvalidArgumentError(
+                format!("unknown graph format `{}`; expected dot or mermaid", s).into(),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Issuer,
+    Subject,
+    Session,
+    Actor,
+}
+
+/// Nodes in the order first seen, and edges with how many tokens have them.
+#[derive(Default)]
+pub struct Graph {
+    nodes: Vec<(Kind, String, String)>,
+    edges: BTreeMap<(usize, usize), usize>,
+    tokens: usize,
+}
+
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+impl Graph {
+    fn node(&mut self, kind: Kind, key: String, label: String) -> usize {
+        match self
+            .nodes
+            .iter()
+            .position(|(k, n, _)| *k == kind && *n == key)
+        {
+            Some(index) => index,
+            None => {
+                self.nodes.push((kind, key, label));
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    /// Adds one token's issuer, subject, session and actors.
+    pub fn add(&mut self, payload: &Value) {
+        self.tokens += 1;
+        let iss = payload.get("iss").and_then(text);
+        let scope = iss.clone().unwrap_or_default();
+        let mut path = Vec::new();
+        if let Some(iss) = &iss {
+            path.push(self.node(Kind::Issuer, iss.clone(), iss.clone()));
+        }
+        if let Some(sub) = payload.get("sub").and_then(text) {
+            path.push(self.node(Kind::Subject, format!("{} {}", scope, sub), sub));
+        }
+        if let Some(sid) = payload.get("sid").and_then(text) {
+            path.push(self.node(
+                Kind::Session,
+                format!("{} {}", scope, sid),
+                format!("session {}", sid),
+            ));
+        }
+        let mut act = payload.get("act");
+        while let Some(actor) = act {
+            // bounded by serde_json's nesting limit
+            let sub = match actor.get("sub").and_then(text) {
+                Some(sub) => sub,
+                None => break,
+            };
+            let actor_scope = actor.get("iss").and_then(text).unwrap_or(scope.clone());
+            path.push(self.node(Kind::Actor, format!("{} {}", actor_scope, sub), sub));
+            act = actor.get("act");
+        }
+        for pair in path.windows(2) {
+            if let [from, to] = pair {
+                if from != to {
+                    *self.edges.entry((*from, *to)).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.dot(),
+            GraphFormat::Mermaid => self.mermaid(),
+        }
+    }
+
+    fn edge_label(&self, count: usize) -> String {
+        if count == 1 {
+            "1 token".to_string()
+        } else {
+            format!("{} tokens", count)
+        }
+    }
+
+    fn dot(&self) -> String {
+        let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut out = format!(
+            "// {} token(s)\ndigraph tokens {{\n  rankdir=LR;\n",
+            self.tokens
+        );
+        for (index, (kind, _, label)) in self.nodes.iter().enumerate() {
+            let shape = match kind {
+                Kind::Issuer => "box",
+                Kind::Subject => "ellipse",
+                Kind::Session => "note",
+                Kind::Actor => "hexagon",
+            };
+            out.push_str(&format!(
+                "  n{} [label={}, shape={}];\n",
+                index,
+                quote(label),
+                shape
+            ));
+        }
+        for ((from, to), count) in &self.edges {
+            out.push_str(&format!(
+                "  n{} -> n{} [label={}];\n",
+                from,
+                to,
+                quote(&self.edge_label(*count))
+            ));
+        }
+        out.push('}');
+        out
+    }
+
+    fn mermaid(&self) -> String {
+        let quote = |text: &str| format!("\"{}\"", text.replace('"', "#quot;"));
+        let mut out = format!("%% {} token(s)\nflowchart LR\n", self.tokens);
+        for (index, (kind, _, label)) in self.nodes.iter().enumerate() {
+            let label = quote(label);
+            let shape = match kind {
+                Kind::Issuer => format!("[{}]", label),
+                Kind::Subject => format!("([{}])", label),
+                Kind::Session => format!("[/{}/]", label),
+                Kind::Actor => format!("{{{{{}}}}}", label),
+            };
+            out.push_str(&format!("  n{}{}\n", index, shape));
+        }
+        for ((from, to), count) in &self.edges {
+            out.push_str(&format!(
+                "  n{} -->|{}| n{}\n",
+                from,
+                self.edge_label(*count),
+                to
+            ));
+        }
+        out.trim_end().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn graph_test() {
+        let mut graph = Graph::default();
+        graph.add(&json!({"iss": "https://idp", "sub": "alice", "sid": "s1",
+                          "act": {"sub": "svc-a", "act": {"sub": "svc-b"}}}));
+        graph.add(&json!({"iss": "https://idp", "sub": "alice", "sid": "s1"}));
+        graph.add(&json!({"iss": "https://other", "sub": "alice\"x"}));
+        assert_eq!(
+            graph.render(GraphFormat::Dot),
+            "// 3 token(s)\ndigraph tokens {\n  rankdir=LR;\n\
+             \x20 n0 [label=\"https://idp\", shape=box];\n\
+             \x20 n1 [label=\"alice\", shape=ellipse];\n\
+             \x20 n2 [label=\"session s1\", shape=note];\n\
+             \x20 n3 [label=\"svc-a\", shape=hexagon];\n\
+             \x20 n4 [label=\"svc-b\", shape=hexagon];\n\
+             \x20 n5 [label=\"https://other\", shape=box];\n\
+             \x20 n6 [label=\"alice\\\"x\", shape=ellipse];\n\
+             \x20 n0 -> n1 [label=\"2 tokens\"];\n\
+             \x20 n1 -> n2 [label=\"2 tokens\"];\n\
+             \x20 n2 -> n3 [label=\"1 token\"];\n\
+             \x20 n3 -> n4 [label=\"1 token\"];\n\
+             \x20 n5 -> n6 [label=\"1 token\"];\n}"
+        );
+        let mermaid = graph.render(GraphFormat::Mermaid);
+        assert!(mermaid.starts_with("%% 3 token(s)\nflowchart LR\n  n0[\"https://idp\"]\n"));
+        assert!(mermaid.contains("  n3{{\"svc-a\"}}\n"));
+        assert!(mermaid.contains("  n6([\"alice#quot;x\"])\n"));
+        assert!(mermaid.ends_with("  n5 -->|1 token| n6"));
+        assert!(matches!(
+            "svg".parse::<GraphFormat>(),
+            Err(JWTError::InvalidArgumentError(e))
+                if e == "unknown graph format `svg`; expected dot or mermaid"
+        ));
+    }
+}
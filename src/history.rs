@@ -0,0 +1,688 @@
+//! `--save-history`: an encrypted local record of the tokens inspected, for
+//! `jwt-check history` to revisit earlier debugging sessions.
+//!
+//! A record holds the token's SHA-256 fingerprint, its header and claims,
+//! and when it was inspected. The signature is left out, so a record
+//! cannot be replayed as a token. The raw token is only kept when asked
+//! for. The store is one file. Its first line names the format, the scrypt
+//! work factor and salt the key is derived from, and a sealed check value
+//! that catches a wrong passphrase before anything is appended. Each line
+//! after that is one record, sealed with XChaCha20-Poly1305 under a random
+//! nonce and then base64url-encoded. Appending never rewrites the file.
+
+use crate::crypto::{scrypt, xchacha20poly1305_open, xchacha20poly1305_seal, Hash};
+use crate::sign::random_bytes;
+use crate::{format_timestamp, humanize_seconds, ErrorMessage, JWTError, JWToken};
+use serde_json::{json, Value};
+use std::convert::TryInto;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const FORMAT: &str = "jwt-check-history/v1";
+const CHECK: &[u8] = b"jwt-check history key";
+/// scrypt's N = 2^15, 32 MiB with r = 8: a fraction of a second per run.
+pub const WORK_FACTOR: u8 = 15;
+
+fn history_error(message: impl Into<ErrorMessage>) -> JWTError {
+    JWTError::HistoryError(message.into())
+}
+
+fn b64(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn unb64(text: &str) -> Result<Vec<u8>, JWTError> {
+    Ok(base64::decode_config(text, base64::URL_SAFE_NO_PAD)?)
+}
+
+/// `$XDG_DATA_HOME/jwt-check/history`, or `~/.local/share/jwt-check/history`.
+pub fn default_path() -> Option<PathBuf> {
+    match (std::env::var_os("XDG_DATA_HOME"), std::env::var_os("HOME")) {
+        (Some(data), _) => Some(PathBuf::from(data).join("jwt-check").join("history")),
+        (None, Some(home)) => Some(
+            PathBuf::from(home)
+                .join(".local")
+                .join("share")
+                .join("jwt-check")
+                .join("history"),
+        ),
+        (None, None) => None,
+    }
+}
+
+/// One inspected token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// When it was inspected, in epoch seconds.
+    pub at: i64,
+    /// The SHA-256 of the raw token, in hex.
+    pub fingerprint: String,
+    pub header: Value,
+    pub claims: Value,
+    /// Only with `--history-raw`.
+    pub token: Option<String>,
+}
+
+impl Record {
+    pub fn new(raw: &str, token: &JWToken, at: i64, keep_raw: bool) -> Record {
+        Record {
+            at,
+            fingerprint: Hash::Sha256
+                .digest(raw.as_bytes())
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            header: token.header.clone(),
+            claims: token.payload.clone(),
+            token: Some(raw.to_string()).filter(|_| keep_raw),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut record = json!({
+            "at": self.at,
+            "fingerprint": self.fingerprint,
+            "header": self.header,
+            "claims": self.claims,
+        });
+        if let (Some(token), Some(object)) = (&self.token, record.as_object_mut()) {
+            object.insert("token".to_string(), token.clone().into());
+        }
+        record
+    }
+
+    fn from_json(record: &Value) -> Option<Record> {
+        Some(Record {
+            at: record.get("at")?.as_i64()?,
+            fingerprint: record.get("fingerprint")?.as_str()?.to_string(),
+            header: record.get("header")?.clone(),
+            claims: record.get("claims")?.clone(),
+            token: record
+                .get("token")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        })
+    }
+
+    /// `iss` from the claims, or an empty string.
+    pub fn issuer(&self) -> &str {
+        self.claims
+            .get("iss")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+    }
+
+    /// One line for `history list`: number, time, alg, iss, sub and the
+    /// start of the fingerprint.
+    pub fn summary(&self, number: usize) -> String {
+        let text = |value: Option<&Value>| value.and_then(Value::as_str).unwrap_or("-").to_string();
+        format!(
+            "{:>4}  {}  {:<6}  {}  {}  {}",
+            number,
+            format_timestamp(self.at),
+            text(self.header.get("alg")),
+            text(self.claims.get("iss")),
+            text(self.claims.get("sub")),
+            self.fingerprint.get(..12).unwrap_or(&self.fingerprint)
+        )
+    }
+}
+
+/// An unlocked history file.
+pub struct Store {
+    path: PathBuf,
+    key: [u8; 32],
+}
+
+impl Store {
+    /// Unlocks the history at `path` with `passphrase`, creating it, private
+    /// to the user, with work factor `log_n` if it does not exist.
+    pub fn open(path: &Path, passphrase: &[u8], log_n: u8) -> Result<Store, JWTError> {
+        let derive = |log_n: u8, salt: &[u8]| {
+            scrypt(passphrase, salt, log_n)
+                .ok_or_else(|| history_error(format!("scrypt work factor {} is too large", log_n)))
+        };
+        if !path.exists() {
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                let mut builder = std::fs::DirBuilder::new();
+                builder.recursive(true);
+                #[cfg(unix)]
+                std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+                builder.create(dir)?;
+            }
+            let salt = random_bytes(16)?;
+            let key = derive(log_n, &salt)?;
+            let check = xchacha20poly1305_seal(&key, &nonce()?, CHECK);
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            writeln!(
+                options.open(path)?,
+                "{} {} {} {}",
+                FORMAT,
+                log_n,
+                b64(&salt),
+                b64(&check)
+            )?;
+            return Ok(Store {
+                path: path.to_path_buf(),
+                key,
+            });
+        }
+        let header = std::fs::read_to_string(path)?
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let fields: Vec<&str> = header.split(' ').collect();
+        let (log_n, salt, check) = match fields.as_slice() {
+            [FORMAT, log_n, salt, check] => (log_n, unb64(salt)?, unb64(check)?),
+            _ => {
+                return Err(history_error(format!(
+                    "{} is not a jwt-check history file",
+                    path.display()
+                )))
+            }
+        };
+        let log_n = log_n
+            .parse::<u8>()
+            .ok()
+            .filter(|n| (1..=crate::age::MAX_WORK_FACTOR).contains(n))
+            .ok_or_else(|| {
+                history_error(format!(
+                    "{} has a bad work factor `{}`",
+                    path.display(),
+                    log_n
+                ))
+            })?;
+        let key = derive(log_n, &salt)?;
+        if xchacha20poly1305_open(&key, &check).as_deref() != Some(CHECK) {
+            return Err(history_error(format!(
+                "wrong passphrase for {}",
+                path.display()
+            )));
+        }
+        Ok(Store {
+            path: path.to_path_buf(),
+            key,
+        })
+    }
+
+    pub fn append(&self, record: &Record) -> Result<(), JWTError> {
+        let sealed = xchacha20poly1305_seal(
+            &self.key,
+            &nonce()?,
+            record.to_json().to_string().as_bytes(),
+        );
+        let mut file = std::fs::OpenOptions::new().append(true).open(&self.path)?;
+        writeln!(file, "{}", b64(&sealed))?;
+        Ok(())
+    }
+
+    /// Every record, oldest first. A line that does not open means the file
+    /// was altered, and is an error rather than skipped.
+    pub fn records(&self) -> Result<Vec<Record>, JWTError> {
+        let text = std::fs::read_to_string(&self.path)?;
+        text.lines()
+            .enumerate()
+            .skip(1)
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(index, line)| {
+                let damaged = || {
+                    history_error(format!(
+                        "line {} of {} is damaged",
+                        index + 1,
+                        self.path.display()
+                    ))
+                };
+                let opened = unb64(line)
+                    .ok()
+                    .and_then(|sealed| xchacha20poly1305_open(&self.key, &sealed))
+                    .ok_or_else(damaged)?;
+                serde_json::from_slice(&opened)
+                    .ok()
+                    .as_ref()
+                    .and_then(Record::from_json)
+                    .ok_or_else(damaged)
+            })
+            .collect()
+    }
+}
+
+fn nonce() -> Result<[u8; 24], JWTError> {
+    random_bytes(24)?
+        .try_into()
+        .map_err(|_| history_error("the random source returned too few bytes"))
+}
+
+/// `history show ID`: a record by its number in `history list`, or by a
+/// unique prefix of its fingerprint.
+pub fn find<'a>(records: &'a [Record], id: &str) -> Result<&'a Record, JWTError> {
+    if let Ok(number) = id.parse::<usize>() {
+        if let Some(record) = number.checked_sub(1).and_then(|index| records.get(index)) {
+            return Ok(record);
+        }
+    }
+    let prefix = id.to_ascii_lowercase();
+    let mut found = records
+        .iter()
+        .filter(|r| r.fingerprint.starts_with(&prefix));
+    match (found.next(), found.next()) {
+        (Some(_), _) if prefix.len() < 4 => Err(history_error(format!(
+            "give at least 4 characters of the fingerprint, not `{}`",
+            id
+        ))),
+        (Some(record), None) => Ok(record),
+        (Some(_), Some(_)) => Err(history_error(format!(
+            "`{}` matches several records; give more of the fingerprint",
+            id
+        ))),
+        (None, _) => Err(history_error(format!("no record `{}` in the history", id))),
+    }
+}
+
+/// `history search TEXT`: the records, with their numbers, whose header or
+/// claims contain `text`, ignoring case.
+pub fn search<'a>(records: &'a [Record], text: &str) -> Vec<(usize, &'a Record)> {
+    let text = text.to_lowercase();
+    records
+        .iter()
+        .enumerate()
+        .filter(|(_, record)| {
+            record.header.to_string().to_lowercase().contains(&text)
+                || record.claims.to_string().to_lowercase().contains(&text)
+        })
+        .map(|(index, record)| (index + 1, record))
+        .collect()
+}
+
+/// Consecutive tokens that agree on one value, such as `alg`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub value: String,
+    pub tokens: usize,
+    pub from: i64,
+    pub to: i64,
+}
+
+/// When a claim name was first and last seen, and in how many tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClaimSpan {
+    pub name: String,
+    pub tokens: usize,
+    pub first: i64,
+    pub last: i64,
+}
+
+/// `history stats --iss`: how one issuer's tokens changed over time. More
+/// than one run of `alg`, `kid` or lifetime is a change in the IdP's
+/// behaviour, as is a claim that appears after the first token or stops
+/// before the last.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub issuer: String,
+    pub tokens: usize,
+    pub from: i64,
+    pub to: i64,
+    pub algs: Vec<Run>,
+    pub kids: Vec<Run>,
+    pub lifetimes: Vec<Run>,
+    pub claims: Vec<ClaimSpan>,
+}
+
+/// The issuer's distinct tokens, ordered by `iat`, or by when they were
+/// inspected when they have none. A token inspected twice counts once.
+pub fn stats(records: &[Record], issuer: &str) -> Result<Stats, JWTError> {
+    let mut seen = std::collections::HashSet::new();
+    let mut tokens: Vec<(i64, &Record)> = records
+        .iter()
+        .filter(|record| record.issuer() == issuer && seen.insert(record.fingerprint.as_str()))
+        .map(|record| {
+            let issued = record.claims.get("iat").and_then(Value::as_i64);
+            (issued.unwrap_or(record.at), record)
+        })
+        .collect();
+    tokens.sort_by_key(|(at, _)| *at);
+    let (from, to) = match (tokens.first(), tokens.last()) {
+        (Some((from, _)), Some((to, _))) => (*from, *to),
+        _ => {
+            return Err(history_error(format!(
+                "no tokens from `{}` in the history",
+                issuer
+            )))
+        }
+    };
+    let runs = |value: &dyn Fn(&Record) -> String| {
+        let mut runs: Vec<Run> = Vec::new();
+        for (at, record) in &tokens {
+            let value = value(record);
+            match runs.last_mut() {
+                Some(run) if run.value == value => {
+                    run.tokens += 1;
+                    run.to = *at;
+                }
+                _ => runs.push(Run {
+                    value,
+                    tokens: 1,
+                    from: *at,
+                    to: *at,
+                }),
+            }
+        }
+        runs
+    };
+    let text = |value: Option<&Value>| value.and_then(Value::as_str).unwrap_or("-").to_string();
+    let mut claims: Vec<ClaimSpan> = Vec::new();
+    for (at, record) in &tokens {
+        for name in record.claims.as_object().into_iter().flat_map(|o| o.keys()) {
+            match claims.iter_mut().find(|span| &span.name == name) {
+                Some(span) => {
+                    span.tokens += 1;
+                    span.last = *at;
+                }
+                None => claims.push(ClaimSpan {
+                    name: name.clone(),
+                    tokens: 1,
+                    first: *at,
+                    last: *at,
+                }),
+            }
+        }
+    }
+    Ok(Stats {
+        issuer: issuer.to_string(),
+        tokens: tokens.len(),
+        from,
+        to,
+        algs: runs(&|record| text(record.header.get("alg"))),
+        kids: runs(&|record| text(record.header.get("kid"))),
+        lifetimes: runs(&|record| {
+            let claim = |name: &str| record.claims.get(name).and_then(Value::as_i64);
+            match (claim("iat"), claim("exp")) {
+                (Some(iat), Some(exp)) => humanize_seconds(exp.saturating_sub(iat)),
+                _ => "-".to_string(),
+            }
+        }),
+        claims,
+    })
+}
+
+impl Stats {
+    pub fn to_json(&self) -> Value {
+        let runs = |runs: &[Run]| -> Value {
+            runs.iter()
+                .map(|run| {
+                    json!({
+                        "value": run.value,
+                        "tokens": run.tokens,
+                        "from": run.from,
+                        "to": run.to,
+                    })
+                })
+                .collect()
+        };
+        json!({
+            "iss": self.issuer,
+            "tokens": self.tokens,
+            "from": self.from,
+            "to": self.to,
+            "alg": runs(&self.algs),
+            "kid": runs(&self.kids),
+            "lifetime": runs(&self.lifetimes),
+            "claims": self.claims.iter().map(|span| json!({
+                "name": span.name,
+                "tokens": span.tokens,
+                "first": span.first,
+                "last": span.last,
+            })).collect::<Vec<Value>>(),
+        })
+    }
+
+    /// A run per line, then the claims that came or went.
+    pub fn render_text(&self) -> String {
+        let mut out = vec![format!(
+            "{} token(s) from {}, {} to {}",
+            self.tokens,
+            self.issuer,
+            format_timestamp(self.from),
+            format_timestamp(self.to)
+        )];
+        for (label, runs) in [
+            ("alg", &self.algs),
+            ("kid", &self.kids),
+            ("lifetime", &self.lifetimes),
+        ] {
+            let changed = if runs.len() > 1 { " (changed)" } else { "" };
+            out.push(format!("{}{}:", label, changed));
+            for run in runs.iter() {
+                out.push(format!(
+                    "  {} in {} token(s), {} to {}",
+                    run.value,
+                    run.tokens,
+                    format_timestamp(run.from),
+                    format_timestamp(run.to)
+                ));
+            }
+        }
+        let mut always = Vec::new();
+        let mut changes = Vec::new();
+        for span in &self.claims {
+            if span.tokens == self.tokens {
+                always.push(span.name.as_str());
+                continue;
+            }
+            let mut change = format!(
+                "  {} in {} of {} token(s)",
+                span.name, span.tokens, self.tokens
+            );
+            if span.first > self.from {
+                change.push_str(&format!(", appeared {}", format_timestamp(span.first)));
+            }
+            if span.last < self.to {
+                change.push_str(&format!(", last seen {}", format_timestamp(span.last)));
+            }
+            changes.push(change);
+        }
+        out.push(format!("claims in every token: {}", always.join(", ")));
+        if !changes.is_empty() {
+            out.push("claims that came or went:".to_string());
+            out.extend(changes);
+        }
+        out.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, SAMPLE_TOKEN};
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jwt-check-history-{}", std::process::id()));
+        let _ = std::fs::remove_file(dir.join(name));
+        dir.join(name)
+    }
+
+    #[test]
+    fn store_test() {
+        let path = temp_path("store");
+        let store = Store::open(&path, b"pass", 4).unwrap();
+        assert!(store.records().unwrap().is_empty());
+        let token = parse(SAMPLE_TOKEN).unwrap();
+        let record = Record::new(SAMPLE_TOKEN, &token, 1_700_000_000, false);
+        store.append(&record).unwrap();
+        store
+            .append(&Record::new(SAMPLE_TOKEN, &token, 1_700_000_060, true))
+            .unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("John Doe") && !text.contains(SAMPLE_TOKEN));
+
+        let records = Store::open(&path, b"pass", WORK_FACTOR)
+            .unwrap()
+            .records()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], record);
+        assert_eq!(records[0].token, None);
+        assert_eq!(records[1].token.as_deref(), Some(SAMPLE_TOKEN));
+        assert!(records[0]
+            .summary(1)
+            .starts_with("   1  2023-11-14T22:13:20Z  HS256   -  1234567890  "));
+
+        assert!(matches!(
+            Store::open(&path, b"wrong", 4),
+            Err(JWTError::HistoryError(e))
+                if e == format!("wrong passphrase for {}", path.display())
+        ));
+        let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+        lines[1].replace_range(..4, "AAAA");
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        assert!(matches!(
+            store.records(),
+            Err(JWTError::HistoryError(e))
+                if e == format!("line 2 of {} is damaged", path.display())
+        ));
+        std::fs::write(&path, "not a history\n").unwrap();
+        assert!(matches!(
+            Store::open(&path, b"pass", 4),
+            Err(JWTError::HistoryError(e))
+                if e == format!("{} is not a jwt-check history file", path.display())
+        ));
+        std::fs::write(&path, format!("{} 99 AAAA AAAA\n", FORMAT)).unwrap();
+        assert!(matches!(
+            Store::open(&path, b"pass", 4),
+            Err(JWTError::HistoryError(e))
+                if e == format!("{} has a bad work factor `99`", path.display())
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stats_test() {
+        let record = |at: i64, fingerprint: &str, header: Value, claims: Value| Record {
+            at,
+            fingerprint: fingerprint.to_string(),
+            header,
+            claims,
+            token: None,
+        };
+        let iss = "https://idp.example";
+        let records = [
+            record(
+                500,
+                "f1",
+                json!({"alg": "RS256", "kid": "k1"}),
+                json!({"iss": iss, "sub": "a", "email": "a@example.com", "iat": 100, "exp": 3700}),
+            ),
+            // inspected again, which doesn't count
+            record(
+                900,
+                "f1",
+                json!({"alg": "RS256", "kid": "k1"}),
+                json!({"iss": iss, "sub": "a", "email": "a@example.com", "iat": 100, "exp": 3700}),
+            ),
+            record(
+                800,
+                "f3",
+                json!({"alg": "ES256", "kid": "k2"}),
+                json!({"iss": iss, "sub": "c", "acr": "mfa", "iat": 300, "exp": 600}),
+            ),
+            record(
+                600,
+                "f2",
+                json!({"alg": "RS256", "kid": "k2"}),
+                json!({"iss": iss, "sub": "b", "email": "b@example.com", "iat": 200, "exp": 3800}),
+            ),
+            record(
+                700,
+                "f4",
+                json!({"alg": "HS256"}),
+                json!({"iss": "https://other.example"}),
+            ),
+        ];
+        let stats = stats(&records, iss).unwrap();
+        assert_eq!((stats.tokens, stats.from, stats.to), (3, 100, 300));
+        let values = |runs: &[Run]| -> Vec<(String, usize)> {
+            runs.iter()
+                .map(|run| (run.value.clone(), run.tokens))
+                .collect()
+        };
+        assert_eq!(
+            values(&stats.algs),
+            [("RS256".to_string(), 2), ("ES256".to_string(), 1)]
+        );
+        assert_eq!(
+            values(&stats.kids),
+            [("k1".to_string(), 1), ("k2".to_string(), 2)]
+        );
+        assert_eq!(
+            values(&stats.lifetimes),
+            [("1 hour".to_string(), 2), ("5 minutes".to_string(), 1)]
+        );
+        let email = stats
+            .claims
+            .iter()
+            .find(|span| span.name == "email")
+            .unwrap();
+        assert_eq!((email.tokens, email.first, email.last), (2, 100, 200));
+        let text = stats.render_text();
+        assert!(text.starts_with("3 token(s) from https://idp.example, 1970-01-01T00:01:40Z to 1970-01-01T00:05:00Z\nalg (changed):\n  RS256 in 2 token(s)"));
+        assert!(text.contains("claims in every token: exp, iat, iss, sub"));
+        assert!(text.contains("  acr in 1 of 3 token(s), appeared 1970-01-01T00:05:00Z"));
+        assert!(text.contains("  email in 2 of 3 token(s), last seen 1970-01-01T00:03:20Z"));
+        assert_eq!(stats.to_json()["lifetime"][1]["value"], "5 minutes");
+
+        assert!(matches!(
+            super::stats(&records, "https://idp.example/"),
+            Err(JWTError::HistoryError(e))
+                if e == "no tokens from `https://idp.example/` in the history"
+        ));
+    }
+
+    #[test]
+    fn find_and_search_test() {
+        let record = |fingerprint: &str, claims: Value| Record {
+            at: 0,
+            fingerprint: fingerprint.to_string(),
+            header: json!({"alg": "RS256"}),
+            claims,
+            token: None,
+        };
+        let records = [
+            record(
+                "abcd1111",
+                json!({"iss": "https://a.example", "sub": "Alice"}),
+            ),
+            record(
+                "abcd2222",
+                json!({"iss": "https://b.example", "sub": "bob"}),
+            ),
+        ];
+        assert_eq!(find(&records, "2").unwrap().fingerprint, "abcd2222");
+        assert_eq!(find(&records, "ABCD1").unwrap().fingerprint, "abcd1111");
+        assert!(matches!(
+            find(&records, "abcd"),
+            Err(JWTError::HistoryError(e))
+                if e == "`abcd` matches several records; give more of the fingerprint"
+        ));
+        assert!(matches!(
+            find(&records, "ab"),
+            Err(JWTError::HistoryError(e))
+                if e == "give at least 4 characters of the fingerprint, not `ab`"
+        ));
+        assert!(matches!(
+            find(&records, "3"),
+            Err(JWTError::HistoryError(e)) if e == "no record `3` in the history"
+        ));
+        assert!(matches!(
+            find(&records, "abc1"),
+            Err(JWTError::HistoryError(e)) if e == "no record `abc1` in the history"
+        ));
+
+        let found: Vec<usize> = search(&records, "alice").iter().map(|(n, _)| *n).collect();
+        assert_eq!(found, [1]);
+        assert_eq!(search(&records, "rs256").len(), 2);
+        assert!(search(&records, "carol").is_empty());
+    }
+}
@@ -0,0 +1,392 @@
+//! `--identity`: one canonical identity record for a token, whichever
+//! identity provider issued it.
+//!
+//! Providers spell the same facts differently: Azure AD's stable user id
+//! is `oid` and its login `upn`, Okta's are `uid` and `sub`, Cognito's
+//! login is `cognito:username`. Each vendor preset lists, for every field
+//! of the record, the claims to try in order, and is picked by a substring
+//! of the token's `iss`. A config file adds vendors or overrides the
+//! presets' fields:
+//!
+//! ```yaml
+//! vendors:
+//!   - name: acme
+//!     issuer: idp.acme.example
+//!     fields:
+//!       id: [employee_id, sub]
+//!       username: [login]
+//!   - name: azure               # a preset: only these fields change
+//!     fields:
+//!       email: [mail, upn]
+//! ```
+//!
+//! A vendor's fields not listed fall back to the generic preset's.
+
+use crate::{claim, JWTError, JWToken};
+use serde_json::{json, Map, Value};
+
+/// The record's fields, in the order they are shown.
+pub const FIELDS: [&str; 5] = ["id", "username", "email", "name", "tenant"];
+
+type Preset = (
+    &'static str,
+    &'static [&'static str],
+    &'static [(&'static str, &'static [&'static str])],
+);
+
+/// The vendor presets: name, `iss` substrings, and the claims each field
+/// is read from. `generic` matches any issuer and is tried last.
+pub const PRESETS: &[Preset] = &[
+    (
+        "azure",
+        &["login.microsoftonline.com", "sts.windows.net"],
+        &[
+            ("id", &["oid", "sub"]),
+            ("username", &["upn", "preferred_username", "unique_name"]),
+            ("email", &["email", "upn"]),
+            ("tenant", &["tid"]),
+        ],
+    ),
+    (
+        "okta",
+        &[".okta.com", ".oktapreview.com"],
+        &[
+            ("id", &["uid", "sub"]),
+            ("username", &["preferred_username", "sub"]),
+        ],
+    ),
+    (
+        "google",
+        &["accounts.google.com"],
+        &[("username", &["email"]), ("tenant", &["hd"])],
+    ),
+    (
+        "auth0",
+        &[".auth0.com"],
+        &[("username", &["nickname", "preferred_username", "email"])],
+    ),
+    (
+        "cognito",
+        &["cognito-idp."],
+        &[("username", &["cognito:username", "username"])],
+    ),
+    ("keycloak", &["/realms/"], &[("tenant", &["azp"])]),
+    (
+        "generic",
+        &[""],
+        &[
+            ("id", &["sub"]),
+            (
+                "username",
+                &["preferred_username", "upn", "username", "email"],
+            ),
+            ("email", &["email"]),
+            ("name", &["name"]),
+            ("tenant", &["tid", "tenant"]),
+        ],
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vendor {
+    pub name: String,
+    /// Substrings of `iss` it is picked by; empty for a preset override,
+    /// which keeps the preset's.
+    pub issuers: Vec<String>,
+    pub fields: Vec<(String, Vec<String>)>,
+}
+
+/// The vendors tried in order: those of a config file, then the presets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vendors {
+    vendors: Vec<Vendor>,
+}
+
+fn strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|item| item.to_string()).collect()
+}
+
+fn string_list(value: &Value, what: &str) -> Result<Vec<String>, JWTError> {
+    let invalid = || JWTError::ConfigError(format!("{} must be a list of strings", what).into());
+    match value {
+        Value::String(item) => Ok(vec![item.clone()]),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| item.as_str().map(str::to_string).ok_or_else(invalid))
+            .collect(),
+        _ => Err(invalid()),
+    }
+}
+
+impl Vendors {
+    pub fn builtin() -> Vendors {
+        Vendors {
+            vendors: PRESETS
+                .iter()
+                .map(|(name, issuers, fields)| Vendor {
+                    name: name.to_string(),
+                    issuers: strings(issuers),
+                    fields: fields
+                        .iter()
+                        .map(|(field, claims)| (field.to_string(), strings(claims)))
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The presets, with the `vendors` of a config file added in front and
+    /// their fields laid over a preset of the same name.
+    pub fn from_config(config: &Value) -> Result<Vendors, JWTError> {
+        let mut vendors = Vendors::builtin();
+        let entries = config
+            .get("vendors")
+            .and_then(Value::as_array)
+            .ok_or_else(|| JWTError::ConfigError("expected a `vendors` list".into()))?;
+        let mut added = Vec::new();
+        for entry in entries {
+            let name = entry
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| {
+                    JWTError::ConfigError("vendors: every vendor needs a `name`".into())
+                })?
+                .to_string();
+            let within = |what: &str| format!("vendor `{}`: {}", name, what);
+            let issuers = match entry.get("issuer") {
+                Some(issuers) => string_list(issuers, &within("`issuer`"))?,
+                None => Vec::new(),
+            };
+            let mut fields = Vec::new();
+            if let Some(mapping) = entry.get("fields") {
+                let mapping = mapping.as_object().ok_or_else(|| {
+                    JWTError::ConfigError(within("`fields` must map fields to claims").into())
+                })?;
+                for (field, claims) in mapping {
+                    if !FIELDS.contains(&field.as_str()) {
+                        return Err(JWTError::ConfigError(
+                            within(&format!("`{}` is not one of {}", field, FIELDS.join(", ")))
+                                .into(),
+                        ));
+                    }
+                    fields.push((field.clone(), string_list(claims, &within(field))?));
+                }
+            }
+            match vendors.vendors.iter_mut().find(|v| v.name == name) {
+                Some(preset) => {
+                    for (field, claims) in fields {
+                        preset.fields.retain(|(existing, _)| *existing != field);
+                        preset.fields.push((field, claims));
+                    }
+                    if !issuers.is_empty() {
+                        preset.issuers = issuers;
+                    }
+                }
+                None if issuers.is_empty() => {
+                    return Err(JWTError::ConfigError(
+                        within("needs an `issuer`, since it is not a preset").into(),
+                    ))
+                }
+                None => added.push(Vendor {
+                    name,
+                    issuers,
+                    fields,
+                }),
+            }
+        }
+        added.append(&mut vendors.vendors);
+        Ok(Vendors { vendors: added })
+    }
+
+    /// The vendor whose issuer substrings match `iss`.
+    fn vendor(&self, iss: &str) -> Option<&Vendor> {
+        self.vendors.iter().find(|vendor| {
+            vendor
+                .issuers
+                .iter()
+                .any(|issuer| iss.contains(issuer.as_str()))
+        })
+    }
+
+    /// The identity record: the vendor, the issuer, each field's value or
+    /// `null`, and the claim each was read from.
+    pub fn identify(&self, token: &JWToken) -> Value {
+        let iss = token
+            .payload
+            .get("iss")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let vendor = self.vendor(iss);
+        let generic = self.vendors.iter().find(|v| v.name == "generic");
+        let mut record = Map::new();
+        record.insert("vendor".to_string(), json!(vendor.map(|v| &v.name)));
+        record.insert("issuer".to_string(), json!(iss));
+        let mut sources = Map::new();
+        for field in FIELDS {
+            let claims = |vendor: Option<&Vendor>| {
+                vendor.and_then(|vendor| {
+                    vendor
+                        .fields
+                        .iter()
+                        .find(|(name, _)| name == field)
+                        .map(|(_, claims)| claims.clone())
+                })
+            };
+            let found = claims(vendor)
+                .or_else(|| claims(generic))
+                .unwrap_or_default()
+                .into_iter()
+                .find_map(|path| {
+                    claim(token, &path)
+                        .filter(|value| !value.is_null())
+                        .map(|value| (path, value.clone()))
+                });
+            match found {
+                Some((path, value)) => {
+                    record.insert(field.to_string(), value);
+                    sources.insert(field.to_string(), path.into());
+                }
+                None => {
+                    record.insert(field.to_string(), Value::Null);
+                }
+            }
+        }
+        record.insert("sources".to_string(), Value::Object(sources));
+        Value::Object(record)
+    }
+}
+
+/// The record as aligned `field: value (claim)` lines.
+pub fn render_text(record: &Value) -> String {
+    let vendor = record
+        .get("vendor")
+        .and_then(Value::as_str)
+        .unwrap_or("none");
+    let mut out = match record.get("issuer").and_then(Value::as_str) {
+        Some(iss) if !iss.is_empty() => format!("identity: {} ({})", vendor, iss),
+        _ => format!("identity: {}", vendor),
+    };
+    for field in FIELDS {
+        let source = record
+            .get("sources")
+            .and_then(|sources| sources.get(field))
+            .and_then(Value::as_str);
+        let line = match (record.get(field), source) {
+            (Some(Value::String(value)), Some(source)) => format!("{} ({})", value, source),
+            (Some(value), Some(source)) => format!("{} ({})", value, source),
+            _ => "-".to_string(),
+        };
+        out.push_str(&format!("\n  {:<9} {}", format!("{}:", field), line));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(payload: Value) -> JWToken {
+        JWToken {
+            header: json!({"alg": "RS256"}),
+            payload,
+            signature: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn identity_test() {
+        let vendors = Vendors::builtin();
+        let azure = vendors.identify(&token(json!({
+            "iss": "https://login.microsoftonline.com/72f988bf/v2.0",
+            "sub": "pairwise-sub", "oid": "0b1c", "upn": "alice@contoso.com",
+            "name": "Alice", "tid": "72f988bf"
+        })));
+        assert_eq!(
+            azure,
+            json!({"vendor": "azure", "issuer": "https://login.microsoftonline.com/72f988bf/v2.0",
+                   "id": "0b1c", "username": "alice@contoso.com", "email": "alice@contoso.com",
+                   "name": "Alice", "tenant": "72f988bf",
+                   "sources": {"id": "oid", "username": "upn", "email": "upn", "name": "name",
+                               "tenant": "tid"}})
+        );
+        let okta = vendors.identify(&token(json!({
+            "iss": "https://acme.okta.com/oauth2/default", "sub": "alice@acme.com", "uid": "00u1"
+        })));
+        assert_eq!(
+            (&okta["id"], &okta["username"]),
+            (&json!("00u1"), &json!("alice@acme.com"))
+        );
+        let unknown = vendors.identify(&token(json!({"sub": "42"})));
+        assert_eq!(
+            (&unknown["vendor"], &unknown["id"]),
+            (&json!("generic"), &json!("42"))
+        );
+        assert_eq!(unknown["email"], Value::Null);
+        assert_eq!(
+            render_text(&unknown),
+            "identity: generic\n  id:       42 (sub)\n  username: -\n  email:    -\n  name:     -\n  tenant:   -"
+        );
+
+        let config = crate::yaml::parse(
+            "vendors:\n  - name: acme\n    issuer: idp.acme.example\n    fields:\n      id: [employee_id, sub]\n  - name: azure\n    fields:\n      email: [mail]\n",
+        )
+        .unwrap();
+        let custom = Vendors::from_config(&config).unwrap();
+        let acme = custom.identify(&token(json!({
+            "iss": "https://idp.acme.example", "sub": "s", "employee_id": 7, "email": "a@acme.example"
+        })));
+        assert_eq!(
+            (&acme["vendor"], &acme["id"], &acme["email"]),
+            (&json!("acme"), &json!(7), &json!("a@acme.example"))
+        );
+        let azure = custom.identify(&token(json!({
+            "iss": "https://sts.windows.net/t/", "upn": "bob@contoso.com", "mail": "bob@mail.example"
+        })));
+        assert_eq!(azure["email"], "bob@mail.example");
+        for bad in [
+            "vendors:\n  - name: acme\n",
+            "vendors:\n  - name: acme\n    issuer: x\n    fields:\n      phone: [phone_number]\n",
+            "vendors: acme\n",
+        ] {
+            let config = crate::yaml::parse(bad).unwrap();
+            assert!(Vendors::from_config(&config).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_vendors_test() {
+        let error_of = |config: Value| error_message!(ConfigError, Vendors::from_config(&config));
+        assert_eq!(
+            error_of(json!({"vendors": "acme"})),
+            "expected a `vendors` list"
+        );
+        assert_eq!(
+            error_of(json!({"vendors": [{"issuer": "https://acme.example"}]})),
+            "vendors: every vendor needs a `name`"
+        );
+        assert_eq!(
+            error_of(json!({"vendors": [{"name": "acme", "issuer": 7}]})),
+            "vendor `acme`: `issuer` must be a list of strings"
+        );
+        assert_eq!(
+            error_of(json!({"vendors": [{"name": "acme", "issuer": "x", "fields": ["email"]}]})),
+            "vendor `acme`: `fields` must map fields to claims"
+        );
+        assert_eq!(
+            error_of(
+                json!({"vendors": [{"name": "acme", "issuer": "x", "fields": {"phone": "tel"}}]})
+            ),
+            "vendor `acme`: `phone` is not one of id, username, email, name, tenant"
+        );
+        assert_eq!(
+            error_of(
+                json!({"vendors": [{"name": "acme", "issuer": "x", "fields": {"email": [1]}}]})
+            ),
+            "vendor `acme`: email must be a list of strings"
+        );
+        assert_eq!(
+            error_of(json!({"vendors": [{"name": "acme"}]})),
+            "vendor `acme`: needs an `issuer`, since it is not a preset"
+        );
+    }
+}
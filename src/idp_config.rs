@@ -0,0 +1,846 @@
+//! `--idp-config`: traces each of a token's claims to the part of the
+//! identity provider's configuration that puts it there, from a Keycloak
+//! realm export or an Auth0 tenant export.
+//!
+//! A Keycloak claim comes from a protocol mapper, on the client or in a
+//! client scope. Client mappers are in effect for the client named by
+//! `azp`; client scope mappers for the client's default scopes and the ones
+//! in the token's `scope`. Each mapper also says whether it adds its claim
+//! to access tokens, ID tokens or both.
+//!
+//! An Auth0 claim beyond the standard ones comes from an Action's
+//! `api.idToken.setCustomClaim` or `api.accessToken.setCustomClaim`, or a
+//! Rule's `context.idToken[...]` or `context.accessToken[...]`, called with
+//! a literal claim name. The `code` of an Action and the `script` of a Rule
+//! may be inline or, as the deploy CLI exports them, a path to a file.
+//!
+//! A claim is inconsistent with the configuration when nothing in effect
+//! puts it in this kind of token. So is a `scope` value the export doesn't
+//! define. A Keycloak mapper in effect whose claim is absent is only noted,
+//! since mappers skip user attributes that are unset.
+
+use crate::profile::Kind;
+use crate::{JWTError, JWToken};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Vendor {
+    Keycloak,
+    Auth0,
+}
+
+/// Where a mapper is configured.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Owner {
+    Client(String),
+    Scope(String),
+    Action(String),
+    Rule(String),
+}
+
+impl std::fmt::Display for Owner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Owner::Client(id) => write!(f, "client `{}`", id),
+            Owner::Scope(name) => write!(f, "client scope `{}`", name),
+            Owner::Action(name) => write!(f, "action `{}`", name),
+            Owner::Rule(name) => write!(f, "rule `{}`", name),
+        }
+    }
+}
+
+/// Something in the export that puts a claim in tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapper {
+    /// The claim, as Keycloak's `claim.name`: `.` nests, `\.` is a dot, and
+    /// `${client_id}` is the token's client.
+    pub claim: String,
+    pub name: String,
+    /// The protocol mapper type, or `action` or `rule`.
+    pub kind: String,
+    pub owner: Owner,
+    pub access_token: bool,
+    pub id_token: bool,
+    /// False for a disabled rule or an action not on the login flow.
+    pub enabled: bool,
+}
+
+impl Mapper {
+    fn describe(&self) -> String {
+        if self.kind == "action" || self.kind == "rule" {
+            self.owner.to_string()
+        } else {
+            format!("mapper `{}` ({}), {}", self.name, self.kind, self.owner)
+        }
+    }
+
+    fn path(&self, client: Option<&str>) -> Vec<String> {
+        let claim = client.map_or(self.claim.clone(), |client| {
+            self.claim.replace("${client_id}", client)
+        });
+        let mut path = vec![String::new()];
+        let mut chars = claim.chars();
+        while let Some(c) = chars.next() {
+            match (c, path.last_mut()) {
+                ('\\', Some(segment)) => segment.extend(chars.next()),
+                ('.', _) => path.push(String::new()),
+                (c, Some(segment)) => segment.push(c),
+                (_, None) => {}
+            }
+        }
+        path
+    }
+}
+
+pub struct Export {
+    pub vendor: Vendor,
+    /// The realm or tenant.
+    pub name: String,
+    pub mappers: Vec<Mapper>,
+    /// The scopes the export defines.
+    pub scopes: Vec<String>,
+    /// Each client's id and default client scopes.
+    pub clients: Vec<(String, Vec<String>)>,
+    /// The scopes of clients that list none.
+    pub default_scopes: Vec<String>,
+}
+
+/// Claims no mapper is needed for: JWT's registered ones and those the
+/// protocol itself adds.
+pub const REGISTERED: &[&str] = &[
+    "iss",
+    "sub",
+    "aud",
+    "exp",
+    "nbf",
+    "iat",
+    "jti",
+    "azp",
+    "nonce",
+    "auth_time",
+    "at_hash",
+    "c_hash",
+    "acr",
+    "amr",
+    "sid",
+    "scope",
+    "typ",
+    "session_state",
+    "gty",
+    "permissions",
+    "org_id",
+    "client_id",
+    "cnf",
+];
+
+/// The OpenID Connect scopes, and the standard claims Auth0 adds for them.
+pub const OIDC_SCOPES: &[(&str, &[&str])] = &[
+    ("openid", &[]),
+    (
+        "profile",
+        &[
+            "name",
+            "family_name",
+            "given_name",
+            "middle_name",
+            "nickname",
+            "preferred_username",
+            "profile",
+            "picture",
+            "website",
+            "gender",
+            "birthdate",
+            "zoneinfo",
+            "locale",
+            "updated_at",
+        ],
+    ),
+    ("email", &["email", "email_verified"]),
+    ("address", &["address"]),
+    ("phone", &["phone_number", "phone_number_verified"]),
+    ("offline_access", &[]),
+];
+
+/// Keycloak mapper types that add a fixed claim and have no `claim.name`.
+const IMPLIED_CLAIMS: &[(&str, &str)] = &[
+    ("oidc-audience-mapper", "aud"),
+    ("oidc-audience-resolve-mapper", "aud"),
+    ("oidc-allowed-origins-mapper", "allowed-origins"),
+    ("oidc-acr-mapper", "acr"),
+    ("oidc-sub-mapper", "sub"),
+    ("oidc-full-name-mapper", "name"),
+    ("oidc-address-mapper", "address"),
+];
+
+fn config_error(message: &str) -> JWTError {
+    JWTError::ConfigError(format!("IdP export: {}", message).into())
+}
+
+fn strings(value: Option<&Value>) -> Vec<String> {
+    value.and_then(Value::as_array).map_or(Vec::new(), |items| {
+        items
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect()
+    })
+}
+
+fn text<'a>(value: &'a Value, key: &str) -> &'a str {
+    value.get(key).and_then(Value::as_str).unwrap_or("")
+}
+
+fn keycloak_mappers(owner: Owner, object: &Value, mappers: &mut Vec<Mapper>) {
+    for mapper in object
+        .get("protocolMappers")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        if mapper.get("protocol").and_then(Value::as_str) == Some("saml") {
+            continue;
+        }
+        let kind = text(mapper, "protocolMapper");
+        let config = mapper.get("config");
+        let setting = |key: &str| config.and_then(|c| c.get(key)).and_then(Value::as_str);
+        let claim = setting("claim.name").map(str::to_string).or_else(|| {
+            IMPLIED_CLAIMS
+                .iter()
+                .find(|(implied, _)| *implied == kind)
+                .map(|(_, claim)| claim.to_string())
+        });
+        if let Some(claim) = claim {
+            mappers.push(Mapper {
+                claim,
+                name: text(mapper, "name").to_string(),
+                kind: kind.to_string(),
+                owner: owner.clone(),
+                access_token: setting("access.token.claim") != Some("false"),
+                id_token: setting("id.token.claim") != Some("false"),
+                enabled: true,
+            });
+        }
+    }
+}
+
+/// The first argument's claim name, when it is a string literal.
+fn literal(code: &str) -> Option<String> {
+    let code = code.trim_start();
+    let quote = code.chars().next().filter(|c| "'\"`".contains(*c))?;
+    let mut name = String::new();
+    let mut chars = code.chars().skip(1);
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => name.extend(chars.next()),
+            '$' if quote == '`' => return None,
+            c if c == quote => return Some(name),
+            c => name.push(c),
+        }
+    }
+    None
+}
+
+/// The claims `code` sets with a literal name, and for which tokens.
+fn custom_claims(code: &str, calls: &[(&str, bool)]) -> Vec<(String, bool)> {
+    let mut claims = Vec::new();
+    for (call, id_token) in calls {
+        for (at, _) in code.match_indices(call) {
+            if let Some(name) = code.get(at + call.len()..).and_then(literal) {
+                claims.push((name, *id_token));
+            }
+        }
+    }
+    claims
+}
+
+fn auth0_code(
+    item: &Value,
+    key: &str,
+    owner: Owner,
+    kind: &str,
+    enabled: bool,
+    read: &dyn Fn(&str) -> Option<String>,
+    mappers: &mut Vec<Mapper>,
+) {
+    let code = text(item, key);
+    let code = if code.ends_with(".js") && !code.contains('\n') {
+        read(code).unwrap_or_default()
+    } else {
+        code.to_string()
+    };
+    let calls: &[(&str, bool)] = if kind == "action" {
+        &[
+            ("api.idToken.setCustomClaim(", true),
+            ("api.accessToken.setCustomClaim(", false),
+        ]
+    } else {
+        &[("context.idToken[", true), ("context.accessToken[", false)]
+    };
+    for (claim, id_token) in custom_claims(&code, calls) {
+        mappers.push(Mapper {
+            claim: claim.replace('.', "\\."),
+            name: owner.to_string(),
+            kind: kind.to_string(),
+            owner: owner.clone(),
+            access_token: !id_token,
+            id_token,
+            enabled,
+        });
+    }
+}
+
+impl Export {
+    /// Reads a Keycloak realm export or an Auth0 tenant export. `read`
+    /// loads the files an Auth0 export's code paths name.
+    pub fn from_value(
+        export: &Value,
+        read: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Export, JWTError> {
+        let mut mappers = Vec::new();
+        if export.get("realm").is_some_and(Value::is_string) {
+            let mut scopes = Vec::new();
+            for scope in export
+                .get("clientScopes")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+            {
+                let name = text(scope, "name").to_string();
+                keycloak_mappers(Owner::Scope(name.clone()), scope, &mut mappers);
+                scopes.push(name);
+            }
+            let mut clients = Vec::new();
+            for client in export
+                .get("clients")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+            {
+                let id = text(client, "clientId").to_string();
+                keycloak_mappers(Owner::Client(id.clone()), client, &mut mappers);
+                clients.push((id, strings(client.get("defaultClientScopes"))));
+            }
+            return Ok(Export {
+                vendor: Vendor::Keycloak,
+                name: text(export, "realm").to_string(),
+                mappers,
+                scopes,
+                clients,
+                default_scopes: strings(export.get("defaultDefaultClientScopes")),
+            });
+        }
+        if !["actions", "rules", "resourceServers", "tenant"]
+            .iter()
+            .any(|key| export.get(key).is_some())
+        {
+            return Err(config_error(
+                "expected a Keycloak realm export or an Auth0 tenant export",
+            ));
+        }
+        for action in export
+            .get("actions")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let triggers = action.get("supported_triggers").and_then(Value::as_array);
+            let on_login = triggers.is_none_or(|triggers| {
+                triggers
+                    .iter()
+                    .any(|trigger| text(trigger, "id") == "post-login")
+            });
+            let owner = Owner::Action(text(action, "name").to_string());
+            auth0_code(
+                action,
+                "code",
+                owner,
+                "action",
+                on_login,
+                read,
+                &mut mappers,
+            );
+        }
+        for rule in export
+            .get("rules")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let enabled = rule.get("enabled").and_then(Value::as_bool) != Some(false);
+            let owner = Owner::Rule(text(rule, "name").to_string());
+            auth0_code(rule, "script", owner, "rule", enabled, read, &mut mappers);
+        }
+        let scopes = export
+            .get("resourceServers")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .flat_map(|server| {
+                server
+                    .get("scopes")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+            })
+            .map(|scope| text(scope, "value").to_string())
+            .collect();
+        let name = export
+            .get("tenant")
+            .and_then(|tenant| tenant.get("friendly_name"))
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .to_string();
+        Ok(Export {
+            vendor: Vendor::Auth0,
+            name,
+            mappers,
+            scopes,
+            clients: Vec::new(),
+            default_scopes: Vec::new(),
+        })
+    }
+}
+
+/// Where one claim comes from, or why it shouldn't be there.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trace {
+    /// The mappers in effect that put it there.
+    Mapped(Vec<usize>),
+    Registered,
+    /// A standard claim of an OpenID Connect scope.
+    Scope(&'static str),
+    /// Mappers that would put it there, and why none is in effect.
+    NotInEffect(Vec<usize>, String),
+    Untraced,
+}
+
+impl Trace {
+    pub fn consistent(&self) -> bool {
+        matches!(self, Trace::Mapped(_) | Trace::Registered | Trace::Scope(_))
+    }
+}
+
+pub struct Report {
+    pub kind: Kind,
+    pub client: Option<String>,
+    pub claims: Vec<(String, Trace)>,
+    /// Mappers in effect whose claim the token lacks.
+    pub absent: Vec<usize>,
+    /// `scope` values the export doesn't define.
+    pub unknown_scopes: Vec<String>,
+}
+
+impl Report {
+    pub fn inconsistencies(&self) -> usize {
+        self.claims.iter().filter(|(_, t)| !t.consistent()).count() + self.unknown_scopes.len()
+    }
+}
+
+fn present(payload: &Value, path: &[String]) -> bool {
+    path.iter()
+        .try_fold(payload, |value, segment| value.get(segment))
+        .is_some()
+}
+
+/// Traces every claim of `token` through `export`.
+pub fn check(export: &Export, token: &JWToken) -> Report {
+    let id_token = match token.payload.get("typ").and_then(Value::as_str) {
+        Some("ID") => true,
+        Some("Bearer") => false,
+        // ID tokens carry no `scope`, whatever `detect` guesses
+        _ => Kind::detect(token) == Kind::IdToken && token.payload.get("scope").is_none(),
+    };
+    let kind = if id_token {
+        Kind::IdToken
+    } else {
+        Kind::AccessToken
+    };
+    let client = token
+        .payload
+        .get("azp")
+        .or_else(|| token.payload.get("client_id"))
+        .and_then(Value::as_str);
+    let scope: Vec<&str> = token
+        .payload
+        .get("scope")
+        .and_then(Value::as_str)
+        .map_or(Vec::new(), |scope| scope.split_whitespace().collect());
+    let default_scopes = export
+        .clients
+        .iter()
+        .find(|(id, _)| Some(id.as_str()) == client)
+        .map(|(_, scopes)| scopes)
+        .filter(|scopes| !scopes.is_empty())
+        .unwrap_or(&export.default_scopes);
+    // why the mapper is not in effect for this token, if it isn't
+    let excluded = |mapper: &Mapper| -> Option<String> {
+        match &mapper.owner {
+            Owner::Client(id) if Some(id.as_str()) != client => Some(format!(
+                "the token's client is `{}`",
+                client.unwrap_or("(none)")
+            )),
+            Owner::Scope(name)
+                if !default_scopes.contains(name) && !scope.contains(&name.as_str()) =>
+            {
+                Some(format!(
+                    "`{}` is neither a default scope of the client nor in the token's scope",
+                    name
+                ))
+            }
+            _ if !mapper.enabled => Some(format!("the {} is not enabled", mapper.kind)),
+            _ if id_token && !mapper.id_token => {
+                Some("it adds it to access tokens only".to_string())
+            }
+            _ if !id_token && !mapper.access_token => {
+                Some("it adds it to ID tokens only".to_string())
+            }
+            _ => None,
+        }
+    };
+    let mut claims = Vec::new();
+    for name in token.payload.as_object().into_iter().flat_map(|o| o.keys()) {
+        let matching: Vec<usize> = export
+            .mappers
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.path(client).first() == Some(name))
+            .map(|(i, _)| i)
+            .collect();
+        let (effective, ineffective): (Vec<usize>, Vec<usize>) =
+            matching.into_iter().partition(|i| {
+                export
+                    .mappers
+                    .get(*i)
+                    .is_some_and(|m| excluded(m).is_none())
+            });
+        let standard = OIDC_SCOPES
+            .iter()
+            .find(|(_, claims)| claims.contains(&name.as_str()))
+            .map(|(scope, _)| *scope)
+            .filter(|_| export.vendor == Vendor::Auth0);
+        let trace = if !effective.is_empty() {
+            Trace::Mapped(effective)
+        } else if REGISTERED.contains(&name.as_str()) {
+            Trace::Registered
+        } else if let Some(scope) = standard {
+            Trace::Scope(scope)
+        } else if let Some(why) = ineffective
+            .first()
+            .and_then(|i| export.mappers.get(*i))
+            .and_then(&excluded)
+        {
+            Trace::NotInEffect(ineffective, why)
+        } else {
+            Trace::Untraced
+        };
+        claims.push((name.clone(), trace));
+    }
+    let absent = export
+        .mappers
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| export.vendor == Vendor::Keycloak && excluded(m).is_none())
+        .filter(|(_, m)| !present(&token.payload, &m.path(client)))
+        .map(|(i, _)| i)
+        .collect();
+    let unknown_scopes = scope
+        .iter()
+        .filter(|s| !export.scopes.iter().any(|defined| defined == *s))
+        .filter(|s| !OIDC_SCOPES.iter().any(|(oidc, _)| oidc == *s))
+        .map(|s| s.to_string())
+        .collect();
+    Report {
+        kind,
+        client: client.map(str::to_string),
+        claims,
+        absent,
+        unknown_scopes,
+    }
+}
+
+fn mappers_text(export: &Export, indices: &[usize]) -> String {
+    indices
+        .iter()
+        .filter_map(|i| export.mappers.get(*i))
+        .map(Mapper::describe)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn trace_text(export: &Export, trace: &Trace) -> String {
+    match trace {
+        Trace::Mapped(indices) => mappers_text(export, indices),
+        Trace::Registered => "registered claim".to_string(),
+        Trace::Scope(scope) => format!("standard claim of the `{}` scope", scope),
+        Trace::NotInEffect(indices, why) => {
+            format!("{}, not in effect: {}", mappers_text(export, indices), why)
+        }
+        Trace::Untraced => "nothing in the export puts it in tokens".to_string(),
+    }
+}
+
+fn heading(export: &Export, report: &Report) -> String {
+    let vendor = match export.vendor {
+        Vendor::Keycloak => format!("keycloak realm `{}`", export.name),
+        Vendor::Auth0 => format!("auth0 tenant `{}`", export.name),
+    };
+    format!(
+        "{}, {} for client `{}`",
+        vendor,
+        report.kind,
+        report.client.as_deref().unwrap_or("(none)")
+    )
+}
+
+/// One `pass`/`FAIL` line per claim, then notes, unknown scopes and a count.
+pub fn render_text(export: &Export, report: &Report) -> String {
+    let width = report
+        .claims
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let mut out = format!("{}\n", heading(export, report));
+    for (name, trace) in &report.claims {
+        out.push_str(&format!(
+            "{} {:w$}  {}\n",
+            if trace.consistent() { "pass" } else { "FAIL" },
+            name,
+            trace_text(export, trace),
+            w = width
+        ));
+    }
+    for scope in &report.unknown_scopes {
+        out.push_str(&format!(
+            "FAIL scope `{}` is not defined in the export\n",
+            scope
+        ));
+    }
+    for mapper in report.absent.iter().filter_map(|i| export.mappers.get(*i)) {
+        out.push_str(&format!(
+            "note: {} is in effect, but the token has no `{}`; its source may be unset for this user\n",
+            mapper.describe(),
+            mapper.claim
+        ));
+    }
+    let inconsistent = report.inconsistencies();
+    out.push_str(&format!(
+        "{} claim(s) traced, {} inconsistency(ies)",
+        report.claims.len(),
+        inconsistent
+    ));
+    out
+}
+
+pub fn to_json(export: &Export, report: &Report) -> Value {
+    let sources = |indices: &[usize]| -> Vec<Value> {
+        indices
+            .iter()
+            .filter_map(|i| export.mappers.get(*i))
+            .map(|m| {
+                json!({
+                    "name": m.name,
+                    "type": m.kind,
+                    "source": m.owner.to_string(),
+                })
+            })
+            .collect()
+    };
+    json!({
+        "export": heading(export, report),
+        "kind": report.kind.to_string(),
+        "client": report.client,
+        "claims": report.claims.iter().map(|(name, trace)| {
+            let (status, mappers, detail) = match trace {
+                Trace::Mapped(indices) => ("mapped", sources(indices), None),
+                Trace::Registered => ("registered", Vec::new(), None),
+                Trace::Scope(scope) => ("scope", Vec::new(), Some(scope.to_string())),
+                Trace::NotInEffect(indices, why) => {
+                    ("not-in-effect", sources(indices), Some(why.clone()))
+                }
+                Trace::Untraced => ("untraced", Vec::new(), None),
+            };
+            json!({
+                "claim": name,
+                "status": status,
+                "consistent": trace.consistent(),
+                "mappers": mappers,
+                "detail": detail,
+            })
+        }).collect::<Vec<_>>(),
+        "unknown_scopes": report.unknown_scopes,
+        "absent": sources(&report.absent),
+        "inconsistencies": report.inconsistencies(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(header: Value, payload: Value) -> JWToken {
+        JWToken {
+            header,
+            payload,
+            signature: Vec::new(),
+        }
+    }
+
+    fn mapper(name: &str, kind: &str, claim: &str, access: bool) -> Value {
+        json!({
+            "name": name,
+            "protocol": "openid-connect",
+            "protocolMapper": kind,
+            "config": {
+                "claim.name": claim,
+                "access.token.claim": access.to_string(),
+                "id.token.claim": "true",
+            },
+        })
+    }
+
+    #[test]
+    fn keycloak_realm_test() {
+        let realm = json!({
+            "realm": "acme",
+            "defaultDefaultClientScopes": ["roles"],
+            "clientScopes": [
+                {"name": "email", "protocolMappers": [
+                    mapper("email", "oidc-usermodel-property-mapper", "email", true),
+                ]},
+                {"name": "roles", "protocolMappers": [
+                    mapper("realm roles", "oidc-usermodel-realm-role-mapper", "realm_access.roles", true),
+                    mapper("client roles", "oidc-usermodel-client-role-mapper", "resource_access.${client_id}.roles", true),
+                    {"name": "audience resolve", "protocolMapper": "oidc-audience-resolve-mapper", "config": {}},
+                ]},
+                {"name": "groups", "protocolMappers": [
+                    mapper("groups", "oidc-group-membership-mapper", "groups", true),
+                ]},
+                {"name": "phone", "protocolMappers": [
+                    mapper("phone number", "oidc-usermodel-attribute-mapper", "phone_number", false),
+                ]},
+            ],
+            "clients": [
+                {"clientId": "web-app", "defaultClientScopes": ["email", "roles"], "protocolMappers": [
+                    mapper("tenant", "oidc-hardcoded-claim-mapper", "https://acme\\.example/tenant", true),
+                ]},
+            ],
+        });
+        let export = Export::from_value(&realm, &|_| None).unwrap();
+        assert_eq!(export.vendor, Vendor::Keycloak);
+        assert_eq!(export.mappers.len(), 7);
+
+        let report = check(
+            &export,
+            &token(
+                json!({"alg": "RS256"}),
+                json!({
+                    "typ": "Bearer",
+                    "azp": "web-app",
+                    "scope": "email phone admin",
+                    "aud": "account",
+                    "email": "alice@acme.example",
+                    "realm_access": {"roles": ["user"]},
+                    "https://acme.example/tenant": "eu",
+                    "phone_number": "+1 555 0100",
+                    "groups": ["/staff"],
+                    "department": "finance",
+                }),
+            ),
+        );
+        let trace = |claim: &str| {
+            report
+                .claims
+                .iter()
+                .find(|(name, _)| name == claim)
+                .map(|(_, trace)| trace.clone())
+                .unwrap()
+        };
+        assert_eq!(trace("email"), Trace::Mapped(vec![0]));
+        assert_eq!(trace("aud"), Trace::Mapped(vec![3]));
+        assert_eq!(trace("https://acme.example/tenant"), Trace::Mapped(vec![6]));
+        assert_eq!(trace("typ"), Trace::Registered);
+        assert_eq!(trace("department"), Trace::Untraced);
+        assert!(matches!(
+            trace("groups"),
+            Trace::NotInEffect(_, why) if why.contains("`groups` is neither a default scope")
+        ));
+        assert!(matches!(
+            trace("phone_number"),
+            Trace::NotInEffect(_, why) if why == "it adds it to ID tokens only"
+        ));
+        assert_eq!(report.unknown_scopes, vec!["admin"]);
+        assert_eq!(report.inconsistencies(), 4);
+        // resource_access.web-app.roles is in effect but absent
+        assert_eq!(report.absent, vec![2]);
+
+        let text = render_text(&export, &report);
+        assert!(text.starts_with("keycloak realm `acme`, access-token for client `web-app`\n"));
+        assert!(text.contains(
+            "pass email                        mapper `email` (oidc-usermodel-property-mapper), client scope `email`\n"
+        ));
+        assert!(text.contains("FAIL scope `admin` is not defined in the export\n"));
+        assert!(text.contains("the token has no `resource_access.${client_id}.roles`"));
+        assert!(text.ends_with("10 claim(s) traced, 4 inconsistency(ies)"));
+        assert_eq!(to_json(&export, &report)["inconsistencies"], 4);
+    }
+
+    #[test]
+    fn auth0_export_test() {
+        let tenant = json!({
+            "tenant": {"friendly_name": "Acme"},
+            "resourceServers": [{"identifier": "https://api.acme.example", "scopes": [{"value": "read:orders"}]}],
+            "actions": [
+                {"name": "add-roles", "code": "./actions/add-roles/code.js",
+                 "supported_triggers": [{"id": "post-login", "version": "v3"}]},
+                {"name": "m2m", "code": "api.accessToken.setCustomClaim(\"https://acme.example/m2m\", true);",
+                 "supported_triggers": [{"id": "credentials-exchange"}]},
+            ],
+            "rules": [
+                {"name": "legacy", "enabled": false,
+                 "script": "function (user, context, cb) { context.idToken['https://acme.example/plan'] = 'pro'; }"},
+            ],
+        });
+        let read = |path: &str| {
+            (path == "./actions/add-roles/code.js").then(|| {
+                "exports.onExecutePostLogin = async (event, api) => {\n  api.idToken.setCustomClaim('https://acme.example/roles', event.authorization.roles);\n  api.accessToken.setCustomClaim(`${ns}/dynamic`, 1);\n};".to_string()
+            })
+        };
+        let export = Export::from_value(&tenant, &read).unwrap();
+        assert_eq!(export.vendor, Vendor::Auth0);
+        assert_eq!(export.mappers.len(), 3);
+
+        let report = check(
+            &export,
+            &token(
+                json!({"alg": "RS256"}),
+                json!({
+                    "iss": "https://acme.eu.auth0.com/",
+                    "aud": "client-1",
+                    "nonce": "n",
+                    "name": "Alice",
+                    "https://acme.example/roles": ["admin"],
+                    "https://acme.example/plan": "pro",
+                }),
+            ),
+        );
+        assert_eq!(report.kind, Kind::IdToken);
+        let traces: Vec<&Trace> = report.claims.iter().map(|(_, t)| t).collect();
+        assert!(traces.contains(&&Trace::Scope("profile")));
+        assert!(traces.contains(&&Trace::Mapped(vec![0])));
+        assert!(traces
+            .iter()
+            .any(|t| matches!(t, Trace::NotInEffect(_, why) if why == "the rule is not enabled")));
+        assert_eq!(report.inconsistencies(), 1);
+        assert!(render_text(&export, &report)
+            .contains("pass https://acme.example/roles  action `add-roles`\n"));
+
+        for unrelated in [json!({"unrelated": true}), json!([]), json!("realm")] {
+            assert!(matches!(
+                Export::from_value(&unrelated, &|_| None),
+                Err(JWTError::ConfigError(e))
+                    if e == "IdP export: expected a Keycloak realm export or an Auth0 tenant export"
+            ));
+        }
+    }
+}
@@ -0,0 +1,540 @@
+//! `jwt-check interop`: signs with every algorithm and has OpenSSL verify
+//! the result, then the other way round, so encoding mismatches such as
+//! ECDSA signatures in DER instead of JOSE's raw `r || s` show up as a
+//! failed cell rather than as a bug report. The RFC examples from
+//! `conformance` fill a third column, as tokens minted elsewhere.
+//!
+//! OpenSSL only sees signatures and signing inputs: this side builds the
+//! tokens, converts ECDSA signatures between DER and `r || s`, and runs the
+//! PSS checks with the salt as long as the hash, as RFC 7518 requires.
+
+use jwt_check::keys::{self, SigningKey, VerifyingKey};
+use jwt_check::verify::{Algorithm, ALGORITHMS};
+use jwt_check::x509::ecdsa_signature_from_der;
+use jwt_check::{conformance, parse, sign, JWTError};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const SECRET: &[u8] = b"jwt-check interop secret, long enough for HS512";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Pass,
+    Fail(String),
+    /// Not run, and why.
+    Skipped(String),
+}
+
+impl Cell {
+    fn from_result(result: Result<(), String>) -> Cell {
+        match result {
+            Ok(()) => Cell::Pass,
+            Err(why) => Cell::Fail(why),
+        }
+    }
+
+    fn short(&self) -> &'static str {
+        match self {
+            Cell::Pass => "pass",
+            Cell::Fail(_) => "FAIL",
+            Cell::Skipped(_) => "-",
+        }
+    }
+}
+
+/// One algorithm's row of the matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub alg: Algorithm,
+    /// Signed here, verified by OpenSSL.
+    pub to_openssl: Cell,
+    /// Signed by OpenSSL, verified here.
+    pub from_openssl: Cell,
+    /// The RFC examples for this algorithm, verified here.
+    pub vectors: Cell,
+}
+
+/// A scratch directory for keys and signatures, removed when dropped.
+struct Scratch(PathBuf);
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+struct Openssl<'a> {
+    program: &'a str,
+    dir: &'a Path,
+}
+
+impl Openssl<'_> {
+    fn run(&self, args: &[&str]) -> Result<Vec<u8>, String> {
+        let output = Command::new(self.program)
+            .args(args)
+            .current_dir(self.dir)
+            .output()
+            .map_err(|e| format!("cannot run {}: {}", self.program, e))?;
+        if output.status.success() {
+            Ok(output.stdout)
+        } else {
+            Err(format!(
+                "openssl {} failed: {}",
+                args.first().copied().unwrap_or_default(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+
+    fn write(&self, name: &str, bytes: &[u8]) -> Result<(), String> {
+        std::fs::write(self.dir.join(name), bytes).map_err(|e| e.to_string())
+    }
+
+    fn read(&self, name: &str) -> Result<String, String> {
+        std::fs::read_to_string(self.dir.join(name)).map_err(|e| e.to_string())
+    }
+
+    /// Generates a private key and its public half, returning both PEMs.
+    fn keypair(&self, name: &str, options: &[&str]) -> Result<(String, String), String> {
+        let private = format!("{}.pem", name);
+        let public = format!("{}.pub.pem", name);
+        let mut args = vec!["genpkey", "-out", private.as_str()];
+        args.extend_from_slice(options);
+        self.run(&args)?;
+        self.run(&["pkey", "-in", &private, "-pubout", "-out", &public])?;
+        Ok((self.read(&private)?, self.read(&public)?))
+    }
+}
+
+/// The `genpkey` options and file name of the key each family uses.
+fn key_spec(alg: Algorithm) -> Option<(&'static str, &'static [&'static str])> {
+    match alg {
+        Algorithm::ES256 => Some((
+            "p256",
+            &["-algorithm", "EC", "-pkeyopt", "ec_paramgen_curve:P-256"],
+        )),
+        Algorithm::ES384 => Some((
+            "p384",
+            &["-algorithm", "EC", "-pkeyopt", "ec_paramgen_curve:P-384"],
+        )),
+        Algorithm::ES256K => Some((
+            "secp256k1",
+            &[
+                "-algorithm",
+                "EC",
+                "-pkeyopt",
+                "ec_paramgen_curve:secp256k1",
+            ],
+        )),
+        Algorithm::EdDSA => Some(("ed25519", &["-algorithm", "ed25519"])),
+        alg if alg.is_symmetric() => None,
+        _ => Some((
+            "rsa",
+            &["-algorithm", "RSA", "-pkeyopt", "rsa_keygen_bits:2048"],
+        )),
+    }
+}
+
+fn digest_flag(alg: Algorithm) -> String {
+    format!("-{}", alg.hash().name().to_lowercase().replace('-', ""))
+}
+
+/// The extra `dgst` options for an algorithm's padding.
+fn padding(alg: Algorithm) -> &'static [&'static str] {
+    match alg {
+        Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512 => &[
+            "-sigopt",
+            "rsa_padding_mode:pss",
+            "-sigopt",
+            "rsa_pss_saltlen:digest",
+        ],
+        _ => &[],
+    }
+}
+
+/// A raw `r || s` ECDSA signature as the DER `SEQUENCE` OpenSSL uses.
+pub fn ecdsa_to_der(raw: &[u8]) -> Vec<u8> {
+    let (r, s) = raw.split_at(raw.len() / 2);
+    let integer = |half: &[u8]| {
+        let trimmed: Vec<u8> = half.iter().copied().skip_while(|b| *b == 0).collect();
+        let mut contents = match trimmed.first() {
+            None => vec![0],
+            Some(b) if b & 0x80 != 0 => std::iter::once(0).chain(trimmed).collect(),
+            Some(_) => trimmed,
+        };
+        let mut tlv = vec![keys::TAG_INTEGER, contents.len() as u8];
+        tlv.append(&mut contents);
+        tlv
+    };
+    let mut body = integer(r);
+    body.extend(integer(s));
+    let mut der = vec![keys::TAG_SEQUENCE];
+    if body.len() >= 0x80 {
+        der.push(0x81);
+    }
+    der.push(body.len() as u8);
+    der.extend(body);
+    der
+}
+
+fn b64(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn header(alg: Algorithm) -> Value {
+    json!({"alg": alg.to_string(), "typ": "JWT"})
+}
+
+fn claims() -> Value {
+    json!({"sub": "interop", "iat": jwt_check::unix_now()})
+}
+
+/// Signs here; OpenSSL verifies (or, for HMAC, recomputes) the signature.
+fn to_openssl(
+    openssl: &Openssl,
+    alg: Algorithm,
+    key: &SigningKey,
+    public: Option<&str>,
+) -> Result<(), String> {
+    let token = sign::sign(&header(alg), &claims(), key).map_err(|e| e.to_string())?;
+    let (input, signature) = token.rsplit_once('.').unwrap_or_default();
+    let signature =
+        base64::decode_config(signature, base64::URL_SAFE_NO_PAD).map_err(|e| e.to_string())?;
+    openssl.write("input", input.as_bytes())?;
+    let digest = digest_flag(alg);
+    let public = match public {
+        None => {
+            let macopt = format!("hexkey:{}", hex(SECRET));
+            let mac = openssl.run(&[
+                "dgst", &digest, "-mac", "HMAC", "-macopt", &macopt, "-binary", "input",
+            ])?;
+            return if mac == signature {
+                Ok(())
+            } else {
+                Err(format!("OpenSSL computes {}", hex(&mac)))
+            };
+        }
+        Some(public) => public,
+    };
+    let signature = match alg.curve() {
+        Some(_) => ecdsa_to_der(&signature),
+        None => signature,
+    };
+    openssl.write("signature", &signature)?;
+    let args = if alg == Algorithm::EdDSA {
+        // Ed25519 takes the message itself, which `dgst` can't pass on
+        vec![
+            "pkeyutl",
+            "-verify",
+            "-pubin",
+            "-inkey",
+            public,
+            "-rawin",
+            "-in",
+            "input",
+            "-sigfile",
+            "signature",
+        ]
+    } else {
+        let mut args = vec![
+            "dgst",
+            digest.as_str(),
+            "-verify",
+            public,
+            "-signature",
+            "signature",
+        ];
+        args.extend_from_slice(padding(alg));
+        args.push("input");
+        args
+    };
+    openssl.run(&args).map(|_| ())
+}
+
+/// OpenSSL signs; this side builds the token and verifies it.
+fn from_openssl(
+    openssl: &Openssl,
+    alg: Algorithm,
+    key: &VerifyingKey,
+    private: Option<&str>,
+) -> Result<(), String> {
+    let json = |value: &Value| serde_json::to_vec(value).map_err(|e| e.to_string());
+    let input = format!("{}.{}", b64(&json(&header(alg))?), b64(&json(&claims())?));
+    openssl.write("input", input.as_bytes())?;
+    let digest = digest_flag(alg);
+    let signature = match private {
+        None => {
+            let macopt = format!("hexkey:{}", hex(SECRET));
+            openssl.run(&[
+                "dgst", &digest, "-mac", "HMAC", "-macopt", &macopt, "-binary", "input",
+            ])?
+        }
+        Some(private) if alg == Algorithm::EdDSA => openssl.run(&[
+            "pkeyutl", "-sign", "-inkey", private, "-rawin", "-in", "input",
+        ])?,
+        Some(private) => {
+            let mut args = vec!["dgst", digest.as_str(), "-sign", private];
+            args.extend_from_slice(padding(alg));
+            args.push("input");
+            openssl.run(&args)?
+        }
+    };
+    let signature = match alg.curve() {
+        Some(curve) => {
+            ecdsa_signature_from_der(&signature, curve.byte_len()).map_err(|e| e.to_string())?
+        }
+        None => signature,
+    };
+    let raw = format!("{}.{}", input, b64(&signature));
+    let token = parse(&raw).map_err(|e| e.to_string())?;
+    jwt_check::verify(&raw, &token, key)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// The `conformance` examples that must verify, with their `alg` and
+/// whether they did.
+fn vectors() -> Vec<(Option<String>, conformance::Outcome)> {
+    conformance::examples()
+        .iter()
+        .zip(conformance::run())
+        .filter(|(example, _)| example.expect == conformance::Expect::Verifies)
+        .map(|(example, outcome)| {
+            let alg = example
+                .token
+                .split('.')
+                .next()
+                .and_then(|h| base64::decode_config(h, base64::URL_SAFE_NO_PAD).ok())
+                .and_then(|h| serde_json::from_slice::<Value>(&h).ok())
+                .and_then(|h| h.get("alg").and_then(Value::as_str).map(str::to_string));
+            (alg, outcome)
+        })
+        .collect()
+}
+
+fn vector_cell(alg: Algorithm, vectors: &[(Option<String>, conformance::Outcome)]) -> Cell {
+    let name = alg.to_string();
+    let mine: Vec<&conformance::Outcome> = vectors
+        .iter()
+        .filter(|(example_alg, _)| example_alg.as_deref() == Some(name.as_str()))
+        .map(|(_, outcome)| outcome)
+        .collect();
+    match mine.iter().find(|outcome| !outcome.passed) {
+        Some(failed) => Cell::Fail(format!("{}: {}", failed.source, failed.detail)),
+        None if mine.is_empty() => Cell::Skipped("no RFC example".to_string()),
+        None => Cell::Pass,
+    }
+}
+
+/// Builds the matrix, running `program` as OpenSSL. When it can't be run,
+/// its columns are skipped rather than failed.
+pub fn run(program: &str) -> Result<Vec<Row>, JWTError> {
+    let scratch =
+        Scratch(std::env::temp_dir().join(format!("jwt-check-interop-{}", std::process::id())));
+    std::fs::create_dir_all(&scratch.0)?;
+    let openssl = Openssl {
+        program,
+        dir: &scratch.0,
+    };
+    let available = openssl.run(&["version"]).map(|_| ());
+    let vectors = vectors();
+    // generated once per family, as each takes a moment
+    type Keypair = Result<(String, String), String>;
+    let mut pairs: Vec<(&str, Keypair)> = Vec::new();
+    let mut rows = Vec::new();
+    for alg in ALGORITHMS.iter().copied() {
+        let (to, from) = match (&available, key_spec(alg)) {
+            (Err(why), _) => (Cell::Skipped(why.clone()), Cell::Skipped(why.clone())),
+            (Ok(()), None) => (
+                Cell::from_result(to_openssl(
+                    &openssl,
+                    alg,
+                    &SigningKey::Hmac(SECRET.to_vec()),
+                    None,
+                )),
+                Cell::from_result(from_openssl(
+                    &openssl,
+                    alg,
+                    &VerifyingKey::Hmac(SECRET.to_vec()),
+                    None,
+                )),
+            ),
+            (Ok(()), Some((name, options))) => {
+                if !pairs.iter().any(|(known, _)| *known == name) {
+                    pairs.push((name, openssl.keypair(name, options)));
+                }
+                let pair = pairs
+                    .iter()
+                    .find(|(known, _)| *known == name)
+                    .map(|(_, pair)| pair.clone())
+                    .unwrap_or_else(|| Err("no key".to_string()));
+                let private = format!("{}.pem", name);
+                let public = format!("{}.pub.pem", name);
+                let keys = pair.and_then(|(private_pem, public_pem)| {
+                    let signing =
+                        keys::parse_signing_key_pem(&private_pem).map_err(|e| e.to_string())?;
+                    let verifying =
+                        keys::parse_public_key_pem(&public_pem).map_err(|e| e.to_string())?;
+                    Ok((signing, verifying))
+                });
+                match keys {
+                    Ok((signing, verifying)) => (
+                        Cell::from_result(to_openssl(&openssl, alg, &signing, Some(&public))),
+                        Cell::from_result(from_openssl(&openssl, alg, &verifying, Some(&private))),
+                    ),
+                    Err(why) => (Cell::Fail(why.clone()), Cell::Fail(why)),
+                }
+            }
+        };
+        rows.push(Row {
+            alg,
+            to_openssl: to,
+            from_openssl: from,
+            vectors: vector_cell(alg, &vectors),
+        });
+    }
+    Ok(rows)
+}
+
+fn cells(row: &Row) -> [(&'static str, &Cell); 3] {
+    [
+        ("jwt-check → openssl", &row.to_openssl),
+        ("openssl → jwt-check", &row.from_openssl),
+        ("RFC examples → jwt-check", &row.vectors),
+    ]
+}
+
+/// The matrix, then a line for each failed cell and why OpenSSL's columns
+/// were skipped, if they were.
+pub fn render_text(rows: &[Row]) -> String {
+    let names: Vec<&str> = rows
+        .first()
+        .map(|row| cells(row).iter().map(|(name, _)| *name).collect())
+        .unwrap_or_default();
+    let mut out = format!("{:6}", "alg");
+    for name in &names {
+        out.push_str(&format!("  {}", name));
+    }
+    let mut notes = Vec::new();
+    for row in rows {
+        let mut line = format!("{:6}", row.alg.to_string());
+        for ((name, cell), header) in cells(row).iter().zip(&names) {
+            line.push_str(&format!(
+                "  {:w$}",
+                cell.short(),
+                w = header.chars().count()
+            ));
+            if let Cell::Fail(why) = cell {
+                notes.push(format!("FAIL {} {}: {}", row.alg, name, why));
+            }
+        }
+        out.push('\n');
+        out.push_str(line.trim_end());
+    }
+    let skipped = rows.iter().find_map(|row| match &row.to_openssl {
+        Cell::Skipped(why) => Some(why),
+        _ => None,
+    });
+    if let Some(why) = skipped {
+        notes.push(format!("openssl columns skipped: {}", why));
+    }
+    for note in notes {
+        out.push('\n');
+        out.push_str(&note);
+    }
+    out
+}
+
+pub fn to_json(rows: &[Row]) -> Value {
+    let cell = |cell: &Cell| match cell {
+        Cell::Pass => json!({"result": "pass"}),
+        Cell::Fail(why) => json!({"result": "fail", "detail": why}),
+        Cell::Skipped(why) => json!({"result": "skipped", "detail": why}),
+    };
+    json!({
+        "algorithms": rows.iter().map(|row| json!({
+            "alg": row.alg.to_string(),
+            "to_openssl": cell(&row.to_openssl),
+            "from_openssl": cell(&row.from_openssl),
+            "rfc_examples": cell(&row.vectors),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+/// How many cells failed.
+pub fn failures(rows: &[Row]) -> usize {
+    rows.iter()
+        .flat_map(|row| cells(row).map(|(_, cell)| matches!(cell, Cell::Fail(_))))
+        .filter(|failed| *failed)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ecdsa_der_round_trip_test() {
+        // r with its high bit set needs a leading zero; s has leading zeros to drop
+        let mut raw = vec![0x80; 32];
+        raw.extend([0, 0, 0x12].iter().chain([0x34; 29].iter()));
+        let der = ecdsa_to_der(&raw);
+        assert_eq!(&der[..5], &[0x30, 67, 0x02, 33, 0x00]);
+        assert_eq!(&der[37..39], &[0x02, 30]);
+        assert_eq!(ecdsa_signature_from_der(&der, 32).unwrap(), raw);
+        // the longest P-384 signature still fits a one-byte length
+        let raw = vec![0xff; 96];
+        assert_eq!(&ecdsa_to_der(&raw)[..5], &[0x30, 102, 0x02, 49, 0x00]);
+        assert_eq!(
+            ecdsa_signature_from_der(&ecdsa_to_der(&raw), 48).unwrap(),
+            raw
+        );
+        assert_eq!(digest_flag(Algorithm::PS384), "-sha384");
+
+        let rows = run("/nonexistent/openssl").unwrap();
+        assert_eq!(rows.len(), ALGORITHMS.len());
+        assert_eq!(
+            rows[0].to_openssl,
+            Cell::Skipped(
+                "cannot run /nonexistent/openssl: No such file or directory (os error 2)"
+                    .to_string()
+            )
+        );
+        assert_eq!(rows[0].vectors, Cell::Pass);
+        assert_eq!(rows[1].vectors, Cell::Skipped("no RFC example".to_string()));
+        assert_eq!(failures(&rows), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn misbehaving_openssl_test() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir =
+            std::env::temp_dir().join(format!("jwt-check-fake-openssl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fake = |name: &str, script: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            run(path.to_str().unwrap()).unwrap()
+        };
+        // an HMAC that is not the one signed here
+        let rows = fake("wrong", "printf wrong");
+        assert_eq!(rows[0].alg, Algorithm::HS256);
+        assert_eq!(
+            rows[0].to_openssl,
+            Cell::Fail("OpenSSL computes 77726f6e67".to_string())
+        );
+        let rows = fake("failing", "echo refused >&2; exit 1");
+        assert_eq!(
+            rows[0].to_openssl,
+            Cell::Skipped("openssl version failed: refused".to_string())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
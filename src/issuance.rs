@@ -0,0 +1,363 @@
+//! `--stats`: how fast a batch's tokens were issued, from their `iat`.
+//!
+//! Tokens are counted in fixed-width time buckets, once per issuer and once
+//! per issuer and subject, so a burst from one client stands out against
+//! the issuer's usual rate. A bucket is flagged as a spike when it holds at
+//! least [`SPIKE_MIN`] tokens and more than [`SPIKE_FACTOR`] times the
+//! series' median bucket.
+
+use crate::{format_timestamp, humanize_seconds, YEAR_9999_END};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Roughly how many buckets the width is chosen for when none is given.
+pub const TARGET_BUCKETS: i64 = 48;
+/// The most buckets a given width may make; a narrower one is widened.
+pub const MAX_BUCKETS: i64 = 100_000;
+/// How many subjects per issuer the text output lists, busiest first.
+pub const TEXT_SUBJECTS: usize = 10;
+pub const SPIKE_MIN: usize = 10;
+pub const SPIKE_FACTOR: usize = 4;
+
+/// Bucket widths picked from when none is given: the smallest that keeps
+/// the histogram within [`TARGET_BUCKETS`].
+const WIDTHS: [i64; 10] = [
+    60,
+    300,
+    900,
+    3_600,
+    6 * 3_600,
+    86_400,
+    7 * 86_400,
+    30 * 86_400,
+    365 * 86_400,
+    3_650 * 86_400,
+];
+
+/// Tokens per bucket for one issuer, or one subject of an issuer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Series {
+    pub issuer: String,
+    pub subject: Option<String>,
+    pub counts: Vec<usize>,
+}
+
+impl Series {
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Indices of the buckets that are spikes.
+    pub fn spikes(&self) -> Vec<usize> {
+        let mut sorted = self.counts.clone();
+        sorted.sort_unstable();
+        let median = sorted.get(sorted.len() / 2).copied().unwrap_or_default();
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count >= SPIKE_MIN && **count > SPIKE_FACTOR * median)
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// Start of the first bucket, in seconds since the epoch.
+    pub start: i64,
+    /// Bucket width in seconds.
+    pub width: i64,
+    /// Issuers, each followed by its subjects; both by descending total.
+    pub series: Vec<Series>,
+    /// Tokens with no numeric `iat`, which are left out.
+    pub without_iat: usize,
+    /// Tokens whose `iat` is before 1970 or after 9999, counted apart so
+    /// they don't stretch the buckets across the whole of `i64`.
+    pub out_of_range: usize,
+}
+
+fn text(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::String(text) => Some(text.clone()),
+        Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+/// Buckets `payloads`, whose `iat` must already be in seconds. `width` is
+/// the bucket width, or `None` to pick one from the span of `iat`s; a width
+/// that would make more than [`MAX_BUCKETS`] buckets is widened to fit.
+pub fn histogram(payloads: &[Value], width: Option<i64>) -> Histogram {
+    let mut stamped = Vec::new();
+    let mut without_iat = 0;
+    let mut out_of_range = 0;
+    for payload in payloads {
+        match payload.get("iat").and_then(Value::as_i64) {
+            Some(iat) if (0..=YEAR_9999_END).contains(&iat) => stamped.push((iat, payload)),
+            Some(_) => out_of_range += 1,
+            None => without_iat += 1,
+        }
+    }
+    let first = stamped
+        .iter()
+        .map(|(iat, _)| *iat)
+        .min()
+        .unwrap_or_default();
+    let last = stamped
+        .iter()
+        .map(|(iat, _)| *iat)
+        .max()
+        .unwrap_or_default();
+    let width = width
+        .filter(|w| *w > 0)
+        .map(|w| w.max((last - first) / MAX_BUCKETS + 1))
+        .unwrap_or_else(|| {
+            WIDTHS
+                .iter()
+                .copied()
+                .find(|w| (last - first) / w < TARGET_BUCKETS)
+                .unwrap_or(3_650 * 86_400)
+        });
+    let start = first.div_euclid(width) * width;
+    let buckets = ((last - start) / width + 1) as usize;
+
+    // issuer -> (its counts, subject -> counts)
+    type Counts = BTreeMap<String, Vec<usize>>;
+    let mut issuers: BTreeMap<String, (Vec<usize>, Counts)> = BTreeMap::new();
+    for (iat, payload) in &stamped {
+        let bucket = ((iat - start) / width) as usize;
+        let issuer = text(payload.get("iss")).unwrap_or_else(|| "(no iss)".to_string());
+        let (counts, subjects) = issuers
+            .entry(issuer)
+            .or_insert_with(|| (vec![0; buckets], BTreeMap::new()));
+        if let Some(slot) = counts.get_mut(bucket) {
+            *slot += 1;
+        }
+        if let Some(subject) = text(payload.get("sub")) {
+            let counts = subjects.entry(subject).or_insert_with(|| vec![0; buckets]);
+            if let Some(slot) = counts.get_mut(bucket) {
+                *slot += 1;
+            }
+        }
+    }
+
+    let by_total = |a: &Series, b: &Series| b.total().cmp(&a.total());
+    let mut grouped: Vec<(Series, Vec<Series>)> = issuers
+        .into_iter()
+        .map(|(issuer, (counts, subjects))| {
+            let mut subjects: Vec<Series> = subjects
+                .into_iter()
+                .map(|(subject, counts)| Series {
+                    issuer: issuer.clone(),
+                    subject: Some(subject),
+                    counts,
+                })
+                .collect();
+            subjects.sort_by(by_total);
+            let series = Series {
+                issuer,
+                subject: None,
+                counts,
+            };
+            (series, subjects)
+        })
+        .collect();
+    grouped.sort_by(|a, b| by_total(&a.0, &b.0));
+    Histogram {
+        start,
+        width,
+        series: grouped
+            .into_iter()
+            .flat_map(|(issuer, subjects)| std::iter::once(issuer).chain(subjects))
+            .collect(),
+        without_iat,
+        out_of_range,
+    }
+}
+
+/// One block character per bucket, scaled to the largest.
+pub fn sparkline(counts: &[usize]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = counts.iter().copied().max().unwrap_or_default();
+    counts
+        .iter()
+        .map(|count| match count {
+            0 => ' ',
+            count => BARS
+                .get((count * (BARS.len() - 1)).div_ceil(max.max(1)))
+                .copied()
+                .unwrap_or('█'),
+        })
+        .collect()
+}
+
+impl Histogram {
+    fn bucket_start(&self, index: usize) -> i64 {
+        self.start + index as i64 * self.width
+    }
+
+    /// The histogram as text, listing at most `subjects` subjects per issuer.
+    pub fn render_text(&self, subjects: usize) -> String {
+        let buckets = self.series.first().map_or(0, |s| s.counts.len());
+        let total: usize = self
+            .series
+            .iter()
+            .filter(|s| s.subject.is_none())
+            .map(Series::total)
+            .sum();
+        let mut out = format!(
+            "issuance by iat: {} token(s) in {} bucket(s) of {} from {}",
+            total,
+            buckets,
+            humanize_seconds(self.width),
+            format_timestamp(self.start)
+        );
+        if self.without_iat > 0 {
+            out.push_str(&format!(" ({} without iat left out)", self.without_iat));
+        }
+        if self.out_of_range > 0 {
+            out.push_str(&format!(
+                " ({} with iat before 1970 or after 9999 counted apart)",
+                self.out_of_range
+            ));
+        }
+        let label = |series: &Series| match &series.subject {
+            Some(subject) => format!("  {}", subject),
+            None => series.issuer.clone(),
+        };
+        let shown: Vec<&Series> = {
+            let mut shown = Vec::new();
+            let mut listed = 0;
+            for series in &self.series {
+                if series.subject.is_none() {
+                    listed = 0;
+                    shown.push(series);
+                } else if listed < subjects {
+                    listed += 1;
+                    shown.push(series);
+                }
+            }
+            shown
+        };
+        let width = shown
+            .iter()
+            .map(|s| label(s).chars().count())
+            .max()
+            .unwrap_or(0);
+        for series in shown {
+            let name = label(series);
+            out.push_str(&format!(
+                "\n{}{} {:>6} |{}|",
+                name,
+                " ".repeat(width - name.chars().count()),
+                series.total(),
+                sparkline(&series.counts)
+            ));
+            let spikes: Vec<String> = series
+                .spikes()
+                .iter()
+                .map(|index| {
+                    format!(
+                        "{} at {}",
+                        series.counts.get(*index).copied().unwrap_or_default(),
+                        format_timestamp(self.bucket_start(*index))
+                    )
+                })
+                .collect();
+            if !spikes.is_empty() {
+                out.push_str(&format!(" spike: {}", spikes.join(", ")));
+            }
+        }
+        out
+    }
+
+    /// The histogram as JSON, with every series and its counts as arrays.
+    pub fn to_json(&self) -> Value {
+        serde_json::json!({
+            "start": self.start,
+            "bucket_seconds": self.width,
+            "buckets": self.series.first().map_or(0, |s| s.counts.len()),
+            "without_iat": self.without_iat,
+            "out_of_range": self.out_of_range,
+            "series": self.series.iter().map(|series| serde_json::json!({
+                "iss": series.issuer,
+                "sub": series.subject,
+                "total": series.total(),
+                "counts": series.counts,
+                "spikes": series.spikes().iter().map(|index| self.bucket_start(*index)).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn buckets_issuance_by_iat_test() {
+        let mut payloads = Vec::new();
+        for hour in 0..6 {
+            payloads.push(json!({"iss": "https://idp", "sub": "alice", "iat": hour * 3_600 + 60}));
+        }
+        // a runaway client in hour 4
+        for i in 0..20 {
+            payloads.push(json!({"iss": "https://idp", "sub": "bot", "iat": 4 * 3_600 + i}));
+        }
+        payloads.push(json!({"iss": "https://other", "iat": 5 * 3_600}));
+        payloads.push(json!({"iss": "https://idp"}));
+
+        let histogram = histogram(&payloads, Some(3_600));
+        assert_eq!(histogram.start, 0);
+        assert_eq!(histogram.without_iat, 1);
+        let names: Vec<(&str, Option<&str>)> = histogram
+            .series
+            .iter()
+            .map(|s| (s.issuer.as_str(), s.subject.as_deref()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("https://idp", None),
+                ("https://idp", Some("bot")),
+                ("https://idp", Some("alice")),
+                ("https://other", None),
+            ]
+        );
+        assert_eq!(histogram.series[0].counts, vec![1, 1, 1, 1, 21, 1]);
+        assert_eq!(histogram.series[1].spikes(), vec![4]);
+        assert!(histogram.series[2].spikes().is_empty());
+        assert_eq!(sparkline(&[0, 1, 2, 8]), " ▂▃█");
+
+        let text = histogram.render_text(1);
+        assert!(text.starts_with(
+            "issuance by iat: 27 token(s) in 6 bucket(s) of 1 hour from 1970-01-01T00:00:00Z \
+             (1 without iat left out)\nhttps://idp       26 |"
+        ));
+        assert!(text.contains("  bot             20 |    █ | spike: 20 at 1970-01-01T04:00:00Z"));
+        assert!(!text.contains("alice"));
+        assert_eq!(histogram.to_json()["series"][1]["spikes"], json!([14_400]));
+
+        // an hour-long span gets minute buckets
+        assert_eq!(super::histogram(&payloads[6..26], None).width, 60);
+
+        // the ends of i64 are counted apart rather than overflowing the buckets
+        payloads.push(json!({"iss": "https://idp", "iat": -9_223_372_036_854_775_807_i64}));
+        payloads.push(json!({"iss": "https://idp", "iat": i64::MAX}));
+        let histogram = super::histogram(&payloads, None);
+        assert_eq!(histogram.out_of_range, 2);
+        assert_eq!(
+            histogram.width,
+            super::histogram(&payloads[..28], None).width
+        );
+        assert!(histogram
+            .render_text(0)
+            .contains("(2 with iat before 1970 or after 9999 counted apart)"));
+        assert_eq!(histogram.to_json()["out_of_range"], 2);
+        // a one-second width over eight thousand years is widened
+        let span = [json!({"iat": 0}), json!({"iat": YEAR_9999_END})];
+        let histogram = super::histogram(&span, Some(1));
+        assert!(histogram.series[0].counts.len() as i64 <= MAX_BUCKETS);
+    }
+}
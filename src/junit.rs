@@ -0,0 +1,128 @@
+//! `--junit`: the checks of one run as a JUnit XML report, so CI systems
+//! show a token's contract as test results.
+//!
+//! Each check is a test case, grouped by kind in its `classname`
+//! (`jwt-check.signature`, `jwt-check.claims`, `jwt-check.script`, ...). A
+//! failed check carries its message in `<failure>`; findings that don't fail
+//! the run, such as medium-severity audit findings, go in `<system-out>`.
+
+/// One check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    /// The kind of check: `signature`, `claims`, `audit`, ...
+    pub kind: &'static str,
+    pub name: String,
+    pub failure: Option<String>,
+    /// What the check noticed without failing.
+    pub notes: Vec<String>,
+}
+
+impl Case {
+    pub fn new(kind: &'static str, name: &str, failure: Option<String>) -> Case {
+        Case {
+            kind,
+            name: name.to_string(),
+            failure,
+            notes: Vec::new(),
+        }
+    }
+}
+
+/// `text` for an XML attribute or element.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            // not allowed in XML 1.0 at all
+            c if (c as u32) < 0x20 && c != '\t' && c != '\r' => escaped.push('\u{fffd}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The report: one `<testsuite>` named `suite`.
+pub fn render(suite: &str, cases: &[Case]) -> String {
+    let failures = cases.iter().filter(|case| case.failure.is_some()).count();
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<testsuites tests=\"{}\" failures=\"{}\">\n",
+        cases.len(),
+        failures
+    ));
+    out.push_str(&format!(
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\">\n",
+        escape(suite),
+        cases.len(),
+        failures
+    ));
+    for case in cases {
+        let open = format!(
+            "    <testcase classname=\"jwt-check.{}\" name=\"{}\"",
+            case.kind,
+            escape(&case.name)
+        );
+        if case.failure.is_none() && case.notes.is_empty() {
+            out.push_str(&format!("{}/>\n", open));
+            continue;
+        }
+        out.push_str(&format!("{}>\n", open));
+        if let Some(failure) = &case.failure {
+            out.push_str(&format!(
+                "      <failure message=\"{}\"/>\n",
+                escape(failure)
+            ));
+        }
+        if !case.notes.is_empty() {
+            out.push_str(&format!(
+                "      <system-out>{}</system-out>\n",
+                escape(&case.notes.join("\n"))
+            ));
+        }
+        out.push_str("    </testcase>\n");
+    }
+    out.push_str("  </testsuite>\n</testsuites>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_test() {
+        let mut audit = Case::new("audit", "no-expiry", None);
+        audit.notes.push("no exp claim".to_string());
+        let cases = [
+            Case::new("signature", "RS256", None),
+            Case::new(
+                "claims",
+                "aud",
+                Some("aud does not contain \"api://<orders>\"".to_string()),
+            ),
+            audit,
+        ];
+        assert_eq!(
+            render("token", &cases),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuites tests=\"3\" failures=\"1\">\n\
+             \x20 <testsuite name=\"token\" tests=\"3\" failures=\"1\" errors=\"0\">\n\
+             \x20   <testcase classname=\"jwt-check.signature\" name=\"RS256\"/>\n\
+             \x20   <testcase classname=\"jwt-check.claims\" name=\"aud\">\n\
+             \x20     <failure message=\"aud does not contain &quot;api://&lt;orders&gt;&quot;\"/>\n\
+             \x20   </testcase>\n\
+             \x20   <testcase classname=\"jwt-check.audit\" name=\"no-expiry\">\n\
+             \x20     <system-out>no exp claim</system-out>\n\
+             \x20   </testcase>\n\
+             \x20 </testsuite>\n\
+             </testsuites>\n"
+        );
+        assert_eq!(escape("a\u{1}b\nc"), "a\u{fffd}b&#10;c");
+    }
+}
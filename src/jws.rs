@@ -0,0 +1,172 @@
+//! `jwt-check jws`: compact JWS (RFC 7515) over arbitrary files, such as
+//! release artifacts or configuration, reusing the keys `encode` signs with
+//! and `--verify` checks with.
+//!
+//! The payload is the file's bytes, so it need not be JSON. A detached JWS
+//! (RFC 7515 Appendix F) leaves the payload part empty and is checked
+//! against the file given alongside it. Unencoded payloads (RFC 7797,
+//! `"b64": false`) are refused rather than misread.
+
+use crate::keys::SigningKey;
+use crate::sign::sign_payload;
+use crate::{ErrorMessage, JWTError, JWToken};
+use serde_json::Value;
+
+fn jws_error(message: impl Into<ErrorMessage>) -> JWTError {
+    JWTError::JwsError(message.into())
+}
+
+fn b64(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// Signs `payload` under `header`; with `detached`, the payload part of the
+/// result is left empty.
+pub fn sign(
+    header: &Value,
+    payload: &[u8],
+    key: &SigningKey,
+    detached: bool,
+) -> Result<String, JWTError> {
+    let jws = sign_payload(header, payload, key)?;
+    if !detached {
+        return Ok(jws);
+    }
+    let mut parts = jws.split('.');
+    match (parts.next(), parts.nth(1)) {
+        (Some(header), Some(signature)) => Ok(format!("{}..{}", header, signature)),
+        _ => Err(jws_error("the signer returned no compact JWS")),
+    }
+}
+
+/// Puts `jws` back together with `payload`: the compact form whose signing
+/// input covers the file, and the token [`crate::verify::verify`] checks.
+/// An attached payload must be the file's.
+pub fn attach(jws: &str, payload: &[u8]) -> Result<(String, JWToken), JWTError> {
+    let parts: Vec<&str> = jws.trim().split('.').collect();
+    let (header_part, payload_part, signature_part) = match parts.as_slice() {
+        [header, payload, signature] => (*header, *payload, *signature),
+        _ => {
+            return Err(jws_error(format!(
+                "a compact JWS has 3 parts, not {}",
+                parts.len()
+            )))
+        }
+    };
+    let header: Value = serde_json::from_slice(&base64::decode_config(
+        header_part,
+        base64::URL_SAFE_NO_PAD,
+    )?)?;
+    if !header.is_object() {
+        return Err(jws_error("the JWS header is not a JSON object"));
+    }
+    if header.get("b64") == Some(&Value::Bool(false)) {
+        return Err(jws_error(
+            "unencoded payloads (\"b64\": false) are not supported",
+        ));
+    }
+    let encoded = b64(payload);
+    if !payload_part.is_empty() && payload_part != encoded {
+        return Err(jws_error("the JWS carries a payload other than the file"));
+    }
+    let signature = base64::decode_config(signature_part, base64::URL_SAFE_NO_PAD)?;
+    let raw = format!("{}.{}.{}", header_part, encoded, signature_part);
+    let token = JWToken {
+        header,
+        payload: Value::Null,
+        signature,
+    };
+    Ok((raw, token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{parse_public_key_pem, parse_signing_key_pem, VerifyingKey};
+    use crate::verify::{verify, Algorithm};
+    use serde_json::json;
+
+    /// The secp256k1 key of the `keys` tests.
+    const K1_SCALAR: &str = "79a8079d5d7ffd18773cc0cf9eb7a430982aa70c758171a88e041189410af9ae";
+    const K1_POINT: &str = "03eb43d00387f80daaff0f29e365576a9b799f8d82153b9931dc343f8a5140e502";
+    const ARTIFACT: &[u8] = b"\x7fELF not json at all\n";
+
+    #[test]
+    fn round_trip_test() {
+        let secret = b"release-signing-secret".to_vec();
+        let header = json!({"alg": "HS256", "kid": "release"});
+        let jws = sign(&header, ARTIFACT, &SigningKey::Hmac(secret.clone()), false).unwrap();
+        assert_eq!(jws.split('.').nth(1), Some(b64(ARTIFACT).as_str()));
+        let (raw, token) = attach(&jws, ARTIFACT).unwrap();
+        assert_eq!(raw, jws);
+        assert_eq!(token.header, header);
+        assert_eq!(
+            verify(&raw, &token, &VerifyingKey::Hmac(secret.clone())).unwrap(),
+            Algorithm::HS256
+        );
+
+        assert!(matches!(
+            verify(
+                &raw,
+                &token,
+                &VerifyingKey::Hmac(b"another secret".to_vec())
+            ),
+            Err(JWTError::InvalidSignatureError)
+        ));
+    }
+
+    #[test]
+    fn detached_test() {
+        let key = parse_signing_key_pem(K1_SCALAR).unwrap();
+        let public = parse_public_key_pem(K1_POINT).unwrap();
+        let jws = sign(&json!({"alg": "ES256K"}), ARTIFACT, &key, true).unwrap();
+        assert!(jws.contains(".."));
+        let (raw, token) = attach(&jws, ARTIFACT).unwrap();
+        assert_eq!(verify(&raw, &token, &public).unwrap(), Algorithm::ES256K);
+
+        // a detached signature over another file doesn't verify
+        let (raw, token) = attach(&jws, b"another file").unwrap();
+        assert!(matches!(
+            verify(&raw, &token, &public),
+            Err(JWTError::InvalidSignatureError)
+        ));
+        assert!(matches!(
+            verify(&raw, &token, &VerifyingKey::Hmac(b"secret".to_vec())),
+            Err(JWTError::KeyError(_))
+        ));
+    }
+
+    #[test]
+    fn attach_test() {
+        let jws = sign(
+            &json!({"alg": "HS256"}),
+            ARTIFACT,
+            &SigningKey::Hmac(b"s".to_vec()),
+            false,
+        )
+        .unwrap();
+        assert!(matches!(
+            attach(&jws, b"tampered"),
+            Err(JWTError::JwsError(e)) if e == "the JWS carries a payload other than the file"
+        ));
+        assert!(matches!(
+            attach("a.b", ARTIFACT),
+            Err(JWTError::JwsError(e)) if e == "a compact JWS has 3 parts, not 2"
+        ));
+        let unencoded = format!("{}..c2ln", b64(br#"{"alg":"HS256","b64":false}"#));
+        assert!(matches!(
+            attach(&unencoded, ARTIFACT),
+            Err(JWTError::JwsError(e))
+                if e == "unencoded payloads (\"b64\": false) are not supported"
+        ));
+        let array = format!("{}..c2ln", b64(b"[1]"));
+        assert!(matches!(
+            attach(&array, ARTIFACT),
+            Err(JWTError::JwsError(e)) if e == "the JWS header is not a JSON object"
+        ));
+        assert!(matches!(
+            attach("!!..c2ln", ARTIFACT),
+            Err(JWTError::DecodeError(_))
+        ));
+    }
+}
@@ -0,0 +1,913 @@
+//! `jwt-check stream`: a Kafka consumer, and the producer behind
+//! `--to-topic`, speaking the wire protocol over plain TCP.
+//!
+//! It is a bare consumer, outside any consumer group: it reads every
+//! partition of one topic from its end, or from its start with
+//! `--from-beginning`, and commits no offsets. Records must come in the
+//! uncompressed batches of message format 2 (Kafka 0.11 and later);
+//! compressed batches are skipped and counted, so a topic needs
+//! `compression.type=uncompressed` or a producer that doesn't compress.
+//! There is no TLS or SASL. The producer writes to partition 0.
+
+use crate::{ErrorMessage, JWTError};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const API_PRODUCE: i16 = 0;
+const API_FETCH: i16 = 1;
+const API_LIST_OFFSETS: i16 = 2;
+const API_METADATA: i16 = 3;
+const CLIENT_ID: &str = "jwt-check";
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a fetch waits at the broker for new records.
+const MAX_WAIT_MS: i32 = 500;
+const MAX_BYTES: i32 = 4 * 1024 * 1024;
+/// Larger responses are taken as a broken stream, not allocated.
+const MAX_RESPONSE: usize = 64 * 1024 * 1024;
+const LATEST: i64 = -1;
+const EARLIEST: i64 = -2;
+
+fn kafka_error(message: impl Into<ErrorMessage>) -> JWTError {
+    JWTError::KafkaError(message.into())
+}
+
+/// The name of a broker error code, for the ones a consumer meets.
+fn error_name(code: i16) -> String {
+    let name = match code {
+        1 => "OFFSET_OUT_OF_RANGE",
+        3 => "UNKNOWN_TOPIC_OR_PARTITION",
+        5 => "LEADER_NOT_AVAILABLE",
+        6 => "NOT_LEADER_OR_FOLLOWER",
+        7 => "REQUEST_TIMED_OUT",
+        10 => "MESSAGE_TOO_LARGE",
+        29 => "TOPIC_AUTHORIZATION_FAILED",
+        _ => return format!("error code {}", code),
+    };
+    name.to_string()
+}
+
+/// One message read from the topic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<Vec<u8>>,
+    pub value: Option<Vec<u8>>,
+}
+
+/// CRC-32C (Castagnoli), the checksum of a record batch.
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0x82f6_3b78 & mask);
+        }
+    }
+    !crc
+}
+
+/// A request or record batch being written, big-endian as Kafka has it.
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn i8(&mut self, n: i8) -> &mut Self {
+        self.0.extend(n.to_be_bytes());
+        self
+    }
+    fn i16(&mut self, n: i16) -> &mut Self {
+        self.0.extend(n.to_be_bytes());
+        self
+    }
+    fn i32(&mut self, n: i32) -> &mut Self {
+        self.0.extend(n.to_be_bytes());
+        self
+    }
+    fn i64(&mut self, n: i64) -> &mut Self {
+        self.0.extend(n.to_be_bytes());
+        self
+    }
+    fn string(&mut self, text: &str) -> &mut Self {
+        self.i16(i16::try_from(text.len()).unwrap_or(i16::MAX));
+        self.0.extend(text.as_bytes());
+        self
+    }
+    fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.i32(i32::try_from(bytes.len()).unwrap_or(i32::MAX));
+        self.0.extend(bytes);
+        self
+    }
+    /// A zigzag varint, as record fields are written.
+    fn varint(&mut self, n: i64) -> &mut Self {
+        let mut zigzag = ((n << 1) ^ (n >> 63)) as u64;
+        loop {
+            let byte = (zigzag & 0x7f) as u8;
+            zigzag >>= 7;
+            if zigzag == 0 {
+                self.0.push(byte);
+                return self;
+            }
+            self.0.push(byte | 0x80);
+        }
+    }
+    fn varint_bytes(&mut self, bytes: Option<&[u8]>) -> &mut Self {
+        match bytes {
+            Some(bytes) => {
+                self.varint(i64::try_from(bytes.len()).unwrap_or(i64::MAX));
+                self.0.extend(bytes);
+            }
+            None => {
+                self.varint(-1);
+            }
+        }
+        self
+    }
+}
+
+/// A response being read. Every read fails cleanly past the end.
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, at: 0 }
+    }
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.at)
+    }
+    fn take(&mut self, n: usize) -> Result<&'a [u8], JWTError> {
+        let taken = self
+            .at
+            .checked_add(n)
+            .and_then(|end| self.data.get(self.at..end))
+            .ok_or_else(|| kafka_error("truncated response".to_string()))?;
+        self.at += n;
+        Ok(taken)
+    }
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], JWTError> {
+        let mut out = [0; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+    fn i8(&mut self) -> Result<i8, JWTError> {
+        Ok(i8::from_be_bytes(self.array()?))
+    }
+    fn i16(&mut self) -> Result<i16, JWTError> {
+        Ok(i16::from_be_bytes(self.array()?))
+    }
+    fn i32(&mut self) -> Result<i32, JWTError> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+    fn i64(&mut self) -> Result<i64, JWTError> {
+        Ok(i64::from_be_bytes(self.array()?))
+    }
+    fn u32(&mut self) -> Result<u32, JWTError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+    fn count(&mut self) -> Result<usize, JWTError> {
+        Ok(usize::try_from(self.i32()?).unwrap_or(0))
+    }
+    fn string(&mut self) -> Result<String, JWTError> {
+        let len = self.i16()?;
+        let len = usize::try_from(len).unwrap_or(0);
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+    fn nullable_bytes(&mut self) -> Result<Option<&'a [u8]>, JWTError> {
+        match usize::try_from(self.i32()?) {
+            Ok(len) => self.take(len).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+    fn varint(&mut self) -> Result<i64, JWTError> {
+        let mut zigzag = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.i8()? as u8;
+            zigzag |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+            }
+        }
+        Err(kafka_error("varint longer than 10 bytes".to_string()))
+    }
+    fn varint_bytes(&mut self) -> Result<Option<&'a [u8]>, JWTError> {
+        match usize::try_from(self.varint()?) {
+            Ok(len) => self.take(len).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// `values` as one uncompressed record batch, offsets counted from 0.
+pub fn encode_batch(values: &[Vec<u8>], timestamp_ms: i64) -> Vec<u8> {
+    let mut records = Writer::default();
+    for (delta, value) in values.iter().enumerate() {
+        let mut record = Writer::default();
+        record
+            .i8(0)
+            .varint(0)
+            .varint(delta as i64)
+            .varint_bytes(None)
+            .varint_bytes(Some(value))
+            .varint(0);
+        records.varint(record.0.len() as i64);
+        records.0.extend(record.0);
+    }
+    let last_delta = i32::try_from(values.len().saturating_sub(1)).unwrap_or(i32::MAX);
+    // what the CRC covers: from the attributes to the end
+    let mut checked = Writer::default();
+    checked
+        .i16(0)
+        .i32(last_delta)
+        .i64(timestamp_ms)
+        .i64(timestamp_ms)
+        .i64(-1)
+        .i16(-1)
+        .i32(-1)
+        .i32(i32::try_from(values.len()).unwrap_or(i32::MAX));
+    checked.0.extend(records.0);
+    let mut batch = Writer::default();
+    // the length counts from the leader epoch on
+    let length = 4 + 1 + 4 + checked.0.len();
+    batch
+        .i64(0)
+        .i32(i32::try_from(length).unwrap_or(i32::MAX))
+        .i32(-1)
+        .i8(2)
+        .i32(crc32c(&checked.0) as i32);
+    batch.0.extend(checked.0);
+    batch.0
+}
+
+/// The records of a fetched partition, the offset to fetch next, and how
+/// many batches were skipped as compressed. A batch cut off at the end of
+/// the response, as brokers send them, is left for the next fetch.
+pub fn decode_records(
+    partition: i32,
+    data: &[u8],
+) -> Result<(Vec<Record>, Option<i64>, usize), JWTError> {
+    let mut reader = Reader::new(data);
+    let (mut records, mut next, mut compressed) = (Vec::new(), None, 0);
+    while reader.remaining() >= 12 {
+        let base_offset = reader.i64()?;
+        let length = usize::try_from(reader.i32()?).unwrap_or(0);
+        if reader.remaining() < length {
+            break;
+        }
+        let mut batch = Reader::new(reader.take(length)?);
+        let _leader_epoch = batch.i32()?;
+        let magic = batch.i8()?;
+        if magic != 2 {
+            return Err(kafka_error(format!(
+                "partition {}: message format {} is not supported; expected 2",
+                partition, magic
+            )));
+        }
+        let crc = batch.u32()?;
+        let checked = batch.data.get(batch.at..).unwrap_or_default();
+        if crc32c(checked) != crc {
+            return Err(kafka_error(format!(
+                "partition {}: batch at offset {} fails its CRC",
+                partition, base_offset
+            )));
+        }
+        let attributes = batch.i16()?;
+        let last_delta = batch.i32()?;
+        next = Some(base_offset + i64::from(last_delta) + 1);
+        let _timestamps = (batch.i64()?, batch.i64()?);
+        let _producer = (batch.i64()?, batch.i16()?, batch.i32()?);
+        let count = batch.count()?;
+        // control batches mark transaction boundaries, not messages
+        if attributes & 0x20 != 0 {
+            continue;
+        }
+        if attributes & 0x07 != 0 {
+            compressed += 1;
+            continue;
+        }
+        for _ in 0..count {
+            let length = usize::try_from(batch.varint()?).unwrap_or(0);
+            let mut record = Reader::new(batch.take(length)?);
+            let _attributes = record.i8()?;
+            let _timestamp_delta = record.varint()?;
+            let offset_delta = record.varint()?;
+            let key = record.varint_bytes()?.map(<[u8]>::to_vec);
+            let value = record.varint_bytes()?.map(<[u8]>::to_vec);
+            records.push(Record {
+                partition,
+                offset: base_offset + offset_delta,
+                key,
+                value,
+            });
+        }
+    }
+    Ok((records, next, compressed))
+}
+
+/// One connection to one broker.
+struct Broker {
+    address: String,
+    stream: TcpStream,
+    correlation: i32,
+}
+
+impl Broker {
+    fn connect(address: &str) -> Result<Broker, JWTError> {
+        let failed = |e: std::io::Error| {
+            kafka_error(ErrorMessage::caused_by(
+                format!("{}: cannot connect: {}", address, e),
+                e,
+            ))
+        };
+        let mut last = None;
+        for resolved in address.to_socket_addrs().map_err(failed)? {
+            match TcpStream::connect_timeout(&resolved, TIMEOUT) {
+                Ok(stream) => {
+                    let timeout = Some(TIMEOUT + Duration::from_millis(MAX_WAIT_MS as u64));
+                    stream.set_read_timeout(timeout)?;
+                    stream.set_write_timeout(timeout)?;
+                    return Ok(Broker {
+                        address: address.to_string(),
+                        stream,
+                        correlation: 0,
+                    });
+                }
+                Err(e) => last = Some(e),
+            }
+        }
+        Err(failed(last.unwrap_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses")
+        })))
+    }
+
+    /// Sends one request and returns the body of its response.
+    fn call(&mut self, api_key: i16, version: i16, body: &[u8]) -> Result<Vec<u8>, JWTError> {
+        self.correlation = self.correlation.wrapping_add(1);
+        let mut header = Writer::default();
+        header
+            .i16(api_key)
+            .i16(version)
+            .i32(self.correlation)
+            .string(CLIENT_ID);
+        let mut request = Writer::default();
+        request.bytes(&[header.0, body.to_vec()].concat());
+        let address = &self.address;
+        let failed = |e: std::io::Error| {
+            kafka_error(ErrorMessage::caused_by(format!("{}: {}", address, e), e))
+        };
+        self.stream.write_all(&request.0).map_err(failed)?;
+        let mut size = [0; 4];
+        self.stream.read_exact(&mut size).map_err(failed)?;
+        let size = usize::try_from(i32::from_be_bytes(size)).unwrap_or(0);
+        if !(4..=MAX_RESPONSE).contains(&size) {
+            return Err(kafka_error(format!(
+                "{}: response of {} bytes",
+                self.address, size
+            )));
+        }
+        let mut response = vec![0; size];
+        self.stream.read_exact(&mut response).map_err(failed)?;
+        let mut reader = Reader::new(&response);
+        if reader.i32()? != self.correlation {
+            return Err(kafka_error(format!(
+                "{}: response to another request",
+                self.address
+            )));
+        }
+        Ok(response.get(4..).unwrap_or_default().to_vec())
+    }
+}
+
+/// Where the topic's partitions are: each partition's leader, by address.
+fn metadata(broker: &mut Broker, topic: &str) -> Result<Vec<(i32, String)>, JWTError> {
+    let mut request = Writer::default();
+    request.i32(1).string(topic);
+    let response = broker.call(API_METADATA, 1, &request.0)?;
+    let mut reader = Reader::new(&response);
+    let mut nodes = HashMap::new();
+    for _ in 0..reader.count()? {
+        let id = reader.i32()?;
+        let host = reader.string()?;
+        let port = reader.i32()?;
+        let _rack = reader.string()?;
+        nodes.insert(id, format!("{}:{}", host, port));
+    }
+    let _controller = reader.i32()?;
+    let mut partitions = Vec::new();
+    for _ in 0..reader.count()? {
+        let error = reader.i16()?;
+        let name = reader.string()?;
+        let _internal = reader.i8()?;
+        if error != 0 {
+            return Err(kafka_error(format!(
+                "topic {}: {}",
+                name,
+                error_name(error)
+            )));
+        }
+        for _ in 0..reader.count()? {
+            let _error = reader.i16()?;
+            let partition = reader.i32()?;
+            let leader = reader.i32()?;
+            for _ in 0..2 {
+                // replicas, then in-sync replicas
+                for _ in 0..reader.count()? {
+                    reader.i32()?;
+                }
+            }
+            let address = nodes.get(&leader).cloned().ok_or_else(|| {
+                kafka_error(format!(
+                    "topic {} partition {} has no leader",
+                    topic, partition
+                ))
+            })?;
+            partitions.push((partition, address));
+        }
+    }
+    if partitions.is_empty() {
+        return Err(kafka_error(format!("topic {} has no partitions", topic)));
+    }
+    partitions.sort();
+    Ok(partitions)
+}
+
+/// The first broker of `brokers` that answers, and where `topic` lives.
+fn bootstrap(brokers: &[String], topic: &str) -> Result<Vec<(i32, String)>, JWTError> {
+    let mut last = kafka_error("no brokers given".to_string());
+    for address in brokers {
+        match Broker::connect(address).and_then(|mut broker| metadata(&mut broker, topic)) {
+            Ok(partitions) => return Ok(partitions),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+/// The partitions one broker leads, and where each is read from next.
+struct Leader {
+    broker: Broker,
+    offsets: Vec<(i32, i64)>,
+}
+
+impl Leader {
+    fn list_offsets(&mut self, topic: &str, time: i64) -> Result<(), JWTError> {
+        let mut request = Writer::default();
+        request
+            .i32(-1)
+            .i32(1)
+            .string(topic)
+            .i32(i32::try_from(self.offsets.len()).unwrap_or(i32::MAX));
+        for (partition, _) in &self.offsets {
+            request.i32(*partition).i64(time);
+        }
+        let response = self.broker.call(API_LIST_OFFSETS, 1, &request.0)?;
+        let mut reader = Reader::new(&response);
+        for _ in 0..reader.count()? {
+            let _name = reader.string()?;
+            for _ in 0..reader.count()? {
+                let partition = reader.i32()?;
+                let error = reader.i16()?;
+                let _timestamp = reader.i64()?;
+                let offset = reader.i64()?;
+                if error != 0 {
+                    return Err(kafka_error(format!(
+                        "topic {} partition {}: {}",
+                        topic,
+                        partition,
+                        error_name(error)
+                    )));
+                }
+                if let Some(entry) = self.offsets.iter_mut().find(|(p, _)| *p == partition) {
+                    entry.1 = offset;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fetch(&mut self, topic: &str) -> Result<(Vec<Record>, usize), JWTError> {
+        let mut request = Writer::default();
+        request
+            .i32(-1)
+            .i32(MAX_WAIT_MS)
+            .i32(1)
+            .i32(MAX_BYTES)
+            .i8(0)
+            .i32(1)
+            .string(topic)
+            .i32(i32::try_from(self.offsets.len()).unwrap_or(i32::MAX));
+        for (partition, offset) in &self.offsets {
+            request.i32(*partition).i64(*offset).i32(MAX_BYTES);
+        }
+        let response = self.broker.call(API_FETCH, 4, &request.0)?;
+        let mut reader = Reader::new(&response);
+        let _throttle = reader.i32()?;
+        let (mut records, mut compressed) = (Vec::new(), 0);
+        for _ in 0..reader.count()? {
+            let _name = reader.string()?;
+            for _ in 0..reader.count()? {
+                let partition = reader.i32()?;
+                let error = reader.i16()?;
+                let _high_watermark = reader.i64()?;
+                let _last_stable = reader.i64()?;
+                for _ in 0..reader.count()? {
+                    // aborted transactions: producer id and first offset
+                    reader.i64()?;
+                    reader.i64()?;
+                }
+                let data = reader.nullable_bytes()?.unwrap_or_default();
+                if error != 0 {
+                    return Err(kafka_error(format!(
+                        "topic {} partition {}: {}",
+                        topic,
+                        partition,
+                        error_name(error)
+                    )));
+                }
+                let (found, next, skipped) = decode_records(partition, data)?;
+                let entry = self.offsets.iter_mut().find(|(p, _)| *p == partition);
+                if let (Some(entry), Some(next)) = (entry, next) {
+                    entry.1 = entry.1.max(next);
+                }
+                records.extend(found);
+                compressed += skipped;
+            }
+        }
+        Ok((records, compressed))
+    }
+}
+
+/// Reads every partition of a topic.
+pub struct Consumer {
+    topic: String,
+    leaders: Vec<Leader>,
+    /// Batches skipped so far for being compressed.
+    pub compressed: usize,
+}
+
+impl Consumer {
+    /// Connects to the leader of each partition of `topic`, through the
+    /// first of `brokers` that answers.
+    pub fn connect(
+        brokers: &[String],
+        topic: &str,
+        from_beginning: bool,
+    ) -> Result<Consumer, JWTError> {
+        let mut by_address: Vec<(String, Vec<(i32, i64)>)> = Vec::new();
+        for (partition, address) in bootstrap(brokers, topic)? {
+            match by_address.iter_mut().find(|(known, _)| *known == address) {
+                Some((_, partitions)) => partitions.push((partition, 0)),
+                None => by_address.push((address, vec![(partition, 0)])),
+            }
+        }
+        let mut leaders = Vec::new();
+        for (address, offsets) in by_address {
+            let mut leader = Leader {
+                broker: Broker::connect(&address)?,
+                offsets,
+            };
+            leader.list_offsets(topic, if from_beginning { EARLIEST } else { LATEST })?;
+            leaders.push(leader);
+        }
+        Ok(Consumer {
+            topic: topic.to_string(),
+            leaders,
+            compressed: 0,
+        })
+    }
+
+    /// One fetch from every leader: the records that arrived, in offset
+    /// order within each partition. Waits up to half a second for them.
+    pub fn poll(&mut self) -> Result<Vec<Record>, JWTError> {
+        let mut records = Vec::new();
+        for leader in &mut self.leaders {
+            let offsets = leader.offsets.clone();
+            let (found, compressed) = leader.fetch(&self.topic)?;
+            self.compressed += compressed;
+            // a fetch can start inside a batch; its earlier records are old
+            records.extend(found.into_iter().filter(|record| {
+                offsets
+                    .iter()
+                    .any(|(p, from)| *p == record.partition && record.offset >= *from)
+            }));
+        }
+        Ok(records)
+    }
+}
+
+/// Writes messages to partition 0 of a topic.
+pub struct Producer {
+    topic: String,
+    broker: Broker,
+}
+
+impl Producer {
+    pub fn connect(brokers: &[String], topic: &str) -> Result<Producer, JWTError> {
+        let partitions = bootstrap(brokers, topic)?;
+        let leader = partitions
+            .iter()
+            .find(|(partition, _)| *partition == 0)
+            .map(|(_, address)| address.as_str())
+            .ok_or_else(|| kafka_error(format!("topic {} has no partition 0", topic)))?;
+        Ok(Producer {
+            topic: topic.to_string(),
+            broker: Broker::connect(leader)?,
+        })
+    }
+
+    /// Sends `values` as one batch, waiting for the leader to have it.
+    pub fn send(&mut self, values: &[Vec<u8>]) -> Result<(), JWTError> {
+        if values.is_empty() {
+            return Ok(());
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as i64);
+        let mut request = Writer::default();
+        request
+            .i16(-1)
+            .i16(1)
+            .i32(TIMEOUT.as_millis() as i32)
+            .i32(1)
+            .string(&self.topic)
+            .i32(1)
+            .i32(0)
+            .bytes(&encode_batch(values, now));
+        let response = self.broker.call(API_PRODUCE, 3, &request.0)?;
+        let mut reader = Reader::new(&response);
+        for _ in 0..reader.count()? {
+            let _name = reader.string()?;
+            for _ in 0..reader.count()? {
+                let partition = reader.i32()?;
+                let error = reader.i16()?;
+                if error != 0 {
+                    return Err(kafka_error(format!(
+                        "topic {} partition {}: {}",
+                        self.topic,
+                        partition,
+                        error_name(error)
+                    )));
+                }
+                let _base_offset = reader.i64()?;
+                let _append_time = reader.i64()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn records_test() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        let values = vec![b"eyJhbGciOi.first".to_vec(), vec![0x80; 200]];
+        let mut batch = encode_batch(&values, 1_700_000_000_000);
+        let (records, next, compressed) = decode_records(3, &batch).unwrap();
+        assert_eq!((records.len(), next, compressed), (2, Some(2), 0));
+        assert_eq!(records[1].offset, 1);
+        assert_eq!(records[1].value.as_deref(), Some(&values[1][..]));
+        assert_eq!(records[0].key, None);
+        // a batch cut short is left for the next fetch
+        let (records, next, _) = decode_records(3, &batch[..batch.len() - 1]).unwrap();
+        assert_eq!((records.len(), next), (0, None));
+        // compressed batches are skipped but still moved past
+        batch[22] = 0x01;
+        assert!(matches!(
+            decode_records(3, &batch),
+            Err(JWTError::KafkaError(e)) if e == "partition 3: batch at offset 0 fails its CRC"
+        ));
+        let crc = crc32c(&batch[21..]);
+        batch[17..21].copy_from_slice(&crc.to_be_bytes());
+        let (records, next, compressed) = decode_records(3, &batch).unwrap();
+        assert_eq!((records.len(), next, compressed), (0, Some(2), 1));
+        batch[16] = 1;
+        assert!(matches!(
+            decode_records(3, &batch),
+            Err(JWTError::KafkaError(e))
+                if e == "partition 3: message format 1 is not supported; expected 2"
+        ));
+
+        assert!(matches!(
+            Reader::new(&[0, 0]).i32(),
+            Err(JWTError::KafkaError(e)) if e == "truncated response"
+        ));
+        assert!(matches!(
+            Reader::new(&[0x80; 11]).varint(),
+            Err(JWTError::KafkaError(e)) if e == "varint longer than 10 bytes"
+        ));
+    }
+
+    /// Answers one connection as a broker leading partition 0 of `auth-logs`.
+    fn broker(listener: TcpListener, port: u16, batch: Vec<u8>) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut fetched = false;
+        loop {
+            let mut size = [0; 4];
+            if stream.read_exact(&mut size).is_err() {
+                return;
+            }
+            let mut request = vec![0; i32::from_be_bytes(size) as usize];
+            stream.read_exact(&mut request).unwrap();
+            let mut reader = Reader::new(&request);
+            let (api, _, correlation) = (
+                reader.i16().unwrap(),
+                reader.i16().unwrap(),
+                reader.i32().unwrap(),
+            );
+            let mut body = Writer::default();
+            body.i32(correlation);
+            match api {
+                API_METADATA => {
+                    body.i32(1)
+                        .i32(0)
+                        .string("127.0.0.1")
+                        .i32(i32::from(port))
+                        .i16(-1);
+                    body.i32(0).i32(1).i16(0).string("auth-logs").i8(0);
+                    body.i32(1).i16(0).i32(0).i32(0).i32(1).i32(0).i32(1).i32(0);
+                }
+                API_LIST_OFFSETS => {
+                    body.i32(1)
+                        .string("auth-logs")
+                        .i32(1)
+                        .i32(0)
+                        .i16(0)
+                        .i64(-1)
+                        .i64(0);
+                }
+                API_FETCH => {
+                    body.i32(0)
+                        .i32(1)
+                        .string("auth-logs")
+                        .i32(1)
+                        .i32(0)
+                        .i16(0)
+                        .i64(2)
+                        .i64(2)
+                        .i32(-1);
+                    if fetched {
+                        body.i32(-1);
+                    } else {
+                        body.bytes(&batch);
+                    }
+                    fetched = true;
+                }
+                _ => {
+                    body.i32(1)
+                        .string("findings")
+                        .i32(1)
+                        .i32(0)
+                        .i16(3)
+                        .i64(-1)
+                        .i64(-1);
+                    body.i32(0);
+                }
+            }
+            let mut response = Writer::default();
+            response.bytes(&body.0);
+            stream.write_all(&response.0).unwrap();
+        }
+    }
+
+    #[test]
+    fn consumer_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let batch = encode_batch(&[b"one".to_vec(), b"two".to_vec()], 0);
+        let served = std::thread::spawn(move || {
+            // the bootstrap connection, then the leader's
+            let copy = listener.try_clone().unwrap();
+            broker(copy, port, Vec::new());
+            broker(listener, port, batch);
+        });
+        let brokers = vec!["127.0.0.1:1".to_string(), format!("127.0.0.1:{}", port)];
+        let mut consumer = Consumer::connect(&brokers, "auth-logs", true).unwrap();
+        let records = consumer.poll().unwrap();
+        let values: Vec<_> = records.iter().map(|r| r.value.clone().unwrap()).collect();
+        assert_eq!(values, [b"one".to_vec(), b"two".to_vec()]);
+        assert!(consumer.poll().unwrap().is_empty());
+        drop(consumer);
+        served.join().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let served = std::thread::spawn(move || {
+            let copy = listener.try_clone().unwrap();
+            broker(copy, port, Vec::new());
+            broker(listener, port, Vec::new());
+        });
+        let brokers = vec![format!("127.0.0.1:{}", port)];
+        let mut producer = Producer::connect(&brokers, "findings").unwrap();
+        assert!(matches!(
+            producer.send(&[b"{}".to_vec()]),
+            Err(JWTError::KafkaError(e)) if e.contains("UNKNOWN_TOPIC_OR_PARTITION")
+        ));
+        drop(producer);
+        served.join().unwrap();
+        assert!(Consumer::connect(&[], "auth-logs", false).is_err());
+    }
+
+    /// A broker that answers one request with `answer(correlation)`, its
+    /// size prefix included.
+    fn scripted(answer: fn(i32) -> Vec<u8>) -> (String, std::thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let served = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut size = [0; 4];
+            stream.read_exact(&mut size).unwrap();
+            let mut request = vec![0; i32::from_be_bytes(size) as usize];
+            stream.read_exact(&mut request).unwrap();
+            let mut reader = Reader::new(&request);
+            reader.take(4).unwrap();
+            let correlation = reader.i32().unwrap();
+            stream.write_all(&answer(correlation)).unwrap();
+        });
+        (address, served)
+    }
+
+    /// A metadata answer: node 0 on 127.0.0.1:9092, then `partitions` as
+    /// (id, leader) for `auth-logs`.
+    fn metadata_answer(correlation: i32, partitions: &[(i32, i32)]) -> Vec<u8> {
+        let mut body = Writer::default();
+        body.i32(correlation);
+        body.i32(1).i32(0).string("127.0.0.1").i32(9092).i16(-1);
+        body.i32(0).i32(1).i16(0).string("auth-logs").i8(0);
+        body.i32(partitions.len() as i32);
+        for (partition, leader) in partitions {
+            body.i16(0).i32(*partition).i32(*leader).i32(0).i32(0);
+        }
+        let mut response = Writer::default();
+        response.bytes(&body.0);
+        response.0
+    }
+
+    #[test]
+    fn malformed_broker_answers_test() {
+        let error_of = |answer: fn(i32) -> Vec<u8>| {
+            let (address, served) = scripted(answer);
+            let error = error_message!(
+                KafkaError,
+                Consumer::connect(std::slice::from_ref(&address), "auth-logs", false)
+            );
+            served.join().unwrap();
+            error.replace(&address, "BROKER")
+        };
+        assert_eq!(
+            error_of(|_| vec![0, 0, 0, 2, 0, 0]),
+            "BROKER: response of 2 bytes"
+        );
+        assert_eq!(
+            error_of(|correlation| {
+                let mut response = Writer::default();
+                response.bytes(&(correlation + 1).to_be_bytes());
+                response.0
+            }),
+            "BROKER: response to another request"
+        );
+        assert_eq!(
+            error_of(|correlation| metadata_answer(correlation, &[])),
+            "topic auth-logs has no partitions"
+        );
+        assert_eq!(
+            error_of(|correlation| metadata_answer(correlation, &[(0, 7)])),
+            "topic auth-logs partition 0 has no leader"
+        );
+
+        let (address, served) = scripted(|correlation| metadata_answer(correlation, &[(1, 0)]));
+        assert!(matches!(
+            Producer::connect(&[address], "auth-logs"),
+            Err(JWTError::KafkaError(e)) if e == "topic auth-logs has no partition 0"
+        ));
+        served.join().unwrap();
+
+        assert!(matches!(
+            Consumer::connect(&[], "auth-logs", false),
+            Err(JWTError::KafkaError(e)) if e == "no brokers given"
+        ));
+        // nothing listens on a port just released
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        assert!(matches!(
+            Consumer::connect(
+                std::slice::from_ref(&closed),
+                "auth-logs",
+                false
+            ),
+            Err(JWTError::KafkaError(e)) if e.starts_with(&format!("{}: cannot connect: ", closed))
+        ));
+    }
+}
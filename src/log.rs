@@ -0,0 +1,87 @@
+//! Verbose diagnostics written to stderr.
+//!
+//! Anything that could be a credential goes through [`Log::token`] or
+//! [`Log::secret`], which redact by default. Only `--unsafe-log-full-values`
+//! makes them print the real value.
+
+use std::fmt;
+
+pub struct Log {
+    enabled: bool,
+    full_values: bool,
+}
+
+impl Log {
+    pub fn new(enabled: bool, full_values: bool) -> Log {
+        Log {
+            enabled,
+            full_values,
+        }
+    }
+
+    pub fn debug(&self, message: fmt::Arguments) {
+        if self.enabled {
+            eprintln!("[debug] {}", message);
+        }
+    }
+
+    /// A token (or any bearer credential) rendered as `prefix...suffix`.
+    pub fn token<'a>(&self, token: &'a str) -> Redacted<'a> {
+        Redacted {
+            value: token,
+            full: self.full_values,
+            keep: true,
+        }
+    }
+
+    /// Secrets and key material, rendered only as their length.
+    pub fn secret<'a>(&self, secret: &'a str) -> Redacted<'a> {
+        Redacted {
+            value: secret,
+            full: self.full_values,
+            keep: false,
+        }
+    }
+}
+
+pub struct Redacted<'a> {
+    value: &'a str,
+    full: bool,
+    keep: bool,
+}
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.full {
+            return write!(f, "{}", self.value);
+        }
+        let chars: Vec<char> = self.value.chars().collect();
+        // short values would be mostly revealed by a prefix and suffix
+        if !self.keep || chars.len() < 24 {
+            return write!(f, "***({} bytes)", self.value.len());
+        }
+        let prefix: String = chars.iter().take(8).collect();
+        let suffix: String = chars.iter().skip(chars.len() - 4).collect();
+        write!(f, "{}...{} ({} bytes)", prefix, suffix, self.value.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_by_default() {
+        let log = Log::new(true, false);
+        let token = "eyJhbGciOiJIUzI1NiJ9.e30.signature-bytes";
+        assert_eq!(log.token(token).to_string(), "eyJhbGci...ytes (40 bytes)");
+        assert_eq!(log.token("short").to_string(), "***(5 bytes)");
+        assert_eq!(
+            log.secret("hunter2-but-much-longer-ok").to_string(),
+            "***(26 bytes)"
+        );
+
+        let log = Log::new(true, true);
+        assert_eq!(log.secret("hunter2").to_string(), "hunter2");
+    }
+}
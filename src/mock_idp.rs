@@ -0,0 +1,351 @@
+//! `jwt-check mock-idp`: a throwaway identity provider for the integration
+//! tests of services that consume JWTs.
+//!
+//! It generates a P-256 key at startup, so nothing outlives the process,
+//! and answers:
+//!
+//! - `GET /.well-known/openid-configuration`: the discovery document;
+//! - `GET /jwks`: the public key, with its RFC 7638 thumbprint as `kid`;
+//! - `POST /token`: an ES256 access token.
+//!
+//! A token has `iss`, `sub`, `iat`, `exp` and `jti`, then the claims the
+//! mock was started with, then what the request asks for. A form body, as
+//! an OAuth client sends, sets `client_id` (also the `sub`), `scope` and
+//! `audience` (as `aud`). A JSON object body sets any claims at all, e.g.
+//! `{"sub": "alice", "roles": ["admin"]}` or `{"exp": 0}` for an expired
+//! token. It is a test fixture, so it checks no client credentials.
+
+use crate::crypto::bigint::BigUint;
+use crate::crypto::ecdsa::{Curve, EcPrivateKey};
+use crate::keys::{SigningKey, VerifyingKey};
+use crate::nested::percent_decode;
+use crate::server::{read_request, write_response, Budget, Limits, Request};
+use crate::sign::{random_bytes, sign};
+use crate::JWTError;
+use serde_json::{json, Map, Value};
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+
+pub const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+pub const JWKS_PATH: &str = "/jwks";
+pub const TOKEN_PATH: &str = "/token";
+
+/// The identity provider: its issuer, key, and the claims it adds.
+#[derive(Debug, Clone)]
+pub struct MockIdp {
+    pub issuer: String,
+    /// Seconds from `iat` to `exp`.
+    pub lifetime: i64,
+    pub claims: Map<String, Value>,
+    key: EcPrivateKey,
+    kid: String,
+}
+
+fn b64(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// A random P-256 private key.
+fn generate_key() -> Result<EcPrivateKey, JWTError> {
+    // a scalar at or past the order is drawn again, as FIPS 186-4 B.4.2 does
+    loop {
+        let d = BigUint::from_be_bytes(&random_bytes(Curve::P256.byte_len())?);
+        if let Some(key) = EcPrivateKey::new(Curve::P256, d) {
+            return Ok(key);
+        }
+    }
+}
+
+/// The `name=value&...` fields of a form body.
+fn form(body: &str) -> Vec<(String, String)> {
+    let decode = |text: &str| percent_decode(text).unwrap_or_else(|| text.replace('+', " "));
+    body.split('&')
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            let (name, value) = field.split_once('=').unwrap_or((field, ""));
+            (decode(name), decode(value))
+        })
+        .collect()
+}
+
+fn oauth_error(status: u16, error: &str, description: String) -> (u16, Value) {
+    (
+        status,
+        json!({"error": error, "error_description": description}),
+    )
+}
+
+impl MockIdp {
+    /// A provider with a new key, answering as `issuer`.
+    pub fn generate(
+        issuer: &str,
+        lifetime: i64,
+        claims: Map<String, Value>,
+    ) -> Result<MockIdp, JWTError> {
+        let key = generate_key()?;
+        let public = key
+            .public_key()
+            .ok_or_else(|| JWTError::KeyError("generated key has no public point".into()))?;
+        Ok(MockIdp {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            lifetime,
+            claims,
+            kid: VerifyingKey::Ec(public).thumbprint(),
+            key,
+        })
+    }
+
+    pub fn discovery(&self) -> Value {
+        json!({
+            "issuer": self.issuer,
+            "jwks_uri": format!("{}{}", self.issuer, JWKS_PATH),
+            "token_endpoint": format!("{}{}", self.issuer, TOKEN_PATH),
+            "grant_types_supported": ["client_credentials"],
+            "response_types_supported": ["token"],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": ["ES256"],
+            "token_endpoint_auth_methods_supported": ["none", "client_secret_post"],
+        })
+    }
+
+    pub fn jwks(&self) -> Value {
+        let (x, y) = self
+            .key
+            .public_key()
+            .map_or((Vec::new(), Vec::new()), |public| public.coordinates());
+        json!({"keys": [{
+            "kty": "EC",
+            "crv": "P-256",
+            "x": b64(&x),
+            "y": b64(&y),
+            "kid": self.kid,
+            "use": "sig",
+            "alg": "ES256",
+        }]})
+    }
+
+    /// A signed token with `requested` over the defaults and the mock's
+    /// own claims.
+    pub fn mint(&self, requested: &Map<String, Value>, now: i64) -> Result<String, JWTError> {
+        let mut claims = Map::new();
+        claims.insert("iss".to_string(), Value::from(self.issuer.as_str()));
+        claims.insert("sub".to_string(), Value::from("mock-user"));
+        claims.insert("iat".to_string(), Value::from(now));
+        claims.insert("exp".to_string(), Value::from(now + self.lifetime));
+        claims.insert("jti".to_string(), Value::from(hex(&random_bytes(16)?)));
+        claims.extend(self.claims.clone());
+        claims.extend(requested.clone());
+        let header = json!({"alg": "ES256", "typ": "JWT", "kid": self.kid});
+        sign(
+            &header,
+            &Value::Object(claims),
+            &SigningKey::Ec(self.key.clone()),
+        )
+    }
+
+    /// The claims a `/token` request asks for.
+    fn requested(request: &Request) -> Result<Map<String, Value>, String> {
+        let body = std::str::from_utf8(&request.body).map_err(|e| e.to_string())?;
+        let json = request.headers.iter().any(|(name, value)| {
+            name.eq_ignore_ascii_case("content-type") && value.starts_with("application/json")
+        });
+        if json {
+            return match serde_json::from_str(body).map_err(|e| e.to_string())? {
+                Value::Object(claims) => Ok(claims),
+                _ => Err("the body must be a JSON object of claims".to_string()),
+            };
+        }
+        let mut claims = Map::new();
+        for (name, value) in form(body) {
+            match name.as_str() {
+                "client_id" => {
+                    claims.insert("sub".to_string(), Value::from(value.as_str()));
+                    claims.insert("client_id".to_string(), Value::from(value));
+                }
+                "scope" => {
+                    claims.insert("scope".to_string(), Value::from(value));
+                }
+                "audience" => {
+                    claims.insert("aud".to_string(), Value::from(value));
+                }
+                _ => {}
+            }
+        }
+        Ok(claims)
+    }
+
+    /// The status and JSON body that answer `request` at `now`.
+    pub fn respond(&self, request: &Request, now: i64) -> (u16, Value) {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", DISCOVERY_PATH) => (200, self.discovery()),
+            ("GET", JWKS_PATH) => (200, self.jwks()),
+            ("POST", TOKEN_PATH) => {
+                let requested = match MockIdp::requested(request) {
+                    Ok(requested) => requested,
+                    Err(e) => return oauth_error(400, "invalid_request", e),
+                };
+                match self.mint(&requested, now) {
+                    Ok(token) => (
+                        200,
+                        json!({
+                            "access_token": token,
+                            "token_type": "Bearer",
+                            "expires_in": self.lifetime,
+                        }),
+                    ),
+                    Err(e) => oauth_error(500, "server_error", e.to_string()),
+                }
+            }
+            (_, DISCOVERY_PATH) | (_, JWKS_PATH) | (_, TOKEN_PATH) => oauth_error(
+                405,
+                "invalid_request",
+                format!("{} does not take {}", request.path, request.method),
+            ),
+            _ => oauth_error(404, "not_found", format!("no {}", request.path)),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn handle(stream: TcpStream, idp: &MockIdp, budget: &Budget) -> Result<(), JWTError> {
+    let limits = Limits::default();
+    stream.set_read_timeout(Some(limits.request_timeout))?;
+    let mut reader = BufReader::new(&stream);
+    let mut reservation = budget.reservation();
+    let (status, body, line) =
+        match read_request(&mut reader, &limits, &mut reservation, Vec::new()) {
+            Ok(request) => {
+                let (status, body) = idp.respond(&request, crate::unix_now());
+                let line = format!("{} {} {}", request.method, request.path, status);
+                (status, body, line)
+            }
+            Err(refused) => {
+                let (status, body) =
+                    oauth_error(refused.status, "invalid_request", refused.message);
+                (status, body, format!("- - {}", status))
+            }
+        };
+    eprintln!("{}", line);
+    write_response(&stream, status, "application/json", &body.to_string())
+}
+
+/// Answers requests on `listener` until the process is killed.
+pub fn serve(listener: TcpListener, idp: MockIdp) -> Result<(), JWTError> {
+    let idp = std::sync::Arc::new(idp);
+    let budget = std::sync::Arc::new(Budget::new(Limits::default().memory));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept failed: {}", e);
+                continue;
+            }
+        };
+        let (idp, budget) = (std::sync::Arc::clone(&idp), std::sync::Arc::clone(&budget));
+        std::thread::spawn(move || {
+            if let Err(e) = handle(stream, &idp, &budget) {
+                eprintln!("request failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwks::select_keys;
+    use crate::{parse, verify};
+
+    fn request(method: &str, path: &str, content_type: &str, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn mock_idp_test() {
+        let mut claims = Map::new();
+        claims.insert("aud".to_string(), json!("api://orders"));
+        let idp = MockIdp::generate("http://127.0.0.1:9000/", 300, claims).unwrap();
+        let (status, discovery) = idp.respond(&request("GET", DISCOVERY_PATH, "", ""), 0);
+        assert_eq!(status, 200);
+        assert_eq!(discovery["issuer"], "http://127.0.0.1:9000");
+        assert_eq!(discovery["jwks_uri"], "http://127.0.0.1:9000/jwks");
+        let (_, jwks) = idp.respond(&request("GET", JWKS_PATH, "", ""), 0);
+
+        let fields =
+            "grant_type=client_credentials&client_id=orders-svc&scope=orders%3Aread+orders%3Awrite";
+        let token = request(
+            "POST",
+            TOKEN_PATH,
+            "application/x-www-form-urlencoded",
+            fields,
+        );
+        let (status, body) = idp.respond(&token, 1_700_000_000);
+        assert_eq!(status, 200);
+        let raw = body["access_token"].as_str().unwrap();
+        let token = parse(raw).unwrap();
+        assert_eq!(token.payload["sub"], "orders-svc");
+        assert_eq!(token.payload["scope"], "orders:read orders:write");
+        assert_eq!(token.payload["aud"], "api://orders");
+        assert_eq!(token.payload["exp"], 1_700_000_300);
+        let kid = token.header["kid"].as_str();
+        let keys = select_keys(&jwks, kid, crate::verify::Algorithm::ES256).unwrap();
+        let key = keys.first().unwrap();
+        verify(raw, &token, key).unwrap();
+
+        let claims = r#"{"sub": "alice", "exp": 0, "roles": ["admin"]}"#;
+        let json = request("POST", TOKEN_PATH, "application/json", claims);
+        let (_, body) = idp.respond(&json, 1_700_000_000);
+        let token = parse(body["access_token"].as_str().unwrap()).unwrap();
+        assert_eq!(token.payload["exp"], 0);
+        assert_eq!(token.payload["roles"], json!(["admin"]));
+
+        let bad = request("POST", TOKEN_PATH, "application/json", "[1]");
+        assert_eq!(
+            idp.respond(&bad, 0),
+            (
+                400,
+                json!({"error": "invalid_request",
+                       "error_description": "the body must be a JSON object of claims"})
+            )
+        );
+        let broken = request("POST", TOKEN_PATH, "application/json", "{");
+        assert_eq!(
+            idp.respond(&broken, 0),
+            (
+                400,
+                json!({"error": "invalid_request",
+                       "error_description": "EOF while parsing an object at line 1 column 1"})
+            )
+        );
+        assert_eq!(
+            idp.respond(&request("GET", TOKEN_PATH, "", ""), 0),
+            (
+                405,
+                json!({"error": "invalid_request",
+                       "error_description": "/token does not take GET"})
+            )
+        );
+        assert_eq!(
+            idp.respond(&request("GET", "/userinfo", "", ""), 0),
+            (
+                404,
+                json!({"error": "not_found", "error_description": "no /userinfo"})
+            )
+        );
+        let fields = form("a=1&b&&c=x%2By");
+        let fields: Vec<(&str, &str)> = fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(fields, [("a", "1"), ("b", ""), ("c", "x+y")]);
+    }
+}
@@ -0,0 +1,289 @@
+//! Verification keys as decentralized identity tooling hands them out:
+//! multibase multikeys (`z...`, as in `did:key` and `publicKeyMultibase`)
+//! and CESR-qualified keys (KERI's `1AAB...`).
+//!
+//! Both carry a key type and the raw public key. EC keys are SEC 1 points,
+//! usually compressed; Ed25519 keys (`z6Mk...`, CESR `B` and `D`) are their
+//! 32 bytes.
+
+use crate::crypto::ecdsa::Curve;
+use crate::keys::{ec_point_key, ed25519_key, VerifyingKey};
+use crate::{ErrorMessage, JWTError};
+
+const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// The multicodec prefixes of public keys.
+const SECP256K1_PUB: u64 = 0xe7;
+const P256_PUB: u64 = 0x1200;
+const P384_PUB: u64 = 0x1201;
+const ED25519_PUB: u64 = 0xed;
+
+/// CESR codes of the EC verification keys, each a 33-byte compressed point:
+/// non-transferable and transferable secp256k1, then secp256r1 (P-256).
+const CESR_EC: [(&str, Curve); 4] = [
+    ("1AAA", Curve::Secp256k1),
+    ("1AAB", Curve::Secp256k1),
+    ("1AAI", Curve::P256),
+    ("1AAJ", Curve::P256),
+];
+/// The length of a CESR EC key: a four-character code and 44 of base64.
+const CESR_EC_LEN: usize = 48;
+/// The length of a CESR Ed25519 key, code included: one character, then
+/// the key base64url-encoded after a zero byte that stands for the code.
+const CESR_ED25519_LEN: usize = 44;
+
+fn key_error(message: impl Into<ErrorMessage>) -> JWTError {
+    JWTError::KeyError(message.into())
+}
+
+/// Decodes base58 with the Bitcoin alphabet.
+pub fn base58_decode(text: &str) -> Result<Vec<u8>, JWTError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in text.bytes() {
+        let digit = BASE58
+            .iter()
+            .position(|b| *b == c)
+            .ok_or_else(|| key_error(format!("`{}` is not a base58 character", c as char)))?;
+        let mut carry = digit as u32;
+        for byte in bytes.iter_mut().rev() {
+            carry += u32::from(*byte) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, (carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = text.bytes().take_while(|c| *c == b'1').count();
+    let mut out = vec![0; zeros];
+    out.extend(bytes);
+    Ok(out)
+}
+
+/// An unsigned LEB128 varint, as multicodec prefixes are written.
+fn varint(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(9) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, bytes.get(i + 1..)?));
+        }
+    }
+    None
+}
+
+/// A multibase multikey: `z`, then base58btc of a multicodec key type and
+/// the key bytes.
+pub fn multikey(text: &str) -> Result<VerifyingKey, JWTError> {
+    let encoded = text.strip_prefix('z').ok_or_else(|| {
+        key_error(format!(
+            "`{}` is not base58btc multibase (it doesn't start with z)",
+            text
+        ))
+    })?;
+    let bytes = base58_decode(encoded)?;
+    let (codec, key) = varint(&bytes).ok_or_else(|| key_error("truncated multikey"))?;
+    match codec {
+        SECP256K1_PUB => ec_point_key(Curve::Secp256k1, key),
+        P256_PUB => ec_point_key(Curve::P256, key),
+        P384_PUB => ec_point_key(Curve::P384, key),
+        ED25519_PUB => ed25519_key(key),
+        other => Err(key_error(format!(
+            "unsupported multicodec key type 0x{:x}",
+            other
+        ))),
+    }
+}
+
+/// A CESR-qualified verification key: `B` or `D` (non-transferable or
+/// transferable) for Ed25519, or one of `CESR_EC`.
+pub fn cesr(text: &str) -> Result<VerifyingKey, JWTError> {
+    if text.len() == CESR_ED25519_LEN && (text.starts_with('B') || text.starts_with('D')) {
+        let padded: String = std::iter::once('A').chain(text.chars().skip(1)).collect();
+        let bytes = base64::decode_config(&padded, base64::URL_SAFE_NO_PAD)?;
+        return match bytes.split_first() {
+            Some((0, key)) => ed25519_key(key),
+            _ => Err(key_error(format!("malformed CESR Ed25519 key `{}`", text))),
+        };
+    }
+    let (code, curve) = CESR_EC
+        .iter()
+        .find(|(code, _)| text.starts_with(code))
+        .ok_or_else(|| {
+            key_error(format!(
+                "unsupported CESR code in `{}`; expected B, D, 1AAA, 1AAB, 1AAI or 1AAJ",
+                text
+            ))
+        })?;
+    if text.len() != CESR_EC_LEN {
+        return Err(key_error(format!(
+            "a CESR {} key is {} characters, not {}",
+            code,
+            CESR_EC_LEN,
+            text.len()
+        )));
+    }
+    let point = base64::decode_config(&text[code.len()..], base64::URL_SAFE_NO_PAD)?;
+    ec_point_key(*curve, &point)
+}
+
+/// A key in one of these encodings, or `None` when `text` is none of them:
+/// a multikey, a `did:key` or a CESR key, as one word.
+pub fn parse(text: &str) -> Option<Result<VerifyingKey, JWTError>> {
+    let text = text.trim();
+    if text.is_empty() || text.contains(char::is_whitespace) {
+        return None;
+    }
+    if let Some(id) = text.strip_prefix("did:key:") {
+        return Some(multikey(id));
+    }
+    if text.starts_with('z') && text.bytes().all(|c| BASE58.contains(&c)) {
+        return Some(multikey(text));
+    }
+    let base64url = text
+        .bytes()
+        .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_');
+    let cesr_length = text.len() == CESR_EC_LEN || text.len() == CESR_ED25519_LEN;
+    if base64url && cesr_length && (text.starts_with("1AA") || text.starts_with(['B', 'D'])) {
+        return Some(cesr(text));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::parse_public_key_pem;
+
+    /// The compressed secp256k1 point of the `keys` tests.
+    const K1_POINT: &str = "03eb43d00387f80daaff0f29e365576a9b799f8d82153b9931dc343f8a5140e502";
+
+    /// The Ed25519 key of the did:key spec's `z6Mk...` example.
+    const ED25519_KEY: [u8; 32] = [
+        0x2e, 0x6f, 0xcc, 0xe3, 0x67, 0x01, 0xdc, 0x79, 0x14, 0x88, 0xe0, 0xd0, 0xb1, 0x74, 0x5c,
+        0xc1, 0xe3, 0x3a, 0x4c, 0x1c, 0x9f, 0xcc, 0x41, 0xc6, 0x3b, 0xd3, 0x43, 0xdb, 0xbe, 0x09,
+        0x70, 0xe6,
+    ];
+
+    fn ed25519_bytes(key: VerifyingKey) -> [u8; 32] {
+        match key {
+            VerifyingKey::Ed25519(key) => key.to_bytes(),
+            other => panic!("expected an Ed25519 key, got {}", other.describe()),
+        }
+    }
+
+    fn coordinates(key: VerifyingKey) -> (Vec<u8>, Vec<u8>) {
+        match key {
+            VerifyingKey::Ec(key) => key.coordinates(),
+            other => panic!("expected an EC key, got {}", other.describe()),
+        }
+    }
+
+    #[test]
+    fn multikey_test() {
+        assert_eq!(base58_decode("1112").unwrap(), [0, 0, 0, 1]);
+        for (text, bad) in [("0OIl", '0'), ("2O", 'O'), ("zI", 'I'), ("1l", 'l')] {
+            assert!(matches!(
+                base58_decode(text),
+                Err(JWTError::KeyError(e)) if e == format!("`{}` is not a base58 character", bad)
+            ));
+        }
+        // the did:key spec's P-256 and secp256k1 examples
+        for (text, expected) in [
+            (
+                "zDnaerDaTF5BXEavCrfRZEk316dpbLsfPDZ3WJ5hRTPFU2169",
+                "EC P-256 public key",
+            ),
+            (
+                "zQ3shokFTS3brHcDQrn82RUDfCZESWL1ZdCEJwekUDPQiYBme",
+                "EC secp256k1 public key",
+            ),
+        ] {
+            assert_eq!(multikey(text).unwrap().describe(), expected);
+        }
+        assert_eq!(
+            coordinates(multikey("zQ3shvUUujektmecwVGbXZ2vbzm2rKKKeg9RqQRvHseBaD3rq").unwrap()),
+            coordinates(parse_public_key_pem(K1_POINT).unwrap())
+        );
+        // and its Ed25519 one
+        assert_eq!(
+            ed25519_bytes(multikey("z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK").unwrap()),
+            ED25519_KEY
+        );
+        assert!(matches!(
+            multikey("uAQID"),
+            Err(JWTError::KeyError(e))
+                if e == "`uAQID` is not base58btc multibase (it doesn't start with z)"
+        ));
+        assert!(matches!(
+            multikey("z"),
+            Err(JWTError::KeyError(e)) if e == "truncated multikey"
+        ));
+        assert!(matches!(
+            multikey("z2"),
+            Err(JWTError::KeyError(e)) if e == "unsupported multicodec key type 0x1"
+        ));
+    }
+
+    #[test]
+    fn cesr_test() {
+        assert_eq!(
+            coordinates(cesr("1AABA-tD0AOH-A2q_w8p42VXapt5n42CFTuZMdw0P4pRQOUC").unwrap()),
+            coordinates(parse_public_key_pem(K1_POINT).unwrap())
+        );
+        assert_eq!(
+            cesr("1AAJA38jWDDdPe-nIu8aoknWoN27pPmQsIF1OJM_VzZAZTVC")
+                .unwrap()
+                .describe(),
+            "EC P-256 public key"
+        );
+        for text in [
+            "DC5vzONnAdx5FIjg0LF0XMHjOkwcn8xBxjvTQ9u-CXDm",
+            "BC5vzONnAdx5FIjg0LF0XMHjOkwcn8xBxjvTQ9u-CXDm",
+        ] {
+            assert_eq!(ed25519_bytes(cesr(text).unwrap()), ED25519_KEY);
+        }
+        // the last character carries bits past the 32 bytes
+        assert!(cesr("DC5vzONnAdx5FIjg0LF0XMHjOkwcn8xBxjvTQ9u-CXDn").is_err());
+        assert!(matches!(
+            cesr("1AAZA38jWDDdPe-nIu8aoknWoN27pPmQsIF1OJM_VzZAZTVC"),
+            Err(JWTError::KeyError(e))
+                if e == "unsupported CESR code in `1AAZA38jWDDdPe-nIu8aoknWoN27pPmQsIF1OJM_VzZAZTVC`; \
+                 expected B, D, 1AAA, 1AAB, 1AAI or 1AAJ"
+        ));
+        assert!(matches!(
+            cesr("1AABA-tD0AOH"),
+            Err(JWTError::KeyError(e)) if e == "a CESR 1AAB key is 48 characters, not 12"
+        ));
+        // a point whose x is not on the curve
+        assert!(matches!(
+            cesr("1AABA-tD0AOH-A2q_w8p42VXapt5n42CFTuZMdw0P4pRQOUD"),
+            Err(JWTError::KeyError(e)) if e == "point is not on curve secp256k1"
+        ));
+    }
+
+    #[test]
+    fn parse_test() {
+        for text in [
+            "did:key:zQ3shvUUujektmecwVGbXZ2vbzm2rKKKeg9RqQRvHseBaD3rq",
+            "zQ3shvUUujektmecwVGbXZ2vbzm2rKKKeg9RqQRvHseBaD3rq\n",
+            "1AABA-tD0AOH-A2q_w8p42VXapt5n42CFTuZMdw0P4pRQOUC",
+        ] {
+            assert_eq!(
+                parse_public_key_pem(text).unwrap().describe(),
+                "EC secp256k1 public key"
+            );
+        }
+        // PEM and free text are left to the other readers
+        assert!(parse("-----BEGIN PUBLIC KEY-----\nMFkw\n-----END PUBLIC KEY-----").is_none());
+        assert!(parse("no armour here").is_none());
+        assert!(parse("").is_none());
+        assert_eq!(
+            parse_public_key_pem("did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK")
+                .unwrap()
+                .describe(),
+            "Ed25519 public key"
+        );
+    }
+}
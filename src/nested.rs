@@ -0,0 +1,172 @@
+//! `--decode-nested`: claim values that are themselves encoded data,
+//! expanded in place.
+//!
+//! A string claim is replaced by an object whose `$decoded` member says
+//! what it was: `jwt` (with `header` and `payload`), `jwe` (its protected
+//! `header`), `json` for stringified JSON, `base64` for base64 or
+//! base64url-encoded JSON, and `url` for percent-encoded text. The
+//! expanded value is decoded again, so a URL-encoded base64 blob still
+//! comes out as JSON. Strings that decode to nothing structured are left
+//! alone.
+
+use crate::{jwe, parse};
+use serde_json::{Map, Value};
+
+/// How many encodings deep one value is unwrapped.
+pub const MAX_DEPTH: usize = 8;
+
+fn marker(kind: &str, members: Vec<(&str, Value)>) -> Value {
+    let mut object = Map::new();
+    object.insert("$decoded".to_string(), Value::String(kind.to_string()));
+    for (name, value) in members {
+        object.insert(name.to_string(), value);
+    }
+    Value::Object(object)
+}
+
+/// JSON text for an object or array; scalars are too likely to be chance.
+fn structured_json(text: &str) -> Option<Value> {
+    let trimmed = text.trim();
+    if !(trimmed.starts_with('{') || trimmed.starts_with('[')) {
+        return None;
+    }
+    serde_json::from_str(trimmed).ok()
+}
+
+fn base64_json(text: &str) -> Option<Value> {
+    if text.len() < 4
+        || !text
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"+/=-_".contains(&b))
+    {
+        return None;
+    }
+    let unpadded = text.trim_end_matches('=');
+    let bytes = base64::decode_config(unpadded, base64::URL_SAFE_NO_PAD)
+        .or_else(|_| base64::decode_config(unpadded, base64::STANDARD_NO_PAD))
+        .ok()?;
+    structured_json(std::str::from_utf8(&bytes).ok()?)
+}
+
+pub(crate) fn percent_decode(text: &str) -> Option<String> {
+    if !text.contains('%') {
+        return None;
+    }
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut at = 0;
+    while let Some(&b) = bytes.get(at) {
+        let escaped = bytes
+            .get(at + 1..at + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (b, escaped) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                at += 3;
+            }
+            (b'%', None) => return None,
+            (b'+', _) => {
+                out.push(b' ');
+                at += 1;
+            }
+            (b, _) => {
+                out.push(b);
+                at += 1;
+            }
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// The expansion of one string, or `None` to leave it as it is.
+fn decode_string(text: &str, depth: usize) -> Option<Value> {
+    if depth >= MAX_DEPTH {
+        return None;
+    }
+    if text.split('.').count() == 3 {
+        if let Ok(token) = parse(text) {
+            let mut payload = token.payload;
+            expand(&mut payload, depth + 1);
+            return Some(marker(
+                "jwt",
+                vec![("header", token.header), ("payload", payload)],
+            ));
+        }
+    }
+    if jwe::is_compact(text) {
+        if let Ok(jwe) = jwe::parse(text) {
+            return Some(marker("jwe", vec![("header", jwe.header)]));
+        }
+    }
+    let (kind, mut value) = if let Some(json) = structured_json(text) {
+        ("json", json)
+    } else if let Some(json) = base64_json(text) {
+        ("base64", json)
+    } else if let Some(decoded) = percent_decode(text) {
+        let value = decode_string(&decoded, depth + 1).unwrap_or(Value::String(decoded));
+        return Some(marker("url", vec![("value", value)]));
+    } else {
+        return None;
+    };
+    expand(&mut value, depth + 1);
+    Some(marker(kind, vec![("value", value)]))
+}
+
+/// Expands every encoded string in `value`, at any depth.
+pub fn expand(value: &mut Value, depth: usize) {
+    match value {
+        Value::Object(object) => {
+            for member in object.values_mut() {
+                expand(member, depth);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                expand(item, depth);
+            }
+        }
+        Value::String(text) => {
+            if let Some(decoded) = decode_string(text, depth) {
+                *value = decoded;
+            }
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SAMPLE_TOKEN;
+    use serde_json::json;
+
+    #[test]
+    fn expands_encoded_claims_test() {
+        let blob = base64::encode(r#"{"tenant":"acme","roles":["admin"]}"#);
+        let mut payload = json!({
+            "id_token_hint": SAMPLE_TOKEN,
+            "data": blob,
+            "ctx": "%7B%22a%22%3A1%7D",
+            "prefs": "{\"theme\":\"dark\"}",
+            "sub": "alice",
+            "note": "100% sure",
+            "id": "abcd",
+        });
+        expand(&mut payload, 0);
+        assert_eq!(payload["id_token_hint"]["$decoded"], "jwt");
+        assert_eq!(payload["id_token_hint"]["payload"]["name"], "John Doe");
+        assert_eq!(
+            payload["data"],
+            json!({"$decoded": "base64", "value": {"tenant": "acme", "roles": ["admin"]}})
+        );
+        assert_eq!(
+            payload["ctx"],
+            json!({"$decoded": "url", "value": {"$decoded": "json", "value": {"a": 1}}})
+        );
+        assert_eq!(payload["prefs"]["value"]["theme"], "dark");
+        assert_eq!(payload["sub"], "alice");
+        assert_eq!(payload["note"], "100% sure");
+        assert_eq!(payload["id"], "abcd");
+    }
+}
@@ -0,0 +1,481 @@
+//! `--otlp-endpoint`: OpenTelemetry traces of `serve`'s requests, exported
+//! over OTLP/HTTP in its JSON encoding.
+//!
+//! Each request is a `SERVER` span, named for its method and the policy that
+//! checked it, with children for the steps of the check: `decode`,
+//! `claim validation`, `key resolution` and `signature check`. A step that
+//! fails has an error status saying why. A request with a W3C
+//! `traceparent` header joins the caller's trace.
+//!
+//! Spans are queued, and a thread of their own POSTs them to
+//! `ENDPOINT/v1/traces` in batches. Requests never wait on the collector.
+//! When it falls behind, spans past the queue are dropped and counted in the
+//! log. The collector is reached over `https://`, or `http://` for an
+//! agent on the same host.
+
+use crate::http::Client;
+use crate::sink::Sink;
+use crate::syslog::{self, Priority};
+use crate::JWTError;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Requests' spans waiting for the exporter thread.
+const QUEUE: usize = 1024;
+/// Most spans in one export.
+const MAX_BATCH: usize = 512;
+/// How long spans wait for a batch to fill.
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+const TRACES_PATH: &str = "/v1/traces";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Internal = 1,
+    Server = 2,
+}
+
+/// One finished span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub parent: Option<[u8; 8]>,
+    pub name: String,
+    pub kind: Kind,
+    /// Unix time in nanoseconds.
+    pub start: u64,
+    pub end: u64,
+    pub attributes: Vec<(&'static str, Value)>,
+    /// Why the step failed.
+    pub error: Option<String>,
+}
+
+/// The request being traced on this thread.
+struct Trace {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent: Option<[u8; 8]>,
+    start: u64,
+    children: Vec<Span>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Trace>> = const { RefCell::new(None) };
+}
+
+/// Now, as Unix time in nanoseconds.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+/// Fresh ids: a SHA-256 of a per-process random seed and a counter, so
+/// the OS is asked for randomness once.
+fn random_id<const N: usize>() -> [u8; N] {
+    static SEED: OnceLock<Vec<u8>> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let seed = SEED.get_or_init(|| {
+        let mut seed = vec![0; 32];
+        let read = std::fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut seed));
+        if read.is_err() {
+            seed = format!("{}:{}", std::process::id(), now()).into_bytes();
+        }
+        seed
+    });
+    let mut input = seed.clone();
+    input.extend_from_slice(&COUNTER.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+    let digest = crate::crypto::Hash::Sha256.digest(&input);
+    let mut id = [0; N];
+    for (byte, random) in id.iter_mut().zip(digest) {
+        *byte = random;
+    }
+    id
+}
+
+fn unhex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    if text.len() != N * 2 {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    // all-zero ids are invalid
+    bytes.iter().any(|b| *b != 0).then_some(bytes)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The trace and parent span of a W3C `traceparent` header,
+/// `00-TRACEID-SPANID-FLAGS`.
+pub fn traceparent(header: &str) -> Option<([u8; 16], [u8; 8])> {
+    let mut parts = header.trim().split('-');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(version), Some(trace), Some(span), Some(flags))
+            if version.len() == 2 && version != "ff" && flags.len() == 2 =>
+        {
+            Some((unhex(trace)?, unhex(span)?))
+        }
+        _ => None,
+    }
+}
+
+/// Starts tracing a request on this thread, in the caller's trace when
+/// `parent` is its `traceparent`.
+pub fn begin(parent: Option<&str>) {
+    let (trace_id, parent) = match parent.and_then(traceparent) {
+        Some((trace_id, span_id)) => (trace_id, Some(span_id)),
+        None => (random_id(), None),
+    };
+    let trace = Trace {
+        trace_id,
+        span_id: random_id(),
+        parent,
+        start: now(),
+        children: Vec::new(),
+    };
+    CURRENT.with(|current| *current.borrow_mut() = Some(trace));
+}
+
+/// Records a step of the request being traced, from `start` until now; it
+/// does nothing when no request is.
+pub fn step(name: &str, start: u64, error: Option<String>) {
+    CURRENT.with(|current| {
+        if let Some(trace) = current.borrow_mut().as_mut() {
+            let span = Span {
+                trace_id: trace.trace_id,
+                span_id: random_id(),
+                parent: Some(trace.span_id),
+                name: name.to_string(),
+                kind: Kind::Internal,
+                start,
+                end: now(),
+                attributes: Vec::new(),
+                error,
+            };
+            trace.children.push(span);
+        }
+    });
+}
+
+/// Ends the request being traced, returning its span and its steps'.
+pub fn end(
+    name: String,
+    attributes: Vec<(&'static str, Value)>,
+    error: Option<String>,
+) -> Vec<Span> {
+    let trace = match CURRENT.with(|current| current.borrow_mut().take()) {
+        Some(trace) => trace,
+        None => return Vec::new(),
+    };
+    let mut spans = vec![Span {
+        trace_id: trace.trace_id,
+        span_id: trace.span_id,
+        parent: trace.parent,
+        name,
+        kind: Kind::Server,
+        start: trace.start,
+        end: now(),
+        attributes,
+        error,
+    }];
+    spans.extend(trace.children);
+    spans
+}
+
+/// An attribute value in OTLP's `AnyValue` form.
+fn any_value(value: &Value) -> Value {
+    match value {
+        Value::Bool(b) => json!({"boolValue": b}),
+        Value::Number(n) if n.is_i64() || n.is_u64() => json!({"intValue": n.to_string()}),
+        Value::Number(n) => json!({"doubleValue": n.as_f64()}),
+        Value::String(text) => json!({"stringValue": text}),
+        other => json!({"stringValue": other.to_string()}),
+    }
+}
+
+/// An `ExportTraceServiceRequest` for `spans`.
+pub fn document(service: &str, spans: &[Span]) -> Value {
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|span| {
+            let mut fields = json!({
+                "traceId": hex(&span.trace_id),
+                "spanId": hex(&span.span_id),
+                "name": span.name,
+                "kind": span.kind as u8,
+                "startTimeUnixNano": span.start.to_string(),
+                "endTimeUnixNano": span.end.to_string(),
+                "attributes": span.attributes.iter().map(|(key, value)| {
+                    json!({"key": key, "value": any_value(value)})
+                }).collect::<Vec<_>>(),
+                "status": match &span.error {
+                    Some(message) => json!({"code": 2, "message": message}),
+                    None => json!({"code": 0}),
+                },
+            });
+            if let (Some(parent), Some(fields)) = (span.parent, fields.as_object_mut()) {
+                fields.insert("parentSpanId".to_string(), hex(&parent).into());
+            }
+            fields
+        })
+        .collect();
+    json!({"resourceSpans": [{
+        "resource": {"attributes": [
+            {"key": "service.name", "value": {"stringValue": service}},
+        ]},
+        "scopeSpans": [{
+            "scope": {"name": "jwt-check", "version": env!("CARGO_PKG_VERSION")},
+            "spans": spans,
+        }],
+    }]})
+}
+
+/// Delivers one encoded export.
+pub type Post = Box<dyn Fn(&[u8]) -> Result<(), JWTError> + Send>;
+
+/// The queue to the exporter thread; dropping it flushes what is queued.
+#[derive(Debug)]
+pub struct Exporter {
+    queue: Option<SyncSender<Vec<Span>>>,
+    dropped: Arc<AtomicUsize>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Exporter {
+    /// Starts the exporter thread, which hands each batch to `post`.
+    pub fn spawn(service: String, post: Post) -> Exporter {
+        let (queue, spans) = mpsc::sync_channel::<Vec<Span>>(QUEUE);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&dropped);
+        let thread = std::thread::spawn(move || {
+            let mut batch = Vec::new();
+            let mut due = Instant::now() + EXPORT_INTERVAL;
+            loop {
+                let wait = due.saturating_duration_since(Instant::now());
+                let open = match spans.recv_timeout(wait) {
+                    Ok(more) => {
+                        batch.extend(more);
+                        if batch.len() < MAX_BATCH && Instant::now() < due {
+                            continue;
+                        }
+                        true
+                    }
+                    Err(RecvTimeoutError::Timeout) => true,
+                    Err(RecvTimeoutError::Disconnected) => false,
+                };
+                flush(&service, &post, &mut batch, &counted);
+                due = Instant::now() + EXPORT_INTERVAL;
+                if !open {
+                    break;
+                }
+            }
+        });
+        Exporter {
+            queue: Some(queue),
+            dropped,
+            thread: Some(thread),
+        }
+    }
+
+    /// An exporter POSTing to the OTLP/HTTP collector at `endpoint`, with
+    /// `/v1/traces` added unless it is there already, through `client`.
+    pub fn to_collector(
+        endpoint: &str,
+        service: &str,
+        client: Client,
+    ) -> Result<Exporter, JWTError> {
+        if !cfg!(feature = "net") {
+            return Err(crate::feature_not_compiled("net"));
+        }
+        let url = match endpoint.trim_end_matches('/') {
+            url if url.ends_with(TRACES_PATH) => url.to_string(),
+            url => format!("{}{}", url, TRACES_PATH),
+        };
+        let sink = match url.parse::<Sink>() {
+            Ok(sink @ Sink::Http(_)) => sink,
+            Ok(_) => {
+                return Err(JWTError::InvalidArgumentError(
+                    format!(
+                        "--otlp-endpoint {}: expected the http[s]:// URL of an OTLP/HTTP collector",
+                        endpoint
+                    )
+                    .into(),
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+        let post: Post =
+            Box::new(move |body| sink.deliver(body, "application/json", 0, &|_| None, &client));
+        Ok(Exporter::spawn(service.to_string(), post))
+    }
+
+    /// Queues a request's spans, or drops them when the queue is full.
+    pub fn export(&self, spans: Vec<Span>) {
+        if spans.is_empty() {
+            return;
+        }
+        if let Some(queue) = &self.queue {
+            if let Err(TrySendError::Full(spans) | TrySendError::Disconnected(spans)) =
+                queue.try_send(spans)
+            {
+                self.dropped.fetch_add(spans.len(), Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        drop(self.queue.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Posts `batch`, logging a failed export and the spans dropped since the
+/// last one.
+fn flush(service: &str, post: &Post, batch: &mut Vec<Span>, dropped: &AtomicUsize) {
+    let dropped = dropped.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        let line = format!(
+            "otlp: dropped {} span(s); the collector is falling behind",
+            dropped
+        );
+        syslog::log(Priority::Warning, &line);
+    }
+    if batch.is_empty() {
+        return;
+    }
+    let body = document(service, batch).to_string();
+    if let Err(e) = post(body.as_bytes()) {
+        let line = format!("otlp: export of {} span(s) failed: {}", batch.len(), e);
+        syslog::log(Priority::Err, &line);
+    }
+    batch.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn trace_test() {
+        step("decode", now(), None);
+        assert!(end("POST".to_string(), Vec::new(), None).is_empty());
+
+        let parent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        begin(Some(parent));
+        let start = now();
+        step("decode", start, None);
+        step(
+            "signature check",
+            start,
+            Some("Error: Invalid signature".to_string()),
+        );
+        let spans = end(
+            "POST internal".to_string(),
+            vec![("http.response.status_code", json!(401))],
+            None,
+        );
+        let (root, decode, signature) = match spans.as_slice() {
+            [root, decode, signature] => (root, decode, signature),
+            _ => panic!("{:?}", spans),
+        };
+        assert_eq!(hex(&root.trace_id), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(
+            root.parent.map(|p| hex(&p)).as_deref(),
+            Some("00f067aa0ba902b7")
+        );
+        assert_eq!(
+            (decode.parent, signature.parent),
+            (Some(root.span_id), Some(root.span_id))
+        );
+        assert_ne!(decode.span_id, signature.span_id);
+        assert!(root.start <= decode.start && signature.end <= root.end);
+
+        let document = document("sidecar", &spans);
+        let sent = &document["resourceSpans"][0];
+        assert_eq!(
+            sent["resource"]["attributes"][0]["value"]["stringValue"],
+            "sidecar"
+        );
+        let sent = &sent["scopeSpans"][0]["spans"];
+        assert_eq!(sent[0]["kind"], 2);
+        assert_eq!(sent[0]["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(
+            sent[0]["attributes"][0],
+            json!({"key": "http.response.status_code", "value": {"intValue": "401"}})
+        );
+        assert_eq!(sent[1]["status"], json!({"code": 0}));
+        assert_eq!(
+            sent[2]["status"],
+            json!({"code": 2, "message": "Error: Invalid signature"})
+        );
+
+        for bad in [
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902bz-01",
+            "garbage",
+        ] {
+            assert_eq!(traceparent(bad), None, "{}", bad);
+        }
+        // an unreadable traceparent starts a trace of its own
+        begin(Some("garbage"));
+        let spans = end("GET".to_string(), Vec::new(), None);
+        assert!(spans.first().is_some_and(|root| root.parent.is_none()));
+    }
+
+    #[test]
+    fn exporter_test() {
+        let posted = Arc::new(Mutex::new(Vec::new()));
+        let sent = Arc::clone(&posted);
+        let exporter = Exporter::spawn(
+            "jwt-check".to_string(),
+            Box::new(move |body| {
+                sent.lock().unwrap().push(body.to_vec());
+                Ok(())
+            }),
+        );
+        begin(None);
+        exporter.export(end("POST".to_string(), Vec::new(), None));
+        exporter.export(Vec::new());
+        drop(exporter);
+        let posted = posted.lock().unwrap();
+        assert_eq!(posted.len(), 1);
+        let document: Value = serde_json::from_slice(&posted[0]).unwrap();
+        let spans = &document["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        assert_eq!(spans.as_array().map(Vec::len), Some(1));
+
+        for bad in ["s3://bucket/traces", "ftp://collector"] {
+            assert!(
+                Exporter::to_collector(bad, "jwt-check", Client::default()).is_err(),
+                "{}",
+                bad
+            );
+        }
+        let s3 = Exporter::to_collector("s3://bucket/traces", "jwt-check", Client::default());
+        if cfg!(feature = "net") {
+            assert!(matches!(
+                s3,
+                Err(JWTError::InvalidArgumentError(e)) if e
+                    == "--otlp-endpoint s3://bucket/traces: expected the http[s]:// URL of an OTLP/HTTP collector"
+            ));
+        } else {
+            assert!(matches!(s3, Err(JWTError::FeatureNotCompiledError(_))));
+        }
+    }
+}
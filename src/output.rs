@@ -0,0 +1,594 @@
+//! Rendering of the decoded token for `--format`.
+
+use crate::{
+    escape_non_ascii, format_timestamp, humanize_seconds, Freshness, JWTError, JWToken, TIME_CLAIMS,
+};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Indented, optionally colored JSON for each part.
+    Pretty,
+    /// One JSON document with `header`, `payload` and `signature` members.
+    Json,
+    /// Compact JSON, one part per line.
+    Raw,
+    /// One row per claim.
+    Table,
+}
+
+impl FromStr for Format {
+    type Err = JWTError;
+
+    fn from_str(name: &str) -> Result<Format, JWTError> {
+        match name {
+            "pretty" => Ok(Format::Pretty),
+            "json" => Ok(Format::Json),
+            "raw" => Ok(Format::Raw),
+            "table" => Ok(Format::Table),
+            _ => Err(JWTError::InvalidArgumentError(
+                format!(
+                    "unknown format `{}`; expected pretty, json, raw or table",
+                    name
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+/// Versions of the `--format json` document this build can emit.
+///
+/// 1 is `{header, payload, signature}` with the signature as a string.
+/// 2 adds `output_version`, `freshness` and `warnings`, and makes the
+/// signature an object with `encoding` and `value`. The default stays at 1
+/// so existing scripts keep working; a version is only dropped in a major
+/// release.
+pub const OUTPUT_VERSIONS: [u8; 2] = [1, 2];
+
+pub fn parse_output_version(value: &str) -> Result<u8, JWTError> {
+    value
+        .parse::<u8>()
+        .ok()
+        .filter(|v| OUTPUT_VERSIONS.contains(v))
+        .ok_or_else(|| {
+            JWTError::InvalidArgumentError(
+                format!("unsupported output version `{}`; expected 1 or 2", value).into(),
+            )
+        })
+}
+
+pub struct Style {
+    pub color: bool,
+    /// Wrap URLs in OSC 8 escapes so terminals make them clickable.
+    pub hyperlinks: bool,
+    pub hex_signature: bool,
+    pub output_version: u8,
+}
+
+/// What the checks concluded, for outputs that carry more than the token.
+pub struct Status<'a> {
+    pub freshness: Freshness,
+    pub warnings: &'a [String],
+}
+
+impl Style {
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// `text` as an OSC 8 hyperlink to `url` when hyperlinks are on.
+    pub fn link(&self, text: &str, url: &str) -> String {
+        if self.hyperlinks {
+            format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn signature(&self, bytes: &[u8]) -> String {
+        if self.hex_signature {
+            bytes.iter().map(|b| format!("{:02x}", b)).collect()
+        } else {
+            base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+        }
+    }
+}
+
+const KEY: &str = "1;34";
+const STRING: &str = "32";
+const NUMBER: &str = "36";
+const LITERAL: &str = "33";
+const LABEL: &str = "1";
+
+/// `--annotated` colors for the header, payload and signature.
+const SEGMENTS: [(&str, &str); 3] = [("header", "31"), ("payload", "35"), ("signature", "36")];
+/// Base64 groups per row of `--annotated`.
+const GROUPS_PER_ROW: usize = 10;
+
+/// `rendered` as it is printed: with `--ascii`, every non-ASCII character
+/// escaped.
+pub fn printable(rendered: &str, ascii: bool) -> Cow<'_, str> {
+    if ascii {
+        Cow::Owned(escape_non_ascii(rendered))
+    } else {
+        Cow::Borrowed(rendered)
+    }
+}
+
+/// Colors are used only on a terminal, and never when `NO_COLOR` is set.
+pub fn use_color(no_color: bool) -> bool {
+    !no_color && std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+}
+
+/// Hyperlinks go with colors, except on terminals that print OSC 8 as
+/// garbage.
+pub fn use_hyperlinks(color: bool) -> bool {
+    color && !matches!(std::env::var("TERM").as_deref(), Ok("dumb") | Ok("linux"))
+}
+
+fn is_url(text: &str) -> bool {
+    (text.starts_with("https://") || text.starts_with("http://"))
+        && !text.contains(|c: char| c.is_whitespace() || c.is_control())
+}
+
+/// Replaces `exp`, `nbf` and `iat` in `payload` with RFC 3339 strings, taking
+/// the values from `timestamps` so millisecond claims are already converted.
+pub fn humanize_dates(payload: &mut Value, timestamps: &Value) {
+    for name in TIME_CLAIMS.iter() {
+        let seconds = timestamps.get(name).and_then(Value::as_i64);
+        if let (Some(seconds), Some(claim)) = (seconds, payload.get_mut(*name)) {
+            *claim = Value::String(format_timestamp(seconds));
+        }
+    }
+}
+
+/// `--annotated`: `raw` with its segments colored, then each segment split
+/// into four-character base64 groups over the three bytes each encodes, so
+/// a chunk of the token can be matched to the JSON it holds. Bytes that are
+/// not printable ASCII show as `.`, and signature bytes as hex.
+pub fn annotate(raw: &str, style: &Style) -> Result<String, JWTError> {
+    let parts: Vec<&str> = raw.split('.').collect();
+    if parts.len() != SEGMENTS.len() {
+        return Err(JWTError::InvalidArgumentError(
+            "--annotated needs a compact token with three segments".into(),
+        ));
+    }
+    let colored: Vec<String> = parts
+        .iter()
+        .zip(SEGMENTS.iter())
+        .map(|(part, (_, code))| style.paint(code, part))
+        .collect();
+    let mut out = colored.join(".");
+    for (part, (name, code)) in parts.iter().zip(SEGMENTS.iter()) {
+        let part = part.trim_end_matches('=');
+        let bytes = base64::decode_config(part, base64::URL_SAFE_NO_PAD)?;
+        out.push_str("\n\n");
+        out.push_str(&style.paint(
+            LABEL,
+            &format!("{}: {} characters, {} bytes", name, part.len(), bytes.len()),
+        ));
+        let chars: Vec<char> = part.chars().collect();
+        let groups: Vec<(String, String)> = chars
+            .chunks(4)
+            .zip(bytes.chunks(3))
+            .map(|(encoded, decoded)| {
+                let shown = if *name == "signature" {
+                    decoded.iter().map(|b| format!("{:02x}", b)).collect()
+                } else {
+                    decoded
+                        .iter()
+                        .map(|b| match b {
+                            0x20..=0x7e => *b as char,
+                            _ => '.',
+                        })
+                        .collect()
+                };
+                (encoded.iter().collect(), shown)
+            })
+            .collect();
+        for row in groups.chunks(GROUPS_PER_ROW) {
+            let line = |texts: Vec<&String>| {
+                let padded: Vec<String> = texts.iter().map(|t| format!("{:<6}", t)).collect();
+                padded.join(" ").trim_end().to_string()
+            };
+            let encoded = line(row.iter().map(|g| &g.0).collect());
+            let decoded = line(row.iter().map(|g| &g.1).collect());
+            out.push_str(&format!(
+                "\n  {}\n  {}",
+                style.paint(code, &encoded),
+                decoded
+            ));
+        }
+    }
+    Ok(out)
+}
+
+pub fn render(
+    token: &JWToken,
+    format: Format,
+    style: &Style,
+    status: &Status,
+) -> Result<String, JWTError> {
+    let signature = style.signature(&token.signature);
+    let rendered = match format {
+        Format::Pretty => {
+            let mut out = String::new();
+            for (label, part) in [("header", &token.header), ("payload", &token.payload)] {
+                out.push_str(&style.paint(LABEL, &format!("{}:", label)));
+                out.push('\n');
+                pretty(&mut out, part, 0, style);
+                out.push('\n');
+            }
+            format!(
+                "{}{} {} ({} bytes)",
+                out,
+                style.paint(LABEL, "signature:"),
+                signature,
+                token.signature.len()
+            )
+        }
+        Format::Json if style.output_version >= 2 => {
+            serde_json::to_string_pretty(&serde_json::json!({
+                "output_version": 2,
+                "header": token.header,
+                "payload": token.payload,
+                "signature": {
+                    "encoding": if style.hex_signature { "hex" } else { "base64url" },
+                    "value": signature,
+                },
+                "freshness": status.freshness.to_string(),
+                "warnings": status.warnings,
+            }))?
+        }
+        Format::Json => serde_json::to_string_pretty(&serde_json::json!({
+            "header": token.header,
+            "payload": token.payload,
+            "signature": signature,
+        }))?,
+        Format::Raw => format!("{}\n{}\n{}", token.header, token.payload, signature),
+        Format::Table => table(token, &signature, style),
+    };
+    Ok(rendered)
+}
+
+/// Same layout as `serde_json::to_string_pretty`, with each token colored.
+fn pretty(out: &mut String, value: &Value, indent: usize, style: &Style) {
+    let pad = "  ".repeat(indent + 1);
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            out.push_str("{\n");
+            for (i, (key, member)) in map.iter().enumerate() {
+                out.push_str(&pad);
+                out.push_str(&style.paint(KEY, &Value::String(key.clone()).to_string()));
+                out.push_str(": ");
+                pretty(out, member, indent + 1, style);
+                out.push_str(if i + 1 < map.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&"  ".repeat(indent));
+            out.push('}');
+        }
+        Value::Array(items) if !items.is_empty() => {
+            out.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                out.push_str(&pad);
+                pretty(out, item, indent + 1, style);
+                out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&"  ".repeat(indent));
+            out.push(']');
+        }
+        Value::String(text) if is_url(text) => {
+            out.push_str(&style.link(&style.paint(STRING, &value.to_string()), text))
+        }
+        Value::String(_) => out.push_str(&style.paint(STRING, &value.to_string())),
+        Value::Number(_) => out.push_str(&style.paint(NUMBER, &value.to_string())),
+        Value::Bool(_) | Value::Null => out.push_str(&style.paint(LITERAL, &value.to_string())),
+        _ => out.push_str(&value.to_string()),
+    }
+}
+
+fn table(token: &JWToken, signature: &str, style: &Style) -> String {
+    let mut rows = Vec::new();
+    for (section, part) in [("header", &token.header), ("payload", &token.payload)] {
+        match part.as_object() {
+            Some(map) => {
+                for (claim, value) in map {
+                    let value = match value {
+                        Value::String(text) => text.clone(),
+                        other => other.to_string(),
+                    };
+                    rows.push((section, claim.as_str(), value));
+                }
+            }
+            None => rows.push((section, "", part.to_string())),
+        }
+    }
+    rows.push(("signature", "", signature.to_string()));
+    let section_width = rows
+        .iter()
+        .map(|r| r.0.len())
+        .fold("SECTION".len(), usize::max);
+    let claim_width = rows
+        .iter()
+        .map(|r| r.1.len())
+        .fold("CLAIM".len(), usize::max);
+    let mut out = style.paint(
+        LABEL,
+        &format!(
+            "{:s$}  {:c$}  VALUE",
+            "SECTION",
+            "CLAIM",
+            s = section_width,
+            c = claim_width
+        ),
+    );
+    for (section, claim, value) in rows {
+        out.push('\n');
+        out.push_str(&format!("{:s$}  ", section, s = section_width));
+        // pad before painting so escape codes don't skew the columns
+        let claim = format!("{:c$}", claim, c = claim_width);
+        out.push_str(&style.paint(KEY, &claim));
+        out.push_str("  ");
+        out.push_str(&value);
+    }
+    out
+}
+
+/// What `--summary-line` shows of a token, kept as it is decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub sub: Option<String>,
+    pub iss: Option<String>,
+    pub exp: Option<i64>,
+    pub freshness: Freshness,
+    pub verified: bool,
+}
+
+impl Summary {
+    pub fn new(token: &JWToken, timestamps: &Value, freshness: Freshness, verified: bool) -> Self {
+        let text = |name: &str| match token.payload.get(name) {
+            Some(Value::String(text)) => Some(text.clone()),
+            Some(other) => Some(other.to_string()),
+            None => None,
+        };
+        Summary {
+            sub: text("sub"),
+            iss: text("iss"),
+            exp: timestamps.get("exp").and_then(Value::as_i64),
+            freshness,
+            verified,
+        }
+    }
+}
+
+/// `--summary-line`: the verdict first, then who the token is for, who
+/// issued it and when it expires, on one line for a chat or a PR comment.
+/// `summary` is `None` when the token could not even be decoded.
+pub fn summary_line(summary: Option<&Summary>, verdict: &Result<(), JWTError>, now: i64) -> String {
+    let mut parts = vec![match (verdict, summary.map(|s| s.freshness)) {
+        (Err(error), _) => format!("❌ {}", error),
+        (Ok(()), Some(Freshness::Expired)) => "⌛ expired".to_string(),
+        (Ok(()), Some(Freshness::NotYetValid)) => "⏳ not yet valid".to_string(),
+        (Ok(()), Some(Freshness::ExpiringSoon)) => "⚠️ expiring soon".to_string(),
+        (Ok(()), _) if summary.is_some_and(|s| s.verified) => "✅ valid, verified".to_string(),
+        (Ok(()), _) => "✅ valid, not verified".to_string(),
+    }];
+    if let Some(summary) = summary {
+        parts.push(format!("sub {}", summary.sub.as_deref().unwrap_or("-")));
+        parts.push(format!("iss {}", summary.iss.as_deref().unwrap_or("-")));
+        parts.push(match summary.exp {
+            Some(exp) if exp > now => format!(
+                "expires {} (in {})",
+                format_timestamp(exp),
+                humanize_seconds(exp.saturating_sub(now))
+            ),
+            Some(exp) => format!(
+                "expired {} ({} ago)",
+                format_timestamp(exp),
+                humanize_seconds(now.saturating_sub(exp))
+            ),
+            None => "never expires".to_string(),
+        });
+    }
+    parts.join(" · ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, SAMPLE_TOKEN};
+
+    const PLAIN: Style = Style {
+        color: false,
+        hyperlinks: false,
+        hex_signature: false,
+        output_version: 1,
+    };
+    const STATUS: Status = Status {
+        freshness: Freshness::Valid,
+        warnings: &[],
+    };
+
+    #[test]
+    fn formats_test() {
+        let token = parse(SAMPLE_TOKEN).unwrap();
+        let pretty_text = render(&token, Format::Pretty, &PLAIN, &STATUS).unwrap();
+        assert_eq!(
+            pretty_text,
+            format!(
+                "header:\n{}\npayload:\n{}\nsignature: {} (32 bytes)",
+                serde_json::to_string_pretty(&token.header).unwrap(),
+                serde_json::to_string_pretty(&token.payload).unwrap(),
+                SAMPLE_TOKEN.rsplit('.').next().unwrap()
+            )
+        );
+        let json: Value =
+            serde_json::from_str(&render(&token, Format::Json, &PLAIN, &STATUS).unwrap()).unwrap();
+        assert_eq!(json["payload"]["name"], "John Doe");
+        let raw = render(&token, Format::Raw, &PLAIN, &STATUS).unwrap();
+        assert_eq!(raw.lines().next(), Some(r#"{"alg":"HS256","typ":"JWT"}"#));
+        let table_text = render(&token, Format::Table, &PLAIN, &STATUS).unwrap();
+        assert!(table_text.contains("\npayload    name   John Doe\n"));
+        let hex = Style {
+            color: false,
+            hyperlinks: false,
+            hex_signature: true,
+            output_version: 1,
+        };
+        assert!(render(&token, Format::Raw, &hex, &STATUS)
+            .unwrap()
+            .ends_with("\n49f94ac7044948c78a285d904f87f0a4c7897f7e8f3a4eb2255fda750b2cc397"));
+        let colored = Style {
+            color: true,
+            hyperlinks: true,
+            hex_signature: false,
+            output_version: 1,
+        };
+        assert!(render(&token, Format::Pretty, &colored, &STATUS)
+            .unwrap()
+            .contains("\x1b[1;34m\"alg\"\x1b[0m: \x1b[32m\"HS256\"\x1b[0m"));
+        let issued = JWToken {
+            payload: serde_json::json!({"iss": "https://idp.example"}),
+            ..token.clone()
+        };
+        assert!(render(&issued, Format::Pretty, &colored, &STATUS)
+            .unwrap()
+            .contains(
+            "\x1b]8;;https://idp.example\x1b\\\x1b[32m\"https://idp.example\"\x1b[0m\x1b]8;;\x1b\\"
+        ));
+        assert!(matches!(
+            "yaml".parse::<Format>(),
+            Err(JWTError::InvalidArgumentError(e))
+                if e == "unknown format `yaml`; expected pretty, json, raw or table"
+        ));
+    }
+
+    #[test]
+    fn annotated_test() {
+        let annotated = annotate(SAMPLE_TOKEN, &PLAIN).unwrap();
+        let mut lines = annotated.lines();
+        assert_eq!(lines.next(), Some(SAMPLE_TOKEN));
+        assert_eq!(lines.next(), Some(""));
+        assert_eq!(lines.next(), Some("header: 36 characters, 27 bytes"));
+        assert_eq!(
+            lines.next(),
+            Some("  eyJh   bGci   OiJI   UzI1   NiIs   InR5   cCI6   IkpX   VCJ9")
+        );
+        assert_eq!(
+            lines.next(),
+            Some(r#"  {"a    lg"    :"H    S25    6",    "ty    p":    "JW    T"}"#)
+        );
+        assert!(annotated.contains("\nsignature: 43 characters, 32 bytes\n  SflK   xwRJ"));
+        assert!(annotated.contains("\n  49f94a c70449"));
+        let colored = Style {
+            color: true,
+            ..PLAIN
+        };
+        assert!(annotate(SAMPLE_TOKEN, &colored)
+            .unwrap()
+            .starts_with("\x1b[31meyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9\x1b[0m.\x1b[35m"));
+        for bad in ["a.b", "a.b.c.d"] {
+            assert!(matches!(
+                annotate(bad, &PLAIN),
+                Err(JWTError::InvalidArgumentError(e))
+                    if e == "--annotated needs a compact token with three segments"
+            ));
+        }
+    }
+
+    #[test]
+    fn output_version_test() {
+        let token = parse(SAMPLE_TOKEN).unwrap();
+        let v2 = Style {
+            color: false,
+            hyperlinks: false,
+            hex_signature: false,
+            output_version: 2,
+        };
+        let warnings = ["payload.name: something odd".to_string()];
+        let status = Status {
+            freshness: Freshness::Expired,
+            warnings: &warnings,
+        };
+        let json: Value =
+            serde_json::from_str(&render(&token, Format::Json, &v2, &status).unwrap()).unwrap();
+        assert_eq!(json["output_version"], 2);
+        assert_eq!(json["signature"]["encoding"], "base64url");
+        assert_eq!(json["freshness"], "expired");
+        assert_eq!(json["warnings"][0], "payload.name: something odd");
+        let v1: Value =
+            serde_json::from_str(&render(&token, Format::Json, &PLAIN, &status).unwrap()).unwrap();
+        assert!(v1["signature"].is_string());
+        assert!(v1.get("output_version").is_none());
+        assert_eq!(parse_output_version("2").unwrap(), 2);
+        for bad in ["3", "", "v2"] {
+            assert!(matches!(
+                parse_output_version(bad),
+                Err(JWTError::InvalidArgumentError(e))
+                    if e == format!("unsupported output version `{}`; expected 1 or 2", bad)
+            ));
+        }
+    }
+
+    #[test]
+    fn printable_test() {
+        assert_eq!(printable("\"José\"", false), "\"José\"");
+        assert_eq!(printable("\"José\"", true), "\"Jos\\u00e9\"");
+    }
+
+    #[test]
+    fn humanize_dates_test() {
+        let mut payload = serde_json::json!({"iat": 1516239022000u64, "sub": "x"});
+        let timestamps = serde_json::json!({"iat": 1516239022, "sub": "x"});
+        humanize_dates(&mut payload, &timestamps);
+        assert_eq!(
+            payload,
+            serde_json::json!({"iat": "2018-01-18T01:30:22Z", "sub": "x"})
+        );
+    }
+
+    #[test]
+    fn summary_line_test() {
+        let token = JWToken {
+            payload: serde_json::json!({"sub": "alice", "iss": "https://idp.example", "exp": 1700003600}),
+            ..parse(SAMPLE_TOKEN).unwrap()
+        };
+        let summary = Summary::new(&token, &token.payload, Freshness::Valid, true);
+        assert_eq!(
+            summary_line(Some(&summary), &Ok(()), 1700000000),
+            "✅ valid, verified · sub alice · iss https://idp.example · expires 2023-11-14T23:13:20Z (in 1 hour)"
+        );
+        let expired = Summary {
+            freshness: Freshness::Expired,
+            verified: false,
+            ..summary.clone()
+        };
+        assert!(
+            summary_line(Some(&expired), &Ok(()), 1700007200).starts_with("⌛ expired · sub alice")
+        );
+        assert!(summary_line(Some(&expired), &Ok(()), 1700007200).ends_with("(1 hour ago)"));
+        assert!(summary_line(
+            Some(&summary),
+            &Err(JWTError::InvalidSignatureError),
+            1700000000
+        )
+        .starts_with("❌ "));
+        let ancient = Summary {
+            exp: Some(i64::MIN),
+            ..expired
+        };
+        assert!(summary_line(Some(&ancient), &Ok(()), 1700007200).starts_with("⌛ expired"));
+        assert_eq!(
+            summary_line(None, &Err(JWTError::MissingPartError), 0),
+            format!("❌ {}", JWTError::MissingPartError)
+        );
+    }
+}
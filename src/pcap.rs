@@ -0,0 +1,426 @@
+//! Packet capture reading: pcap and pcapng files, down to reassembled TCP
+//! streams.
+//!
+//! Only what token extraction needs is decoded: Ethernet, Linux cooked,
+//! loopback and raw IP links, IPv4 (unfragmented) and IPv6, and TCP. Each
+//! connection becomes two byte streams, one per direction.
+
+use crate::JWTError;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_ACK: u8 = 0x10;
+
+fn capture_error(message: &str) -> JWTError {
+    JWTError::InvalidArgumentError(format!("capture: {}", message).into())
+}
+
+fn u16_at(data: &[u8], at: usize, big_endian: bool) -> Option<u16> {
+    let bytes = [*data.get(at)?, *data.get(at + 1)?];
+    Some(if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    })
+}
+
+fn u32_at(data: &[u8], at: usize, big_endian: bool) -> Option<u32> {
+    let bytes = [
+        *data.get(at)?,
+        *data.get(at + 1)?,
+        *data.get(at + 2)?,
+        *data.get(at + 3)?,
+    ];
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+/// One captured frame and the link type needed to decode it.
+struct Frame<'a> {
+    link: u32,
+    data: &'a [u8],
+}
+
+fn pcap_frames(file: &[u8], big_endian: bool) -> Result<Vec<Frame<'_>>, JWTError> {
+    let truncated = || capture_error("truncated pcap file");
+    let link = u32_at(file, 20, big_endian).ok_or_else(truncated)?;
+    let mut frames = Vec::new();
+    let mut at = 24;
+    while at < file.len() {
+        let captured = u32_at(file, at + 8, big_endian).ok_or_else(truncated)? as usize;
+        let data = file
+            .get(at + 16..at + 16 + captured)
+            .ok_or_else(truncated)?;
+        frames.push(Frame { link, data });
+        at += 16 + captured;
+    }
+    Ok(frames)
+}
+
+fn pcapng_frames(file: &[u8]) -> Result<Vec<Frame<'_>>, JWTError> {
+    let truncated = || capture_error("truncated pcapng file");
+    let mut frames = Vec::new();
+    let mut links = Vec::new();
+    let mut big_endian = false;
+    let mut at = 0;
+    while at < file.len() {
+        let kind = u32_at(file, at, big_endian).ok_or_else(truncated)?;
+        if kind == 0x0a0d_0d0a {
+            // a section header resets byte order and interfaces
+            big_endian = file.get(at + 8..at + 12) == Some(&[0x1a, 0x2b, 0x3c, 0x4d]);
+            links.clear();
+        }
+        let length = u32_at(file, at + 4, big_endian).ok_or_else(truncated)? as usize;
+        if length < 12 || length % 4 != 0 {
+            return Err(capture_error("malformed pcapng block"));
+        }
+        let block = file.get(at..at + length).ok_or_else(truncated)?;
+        match kind {
+            // interface description
+            1 => links.push(u16_at(block, 8, big_endian).ok_or_else(truncated)? as u32),
+            // enhanced packet
+            6 => {
+                let interface = u32_at(block, 8, big_endian).ok_or_else(truncated)? as usize;
+                let captured = u32_at(block, 20, big_endian).ok_or_else(truncated)? as usize;
+                let link = *links
+                    .get(interface)
+                    .ok_or_else(|| capture_error("packet names an unknown interface"))?;
+                let data = block.get(28..28 + captured).ok_or_else(truncated)?;
+                frames.push(Frame { link, data });
+            }
+            // simple packet, always on the first interface
+            3 => {
+                let link = *links
+                    .first()
+                    .ok_or_else(|| capture_error("packet before any interface"))?;
+                let data = block.get(12..length - 4).ok_or_else(truncated)?;
+                frames.push(Frame { link, data });
+            }
+            _ => (),
+        }
+        at += length;
+    }
+    Ok(frames)
+}
+
+fn frames(file: &[u8]) -> Result<Vec<Frame<'_>>, JWTError> {
+    match file.get(..4) {
+        Some([0xd4, 0xc3, 0xb2, 0xa1]) | Some([0x4d, 0x3c, 0xb2, 0xa1]) => pcap_frames(file, false),
+        Some([0xa1, 0xb2, 0xc3, 0xd4]) | Some([0xa1, 0xb2, 0x3c, 0x4d]) => pcap_frames(file, true),
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => pcapng_frames(file),
+        _ => Err(capture_error("not a pcap or pcapng file")),
+    }
+}
+
+/// The IP packet inside a link-layer frame, if it carries one.
+fn ip_packet<'a>(frame: &Frame<'a>) -> Option<&'a [u8]> {
+    let data = frame.data;
+    match frame.link {
+        LINKTYPE_ETHERNET => {
+            let mut at = 12;
+            let mut ethertype = u16_at(data, at, true)?;
+            // skip 802.1Q / 802.1ad VLAN tags
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                at += 4;
+                ethertype = u16_at(data, at, true)?;
+            }
+            match ethertype {
+                0x0800 | 0x86dd => data.get(at + 2..),
+                _ => None,
+            }
+        }
+        LINKTYPE_LINUX_SLL => data.get(16..),
+        LINKTYPE_LINUX_SLL2 => data.get(20..),
+        LINKTYPE_NULL => data.get(4..),
+        LINKTYPE_RAW | 12 | 14 => Some(data),
+        _ => None,
+    }
+}
+
+/// A TCP segment with its endpoints.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub seq: u32,
+    pub flags: u8,
+    pub data: Vec<u8>,
+}
+
+fn tcp_segment(packet: &[u8]) -> Option<Segment> {
+    let version = packet.first()? >> 4;
+    let (src, dst, tcp) = match version {
+        4 => {
+            let header_len = usize::from(packet.first()? & 0x0f) * 4;
+            let total_len = usize::from(u16_at(packet, 2, true)?);
+            let fragment = u16_at(packet, 6, true)?;
+            // later fragments and non-TCP packets carry nothing we can use
+            if *packet.get(9)? != 6 || fragment & 0x1fff != 0 || fragment & 0x2000 != 0 {
+                return None;
+            }
+            let address = |at: usize| -> Option<IpAddr> {
+                let b = packet.get(at..at + 4)?;
+                Some(IpAddr::V4(Ipv4Addr::new(
+                    *b.first()?,
+                    *b.get(1)?,
+                    *b.get(2)?,
+                    *b.get(3)?,
+                )))
+            };
+            let end = total_len.min(packet.len());
+            (address(12)?, address(16)?, packet.get(header_len..end)?)
+        }
+        6 => {
+            let payload_len = usize::from(u16_at(packet, 4, true)?);
+            let address = |at: usize| -> Option<IpAddr> {
+                let mut b = [0u8; 16];
+                b.copy_from_slice(packet.get(at..at + 16)?);
+                Some(IpAddr::V6(Ipv6Addr::from(b)))
+            };
+            let mut next = *packet.get(6)?;
+            let mut at = 40;
+            // hop-by-hop, routing and destination options headers
+            while matches!(next, 0 | 43 | 60) {
+                next = *packet.get(at)?;
+                at += (usize::from(*packet.get(at + 1)?) + 1) * 8;
+            }
+            if next != 6 {
+                return None;
+            }
+            let end = (40 + payload_len).min(packet.len());
+            (address(8)?, address(24)?, packet.get(at..end)?)
+        }
+        _ => return None,
+    };
+    let data_offset = usize::from(tcp.get(12)? >> 4) * 4;
+    Some(Segment {
+        src: SocketAddr::new(src, u16_at(tcp, 0, true)?),
+        dst: SocketAddr::new(dst, u16_at(tcp, 2, true)?),
+        seq: u32_at(tcp, 4, true)?,
+        flags: *tcp.get(13)?,
+        data: tcp.get(data_offset..)?.to_vec(),
+    })
+}
+
+/// Every TCP segment in a capture file, in capture order.
+pub fn tcp_segments(file: &[u8]) -> Result<Vec<Segment>, JWTError> {
+    Ok(frames(file)?
+        .iter()
+        .filter_map(ip_packet)
+        .filter_map(tcp_segment)
+        .collect())
+}
+
+/// One direction of a connection, as received so far.
+#[derive(Default)]
+struct Direction {
+    base: Option<u32>,
+    chunks: BTreeMap<u32, Vec<u8>>,
+}
+
+impl Direction {
+    fn add(&mut self, segment: &Segment) {
+        if segment.flags & TCP_SYN != 0 {
+            self.base = Some(segment.seq.wrapping_add(1));
+        }
+        if segment.data.is_empty() {
+            return;
+        }
+        let base = *self.base.get_or_insert(segment.seq);
+        let offset = segment.seq.wrapping_sub(base);
+        // data from before the first segment we saw
+        if offset > u32::MAX / 2 {
+            return;
+        }
+        let chunk = self.chunks.entry(offset).or_default();
+        if segment.data.len() > chunk.len() {
+            *chunk = segment.data.clone();
+        }
+    }
+
+    /// The bytes in sequence order, with retransmissions and overlaps removed.
+    /// Gaps from lost segments are skipped.
+    fn assemble(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut end = 0usize;
+        for (offset, data) in &self.chunks {
+            let offset = *offset as usize;
+            let skip = end.saturating_sub(offset);
+            if let Some(fresh) = data.get(skip..) {
+                out.extend_from_slice(fresh);
+                end = end.max(offset + data.len());
+            }
+        }
+        out
+    }
+}
+
+/// A reassembled TCP connection. The client is the side that sent the SYN,
+/// or the first packet when the handshake was not captured.
+pub struct Stream {
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    pub to_server: Vec<u8>,
+    pub to_client: Vec<u8>,
+}
+
+pub fn streams(segments: &[Segment]) -> Vec<Stream> {
+    let mut order = Vec::new();
+    let mut connections: HashMap<(SocketAddr, SocketAddr), (Direction, Direction)> = HashMap::new();
+    for segment in segments {
+        let forward = (segment.src, segment.dst);
+        let reverse = (segment.dst, segment.src);
+        let (key, outbound) = if connections.contains_key(&reverse) {
+            (reverse, false)
+        } else if connections.contains_key(&forward) {
+            (forward, true)
+        } else {
+            // a SYN/ACK means we missed the SYN and this is the server
+            let from_server = segment.flags & (TCP_SYN | TCP_ACK) == TCP_SYN | TCP_ACK;
+            let key = if from_server { reverse } else { forward };
+            order.push(key);
+            (key, !from_server)
+        };
+        let (to_server, to_client) = connections.entry(key).or_default();
+        if outbound {
+            to_server.add(segment);
+        } else {
+            to_client.add(segment);
+        }
+    }
+    order
+        .into_iter()
+        .filter_map(|key| {
+            let (to_server, to_client) = connections.remove(&key)?;
+            Some(Stream {
+                client: key.0,
+                server: key.1,
+                to_server: to_server.assemble(),
+                to_client: to_client.assemble(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A classic little-endian Ethernet pcap holding IPv4 TCP segments given
+    /// as (from client, flags, seq, payload).
+    pub(crate) fn capture(segments: &[(bool, u8, u32, &[u8])]) -> Vec<u8> {
+        let mut file = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        file.extend_from_slice(&[0; 8]);
+        file.extend_from_slice(&65535u32.to_le_bytes());
+        file.extend_from_slice(&1u32.to_le_bytes());
+        for (from_client, flags, seq, payload) in segments {
+            let (src, dst, sport, dport) = if *from_client {
+                ([10, 0, 0, 1], [10, 0, 0, 2], 40000u16, 80u16)
+            } else {
+                ([10, 0, 0, 2], [10, 0, 0, 1], 80, 40000)
+            };
+            let mut tcp = Vec::new();
+            tcp.extend_from_slice(&sport.to_be_bytes());
+            tcp.extend_from_slice(&dport.to_be_bytes());
+            tcp.extend_from_slice(&seq.to_be_bytes());
+            tcp.extend_from_slice(&[0, 0, 0, 0, 0x50, *flags, 0xff, 0xff, 0, 0, 0, 0]);
+            tcp.extend_from_slice(payload);
+            let mut ip = vec![0x45, 0];
+            ip.extend_from_slice(&((20 + tcp.len()) as u16).to_be_bytes());
+            ip.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+            ip.extend_from_slice(&src);
+            ip.extend_from_slice(&dst);
+            ip.extend_from_slice(&tcp);
+            let mut frame = vec![0u8; 12];
+            frame.extend_from_slice(&[0x08, 0x00]);
+            frame.extend_from_slice(&ip);
+            file.extend_from_slice(&[0; 8]);
+            file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            file.extend_from_slice(&frame);
+        }
+        file
+    }
+
+    #[test]
+    fn reassembles_out_of_order_and_retransmitted_segments_test() {
+        let file = capture(&[
+            (true, TCP_SYN, 99, b""),
+            (false, TCP_SYN | TCP_ACK, 499, b""),
+            (true, TCP_ACK, 106, b"world"),
+            (true, TCP_ACK, 100, b"hello "),
+            (true, TCP_ACK, 100, b"hello "),
+            (false, TCP_ACK, 500, b"ok"),
+        ]);
+        let streams = streams(&tcp_segments(&file).unwrap());
+        assert_eq!(streams.len(), 1);
+        let stream = &streams[0];
+        assert_eq!(stream.client.to_string(), "10.0.0.1:40000");
+        assert_eq!(stream.server.to_string(), "10.0.0.2:80");
+        assert_eq!(stream.to_server, b"hello world");
+        assert_eq!(stream.to_client, b"ok");
+        assert!(tcp_segments(b"not a capture").is_err());
+    }
+
+    /// A little-endian pcapng block of `kind` around `body`.
+    fn block(kind: u32, body: &[u8]) -> Vec<u8> {
+        let length = (12 + body.len()) as u32;
+        let mut block = kind.to_le_bytes().to_vec();
+        block.extend_from_slice(&length.to_le_bytes());
+        block.extend_from_slice(body);
+        block.extend_from_slice(&length.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn malformed_captures_test() {
+        let error_of = |file: &[u8]| error_message!(InvalidArgumentError, tcp_segments(file));
+        assert_eq!(
+            error_of(b"not a capture"),
+            "capture: not a pcap or pcapng file"
+        );
+        let file = capture(&[(true, TCP_SYN, 99, b"")]);
+        assert_eq!(
+            error_of(&file[..file.len() - 1]),
+            "capture: truncated pcap file"
+        );
+        assert_eq!(error_of(&file[..4]), "capture: truncated pcap file");
+
+        let section = block(
+            0x0a0d_0d0a,
+            &[
+                0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            ],
+        );
+        let after_section = |blocks: &[u8]| [&section[..], blocks].concat();
+        let mut odd = block(1, &[1, 0, 0, 0, 0, 0, 0, 0]);
+        odd[4] = 13;
+        assert_eq!(
+            error_of(&after_section(&odd)),
+            "capture: malformed pcapng block"
+        );
+        let packet = block(6, &[0; 20]);
+        assert_eq!(
+            error_of(&after_section(&packet[..packet.len() - 4])),
+            "capture: truncated pcapng file"
+        );
+        assert_eq!(
+            error_of(&after_section(&packet)),
+            "capture: packet names an unknown interface"
+        );
+        assert_eq!(
+            error_of(&after_section(&block(3, &[0; 4]))),
+            "capture: packet before any interface"
+        );
+    }
+}
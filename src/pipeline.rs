@@ -0,0 +1,202 @@
+//! `jwt-check pipe`: claim edits applied in order to a decoded token, then
+//! the key it is re-signed with, for turning captured (and redacted)
+//! production tokens into test fixtures in one invocation.
+
+use crate::verify::Algorithm;
+use crate::{parse_duration, JWTError};
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+/// One `--drop-claim` or `--set`, in the order given on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Drop(String),
+    Set(String, String),
+}
+
+impl Step {
+    /// `--set KEY=VALUE`.
+    pub fn set(spec: &str) -> Result<Step, JWTError> {
+        let (name, value) = spec.split_once('=').ok_or_else(|| {
+            JWTError::InvalidArgumentError(
+                format!("expected --set KEY=VALUE, got `{}`", spec).into(),
+            )
+        })?;
+        Ok(Step::Set(name.to_string(), value.to_string()))
+    }
+
+    /// Dropping a claim the token lacks is not an error, so one pipeline
+    /// fits tokens that differ in their optional claims.
+    pub fn apply(&self, claims: &mut Map<String, Value>, now: i64) -> Result<(), JWTError> {
+        match self {
+            Step::Drop(name) => {
+                claims.remove(name);
+            }
+            Step::Set(name, value) => {
+                claims.insert(name.clone(), set_value(value, now)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A `--set` value: `+DURATION` or `-DURATION` is that long after or before
+/// `now`, in epoch seconds; anything else is JSON when it parses, else a
+/// string.
+pub fn set_value(value: &str, now: i64) -> Result<Value, JWTError> {
+    let relative = |rest: &str| rest.ends_with(|c: char| c.is_ascii_alphabetic());
+    if let Some(rest) = value.strip_prefix('+').filter(|rest| relative(rest)) {
+        return Ok(now.saturating_add(parse_duration(rest)?).into());
+    }
+    if let Some(rest) = value.strip_prefix('-').filter(|rest| relative(rest)) {
+        return Ok(now.saturating_sub(parse_duration(rest)?).into());
+    }
+    Ok(serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string())))
+}
+
+/// Where `--resign` finds its secret or PEM key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeySource {
+    Env(String),
+    File(String),
+}
+
+/// `--resign ALG:env:VAR` or `--resign ALG:file:PATH`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resign {
+    pub alg: Algorithm,
+    pub key: KeySource,
+}
+
+impl FromStr for Resign {
+    type Err = JWTError;
+
+    fn from_str(spec: &str) -> Result<Resign, JWTError> {
+        let expected = || {
+            JWTError::InvalidArgumentError(
+                format!(
+                    "expected --resign ALG:env:VAR or ALG:file:PATH, got `{}`",
+                    spec
+                )
+                .into(),
+            )
+        };
+        let mut parts = spec.splitn(3, ':');
+        let (alg, source, reference) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(alg), Some(source), Some(reference)) if !reference.is_empty() => {
+                (alg, source, reference.to_string())
+            }
+            _ => return Err(expected()),
+        };
+        let key = match source {
+            "env" => KeySource::Env(reference),
+            "file" => KeySource::File(reference),
+            _ => return Err(expected()),
+        };
+        Ok(Resign {
+            alg: alg.to_ascii_uppercase().parse()?,
+            key,
+        })
+    }
+}
+
+/// The captured header with the new `alg`. Its `kid` named the production
+/// key, so it is replaced by `kid` or else dropped.
+pub fn header(captured: &Value, alg: Algorithm, kid: Option<&str>) -> Value {
+    let mut header = captured.as_object().cloned().unwrap_or_default();
+    header.insert("alg".to_string(), alg.to_string().into());
+    match kid {
+        Some(kid) => header.insert("kid".to_string(), kid.into()),
+        None => header.remove("kid"),
+    };
+    Value::Object(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn steps_test() {
+        let now = 1_700_000_000;
+        let mut claims = json!({"sub": "u-1", "email": "a@example.com", "exp": 1})
+            .as_object()
+            .cloned()
+            .unwrap();
+        let steps = [
+            Step::Drop("email".to_string()),
+            Step::Drop("missing".to_string()),
+            Step::set("exp=+1h").unwrap(),
+            Step::set("nbf=-5m").unwrap(),
+            Step::set("roles=[\"admin\"]").unwrap(),
+            Step::set("note=-5").unwrap(),
+            Step::set("env=staging").unwrap(),
+        ];
+        for step in &steps {
+            step.apply(&mut claims, now).unwrap();
+        }
+        assert_eq!(
+            Value::Object(claims),
+            json!({
+                "sub": "u-1",
+                "exp": now + 3600,
+                "nbf": now - 300,
+                "roles": ["admin"],
+                "note": -5,
+                "env": "staging"
+            })
+        );
+        assert!(matches!(
+            Step::set("exp"),
+            Err(JWTError::InvalidArgumentError(e)) if e == "expected --set KEY=VALUE, got `exp`"
+        ));
+        assert!(matches!(
+            set_value("+1w", now),
+            Err(JWTError::InvalidArgumentError(e))
+                if e == "expected a duration like 30s, 5m or 1h, got `1w`"
+        ));
+    }
+
+    #[test]
+    fn resign_test() {
+        assert_eq!(
+            "hs256:env:SECRET".parse::<Resign>().unwrap(),
+            Resign {
+                alg: Algorithm::HS256,
+                key: KeySource::Env("SECRET".to_string())
+            }
+        );
+        assert_eq!(
+            "ES256:file:C:/keys/test.pem".parse::<Resign>().unwrap().key,
+            KeySource::File("C:/keys/test.pem".to_string())
+        );
+        for bad in ["hs256", "hs256:env:", "hs256:vault:secret/jwt"] {
+            assert!(matches!(
+                bad.parse::<Resign>(),
+                Err(JWTError::InvalidArgumentError(e))
+                    if e == format!(
+                        "expected --resign ALG:env:VAR or ALG:file:PATH, got `{}`",
+                        bad
+                    )
+            ));
+        }
+        assert!(matches!(
+            "none:env:SECRET".parse::<Resign>(),
+            Err(JWTError::UnsupportedAlgorithmError(_))
+        ));
+    }
+
+    #[test]
+    fn header_test() {
+        let captured = json!({"alg": "RS256", "typ": "JWT", "kid": "prod-2024"});
+        assert_eq!(
+            header(&captured, Algorithm::HS256, None),
+            json!({"alg": "HS256", "typ": "JWT"})
+        );
+        assert_eq!(
+            header(&captured, Algorithm::ES256, Some("fixture"))["kid"],
+            "fixture"
+        );
+    }
+}
@@ -0,0 +1,628 @@
+//! `--preset`: the issuers, JWKS endpoints, algorithms and audience rules of
+//! identity providers whose tokens are validated often enough that getting
+//! them wrong is a common question.
+//!
+//! A preset reports its requirements the way `--profile` does, and with
+//! `--verify` and no other key supplies the provider's JWKS URL. Each also
+//! prints the provider's known quirks, which explain most tokens that look
+//! right and still fail.
+//!
+//! - `sign-in-with-apple`: `iss` `https://appleid.apple.com`, RS256 keys at
+//!   `https://appleid.apple.com/auth/keys`, and `aud` the app's bundle ID
+//!   (native) or Services ID (web).
+//! - `google-id-token`: `iss` `https://accounts.google.com` or, for older
+//!   clients, `accounts.google.com`; RS256 keys at
+//!   `https://www.googleapis.com/oauth2/v3/certs`; `aud` the OAuth client ID,
+//!   with `azp` naming the presenter when there are several audiences.
+//! - `firebase`: `iss` `https://securetoken.google.com/{project}` and `aud`
+//!   the project ID, RS256 keys from the securetoken service account (not
+//!   Google's OAuth keys), and the user's UID as `sub`.
+//! - `supabase`: `iss` `https://{project}.supabase.co/auth/v1` and `aud`
+//!   `authenticated`, HS256 with the project's JWT secret, or RS256 or ES256
+//!   with the project's JWKS once it has moved to asymmetric keys. A secret
+//!   that is itself a Supabase API key (`anon` or `service_role`) is refused
+//!   with an explanation, since that mix-up fails every token.
+
+use crate::profile::{requirement, Requirement};
+use crate::{audiences, parse, JWTError, JWToken};
+use serde_json::Value;
+use std::fmt;
+
+/// The names `--preset` takes.
+pub const PRESETS: [&str; 4] = [
+    "sign-in-with-apple",
+    "google-id-token",
+    "firebase",
+    "supabase",
+];
+
+const OIDC: &str = "OpenID Connect Core";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Provider {
+    Apple,
+    Google,
+    Firebase,
+    Supabase,
+}
+
+/// A provider, and the app whose tokens are being checked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preset {
+    pub provider: Provider,
+    /// `--client-id`: the `aud` tokens for this app carry.
+    pub client_id: Option<String>,
+    /// `--project`: the Firebase project ID or Supabase project ref.
+    pub project: Option<String>,
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.provider {
+            Provider::Apple => "sign-in-with-apple",
+            Provider::Google => "google-id-token",
+            Provider::Firebase => "firebase",
+            Provider::Supabase => "supabase",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Preset {
+    pub fn new(
+        name: &str,
+        client_id: Option<&str>,
+        project: Option<&str>,
+    ) -> Result<Preset, JWTError> {
+        let provider = match name {
+            "sign-in-with-apple" => Provider::Apple,
+            "google-id-token" => Provider::Google,
+            "firebase" => Provider::Firebase,
+            "supabase" => Provider::Supabase,
+            other => {
+                return Err(JWTError::InvalidArgumentError(
+                    format!(
+                        "`{}` is not a preset; expected one of {}",
+                        other,
+                        PRESETS.join(", ")
+                    )
+                    .into(),
+                ))
+            }
+        };
+        Ok(Preset {
+            provider,
+            client_id: client_id.map(str::to_string),
+            project: project.map(str::to_string),
+        })
+    }
+
+    /// The `iss` values the provider signs with; none for a per-project
+    /// issuer without `--project`.
+    pub fn issuers(&self) -> Vec<String> {
+        match (self.provider, &self.project) {
+            (Provider::Apple, _) => vec!["https://appleid.apple.com".to_string()],
+            (Provider::Google, _) => vec![
+                "https://accounts.google.com".to_string(),
+                "accounts.google.com".to_string(),
+            ],
+            (Provider::Firebase, Some(project)) => {
+                vec![format!("https://securetoken.google.com/{}", project)]
+            }
+            (Provider::Supabase, Some(project)) => {
+                vec![format!("https://{}.supabase.co/auth/v1", project)]
+            }
+            (Provider::Firebase, None) | (Provider::Supabase, None) => Vec::new(),
+        }
+    }
+
+    /// The `aud` tokens for this app must have.
+    fn audience(&self) -> Option<&str> {
+        match self.provider {
+            Provider::Apple | Provider::Google => self.client_id.as_deref(),
+            Provider::Firebase => self.project.as_deref(),
+            Provider::Supabase => Some("authenticated"),
+        }
+    }
+
+    /// Where the provider publishes its signing keys. A Supabase project's
+    /// JWKS only has keys once it uses asymmetric signing; the HS256 secret
+    /// is never published.
+    pub fn jwks_url(&self) -> Option<String> {
+        match (self.provider, &self.project) {
+            (Provider::Apple, _) => Some("https://appleid.apple.com/auth/keys".to_string()),
+            (Provider::Google, _) => {
+                Some("https://www.googleapis.com/oauth2/v3/certs".to_string())
+            }
+            (Provider::Firebase, _) => Some(
+                "https://www.googleapis.com/service_accounts/v1/jwk/securetoken@system.gserviceaccount.com"
+                    .to_string(),
+            ),
+            (Provider::Supabase, _) => None,
+        }
+    }
+
+    /// The algorithms the provider's tokens are signed with.
+    pub fn algorithms(&self) -> &'static [&'static str] {
+        match self.provider {
+            Provider::Apple | Provider::Google | Provider::Firebase => &["RS256"],
+            Provider::Supabase => &["HS256", "RS256", "ES256"],
+        }
+    }
+
+    /// Why `secret` can't be the key this provider's tokens are signed
+    /// with, when it is recognizably something else.
+    pub fn secret_problem(&self, secret: &[u8]) -> Option<String> {
+        if self.provider != Provider::Supabase {
+            return None;
+        }
+        let key = parse(std::str::from_utf8(secret).ok()?.trim()).ok()?;
+        if key.payload.get("iss").and_then(Value::as_str) != Some("supabase") {
+            return None;
+        }
+        let role = key.payload.get("role").and_then(Value::as_str)?;
+        let why = match role {
+            "anon" => "the anon key is a token signed with the JWT secret, not the secret itself",
+            "service_role" => "the service_role key is a token signed with the JWT secret, not the secret itself; it is also an admin credential, so keep it out of shell history",
+            _ => return None,
+        };
+        Some(format!(
+            "the secret is the project's {} API key: {}. Use the JWT secret from the project's API settings",
+            role, why
+        ))
+    }
+
+    /// What trips people up with this provider's tokens.
+    pub fn quirks(&self) -> &'static [&'static str] {
+        match self.provider {
+            Provider::Apple => &[
+                "Apple publishes only a few keys and rotates them without notice: a kid missing from a cached JWKS means refetch, not a forged token",
+                "aud is the bundle ID for sign-in from an app, but the Services ID for sign-in on the web",
+                "email_verified and is_private_email may be the strings \"true\" and \"false\" rather than booleans",
+            ],
+            Provider::Google => &[
+                "iss is https://accounts.google.com or accounts.google.com; accept both",
+                "the JWKS rotates about weekly; cache it for its Cache-Control max-age and no longer",
+                "a token an Android or iOS client gets for its backend has the backend's client ID as aud and the app's as azp",
+            ],
+            Provider::Firebase => &[
+                "the keys are the securetoken service account's, not Google's OAuth certs: a Google sign-in JWKS finds no matching kid",
+                "session cookies from createSessionCookie are issued by https://session.firebase.google.com/{project} and are not ID tokens",
+                "ID tokens last an hour; the SDK refreshes them, so a backend should never extend one",
+            ],
+            Provider::Supabase => &[
+                "the anon and service_role API keys are tokens signed with the JWT secret; neither is the secret",
+                "aud is authenticated for signed-in users; anon requests carry role anon instead",
+                "a project that moved to asymmetric signing keys publishes them at https://{project}.supabase.co/auth/v1/.well-known/jwks.json",
+            ],
+        }
+    }
+
+    /// Checks `token` against the provider's rules. `payload` is the payload
+    /// with timestamps normalized to seconds.
+    pub fn check(&self, token: &JWToken, payload: &Value) -> Vec<Requirement> {
+        let source = match self.provider {
+            Provider::Apple => "Sign in with Apple",
+            Provider::Google => "Google Identity",
+            Provider::Firebase => "Firebase Auth",
+            Provider::Supabase => "Supabase Auth",
+        };
+        let mut requirements = Vec::new();
+        let issuers = self.issuers();
+        let iss = payload.get("iss").and_then(Value::as_str);
+        requirements.push(requirement(
+            OIDC,
+            "3.1.3.7",
+            &match issuers.as_slice() {
+                [] => "issued by the project".to_string(),
+                issuers => format!("issued by {}", issuers.join(" or ")),
+            },
+            match iss {
+                Some(iss) if issuers.iter().any(|known| known == iss) => {
+                    Ok(format!("iss is {}", iss))
+                }
+                Some(iss) if issuers.is_empty() => Err(format!(
+                    "iss is {}, and there is no --project to compare it with",
+                    iss
+                )),
+                Some(iss)
+                    if self.provider == Provider::Firebase
+                        && iss.starts_with("https://session.firebase.google.com/") =>
+                {
+                    Err(format!("iss is {}: a session cookie, not an ID token", iss))
+                }
+                Some(iss) => Err(format!("iss is {}", iss)),
+                None => Err("no iss".to_string()),
+            },
+        ));
+        let algorithms = self.algorithms();
+        requirements.push(requirement(
+            source,
+            "ID token",
+            &format!("signed with {}", algorithms.join(" or ")),
+            match token.header.get("alg").and_then(Value::as_str) {
+                Some(alg) if algorithms.contains(&alg) => Ok(format!("alg is {}", alg)),
+                Some(alg) => Err(format!("alg is {}", alg)),
+                None => Err("no alg".to_string()),
+            },
+        ));
+        let missing: Vec<&str> = ["iss", "sub", "aud", "exp", "iat"]
+            .iter()
+            .copied()
+            .filter(|claim| payload.get(claim).is_none())
+            .collect();
+        requirements.push(requirement(
+            OIDC,
+            "2",
+            "ID token claims",
+            if missing.is_empty() {
+                Ok("has iss, sub, aud, exp, iat".to_string())
+            } else {
+                Err(format!("missing {}", missing.join(", ")))
+            },
+        ));
+        let audiences = audiences(payload);
+        requirements.push(requirement(
+            OIDC,
+            "3.1.3.7",
+            match self.provider {
+                Provider::Apple | Provider::Google => "aud is this app's client ID",
+                Provider::Firebase => "aud is the project ID",
+                Provider::Supabase => "aud is authenticated",
+            },
+            match self.audience() {
+                Some(expected) if audiences.contains(&expected) => {
+                    Ok(format!("aud has {}", expected))
+                }
+                Some(expected) if audiences.is_empty() => {
+                    Err(format!("no aud, expected {}", expected))
+                }
+                Some(expected) => Err(format!(
+                    "aud is {}, not {}",
+                    audiences.join(", "),
+                    expected
+                )),
+                None if self.provider == Provider::Firebase => Err(
+                    "no --project to compare aud with: a token from any other project would pass"
+                        .to_string(),
+                ),
+                None => Err(
+                    "no --client-id to compare aud with: a token issued to any other app would pass"
+                        .to_string(),
+                ),
+            },
+        ));
+        match self.provider {
+            Provider::Apple => requirements.push(requirement(
+                source,
+                "ID token",
+                "email_verified is a boolean or \"true\"/\"false\"",
+                match payload.get("email_verified") {
+                    Some(Value::Bool(verified)) => Ok(format!("email_verified is {}", verified)),
+                    Some(Value::String(verified)) if verified == "true" || verified == "false" => {
+                        Ok(format!(
+                            "email_verified is the string \"{}\"; compare it as a string",
+                            verified
+                        ))
+                    }
+                    Some(other) => Err(format!("email_verified is {}", other)),
+                    None => Ok("no email_verified (no email shared)".to_string()),
+                },
+            )),
+            Provider::Google => requirements.push(requirement(
+                OIDC,
+                "3.1.3.7",
+                "azp present when there are several audiences",
+                match payload.get("azp").and_then(Value::as_str) {
+                    Some(azp) if audiences.len() == 1 && audiences.contains(&azp) => {
+                        Ok(format!("azp is the audience {}", azp))
+                    }
+                    Some(azp) => Ok(format!(
+                        "azp is {}: presented by that client for {}",
+                        azp,
+                        audiences.join(", ")
+                    )),
+                    None if audiences.len() > 1 => Err(format!(
+                        "{} audiences and no azp to say which client the token was issued to",
+                        audiences.len()
+                    )),
+                    None => Ok("one audience, so no azp needed".to_string()),
+                },
+            )),
+            Provider::Firebase => requirements.push(requirement(
+                source,
+                "ID token",
+                "sub is the user's UID",
+                match payload.get("sub") {
+                    Some(Value::String(uid)) if !uid.is_empty() && uid.len() <= 128 => {
+                        Ok(format!("sub is {}", uid))
+                    }
+                    Some(Value::String(uid)) if uid.is_empty() => Err("sub is empty".to_string()),
+                    Some(Value::String(uid)) => {
+                        Err(format!("sub is {} characters; UIDs are at most 128", uid.len()))
+                    }
+                    Some(other) => Err(format!("sub is {}", other)),
+                    None => Err("no sub".to_string()),
+                },
+            )),
+            Provider::Supabase => requirements.push(requirement(
+                source,
+                "JWT",
+                "role is a database role",
+                match payload.get("role").and_then(Value::as_str) {
+                    Some("service_role") => Err(
+                        "role is service_role: the project's admin API key, not a user's token"
+                            .to_string(),
+                    ),
+                    Some("anon") => Err(
+                        "role is anon: this is the project's public API key or a signed-out request, not a user's token"
+                            .to_string(),
+                    ),
+                    Some(role) => Ok(format!("role is {}", role)),
+                    None => Err("no role".to_string()),
+                },
+            )),
+        }
+        requirements
+    }
+}
+
+pub fn render_text(preset: &Preset, requirements: &[Requirement]) -> String {
+    let width = requirements
+        .iter()
+        .map(|r| r.source.len())
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    for requirement in requirements {
+        out.push_str(&format!(
+            "{} {:w$}  {}: {}\n",
+            if requirement.passed { "pass" } else { "FAIL" },
+            requirement.source,
+            requirement.description,
+            requirement.detail,
+            w = width
+        ));
+    }
+    for quirk in preset.quirks() {
+        out.push_str(&format!("note: {}\n", quirk));
+    }
+    let failed = requirements.iter().filter(|r| !r.passed).count();
+    out.push_str(&format!(
+        "preset {}: {} of {} requirement(s) met",
+        preset,
+        requirements.len() - failed,
+        requirements.len()
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn token(alg: &str, payload: Value) -> JWToken {
+        JWToken {
+            header: json!({"alg": alg, "kid": "k1"}),
+            payload,
+            signature: Vec::new(),
+        }
+    }
+
+    fn failures(requirements: &[Requirement]) -> Vec<&str> {
+        requirements
+            .iter()
+            .filter(|r| !r.passed)
+            .map(|r| r.detail.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn apple_test() {
+        let preset = Preset::new("sign-in-with-apple", Some("com.example.app"), None).unwrap();
+        let apple = token(
+            "RS256",
+            json!({"iss": "https://appleid.apple.com", "sub": "001234.abcd", "aud": "com.example.app",
+                   "exp": 1_700_000_600, "iat": 1_700_000_000, "email_verified": "true"}),
+        );
+        let requirements = preset.check(&apple, &apple.payload);
+        assert!(failures(&requirements).is_empty(), "{:?}", requirements);
+        assert!(requirements
+            .iter()
+            .any(|r| r.detail == "email_verified is the string \"true\"; compare it as a string"));
+        let text = render_text(&preset, &requirements);
+        assert!(text.ends_with("preset sign-in-with-apple: 5 of 5 requirement(s) met"));
+        assert!(text.contains("note: aud is the bundle ID"));
+
+        // the web flow's Services ID is another audience
+        let web = Preset::new("sign-in-with-apple", Some("com.example.web"), None).unwrap();
+        assert_eq!(
+            failures(&web.check(&apple, &apple.payload)),
+            ["aud is com.example.app, not com.example.web"]
+        );
+        let forged = token(
+            "HS256",
+            json!({"iss": "https://appleid.apple.com.evil", "sub": "x", "aud": "com.example.app",
+                   "exp": 1, "iat": 0, "email_verified": 1}),
+        );
+        assert_eq!(
+            failures(&preset.check(&forged, &forged.payload)),
+            [
+                "iss is https://appleid.apple.com.evil",
+                "alg is HS256",
+                "email_verified is 1"
+            ]
+        );
+        // an account that shared no email has none to verify
+        let mut private = apple.payload.clone();
+        private.as_object_mut().unwrap().remove("email_verified");
+        assert!(preset
+            .check(&apple, &private)
+            .iter()
+            .any(|r| r.passed && r.detail == "no email_verified (no email shared)"));
+    }
+
+    #[test]
+    fn google_test() {
+        let preset = Preset::new(
+            "google-id-token",
+            Some("backend.apps.googleusercontent.com"),
+            None,
+        )
+        .unwrap();
+        for iss in ["https://accounts.google.com", "accounts.google.com"] {
+            let google = token(
+                "RS256",
+                json!({"iss": iss, "sub": "1100", "azp": "android.apps.googleusercontent.com",
+                       "aud": "backend.apps.googleusercontent.com", "exp": 2, "iat": 1}),
+            );
+            let requirements = preset.check(&google, &google.payload);
+            assert!(failures(&requirements).is_empty(), "{:?}", requirements);
+        }
+        let several = token(
+            "RS256",
+            json!({"iss": "https://accounts.google.com", "sub": "1100", "exp": 2, "iat": 1,
+                   "aud": ["backend.apps.googleusercontent.com", "other.apps.googleusercontent.com"]}),
+        );
+        assert_eq!(
+            failures(&preset.check(&several, &several.payload)),
+            ["2 audiences and no azp to say which client the token was issued to"]
+        );
+        let unchecked = Preset::new("google-id-token", None, None).unwrap();
+        let bare = token("RS256", json!({"iss": "https://accounts.google.com"}));
+        assert_eq!(
+            failures(&unchecked.check(&bare, &bare.payload)),
+            [
+                "missing sub, aud, exp, iat",
+                "no --client-id to compare aud with: a token issued to any other app would pass"
+            ]
+        );
+        assert_eq!(
+            unchecked.jwks_url().as_deref(),
+            Some("https://www.googleapis.com/oauth2/v3/certs")
+        );
+        assert!(matches!(
+            Preset::new("okta", None, None),
+            Err(JWTError::InvalidArgumentError(e)) if e == "`okta` is not a preset; expected one of sign-in-with-apple, google-id-token, firebase, supabase"
+        ));
+    }
+
+    #[test]
+    fn firebase_test() {
+        let preset = Preset::new("firebase", None, Some("demo-app")).unwrap();
+        let firebase = token(
+            "RS256",
+            json!({"iss": "https://securetoken.google.com/demo-app", "aud": "demo-app",
+                   "sub": "kXz7W2", "auth_time": 1, "iat": 1, "exp": 3_601}),
+        );
+        let requirements = preset.check(&firebase, &firebase.payload);
+        assert!(failures(&requirements).is_empty(), "{:?}", requirements);
+        let cookie = token(
+            "RS256",
+            json!({"iss": "https://session.firebase.google.com/demo-app", "aud": "other-app",
+                   "sub": "", "iat": 1, "exp": 2}),
+        );
+        assert_eq!(
+            failures(&preset.check(&cookie, &cookie.payload)),
+            [
+                "iss is https://session.firebase.google.com/demo-app: a session cookie, not an ID token",
+                "aud is other-app, not demo-app",
+                "sub is empty"
+            ]
+        );
+        let unscoped = Preset::new("firebase", None, None).unwrap();
+        assert_eq!(
+            failures(&unscoped.check(&firebase, &firebase.payload)),
+            [
+                "iss is https://securetoken.google.com/demo-app, and there is no --project to compare it with",
+                "no --project to compare aud with: a token from any other project would pass"
+            ]
+        );
+        assert!(unscoped
+            .jwks_url()
+            .is_some_and(|url| url.ends_with("securetoken@system.gserviceaccount.com")));
+        for (sub, failure) in [
+            (
+                json!("u".repeat(129)),
+                "sub is 129 characters; UIDs are at most 128",
+            ),
+            (json!(42), "sub is 42"),
+        ] {
+            let mut payload = firebase.payload.clone();
+            payload["sub"] = sub;
+            assert_eq!(failures(&preset.check(&firebase, &payload)), [failure]);
+        }
+    }
+
+    #[test]
+    fn supabase_test() {
+        let preset = Preset::new("supabase", None, Some("abcdefghij")).unwrap();
+        let user = token(
+            "HS256",
+            json!({"iss": "https://abcdefghij.supabase.co/auth/v1", "aud": "authenticated",
+                   "sub": "9f1c", "role": "authenticated", "iat": 1, "exp": 3_601}),
+        );
+        let requirements = preset.check(&user, &user.payload);
+        assert!(failures(&requirements).is_empty(), "{:?}", requirements);
+        assert_eq!(preset.jwks_url(), None);
+        let anon = token(
+            "HS256",
+            json!({"iss": "supabase", "ref": "abcdefghij", "role": "anon", "iat": 1, "exp": 2}),
+        );
+        assert_eq!(
+            failures(&preset.check(&anon, &anon.payload)),
+            [
+                "iss is supabase",
+                "missing sub, aud",
+                "no aud, expected authenticated",
+                "role is anon: this is the project's public API key or a signed-out request, not a user's token"
+            ]
+        );
+
+        // an API key is a token, so it can't be the secret
+        let anon_key = crate::sign::sign(
+            &json!({"alg": "HS256", "typ": "JWT"}),
+            &anon.payload,
+            &crate::keys::SigningKey::Hmac(b"jwt-secret".to_vec()),
+        )
+        .unwrap();
+        assert!(
+            preset.secret_problem(anon_key.as_bytes()).is_some_and(
+                |problem| problem.starts_with("the secret is the project's anon API key")
+            )
+        );
+        let service_key = crate::sign::sign(
+            &json!({"alg": "HS256"}),
+            &json!({"iss": "supabase", "role": "service_role"}),
+            &crate::keys::SigningKey::Hmac(b"jwt-secret".to_vec()),
+        )
+        .unwrap();
+        assert!(preset
+            .secret_problem(format!("{}\n", service_key).as_bytes())
+            .is_some_and(|problem| problem.contains("admin credential")));
+        assert_eq!(
+            preset.secret_problem(b"super-secret-jwt-token-with-at-least-32-characters"),
+            None
+        );
+        let google = Preset::new("google-id-token", None, None).unwrap();
+        assert_eq!(google.secret_problem(anon_key.as_bytes()), None);
+
+        for (role, failure) in [
+            (
+                Some("service_role"),
+                "role is service_role: the project's admin API key, not a user's token",
+            ),
+            (None, "no role"),
+        ] {
+            let mut payload = user.payload.clone();
+            match role {
+                Some(role) => payload["role"] = json!(role),
+                None => {
+                    payload.as_object_mut().unwrap().remove("role");
+                }
+            }
+            assert_eq!(failures(&preset.check(&user, &payload)), [failure]);
+        }
+    }
+}
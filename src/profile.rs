@@ -0,0 +1,1043 @@
+//! `--profile`: security-profile presets that check a token against a
+//! standard's requirements and report each one as met or not, the way
+//! `conformance` reports examples.
+//!
+//! `fapi2` covers the FAPI 2.0 Security Profile and FAPI 2.0 Message
+//! Signing. What applies depends on the kind of token, which comes from
+//! `typ` and the claims present unless `--token-kind` says otherwise:
+//!
+//! - request objects (RFC 9101) need `iss`, `aud`, `client_id`, `nbf` and an
+//!   `exp` at most 60 minutes after `nbf`, `response_type` `code`, a
+//!   `redirect_uri`, and PKCE with `S256`;
+//! - access tokens (RFC 9068) need the JWT profile's claims and must be
+//!   sender-constrained: a `cnf` holding an mTLS `x5t#S256` or a DPoP `jkt`;
+//! - client assertions (`private_key_jwt`, RFC 7523) need `iss` equal to
+//!   `sub`, a `jti`, and the issuer as their only audience;
+//! - DPoP proofs (RFC 9449) need a public `jwk` header and `htm`, `htu`,
+//!   `iat` and `jti`;
+//! - ID tokens need `iss`, `sub`, `aud`, `exp` and `iat`.
+//!
+//! Every kind must be signed with PS256, ES256 or EdDSA and carry an `exp`
+//! (DPoP proofs excepted, whose freshness is their `iat`).
+//!
+//! `rfc9068` is the JWT profile for OAuth 2.0 access tokens, which every
+//! token it checks is taken to be: `typ` `at+jwt`, a real signature, the
+//! claims section 2.2 requires with the types it gives them, `exp` after
+//! `iat`, and `scope` and the authorization claims in their defined shapes.
+//!
+//! `license` is for long-lived signed tokens such as software licenses and
+//! API keys issued as JWTs. A long or missing `exp` is expected, so what
+//! matters is that the token can be revoked: an asymmetric signature (a
+//! shared secret would let every licensee mint licenses), a `jti`, and that
+//! `jti` absent from the revocation list ([`revocation_requirement`]).
+
+use crate::junit;
+use crate::{humanize_seconds, numeric_claim, JWTError, JWToken};
+use serde_json::{json, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/// FAPI 2.0 Message Signing's limit on a request object's lifetime.
+pub const MAX_REQUEST_OBJECT_LIFETIME: i64 = 3_600;
+
+/// The algorithms the FAPI 2.0 Security Profile allows.
+pub const FAPI2_ALGORITHMS: [&str; 3] = ["PS256", "ES256", "EdDSA"];
+
+const SECURITY_PROFILE: &str = "FAPI 2.0 Security Profile";
+const MESSAGE_SIGNING: &str = "FAPI 2.0 Message Signing";
+
+/// The names `--profile` takes.
+pub const PRESETS: [&str; 3] = ["fapi2", "rfc9068", "license"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    Fapi2,
+    Rfc9068,
+    License,
+}
+
+impl FromStr for Profile {
+    type Err = JWTError;
+
+    fn from_str(name: &str) -> Result<Profile, JWTError> {
+        match name {
+            "fapi2" => Ok(Profile::Fapi2),
+            "rfc9068" => Ok(Profile::Rfc9068),
+            "license" => Ok(Profile::License),
+            other => Err(JWTError::InvalidArgumentError(
+                format!(
+                    "`{}` is not a profile; expected {}",
+                    other,
+                    PRESETS.join(" or ")
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Profile::Fapi2 => write!(f, "fapi2"),
+            Profile::Rfc9068 => write!(f, "rfc9068"),
+            Profile::License => write!(f, "license"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    RequestObject,
+    AccessToken,
+    ClientAssertion,
+    DpopProof,
+    IdToken,
+    License,
+}
+
+pub const KINDS: [(&str, Kind); 6] = [
+    ("request-object", Kind::RequestObject),
+    ("access-token", Kind::AccessToken),
+    ("client-assertion", Kind::ClientAssertion),
+    ("dpop-proof", Kind::DpopProof),
+    ("id-token", Kind::IdToken),
+    ("license", Kind::License),
+];
+
+impl FromStr for Kind {
+    type Err = JWTError;
+
+    fn from_str(name: &str) -> Result<Kind, JWTError> {
+        KINDS
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, kind)| *kind)
+            .ok_or_else(|| {
+                JWTError::InvalidArgumentError(format!("`{}` is not a token kind", name).into())
+            })
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = KINDS
+            .iter()
+            .find(|(_, kind)| kind == self)
+            .map_or("token", |(name, _)| *name);
+        write!(f, "{}", name)
+    }
+}
+
+impl Kind {
+    /// Guesses the kind of `token` from its `typ` and claims.
+    pub fn detect(token: &JWToken) -> Kind {
+        let typ = token
+            .header
+            .get("typ")
+            .and_then(Value::as_str)
+            .map(|typ| typ.to_ascii_lowercase());
+        let has = |claim: &str| token.payload.get(claim).is_some();
+        let typ = typ
+            .as_deref()
+            .map(|typ| typ.trim_start_matches("application/"));
+        if typ == Some("dpop+jwt") {
+            return Kind::DpopProof;
+        }
+        if typ == Some("oauth-authz-req+jwt")
+            || ["response_type", "redirect_uri", "code_challenge"]
+                .iter()
+                .any(|claim| has(claim))
+        {
+            return Kind::RequestObject;
+        }
+        if typ == Some("at+jwt") || has("cnf") {
+            return Kind::AccessToken;
+        }
+        let iss = token.payload.get("iss");
+        if iss.is_some() && iss == token.payload.get("sub") && has("jti") && !has("nonce") {
+            return Kind::ClientAssertion;
+        }
+        Kind::IdToken
+    }
+}
+
+/// One requirement of a profile and whether the token meets it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Requirement {
+    /// The specification and section it comes from.
+    pub source: String,
+    pub description: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl Requirement {
+    /// The requirement as a `--junit` case of `kind`, failed with its detail
+    /// when the token doesn't meet it.
+    pub fn case(&self, kind: &'static str) -> junit::Case {
+        let name = format!("{}: {}", self.source, self.description);
+        junit::Case::new(
+            kind,
+            &name,
+            Some(self.detail.clone()).filter(|_| !self.passed),
+        )
+    }
+}
+
+pub(crate) fn requirement(
+    source: &str,
+    section: &str,
+    description: &str,
+    result: Result<String, String>,
+) -> Requirement {
+    let (passed, detail) = match result {
+        Ok(detail) => (true, detail),
+        Err(detail) => (false, detail),
+    };
+    Requirement {
+        source: format!("{} {}", source, section),
+        description: description.to_string(),
+        passed,
+        detail,
+    }
+}
+
+/// A claim value for a detail line, with strings unquoted.
+fn shown(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn required_claims(payload: &Value, claims: &[&str]) -> Result<String, String> {
+    let missing: Vec<&str> = claims
+        .iter()
+        .copied()
+        .filter(|claim| payload.get(claim).is_none())
+        .collect();
+    if missing.is_empty() {
+        Ok(format!("has {}", claims.join(", ")))
+    } else {
+        Err(format!("missing {}", missing.join(", ")))
+    }
+}
+
+fn claim_equals(payload: &Value, claim: &str, expected: &str) -> Result<String, String> {
+    match payload.get(claim) {
+        Some(Value::String(value)) if value == expected => Ok(format!("{} is {}", claim, value)),
+        Some(value) => Err(format!(
+            "{} is {}, expected {}",
+            claim,
+            shown(value),
+            expected
+        )),
+        None => Err(format!("no {}", claim)),
+    }
+}
+
+impl Profile {
+    /// Checks `token` as `kind`, or the kind [`Kind::detect`] finds; a
+    /// profile for one kind of token checks every token as that kind.
+    /// `payload` is the payload with timestamps normalized to seconds.
+    pub fn check(
+        &self,
+        token: &JWToken,
+        payload: &Value,
+        kind: Option<Kind>,
+    ) -> (Kind, Vec<Requirement>) {
+        match self {
+            Profile::Fapi2 => {
+                let kind = kind.unwrap_or_else(|| Kind::detect(token));
+                (kind, fapi2(token, payload, kind))
+            }
+            Profile::Rfc9068 => (Kind::AccessToken, rfc9068(token, payload)),
+            Profile::License => (Kind::License, license(token, payload)),
+        }
+    }
+}
+
+fn fapi2(token: &JWToken, payload: &Value, kind: Kind) -> Vec<Requirement> {
+    let mut requirements = Vec::new();
+    let alg = token.header.get("alg").and_then(Value::as_str);
+    requirements.push(requirement(
+        SECURITY_PROFILE,
+        "5.4",
+        "signed with PS256, ES256 or EdDSA",
+        match alg {
+            Some(alg) if FAPI2_ALGORITHMS.contains(&alg) => Ok(format!("alg is {}", alg)),
+            Some(alg) => Err(format!("alg is {}", alg)),
+            None => Err("no alg".to_string()),
+        },
+    ));
+    if kind != Kind::DpopProof {
+        requirements.push(requirement(
+            "RFC 7519",
+            "4.1.4",
+            "has an expiry",
+            match numeric_claim(payload, "exp") {
+                Some(_) => Ok("has exp".to_string()),
+                None => Err("no exp: the token never expires".to_string()),
+            },
+        ));
+    }
+    match kind {
+        Kind::RequestObject => {
+            requirements.push(requirement(
+                MESSAGE_SIGNING,
+                "5.3",
+                "request object claims",
+                required_claims(payload, &["iss", "aud", "client_id", "exp", "nbf"]),
+            ));
+            requirements.push(requirement(
+                MESSAGE_SIGNING,
+                "5.3",
+                "exp at most 60 minutes after nbf",
+                match (numeric_claim(payload, "nbf"), numeric_claim(payload, "exp")) {
+                    (Some(nbf), Some(exp)) => match exp.checked_sub(nbf) {
+                        Some(lifetime) if lifetime <= MAX_REQUEST_OBJECT_LIFETIME => {
+                            Ok(format!("valid for {}", humanize_seconds(lifetime)))
+                        }
+                        Some(lifetime) => Err(format!("valid for {}", humanize_seconds(lifetime))),
+                        None => Err("exp - nbf overflows 64 bits".to_string()),
+                    },
+                    _ => Err("needs both nbf and exp".to_string()),
+                },
+            ));
+            requirements.push(requirement(
+                "RFC 9101",
+                "4",
+                "iss is the client_id",
+                match (payload.get("iss"), payload.get("client_id")) {
+                    (Some(iss), Some(client_id)) if iss == client_id => {
+                        Ok(format!("both are {}", shown(iss)))
+                    }
+                    (Some(iss), Some(client_id)) => Err(format!(
+                        "iss is {}, client_id is {}",
+                        shown(iss),
+                        shown(client_id)
+                    )),
+                    _ => Err("needs both iss and client_id".to_string()),
+                },
+            ));
+            requirements.push(requirement(
+                SECURITY_PROFILE,
+                "5.3.2.2",
+                "authorization code flow only",
+                claim_equals(payload, "response_type", "code"),
+            ));
+            requirements.push(requirement(
+                SECURITY_PROFILE,
+                "5.3.2.2",
+                "PKCE with S256",
+                claim_equals(payload, "code_challenge_method", "S256").and_then(|detail| {
+                    required_claims(payload, &["code_challenge"]).map(|_| detail)
+                }),
+            ));
+            requirements.push(requirement(
+                SECURITY_PROFILE,
+                "5.3.2.2",
+                "redirect_uri present",
+                required_claims(payload, &["redirect_uri"]),
+            ));
+        }
+        Kind::AccessToken => {
+            requirements.push(requirement(
+                "RFC 9068",
+                "2.2",
+                "JWT access token claims",
+                required_claims(
+                    payload,
+                    &["iss", "exp", "aud", "sub", "client_id", "iat", "jti"],
+                ),
+            ));
+            let cnf = payload.get("cnf");
+            let bound = |member: &str| cnf.and_then(|cnf| cnf.get(member)).is_some();
+            requirements.push(requirement(
+                SECURITY_PROFILE,
+                "5.3.2.1",
+                "sender-constrained with mTLS or DPoP",
+                if bound("x5t#S256") {
+                    Ok("bound to a client certificate (cnf.x5t#S256)".to_string())
+                } else if bound("jkt") {
+                    Ok("bound to a DPoP key (cnf.jkt)".to_string())
+                } else {
+                    Err(
+                        "no cnf with x5t#S256 or jkt: anyone holding the token can use it"
+                            .to_string(),
+                    )
+                },
+            ));
+        }
+        Kind::ClientAssertion => {
+            requirements.push(requirement(
+                "RFC 7523",
+                "3",
+                "client assertion claims",
+                required_claims(payload, &["iss", "sub", "aud", "jti", "exp"]),
+            ));
+            requirements.push(requirement(
+                "RFC 7523",
+                "3",
+                "iss and sub are the client_id",
+                match (payload.get("iss"), payload.get("sub")) {
+                    (Some(iss), Some(sub)) if iss == sub => Ok(format!("both are {}", shown(iss))),
+                    (Some(iss), Some(sub)) => {
+                        Err(format!("iss is {}, sub is {}", shown(iss), shown(sub)))
+                    }
+                    _ => Err("needs both iss and sub".to_string()),
+                },
+            ));
+            requirements.push(requirement(
+                SECURITY_PROFILE,
+                "5.3.2.1",
+                "the issuer is the only audience",
+                match payload.get("aud") {
+                    Some(Value::String(aud)) => Ok(format!("aud is {}", aud)),
+                    Some(aud) => Err(format!(
+                        "aud is {}, expected the issuer as a single string",
+                        aud
+                    )),
+                    None => Err("no aud".to_string()),
+                },
+            ));
+        }
+        Kind::DpopProof => {
+            requirements.push(requirement(
+                "RFC 9449",
+                "4.2",
+                "public jwk in the header",
+                match token.header.get("jwk") {
+                    Some(jwk) if jwk.get("d").is_some() => {
+                        Err("the jwk includes its private key (d)".to_string())
+                    }
+                    Some(jwk) => Ok(format!(
+                        "{} key",
+                        jwk.get("kty").and_then(Value::as_str).unwrap_or("unknown")
+                    )),
+                    None => Err("no jwk".to_string()),
+                },
+            ));
+            requirements.push(requirement(
+                "RFC 9449",
+                "4.2",
+                "DPoP proof claims",
+                required_claims(payload, &["jti", "htm", "htu", "iat"]),
+            ));
+        }
+        Kind::IdToken => {
+            requirements.push(requirement(
+                "OpenID Connect Core",
+                "2",
+                "ID token claims",
+                required_claims(payload, &["iss", "sub", "aud", "exp", "iat"]),
+            ));
+        }
+        Kind::License => requirements.push(revocable(payload)),
+    }
+    requirements
+}
+
+/// A `jti` to revoke the token by.
+fn revocable(payload: &Value) -> Requirement {
+    requirement(
+        "RFC 7519",
+        "4.1.7",
+        "a jti to revoke it by",
+        match payload.get("jti") {
+            Some(Value::String(jti)) if !jti.is_empty() => Ok(format!("jti is {}", jti)),
+            Some(jti) => Err(format!("jti is {}, expected a non-empty string", jti)),
+            None => Err("no jti: a leaked token can only be revoked with its key".to_string()),
+        },
+    )
+}
+
+fn license(token: &JWToken, payload: &Value) -> Vec<Requirement> {
+    let mut requirements = Vec::new();
+    let alg = token.header.get("alg").and_then(Value::as_str);
+    requirements.push(requirement(
+        "RFC 8725",
+        "3.2",
+        "signed with an asymmetric algorithm",
+        match alg {
+            Some(alg) if alg.eq_ignore_ascii_case("none") => Err("alg is none".to_string()),
+            Some(alg) if alg.starts_with("HS") => Err(format!(
+                "alg is {}: whoever can check a license can also mint one",
+                alg
+            )),
+            Some(alg) => Ok(format!("alg is {}", alg)),
+            None => Err("no alg".to_string()),
+        },
+    ));
+    requirements.push(revocable(payload));
+    requirements.push(requirement(
+        "RFC 7519",
+        "4.1",
+        "issuer, licensee and issue time",
+        required_claims(payload, &["iss", "sub", "iat"]),
+    ));
+    requirements.push(requirement(
+        "RFC 7519",
+        "4.1.4",
+        "exp, if any, after iat",
+        match (numeric_claim(payload, "iat"), numeric_claim(payload, "exp")) {
+            (_, None) => Ok("no exp: perpetual, so only revocation ends it".to_string()),
+            (Some(iat), Some(exp)) if exp > iat => match exp.checked_sub(iat) {
+                Some(lifetime) => Ok(format!("valid for {}", humanize_seconds(lifetime))),
+                None => Ok("exp - iat overflows 64 bits".to_string()),
+            },
+            (Some(_), Some(_)) => Err("exp is not after iat".to_string()),
+            (None, Some(_)) => Ok("has exp".to_string()),
+        },
+    ));
+    requirements
+}
+
+/// The `jti`s of a revocation list: one per line, with blank lines and `#`
+/// comments ignored.
+pub fn revocation_list(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// The `license` profile's revocation check: that the token's `jti` is not
+/// on `revoked`. Without a list the check fails, since a license nobody
+/// can revoke outlives any leak.
+pub fn revocation_requirement(payload: &Value, revoked: Option<&[String]>) -> Requirement {
+    requirement(
+        "RFC 7519",
+        "4.1.7",
+        "jti not on the revocation list",
+        match (payload.get("jti").and_then(Value::as_str), revoked) {
+            (_, None) => Err("no revocation list; pass --revoked-jti".to_string()),
+            (None, Some(_)) => Err("no jti to look up".to_string()),
+            (Some(jti), Some(revoked)) if revoked.iter().any(|r| r == jti) => {
+                Err(format!("jti {} is revoked", jti))
+            }
+            (Some(jti), Some(revoked)) => Ok(format!(
+                "jti {} is not among {} revoked",
+                jti,
+                revoked.len()
+            )),
+        },
+    )
+}
+
+/// Claims whose JSON type isn't the one `expected` says, `(claim, type)`.
+fn mistyped(payload: &Value, expected: &[(&str, &str)]) -> Result<String, String> {
+    let wrong: Vec<String> = expected
+        .iter()
+        .filter(|(claim, kind)| {
+            payload.get(claim).is_some_and(|value| match *kind {
+                "a string" => !value.is_string(),
+                "a number" => !value.is_number(),
+                "a string or an array of strings" => {
+                    !(value.is_string()
+                        || value
+                            .as_array()
+                            .is_some_and(|items| items.iter().all(Value::is_string)))
+                }
+                _ => !value
+                    .as_array()
+                    .is_some_and(|items| items.iter().all(Value::is_string)),
+            })
+        })
+        .map(|(claim, kind)| format!("{} should be {}", claim, kind))
+        .collect();
+    if wrong.is_empty() {
+        Ok("as defined".to_string())
+    } else {
+        Err(wrong.join("; "))
+    }
+}
+
+fn rfc9068(token: &JWToken, payload: &Value) -> Vec<Requirement> {
+    let mut requirements = Vec::new();
+    let typ = token.header.get("typ").and_then(Value::as_str);
+    requirements.push(requirement(
+        "RFC 9068",
+        "2.1",
+        "typ is at+jwt",
+        match typ {
+            Some(typ)
+                if typ.eq_ignore_ascii_case("at+jwt")
+                    || typ.eq_ignore_ascii_case("application/at+jwt") =>
+            {
+                Ok(format!("typ is {}", typ))
+            }
+            Some(typ) => Err(format!(
+                "typ is {}: other JWTs could be passed off as access tokens",
+                typ
+            )),
+            None => Err("no typ".to_string()),
+        },
+    ));
+    let alg = token.header.get("alg").and_then(Value::as_str);
+    requirements.push(requirement(
+        "RFC 9068",
+        "2.1",
+        "signed, not alg none",
+        match alg {
+            Some(alg) if alg.eq_ignore_ascii_case("none") => Err("alg is none".to_string()),
+            Some(alg) => Ok(format!("alg is {}", alg)),
+            None => Err("no alg".to_string()),
+        },
+    ));
+    requirements.push(requirement(
+        "RFC 9068",
+        "2.2",
+        "required claims",
+        required_claims(
+            payload,
+            &["iss", "exp", "aud", "sub", "client_id", "iat", "jti"],
+        ),
+    ));
+    requirements.push(requirement(
+        "RFC 9068",
+        "2.2",
+        "claim types",
+        mistyped(
+            payload,
+            &[
+                ("iss", "a string"),
+                ("exp", "a number"),
+                ("aud", "a string or an array of strings"),
+                ("sub", "a string"),
+                ("client_id", "a string"),
+                ("iat", "a number"),
+                ("jti", "a string"),
+                ("auth_time", "a number"),
+                ("acr", "a string"),
+                ("amr", "an array of strings"),
+            ],
+        ),
+    ));
+    requirements.push(requirement(
+        "RFC 7519",
+        "4.1.4",
+        "exp after iat",
+        match (numeric_claim(payload, "iat"), numeric_claim(payload, "exp")) {
+            (Some(iat), Some(exp)) if exp > iat => match exp.checked_sub(iat) {
+                Some(lifetime) => Ok(format!("valid for {}", humanize_seconds(lifetime))),
+                None => Ok("exp - iat overflows 64 bits".to_string()),
+            },
+            (Some(_), Some(_)) => Err("exp is not after iat".to_string()),
+            _ => Err("needs both iat and exp".to_string()),
+        },
+    ));
+    requirements.push(requirement(
+        "RFC 9068",
+        "2.2.3",
+        "scope is a space-separated string",
+        match payload.get("scope") {
+            Some(Value::String(scope)) => Ok(format!("scope is {}", scope)),
+            Some(scope) => Err(format!("scope is {}", scope)),
+            None => Ok("no scope".to_string()),
+        },
+    ));
+    requirements.push(requirement(
+        "RFC 9068",
+        "2.2.3.1",
+        "groups, roles and entitlements are lists",
+        mistyped(
+            payload,
+            &[
+                ("groups", "an array of strings"),
+                ("roles", "an array of strings"),
+                ("entitlements", "an array of strings"),
+            ],
+        ),
+    ));
+    requirements
+}
+
+pub fn render_text(profile: Profile, kind: Kind, requirements: &[Requirement]) -> String {
+    let width = requirements
+        .iter()
+        .map(|r| r.source.len())
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    for requirement in requirements {
+        out.push_str(&format!(
+            "{} {:w$}  {}: {}\n",
+            if requirement.passed { "pass" } else { "FAIL" },
+            requirement.source,
+            requirement.description,
+            requirement.detail,
+            w = width
+        ));
+    }
+    let failed = requirements.iter().filter(|r| !r.passed).count();
+    out.push_str(&format!(
+        "profile {} ({}): {} of {} requirement(s) met",
+        profile,
+        kind,
+        requirements.len() - failed,
+        requirements.len()
+    ));
+    out
+}
+
+pub fn to_json(profile: Profile, kind: Kind, requirements: &[Requirement]) -> Value {
+    json!({
+        "profile": profile.to_string(),
+        "kind": kind.to_string(),
+        "passed": requirements.iter().filter(|r| r.passed).count(),
+        "failed": requirements.iter().filter(|r| !r.passed).count(),
+        "requirements": requirements.iter().map(|requirement| json!({
+            "source": requirement.source,
+            "description": requirement.description,
+            "passed": requirement.passed,
+            "detail": requirement.detail,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(header: Value, payload: Value) -> JWToken {
+        JWToken {
+            header,
+            payload,
+            signature: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn license_profile_test() {
+        let license = token(
+            json!({"alg": "ES256", "kid": "2021"}),
+            json!({"iss": "https://licenses.example", "sub": "acme-corp", "iat": 1_000,
+                   "jti": "lic-42", "seats": 25}),
+        );
+        let (kind, requirements) = Profile::License.check(&license, &license.payload, None);
+        assert_eq!(kind, Kind::License);
+        assert!(requirements.iter().all(|r| r.passed), "{:?}", requirements);
+        assert!(render_text(Profile::License, kind, &requirements)
+            .ends_with("profile license (license): 4 of 4 requirement(s) met"));
+        assert_eq!(
+            requirements[3].detail,
+            "no exp: perpetual, so only revocation ends it"
+        );
+
+        let mut payload = license.payload.clone();
+        payload.as_object_mut().unwrap().remove("jti");
+        payload["exp"] = json!(999);
+        let bad = token(json!({"alg": "HS256"}), payload);
+        let (_, requirements) = Profile::License.check(&bad, &bad.payload, None);
+        let failed: Vec<&str> = requirements
+            .iter()
+            .filter(|r| !r.passed)
+            .map(|r| r.description.as_str())
+            .collect();
+        assert_eq!(
+            failed,
+            [
+                "signed with an asymmetric algorithm",
+                "a jti to revoke it by",
+                "exp, if any, after iat"
+            ]
+        );
+        assert!(requirements[0].detail.contains("can also mint one"));
+
+        let revoked = revocation_list("# rotated out 2024-03\nlic-7\n\n  lic-42  \n");
+        assert_eq!(revoked, ["lic-7", "lic-42"]);
+        let check = |payload: &Value, revoked: Option<&[String]>| {
+            let requirement = revocation_requirement(payload, revoked);
+            (requirement.passed, requirement.detail)
+        };
+        assert_eq!(
+            check(&license.payload, Some(&revoked)),
+            (false, "jti lic-42 is revoked".to_string())
+        );
+        assert_eq!(
+            check(&license.payload, Some(&revoked[..1])),
+            (true, "jti lic-42 is not among 1 revoked".to_string())
+        );
+        assert_eq!(
+            check(&license.payload, None),
+            (false, "no revocation list; pass --revoked-jti".to_string())
+        );
+        assert_eq!(
+            check(&bad.payload, Some(&revoked)),
+            (false, "no jti to look up".to_string())
+        );
+        assert_eq!("license".parse::<Kind>().unwrap(), Kind::License);
+    }
+
+    #[test]
+    fn rfc9068_profile_test() {
+        let access = token(
+            json!({"alg": "RS256", "typ": "at+jwt"}),
+            json!({"iss": "https://as.example", "sub": "alice", "aud": ["https://rs.example"],
+                   "client_id": "client-1", "iat": 1_000, "exp": 1_300, "jti": "a1",
+                   "scope": "read write", "roles": ["admin"]}),
+        );
+        // whatever --token-kind says
+        let (kind, requirements) =
+            Profile::Rfc9068.check(&access, &access.payload, Some(Kind::IdToken));
+        assert_eq!(kind, Kind::AccessToken);
+        assert!(requirements.iter().all(|r| r.passed), "{:?}", requirements);
+        assert!(render_text(Profile::Rfc9068, kind, &requirements)
+            .ends_with("profile rfc9068 (access-token): 7 of 7 requirement(s) met"));
+
+        let mut payload = access.payload.clone();
+        payload.as_object_mut().unwrap().remove("client_id");
+        payload["scope"] = json!(["read", "write"]);
+        payload["sub"] = json!(42);
+        payload["roles"] = json!("admin");
+        let id_token = token(json!({"alg": "RS256", "typ": "JWT"}), payload);
+        let (_, requirements) = Profile::Rfc9068.check(&id_token, &id_token.payload, None);
+        let failed: Vec<(&str, &str)> = requirements
+            .iter()
+            .filter(|r| !r.passed)
+            .map(|r| (r.description.as_str(), r.detail.as_str()))
+            .collect();
+        assert_eq!(
+            failed,
+            vec![
+                (
+                    "typ is at+jwt",
+                    "typ is JWT: other JWTs could be passed off as access tokens"
+                ),
+                ("required claims", "missing client_id"),
+                ("claim types", "sub should be a string"),
+                (
+                    "scope is a space-separated string",
+                    "scope is [\"read\",\"write\"]"
+                ),
+                (
+                    "groups, roles and entitlements are lists",
+                    "roles should be an array of strings"
+                ),
+            ]
+        );
+
+        let mut payload = access.payload.clone();
+        payload["iat"] = json!(-9_223_372_036_854_775_807_i64);
+        payload["exp"] = json!(i64::MAX);
+        let (_, requirements) = Profile::Rfc9068.check(&access, &payload, None);
+        let lifetime = requirements
+            .iter()
+            .find(|r| r.description == "exp after iat")
+            .unwrap();
+        assert_eq!(lifetime.detail, "exp - iat overflows 64 bits");
+    }
+
+    #[test]
+    fn fapi2_profile_test() {
+        let request = token(
+            json!({"alg": "PS256", "typ": "oauth-authz-req+jwt"}),
+            json!({"iss": "client-1", "client_id": "client-1", "aud": "https://as.example",
+                   "nbf": 1_000, "exp": 2_800, "response_type": "code",
+                   "redirect_uri": "https://client.example/cb",
+                   "code_challenge": "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM",
+                   "code_challenge_method": "S256"}),
+        );
+        let (kind, requirements) = Profile::Fapi2.check(&request, &request.payload, None);
+        assert_eq!(kind, Kind::RequestObject);
+        assert!(requirements.iter().all(|r| r.passed), "{:?}", requirements);
+        assert!(render_text(Profile::Fapi2, kind, &requirements)
+            .ends_with("profile fapi2 (request-object): 8 of 8 requirement(s) met"));
+
+        // an hour and a half, with implicit flow and plain PKCE, signed RS256
+        let mut payload = request.payload.clone();
+        payload["exp"] = json!(6_400);
+        payload["response_type"] = json!("code id_token");
+        payload["code_challenge_method"] = json!("plain");
+        let bad = token(json!({"alg": "RS256"}), payload);
+        let (kind, requirements) = Profile::Fapi2.check(&bad, &bad.payload, None);
+        assert_eq!(kind, Kind::RequestObject);
+        let failed: Vec<&str> = requirements
+            .iter()
+            .filter(|r| !r.passed)
+            .map(|r| r.description.as_str())
+            .collect();
+        assert_eq!(
+            failed,
+            vec![
+                "signed with PS256, ES256 or EdDSA",
+                "exp at most 60 minutes after nbf",
+                "authorization code flow only",
+                "PKCE with S256"
+            ]
+        );
+        assert_eq!(requirements[3].detail, "valid for 90 minutes");
+        assert_eq!(to_json(Profile::Fapi2, kind, &requirements)["failed"], 4);
+
+        let mut payload = request.payload.clone();
+        payload["nbf"] = json!(-9_223_372_036_854_775_807_i64);
+        payload["exp"] = json!(i64::MAX);
+        let (_, requirements) = Profile::Fapi2.check(&request, &payload, None);
+        assert!(!requirements[3].passed);
+        assert_eq!(requirements[3].detail, "exp - nbf overflows 64 bits");
+
+        let access = token(
+            json!({"alg": "ES256", "typ": "at+jwt"}),
+            json!({"iss": "https://as.example", "sub": "alice", "aud": "https://rs.example",
+                   "client_id": "client-1", "iat": 1_000, "exp": 1_300, "jti": "a1"}),
+        );
+        let (kind, requirements) = Profile::Fapi2.check(&access, &access.payload, None);
+        assert_eq!(kind, Kind::AccessToken);
+        assert_eq!(
+            requirements.last().unwrap().detail,
+            "no cnf with x5t#S256 or jkt: anyone holding the token can use it"
+        );
+        assert!(!requirements.last().unwrap().passed);
+        let mut bound = access.payload.clone();
+        bound["cnf"] = json!({"jkt": "0ZcOCORZNYy-DWpqq30jZyJGHTN0d2HglBV3uiguA4I"});
+        let (_, requirements) = Profile::Fapi2.check(&access, &bound, None);
+        assert!(requirements.iter().all(|r| r.passed));
+
+        let assertion = token(
+            json!({"alg": "ES256"}),
+            json!({"iss": "client-1", "sub": "client-1", "aud": ["https://as.example"],
+                   "jti": "x", "exp": 1_060}),
+        );
+        assert_eq!(Kind::detect(&assertion), Kind::ClientAssertion);
+        let (_, requirements) = Profile::Fapi2.check(&assertion, &assertion.payload, None);
+        assert_eq!(requirements.iter().filter(|r| !r.passed).count(), 1);
+
+        let proof = token(
+            json!({"alg": "ES256", "typ": "dpop+jwt", "jwk": {"kty": "EC", "d": "secret"}}),
+            json!({"jti": "p", "htm": "POST", "htu": "https://as.example/token", "iat": 1_000}),
+        );
+        let (kind, requirements) = Profile::Fapi2.check(&proof, &proof.payload, None);
+        assert_eq!(kind, Kind::DpopProof);
+        assert_eq!(
+            requirements[1].detail,
+            "the jwk includes its private key (d)"
+        );
+        // the kind can be forced
+        assert_eq!(
+            Profile::Fapi2
+                .check(&proof, &proof.payload, Some(Kind::IdToken))
+                .0,
+            Kind::IdToken
+        );
+        assert!("fapi1".parse::<Profile>().is_err());
+        assert_eq!("dpop-proof".parse::<Kind>().unwrap(), Kind::DpopProof);
+    }
+
+    #[test]
+    fn profile_failures_test() {
+        assert_eq!(
+            error_message!(InvalidArgumentError, "fapi1".parse::<Profile>()),
+            "`fapi1` is not a profile; expected fapi2 or rfc9068 or license"
+        );
+        assert_eq!(
+            error_message!(InvalidArgumentError, "refresh-token".parse::<Kind>()),
+            "`refresh-token` is not a token kind"
+        );
+
+        let failures = |profile: Profile, header: Value, payload: Value, kind: Kind| {
+            let token = token(header, payload);
+            profile
+                .check(&token, &token.payload, Some(kind))
+                .1
+                .into_iter()
+                .filter(|r| !r.passed)
+                .map(|r| r.detail)
+                .collect::<Vec<String>>()
+        };
+        let es256 = || json!({"alg": "ES256"});
+        assert_eq!(
+            failures(
+                Profile::Fapi2,
+                es256(),
+                json!({"iss": "client-1", "client_id": "client-2", "exp": 1_000}),
+                Kind::RequestObject
+            )[..3],
+            [
+                "missing aud, nbf",
+                "needs both nbf and exp",
+                "iss is client-1, client_id is client-2"
+            ]
+        );
+        assert_eq!(
+            failures(
+                Profile::Fapi2,
+                es256(),
+                json!({"client_id": "client-1"}),
+                Kind::RequestObject
+            )[..3],
+            [
+                "no exp: the token never expires",
+                "missing iss, aud, exp, nbf",
+                "needs both nbf and exp"
+            ]
+        );
+        assert_eq!(
+            failures(Profile::Fapi2, es256(), json!({}), Kind::RequestObject)[3],
+            "needs both iss and client_id"
+        );
+        assert_eq!(
+            failures(
+                Profile::Fapi2,
+                es256(),
+                json!({"iss": "client-1", "sub": "client-2", "aud": ["a", "b"],
+                       "jti": "x", "exp": 1_060}),
+                Kind::ClientAssertion
+            ),
+            [
+                "iss is client-1, sub is client-2",
+                "aud is [\"a\",\"b\"], expected the issuer as a single string"
+            ]
+        );
+        assert_eq!(
+            failures(
+                Profile::Fapi2,
+                es256(),
+                json!({"exp": 1_060}),
+                Kind::ClientAssertion
+            )[1],
+            "needs both iss and sub"
+        );
+        assert_eq!(
+            failures(
+                Profile::License,
+                json!({"alg": "none"}),
+                json!({"iss": "i", "sub": "s", "iat": 1_000, "exp": 1_000, "jti": ""}),
+                Kind::License
+            ),
+            [
+                "alg is none",
+                "jti is \"\", expected a non-empty string",
+                "exp is not after iat"
+            ]
+        );
+        assert_eq!(
+            failures(
+                Profile::License,
+                json!({"alg": "HS512"}),
+                json!({"iss": "i", "sub": "s", "iat": 1_000}),
+                Kind::License
+            ),
+            [
+                "alg is HS512: whoever can check a license can also mint one",
+                "no jti: a leaked token can only be revoked with its key"
+            ]
+        );
+        let access = json!({"iss": "i", "sub": "s", "aud": "a", "client_id": "c", "jti": "j",
+                            "typ": "at+jwt"});
+        let mut expired = access.clone();
+        expired["iat"] = json!(1_000);
+        expired["exp"] = json!(900);
+        let header = || json!({"alg": "ES256", "typ": "at+jwt"});
+        assert_eq!(
+            failures(Profile::Rfc9068, header(), expired, Kind::AccessToken),
+            ["exp is not after iat"]
+        );
+        assert_eq!(
+            failures(Profile::Rfc9068, header(), access, Kind::AccessToken),
+            ["missing exp, iat", "needs both iat and exp"]
+        );
+    }
+}
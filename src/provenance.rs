@@ -0,0 +1,99 @@
+//! Build provenance embedded by `build.rs`, for `--version --verbose` and the
+//! `provenance` subcommand.
+
+use crate::JWTError;
+use jwt_check::crypto::Hash;
+use serde_json::Value;
+
+pub const COMMIT: &str = env!("JWT_CHECK_GIT_COMMIT");
+/// `true`, `false`, or `unknown` when the tree was not a git checkout.
+pub const DIRTY: &str = env!("JWT_CHECK_GIT_DIRTY");
+pub const RUSTC: &str = env!("JWT_CHECK_RUSTC_VERSION");
+pub const TARGET: &str = env!("JWT_CHECK_TARGET");
+pub const PROFILE: &str = env!("JWT_CHECK_PROFILE");
+/// Comma-separated Cargo features, empty for the default build.
+pub const FEATURES: &str = env!("JWT_CHECK_FEATURES");
+/// `JWT_CHECK_BUILDER_ID` at build time, so CI can name itself.
+pub const BUILDER_ID: &str = env!("JWT_CHECK_BUILDER_ID");
+
+const REPOSITORY: &str = "https://github.com/tek-shinobi/jwt-check";
+
+pub fn features() -> Vec<&'static str> {
+    FEATURES.split(',').filter(|f| !f.is_empty()).collect()
+}
+
+/// `--version --verbose`: the plain `--version` line, then the build details.
+pub fn version_verbose(version_line: &str) -> String {
+    format!(
+        "{}\ncommit: {}{}\nrustc: {}\ntarget: {}\nprofile: {}\nfeatures: {}",
+        version_line,
+        COMMIT,
+        if DIRTY == "true" { " (dirty)" } else { "" },
+        RUSTC,
+        TARGET,
+        PROFILE,
+        if FEATURES.is_empty() {
+            "(none)"
+        } else {
+            FEATURES
+        }
+    )
+}
+
+/// An in-toto statement with a SLSA v1 provenance predicate. The subject is
+/// the running executable, hashed now, so the statement describes exactly
+/// the binary that printed it.
+pub fn statement() -> Result<Value, JWTError> {
+    let executable = std::env::current_exe()?;
+    let digest: String = Hash::Sha256
+        .digest(&std::fs::read(&executable)?)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok(serde_json::json!({
+        "_type": "https://in-toto.io/Statement/v1",
+        "subject": [{
+            "name": executable.file_name().map(|n| n.to_string_lossy().into_owned()),
+            "digest": {"sha256": digest},
+        }],
+        "predicateType": "https://slsa.dev/provenance/v1",
+        "predicate": {
+            "buildDefinition": {
+                "buildType": format!("{}/cargo-build@v1", REPOSITORY),
+                "externalParameters": {
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "profile": PROFILE,
+                    "target": TARGET,
+                    "features": features(),
+                },
+                "internalParameters": {"rustc": RUSTC},
+                "resolvedDependencies": [{
+                    "uri": format!("git+{}", REPOSITORY),
+                    "digest": {"gitCommit": COMMIT},
+                    "annotations": {"dirty": DIRTY},
+                }],
+            },
+            "runDetails": {
+                "builder": {"id": BUILDER_ID},
+            },
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statement_describes_this_binary_test() {
+        let statement = statement().unwrap();
+        assert_eq!(statement["predicateType"], "https://slsa.dev/provenance/v1");
+        let digest = statement["subject"][0]["digest"]["sha256"]
+            .as_str()
+            .unwrap();
+        assert_eq!(digest.len(), 64);
+        let dependency = &statement["predicate"]["buildDefinition"]["resolvedDependencies"][0];
+        assert_eq!(dependency["digest"]["gitCommit"], COMMIT);
+        assert!(version_verbose("jwt-check 0.1.0").starts_with("jwt-check 0.1.0\ncommit: "));
+    }
+}
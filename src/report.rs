@@ -0,0 +1,429 @@
+//! `--report`: a self-contained HTML or Markdown report for people, where
+//! `--out` and `--sarif` are for machines.
+//!
+//! An `--audit` report has a summary of the token, its findings grouped by
+//! severity with their RFC references, and the decoded header and payload.
+//! A `--batch --stats` report has the issuance histogram, drawn as an
+//! inline SVG bar chart per issuer in HTML and as sparklines in Markdown,
+//! and the anomalies found. The HTML needs nothing but itself: styles are
+//! inline and there are no scripts.
+
+use crate::anomalies::Anomaly;
+use crate::audit::{Finding, Severity};
+use crate::issuance::{sparkline, Histogram};
+use crate::junit::escape;
+use crate::{format_timestamp, humanize_seconds, JWTError, JWToken};
+use serde_json::Value;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Html,
+    Markdown,
+}
+
+impl FromStr for ReportFormat {
+    type Err = JWTError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(ReportFormat::Html),
+            "md" | "markdown" => Ok(ReportFormat::Markdown),
+            _ => Err(JWTError::InvalidArgumentError(
+                format!("unknown report format `{}`; expected html or md", s).into(),
+            )),
+        }
+    }
+}
+
+impl ReportFormat {
+    /// HTML for a `.html` or `.htm` destination, Markdown otherwise.
+    pub fn for_destination(destination: &str) -> ReportFormat {
+        let lower = destination.to_ascii_lowercase();
+        if lower.ends_with(".html") || lower.ends_with(".htm") {
+            ReportFormat::Html
+        } else {
+            ReportFormat::Markdown
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ReportFormat::Html => "text/html; charset=utf-8",
+            ReportFormat::Markdown => "text/markdown; charset=utf-8",
+        }
+    }
+}
+
+/// What a report shows; sections with nothing in them are left out.
+#[derive(Default)]
+pub struct Report<'a> {
+    pub title: String,
+    /// Label and value rows at the top.
+    pub summary: Vec<(String, String)>,
+    /// `None` when the run didn't audit, so there is no findings section.
+    pub findings: Option<&'a [Finding]>,
+    pub token: Option<&'a JWToken>,
+    pub histogram: Option<&'a Histogram>,
+    pub anomalies: Option<&'a [Anomaly]>,
+}
+
+/// The summary rows for a token: its `alg`, `kid` and `typ`, then `iss`,
+/// `sub`, `aud` and `exp`, each only when the token has it. `timestamps`
+/// holds `exp` in seconds.
+pub fn token_summary(token: &JWToken, timestamps: &Value) -> Vec<(String, String)> {
+    let mut rows = Vec::new();
+    for name in ["alg", "kid", "typ"] {
+        if let Some(value) = token.header.get(name).and_then(Value::as_str) {
+            rows.push((name.to_string(), value.to_string()));
+        }
+    }
+    for name in ["iss", "sub", "aud"] {
+        if let Some(value) = token.payload.get(name) {
+            let text = value
+                .as_str()
+                .map_or_else(|| value.to_string(), str::to_string);
+            rows.push((name.to_string(), text));
+        }
+    }
+    if let Some(exp) = timestamps.get("exp").and_then(Value::as_i64) {
+        rows.push(("exp".to_string(), format_timestamp(exp)));
+    }
+    rows
+}
+
+const SEVERITIES: [Severity; 3] = [Severity::High, Severity::Medium, Severity::Low];
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;max-width:60em;margin:2em auto;padding:0 1em;color:#222}\
+table{border-collapse:collapse}td,th{border:1px solid #ccc;padding:.3em .6em;text-align:left}\
+pre{background:#f6f6f6;padding:1em;overflow-x:auto}.high{color:#b00020}.medium{color:#b36b00}.low{color:#555}\
+svg rect{fill:#4a7bd0}svg rect.spike{fill:#b00020}";
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_default()
+}
+
+/// `|` and newlines would break a Markdown table row.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+fn bar_chart(histogram: &Histogram, counts: &[usize], spikes: &[usize]) -> String {
+    const BAR: usize = 6;
+    const HEIGHT: usize = 40;
+    let max = counts.iter().copied().max().unwrap_or_default().max(1);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" role=\"img\">",
+        counts.len() * BAR,
+        HEIGHT
+    );
+    for (index, count) in counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+        let height = (count * HEIGHT).div_ceil(max);
+        let start = histogram.start + index as i64 * histogram.width;
+        svg.push_str(&format!(
+            "<rect{} x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"><title>{} at {}</title></rect>",
+            if spikes.contains(&index) {
+                " class=\"spike\""
+            } else {
+                ""
+            },
+            index * BAR,
+            HEIGHT - height,
+            BAR - 1,
+            height,
+            count,
+            format_timestamp(start)
+        ));
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+fn histogram_caption(histogram: &Histogram) -> String {
+    format!(
+        "Tokens by iat in buckets of {} from {}.",
+        humanize_seconds(histogram.width),
+        format_timestamp(histogram.start)
+    )
+}
+
+fn render_markdown(report: &Report) -> String {
+    let mut out = format!("# {}\n", report.title);
+    if !report.summary.is_empty() {
+        out.push_str("\n| | |\n|---|---|\n");
+        for (label, value) in &report.summary {
+            out.push_str(&format!("| {} | {} |\n", cell(label), cell(value)));
+        }
+    }
+    if let Some(findings) = report.findings {
+        out.push_str("\n## Findings\n");
+        if findings.is_empty() {
+            out.push_str("\nNo findings.\n");
+        }
+        for severity in SEVERITIES {
+            let found: Vec<&Finding> = findings.iter().filter(|f| f.severity == severity).collect();
+            if found.is_empty() {
+                continue;
+            }
+            out.push_str(&format!("\n### {} ({})\n\n", severity, found.len()));
+            for finding in found {
+                out.push_str(&format!("- **{}**: {}", finding.rule.id(), finding.message));
+                if let Some(reference) = finding.reference {
+                    out.push_str(&format!(" ([{}]({}))", reference, reference.url()));
+                }
+                out.push('\n');
+            }
+        }
+    }
+    if let Some(token) = report.token {
+        out.push_str(&format!(
+            "\n## Token\n\nHeader:\n\n```json\n{}\n```\n\nPayload:\n\n```json\n{}\n```\n",
+            pretty(&token.header),
+            pretty(&token.payload)
+        ));
+    }
+    if let Some(histogram) = report.histogram {
+        out.push_str(&format!(
+            "\n## Issuance\n\n{}\n\n| Issuer | Tokens | By bucket | Spikes |\n|---|---|---|---|\n",
+            histogram_caption(histogram)
+        ));
+        for series in histogram.series.iter().filter(|s| s.subject.is_none()) {
+            out.push_str(&format!(
+                "| {} | {} | `{}` | {} |\n",
+                cell(&series.issuer),
+                series.total(),
+                sparkline(&series.counts),
+                series.spikes().len()
+            ));
+        }
+    }
+    if let Some(anomalies) = report.anomalies {
+        out.push_str(&format!("\n## Anomalies ({})\n", anomalies.len()));
+        if !anomalies.is_empty() {
+            out.push_str("\n| Line | Issuer | Kind | |\n|---|---|---|---|\n");
+        }
+        for anomaly in anomalies {
+            out.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                anomaly.line,
+                cell(&anomaly.issuer),
+                anomaly.kind,
+                cell(&anomaly.message)
+            ));
+        }
+    }
+    out
+}
+
+fn render_html(report: &Report) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        escape(&report.title),
+        STYLE,
+        escape(&report.title)
+    );
+    if !report.summary.is_empty() {
+        out.push_str("<table>\n");
+        for (label, value) in &report.summary {
+            out.push_str(&format!(
+                "<tr><th>{}</th><td>{}</td></tr>\n",
+                escape(label),
+                escape(value)
+            ));
+        }
+        out.push_str("</table>\n");
+    }
+    if let Some(findings) = report.findings {
+        out.push_str("<h2>Findings</h2>\n");
+        if findings.is_empty() {
+            out.push_str("<p>No findings.</p>\n");
+        }
+        for severity in SEVERITIES {
+            let found: Vec<&Finding> = findings.iter().filter(|f| f.severity == severity).collect();
+            if found.is_empty() {
+                continue;
+            }
+            out.push_str(&format!(
+                "<h3 class=\"{}\">{} ({})</h3>\n<ul>\n",
+                severity,
+                severity,
+                found.len()
+            ));
+            for finding in found {
+                out.push_str(&format!(
+                    "<li><strong>{}</strong>: {}",
+                    finding.rule.id(),
+                    escape(&finding.message)
+                ));
+                if let Some(reference) = finding.reference {
+                    out.push_str(&format!(
+                        " (<a href=\"{}\">{}</a>)",
+                        escape(&reference.url()),
+                        reference
+                    ));
+                }
+                out.push_str("</li>\n");
+            }
+            out.push_str("</ul>\n");
+        }
+    }
+    if let Some(token) = report.token {
+        out.push_str(&format!(
+            "<h2>Token</h2>\n<h3>Header</h3>\n<pre>{}</pre>\n<h3>Payload</h3>\n<pre>{}</pre>\n",
+            escape(&pretty(&token.header)).replace("&#10;", "\n"),
+            escape(&pretty(&token.payload)).replace("&#10;", "\n")
+        ));
+    }
+    if let Some(histogram) = report.histogram {
+        out.push_str(&format!(
+            "<h2>Issuance</h2>\n<p>{}</p>\n<table>\n<tr><th>Issuer</th><th>Tokens</th><th>By bucket</th></tr>\n",
+            escape(&histogram_caption(histogram))
+        ));
+        for series in histogram.series.iter().filter(|s| s.subject.is_none()) {
+            out.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                escape(&series.issuer),
+                series.total(),
+                bar_chart(histogram, &series.counts, &series.spikes())
+            ));
+        }
+        out.push_str("</table>\n");
+    }
+    if let Some(anomalies) = report.anomalies {
+        out.push_str(&format!("<h2>Anomalies ({})</h2>\n", anomalies.len()));
+        if !anomalies.is_empty() {
+            out.push_str("<table>\n<tr><th>Line</th><th>Issuer</th><th>Kind</th><th></th></tr>\n");
+            for anomaly in anomalies {
+                out.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    anomaly.line,
+                    escape(&anomaly.issuer),
+                    anomaly.kind,
+                    escape(&anomaly.message)
+                ));
+            }
+            out.push_str("</table>\n");
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+pub fn render(report: &Report, format: ReportFormat) -> String {
+    match format {
+        ReportFormat::Html => render_html(report),
+        ReportFormat::Markdown => render_markdown(report),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::Rule;
+    use serde_json::json;
+
+    #[test]
+    fn render_test() {
+        let token = JWToken {
+            header: json!({"alg": "none"}),
+            payload: json!({"sub": "<script>"}),
+            signature: Vec::new(),
+        };
+        let findings = [
+            Finding {
+                rule: Rule::NoExpiry,
+                severity: Severity::Medium,
+                message: "no exp claim".to_string(),
+                reference: None,
+                fix: None,
+            },
+            Finding {
+                rule: Rule::AlgNone,
+                severity: Severity::High,
+                message: "alg is none | unsigned".to_string(),
+                reference: Some(crate::audit::Reference {
+                    rfc: 8725,
+                    section: "3.1",
+                }),
+                fix: None,
+            },
+        ];
+        let report = Report {
+            title: "jwt-check audit".to_string(),
+            summary: vec![("alg".to_string(), "none".to_string())],
+            findings: Some(&findings),
+            token: Some(&token),
+            ..Report::default()
+        };
+        let markdown = render(&report, ReportFormat::Markdown);
+        assert!(markdown.starts_with("# jwt-check audit\n\n| | |\n|---|---|\n| alg | none |\n\n## Findings\n\n### high (1)\n\n"));
+        assert!(markdown.contains(
+            "- **alg-none**: alg is none | unsigned ([RFC 8725 section 3.1](https://www.rfc-editor.org/rfc/rfc8725#section-3.1))\n\n### medium (1)\n\n- **no-expiry**: no exp claim\n"
+        ));
+        assert!(markdown.contains("```json\n{\n  \"sub\": \"<script>\"\n}\n```"));
+        assert!(!markdown.contains("## Issuance"));
+
+        let html = render(&report, ReportFormat::Html);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<h3 class=\"high\">high (1)</h3>"));
+        assert!(html.contains("&quot;sub&quot;: &quot;&lt;script&gt;&quot;"));
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<h2>Anomalies"));
+
+        let histogram = crate::issuance::histogram(
+            &[
+                json!({"iss": "https://idp", "iat": 0}),
+                json!({"iss": "https://idp", "iat": 7_200}),
+            ],
+            Some(3_600),
+        );
+        let stats = Report {
+            title: "jwt-check batch stats".to_string(),
+            histogram: Some(&histogram),
+            anomalies: Some(&[]),
+            ..Report::default()
+        };
+        let markdown = render(&stats, ReportFormat::Markdown);
+        assert!(markdown.contains("| https://idp | 2 | `█ █` | 0 |\n"));
+        assert!(markdown.ends_with("## Anomalies (0)\n"));
+        let html = render(&stats, ReportFormat::Html);
+        assert!(html.contains(
+            "<rect x=\"0\" y=\"0\" width=\"5\" height=\"40\"><title>1 at 1970-01-01T00:00:00Z</title></rect>"
+        ));
+        assert!(!html.contains("<h2>Findings"));
+
+        assert_eq!(
+            ReportFormat::for_destination("out/Report.HTML"),
+            ReportFormat::Html
+        );
+        assert_eq!(
+            ReportFormat::for_destination("s3://bucket/report.md"),
+            ReportFormat::Markdown
+        );
+        assert!(matches!(
+            "pdf".parse::<ReportFormat>(),
+            Err(JWTError::InvalidArgumentError(e)) if e == "unknown report format `pdf`; expected html or md"
+        ));
+    }
+
+    #[test]
+    fn token_summary_test() {
+        let token = JWToken {
+            header: json!({"alg": "RS256", "kid": "k1"}),
+            payload: json!({"iss": "https://idp.example", "aud": ["a", "b"], "exp": 1_700_000_000_000i64}),
+            signature: Vec::new(),
+        };
+        let rows = token_summary(&token, &json!({"exp": 1_700_000_000}));
+        let rows: Vec<(&str, &str)> = rows.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(
+            rows,
+            [
+                ("alg", "RS256"),
+                ("kid", "k1"),
+                ("iss", "https://idp.example"),
+                ("aud", "[\"a\",\"b\"]"),
+                ("exp", "2023-11-14T22:13:20Z"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,830 @@
+//! Revocation checks for `x5c` chains: `--crl` files, OCSP responses
+//! stapled with `--ocsp-response`, and `--ocsp` queries to the responder a
+//! certificate names in its `authorityInfoAccess`.
+//!
+//! Each certificate below the root is looked up in turn: first its OCSP
+//! responder when `--ocsp` is given, falling back to a stapled response
+//! when the responder can't be reached or gives no usable answer, then the
+//! CRLs. A CRL or response only counts when its issuer's key verifies its
+//! signature and its `nextUpdate` has not yet passed.
+
+use crate::crypto::Hash;
+use crate::keys::{der_expect, der_read, TAG_BIT_STRING, TAG_OCTET_STRING, TAG_OID, TAG_SEQUENCE};
+use crate::x509::{parse_time, tlv, verify_signature, Certificate};
+use crate::{format_timestamp, JWTError};
+
+const TAG_ENUMERATED: u8 = 0x0a;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_UTC_TIME: u8 = 0x17;
+
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const OID_REASON_CODE: &[u8] = &[0x55, 0x1d, 0x15];
+const OID_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+const OID_OCSP_SIGNING: &str = "1.3.6.1.5.5.7.3.9";
+
+/// CRLReason (RFC 5280 section 5.3.1); 7 is unused.
+const REASONS: [&str; 11] = [
+    "unspecified",
+    "keyCompromise",
+    "cACompromise",
+    "affiliationChanged",
+    "superseded",
+    "cessationOfOperation",
+    "certificateHold",
+    "",
+    "removeFromCRL",
+    "privilegeWithdrawn",
+    "aACompromise",
+];
+
+fn invalid(what: &str) -> JWTError {
+    JWTError::CertificateError(what.to_string().into())
+}
+
+fn reason_name(code: u8) -> String {
+    match REASONS.get(usize::from(code)) {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => format!("reason {}", code),
+    }
+}
+
+/// What a CRL or OCSP response says about one certificate.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    Good,
+    Revoked {
+        at: i64,
+        reason: Option<String>,
+    },
+    /// OCSP's `unknown`: the responder doesn't know the certificate.
+    Unknown,
+}
+
+/// Where revocation status comes from. Nothing is checked when it is empty.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Revocation {
+    pub crls: Vec<Crl>,
+    pub stapled: Vec<OcspResponse>,
+    /// `--ocsp`: ask each certificate's responder.
+    pub query_responders: bool,
+}
+
+impl Revocation {
+    pub fn is_empty(&self) -> bool {
+        self.crls.is_empty() && self.stapled.is_empty() && !self.query_responders
+    }
+}
+
+/// A time if the next field is one, for the optional `nextUpdate`.
+fn optional_time(input: &[u8]) -> Result<(Option<i64>, &[u8]), JWTError> {
+    match input.first() {
+        Some(&TAG_UTC_TIME) | Some(&TAG_GENERALIZED_TIME) => {
+            let (time, rest) = parse_time(input)?;
+            Ok((Some(time), rest))
+        }
+        _ => Ok((None, input)),
+    }
+}
+
+/// The signed part, signature AlgorithmIdentifier contents, signature and any trailing fields
+/// of a CRL or a BasicOCSPResponse, which share a certificate's shape.
+type Signed<'a> = (&'a [u8], &'a [u8], Vec<u8>, &'a [u8]);
+
+fn split_signed(der: &[u8]) -> Result<Signed<'_>, JWTError> {
+    let (outer, _) = der_expect(der, TAG_SEQUENCE)?;
+    let (tbs, rest) = tlv(outer)?;
+    let (algorithm, rest) = der_expect(rest, TAG_SEQUENCE)?;
+    let (signature, rest) = der_expect(rest, TAG_BIT_STRING)?;
+    let signature = match signature.split_first() {
+        Some((0, signature)) => signature.to_vec(),
+        _ => return Err(invalid("signature has unused bits")),
+    };
+    Ok((tbs, algorithm, signature, rest))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Crl {
+    tbs: Vec<u8>,
+    signature_algorithm: Vec<u8>,
+    signature: Vec<u8>,
+    pub issuer: Vec<u8>,
+    pub this_update: i64,
+    pub next_update: Option<i64>,
+    /// Serial, revocation time and reason code of each revoked certificate.
+    pub revoked: Vec<(Vec<u8>, i64, Option<u8>)>,
+}
+
+impl Crl {
+    /// Reads a CRL in DER or as an `X509 CRL` PEM block.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Crl, JWTError> {
+        match std::str::from_utf8(bytes) {
+            Ok(text) if text.contains("-----BEGIN X509 CRL-----") => {
+                Crl::from_der(&crate::keys::pem_decode(text)?.1)
+            }
+            _ => Crl::from_der(bytes),
+        }
+    }
+
+    pub fn from_der(der: &[u8]) -> Result<Crl, JWTError> {
+        let (tbs, signature_algorithm, signature, _) = split_signed(der)?;
+        let (mut fields, _) = der_expect(tbs, TAG_SEQUENCE)?;
+        // optional version
+        if fields.first() == Some(&0x02) {
+            fields = der_read(fields)?.2;
+        }
+        let (_, _, rest) = der_read(fields)?;
+        let (issuer, rest) = tlv(rest)?;
+        let (this_update, rest) = parse_time(rest)?;
+        let (next_update, rest) = optional_time(rest)?;
+        let mut revoked = Vec::new();
+        if rest.first() == Some(&TAG_SEQUENCE) {
+            let (mut entries, _) = der_expect(rest, TAG_SEQUENCE)?;
+            while !entries.is_empty() {
+                let (entry, after) = der_expect(entries, TAG_SEQUENCE)?;
+                entries = after;
+                let (serial, rest) = der_expect(entry, 0x02)?;
+                let (at, rest) = parse_time(rest)?;
+                let reason = match rest.first() {
+                    Some(&TAG_SEQUENCE) => entry_reason(der_expect(rest, TAG_SEQUENCE)?.0)?,
+                    _ => None,
+                };
+                revoked.push((serial.to_vec(), at, reason));
+            }
+        }
+        Ok(Crl {
+            tbs: tbs.to_vec(),
+            signature_algorithm: signature_algorithm.to_vec(),
+            signature,
+            issuer: issuer.to_vec(),
+            this_update,
+            next_update,
+            revoked,
+        })
+    }
+
+    /// `certificate`'s status, or why this CRL can't say.
+    fn status(
+        &self,
+        certificate: &Certificate,
+        issuer: &Certificate,
+        now: i64,
+    ) -> Result<Status, String> {
+        if self.issuer != certificate.issuer {
+            return Err("no CRL from its issuer".to_string());
+        }
+        let signed = verify_signature(
+            &issuer.public_key().map_err(|e| e.to_string())?,
+            &self.signature_algorithm,
+            &self.tbs,
+            &self.signature,
+        );
+        if !signed.map_err(|e| e.to_string())? {
+            return Err(format!("the CRL is not signed by `{}`", issuer.name()));
+        }
+        if let Some(next) = self.next_update.filter(|next| *next < now) {
+            return Err(format!(
+                "the CRL from `{}` is out of date since {}",
+                issuer.name(),
+                format_timestamp(next)
+            ));
+        }
+        Ok(self
+            .revoked
+            .iter()
+            .find(|(serial, _, _)| *serial == certificate.serial)
+            .map_or(Status::Good, |(_, at, reason)| Status::Revoked {
+                at: *at,
+                reason: reason.map(reason_name),
+            }))
+    }
+}
+
+/// The `reasonCode` among a CRL entry's extensions.
+fn entry_reason(mut extensions: &[u8]) -> Result<Option<u8>, JWTError> {
+    while !extensions.is_empty() {
+        let (extension, after) = der_expect(extensions, TAG_SEQUENCE)?;
+        extensions = after;
+        let (oid, rest) = der_expect(extension, TAG_OID)?;
+        if oid == OID_REASON_CODE {
+            let (value, _) = der_expect(rest, TAG_OCTET_STRING)?;
+            let (code, _) = der_expect(value, TAG_ENUMERATED)?;
+            return Ok(code.first().copied());
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Responder {
+    Name(Vec<u8>),
+    /// SHA-1 of the responder's public key bits.
+    KeyHash(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SingleResponse {
+    hash_algorithm: Vec<u8>,
+    issuer_name_hash: Vec<u8>,
+    issuer_key_hash: Vec<u8>,
+    serial: Vec<u8>,
+    status: Status,
+    next_update: Option<i64>,
+}
+
+/// A successful BasicOCSPResponse.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OcspResponse {
+    tbs: Vec<u8>,
+    signature_algorithm: Vec<u8>,
+    signature: Vec<u8>,
+    responder: Responder,
+    pub produced_at: i64,
+    responses: Vec<SingleResponse>,
+    /// Certificates sent along, such as a delegated responder's.
+    certificates: Vec<Certificate>,
+}
+
+impl OcspResponse {
+    pub fn from_der(der: &[u8]) -> Result<OcspResponse, JWTError> {
+        let (response, _) = der_expect(der, TAG_SEQUENCE)?;
+        let (status, rest) = der_expect(response, TAG_ENUMERATED)?;
+        if status != [0] {
+            return Err(JWTError::CertificateError(
+                format!(
+                    "the OCSP responder answered with status {}",
+                    status.first().copied().unwrap_or_default()
+                )
+                .into(),
+            ));
+        }
+        let (bytes, _) = der_expect(rest, 0xa0)?;
+        let (bytes, _) = der_expect(bytes, TAG_SEQUENCE)?;
+        let (kind, rest) = der_expect(bytes, TAG_OID)?;
+        if kind != OID_OCSP_BASIC {
+            return Err(invalid("not a basic OCSP response"));
+        }
+        let (basic, _) = der_expect(rest, TAG_OCTET_STRING)?;
+        let (tbs, signature_algorithm, signature, rest) = split_signed(basic)?;
+        let mut certificates = Vec::new();
+        if rest.first() == Some(&0xa0) {
+            let (list, _) = der_expect(rest, 0xa0)?;
+            let (mut list, _) = der_expect(list, TAG_SEQUENCE)?;
+            while !list.is_empty() {
+                let (certificate, after) = tlv(list)?;
+                certificates.push(Certificate::from_der(certificate)?);
+                list = after;
+            }
+        }
+
+        let (mut fields, _) = der_expect(tbs, TAG_SEQUENCE)?;
+        if fields.first() == Some(&0xa0) {
+            fields = der_read(fields)?.2;
+        }
+        let (tag, id, rest) = der_read(fields)?;
+        let responder = match tag {
+            0xa1 => Responder::Name(tlv(id)?.0.to_vec()),
+            0xa2 => Responder::KeyHash(der_expect(id, TAG_OCTET_STRING)?.0.to_vec()),
+            _ => return Err(invalid("unsupported OCSP responderID")),
+        };
+        let (produced_at, rest) = parse_time(rest)?;
+        let (mut list, _) = der_expect(rest, TAG_SEQUENCE)?;
+        let mut responses = Vec::new();
+        while !list.is_empty() {
+            let (single, after) = der_expect(list, TAG_SEQUENCE)?;
+            list = after;
+            responses.push(single_response(single)?);
+        }
+        Ok(OcspResponse {
+            tbs: tbs.to_vec(),
+            signature_algorithm: signature_algorithm.to_vec(),
+            signature,
+            responder,
+            produced_at,
+            responses,
+            certificates,
+        })
+    }
+
+    /// The certificate whose key signed this response: the issuer itself,
+    /// or a responder it delegated to with the OCSPSigning usage.
+    fn signer<'a>(&'a self, issuer: &'a Certificate) -> Result<&'a Certificate, String> {
+        let names = |certificate: &Certificate| match &self.responder {
+            Responder::Name(name) => *name == certificate.subject,
+            Responder::KeyHash(hash) => certificate
+                .public_key_bits()
+                .is_ok_and(|bits| Hash::Sha1.digest(&bits) == *hash),
+        };
+        if names(issuer) {
+            return Ok(issuer);
+        }
+        self.certificates
+            .iter()
+            .find(|delegate| {
+                names(delegate)
+                    && delegate.issued_by(issuer).is_ok()
+                    && delegate
+                        .extended_key_usage
+                        .as_deref()
+                        .unwrap_or_default()
+                        .iter()
+                        .any(|oid| oid == OID_OCSP_SIGNING)
+            })
+            .ok_or_else(|| {
+                format!(
+                    "the OCSP response is neither from `{}` nor from a responder it authorized",
+                    issuer.name()
+                )
+            })
+    }
+
+    /// `certificate`'s status, or why this response can't say.
+    fn status(
+        &self,
+        certificate: &Certificate,
+        issuer: &Certificate,
+        now: i64,
+    ) -> Result<Status, String> {
+        let key_bits = issuer.public_key_bits().map_err(|e| e.to_string())?;
+        let single = self
+            .responses
+            .iter()
+            .find(|single| {
+                let hash = match single.hash_algorithm.as_slice() {
+                    OID_SHA1 => Hash::Sha1,
+                    OID_SHA256 => Hash::Sha256,
+                    _ => return false,
+                };
+                single.serial == certificate.serial
+                    && single.issuer_name_hash == hash.digest(&certificate.issuer)
+                    && single.issuer_key_hash == hash.digest(&key_bits)
+            })
+            .ok_or_else(|| "no OCSP response for it".to_string())?;
+        let signer = self.signer(issuer)?;
+        let signed = verify_signature(
+            &signer.public_key().map_err(|e| e.to_string())?,
+            &self.signature_algorithm,
+            &self.tbs,
+            &self.signature,
+        );
+        if !signed.map_err(|e| e.to_string())? {
+            return Err(format!(
+                "the OCSP response signature does not verify with the key of `{}`",
+                signer.name()
+            ));
+        }
+        if let Some(next) = single.next_update.filter(|next| *next < now) {
+            return Err(format!(
+                "the OCSP response is out of date since {}",
+                format_timestamp(next)
+            ));
+        }
+        Ok(single.status.clone())
+    }
+}
+
+fn single_response(single: &[u8]) -> Result<SingleResponse, JWTError> {
+    let (id, rest) = der_expect(single, TAG_SEQUENCE)?;
+    let (algorithm, id_rest) = der_expect(id, TAG_SEQUENCE)?;
+    let (hash_algorithm, _) = der_expect(algorithm, TAG_OID)?;
+    let (issuer_name_hash, id_rest) = der_expect(id_rest, TAG_OCTET_STRING)?;
+    let (issuer_key_hash, id_rest) = der_expect(id_rest, TAG_OCTET_STRING)?;
+    let (serial, _) = der_expect(id_rest, 0x02)?;
+    let (tag, contents, rest) = der_read(rest)?;
+    let status = match tag {
+        0x80 => Status::Good,
+        0xa1 => {
+            let (at, after) = parse_time(contents)?;
+            let reason = match der_expect(after, 0xa0) {
+                Ok((reason, _)) => der_expect(reason, TAG_ENUMERATED)?
+                    .0
+                    .first()
+                    .map(|code| reason_name(*code)),
+                Err(_) => None,
+            };
+            Status::Revoked { at, reason }
+        }
+        0x82 => Status::Unknown,
+        _ => return Err(invalid("unsupported OCSP certStatus")),
+    };
+    let (_, rest) = parse_time(rest)?;
+    let next_update = match der_expect(rest, 0xa0) {
+        Ok((next, _)) => Some(parse_time(next)?.0),
+        Err(_) => None,
+    };
+    Ok(SingleResponse {
+        hash_algorithm: hash_algorithm.to_vec(),
+        issuer_name_hash: issuer_name_hash.to_vec(),
+        issuer_key_hash: issuer_key_hash.to_vec(),
+        serial: serial.to_vec(),
+        status,
+        next_update,
+    })
+}
+
+fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = contents.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len
+            .to_be_bytes()
+            .iter()
+            .copied()
+            .skip_while(|b| *b == 0)
+            .collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend(contents);
+    out
+}
+
+/// A DER OCSPRequest for `certificate`, with a SHA-1 CertID as responders
+/// expect, and no nonce.
+pub fn request(certificate: &Certificate, issuer: &Certificate) -> Result<Vec<u8>, JWTError> {
+    let mut algorithm = der(TAG_OID, OID_SHA1);
+    algorithm.extend([0x05, 0x00]);
+    let mut id = der(TAG_SEQUENCE, &algorithm);
+    id.extend(der(
+        TAG_OCTET_STRING,
+        &Hash::Sha1.digest(&certificate.issuer),
+    ));
+    id.extend(der(
+        TAG_OCTET_STRING,
+        &Hash::Sha1.digest(&issuer.public_key_bits()?),
+    ));
+    id.extend(der(0x02, &certificate.serial));
+    let request = der(TAG_SEQUENCE, &der(TAG_SEQUENCE, &id));
+    let list = der(TAG_SEQUENCE, &request);
+    Ok(der(TAG_SEQUENCE, &der(TAG_SEQUENCE, &list)))
+}
+
+/// The GET form of an OCSP request (RFC 6960 appendix A.1).
+pub fn request_url(responder: &str, request: &[u8]) -> String {
+    let encoded: String = base64::encode(request)
+        .chars()
+        .map(|c| match c {
+            '+' => "%2B".to_string(),
+            '/' => "%2F".to_string(),
+            '=' => "%3D".to_string(),
+            c => c.to_string(),
+        })
+        .collect();
+    format!("{}/{}", responder.trim_end_matches('/'), encoded)
+}
+
+/// Checks every certificate of `chain` (leaf first, root last) but the
+/// root, returning the ones whose status couldn't be established.
+/// `fetch` makes the `--ocsp` queries. A revoked certificate is an error.
+pub fn check(
+    chain: &[Certificate],
+    now: i64,
+    revocation: &Revocation,
+    fetch: &dyn Fn(&str) -> Result<Vec<u8>, JWTError>,
+) -> Result<Vec<String>, JWTError> {
+    let mut problems = Vec::new();
+    for pair in chain.windows(2) {
+        let (certificate, issuer) = match pair {
+            [certificate, issuer] => (certificate, issuer),
+            _ => continue,
+        };
+        let mut reasons = Vec::new();
+        let mut status = None;
+        if revocation.query_responders {
+            match &certificate.ocsp_url {
+                Some(url) => {
+                    let answer = request(certificate, issuer)
+                        .and_then(|request| fetch(&request_url(url, &request)))
+                        .and_then(|body| OcspResponse::from_der(&body))
+                        .map_err(|e| e.to_string())
+                        .and_then(|response| response.status(certificate, issuer, now));
+                    match answer {
+                        Ok(found) => status = Some(found),
+                        Err(e) => reasons.push(format!("{}: {}", url, e)),
+                    }
+                }
+                None => reasons.push("it names no OCSP responder".to_string()),
+            }
+        }
+        let candidates = revocation
+            .stapled
+            .iter()
+            .map(|response| response.status(certificate, issuer, now))
+            .chain(
+                revocation
+                    .crls
+                    .iter()
+                    .map(|crl| crl.status(certificate, issuer, now)),
+            );
+        for candidate in candidates {
+            if status.is_some() {
+                break;
+            }
+            match candidate {
+                Ok(found) => status = Some(found),
+                Err(e) => reasons.push(e),
+            }
+        }
+        match status {
+            Some(Status::Good) => (),
+            Some(Status::Revoked { at, reason }) => {
+                return Err(JWTError::RevokedCertificateError(
+                    format!(
+                        "`{}` was revoked at {}{}",
+                        certificate.name(),
+                        format_timestamp(at),
+                        reason.map_or(String::new(), |reason| format!(" ({})", reason))
+                    )
+                    .into(),
+                ))
+            }
+            Some(Status::Unknown) => problems.push(format!(
+                "the OCSP responder does not know `{}`",
+                certificate.name()
+            )),
+            None => {
+                reasons.dedup();
+                if reasons.is_empty() {
+                    reasons.push("no CRL or OCSP response was given".to_string());
+                }
+                problems.push(format!(
+                    "the revocation status of `{}` is unknown: {}",
+                    certificate.name(),
+                    reasons.join("; ")
+                ));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: &str = "-----BEGIN CERTIFICATE-----
+MIIC+jCCAeKgAwIBAgIUN6EfvkzkElVNh9WbvwQdpT1yFI8wDQYJKoZIhvcNAQEL
+BQAwFDESMBAGA1UEAwwJVGVzdCBSb290MCAXDTI2MTAxNDEyNTAyNloYDzIxMjYw
+OTIwMTI1MDI2WjAUMRIwEAYDVQQDDAlUZXN0IFJvb3QwggEiMA0GCSqGSIb3DQEB
+AQUAA4IBDwAwggEKAoIBAQDf8LEauEbLnkbAP9I8JV2c4PoH2IjkL6nAG8PBbEFw
+tVgMYCZt8BiM9rjWtZ9m/VzHDSaQ8yw4wwg6Ig9GBAM+x4Sk7lukX0uTGNU2zx2r
+i+U0trYtxnkX0UKGQcOXpHrhy+t+t+KLYBiUqCCps0rzhWMuhRFakZcqpbgJE7b3
+do4l2bcTAxfnm+09SE+FREXUBdSqHrdyEWb1jxwSoLQYtRo9+OzBEL90sqKvXLAw
+MxGmQoIkYRrDZK9wiL4iIFsigfhVefDwSubiuqRg/xm9H7YILwN9RXKyetXA0cM6
+x+Bf/5ipbWCoYoE3pWFItWKRMCktg70G7tptMyE2D4SRAgMBAAGjQjBAMA8GA1Ud
+EwEB/wQFMAMBAf8wDgYDVR0PAQH/BAQDAgEGMB0GA1UdDgQWBBSw1cO6fRMsNuFg
+N4ilqIBuTLP9ijANBgkqhkiG9w0BAQsFAAOCAQEArnZRYJuwrhD4nwYng6Wa1G97
+R3fMyuBoN0TYY9mFe9O2TkAEejIgfY2rNtaAnTFDGK6UX/0Uzo8fdfHytnpIucOu
+ESmbSeff2WnjBMMuS7YXDtwq5Kp+tXvYnDpO+8RVbjNVqRKJydAR+7phM+0vjtSB
+fjh9xFT+PLA2s80BuDPTFkpsh++PVbq5Nn8mW98PZiobPXVWqofSYa/m0CLeCUNg
+qKhAiy7mURRwK+Xisz3hcQI8g0HW7C1ZCbwpeM0IhNYtYHSKLqaY1nN72rdPfLrW
+7TBkYxRm69AlAVUDUt7l+VRvVTjSNbRhnnLX7dnl1hiWo5Zmr0kTAOXZzSVdrA==
+-----END CERTIFICATE-----";
+    /// Serial 0x1234, issued by ROOT.
+    const LEAF: &str = "MIICYTCCAUmgAwIBAgICEjQwDQYJKoZIhvcNAQELBQAwFDESMBAGA1UEAwwJVGVz\
+dCBSb290MCAXDTI2MTAxNDEyNTAyNloYDzIxMjYwOTIwMTI1MDI2WjAoMQ0wCwYD\
+VQQKDARBY21lMRcwFQYDVQQDDA5zaWduZXIuZXhhbXBsZTBZMBMGByqGSM49AgEG\
+CCqGSM49AwEHA0IABKqKbR810To749ofZY78rNhKOff11W5Ehe6i3mr+xDpeJe2C\
+vOZaq2dYGhZg4Mks1ImwTkPiFabNe5NpnjDeUNujcjBwMAkGA1UdEwQCMAAwDgYD\
+VR0PAQH/BAQDAgeAMBMGA1UdJQQMMAoGCCsGAQUFBwMCMB0GA1UdDgQWBBRgdz1y\
+GQFDFrWVuwPK1mJ40HJ1TzAfBgNVHSMEGDAWgBSw1cO6fRMsNuFgN4ilqIBuTLP9\
+ijANBgkqhkiG9w0BAQsFAAOCAQEAV6C7F4rVi24g5fVvFsIZVkdzDpFJmm5OUM9+\
+E5jSNOxVbyZjWyhO2BbMwZ3JWfpLwLrc/5f0qOlDKrMFi0vElsgy2X4DjRYSBTSU\
+ML6gYD4hHuu3dGItbBH1d0F99YVBsb4EvRtejJz44MdK9tPRUkFnE7FIv/aqDyvI\
+vJTAVPP8r1YNlQ4cIol10X3Xg0y0aUJ5OdytRkFv4rJ7BBjbDPX6MfSVMOSHBRLA\
+D60uho3KKaF5H03Rfoa8cQMMUxouunHFQSIcvaF74sLFWjOkmM4UgiJkgM4MA4eY\
+AD5FGjT+Y1tNeeukpWIx413P3ff2tqmCpyjWfxImNJVCHb3LTw==";
+    /// ROOT's CRL, revoking LEAF for keyCompromise.
+    const CRL: &str = "-----BEGIN X509 CRL-----
+MIIBkzB9AgEBMA0GCSqGSIb3DQEBCwUAMBQxEjAQBgNVBAMMCVRlc3QgUm9vdBcN
+MjYxMDE0MTI1NDI5WhgPMjEyNjA5MjAxMjU0MjlaMCMwIQICEjQXDTI2MTAxNDEy
+NTQyOVowDDAKBgNVHRUEAwoBAaAOMAwwCgYDVR0UBAMCAQEwDQYJKoZIhvcNAQEL
+BQADggEBALc3x3IP/UOwPV8NCYRel8x7HP0om1sd4h0I8aHzn7swr90lRhwytxnT
+hVjzt/mLVwz2jVvbkO/fZS7kaiu92l/GHqOt9XpoeJLNZffnTE+I7/7X88ieI06S
+9Af5GpC+iicI9AzibSqdK2uUBhUe4ATGRGkqZxZ+DhkMIzkw2ALgbk1nsebiW8AC
+gB+SAWXltngOT/dXwt11N71ICs50mI7z2MxKLeDWI13wpTf+uhJ5wRb8avLn3SKR
+sXetTArE1EioUYjo3lGt4oGz5TkFxI4mBpfJTqm8K6aj43z6iSk20Ynqxj2ySuuZ
+Iq+xEaTOjXQeTcopC97e72Wt7NdYS0M=
+-----END X509 CRL-----";
+    /// ROOT's OCSP answer for LEAF: good.
+    const GOOD: &str = "MIIBxQoBAKCCAb4wggG6BgkrBgEFBQcwAQEEggGrMIIBpzCBkKEWMBQxEjAQBgNV\
+BAMMCVRlc3QgUm9vdBgPMjAyNjEwMTQxMjU0MzZaMGUwYzA7MAkGBSsOAwIaBQAE\
+FEEGi0B5QzLOLhS9xgudIbD4Z0b9BBSw1cO6fRMsNuFgN4ilqIBuTLP9igICEjSA\
+ABgPMjAyNjEwMTQxMjU0MzZaoBEYDzIxMjYwOTIwMTI1NDM2WjANBgkqhkiG9w0B\
+AQsFAAOCAQEAr/KpLvAhMEgESL5HmeDcL5ZaBQs4MrSGNX7VJOi4ehtCEyIgoxBc\
+9MXdt5KwqQzPPi4VZL0DL4YIQWbQluo8hClAeeNciC+C6ZF+NZoXXPzF76wNwE2U\
+C7GyrXvwqR2uFFvG8+veGudGc2vF4h2/U6G1qI/gXkRXdHzWWqTs5eEO4I8SlgE3\
+AhbX5Et6QBqRRT8JesDBNu1Qq4vlCJaQJao8an8la3k+Y99aLgr/eRDm0eCJjNKj\
+i9XKEZa0hIRwr1eKdHlBSktFMbSG1Kxb+Y+VaFHSwgO3xoJtzeDNo4rP7cybBKGF\
+jHEFW5GTKoXItDnpG0qWE9AuDtS1p9/cPw==";
+    /// ROOT's OCSP answer for LEAF: revoked, keyCompromise.
+    const REVOKED: &str = "MIIB2woBAKCCAdQwggHQBgkrBgEFBQcwAQEEggHBMIIBvTCBpqEWMBQxEjAQBgNV\
+BAMMCVRlc3QgUm9vdBgPMjAyNjEwMTQxMjU0MzZaMHsweTA7MAkGBSsOAwIaBQAE\
+FEEGi0B5QzLOLhS9xgudIbD4Z0b9BBSw1cO6fRMsNuFgN4ilqIBuTLP9igICEjSh\
+FhgPMjAyNjEwMTQxMjU0MjlaoAMKAQEYDzIwMjYxMDE0MTI1NDM2WqARGA8yMTI2\
+MDkyMDEyNTQzNlowDQYJKoZIhvcNAQELBQADggEBAC+6xS2aWl9T8SGNjmTEJfrN\
+bIL2oYxO42OLltb0JWK27boDjUga4hoJXQ8S6QE1q1Fw54jNZaJUWrjzrDNg+FH5\
+jmKaU4Fj2fF/08s4CnjRmhlt4E05iQ8v39CT65La9tu9s8ZkX6JzVh5F1h5jTC1C\
+stVKm8bLD+3R2P5/1eINpm3VQv4hws1MuUdNXUP2jQe7TRyX4LYvXDm2QrgRuXd9\
+YYm3pD4GzXo3QhMIfvyC/tRHXN4d8bTd6M4WHXnYRQewISWoFVw0AZkaHtxZR60c\
+Gtb8NtmHchoejUCWGZo89tOeJ4oZMbb8XDNApiX5LVkmDuGaisOViigVDIYrx18=";
+
+    #[test]
+    fn revocation_sources_test() {
+        let root = Certificate::from_pem_bundle(ROOT).unwrap().remove(0);
+        let leaf = Certificate::from_der(&base64::decode(LEAF).unwrap()).unwrap();
+        let chain = vec![leaf.clone(), root.clone()];
+        let now = leaf.not_before + 3_600;
+        let offline = |_: &str| -> Result<Vec<u8>, JWTError> { panic!("no --ocsp") };
+        let response = |b64: &str| OcspResponse::from_der(&base64::decode(b64).unwrap()).unwrap();
+
+        // the same request `openssl ocsp -issuer root.pem -cert leaf.pem -no_nonce` writes
+        let der = request(&leaf, &root).unwrap();
+        assert_eq!(der.len(), 69);
+        assert_eq!(&der[..8], &[0x30, 0x43, 0x30, 0x41, 0x30, 0x3f, 0x30, 0x3d]);
+        assert_eq!(&der[65..], &[0x02, 0x02, 0x12, 0x34]);
+        assert!(request_url("http://ocsp.example/", &der)
+            .starts_with("http://ocsp.example/MEMwQTA%2FMD0w"));
+
+        let mut revocation = Revocation::default();
+        assert!(revocation.is_empty());
+        revocation.stapled.push(response(GOOD));
+        assert!(check(&chain, now, &revocation, &offline)
+            .unwrap()
+            .is_empty());
+
+        let crl = Crl::from_bytes(CRL.as_bytes()).unwrap();
+        assert_eq!(format_timestamp(crl.this_update), "2026-10-14T12:54:29Z");
+        assert_eq!(crl.revoked.len(), 1);
+        // the stapled response is consulted first
+        revocation.crls.push(crl.clone());
+        assert!(check(&chain, now, &revocation, &offline)
+            .unwrap()
+            .is_empty());
+        for revoked in [
+            Revocation {
+                crls: vec![crl.clone()],
+                ..Revocation::default()
+            },
+            Revocation {
+                stapled: vec![response(REVOKED)],
+                ..Revocation::default()
+            },
+        ] {
+            assert!(matches!(
+                check(&chain, now, &revoked, &offline),
+                Err(JWTError::RevokedCertificateError(e))
+                    if e == "`signer.example` was revoked at 2026-10-14T12:54:29Z (keyCompromise)"
+            ));
+        }
+
+        // a responder that can't be reached falls back to the stapled response
+        let unreachable = |url: &str| -> Result<Vec<u8>, JWTError> {
+            assert!(url.starts_with("http://ocsp.example/MEMw"));
+            Err(JWTError::HttpError("connection refused".into()))
+        };
+        let mut leaf = leaf;
+        leaf.ocsp_url = Some("http://ocsp.example".to_string());
+        let chain = vec![leaf, root];
+        let stapled = Revocation {
+            stapled: vec![response(GOOD)],
+            query_responders: true,
+            ..Revocation::default()
+        };
+        assert!(check(&chain, now, &stapled, &unreachable)
+            .unwrap()
+            .is_empty());
+        let answered = |_: &str| Ok(base64::decode(REVOKED).unwrap());
+        assert!(check(&chain, now, &stapled, &answered).is_err());
+        let nothing = Revocation {
+            query_responders: true,
+            ..Revocation::default()
+        };
+        assert_eq!(
+            check(&chain, now, &nothing, &unreachable).unwrap(),
+            vec!["the revocation status of `signer.example` is unknown: http://ocsp.example: HTTP error: connection refused"]
+        );
+
+        // out of date once nextUpdate has passed
+        let stale = Revocation {
+            crls: vec![crl],
+            ..Revocation::default()
+        };
+        let problems = check(&chain, 5_000_000_000, &stale, &offline).unwrap();
+        assert!(problems[0]
+            .ends_with("the CRL from `Test Root` is out of date since 2126-09-20T12:54:29Z"));
+    }
+
+    #[test]
+    fn revocation_failures_test() {
+        let root = Certificate::from_pem_bundle(ROOT).unwrap().remove(0);
+        let leaf = Certificate::from_der(&base64::decode(LEAF).unwrap()).unwrap();
+        let chain = vec![leaf.clone(), root];
+        let now = leaf.not_before + 3_600;
+        let offline = |_: &str| -> Result<Vec<u8>, JWTError> { panic!("no --ocsp") };
+        let unknown = |revocation: Revocation, now: i64| {
+            let problems = check(&chain, now, &revocation, &offline).unwrap();
+            let prefix = "the revocation status of `signer.example` is unknown: ";
+            match problems.as_slice() {
+                [problem] => problem.strip_prefix(prefix).unwrap().to_string(),
+                _ => panic!("expected one problem, got {:?}", problems),
+            }
+        };
+        let certificate_error_of =
+            |result: Result<OcspResponse, JWTError>| error_message!(CertificateError, result);
+
+        let crl_der = crate::keys::pem_decode(CRL).unwrap().1;
+        let mut unused_bits = crl_der.clone();
+        let at = unused_bits.len() - 257;
+        assert_eq!(unused_bits[at], 0);
+        unused_bits[at] = 1;
+        assert!(matches!(
+            Crl::from_der(&unused_bits),
+            Err(JWTError::CertificateError(e)) if e == "signature has unused bits"
+        ));
+        let with_crl = |edit: &dyn Fn(&mut Crl)| {
+            let mut crl = Crl::from_der(&crl_der).unwrap();
+            edit(&mut crl);
+            Revocation {
+                crls: vec![crl],
+                ..Revocation::default()
+            }
+        };
+        assert_eq!(
+            unknown(with_crl(&|crl| crl.issuer.clear()), now),
+            "no CRL from its issuer"
+        );
+        assert_eq!(
+            unknown(with_crl(&|crl| crl.signature[0] ^= 1), now),
+            "the CRL is not signed by `Test Root`"
+        );
+        assert_eq!(
+            unknown(Revocation::default(), now),
+            "no CRL or OCSP response was given"
+        );
+
+        let good = base64::decode(GOOD).unwrap();
+        assert_eq!(
+            certificate_error_of(OcspResponse::from_der(&[0x30, 0x03, 0x0a, 0x01, 0x01])),
+            "the OCSP responder answered with status 1"
+        );
+        let edited = |find: &[u8], offset: usize, byte: u8| {
+            let mut der = good.clone();
+            let at = der.windows(find.len()).position(|w| w == find).unwrap();
+            der[at + offset] = byte;
+            OcspResponse::from_der(&der)
+        };
+        let basic = [0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+        assert_eq!(
+            certificate_error_of(edited(&basic, 8, 0x02)),
+            "not a basic OCSP response"
+        );
+        assert_eq!(
+            certificate_error_of(edited(&[0x30, 0x81, 0x90, 0xa1], 3, 0xa3)),
+            "unsupported OCSP responderID"
+        );
+        assert_eq!(
+            certificate_error_of(edited(&[0x12, 0x34, 0x80, 0x00], 2, 0x83)),
+            "unsupported OCSP certStatus"
+        );
+        let with_response = |edit: &dyn Fn(&mut OcspResponse)| {
+            let mut response = OcspResponse::from_der(&good).unwrap();
+            edit(&mut response);
+            Revocation {
+                stapled: vec![response],
+                ..Revocation::default()
+            }
+        };
+        assert_eq!(
+            unknown(with_response(&|r| r.responses[0].serial = vec![1]), now),
+            "no OCSP response for it"
+        );
+        assert_eq!(
+            unknown(
+                with_response(&|r| r.responder = Responder::Name(Vec::new())),
+                now
+            ),
+            "the OCSP response is neither from `Test Root` nor from a responder it authorized"
+        );
+        assert_eq!(
+            unknown(with_response(&|r| r.signature[0] ^= 1), now),
+            "the OCSP response signature does not verify with the key of `Test Root`"
+        );
+        assert_eq!(
+            unknown(with_response(&|_| ()), 5_000_000_000),
+            "the OCSP response is out of date since 2126-09-20T12:54:36Z"
+        );
+        assert_eq!(
+            unknown(
+                Revocation {
+                    query_responders: true,
+                    ..Revocation::default()
+                },
+                now
+            ),
+            "it names no OCSP responder"
+        );
+    }
+}
@@ -0,0 +1,372 @@
+//! `--routes`: a dry run of claim-based routing, the way an API gateway
+//! picks a backend from a token.
+//!
+//! The config is YAML with a `routes` list. Each route has a `name`, an
+//! optional `route` (the backend, shown as is) and a `match` mapping from
+//! claim paths to conditions; an empty `match` always holds. Routes are
+//! tried in order and the first whose conditions all hold wins.
+//!
+//! ```yaml
+//! routes:
+//!   - name: admin
+//!     match:
+//!       iss: https://idp.example.com
+//!       realm_access.roles: admin
+//!       scope: {contains: write}
+//!     route: http://admin-api
+//!   - name: default
+//!     route: http://public-api
+//! ```
+//!
+//! A scalar condition holds when the claim equals it, or when the claim is
+//! an array holding it. A list holds when any of its values would. A
+//! mapping names one test: `prefix`, `suffix`, `contains` (an array member,
+//! or a word of a space-separated string such as `scope`) or `present`.
+//! Claim paths are dot paths into the payload, or into the header when
+//! prefixed with `header.`, as with `--claim`.
+
+use crate::{claim, JWTError, JWToken};
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Test {
+    Equals(Value),
+    AnyOf(Vec<Value>),
+    Prefix(String),
+    Suffix(String),
+    Contains(Value),
+    Present(bool),
+}
+
+impl Test {
+    fn parse(route: &str, path: &str, value: &Value) -> Result<Test, JWTError> {
+        let invalid = |what: &str| {
+            JWTError::ConfigError(format!("route `{}`, claim `{}`: {}", route, path, what).into())
+        };
+        let object = match value {
+            Value::Array(items) => return Ok(Test::AnyOf(items.clone())),
+            Value::Object(object) => object,
+            other => return Ok(Test::Equals(other.clone())),
+        };
+        let (name, operand) = match object.iter().next() {
+            Some(only) if object.len() == 1 => only,
+            _ => return Err(invalid("a condition mapping takes exactly one test")),
+        };
+        let text = || {
+            operand
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| invalid(&format!("`{}` takes a string", name)))
+        };
+        match name.as_str() {
+            "prefix" => Ok(Test::Prefix(text()?)),
+            "suffix" => Ok(Test::Suffix(text()?)),
+            "contains" => Ok(Test::Contains(operand.clone())),
+            "present" => operand
+                .as_bool()
+                .map(Test::Present)
+                .ok_or_else(|| invalid("`present` takes true or false")),
+            other => Err(invalid(&format!(
+                "unknown test `{}`; expected prefix, suffix, contains or present",
+                other
+            ))),
+        }
+    }
+
+    fn holds(&self, actual: Option<&Value>) -> bool {
+        let equals = |expected: &Value, actual: &Value| match actual {
+            Value::Array(items) => items.contains(expected),
+            other => other == expected,
+        };
+        match (self, actual) {
+            (Test::Present(present), actual) => actual.is_some() == *present,
+            (_, None) => false,
+            (Test::Equals(expected), Some(actual)) => equals(expected, actual),
+            (Test::AnyOf(options), Some(actual)) => options.iter().any(|o| equals(o, actual)),
+            (Test::Prefix(prefix), Some(actual)) => actual
+                .as_str()
+                .is_some_and(|s| s.starts_with(prefix.as_str())),
+            (Test::Suffix(suffix), Some(actual)) => actual
+                .as_str()
+                .is_some_and(|s| s.ends_with(suffix.as_str())),
+            (Test::Contains(needle), Some(Value::Array(items))) => items.contains(needle),
+            (Test::Contains(Value::String(needle)), Some(Value::String(text))) => {
+                text.split(' ').any(|word| word == needle)
+            }
+            (Test::Contains(_), Some(_)) => false,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Test::Equals(value) => format!("is {}", value),
+            Test::AnyOf(options) => format!(
+                "is one of {}",
+                options
+                    .iter()
+                    .map(Value::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Test::Prefix(prefix) => format!("starts with {:?}", prefix),
+            Test::Suffix(suffix) => format!("ends with {:?}", suffix),
+            Test::Contains(needle) => format!("contains {}", needle),
+            Test::Present(true) => "is present".to_string(),
+            Test::Present(false) => "is absent".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub name: String,
+    pub target: Option<String>,
+    pub conditions: Vec<(String, Test)>,
+}
+
+/// The routes in a parsed config, in order.
+pub fn parse_routes(config: &Value) -> Result<Vec<Route>, JWTError> {
+    let routes = config
+        .get("routes")
+        .and_then(Value::as_array)
+        .ok_or_else(|| JWTError::ConfigError("routing config needs a `routes` list".into()))?;
+    routes
+        .iter()
+        .enumerate()
+        .map(|(index, route)| {
+            let name = route
+                .get("name")
+                .and_then(Value::as_str)
+                .map_or_else(|| format!("#{}", index + 1), str::to_string);
+            let conditions = match route.get("match") {
+                None | Some(Value::Null) => Vec::new(),
+                Some(Value::Object(conditions)) => conditions
+                    .iter()
+                    .map(|(path, value)| Ok((path.clone(), Test::parse(&name, path, value)?)))
+                    .collect::<Result<_, JWTError>>()?,
+                Some(_) => {
+                    return Err(JWTError::ConfigError(
+                        format!(
+                            "route `{}`: `match` must be a mapping of claims to conditions",
+                            name
+                        )
+                        .into(),
+                    ))
+                }
+            };
+            let target = route.get("route").map(|target| match target {
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            });
+            Ok(Route {
+                name,
+                target,
+                conditions,
+            })
+        })
+        .collect()
+}
+
+/// One condition of a route against the token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub claim: String,
+    pub condition: String,
+    pub actual: Option<Value>,
+    pub holds: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub name: String,
+    pub target: Option<String>,
+    pub checks: Vec<Check>,
+}
+
+impl Outcome {
+    pub fn matches(&self) -> bool {
+        self.checks.iter().all(|check| check.holds)
+    }
+}
+
+/// Every route against `token`, in order; the selected route is the first
+/// outcome that [`Outcome::matches`].
+pub fn evaluate(routes: &[Route], token: &JWToken) -> Vec<Outcome> {
+    routes
+        .iter()
+        .map(|route| Outcome {
+            name: route.name.clone(),
+            target: route.target.clone(),
+            checks: route
+                .conditions
+                .iter()
+                .map(|(path, test)| {
+                    let actual = claim(token, path);
+                    Check {
+                        claim: path.clone(),
+                        condition: test.describe(),
+                        holds: test.holds(actual),
+                        actual: actual.cloned(),
+                    }
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// The outcomes as text: the selected route, then why each route did or
+/// did not match.
+pub fn render_text(outcomes: &[Outcome]) -> String {
+    let selected = outcomes.iter().position(Outcome::matches);
+    let heading = |outcome: &Outcome| match &outcome.target {
+        Some(target) => format!("{} → {}", outcome.name, target),
+        None => outcome.name.clone(),
+    };
+    let mut out = match selected.and_then(|index| outcomes.get(index)) {
+        Some(outcome) => format!("route: {}\n", heading(outcome)),
+        None => "route: none matches\n".to_string(),
+    };
+    for (index, outcome) in outcomes.iter().enumerate() {
+        let verdict = match selected {
+            Some(chosen) if chosen == index => "selected",
+            Some(chosen) if chosen < index && outcome.matches() => "also matches, but comes later",
+            _ if outcome.matches() => "matches",
+            _ => "no match",
+        };
+        out.push_str(&format!(
+            "\n{}. {}: {}",
+            index + 1,
+            heading(outcome),
+            verdict
+        ));
+        if outcome.checks.is_empty() {
+            out.push_str("\n   (no conditions)");
+        }
+        for check in &outcome.checks {
+            let actual = match &check.actual {
+                Some(value) => format!("token has {}", value),
+                None => "not in token".to_string(),
+            };
+            out.push_str(&format!(
+                "\n   {} {} {} ({})",
+                if check.holds { "pass" } else { "FAIL" },
+                check.claim,
+                check.condition,
+                actual
+            ));
+        }
+    }
+    out
+}
+
+/// The outcomes as JSON: the selected route's name, or null, and each
+/// route's checks.
+pub fn to_json(outcomes: &[Outcome]) -> Value {
+    let selected = outcomes.iter().find(|outcome| outcome.matches());
+    serde_json::json!({
+        "route": selected.map(|outcome| &outcome.name),
+        "target": selected.and_then(|outcome| outcome.target.as_ref()),
+        "routes": outcomes.iter().map(|outcome| serde_json::json!({
+            "name": outcome.name,
+            "target": outcome.target,
+            "matches": outcome.matches(),
+            "checks": outcome.checks.iter().map(|check| serde_json::json!({
+                "claim": check.claim,
+                "condition": check.condition,
+                "actual": check.actual,
+                "holds": check.holds,
+            })).collect::<Vec<_>>(),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn routes_first_match_test() {
+        let config = crate::yaml::parse(
+            "routes:\n\
+             \x20 - name: legacy\n\
+             \x20   match:\n\
+             \x20     iss: https://old-idp\n\
+             \x20   route: http://legacy\n\
+             \x20 - name: admin\n\
+             \x20   match:\n\
+             \x20     iss: [https://idp, https://idp2]\n\
+             \x20     realm_access.roles: admin\n\
+             \x20     scope: {contains: write}\n\
+             \x20     header.kid: {prefix: prod-}\n\
+             \x20     azp: {present: false}\n\
+             \x20   route: http://admin\n\
+             \x20 - name: default\n\
+             \x20   route: http://public\n",
+        )
+        .unwrap();
+        let routes = parse_routes(&config).unwrap();
+        let token = JWToken {
+            header: json!({"alg": "RS256", "kid": "prod-1"}),
+            payload: json!({"iss": "https://idp", "scope": "read write",
+                            "realm_access": {"roles": ["user", "admin"]}}),
+            signature: Vec::new(),
+        };
+        let outcomes = evaluate(&routes, &token);
+        assert_eq!(
+            outcomes.iter().map(Outcome::matches).collect::<Vec<_>>(),
+            vec![false, true, true]
+        );
+        let text = render_text(&outcomes);
+        assert!(text.starts_with("route: admin → http://admin\n"));
+        assert!(text.contains(
+            "1. legacy → http://legacy: no match\n   \
+             FAIL iss is \"https://old-idp\" (token has \"https://idp\")"
+        ));
+        assert!(text.contains("   pass azp is absent (not in token)"));
+        assert!(text.ends_with(
+            "3. default → http://public: also matches, but comes later\n   (no conditions)"
+        ));
+        assert_eq!(to_json(&outcomes)["route"], "admin");
+
+        let junior = JWToken {
+            payload: json!({"iss": "https://idp", "scope": "read"}),
+            ..token
+        };
+        assert_eq!(to_json(&evaluate(&routes, &junior))["route"], "default");
+
+        let bad = crate::yaml::parse("routes:\n  - match:\n      sub: {regex: x}\n").unwrap();
+        assert!(matches!(parse_routes(&bad), Err(JWTError::ConfigError(e)) if e.contains("#1")));
+    }
+
+    #[test]
+    fn rejects_bad_routes_test() {
+        let error_of = |config: Value| error_message!(ConfigError, parse_routes(&config));
+        let route = |conditions: Value| json!({"routes": [{"name": "admin", "match": conditions}]});
+        assert_eq!(error_of(json!({})), "routing config needs a `routes` list");
+        assert_eq!(
+            error_of(json!({"routes": [{"name": "admin", "match": "iss"}]})),
+            "route `admin`: `match` must be a mapping of claims to conditions"
+        );
+        assert_eq!(
+            error_of(route(json!({"sub": {"prefix": "a", "suffix": "b"}}))),
+            "route `admin`, claim `sub`: a condition mapping takes exactly one test"
+        );
+        assert_eq!(
+            error_of(route(json!({"sub": {}}))),
+            "route `admin`, claim `sub`: a condition mapping takes exactly one test"
+        );
+        assert_eq!(
+            error_of(route(json!({"sub": {"suffix": 7}}))),
+            "route `admin`, claim `sub`: `suffix` takes a string"
+        );
+        assert_eq!(
+            error_of(route(json!({"azp": {"present": "no"}}))),
+            "route `admin`, claim `azp`: `present` takes true or false"
+        );
+        assert_eq!(
+            error_of(route(json!({"sub": {"regex": "^a"}}))),
+            "route `admin`, claim `sub`: unknown test `regex`; expected prefix, suffix, contains or present"
+        );
+    }
+}
@@ -0,0 +1,531 @@
+//! Self-confinement for runs that handle untrusted tokens.
+//!
+//! `--sandbox` makes the filesystem read-only for the rest of the process
+//! with Landlock, keeping only the `--cache` and history directories, the
+//! `--record` directory and the local output files writable, and sets
+//! `no_new_privs`. `--offline` installs a seccomp filter that fails every
+//! attempt to open a socket. Both fail closed: if the kernel cannot apply
+//! them, the run stops instead of continuing unconfined.
+//!
+//! The daemon and `serve` drop root for the user named by `--user` once
+//! their socket is bound. `serve` then applies its own `--sandbox` and
+//! `--offline`, before it starts any thread, so every connection thread is
+//! confined too.
+
+use crate::{ErrorMessage, JWTError};
+use std::path::PathBuf;
+
+fn sandbox_error(message: impl Into<ErrorMessage>) -> JWTError {
+    JWTError::InvalidArgumentError(message.into())
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::sandbox_error;
+    use crate::{ErrorMessage, JWTError};
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+
+    const SYS_LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+    const SYS_LANDLOCK_ADD_RULE: libc::c_long = 445;
+    const SYS_LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+    const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1;
+    const LANDLOCK_RULE_PATH_BENEATH: libc::c_uint = 1;
+
+    const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    /// Every filesystem right in Landlock ABI 1.
+    const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+    const ACCESS_FS_REFER: u64 = 1 << 13;
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: libc::c_int,
+    }
+
+    fn os_error(what: &str) -> JWTError {
+        let e = std::io::Error::last_os_error();
+        sandbox_error(ErrorMessage::caused_by(format!("{}: {}", what, e), e))
+    }
+
+    fn no_new_privs() -> Result<(), JWTError> {
+        // SAFETY: prctl with integer arguments only
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(os_error("cannot set no_new_privs"));
+        }
+        Ok(())
+    }
+
+    fn add_rule(ruleset: libc::c_int, path: &Path, allowed_access: u64) -> Result<(), JWTError> {
+        let name = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| sandbox_error(format!("{}: path contains NUL", path.display())))?;
+        // SAFETY: `name` is a valid NUL-terminated string
+        let fd = unsafe { libc::open(name.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(os_error(&format!("cannot open {}", path.display())));
+        }
+        let rule = PathBeneathAttr {
+            allowed_access,
+            parent_fd: fd,
+        };
+        // SAFETY: `rule` outlives the call and matches the kernel's layout
+        let added = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_ADD_RULE,
+                ruleset,
+                LANDLOCK_RULE_PATH_BENEATH,
+                &rule as *const PathBeneathAttr,
+                0,
+            )
+        };
+        // SAFETY: `fd` was opened above and is not used afterwards
+        unsafe { libc::close(fd) };
+        if added != 0 {
+            return Err(os_error(&format!("cannot allow {}", path.display())));
+        }
+        Ok(())
+    }
+
+    pub fn read_only_filesystem(writable: &[PathBuf]) -> Result<(), JWTError> {
+        // SAFETY: the version query takes no pointers
+        let abi = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 1 {
+            return Err(sandbox_error(
+                "--sandbox needs Landlock (Linux 5.13 or later, with the landlock LSM enabled)"
+                    .to_string(),
+            ));
+        }
+        let mut handled = ACCESS_FS_V1;
+        if abi >= 2 {
+            handled |= ACCESS_FS_REFER;
+        }
+        if abi >= 3 {
+            handled |= ACCESS_FS_TRUNCATE;
+        }
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        // SAFETY: `attr` outlives the call and its size is passed alongside
+        let ruleset = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        } as libc::c_int;
+        if ruleset < 0 {
+            return Err(os_error("cannot create a Landlock ruleset"));
+        }
+        let rules = std::iter::once(add_rule(
+            ruleset,
+            Path::new("/"),
+            ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR,
+        ))
+        .chain(writable.iter().map(|path| {
+            // a rule on a file may only grant the rights that apply to files
+            let allowed = if path.is_dir() {
+                handled & !ACCESS_FS_EXECUTE
+            } else {
+                handled & (ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE)
+            };
+            add_rule(ruleset, path, allowed)
+        }))
+        .collect::<Result<Vec<()>, JWTError>>()
+        .and_then(|_| no_new_privs());
+        // SAFETY: `ruleset` is a descriptor created above
+        let restricted = rules.and_then(|_| {
+            if unsafe { libc::syscall(SYS_LANDLOCK_RESTRICT_SELF, ruleset, 0) } != 0 {
+                Err(os_error("cannot enforce the Landlock ruleset"))
+            } else {
+                Ok(())
+            }
+        });
+        // SAFETY: closing the descriptor created above
+        unsafe { libc::close(ruleset) };
+        restricted
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    const BPF_LD_W_ABS: u16 = 0x20;
+    const BPF_JEQ_K: u16 = 0x15;
+    const BPF_JGE_K: u16 = 0x35;
+    const BPF_RET_K: u16 = 0x06;
+    const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+    const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+    /// Offsets of `nr` and `arch` in `struct seccomp_data`.
+    const DATA_NR: u32 = 0;
+    const DATA_ARCH: u32 = 4;
+    /// Set in `nr` by x32 syscalls, which share x86_64's `arch`.
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    fn statement(code: u16, k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    /// A seccomp program that kills the process on a foreign syscall ABI,
+    /// fails x32 syscalls and each of `denied` with `EPERM`, and allows
+    /// everything else. x32 numbers have to be refused outright: they pass
+    /// the `arch` check, and `socket` among them would slip past `denied`.
+    pub fn deny_filter(arch: u32, denied: &[u32]) -> Vec<libc::sock_filter> {
+        let n = denied.len();
+        let mut program = vec![
+            statement(BPF_LD_W_ABS, DATA_ARCH),
+            libc::sock_filter {
+                code: BPF_JEQ_K,
+                jt: 1,
+                jf: 0,
+                k: arch,
+            },
+            statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+            statement(BPF_LD_W_ABS, DATA_NR),
+            libc::sock_filter {
+                code: BPF_JGE_K,
+                jt: (n + 1) as u8,
+                jf: 0,
+                k: X32_SYSCALL_BIT,
+            },
+        ];
+        for (i, nr) in denied.iter().enumerate() {
+            program.push(libc::sock_filter {
+                code: BPF_JEQ_K,
+                // past the remaining comparisons and the allow, to the EPERM
+                jt: (n - i) as u8,
+                jf: 0,
+                k: *nr,
+            });
+        }
+        program.push(statement(BPF_RET_K, SECCOMP_RET_ALLOW));
+        program.push(statement(
+            BPF_RET_K,
+            libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+        ));
+        program
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub fn no_network() -> Result<(), JWTError> {
+        let mut program = deny_filter(
+            AUDIT_ARCH,
+            &[libc::SYS_socket as u32, libc::SYS_io_uring_setup as u32],
+        );
+        let fprog = libc::sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_mut_ptr(),
+        };
+        no_new_privs()?;
+        // SAFETY: `fprog` points into `program`, which outlives the call
+        let installed = unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &fprog as *const libc::sock_fprog,
+                0,
+                0,
+            )
+        };
+        if installed != 0 {
+            return Err(os_error("cannot install the seccomp filter"));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn no_network() -> Result<(), JWTError> {
+        Err(sandbox_error(
+            "--offline enforcement is only built for x86_64 and aarch64".to_string(),
+        ))
+    }
+
+    /// Drops root for `user`, handing it `socket` first, if there is one, so
+    /// it can still be removed and replaced later.
+    pub fn drop_to(user: &str, socket: Option<&Path>) -> Result<(), JWTError> {
+        let name =
+            CString::new(user).map_err(|_| sandbox_error("--user contains NUL".to_string()))?;
+        // SAFETY: `name` is NUL-terminated; the entry is copied out at once
+        let (uid, gid) = unsafe {
+            let entry = libc::getpwnam(name.as_ptr());
+            if entry.is_null() {
+                return Err(sandbox_error(format!("no such user `{}`", user)));
+            }
+            ((*entry).pw_uid, (*entry).pw_gid)
+        };
+        if uid == 0 {
+            return Err(sandbox_error(format!(
+                "--user `{}` is root; pick an unprivileged account",
+                user
+            )));
+        }
+        if let Some(socket) = socket {
+            let path = CString::new(socket.as_os_str().as_bytes())
+                .map_err(|_| sandbox_error("socket path contains NUL".to_string()))?;
+            // SAFETY: `path` is NUL-terminated
+            if unsafe { libc::chown(path.as_ptr(), uid, gid) } != 0 {
+                return Err(os_error("cannot hand the socket to --user"));
+            }
+        }
+        // SAFETY: plain syscalls on values checked above
+        unsafe {
+            // supplementary groups first, then the group, then the user
+            if libc::setgroups(0, std::ptr::null()) != 0
+                || libc::setgid(gid) != 0
+                || libc::setuid(uid) != 0
+            {
+                return Err(os_error("cannot drop privileges"));
+            }
+            if libc::setuid(0) == 0 {
+                return Err(sandbox_error("regained root after dropping it".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_root() -> bool {
+        // SAFETY: geteuid cannot fail
+        unsafe { libc::geteuid() == 0 }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// Just enough of a classic BPF interpreter to run `deny_filter`.
+        fn run(program: &[libc::sock_filter], arch: u32, nr: u32) -> u32 {
+            let mut pc = 0;
+            let mut accumulator = 0;
+            loop {
+                let op = program[pc];
+                match op.code {
+                    BPF_LD_W_ABS => {
+                        accumulator = if op.k == DATA_ARCH { arch } else { nr };
+                        pc += 1;
+                    }
+                    BPF_JEQ_K => {
+                        let skip = if accumulator == op.k { op.jt } else { op.jf };
+                        pc += 1 + skip as usize;
+                    }
+                    BPF_JGE_K => {
+                        let skip = if accumulator >= op.k { op.jt } else { op.jf };
+                        pc += 1 + skip as usize;
+                    }
+                    BPF_RET_K => return op.k,
+                    other => panic!("unexpected opcode {:#x}", other),
+                }
+            }
+        }
+
+        #[test]
+        fn deny_filter_test() {
+            let program = deny_filter(0xc000_003e, &[41, 425]);
+            let eperm = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+            assert_eq!(run(&program, 0xc000_003e, 41), eperm);
+            assert_eq!(run(&program, 0xc000_003e, 425), eperm);
+            assert_eq!(run(&program, 0xc000_003e, 0), SECCOMP_RET_ALLOW);
+            assert_eq!(run(&program, 0x4000_0003, 41), SECCOMP_RET_KILL_PROCESS);
+            // x32 socket and x32 anything else
+            assert_eq!(run(&program, 0xc000_003e, X32_SYSCALL_BIT | 41), eperm);
+            assert_eq!(run(&program, 0xc000_003e, X32_SYSCALL_BIT), eperm);
+            assert_eq!(
+                run(&deny_filter(0xc000_003e, &[]), 0xc000_003e, X32_SYSCALL_BIT),
+                eperm
+            );
+        }
+
+        #[test]
+        fn serve_confinement_test() {
+            use std::net::{TcpListener, TcpStream};
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            // seccomp and Landlock bind the calling thread and the threads it
+            // starts, so the rest of the test binary stays unconfined
+            let confined = std::thread::spawn(move || {
+                let result = crate::sandbox::serve_confinement(None, false, true);
+                if is_root() {
+                    assert!(matches!(
+                        result,
+                        Err(JWTError::InvalidArgumentError(e))
+                            if e == "refusing to run serve as root; pass --user to drop privileges"
+                    ));
+                    crate::sandbox::confine(false, true, &[]).unwrap();
+                } else {
+                    result.unwrap();
+                }
+                // the bound port still takes connections; new sockets are refused
+                let (_stream, _) = listener.accept().unwrap();
+                let denied = TcpStream::connect(address).unwrap_err();
+                assert_eq!(denied.raw_os_error(), Some(libc::EPERM));
+                let worker = std::thread::spawn(move || TcpStream::connect(address).is_err());
+                assert!(worker.join().unwrap());
+            });
+            let _client = TcpStream::connect(address).unwrap();
+            confined.join().unwrap();
+
+            let sandboxed =
+                std::thread::spawn(|| match crate::sandbox::confine(true, false, &[]) {
+                    Err(JWTError::InvalidArgumentError(e)) if e.contains("needs Landlock") => {}
+                    result => {
+                        result.unwrap();
+                        let path = std::env::temp_dir().join("jwt-check-serve-sandbox");
+                        assert!(std::fs::write(path, "x").is_err());
+                        assert!(std::fs::read_to_string("/proc/self/status").is_ok());
+                    }
+                });
+            sandboxed.join().unwrap();
+        }
+
+        #[test]
+        fn refuses_before_confining_test() {
+            assert!(matches!(
+                add_rule(-1, Path::new("/tmp/a\0b"), 0),
+                Err(JWTError::InvalidArgumentError(e)) if e == "/tmp/a\0b: path contains NUL"
+            ));
+            let missing = Path::new("/nonexistent/jwt-check");
+            assert!(matches!(
+                add_rule(-1, missing, 0),
+                Err(JWTError::InvalidArgumentError(e))
+                    if e.starts_with("cannot open /nonexistent/jwt-check: ")
+            ));
+            // not a ruleset, so the rule can't be added
+            assert!(matches!(
+                add_rule(-1, Path::new("/"), 0),
+                Err(JWTError::InvalidArgumentError(e)) if e.starts_with("cannot allow /: ")
+            ));
+
+            // each of these is refused before any privilege changes
+            assert!(matches!(
+                drop_to("a\0b", None),
+                Err(JWTError::InvalidArgumentError(e)) if e == "--user contains NUL"
+            ));
+            assert!(matches!(
+                drop_to("jwt-check-no-such-user", None),
+                Err(JWTError::InvalidArgumentError(e))
+                    if e == "no such user `jwt-check-no-such-user`"
+            ));
+            assert!(matches!(
+                drop_to("root", None),
+                Err(JWTError::InvalidArgumentError(e))
+                    if e == "--user `root` is root; pick an unprivileged account"
+            ));
+            match drop_to("nobody", Some(Path::new("/tmp/a\0b"))) {
+                Err(JWTError::InvalidArgumentError(e)) if e.starts_with("no such user") => {}
+                result => assert!(matches!(
+                    result,
+                    Err(JWTError::InvalidArgumentError(e)) if e == "socket path contains NUL"
+                )),
+            }
+            if !is_root() {
+                assert!(matches!(
+                    crate::sandbox::serve_confinement(
+                        Some("nobody"),
+                        false,
+                        false
+                    ),
+                    Err(JWTError::InvalidArgumentError(e))
+                        if e == "--user only applies when serve is started as root"
+                ));
+            }
+        }
+    }
+}
+
+/// Applies `--sandbox` and `--offline` to the rest of this process.
+/// `writable` lists directories and existing files that stay writable under
+/// `--sandbox`.
+pub fn confine(sandbox: bool, offline: bool, writable: &[PathBuf]) -> Result<(), JWTError> {
+    #[cfg(target_os = "linux")]
+    {
+        if sandbox {
+            linux::read_only_filesystem(writable)?;
+        }
+        if offline {
+            linux::no_network()?;
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (offline, writable);
+        if sandbox {
+            return Err(sandbox_error(
+                "--sandbox is only available on Linux".to_string(),
+            ));
+        }
+        // --offline still refuses every network option; there is just no
+        // kernel filter behind it here
+        Ok(())
+    }
+}
+
+/// Drops root for `user` once `what` has bound its socket; `socket` is a
+/// filesystem socket to hand over first.
+fn drop_privileges(
+    what: &str,
+    user: Option<&str>,
+    socket: Option<&std::path::Path>,
+) -> Result<(), JWTError> {
+    #[cfg(target_os = "linux")]
+    {
+        match (linux::is_root(), user) {
+            (true, Some(user)) => linux::drop_to(user, socket),
+            (true, None) => Err(sandbox_error(format!(
+                "refusing to run {} as root; pass --user to drop privileges",
+                what
+            ))),
+            (false, Some(_)) => Err(sandbox_error(format!(
+                "--user only applies when {} is started as root",
+                what
+            ))),
+            (false, None) => Ok(()),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (what, socket);
+        match user {
+            Some(_) => Err(sandbox_error(
+                "--user is only available on Linux".to_string(),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Lets the daemon run as root only long enough to bind its socket.
+#[cfg(all(unix, feature = "net"))]
+pub fn daemon_privileges(user: Option<&str>, socket: &std::path::Path) -> Result<(), JWTError> {
+    drop_privileges("the daemon", user, Some(socket))
+}
+
+/// Confines `serve` once its port is bound: root is dropped for `user`,
+/// then `--sandbox` and `--offline` apply as for a single run. Called
+/// before any thread starts, so all of them inherit it.
+pub fn serve_confinement(user: Option<&str>, sandbox: bool, offline: bool) -> Result<(), JWTError> {
+    drop_privileges("serve", user, None)?;
+    confine(sandbox, offline, &[])
+}
@@ -0,0 +1,149 @@
+//! `--sarif`: audit findings as a SARIF 2.1.0 log, for code scanning
+//! dashboards that track findings across runs.
+//!
+//! Every audit rule is listed in the driver with its stable id, so a
+//! dashboard sees a rule disappear as fixed rather than as renamed. Levels
+//! follow the severity (`high` is `error`, `medium` `warning`, `low`
+//! `note`), and `security-severity` puts them on the CVSS-like scale that
+//! GitHub code scanning sorts by.
+
+use crate::audit::{Finding, Rule, Severity};
+use serde_json::{json, Value};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Where the findings for one token came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    /// The file the token was read from, if any.
+    pub file: Option<String>,
+    /// Where in it: `token`, or the connection and header of a capture.
+    pub place: String,
+}
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::High => "error",
+        Severity::Medium => "warning",
+        Severity::Low => "note",
+    }
+}
+
+fn security_severity(severity: Severity) -> &'static str {
+    match severity {
+        Severity::High => "8.0",
+        Severity::Medium => "5.0",
+        Severity::Low => "2.0",
+    }
+}
+
+fn rule_index(rule: Rule) -> usize {
+    Rule::ALL
+        .iter()
+        .position(|r| *r == rule)
+        .unwrap_or_default()
+}
+
+fn result(finding: &Finding, location: &Location) -> Value {
+    let text = match finding.reference {
+        Some(reference) => format!("{} ({})", finding.message, reference),
+        None => finding.message.clone(),
+    };
+    let mut place = json!({
+        "logicalLocations": [{"fullyQualifiedName": location.place, "kind": "object"}],
+    });
+    if let (Some(file), Some(object)) = (&location.file, place.as_object_mut()) {
+        object.insert(
+            "physicalLocation".to_string(),
+            json!({
+                "artifactLocation": {"uri": file},
+                "region": {"startLine": 1},
+            }),
+        );
+    }
+    json!({
+        "ruleId": finding.rule.id(),
+        "ruleIndex": rule_index(finding.rule),
+        "level": level(finding.severity),
+        "message": {"text": text},
+        "locations": [place],
+    })
+}
+
+/// The log for `findings`, each with where its token came from.
+pub fn log(findings: &[(Location, Vec<Finding>)]) -> Value {
+    let rules: Vec<Value> = Rule::ALL
+        .iter()
+        .map(|rule| {
+            json!({
+                "id": rule.id(),
+                "shortDescription": {"text": rule.summary()},
+                "defaultConfiguration": {"level": level(rule.severity())},
+                "properties": {
+                    "security-severity": security_severity(rule.severity()),
+                    "tags": ["security"],
+                },
+            })
+        })
+        .collect();
+    let results: Vec<Value> = findings
+        .iter()
+        .flat_map(|(location, findings)| findings.iter().map(move |f| result(f, location)))
+        .collect();
+    json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {"driver": {
+                "name": "jwt-check",
+                "version": env!("CARGO_PKG_VERSION"),
+                "informationUri": "https://github.com/tek-shinobi/jwt-check",
+                "rules": rules,
+            }},
+            "results": results,
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::{audit, AuditOptions};
+    use crate::{parse, SAMPLE_TOKEN};
+
+    #[test]
+    fn log_test() {
+        let token = parse(SAMPLE_TOKEN).unwrap();
+        let options = AuditOptions {
+            secret: None,
+            expect_asymmetric: false,
+        };
+        let findings = audit(SAMPLE_TOKEN, &token, &token.payload, 0, &options);
+        let location = Location {
+            file: Some("tokens/sample.jwt".to_string()),
+            place: "token".to_string(),
+        };
+        let log = log(&[(location, findings)]);
+        let rules = log.pointer("/runs/0/tool/driver/rules").unwrap();
+        assert_eq!(rules.as_array().unwrap().len(), Rule::ALL.len());
+        let first = log.pointer("/runs/0/results/0").unwrap();
+        assert_eq!(first["ruleId"], "well-known-secret");
+        assert_eq!(first["level"], "error");
+        let index = first["ruleIndex"].as_u64().unwrap() as usize;
+        assert_eq!(rules[index]["id"], "well-known-secret");
+        assert_eq!(
+            first.pointer("/locations/0/physicalLocation/artifactLocation/uri"),
+            Some(&json!("tokens/sample.jwt"))
+        );
+        assert_eq!(
+            log.pointer("/runs/0/results/1/level"),
+            Some(&json!("warning"))
+        );
+        let inline = Location {
+            file: None,
+            place: "token".to_string(),
+        };
+        let empty = super::log(&[(inline, Vec::new())]);
+        assert_eq!(empty.pointer("/runs/0/results"), Some(&json!([])));
+    }
+}
@@ -0,0 +1,439 @@
+//! `jwt-check scan`: JWTs in the HTTP/1.x traffic of reassembled TCP
+//! connections, with where in each message they were found.
+//!
+//! Streams that don't start with an HTTP message are searched as raw bytes,
+//! so tokens in other cleartext protocols still turn up.
+
+use crate::pcap::{self, Stream};
+use crate::tls::{self, KeyLog};
+use crate::{jwe, parse, JWTError};
+use serde_json::Value;
+use std::net::SocketAddr;
+
+/// One HTTP message: its start line, headers in order, and decoded body.
+pub struct Message {
+    pub start_line: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Message {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn is_start_line(line: &str) -> bool {
+    if line.starts_with("HTTP/") {
+        return true;
+    }
+    let mut parts = line.split(' ');
+    let method = parts.next().unwrap_or_default();
+    let _target = parts.next();
+    !method.is_empty()
+        && method.bytes().all(|b| b.is_ascii_uppercase())
+        && parts.next().is_some_and(|v| v.starts_with("HTTP/"))
+        && parts.next().is_none()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Splits off one line ending in `\n`, without the line ending.
+fn split_line(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let at = find(data, b"\n")?;
+    let (line, rest) = data.split_at(at);
+    Some((
+        line.strip_suffix(b"\r").unwrap_or(line),
+        rest.get(1..).unwrap_or_default(),
+    ))
+}
+
+/// A chunked body, decoded, and what follows it.
+fn dechunk(mut data: &[u8]) -> (Vec<u8>, &[u8]) {
+    let mut body = Vec::new();
+    while let Some((line, rest)) = split_line(data) {
+        let size = String::from_utf8_lossy(line);
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = match usize::from_str_radix(size, 16) {
+            Ok(size) => size,
+            Err(_) => break,
+        };
+        if size == 0 {
+            // skip trailers up to the blank line
+            data = rest;
+            while let Some((line, rest)) = split_line(data) {
+                data = rest;
+                if line.is_empty() {
+                    break;
+                }
+            }
+            return (body, data);
+        }
+        let chunk = rest.get(..size).unwrap_or(rest);
+        body.extend_from_slice(chunk);
+        data = rest.get(size..).unwrap_or_default();
+        data = data.strip_prefix(b"\r\n").unwrap_or(data);
+    }
+    (body, &[])
+}
+
+/// The HTTP messages in one direction of a connection. Empty when the
+/// stream doesn't start with an HTTP message.
+pub fn messages(mut stream: &[u8]) -> Vec<Message> {
+    let mut out = Vec::new();
+    while let Some((line, mut rest)) = split_line(stream) {
+        let start_line = String::from_utf8_lossy(line).into_owned();
+        if !is_start_line(&start_line) {
+            break;
+        }
+        let mut headers = Vec::new();
+        while let Some((line, next)) = split_line(rest) {
+            rest = next;
+            if line.is_empty() {
+                break;
+            }
+            let line = String::from_utf8_lossy(line);
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+        let mut message = Message {
+            start_line,
+            headers,
+            body: Vec::new(),
+        };
+        let chunked = message
+            .header("transfer-encoding")
+            .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+        let length = message
+            .header("content-length")
+            .and_then(|n| n.parse::<usize>().ok());
+        let (body, next) = if chunked {
+            dechunk(rest)
+        } else if let Some(length) = length {
+            let split = length.min(rest.len());
+            let (body, next) = rest.split_at(split);
+            (body.to_vec(), next)
+        } else if message.start_line.starts_with("HTTP/") && !rest.starts_with(b"HTTP/") {
+            // a response without a length runs until the connection closes
+            (rest.to_vec(), &[][..])
+        } else {
+            (Vec::new(), rest)
+        };
+        message.body = body;
+        out.push(message);
+        stream = next;
+    }
+    out
+}
+
+fn is_base64url(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+/// Compact JWS and JWE tokens in `text`. Candidates start with `eyJ` (an
+/// encoded `{"`) and only count when they parse.
+pub fn find_tokens(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut from = 0;
+    while let Some(at) = text.get(from..).and_then(|rest| rest.find("eyJ")) {
+        let start = from + at;
+        let rest = text.get(start..).unwrap_or_default();
+        let len = rest
+            .find(|c: char| !is_base64url(c) && c != '.')
+            .unwrap_or(rest.len());
+        let candidate = rest.get(..len).unwrap_or_default().trim_end_matches('.');
+        let preceded = text
+            .get(..start)
+            .and_then(|before| before.chars().next_back())
+            .is_some_and(is_base64url);
+        let valid = match candidate.split('.').count() {
+            3 => parse(candidate).is_ok(),
+            5 => jwe::parse(candidate).is_ok(),
+            _ => false,
+        };
+        if valid && !preceded {
+            out.push(candidate);
+            from = start + candidate.len();
+        } else {
+            from = start + 3;
+        }
+    }
+    out
+}
+
+/// A token and where it was found: `header <Name>`, `cookie <name>`,
+/// `set-cookie <name>`, `body`, or `stream` for non-HTTP data.
+pub struct Found {
+    pub location: String,
+    pub token: String,
+}
+
+/// Every token in one message, in header then body order.
+pub fn message_tokens(message: &Message) -> Vec<Found> {
+    let mut found: Vec<Found> = Vec::new();
+    let mut add = |location: String, text: &str| {
+        for token in find_tokens(text) {
+            if !found
+                .iter()
+                .any(|f| f.location == location && f.token == token)
+            {
+                found.push(Found {
+                    location: location.clone(),
+                    token: token.to_string(),
+                });
+            }
+        }
+    };
+    for (name, value) in &message.headers {
+        if name.eq_ignore_ascii_case("cookie") {
+            for pair in value.split(';') {
+                let (cookie, value) = pair.split_once('=').unwrap_or(("", pair));
+                add(format!("cookie {}", cookie.trim()), value);
+            }
+        } else if name.eq_ignore_ascii_case("set-cookie") {
+            let pair = value.split(';').next().unwrap_or_default();
+            let (cookie, value) = pair.split_once('=').unwrap_or(("", pair));
+            add(format!("set-cookie {}", cookie.trim()), value);
+        } else {
+            add(format!("header {}", name), value);
+        }
+    }
+    add("body".to_string(), &String::from_utf8_lossy(&message.body));
+    found
+}
+
+/// A token found in a capture, with the connection and message it was in.
+pub struct Sighting {
+    /// The connection's number in the capture, from 1.
+    pub connection: usize,
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    /// The TLS version of a connection that was decrypted.
+    pub tls: Option<&'static str>,
+    /// The `Host` of the connection's first request that names one.
+    pub host: Option<String>,
+    /// `request` or `response`.
+    pub direction: &'static str,
+    /// The HTTP message's start line; `None` for data that isn't HTTP.
+    pub message: Option<String>,
+    pub found: Found,
+}
+
+impl Sighting {
+    /// Where in the capture the token is, for a SARIF location.
+    pub fn place(&self) -> String {
+        format!(
+            "connection {} {} {}",
+            self.connection, self.direction, self.found.location
+        )
+    }
+
+    /// The sighting as a `scan` record, with the token decoded as far as it
+    /// can be: a JWE only shows its header.
+    pub fn record(&self) -> Value {
+        let (header, payload) = match parse(&self.found.token) {
+            Ok(token) => (token.header, token.payload),
+            Err(_) => (
+                jwe::parse(&self.found.token).map_or(Value::Null, |jwe| jwe.header),
+                Value::Null,
+            ),
+        };
+        serde_json::json!({
+            "connection": self.connection,
+            "client": self.client.to_string(),
+            "server": self.server.to_string(),
+            "tls": self.tls,
+            "host": self.host,
+            "direction": self.direction,
+            "message": self.message,
+            "location": self.found.location,
+            "token": self.found.token,
+            "header": header,
+            "payload": payload,
+        })
+    }
+}
+
+/// What a capture holds: how many TCP connections, and the tokens in them.
+pub struct Capture {
+    pub connections: usize,
+    pub sightings: Vec<Sighting>,
+}
+
+/// Every token in the TCP connections of a pcap or pcapng `file`, TLS ones
+/// decrypted with `keylog`. A connection that can't be read is left out,
+/// and `skipped` told why.
+pub fn capture(
+    file: &[u8],
+    keylog: Option<&KeyLog>,
+    skipped: &mut dyn FnMut(usize, &Stream, &str),
+) -> Result<Capture, JWTError> {
+    let streams = pcap::streams(&pcap::tcp_segments(file)?);
+    let mut sightings = Vec::new();
+    for (index, stream) in streams.iter().enumerate() {
+        let connection = index + 1;
+        let decrypted;
+        let (version, to_server, to_client) = if tls::looks_like_tls(&stream.to_server) {
+            let keylog = match keylog {
+                Some(keylog) => keylog,
+                None => {
+                    skipped(
+                        connection,
+                        stream,
+                        "TLS, and no --keylog or $SSLKEYLOGFILE to decrypt it with",
+                    );
+                    continue;
+                }
+            };
+            decrypted = match tls::decrypt(keylog, &stream.to_server, &stream.to_client) {
+                Ok(decrypted) => decrypted,
+                Err(e) => {
+                    skipped(connection, stream, &format!("TLS not decrypted: {}", e));
+                    continue;
+                }
+            };
+            (
+                Some(decrypted.version),
+                &decrypted.to_server,
+                &decrypted.to_client,
+            )
+        } else {
+            (None, &stream.to_server, &stream.to_client)
+        };
+        if to_server.starts_with(b"PRI * HTTP/2.0") {
+            skipped(connection, stream, "HTTP/2 is not supported");
+            continue;
+        }
+        let host = messages(to_server)
+            .iter()
+            .find_map(|m| m.header("host"))
+            .map(str::to_string);
+        for (direction, data) in [("request", to_server), ("response", to_client)] {
+            let messages = messages(data);
+            let found: Vec<(Option<String>, Found)> = if messages.is_empty() {
+                find_tokens(&String::from_utf8_lossy(data))
+                    .into_iter()
+                    .map(|token| {
+                        let found = Found {
+                            location: "stream".to_string(),
+                            token: token.to_string(),
+                        };
+                        (None, found)
+                    })
+                    .collect()
+            } else {
+                messages
+                    .iter()
+                    .flat_map(|message| {
+                        message_tokens(message)
+                            .into_iter()
+                            .map(move |found| (Some(message.start_line.clone()), found))
+                    })
+                    .collect()
+            };
+            for (message, found) in found {
+                sightings.push(Sighting {
+                    connection,
+                    client: stream.client,
+                    server: stream.server,
+                    tls: version,
+                    host: host.clone(),
+                    direction,
+                    message,
+                    found,
+                });
+            }
+        }
+    }
+    Ok(Capture {
+        connections: streams.len(),
+        sightings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SAMPLE_TOKEN;
+
+    #[test]
+    fn finds_tokens_in_http_messages_test() {
+        let request = format!(
+            "GET /api HTTP/1.1\r\nHost: api.example\r\nAuthorization: Bearer {t}\r\n\
+             Cookie: theme=dark; session={t}\r\n\r\n\
+             POST /refresh HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\n{{\"a\":\r\n{len:x}\r\n\"{t}\"}}\r\n0\r\n\r\n",
+            t = SAMPLE_TOKEN,
+            len = SAMPLE_TOKEN.len() + 3
+        );
+        let messages = messages(request.as_bytes());
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].header("host"), Some("api.example"));
+        let found = message_tokens(&messages[0]);
+        let locations: Vec<&str> = found.iter().map(|f| f.location.as_str()).collect();
+        assert_eq!(locations, vec!["header Authorization", "cookie session"]);
+        assert!(found.iter().all(|f| f.token == SAMPLE_TOKEN));
+        assert_eq!(
+            messages[1].body,
+            format!("{{\"a\":\"{}\"}}", SAMPLE_TOKEN).as_bytes()
+        );
+        assert_eq!(message_tokens(&messages[1])[0].location, "body");
+
+        assert!(super::messages(b"\x16\x03\x01\x00\x05hello").is_empty());
+        // a prefix or a truncated token is not a match
+        assert!(find_tokens("xeyJhbGciOiJIUzI1NiJ9.e30.c2ln eyJhbGciOiJIUzI1NiJ9.e30").is_empty());
+    }
+
+    #[test]
+    fn capture_test() {
+        const SYN: u8 = 0x02;
+        const ACK: u8 = 0x10;
+        let request = format!(
+            "GET /api HTTP/1.1\r\nHost: api.example\r\nAuthorization: Bearer {}\r\n\r\n",
+            SAMPLE_TOKEN
+        );
+        let file = pcap::tests::capture(&[
+            (true, SYN, 99, b""),
+            (false, SYN | ACK, 499, b""),
+            (true, ACK, 100, request.as_bytes()),
+        ]);
+        let mut skipped = Vec::new();
+        let mut skip =
+            |connection: usize, _: &Stream, why: &str| skipped.push((connection, why.to_string()));
+        let found = capture(&file, None, &mut skip).unwrap();
+        assert_eq!(found.connections, 1);
+        assert_eq!(found.sightings.len(), 1);
+        let sighting = &found.sightings[0];
+        assert_eq!(
+            sighting.place(),
+            "connection 1 request header Authorization"
+        );
+        let record = sighting.record();
+        assert_eq!(record["host"], "api.example");
+        assert_eq!(record["message"], "GET /api HTTP/1.1");
+        assert_eq!(record["tls"], Value::Null);
+        assert_eq!(record["payload"]["sub"], "1234567890");
+
+        let file = pcap::tests::capture(&[
+            (true, SYN, 99, b""),
+            (true, ACK, 100, b"\x16\x03\x01\x00\x05hello"),
+        ]);
+        assert!(capture(&file, None, &mut skip)
+            .unwrap()
+            .sightings
+            .is_empty());
+        assert_eq!(
+            skipped,
+            [(
+                1,
+                "TLS, and no --keylog or $SSLKEYLOGFILE to decrypt it with".to_string()
+            )]
+        );
+    }
+}
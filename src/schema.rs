@@ -0,0 +1,461 @@
+//! `jwt-check schema`: JSON Schemas for the JSON this tool prints, so
+//! downstream parsers can validate what they read.
+//!
+//! - `decode` is the `--format json` document, in each `--output-version`;
+//! - `verification` is the body `serve` answers with;
+//! - `audit` is the findings document `--audit --out` delivers;
+//! - `batch` is one `--batch` NDJSON record: a token's, or the closing
+//!   summary.
+//!
+//! Each schema's `$id` names its version, and a version is a promise:
+//! members may be added to it, and audit rules to the `rule` list, but none
+//! is removed, renamed or given another type. That takes a new version,
+//! and the old ones stay printable. Since members may be added, the
+//! schemas allow members they don't list.
+//!
+//! `check` validates a document against one of them. It knows the
+//! keywords these schemas use (`type`, `const`, `enum`, `required`,
+//! `properties`, `items`, `oneOf` and `minimum`), not all of JSON Schema.
+
+use crate::audit::Rule;
+use crate::JWTError;
+use serde_json::{json, Value};
+use std::fmt;
+use std::str::FromStr;
+
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+const BASE: &str = "https://github.com/tek-shinobi/jwt-check/schemas";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    Decode,
+    Verification,
+    Audit,
+    Batch,
+}
+
+/// The names `jwt-check schema` takes.
+pub const KINDS: [(&str, Output); 4] = [
+    ("decode", Output::Decode),
+    ("verification", Output::Verification),
+    ("audit", Output::Audit),
+    ("batch", Output::Batch),
+];
+
+impl FromStr for Output {
+    type Err = JWTError;
+
+    fn from_str(name: &str) -> Result<Output, JWTError> {
+        KINDS
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, output)| *output)
+            .ok_or_else(|| {
+                let names: Vec<&str> = KINDS.iter().map(|(name, _)| *name).collect();
+                JWTError::InvalidArgumentError(
+                    format!(
+                        "`{}` is not an output with a schema; expected {}",
+                        name,
+                        names.join(", ")
+                    )
+                    .into(),
+                )
+            })
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = KINDS
+            .iter()
+            .find(|(_, output)| output == self)
+            .map_or("", |(name, _)| *name);
+        write!(f, "{}", name)
+    }
+}
+
+impl Output {
+    /// The schema versions there are, oldest first. `decode`'s follow
+    /// `--output-version`.
+    pub fn versions(self) -> &'static [u8] {
+        match self {
+            Output::Decode => &[1, 2],
+            _ => &[1],
+        }
+    }
+
+    /// The schema for `version` of this output.
+    pub fn schema(self, version: u8) -> Result<Value, JWTError> {
+        let body = match (self, version) {
+            (Output::Decode, 1) => decode_v1(),
+            (Output::Decode, 2) => decode_v2(),
+            (Output::Verification, 1) => verification_v1(),
+            (Output::Audit, 1) => audit_v1(),
+            (Output::Batch, 1) => batch_v1(),
+            _ => {
+                let known: Vec<String> = self.versions().iter().map(u8::to_string).collect();
+                return Err(JWTError::InvalidArgumentError(
+                    format!(
+                        "the {} schema has no version {}; expected {}",
+                        self,
+                        version,
+                        known.join(" or ")
+                    )
+                    .into(),
+                ));
+            }
+        };
+        let mut schema = json!({
+            "$schema": DIALECT,
+            "$id": format!("{}/{}-v{}.json", BASE, self, version),
+        });
+        if let (Some(schema), Value::Object(body)) = (schema.as_object_mut(), body) {
+            schema.extend(body);
+        }
+        Ok(schema)
+    }
+}
+
+fn decode_v1() -> Value {
+    json!({
+        "title": "jwt-check --format json, output version 1",
+        "description": "A JWE has only header and payload.",
+        "type": "object",
+        "required": ["header", "payload"],
+        "properties": {
+            "header": {"type": "object"},
+            "payload": {
+                "description": "The claims; for a JWE, the plaintext, or null when it was not decrypted.",
+                "type": ["object", "string", "null"],
+            },
+            "signature": {
+                "description": "base64url, or hex with --signature-encoding hex.",
+                "type": "string",
+            },
+        },
+    })
+}
+
+fn decode_v2() -> Value {
+    json!({
+        "title": "jwt-check --format json, output version 2",
+        "description": "A JWE has only header and payload.",
+        "type": "object",
+        "required": ["header", "payload"],
+        "properties": {
+            "output_version": {"const": 2},
+            "header": {"type": "object"},
+            "payload": {
+                "description": "The claims; for a JWE, the plaintext, or null when it was not decrypted.",
+                "type": ["object", "string", "null"],
+            },
+            "signature": {
+                "type": "object",
+                "required": ["encoding", "value"],
+                "properties": {
+                    "encoding": {"enum": ["base64url", "hex"]},
+                    "value": {"type": "string"},
+                },
+            },
+            "freshness": {"enum": ["valid", "expiring-soon", "expired", "not-yet-valid"]},
+            "warnings": {"type": "array", "items": {"type": "string"}},
+        },
+    })
+}
+
+fn verification_v1() -> Value {
+    let strings = json!({"type": "array", "items": {"type": "string"}});
+    json!({
+        "title": "jwt-check serve response body",
+        "type": "object",
+        "required": ["valid"],
+        "properties": {
+            "valid": {"type": "boolean"},
+            "claims": {"type": "object"},
+            "errors": strings,
+            "findings": strings,
+            "allowed": {"type": "string"},
+            "policy": {"type": "string"},
+            "tenant": {"type": "string"},
+            "key_age": {"type": "integer", "minimum": 0},
+            "key_stale": {"type": "boolean"},
+        },
+        "oneOf": [
+            {"properties": {"valid": {"const": true}}, "required": ["claims"]},
+            {"properties": {"valid": {"const": false}}, "required": ["errors"]},
+        ],
+    })
+}
+
+fn audit_v1() -> Value {
+    let rules: Vec<&str> = Rule::ALL.iter().map(|rule| rule.id()).collect();
+    json!({
+        "title": "jwt-check --audit --out findings",
+        "type": "object",
+        "required": ["header", "payload", "findings"],
+        "properties": {
+            "header": {"type": "object"},
+            "payload": {"type": "object"},
+            "findings": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["rule", "severity", "message", "reference"],
+                    "properties": {
+                        "rule": {"enum": rules},
+                        "severity": {"enum": ["high", "medium", "low"]},
+                        "message": {"type": "string"},
+                        "reference": {"type": ["string", "null"]},
+                    },
+                },
+            },
+        },
+    })
+}
+
+fn batch_v1() -> Value {
+    let count = json!({"type": "integer", "minimum": 0});
+    json!({
+        "title": "jwt-check --batch record",
+        "oneOf": [
+            {
+                "description": "One input line.",
+                "type": "object",
+                "required": ["line", "header", "payload", "status", "error"],
+                "properties": {
+                    "line": {"type": "integer", "minimum": 1},
+                    "header": {"type": ["object", "null"]},
+                    "payload": {"type": ["object", "null"]},
+                    "status": {"enum": ["valid", "invalid", "expired"]},
+                    "error": {"type": ["string", "null"]},
+                    "key_age": count,
+                    "canary": {
+                        "type": "object",
+                        "required": ["verdict", "agrees"],
+                        "properties": {
+                            "verdict": {"type": ["string", "null"]},
+                            "agrees": {"type": "boolean"},
+                            "reason": {"type": "string"},
+                            "error": {"type": "string"},
+                        },
+                    },
+                },
+            },
+            {
+                "description": "The last record: how many tokens ended up in each status.",
+                "type": "object",
+                "required": ["summary"],
+                "properties": {
+                    "summary": {
+                        "type": "object",
+                        "required": ["total", "valid", "invalid", "expired"],
+                        "properties": {
+                            "total": count,
+                            "valid": count,
+                            "invalid": count,
+                            "expired": count,
+                            "unchecked": count,
+                        },
+                    },
+                },
+            },
+        ],
+    })
+}
+
+/// Where `document` doesn't match `schema`, one message each, naming the
+/// member by its JSON pointer. Empty when it matches.
+pub fn check(schema: &Value, document: &Value) -> Vec<String> {
+    let mut problems = Vec::new();
+    check_at(schema, document, "", &mut problems);
+    problems
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn check_at(schema: &Value, value: &Value, at: &str, problems: &mut Vec<String>) {
+    let place = if at.is_empty() { "document" } else { at };
+    let actual = type_name(value);
+    let allowed: Vec<&str> = match schema.get("type") {
+        Some(Value::String(name)) => vec![name.as_str()],
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    let fits = |name: &&str| *name == actual || (*name == "number" && actual == "integer");
+    if !allowed.is_empty() && !allowed.iter().any(fits) {
+        problems.push(format!(
+            "{}: {} is not {}",
+            place,
+            actual,
+            allowed.join(" or ")
+        ));
+        return;
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            problems.push(format!("{}: {} is not {}", place, value, expected));
+        }
+    }
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(value) {
+            let options: Vec<String> = options.iter().map(Value::to_string).collect();
+            problems.push(format!(
+                "{}: {} is not one of {}",
+                place,
+                value,
+                options.join(", ")
+            ));
+        }
+    }
+    if let (Some(minimum), Some(number)) = (
+        schema.get("minimum").and_then(Value::as_f64),
+        value.as_f64(),
+    ) {
+        if number < minimum {
+            problems.push(format!("{}: {} is below {}", place, value, minimum));
+        }
+    }
+    if let Value::Object(members) = value {
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !members.contains_key(name) {
+                    problems.push(format!("{}: {} is missing", place, name));
+                }
+            }
+        }
+        if let Some(Value::Object(properties)) = schema.get("properties") {
+            for (name, property) in properties {
+                if let Some(member) = members.get(name) {
+                    let at = format!("{}/{}", at, name.replace('~', "~0").replace('/', "~1"));
+                    check_at(property, member, &at, problems);
+                }
+            }
+        }
+    }
+    if let (Value::Array(items), Some(item)) = (value, schema.get("items")) {
+        for (index, value) in items.iter().enumerate() {
+            check_at(item, value, &format!("{}/{}", at, index), problems);
+        }
+    }
+    if let Some(Value::Array(options)) = schema.get("oneOf") {
+        let matching = options
+            .iter()
+            .filter(|option| check(option, value).is_empty())
+            .count();
+        match matching {
+            1 => {}
+            0 if options.len() == 1 => {
+                if let Some(option) = options.first() {
+                    check_at(option, value, at, problems);
+                }
+            }
+            0 => problems.push(format!(
+                "{}: matches none of the {} alternatives",
+                place,
+                options.len()
+            )),
+            n => problems.push(format!(
+                "{}: matches {} of the alternatives, not one",
+                place, n
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_test() {
+        for (name, output) in KINDS.iter() {
+            assert_eq!(name.parse::<Output>().unwrap(), *output);
+            for version in output.versions() {
+                let schema = output.schema(*version).unwrap();
+                assert_eq!(
+                    schema["$id"],
+                    format!("{}/{}-v{}.json", BASE, name, version)
+                );
+            }
+        }
+        assert!(matches!(
+            "jwks".parse::<Output>(),
+            Err(JWTError::InvalidArgumentError(e))
+                if e == "`jwks` is not an output with a schema; expected decode, verification, audit, batch"
+        ));
+        for (output, version, expected) in [
+            (
+                Output::Audit,
+                2,
+                "the audit schema has no version 2; expected 1",
+            ),
+            (
+                Output::Decode,
+                3,
+                "the decode schema has no version 3; expected 1 or 2",
+            ),
+        ] {
+            assert!(matches!(
+                output.schema(version),
+                Err(JWTError::InvalidArgumentError(e)) if e == expected
+            ));
+        }
+
+        let audit = Output::Audit.schema(1).unwrap();
+        let document = json!({
+            "header": {"alg": "HS256"},
+            "payload": {"sub": "x"},
+            "findings": [{"rule": "no-expiry", "severity": "medium",
+                         "message": "no exp claim", "reference": null}],
+        });
+        assert!(check(&audit, &document).is_empty());
+        let wrong = json!({
+            "header": {"alg": "HS256"},
+            "findings": [{"rule": "no-expiry", "severity": "critical",
+                         "message": 5, "reference": null}],
+        });
+        assert_eq!(
+            check(&audit, &wrong),
+            [
+                "document: payload is missing",
+                "/findings/0/message: integer is not string",
+                "/findings/0/severity: \"critical\" is not one of \"high\", \"medium\", \"low\"",
+            ]
+        );
+
+        let batch = Output::Batch.schema(1).unwrap();
+        let summary = json!({"summary": {"total": 1, "valid": 1, "invalid": 0, "expired": 0}});
+        assert!(check(&batch, &summary).is_empty());
+        assert_eq!(
+            check(&batch, &json!({"line": 0})),
+            ["document: matches none of the 2 alternatives"]
+        );
+        let verification = Output::Verification.schema(1).unwrap();
+        assert!(check(
+            &verification,
+            &json!({"valid": false, "errors": ["expired"]})
+        )
+        .is_empty());
+        assert_eq!(
+            check(
+                &verification,
+                &json!({"valid": true, "errors": ["expired"]})
+            ),
+            ["document: matches none of the 2 alternatives"]
+        );
+        assert_eq!(
+            check(&verification, &json!([])),
+            ["document: array is not object"]
+        );
+    }
+}
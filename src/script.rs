@@ -0,0 +1,247 @@
+//! `--script`: a Rhai script run against each token, for the checks that
+//! fall between the built-in ones and a plugin of your own.
+//!
+//! The script sees the decoded token as `token.header` and `token.payload`,
+//! its time claims in seconds, and how it is being checked as `context`:
+//! `context.mode` (`check` or `serve`), `context.verified`, `context.now`,
+//! `context.failures`, the messages of the built-in checks it failed, and in
+//! `serve` `context.policy` and `context.tenant`. It answers by calling
+//! `warn`, `deny` and `allow`:
+//!
+//! ```text
+//! // the orders API
+//! if token.payload.exp - context.now > 86400 {
+//!     warn("lives longer than a day");
+//! }
+//! if "refunds" in token.payload.scope.split(" ") && token.payload.amr == () {
+//!     deny("refunds need MFA");
+//! }
+//! if context.failures.len() == 1 && token.payload.sub == "replay-job" {
+//!     allow("the replay job may use a token its claims checks fail");
+//! }
+//! ```
+//!
+//! `warn` adds a finding and `deny` fails the token. `allow` overrides the
+//! verdict of the claim checks, passing a token that failed them, but never
+//! one whose signature failed or one the script also denied. A script that
+//! fails while it runs, such as one that spends more than its operation
+//! budget, denies the token.
+
+use crate::{JWTError, JWToken};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Dynamic, Engine, Scope, AST};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// What a script made of a token.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Outcome {
+    /// `warn(message)`: findings that leave the verdict alone.
+    pub findings: Vec<String>,
+    /// `deny(message)`, and the error of a script that failed.
+    pub denials: Vec<String>,
+    /// `allow(message)`: why to pass a token whose claim checks failed.
+    pub allowed: Option<String>,
+}
+
+impl Outcome {
+    /// Whether the token passes, given whether the built-in checks passed
+    /// it.
+    pub fn passes(&self, checked: bool) -> bool {
+        self.denials.is_empty() && (checked || self.allowed.is_some())
+    }
+}
+
+thread_local! {
+    /// What the script running on this thread has called so far.
+    static OUTCOME: RefCell<Outcome> = RefCell::new(Outcome::default());
+}
+
+/// The engine every script compiles and runs in: no modules, no `eval`,
+/// and bounded work, so a script can't stall the server or reach past the
+/// token.
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let mut engine = Engine::new();
+        engine
+            .set_module_resolver(DummyModuleResolver::new())
+            .set_max_operations(100_000)
+            .set_max_call_levels(32)
+            .set_max_expr_depths(64, 32)
+            .set_max_string_size(65_536)
+            .set_max_array_size(10_000)
+            .set_max_map_size(10_000)
+            .on_print(|text| eprintln!("script: {}", text))
+            .on_debug(|text, _, position| eprintln!("script: {} ({})", text, position));
+        engine.disable_symbol("eval");
+        engine.register_fn("warn", |message: &str| {
+            OUTCOME.with(|outcome| outcome.borrow_mut().findings.push(message.to_string()))
+        });
+        engine.register_fn("deny", |message: &str| {
+            OUTCOME.with(|outcome| outcome.borrow_mut().denials.push(message.to_string()))
+        });
+        engine.register_fn("allow", |message: &str| {
+            OUTCOME.with(|outcome| outcome.borrow_mut().allowed = Some(message.to_string()))
+        });
+        engine
+    })
+}
+
+/// `value` as the script sees it: `null` is `()`, and integers stay
+/// integers.
+fn dynamic(value: &Value) -> Dynamic {
+    match value {
+        Value::Null => Dynamic::UNIT,
+        Value::Bool(b) => (*b).into(),
+        Value::Number(n) => match n.as_i64() {
+            Some(n) => n.into(),
+            None => n.as_f64().unwrap_or(f64::NAN).into(),
+        },
+        Value::String(s) => s.clone().into(),
+        Value::Array(items) => Dynamic::from_array(items.iter().map(dynamic).collect()),
+        Value::Object(fields) => Dynamic::from_map(
+            fields
+                .iter()
+                .map(|(name, value)| (name.as_str().into(), dynamic(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// A compiled script. Two scripts are equal when their source is.
+#[derive(Debug, Clone)]
+pub struct Script {
+    source: String,
+    ast: AST,
+}
+
+impl PartialEq for Script {
+    fn eq(&self, other: &Script) -> bool {
+        self.source == other.source
+    }
+}
+
+impl FromStr for Script {
+    type Err = JWTError;
+
+    fn from_str(text: &str) -> Result<Script, JWTError> {
+        let ast = engine()
+            .compile(text)
+            .map_err(|e| JWTError::ConfigError(format!("script: {}", e).into()))?;
+        Ok(Script {
+            source: text.to_string(),
+            ast,
+        })
+    }
+}
+
+impl Script {
+    /// Runs the script against `token`, whose time claims are in seconds,
+    /// and `context`.
+    pub fn run(&self, token: &JWToken, context: &Value) -> Outcome {
+        let mut scope = Scope::new();
+        scope.push_constant(
+            "token",
+            dynamic(&serde_json::json!({
+                "header": token.header,
+                "payload": token.payload,
+            })),
+        );
+        scope.push_constant("context", dynamic(context));
+        OUTCOME.with(|outcome| outcome.take());
+        let result = engine().run_ast_with_scope(&mut scope, &self.ast);
+        let mut outcome = OUTCOME.with(|outcome| outcome.take());
+        if let Err(e) = result {
+            outcome.denials.push(format!("the script failed: {}", e));
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn script_test() {
+        let script: Script = r#"
+            // orders
+            if token.payload.exp - context.now > 86400 {
+                warn("lives longer than a day");
+            }
+            if "refunds" in token.payload.scope.split(" ") && token.payload.amr == () {
+                deny("refunds need MFA");
+            }
+            if context.mode == "serve" && context.policy == "partner" && token.payload.client_id != () {
+                deny("no partner service tokens");
+            }
+            if context.failures.len() == 1 && token.header.kid == "replay" {
+                allow("replayed on purpose");
+            }
+        "#
+        .parse()
+        .unwrap();
+        let token = JWToken {
+            header: json!({"alg": "HS256", "kid": "replay"}),
+            payload: json!({"exp": NOW + 7 * 86_400, "scope": "orders refunds", "client_id": "svc"}),
+            signature: Vec::new(),
+        };
+        let outcome = script.run(
+            &token,
+            &json!({"mode": "check", "now": NOW, "failures": []}),
+        );
+        assert_eq!(outcome.findings, ["lives longer than a day"]);
+        assert_eq!(outcome.denials, ["refunds need MFA"]);
+        assert!(!outcome.passes(true));
+        let context = json!({"mode": "serve", "now": NOW, "policy": "partner", "failures": []});
+        assert_eq!(
+            script.run(&token, &context).denials,
+            ["refunds need MFA", "no partner service tokens"]
+        );
+
+        // `allow` passes a token only its claim checks failed
+        let token = JWToken {
+            payload: json!({"exp": NOW - 60, "scope": "orders"}),
+            ..token
+        };
+        let context =
+            json!({"mode": "check", "now": NOW, "failures": ["expired at 2023-11-14T22:12:20Z"]});
+        let outcome = script.run(&token, &context);
+        assert_eq!(outcome.allowed.as_deref(), Some("replayed on purpose"));
+        assert!(outcome.passes(false));
+        let outcome = script.run(
+            &token,
+            &json!({"mode": "check", "now": NOW, "failures": []}),
+        );
+        assert_eq!(outcome, Outcome::default());
+
+        // a script that fails denies the token
+        let token = JWToken {
+            payload: json!({"scope": "orders"}),
+            ..token
+        };
+        let outcome = script.run(&token, &json!({"now": NOW}));
+        assert_eq!(outcome.denials.len(), 1);
+        assert!(outcome.denials[0].starts_with("the script failed: "));
+        let looping: Script = "loop {}".parse().unwrap();
+        assert!(!looping.run(&token, &json!({})).passes(true));
+
+        for bad in ["deny(", "eval(\"deny(1)\")", "import \"other\" as other;"] {
+            match bad.parse::<Script>() {
+                Err(JWTError::ConfigError(error)) => {
+                    assert!(String::from(error).starts_with("script: "), "{}", bad)
+                }
+                other => match other.map(|script| script.run(&token, &json!({}))) {
+                    Ok(outcome) => assert!(!outcome.passes(true), "{}", bad),
+                    Err(e) => panic!("expected a config error, got {:?}", e),
+                },
+            }
+        }
+    }
+}
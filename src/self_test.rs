@@ -0,0 +1,363 @@
+//! `jwt-check self-test`: a corpus of tricky tokens run through the parser,
+//! to check a build on an unusual platform or architecture.
+//!
+//! The cases are about decoding, not signatures, which `conformance`
+//! covers: padding and alphabet variants, unicode and escaped claims,
+//! numbers past 64 bits, a megabyte payload, deep nesting and malformed
+//! segments. Each input goes through `extract_token` and `parse`, as the
+//! decoder's does, and must decode to the expected claims or be rejected
+//! for the expected reason.
+
+use crate::{claim, extract_token, parse, JWTError, SAMPLE_TOKEN};
+use serde_json::{json, Value};
+
+/// `{"alg":"HS256"}`, the header of the hand-made cases.
+const HEADER: &str = "eyJhbGciOiJIUzI1NiJ9";
+
+/// Why a malformed input must be rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rejection {
+    Base64,
+    Utf8,
+    Json,
+    MissingPart,
+    ExtraPart,
+}
+
+impl Rejection {
+    fn describe(self) -> &'static str {
+        match self {
+            Rejection::Base64 => "invalid base64url",
+            Rejection::Utf8 => "invalid UTF-8",
+            Rejection::Json => "invalid JSON",
+            Rejection::MissingPart => "a missing segment",
+            Rejection::ExtraPart => "an extra segment",
+        }
+    }
+
+    fn fits(self, error: &JWTError) -> bool {
+        matches!(
+            (self, error),
+            (Rejection::Base64, JWTError::DecodeError(_))
+                | (Rejection::Utf8, JWTError::UTF8Error(_))
+                | (Rejection::Json, JWTError::SerdeJsonError(_))
+                | (Rejection::MissingPart, JWTError::MissingPartError)
+                | (Rejection::ExtraPart, JWTError::UnknownPartError)
+        )
+    }
+}
+
+/// What parsing a case must come to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expect {
+    /// Decodes, with the claim at the path (as for `--claim`) equal to the value.
+    Claim(&'static str, Value),
+    Rejected(Rejection),
+}
+
+pub struct Case {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub input: String,
+    pub expect: Expect,
+}
+
+fn segment(json: &str) -> String {
+    base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+}
+
+fn case(name: &'static str, description: &'static str, input: &str, expect: Expect) -> Case {
+    Case {
+        name,
+        description,
+        input: input.to_string(),
+        expect,
+    }
+}
+
+/// Every case: the well-formed ones, then the ones that must be rejected.
+pub fn corpus() -> Vec<Case> {
+    let big = "x".repeat(1 << 20);
+    let nested = format!("{}{}", "[".repeat(100), "]".repeat(100));
+    let too_deep = format!("{}{}", "[".repeat(200), "]".repeat(200));
+    let big_numbers = format!(
+        "{}.eyJpZCI6MTg0NDY3NDQwNzM3MDk1NTE2MTcsImYiOjFlNDAwfQ.",
+        HEADER
+    );
+    vec![
+        case(
+            "sample",
+            "the jwt.io sample token",
+            SAMPLE_TOKEN,
+            Expect::Claim("name", json!("John Doe")),
+        ),
+        case(
+            "bearer",
+            "pasted with an Authorization header and stray whitespace",
+            &format!("  Authorization: Bearer {}\n", SAMPLE_TOKEN),
+            Expect::Claim("sub", json!("1234567890")),
+        ),
+        case(
+            "padded",
+            "a payload segment with its `=` padding kept",
+            &format!("{}.eyJzdWIiOiJhIn0=.", HEADER),
+            Expect::Claim("sub", json!("a")),
+        ),
+        case(
+            "url-alphabet",
+            "`-` and `_` in place of `+` and `/`",
+            &format!("{}.eyJuYW1lIjoiWm_DqyDwn5iAIn0.", HEADER),
+            Expect::Claim("name", json!("Zoë 😀")),
+        ),
+        case(
+            "unicode",
+            "non-ASCII claims, four-byte characters included",
+            &format!(
+                "{}.eyJuYW1lIjoiWm_DqyDwn5iAIiwib3JnIjoi5qCq5byP5Lya56S-In0.",
+                HEADER
+            ),
+            Expect::Claim("org", json!("株式会社")),
+        ),
+        case(
+            "escaped-unicode",
+            "`\\u` escapes, a surrogate pair included",
+            &format!("{}.eyJuYW1lIjoiWm9cdTAwZWIgXHVkODNkXHVkZTAwIn0.", HEADER),
+            Expect::Claim("name", json!("Zoë 😀")),
+        ),
+        case(
+            "nul",
+            "an escaped NUL inside a claim",
+            &format!("{}.eyJzdWIiOiJhXHUwMDAwYiJ9.", HEADER),
+            Expect::Claim("sub", json!("a\u{0}b")),
+        ),
+        if cfg!(feature = "exact-numbers") {
+            case(
+                "big-numbers",
+                "an integer past 64 bits, kept exactly",
+                &big_numbers,
+                Expect::Claim(
+                    "id",
+                    serde_json::from_str("18446744073709551617").unwrap_or_default(),
+                ),
+            )
+        } else {
+            case(
+                "big-numbers",
+                "an integer past 64 bits, refused without exact-numbers",
+                &big_numbers,
+                Expect::Rejected(Rejection::Json),
+            )
+        },
+        case(
+            "duplicate-claims",
+            "a claim given twice, where the last one counts",
+            &format!("{}.eyJzdWIiOiJhIiwic3ViIjoiYiJ9.", HEADER),
+            Expect::Claim("sub", json!("b")),
+        ),
+        case(
+            "huge",
+            "a one-megabyte claim",
+            &format!(
+                "{}.{}.",
+                HEADER,
+                segment(&json!({ "big": big }).to_string())
+            ),
+            Expect::Claim("big", json!(big)),
+        ),
+        case(
+            "nested",
+            "arrays nested 100 deep",
+            &format!("{}.{}.", HEADER, segment(&format!("{{\"n\":{}}}", nested))),
+            Expect::Claim("n", serde_json::from_str(&nested).unwrap_or_default()),
+        ),
+        case(
+            "too-deep",
+            "arrays nested 200 deep, past the JSON parser's limit",
+            &format!(
+                "{}.{}.",
+                HEADER,
+                segment(&format!("{{\"n\":{}}}", too_deep))
+            ),
+            Expect::Rejected(Rejection::Json),
+        ),
+        case(
+            "standard-alphabet",
+            "`/` from standard base64 instead of base64url",
+            &format!("{}.eyJzdWIiOiI/Pz4ifQ.", HEADER),
+            Expect::Rejected(Rejection::Base64),
+        ),
+        case(
+            "bad-utf8",
+            "bytes that are not UTF-8 inside a claim",
+            &format!("{}.eyJuYW1lIjoi__4ifQ.", HEADER),
+            Expect::Rejected(Rejection::Utf8),
+        ),
+        case(
+            "lone-surrogate",
+            "an escaped UTF-16 surrogate with no pair",
+            &format!("{}.eyJuYW1lIjoiXHVkODAwIn0.", HEADER),
+            Expect::Rejected(Rejection::Json),
+        ),
+        case(
+            "byte-order-mark",
+            "a UTF-8 byte order mark before the payload",
+            &format!("{}.77u_eyJzdWIiOiJhIn0.", HEADER),
+            Expect::Rejected(Rejection::Json),
+        ),
+        case(
+            "empty-header",
+            "an empty header segment",
+            ".eyJzdWIiOiJhIn0.",
+            Expect::Rejected(Rejection::Json),
+        ),
+        case(
+            "two-segments",
+            "no signature segment",
+            &format!("{}.eyJzdWIiOiJhIn0", HEADER),
+            Expect::Rejected(Rejection::MissingPart),
+        ),
+        case(
+            "four-segments",
+            "one segment too many",
+            &format!("{}.eyJzdWIiOiJhIn0..", HEADER),
+            Expect::Rejected(Rejection::ExtraPart),
+        ),
+    ]
+}
+
+/// How one case fared.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub passed: bool,
+    /// What the decoder made of the tricky input, and for a failure how that
+    /// differs from the case's expectation.
+    pub detail: String,
+}
+
+fn check(case: &Case) -> Result<String, String> {
+    match (parse(extract_token(&case.input)), &case.expect) {
+        (Ok(token), Expect::Claim(path, expected)) => match claim(&token, path) {
+            Some(value) if value == expected => Ok(format!("decodes, with {} as expected", path)),
+            Some(_) => Err(format!("decodes, but {} is not as expected", path)),
+            None => Err(format!("decodes, but without {}", path)),
+        },
+        (Ok(_), Expect::Rejected(rejection)) => Err(format!(
+            "expected to be rejected for {}, but decodes",
+            rejection.describe()
+        )),
+        (Err(error), Expect::Rejected(rejection)) if rejection.fits(&error) => {
+            Ok(format!("rejected: {}", error))
+        }
+        (Err(error), Expect::Rejected(rejection)) => Err(format!(
+            "expected to be rejected for {}, but rejected: {}",
+            rejection.describe(),
+            error
+        )),
+        (Err(error), Expect::Claim(..)) => Err(format!("does not decode: {}", error)),
+    }
+}
+
+pub fn run() -> Vec<Outcome> {
+    corpus()
+        .iter()
+        .map(|case| {
+            let (passed, detail) = match check(case) {
+                Ok(detail) => (true, detail),
+                Err(detail) => (false, detail),
+            };
+            Outcome {
+                name: case.name,
+                description: case.description,
+                passed,
+                detail,
+            }
+        })
+        .collect()
+}
+
+/// One `pass`/`FAIL` line per case, then a count.
+pub fn render_text(outcomes: &[Outcome]) -> String {
+    let width = outcomes.iter().map(|o| o.name.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for outcome in outcomes {
+        out.push_str(&format!(
+            "{} {:w$}  {}: {}\n",
+            if outcome.passed { "pass" } else { "FAIL" },
+            outcome.name,
+            outcome.description,
+            outcome.detail,
+            w = width
+        ));
+    }
+    let failed = outcomes.iter().filter(|o| !o.passed).count();
+    out.push_str(&format!(
+        "{} of {} case(s) passed",
+        outcomes.len() - failed,
+        outcomes.len()
+    ));
+    out
+}
+
+pub fn to_json(outcomes: &[Outcome]) -> Value {
+    json!({
+        "passed": outcomes.iter().filter(|o| o.passed).count(),
+        "failed": outcomes.iter().filter(|o| !o.passed).count(),
+        "cases": outcomes.iter().map(|outcome| json!({
+            "name": outcome.name,
+            "description": outcome.description,
+            "passed": outcome.passed,
+            "detail": outcome.detail,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_test_corpus_passes_test() {
+        let outcomes = run();
+        for outcome in &outcomes {
+            assert!(outcome.passed, "{}: {}", outcome.name, outcome.detail);
+        }
+        assert!(render_text(&outcomes).ends_with("19 of 19 case(s) passed"));
+
+        let mut wrong = corpus().remove(0);
+        wrong.expect = Expect::Rejected(Rejection::Base64);
+        assert_eq!(
+            check(&wrong),
+            Err("expected to be rejected for invalid base64url, but decodes".to_string())
+        );
+        let two_segments = format!("{}.eyJzdWIiOiJhIn0", HEADER);
+        for (input, expect, detail) in [
+            (
+                SAMPLE_TOKEN,
+                Expect::Claim("name", json!("Jane Doe")),
+                "decodes, but name is not as expected".to_string(),
+            ),
+            (
+                SAMPLE_TOKEN,
+                Expect::Claim("email", json!("a@example.com")),
+                "decodes, but without email".to_string(),
+            ),
+            (
+                two_segments.as_str(),
+                Expect::Claim("sub", json!("a")),
+                format!("does not decode: {}", JWTError::MissingPartError),
+            ),
+            (
+                two_segments.as_str(),
+                Expect::Rejected(Rejection::Json),
+                format!(
+                    "expected to be rejected for invalid JSON, but rejected: {}",
+                    JWTError::MissingPartError
+                ),
+            ),
+        ] {
+            assert_eq!(check(&case("wrong", "", input, expect)), Err(detail));
+        }
+    }
+}
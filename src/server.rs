@@ -0,0 +1,2750 @@
+//! `jwt-check serve`: a verification sidecar over HTTP, with one policy per
+//! trust domain.
+//!
+//! The config is YAML with a `policies` list. A request is checked by the
+//! first policy that matches it: on its `path`, on a `header` value, or on
+//! both. A policy with neither matches every request, so it works as a
+//! default at the end of the list.
+//!
+//! ```yaml
+//! policies:
+//!   - name: internal
+//!     path: /verify/internal
+//!     issuers: [https://idp.internal.example]
+//!     audiences: [api://orders]
+//!     algorithms: [ES256]
+//!     jwks: internal.jwks
+//!   - name: partner
+//!     header: {X-Trust-Domain: partner}
+//!     issuers: [https://login.partner.example]
+//!     algorithms: [RS256, PS256]
+//!     jwks_url: https://login.partner.example/jwks
+//!     leeway: 30s
+//! ```
+//!
+//! `issuers` and `audiences` are allow-lists: the token's `iss` must be one
+//! of the issuers, and its `aud` must contain one of the audiences. An
+//! empty or missing list allows any value. `algorithms` limits the `alg`
+//! the token may be signed with. `required_claims` lists the claims a token
+//! must carry, `[exp]` by default, so a token that never expires is refused
+//! unless the policy sets `required_claims: []`. `jwks` is a JWKS file, relative to the
+//! config file, and `jwks_url` is fetched at startup and again once the
+//! keys are older than `jwks_ttl` (default 5m). `resolve` pins the hosts
+//! of the `jwks_url` fetch, with entries as for `--resolve`, for an issuer
+//! only split-horizon DNS knows.
+//!
+//! The refetch happens in the background: until it succeeds, requests are
+//! still checked with the stale keys, for up to `max_stale` (default 1h)
+//! past the TTL. After that the policy answers `503` rather than trust keys
+//! that may have been rotated out. Results from a `jwks_url` policy carry
+//! `key_age`, the keys' age in seconds, and `key_stale`.
+//!
+//! The token comes from `Authorization: Bearer`, or else is the request body.
+//! A valid token gets `200` with its claims, an invalid one `401` with the
+//! reasons, and a request no policy matches `404`.
+//!
+//! The config and JWKS files are watched, and are also reread on `SIGHUP`.
+//! A config that fails to load is logged and the running policies are kept,
+//! so a bad edit never takes the sidecar down. Requests in flight finish
+//! with the policies they started with.
+//!
+//! One sidecar can serve many teams. With `tenant`, saying how a request
+//! names its tenant, the policies go under `tenants` instead:
+//!
+//! ```yaml
+//! tenant: {header: X-Tenant}      # or {prefix: /tenants}
+//! tenants:
+//!   - name: orders
+//!     rate_limit: 200             # requests per second
+//!     policies:
+//!       - name: internal
+//!         jwks_url: https://idp.orders.example/jwks
+//!   - name: billing
+//!     policies: [...]
+//! ```
+//!
+//! A request is routed only among its tenant's policies, and with a
+//! `prefix` on the path left after `/tenants/orders`. Policy names need
+//! only be unique within a tenant. Each tenant's policies keep their own
+//! keys, a tenant over its `rate_limit` gets `429`, and the metrics are
+//! labelled by tenant.
+//!
+//! Each request is logged, to stderr or the `--log-sink`, with its status
+//! and policy, and with only the claims `access_log` allows:
+//!
+//! ```yaml
+//! access_log:
+//!   claims: [iss, aud, exp]       # logged as they are
+//!   hashed: [sub, email]          # logged as a hash, for correlation
+//!   hash_key_env: LOG_HASH_KEY    # keys the hash with HMAC-SHA256
+//! ```
+//!
+//! That is also the default, less `email` and the key, so nothing beyond
+//! `iss`, `aud`, `exp` and a hash of `sub` is logged unless asked for. A
+//! policy, or a tenant, may have its own `access_log`, which replaces it.
+//!
+//! A policy's `script`, relative to the config file, is a `--script` run on
+//! every token the policy passes, and on every token whose signature
+//! verified but whose claims failed. Its `warn` calls add `findings` to the
+//! response, a `deny` turns it into a `401`, and an `allow` passes a token
+//! that failed only its claims, with the reason in `allowed`.
+//!
+//! On `SIGTERM` the sidecar stops accepting connections, gives the requests
+//! in flight until the drain deadline to finish, logs how many requests it
+//! answered, and exits non-zero only when some were cut off.
+
+use crate::crypto::{hmac, Hash};
+use crate::jwks::select_keys;
+use crate::otlp::{self, Exporter};
+use crate::script::Script;
+use crate::syslog::{self, Priority};
+use crate::verify::{token_algorithm, verify, Algorithm};
+use crate::{
+    audiences, claim, extract_token, humanize_seconds, parse, parse_duration, validate, AudMatch,
+    ClaimChecks, JWTError, JWToken, Normalization,
+};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+const MAX_HEADERS: usize = 100;
+/// Reserved for the sidecar's own metrics, whatever the policies say.
+pub const METRICS_PATH: &str = "/metrics";
+/// Most request buffers kept for reuse, and the largest kept.
+const POOL_BUFFERS: usize = 64;
+const POOL_KEEP: usize = 64 * 1024;
+/// How often the watched files are looked at.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// How long after a failed `jwks_url` refetch it is tried again.
+const REFRESH_RETRY: Duration = Duration::from_secs(10);
+/// How often the listener is polled, and the requests in flight while
+/// draining.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How a policy is picked for a request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Route {
+    /// A header name, compared case-insensitively, and its exact value.
+    pub header: Option<(String, String)>,
+    pub path: Option<String>,
+}
+
+impl Route {
+    fn matches(&self, path: &str, headers: &[(String, String)]) -> bool {
+        self.path.as_deref().is_none_or(|expected| expected == path)
+            && self.header.as_ref().is_none_or(|(name, expected)| {
+                headers
+                    .iter()
+                    .any(|(n, value)| n.eq_ignore_ascii_case(name) && value == expected)
+            })
+    }
+}
+
+/// One trust domain: which tokens it accepts and the keys that sign them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    pub name: String,
+    /// The tenant whose `policies` it is in, when there are tenants.
+    pub tenant: Option<String>,
+    pub route: Route,
+    pub issuers: Vec<String>,
+    pub audiences: Vec<String>,
+    pub algorithms: Vec<Algorithm>,
+    /// Allowed clock skew in seconds for `exp`, `nbf` and `iat`.
+    pub leeway: i64,
+    /// Claims a token must carry; `exp` unless `required_claims` says
+    /// otherwise.
+    pub required: Vec<String>,
+    pub keys: Value,
+    pub jwks_url: Option<String>,
+    /// `--resolve` entries for fetching `jwks_url`.
+    pub resolve: Vec<String>,
+    /// When the keys were fetched, in Unix seconds.
+    pub fetched: i64,
+    /// How long fetched keys stay fresh, and how much longer stale ones
+    /// are used while they are refreshed, in seconds.
+    pub jwks_ttl: i64,
+    pub max_stale: i64,
+    /// Replaces the config's `access_log` for this policy's requests.
+    pub access_log: Option<LogProfile>,
+    pub script: Option<Script>,
+}
+
+// What an admin endpoint or a dump of the running config shows, with its
+// keys sorted. Keys are listed by `kid`, as a reload logs them: a JWKS may
+// hold HMAC secrets.
+impl Serialize for Policy {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Policy", 14)?;
+        state.serialize_field("access_log", &self.access_log)?;
+        state.serialize_field("algorithms", &self.algorithms)?;
+        state.serialize_field("audiences", &self.audiences)?;
+        state.serialize_field("issuers", &self.issuers)?;
+        state.serialize_field("jwks_ttl", &self.jwks_ttl)?;
+        state.serialize_field("jwks_url", &self.jwks_url)?;
+        state.serialize_field("keys", &kids(&self.keys))?;
+        state.serialize_field("leeway", &self.leeway)?;
+        state.serialize_field("max_stale", &self.max_stale)?;
+        state.serialize_field("name", &self.name)?;
+        state.serialize_field("required_claims", &self.required)?;
+        state.serialize_field("resolve", &self.resolve)?;
+        state.serialize_field("route", &self.route)?;
+        state.serialize_field("tenant", &self.tenant)?;
+        state.end()
+    }
+}
+
+/// Which claims the access log may show. `claims` are logged as they are
+/// and `hashed` only as a hash, so requests can be correlated without the
+/// log holding the value. No other claim is ever logged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogProfile {
+    /// Paths as for `--claim`.
+    pub claims: Vec<String>,
+    pub hashed: Vec<String>,
+    /// The HMAC key for `hashed`, read from `hash_key_env`; the hash is
+    /// plain SHA-256 without one.
+    pub hash_key: Option<Vec<u8>>,
+}
+
+impl Default for LogProfile {
+    fn default() -> LogProfile {
+        LogProfile {
+            claims: vec!["iss".to_string(), "aud".to_string(), "exp".to_string()],
+            hashed: vec!["sub".to_string()],
+            hash_key: None,
+        }
+    }
+}
+
+// As a reload shows it, so the HMAC key is never written out.
+impl Serialize for LogProfile {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json().serialize(serializer)
+    }
+}
+
+impl LogProfile {
+    fn from_config(
+        config: &Value,
+        invalid: &dyn Fn(String) -> JWTError,
+    ) -> Result<LogProfile, JWTError> {
+        let invalid = |what: String| invalid(format!("access_log: {}", what));
+        if !config.is_object() {
+            return Err(invalid(
+                "expected `claims`, `hashed` and `hash_key_env`".to_string(),
+            ));
+        }
+        let claims = string_list(config, "claims", &invalid)?;
+        let hashed = string_list(config, "hashed", &invalid)?;
+        if let Some(both) = claims.iter().find(|path| hashed.contains(path)) {
+            return Err(invalid(format!("`{}` is both logged and hashed", both)));
+        }
+        let hash_key = match config.get("hash_key_env").map(Value::as_str) {
+            None => None,
+            Some(Some(name)) => match std::env::var(name) {
+                Ok(key) if !key.is_empty() => Some(key.into_bytes()),
+                _ => return Err(invalid(format!("`hash_key_env` {} is not set", name))),
+            },
+            Some(None) => return Err(invalid("`hash_key_env` must be a name".to_string())),
+        };
+        Ok(LogProfile {
+            claims,
+            hashed,
+            hash_key,
+        })
+    }
+
+    /// How a reload shows the profile; the key only as whether there is one.
+    fn to_json(&self) -> Value {
+        json!({"claims": self.claims, "hashed": self.hashed, "keyed": self.hash_key.is_some()})
+    }
+
+    /// The access log fields for `token`: ` iss="https://idp" sub#=9f86d081884c7d65`.
+    /// Claims the token lacks are left out.
+    pub fn render(&self, token: &JWToken) -> String {
+        let mut out = String::new();
+        for path in &self.claims {
+            if let Some(value) = claim(token, path) {
+                out.push_str(&format!(" {}={}", path, value));
+            }
+        }
+        for path in &self.hashed {
+            if let Some(value) = claim(token, path) {
+                let text = match value {
+                    Value::String(text) => text.clone(),
+                    value => value.to_string(),
+                };
+                let digest = match &self.hash_key {
+                    Some(key) => hmac(Hash::Sha256, key, text.as_bytes()),
+                    None => Hash::Sha256.digest(text.as_bytes()),
+                };
+                let hex: String = digest
+                    .iter()
+                    .take(8)
+                    .map(|b| format!("{:02x}", b))
+                    .collect();
+                out.push_str(&format!(" {}#={}", path, hex));
+            }
+        }
+        out
+    }
+}
+
+/// Gets a `jwks_url`, connecting to the hosts of the policy's `resolve`
+/// pins at their pinned addresses: the body, and how long ago it was
+/// fetched when it comes from a cache.
+pub type Fetch<'a> = dyn Fn(&str, &[String]) -> Result<(Vec<u8>, Duration), JWTError> + 'a;
+
+fn duration(
+    config: &Value,
+    key: &str,
+    default: i64,
+    invalid: &dyn Fn(String) -> JWTError,
+) -> Result<i64, JWTError> {
+    match config.get(key) {
+        None => Ok(default),
+        Some(Value::String(value)) => parse_duration(value),
+        Some(value) => value
+            .as_i64()
+            .ok_or_else(|| invalid(format!("`{}` must be a duration such as 30s", key))),
+    }
+}
+
+fn string_list(
+    config: &Value,
+    key: &str,
+    invalid: &dyn Fn(String) -> JWTError,
+) -> Result<Vec<String>, JWTError> {
+    match config.get(key) {
+        None => Ok(Vec::new()),
+        Some(Value::String(value)) => Ok(vec![value.clone()]),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|item| {
+                item.as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| invalid(format!("`{}` must be a list of strings", key)))
+            })
+            .collect(),
+        Some(_) => Err(invalid(format!("`{}` must be a list", key))),
+    }
+}
+
+impl Policy {
+    /// Reads one entry of `policies`. `base` is the config file's directory;
+    /// `fetch` gets a `jwks_url`.
+    pub fn from_config(config: &Value, base: &Path, fetch: &Fetch) -> Result<Policy, JWTError> {
+        let name = config
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| JWTError::ConfigError("policies: every policy needs a `name`".into()))?
+            .to_string();
+        let invalid =
+            |what: String| JWTError::ConfigError(format!("policy `{}`: {}", name, what).into());
+        let path = match config.get("path") {
+            None => None,
+            Some(Value::String(path)) if path.starts_with('/') => Some(path.clone()),
+            Some(_) => {
+                return Err(invalid(
+                    "`path` must be a path such as /verify/internal".to_string(),
+                ))
+            }
+        };
+        let header = match config.get("header") {
+            None => None,
+            Some(Value::Object(header)) if header.len() == 1 => match header.iter().next() {
+                Some((name, Value::String(value))) => Some((name.clone(), value.clone())),
+                _ => return Err(invalid("the `header` value must be a string".to_string())),
+            },
+            Some(_) => {
+                return Err(invalid(
+                    "`header` must map one header name to its value".to_string(),
+                ))
+            }
+        };
+        let algorithms = string_list(config, "algorithms", &invalid)?
+            .iter()
+            .map(|alg| {
+                alg.parse()
+                    .map_err(|_| invalid(format!("unsupported algorithm `{}`", alg)))
+            })
+            .collect::<Result<_, _>>()?;
+        let jwks_url = config
+            .get("jwks_url")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let resolve = string_list(config, "resolve", &invalid)?;
+        if !resolve.is_empty() && jwks_url.is_none() {
+            return Err(invalid(
+                "`resolve` is for fetching a `jwks_url`".to_string(),
+            ));
+        }
+        let (jwks, age) = match (config.get("jwks").and_then(Value::as_str), &jwks_url) {
+            (Some(file), None) => {
+                let jwks = std::fs::read(base.join(file))
+                    .map_err(|e| invalid(format!("cannot read {}: {}", file, e)))?;
+                (jwks, Duration::ZERO)
+            }
+            (None, Some(url)) => fetch(url, &resolve)?,
+            _ => return Err(invalid("needs either `jwks` or `jwks_url`".to_string())),
+        };
+        let keys: Value = serde_json::from_slice(&jwks)
+            .map_err(|e| invalid(format!("the JWKS is not valid JSON: {}", e)))?;
+        Ok(Policy {
+            route: Route { path, header },
+            issuers: string_list(config, "issuers", &invalid)?,
+            audiences: string_list(config, "audiences", &invalid)?,
+            algorithms,
+            leeway: duration(config, "leeway", 0, &invalid)?,
+            required: match config.get("required_claims") {
+                None => vec!["exp".to_string()],
+                Some(_) => string_list(config, "required_claims", &invalid)?,
+            },
+            keys,
+            jwks_url,
+            resolve,
+            fetched: crate::unix_now() - age.as_secs() as i64,
+            jwks_ttl: duration(config, "jwks_ttl", 300, &invalid)?,
+            max_stale: duration(config, "max_stale", 3_600, &invalid)?,
+            script: match config.get("script") {
+                None => None,
+                Some(Value::String(file)) => Some(
+                    std::fs::read_to_string(base.join(file))
+                        .map_err(|e| invalid(format!("cannot read {}: {}", file, e)))?
+                        .parse()
+                        .map_err(|e| match e {
+                            JWTError::ConfigError(why) => invalid(format!("{}: {}", file, why)),
+                            e => invalid(format!("{}: {}", file, e)),
+                        })?,
+                ),
+                Some(_) => return Err(invalid("`script` must be a file name".to_string())),
+            },
+            access_log: config
+                .get("access_log")
+                .map(|profile| LogProfile::from_config(profile, &invalid))
+                .transpose()?,
+            name,
+            tenant: None,
+        })
+    }
+
+    /// The name, qualified by the tenant when there is one:
+    /// `orders/internal`.
+    pub fn label(&self) -> String {
+        match &self.tenant {
+            Some(tenant) => format!("{}/{}", tenant, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// How long ago the keys were fetched from `jwks_url`; `None` for keys
+    /// from a file, which are reread when it changes instead.
+    pub fn key_age(&self, now: i64) -> Option<i64> {
+        self.jwks_url.as_ref().map(|_| (now - self.fetched).max(0))
+    }
+
+    /// Refetches the keys from `jwks_url`, for a policy that has one.
+    pub fn refresh(&mut self, fetch: &Fetch) -> Result<(), JWTError> {
+        let url = match &self.jwks_url {
+            Some(url) => url,
+            None => return Ok(()),
+        };
+        let (jwks, age) = fetch(url, &self.resolve)?;
+        self.keys = serde_json::from_slice(&jwks).map_err(|e| {
+            JWTError::ConfigError(
+                format!(
+                    "policy `{}`: the JWKS is not valid JSON: {}",
+                    self.label(),
+                    e
+                )
+                .into(),
+            )
+        })?;
+        self.fetched = crate::unix_now() - age.as_secs() as i64;
+        Ok(())
+    }
+
+    /// Whether the keys are too old to use even while being refreshed.
+    fn keys_expired(&self, now: i64) -> bool {
+        self.key_age(now)
+            .is_some_and(|age| age > self.jwks_ttl.saturating_add(self.max_stale))
+    }
+
+    /// Checks a compact token, returning it when every check passes and
+    /// otherwise one message per failed check.
+    /// Each step is an `otlp` span when the request is traced.
+    pub fn check(&self, raw: &str, now: i64) -> Result<JWToken, Rejection> {
+        let started = otlp::now();
+        let token = parse(raw).map_err(|e| Rejection {
+            errors: vec![e.to_string()],
+            token: None,
+        });
+        otlp::step(
+            "decode",
+            started,
+            token.as_ref().err().map(|e| e.errors.join("; ")),
+        );
+        let token = token?;
+        let started = otlp::now();
+        let mut failures = Vec::new();
+        let alg = token_algorithm(&token);
+        let mut verified = false;
+        match &alg {
+            Ok(alg) if self.algorithms.is_empty() || self.algorithms.contains(alg) => {}
+            Ok(alg) => failures.push(format!("alg {} is not allowed", alg)),
+            Err(e) => failures.push(e.to_string()),
+        }
+        if !self.issuers.is_empty() {
+            match token.payload.get("iss").and_then(Value::as_str) {
+                Some(iss) if self.issuers.iter().any(|allowed| allowed == iss) => {}
+                Some(iss) => failures.push(format!("iss \"{}\" is not an allowed issuer", iss)),
+                None => failures.push("iss is missing".to_string()),
+            }
+        }
+        if !self.audiences.is_empty() {
+            let matched = audiences(&token.payload)
+                .iter()
+                .any(|aud| self.audiences.iter().any(|allowed| allowed == aud));
+            if !matched {
+                failures.push("aud contains none of the allowed audiences".to_string());
+            }
+        }
+        failures.extend(
+            self.required
+                .iter()
+                .filter(|claim| token.payload.get(claim.as_str()).is_none())
+                .map(|claim| format!("{} is missing", claim)),
+        );
+        let checks = ClaimChecks {
+            leeway: self.leeway,
+            iss: None,
+            aud: &[],
+            aud_match: AudMatch::Any,
+            normalize: Normalization::default(),
+            sub: None,
+        };
+        failures.extend(validate(&token.payload, now, &checks));
+        let failed = (!failures.is_empty()).then(|| failures.join("; "));
+        otlp::step("claim validation", started, failed);
+        if let Ok(alg) = alg {
+            let kid = token.header.get("kid").and_then(Value::as_str);
+            let started = otlp::now();
+            let keys = select_keys(&self.keys, kid, alg).map_err(|e| e.to_string());
+            otlp::step("key resolution", started, keys.as_ref().err().cloned());
+            match keys {
+                Ok(keys) => {
+                    let started = otlp::now();
+                    verified = keys.iter().any(|key| verify(raw, &token, key).is_ok());
+                    let error = (!verified).then(|| JWTError::InvalidSignatureError.to_string());
+                    otlp::step("signature check", started, error.clone());
+                    failures.extend(error);
+                }
+                Err(e) => failures.push(e),
+            }
+        }
+        let allowed =
+            alg.is_ok_and(|alg| self.algorithms.is_empty() || self.algorithms.contains(&alg));
+        match (failures.is_empty(), verified && allowed) {
+            (true, _) => Ok(token),
+            (false, signed) => Err(Rejection {
+                errors: failures,
+                token: signed.then_some(token),
+            }),
+        }
+    }
+}
+
+/// Why [`Policy::check`] failed a token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    /// One message per failed check.
+    pub errors: Vec<String>,
+    /// The token, when its algorithm is allowed and its signature verified,
+    /// so only its claims failed and a script may still allow it.
+    pub token: Option<JWToken>,
+}
+
+/// How a request names its tenant. It serializes as it is configured,
+/// `{"header": NAME}` or `{"prefix": PATH}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Identify {
+    /// The value of this header.
+    Header(String),
+    /// The first path segment after this prefix, which may be empty.
+    Prefix(String),
+}
+
+impl Identify {
+    fn from_config(config: &Value) -> Result<Identify, JWTError> {
+        let header = config.get("header").and_then(Value::as_str);
+        let prefix = config.get("prefix").and_then(Value::as_str);
+        match (header, prefix, config.as_object().map(|o| o.len())) {
+            (Some(header), None, Some(1)) => Ok(Identify::Header(header.to_string())),
+            (None, Some(prefix), Some(1)) if prefix.starts_with('/') => {
+                Ok(Identify::Prefix(prefix.trim_end_matches('/').to_string()))
+            }
+            _ => Err(JWTError::ConfigError(
+                "`tenant` must be either {header: NAME} or {prefix: /PATH}".into(),
+            )),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Identify::Header(name) => format!("the {} header", name),
+            Identify::Prefix(prefix) => format!("a path under {}/TENANT", prefix),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tenant {
+    pub name: String,
+    /// Requests per second, with bursts of up to a second's worth.
+    pub rate_limit: Option<f64>,
+}
+
+/// How requests are shared out among tenants.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tenancy {
+    pub identify: Identify,
+    pub tenants: Vec<Tenant>,
+}
+
+impl Tenancy {
+    /// The tenant a request names, and the path its policies are routed on.
+    fn identify<'r>(&self, request: &'r Request) -> Result<(&'r str, &'r str), (u16, String)> {
+        let named = match &self.identify {
+            Identify::Header(name) => request
+                .header(name)
+                .filter(|tenant| !tenant.is_empty())
+                .map(|tenant| (tenant, request.path.as_str())),
+            Identify::Prefix(prefix) => request
+                .path
+                .strip_prefix(prefix.as_str())
+                .and_then(|rest| rest.strip_prefix('/'))
+                .and_then(|rest| {
+                    let tenant = rest.split('/').next().unwrap_or_default();
+                    let path = rest.get(tenant.len()..).filter(|path| !path.is_empty());
+                    Some((tenant, path.unwrap_or("/"))).filter(|_| !tenant.is_empty())
+                }),
+        };
+        let (tenant, path) = named.ok_or_else(|| {
+            (
+                400,
+                format!("no tenant: expected {}", self.identify.describe()),
+            )
+        })?;
+        if !self.tenants.iter().any(|known| known.name == tenant) {
+            return Err((404, format!("no tenant {}", tenant)));
+        }
+        Ok((tenant, path))
+    }
+}
+
+/// The `policies` of a config file, in the order they are tried. The fields
+/// are declared in sorted order, the order they serialize in.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Policies {
+    /// What the access log shows, unless a policy says otherwise.
+    pub access_log: LogProfile,
+    /// The files they were read from, to watch for changes.
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+    pub policies: Vec<Policy>,
+    /// `None` when the config has no `tenant`.
+    pub tenancy: Option<Tenancy>,
+}
+
+/// The policies requests are checked with, swapped whole on reload.
+pub type Shared = Arc<RwLock<Arc<Policies>>>;
+
+impl Policies {
+    pub fn from_config(config: &Value, base: &Path, fetch: &Fetch) -> Result<Policies, JWTError> {
+        let access_log = match config.get("access_log") {
+            Some(profile) => {
+                LogProfile::from_config(profile, &|why| JWTError::ConfigError(why.into()))?
+            }
+            None => LogProfile::default(),
+        };
+        let mut policies = Policies {
+            policies: Vec::new(),
+            files: Vec::new(),
+            tenancy: None,
+            access_log,
+        };
+        let identify = match config.get("tenant") {
+            Some(identify) => Identify::from_config(identify)?,
+            None if config.get("tenants").is_some() => {
+                return Err(JWTError::ConfigError(
+                    "`tenants` needs `tenant`, saying how a request names its tenant".into(),
+                ))
+            }
+            None => {
+                policies.add(config, None, base, fetch)?;
+                return Ok(policies);
+            }
+        };
+        if config.get("policies").is_some() {
+            return Err(JWTError::ConfigError(
+                "with `tenant`, the policies go under `tenants`".into(),
+            ));
+        }
+        let entries = config
+            .get("tenants")
+            .and_then(Value::as_array)
+            .filter(|entries| !entries.is_empty())
+            .ok_or_else(|| {
+                JWTError::ConfigError("`tenant` needs a non-empty `tenants` list".into())
+            })?;
+        let mut tenants: Vec<Tenant> = Vec::new();
+        for entry in entries {
+            let name = entry
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| {
+                    JWTError::ConfigError("tenants: every tenant needs a `name`".into())
+                })?
+                .to_string();
+            if tenants.iter().any(|earlier| earlier.name == name) {
+                return Err(JWTError::ConfigError(
+                    format!("tenants: `{}` is defined twice", name).into(),
+                ));
+            }
+            let rate_limit = match entry.get("rate_limit") {
+                None => None,
+                Some(rate) => Some(rate.as_f64().filter(|rate| *rate > 0.0).ok_or_else(|| {
+                    JWTError::ConfigError(format!(
+                        "tenant `{}`: `rate_limit` must be a positive number of requests per second",
+                        name
+                    ).into())
+                })?),
+            };
+            policies.add(entry, Some(&name), base, fetch)?;
+            tenants.push(Tenant { name, rate_limit });
+        }
+        policies.tenancy = Some(Tenancy { identify, tenants });
+        Ok(policies)
+    }
+
+    /// Reads the `policies` list of `config`, the whole config or one
+    /// tenant's entry.
+    fn add(
+        &mut self,
+        config: &Value,
+        tenant: Option<&str>,
+        base: &Path,
+        fetch: &Fetch,
+    ) -> Result<(), JWTError> {
+        let within = tenant.map_or(String::new(), |tenant| format!("tenant `{}`: ", tenant));
+        let entries = config
+            .get("policies")
+            .and_then(Value::as_array)
+            .filter(|entries| !entries.is_empty())
+            .ok_or_else(|| {
+                JWTError::ConfigError(
+                    format!("{}expected a non-empty `policies` list", within).into(),
+                )
+            })?;
+        let within_error = |e: JWTError| match e {
+            JWTError::ConfigError(e) => JWTError::ConfigError(format!("{}{}", within, e).into()),
+            e => e,
+        };
+        // a tenant's own access_log, for its policies without one
+        let access_log = match (tenant, config.get("access_log")) {
+            (Some(_), Some(profile)) => Some(
+                LogProfile::from_config(profile, &|why| JWTError::ConfigError(why.into()))
+                    .map_err(within_error)?,
+            ),
+            _ => None,
+        };
+        for entry in entries {
+            let mut policy = Policy::from_config(entry, base, fetch).map_err(within_error)?;
+            policy.tenant = tenant.map(str::to_string);
+            if policy.access_log.is_none() {
+                policy.access_log = access_log.clone();
+            }
+            if self
+                .policies
+                .iter()
+                .any(|earlier| earlier.tenant == policy.tenant && earlier.name == policy.name)
+            {
+                return Err(JWTError::ConfigError(
+                    format!("{}policies: `{}` is defined twice", within, policy.name).into(),
+                ));
+            }
+            for key in ["jwks", "script"] {
+                if let Some(file) = entry.get(key).and_then(Value::as_str) {
+                    self.files.push(base.join(file));
+                }
+            }
+            self.policies.push(policy);
+        }
+        Ok(())
+    }
+
+    /// Reads a config file; its JWKS files are relative to it.
+    pub fn load(path: &Path, fetch: &Fetch) -> Result<Policies, JWTError> {
+        let config = crate::yaml::parse(&std::fs::read_to_string(path)?)
+            .map_err(|e| JWTError::ConfigError(e.into()))?;
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        let mut policies = Policies::from_config(&config, base, fetch)?;
+        policies.files.insert(0, path.to_path_buf());
+        Ok(policies)
+    }
+
+    /// The tenant a request names, if there are tenants, and the path its
+    /// policies are routed on; otherwise the status and error refusing it.
+    pub fn identify<'r>(
+        &self,
+        request: &'r Request,
+    ) -> Result<(Option<&'r str>, &'r str), (u16, String)> {
+        match &self.tenancy {
+            Some(tenancy) => tenancy
+                .identify(request)
+                .map(|(tenant, path)| (Some(tenant), path)),
+            None => Ok((None, request.path.as_str())),
+        }
+    }
+
+    /// The first of a tenant's policies that matches a request.
+    pub fn route(
+        &self,
+        tenant: Option<&str>,
+        path: &str,
+        headers: &[(String, String)],
+    ) -> Option<&Policy> {
+        self.policies.iter().find(|policy| {
+            policy.tenant.as_deref() == tenant && policy.route.matches(path, headers)
+        })
+    }
+
+    fn rate_limit(&self, tenant: &str) -> Option<f64> {
+        self.tenancy
+            .as_ref()?
+            .tenants
+            .iter()
+            .find(|known| known.name == tenant)?
+            .rate_limit
+    }
+}
+
+/// The parts of an HTTP request the sidecar looks at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// Without the query string.
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Why a request was refused before a policy saw it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Refusal {
+    pub status: u16,
+    pub message: String,
+}
+
+fn refusal(status: u16, message: String) -> Refusal {
+    Refusal { status, message }
+}
+
+/// Reads one line, counting it against what is left of the request size
+/// and reserving it from the memory budget.
+fn read_line(
+    reader: &mut impl BufRead,
+    limits: &Limits,
+    left: &mut usize,
+    reservation: &mut Reservation,
+) -> Result<String, Refusal> {
+    let mut line = String::new();
+    let read = reader
+        .take(*left as u64)
+        .read_line(&mut line)
+        .map_err(|e| unreadable(e, limits))?;
+    if !line.ends_with('\n') {
+        return Err(if read == *left {
+            too_large(limits)
+        } else {
+            refusal(400, "bad request: truncated".to_string())
+        });
+    }
+    *left -= read;
+    if !reservation.grow(read) {
+        return Err(busy());
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// A failed read: the deadline, or a connection that broke off.
+fn unreadable(e: std::io::Error, limits: &Limits) -> Refusal {
+    match e.kind() {
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => refusal(
+            408,
+            format!(
+                "request not received within {}s",
+                limits.request_timeout.as_secs()
+            ),
+        ),
+        _ => refusal(400, format!("bad request: {}", e)),
+    }
+}
+
+fn too_large(limits: &Limits) -> Refusal {
+    refusal(
+        413,
+        format!("request larger than {} bytes", limits.max_request),
+    )
+}
+
+fn busy() -> Refusal {
+    refusal(503, "over the memory limit; retry shortly".to_string())
+}
+
+/// Reads one HTTP/1.1 request of at most `limits.max_request` bytes,
+/// reserving what it holds from the memory budget. The body is read into
+/// `body`, which may be a pooled buffer.
+pub fn read_request(
+    reader: &mut impl BufRead,
+    limits: &Limits,
+    reservation: &mut Reservation,
+    mut body: Vec<u8>,
+) -> Result<Request, Refusal> {
+    let mut left = limits.max_request;
+    let line = read_line(reader, limits, &mut left, reservation)?;
+    let mut parts = line.split(' ');
+    let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method.to_string(), target)
+        }
+        _ => {
+            return Err(refusal(
+                400,
+                "bad request: malformed request line".to_string(),
+            ))
+        }
+    };
+    let path = target.split('?').next().unwrap_or_default().to_string();
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader, limits, &mut left, reservation)?;
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(refusal(400, "bad request: too many headers".to_string()));
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| refusal(400, "bad request: malformed header".to_string()))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    body.clear();
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body,
+    };
+    if let Some(length) = request.header("content-length") {
+        let length: usize = length
+            .parse()
+            .map_err(|_| refusal(400, "bad request: bad Content-Length".to_string()))?;
+        if length > left {
+            return Err(too_large(limits));
+        }
+        if !reservation.grow(length) {
+            return Err(busy());
+        }
+        request.body.resize(length, 0);
+        reader
+            .read_exact(&mut request.body)
+            .map_err(|e| unreadable(e, limits))?;
+    }
+    Ok(request)
+}
+
+/// The token a request carries: from `Authorization: Bearer`, or else the
+/// body.
+fn bearer(request: &Request) -> String {
+    match request.header("authorization") {
+        Some(value) => extract_token(value).to_string(),
+        None => extract_token(&String::from_utf8_lossy(&request.body)).to_string(),
+    }
+}
+
+/// The answer for a token `policy` passed, or failed with `failures` only
+/// in its claims, once its script has run.
+fn scripted(
+    script: &Script,
+    policy: &Policy,
+    token: &JWToken,
+    failures: &[String],
+    now: i64,
+) -> (u16, Value) {
+    let context = json!({
+        "mode": "serve",
+        "verified": true,
+        "now": now,
+        "failures": failures,
+        "policy": policy.name,
+        "tenant": policy.tenant,
+    });
+    let outcome = script.run(token, &context);
+    let passes = outcome.passes(failures.is_empty());
+    let mut body = match (passes, outcome.denials.is_empty()) {
+        (true, _) => json!({"valid": true, "claims": token.payload}),
+        (false, true) => json!({"valid": false, "errors": failures}),
+        (false, false) => json!({"valid": false, "errors": outcome.denials}),
+    };
+    if let Some(fields) = body.as_object_mut() {
+        if !outcome.findings.is_empty() {
+            fields.insert("findings".to_string(), outcome.findings.into());
+        }
+        if let (true, Some(reason)) = (!failures.is_empty(), outcome.allowed) {
+            fields.insert("allowed".to_string(), reason.into());
+        }
+    }
+    (if passes { 200 } else { 401 }, body)
+}
+
+/// The status and JSON body answering `request`, and the policy that
+/// checked it.
+pub fn respond<'a>(
+    policies: &'a Policies,
+    request: &Request,
+    now: i64,
+) -> (u16, Value, Option<&'a Policy>) {
+    let (tenant, path) = match policies.identify(request) {
+        Ok(named) => named,
+        Err((status, error)) => return (status, json!({"valid": false, "errors": [error]}), None),
+    };
+    let policy = match policies.route(tenant, path, &request.headers) {
+        Some(policy) => policy,
+        None => {
+            let error = match tenant {
+                Some(tenant) => format!("no policy for {} in tenant {}", path, tenant),
+                None => format!("no policy for {}", path),
+            };
+            return (404, json!({"valid": false, "errors": [error]}), None);
+        }
+    };
+    let answer = |status: u16, mut body: Value| {
+        if let Some(fields) = body.as_object_mut() {
+            fields.insert("policy".to_string(), policy.name.clone().into());
+            if let Some(tenant) = &policy.tenant {
+                fields.insert("tenant".to_string(), tenant.clone().into());
+            }
+        }
+        (status, body, Some(policy))
+    };
+    let raw = bearer(request);
+    if raw.is_empty() {
+        let error = "no token: send it as `Authorization: Bearer` or as the body";
+        return answer(400, json!({"valid": false, "errors": [error]}));
+    }
+    if policy.keys_expired(now) {
+        let error = format!(
+            "the keys were fetched {} ago and could not be refreshed since",
+            humanize_seconds(policy.key_age(now).unwrap_or_default())
+        );
+        return answer(503, json!({"valid": false, "errors": [error]}));
+    }
+    let (status, mut body) = match (policy.check(&raw, now), &policy.script) {
+        (Ok(token), Some(script)) => scripted(script, policy, &token, &[], now),
+        (Ok(token), None) => (200, json!({"valid": true, "claims": token.payload})),
+        (
+            Err(Rejection {
+                errors,
+                token: Some(token),
+            }),
+            Some(script),
+        ) => scripted(script, policy, &token, &errors, now),
+        (Err(rejection), _) => (401, json!({"valid": false, "errors": rejection.errors})),
+    };
+    if let (Some(body), Some(age)) = (body.as_object_mut(), policy.key_age(now)) {
+        body.insert("key_age".to_string(), age.into());
+        body.insert("key_stale".to_string(), (age >= policy.jwks_ttl).into());
+    }
+    answer(status, body)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+pub(crate) fn write_response(
+    mut stream: &TcpStream,
+    status: u16,
+    content_type: &str,
+    body: &str,
+) -> Result<(), JWTError> {
+    let retry = if status == 429 || status == 503 {
+        "Retry-After: 1\r\n"
+    } else {
+        ""
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        content_type,
+        body.len(),
+        retry,
+        body
+    )?;
+    Ok(())
+}
+
+fn current(shared: &Shared) -> Result<Arc<Policies>, JWTError> {
+    shared
+        .read()
+        .map(|policies| Arc::clone(&policies))
+        .map_err(|_| JWTError::InvalidArgumentError("policies lock poisoned".into()))
+}
+
+/// How large requests may be, how much memory they may hold at once, how
+/// many connections are served at once and how long each may take to send
+/// its request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Request line, headers and body together.
+    pub max_request: usize,
+    pub memory: usize,
+    /// Connections over it are answered `503` without a thread of their own.
+    pub connections: usize,
+    /// From accepting the connection to the last byte of the body.
+    pub request_timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_request: 1 << 20,
+            memory: 64 << 20,
+            connections: 256,
+            request_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A connection read only until `until`, however slowly the client sends,
+/// so a trickle of bytes can't hold a thread past the request deadline.
+struct Deadlined<'a> {
+    stream: &'a TcpStream,
+    until: Instant,
+}
+
+impl Read for Deadlined<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        let mut stream = self.stream;
+        stream.read(buffer)
+    }
+}
+
+/// The request memory in use across connections, held to a limit. A
+/// request that would go over it is refused with `503` rather than queued,
+/// so a burst of giant tokens can't grow the process.
+#[derive(Debug)]
+pub struct Budget {
+    limit: usize,
+    used: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl Budget {
+    pub fn new(limit: usize) -> Budget {
+        Budget {
+            limit,
+            used: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn reservation(&self) -> Reservation<'_> {
+        Reservation {
+            budget: self,
+            bytes: 0,
+        }
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+}
+
+/// Memory one request holds, given back when it is dropped.
+#[derive(Debug)]
+pub struct Reservation<'a> {
+    budget: &'a Budget,
+    bytes: usize,
+}
+
+impl Reservation<'_> {
+    /// Reserves `bytes` more, unless that would go over the limit.
+    pub fn grow(&mut self, bytes: usize) -> bool {
+        let budget = self.budget;
+        let reserved = budget
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes)
+                    .filter(|total| *total <= budget.limit)
+            });
+        match reserved {
+            Ok(before) => {
+                self.bytes += bytes;
+                budget.peak.fetch_max(before + bytes, Ordering::SeqCst);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+/// Request body buffers, kept for the next request. A buffer a giant token
+/// grew past `POOL_KEEP` is freed instead, so memory goes back down after
+/// a burst.
+#[derive(Debug, Default)]
+pub struct Pool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+}
+
+impl Pool {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Vec<u8>>> {
+        self.buffers
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    pub fn take(&self) -> Vec<u8> {
+        self.lock().pop().unwrap_or_default()
+    }
+
+    pub fn give(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() > POOL_KEEP {
+            return;
+        }
+        buffer.clear();
+        let mut buffers = self.lock();
+        if buffers.len() < POOL_BUFFERS {
+            buffers.push(buffer);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The requests being answered, and how many got each status, by tenant
+/// (`""` without tenants, or before the tenant is known).
+#[derive(Debug, Default)]
+struct Tally {
+    in_flight: AtomicUsize,
+    statuses: Mutex<BTreeMap<(String, u16), usize>>,
+}
+
+impl Tally {
+    fn statuses(&self) -> std::sync::MutexGuard<'_, BTreeMap<(String, u16), usize>> {
+        self.statuses
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn record(&self, tenant: &str, status: u16) {
+        *self
+            .statuses()
+            .entry((tenant.to_string(), status))
+            .or_default() += 1;
+    }
+
+    fn summary(&self) -> String {
+        let mut statuses = BTreeMap::new();
+        for ((_, status), count) in self.statuses().iter() {
+            *statuses.entry(*status).or_insert(0) += count;
+        }
+        let total: usize = statuses.values().sum();
+        let counts: Vec<String> = statuses
+            .iter()
+            .map(|(status, count)| format!("{} {}", count, status))
+            .collect();
+        match counts.is_empty() {
+            true => format!("answered {} request(s)", total),
+            false => format!("answered {} request(s): {}", total, counts.join(", ")),
+        }
+    }
+}
+
+/// A token bucket per rate-limited tenant, refilled at its `rate_limit`
+/// and holding up to a second's worth.
+#[derive(Debug, Default)]
+struct Limiter {
+    buckets: Mutex<BTreeMap<String, (f64, Instant)>>,
+}
+
+impl Limiter {
+    fn allow(&self, tenant: &str, rate: f64, now: Instant) -> bool {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let capacity = rate.max(1.0);
+        let (tokens, at) = buckets.entry(tenant.to_string()).or_insert((capacity, now));
+        let refill = now.saturating_duration_since(*at).as_secs_f64() * rate;
+        *tokens = (*tokens + refill).min(capacity);
+        *at = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+/// Everything the connection threads share.
+#[derive(Debug)]
+struct State {
+    policies: Shared,
+    limits: Limits,
+    budget: Budget,
+    pool: Pool,
+    tally: Tally,
+    limiter: Limiter,
+    tracer: Option<Exporter>,
+}
+
+impl State {
+    /// `GET /metrics`, in the Prometheus text format.
+    fn metrics(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, usize)>| {
+            out.push_str(&format!(
+                "# HELP jwt_check_{} {}\n# TYPE jwt_check_{} {}\n",
+                name, help, name, kind
+            ));
+            for (labels, value) in samples {
+                out.push_str(&format!("jwt_check_{}{} {}\n", name, labels, value));
+            }
+        };
+        let statuses = self
+            .tally
+            .statuses()
+            .iter()
+            .map(|((tenant, status), count)| {
+                let labels = match tenant.as_str() {
+                    "" => format!("{{status=\"{}\"}}", status),
+                    tenant => format!("{{tenant=\"{}\",status=\"{}\"}}", tenant, status),
+                };
+                (labels, *count)
+            })
+            .collect();
+        let value = |value: usize| vec![(String::new(), value)];
+        metric(
+            "requests_total",
+            "counter",
+            "Requests answered, by tenant and status.",
+            statuses,
+        );
+        metric(
+            "requests_in_flight",
+            "gauge",
+            "Requests being answered.",
+            value(self.tally.in_flight.load(Ordering::SeqCst)),
+        );
+        metric(
+            "memory_bytes",
+            "gauge",
+            "Request memory held now.",
+            value(self.budget.used()),
+        );
+        metric(
+            "memory_peak_bytes",
+            "gauge",
+            "Most request memory held at once.",
+            value(self.budget.peak.load(Ordering::SeqCst)),
+        );
+        metric(
+            "memory_limit_bytes",
+            "gauge",
+            "The --memory-limit.",
+            value(self.budget.limit),
+        );
+        metric(
+            "pooled_buffers",
+            "gauge",
+            "Request buffers kept for reuse.",
+            value(self.pool.len()),
+        );
+        let now = crate::unix_now();
+        let ages = current(&self.policies)
+            .map(|policies| {
+                policies
+                    .policies
+                    .iter()
+                    .filter_map(|policy| {
+                        let age = policy.key_age(now)?;
+                        let labels = match &policy.tenant {
+                            Some(tenant) => {
+                                format!("{{tenant=\"{}\",policy=\"{}\"}}", tenant, policy.name)
+                            }
+                            None => format!("{{policy=\"{}\"}}", policy.name),
+                        };
+                        Some((labels, age as usize))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        metric(
+            "key_age_seconds",
+            "gauge",
+            "How long ago each jwks_url policy's keys were fetched.",
+            ages,
+        );
+        out
+    }
+}
+
+/// Answers one request, returning its tenant, if known, and its status.
+/// The request must arrive within `request_timeout` of `accepted`.
+fn handle(stream: TcpStream, state: &State, accepted: Instant) -> Result<(String, u16), JWTError> {
+    let policies = current(&state.policies)?;
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(state.limits.request_timeout))?;
+    let mut reader = BufReader::new(Deadlined {
+        stream: &stream,
+        until: accepted + state.limits.request_timeout,
+    });
+    let mut reservation = state.budget.reservation();
+    let buffer = state.pool.take();
+    let request = match read_request(&mut reader, &state.limits, &mut reservation, buffer) {
+        Ok(request) => request,
+        Err(refused) => {
+            let body = json!({"valid": false, "errors": [refused.message]}).to_string();
+            write_response(&stream, refused.status, "application/json", &body)?;
+            return Ok((String::new(), refused.status));
+        }
+    };
+    if request.method == "GET" && request.path == METRICS_PATH {
+        let text = state.metrics();
+        write_response(&stream, 200, "text/plain; version=0.0.4", &text)?;
+        state.pool.give(request.body);
+        return Ok((String::new(), 200));
+    }
+    if state.tracer.is_some() {
+        otlp::begin(request.header("traceparent"));
+    }
+    let tenant = match policies.identify(&request) {
+        Ok((Some(tenant), _)) => tenant.to_string(),
+        _ => String::new(),
+    };
+    let limited = policies
+        .rate_limit(&tenant)
+        .filter(|rate| !state.limiter.allow(&tenant, *rate, Instant::now()));
+    let (status, body, policy) = match limited {
+        Some(rate) => {
+            let error = format!(
+                "tenant {} is over its rate limit of {} request(s) per second",
+                tenant, rate
+            );
+            let body = json!({"valid": false, "tenant": tenant, "errors": [error]});
+            (429, body, None)
+        }
+        None => respond(&policies, &request, crate::unix_now()),
+    };
+    // only the claims the policy's access_log allows, whatever the verdict
+    let claims = match (policy, parse(bearer(&request))) {
+        (Some(policy), Ok(token)) => policy
+            .access_log
+            .as_ref()
+            .unwrap_or(&policies.access_log)
+            .render(&token),
+        _ => String::new(),
+    };
+    // a script's warnings make a passed token worth a look
+    let warned = body
+        .get("findings")
+        .and_then(Value::as_array)
+        .is_some_and(|findings| !findings.is_empty());
+    let priority = match Priority::of_status(status) {
+        Priority::Info if warned => Priority::Warning,
+        priority => priority,
+    };
+    let line = format!(
+        "{} {} {} {}{}",
+        request.method,
+        request.path,
+        status,
+        policy.map_or("-".to_string(), Policy::label),
+        claims
+    );
+    syslog::log(priority, &line);
+    if let Some(tracer) = &state.tracer {
+        let mut attributes = vec![
+            ("http.request.method", json!(request.method)),
+            ("url.path", json!(request.path)),
+            ("http.response.status_code", json!(status)),
+        ];
+        if let Some(policy) = policy {
+            attributes.push(("jwt_check.policy", json!(policy.name)));
+        }
+        if !tenant.is_empty() {
+            attributes.push(("jwt_check.tenant", json!(tenant)));
+        }
+        // as for HTTP servers, only a fault of the sidecar's own is an error
+        let error = (status >= 500).then(|| {
+            body.pointer("/errors/0")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        });
+        let name = match policy {
+            Some(policy) => format!("{} {}", request.method, policy.label()),
+            None => request.method.clone(),
+        };
+        tracer.export(otlp::end(name, attributes, error));
+    }
+    write_response(&stream, status, "application/json", &body.to_string())?;
+    state.pool.give(request.body);
+    Ok((tenant, status))
+}
+
+/// Counts a request as in flight until its thread finishes, however it
+/// finishes.
+struct InFlight(Arc<State>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.tally.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Answers verification requests, one thread per connection and at most
+/// `limits.connections` at once, until `stop` says to. A connection over the
+/// cap is answered `503` on the accepting thread. It then stops accepting
+/// connections and waits up to `drain` for the requests in flight, failing
+/// with how many were left unfinished. Requests are traced to `tracer`.
+pub fn serve(
+    listener: TcpListener,
+    policies: &Shared,
+    limits: Limits,
+    tracer: Option<Exporter>,
+    stop: &dyn Fn() -> bool,
+    drain: Duration,
+) -> Result<(), JWTError> {
+    let state = Arc::new(State {
+        policies: Arc::clone(policies),
+        limits,
+        budget: Budget::new(limits.memory),
+        pool: Pool::default(),
+        tally: Tally::default(),
+        limiter: Limiter::default(),
+        tracer,
+    });
+    // polled, so that a stop is noticed without waiting for a connection
+    listener.set_nonblocking(true)?;
+    while !stop() {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(ACCEPT_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                syslog::log(Priority::Err, &format!("accept failed: {}", e));
+                continue;
+            }
+        };
+        if state.tally.in_flight.load(Ordering::SeqCst) >= limits.connections {
+            let error = format!(
+                "over the limit of {} connections; retry shortly",
+                limits.connections
+            );
+            let body = json!({"valid": false, "errors": [error]}).to_string();
+            let refused = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_write_timeout(Some(ACCEPT_INTERVAL)))
+                .map_err(JWTError::from)
+                .and_then(|_| write_response(&stream, 503, "application/json", &body));
+            match refused {
+                Ok(()) => state.tally.record("", 503),
+                Err(e) => syslog::log(Priority::Err, &format!("request failed: {}", e)),
+            }
+            continue;
+        }
+        let accepted = Instant::now();
+        state.tally.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlight(Arc::clone(&state));
+        std::thread::spawn(move || {
+            match handle(stream, &guard.0, accepted) {
+                Ok((tenant, status)) => guard.0.tally.record(&tenant, status),
+                Err(e) => syslog::log(Priority::Err, &format!("request failed: {}", e)),
+            }
+            drop(guard);
+        });
+    }
+    drop(listener);
+    let in_flight = || state.tally.in_flight.load(Ordering::SeqCst);
+    let deadline = Instant::now() + drain;
+    if in_flight() > 0 {
+        let line = format!(
+            "serve: stopped accepting; draining {} request(s) in flight",
+            in_flight()
+        );
+        syslog::log(Priority::Notice, &line);
+    }
+    while in_flight() > 0 && Instant::now() < deadline {
+        std::thread::sleep(ACCEPT_INTERVAL);
+    }
+    syslog::log(
+        Priority::Notice,
+        &format!("serve: {}", state.tally.summary()),
+    );
+    match in_flight() {
+        0 => Ok(()),
+        unfinished => Err(JWTError::ShutdownError(unfinished)),
+    }
+}
+
+fn kids(keys: &Value) -> Vec<&str> {
+    keys.get("keys")
+        .and_then(Value::as_array)
+        .map(|keys| {
+            keys.iter()
+                .map(|jwk| jwk.get("kid").and_then(Value::as_str).unwrap_or("(no kid)"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn changes(old: &Policy, new: &Policy) -> Vec<String> {
+    let mut changes = Vec::new();
+    let mut field = |name: &str, before: Value, after: Value| {
+        if before != after {
+            changes.push(format!(
+                "policy {}: {} {} -> {}",
+                new.label(),
+                name,
+                before,
+                after
+            ));
+        }
+    };
+    let route = |policy: &Policy| {
+        let header = policy
+            .route
+            .header
+            .as_ref()
+            .map(|(name, value)| format!("{}: {}", name, value));
+        json!({"path": policy.route.path, "header": header})
+    };
+    let algorithms = |policy: &Policy| {
+        policy
+            .algorithms
+            .iter()
+            .map(|alg| Value::String(alg.to_string()))
+            .collect::<Value>()
+    };
+    field("route", route(old), route(new));
+    field("issuers", json!(old.issuers), json!(new.issuers));
+    field("audiences", json!(old.audiences), json!(new.audiences));
+    field("algorithms", algorithms(old), algorithms(new));
+    field("leeway", json!(old.leeway), json!(new.leeway));
+    field("required_claims", json!(old.required), json!(new.required));
+    field("jwks_url", json!(old.jwks_url), json!(new.jwks_url));
+    field("jwks_ttl", json!(old.jwks_ttl), json!(new.jwks_ttl));
+    field("max_stale", json!(old.max_stale), json!(new.max_stale));
+    let access_log = |policy: &Policy| policy.access_log.as_ref().map(LogProfile::to_json);
+    field("access_log", json!(access_log(old)), json!(access_log(new)));
+    field("keys", json!(kids(&old.keys)), json!(kids(&new.keys)));
+    if kids(&old.keys) == kids(&new.keys) && old.keys != new.keys {
+        changes.push(format!(
+            "policy {}: keys {} changed in place",
+            new.label(),
+            json!(kids(&new.keys))
+        ));
+    }
+    if old.script != new.script {
+        changes.push(format!("policy {}: script changed", new.label()));
+    }
+    changes
+}
+
+fn tenancy_changes(old: Option<&Tenancy>, new: Option<&Tenancy>) -> Vec<String> {
+    let mut lines = Vec::new();
+    let identify = |tenancy: Option<&Tenancy>| match tenancy.map(|t| &t.identify) {
+        Some(Identify::Header(name)) => json!({ "header": name }),
+        Some(Identify::Prefix(prefix)) => json!({ "prefix": prefix }),
+        None => Value::Null,
+    };
+    if identify(old) != identify(new) {
+        lines.push(format!("tenant {} -> {}", identify(old), identify(new)));
+    }
+    let (old, new) = (
+        old.map_or(&[][..], |t| t.tenants.as_slice()),
+        new.map_or(&[][..], |t| t.tenants.as_slice()),
+    );
+    for tenant in old {
+        if !new.iter().any(|t| t.name == tenant.name) {
+            lines.push(format!("tenant {}: removed", tenant.name));
+        }
+    }
+    for tenant in new {
+        match old.iter().find(|t| t.name == tenant.name) {
+            Some(previous) if previous.rate_limit != tenant.rate_limit => lines.push(format!(
+                "tenant {}: rate_limit {} -> {}",
+                tenant.name,
+                json!(previous.rate_limit),
+                json!(tenant.rate_limit)
+            )),
+            Some(_) => {}
+            None => lines.push(format!("tenant {}: added", tenant.name)),
+        }
+    }
+    lines
+}
+
+/// What a reload changed: one line per tenant or policy added or removed
+/// and per field changed, with the old and new values as JSON.
+pub fn diff(old: &Policies, new: &Policies) -> Vec<String> {
+    let mut lines = tenancy_changes(old.tenancy.as_ref(), new.tenancy.as_ref());
+    if old.access_log != new.access_log {
+        lines.push(format!(
+            "access_log {} -> {}",
+            old.access_log.to_json(),
+            new.access_log.to_json()
+        ));
+    }
+    let same = |a: &Policy, b: &Policy| a.tenant == b.tenant && a.name == b.name;
+    for policy in &old.policies {
+        if !new.policies.iter().any(|p| same(p, policy)) {
+            lines.push(format!("policy {}: removed", policy.label()));
+        }
+    }
+    for policy in &new.policies {
+        match old.policies.iter().find(|p| same(p, policy)) {
+            Some(previous) => lines.extend(changes(previous, policy)),
+            None => lines.push(format!("policy {}: added", policy.label())),
+        }
+    }
+    // the order policies are tried in, among those in both
+    let order = |policies: &Policies| {
+        policies
+            .policies
+            .iter()
+            .map(Policy::label)
+            .filter(|label| {
+                old.policies.iter().any(|p| p.label() == *label)
+                    && new.policies.iter().any(|p| p.label() == *label)
+            })
+            .collect::<Vec<_>>()
+    };
+    if order(old) != order(new) {
+        lines.push(format!(
+            "order {} -> {}",
+            json!(order(old)),
+            json!(order(new))
+        ));
+    }
+    lines
+}
+
+/// When each file was last modified, and its size; `None` for a file that
+/// cannot be read.
+fn stamps(files: &[PathBuf]) -> Vec<Option<(SystemTime, u64)>> {
+    files
+        .iter()
+        .map(|file| {
+            let metadata = std::fs::metadata(file).ok()?;
+            Some((metadata.modified().ok()?, metadata.len()))
+        })
+        .collect()
+}
+
+fn replace(shared: &Shared, policies: Policies) -> Result<(), JWTError> {
+    let mut slot = shared
+        .write()
+        .map_err(|_| JWTError::InvalidArgumentError("policies lock poisoned".into()))?;
+    *slot = Arc::new(policies);
+    Ok(())
+}
+
+/// Refetches the keys of the policies whose `jwks_url` keys are past their
+/// TTL, swapping in the refreshed policies. A failed refetch is logged and
+/// retried after `REFRESH_RETRY`, and the stale keys stay in use meanwhile.
+fn refresh_keys(
+    shared: &Shared,
+    fetch: &Fetch,
+    attempts: &mut BTreeMap<String, Instant>,
+) -> Result<(), JWTError> {
+    let now = crate::unix_now();
+    let running = current(shared)?;
+    let mut policies = Policies::clone(&running);
+    let mut refreshed = false;
+    for policy in policies.policies.iter_mut() {
+        if policy.key_age(now).is_none_or(|age| age < policy.jwks_ttl) {
+            continue;
+        }
+        let label = policy.label();
+        if attempts
+            .get(&label)
+            .is_some_and(|at| at.elapsed() < REFRESH_RETRY)
+        {
+            continue;
+        }
+        attempts.insert(label.clone(), Instant::now());
+        let before = policy.keys.clone();
+        match policy.refresh(fetch) {
+            Ok(()) => {
+                attempts.remove(&label);
+                refreshed = true;
+                if kids(&before) != kids(&policy.keys) {
+                    let line = format!(
+                        "refresh: policy {}: keys {} -> {}",
+                        label,
+                        json!(kids(&before)),
+                        json!(kids(&policy.keys))
+                    );
+                    syslog::log(Priority::Notice, &line);
+                }
+            }
+            Err(e) => syslog::log(
+                Priority::Err,
+                &format!("refresh failed for policy {}: {}", label, e),
+            ),
+        }
+    }
+    if refreshed {
+        replace(shared, policies)?;
+    }
+    Ok(())
+}
+
+/// Reloads the policies whenever one of their files changes or `hangup`
+/// says a reload was asked for, logging what changed, and refetches
+/// `jwks_url` keys as they go stale. Runs until `stop` says to.
+///
+/// Verification never waits on a refetch: requests keep reading the
+/// policies they were handed, and a refreshed copy is swapped in whole.
+pub fn watch(
+    shared: &Shared,
+    reload: &dyn Fn() -> Result<Policies, JWTError>,
+    fetch: &Fetch,
+    hangup: &dyn Fn() -> bool,
+    stop: &dyn Fn() -> bool,
+) -> Result<(), JWTError> {
+    let mut seen = stamps(&current(shared)?.files);
+    let mut attempts = BTreeMap::new();
+    while !stop() {
+        std::thread::sleep(WATCH_INTERVAL);
+        refresh_keys(shared, fetch, &mut attempts)?;
+        let files = current(shared)?.files.clone();
+        if !hangup() && stamps(&files) == seen {
+            continue;
+        }
+        // a file caught halfway through being written fails to load, and is
+        // retried when the write finishes and changes it again
+        seen = stamps(&files);
+        match reload() {
+            Ok(policies) => {
+                let lines = diff(current(shared)?.as_ref(), &policies);
+                if lines.is_empty() {
+                    syslog::log(Priority::Info, "reload: no changes");
+                }
+                for line in lines {
+                    syslog::log(Priority::Notice, &format!("reload: {}", line));
+                }
+                seen = stamps(&policies.files);
+                replace(shared, policies)?;
+            }
+            Err(e) => syslog::log(
+                Priority::Err,
+                &format!("reload failed, keeping the current policies: {}", e),
+            ),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::SigningKey;
+    use crate::sign::sign;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn jwks(kid: &str, secret: &[u8]) -> String {
+        json!({"keys": [{"kty": "oct", "kid": kid,
+            "k": base64::encode_config(secret, base64::URL_SAFE_NO_PAD)}]})
+        .to_string()
+    }
+
+    fn request(path: &str, headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn per_route_policies_test() {
+        let config = crate::yaml::parse(
+            "policies:\n  - name: internal\n    path: /verify/internal\n    issuers: [https://idp.internal]\n    audiences: [api://orders]\n    algorithms: [HS256]\n    jwks: internal.jwks\n  - name: partner\n    header: {X-Trust-Domain: partner}\n    issuers: [https://partner]\n    algorithms: [HS384]\n    jwks_url: http://partner/jwks\n    resolve: partner:10.20.0.5\n    leeway: 1m\n",
+        )
+        .unwrap();
+        let base =
+            std::env::temp_dir().join(format!("jwt-check-serve-test-{}", std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        std::fs::write(base.join("internal.jwks"), jwks("in-1", b"internal secret")).unwrap();
+        let fetch = |url: &str, pins: &[String]| {
+            assert_eq!(
+                (url, pins),
+                (
+                    "http://partner/jwks",
+                    &["partner:10.20.0.5".to_string()][..]
+                )
+            );
+            Ok((jwks("pa-1", b"partner secret").into_bytes(), Duration::ZERO))
+        };
+        let mut policies = Policies::from_config(&config, &base, &fetch).unwrap();
+        assert_eq!(policies.files, [base.join("internal.jwks")]);
+        // as every document the tool prints, the dump has its keys sorted
+        let dump = serde_json::to_string(&policies).unwrap();
+        assert_eq!(
+            dump,
+            serde_json::from_str::<Value>(&dump).unwrap().to_string()
+        );
+        let pinned_file = crate::yaml::parse(
+            "policies:\n  - name: internal\n    jwks: internal.jwks\n    resolve: idp:10.0.0.1\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            Policies::from_config(&pinned_file, &base, &fetch),
+            Err(JWTError::ConfigError(e))
+                if e == "policy `internal`: `resolve` is for fetching a `jwks_url`"
+        ));
+        std::fs::remove_dir_all(&base).unwrap();
+
+        let token = |alg: &str, kid: &str, secret: &[u8], claims: Value| {
+            sign(
+                &json!({"alg": alg, "kid": kid}),
+                &claims,
+                &SigningKey::Hmac(secret.to_vec()),
+            )
+            .unwrap()
+        };
+        let internal = token(
+            "HS256",
+            "in-1",
+            b"internal secret",
+            json!({"iss": "https://idp.internal", "aud": ["api://orders"], "exp": NOW + 60}),
+        );
+        let bearer = format!("Bearer {}", internal);
+        let (status, body, _) = respond(
+            &policies,
+            &request("/verify/internal", &[("authorization", &bearer)]),
+            NOW,
+        );
+        assert_eq!(status, 200);
+        assert_eq!(body["policy"], "internal");
+        assert_eq!(body["claims"]["iss"], "https://idp.internal");
+        let schema = crate::schema::Output::Verification.schema(1).unwrap();
+        assert!(crate::schema::check(&schema, &body).is_empty());
+
+        // the same token under the partner policy: wrong issuer, alg and key
+        let (status, body, policy) = respond(
+            &policies,
+            &request(
+                "/verify",
+                &[("x-trust-domain", "partner"), ("Authorization", &bearer)],
+            ),
+            NOW,
+        );
+        assert_eq!(
+            (status, policy.map(|p| p.name.as_str())),
+            (401, Some("partner"))
+        );
+        assert_eq!(body["errors"][0], "alg HS256 is not allowed");
+        assert_eq!(
+            body["errors"][1],
+            "iss \"https://idp.internal\" is not an allowed issuer"
+        );
+        assert!(crate::schema::check(&schema, &body).is_empty());
+
+        // within the partner's leeway
+        let partner = token(
+            "HS384",
+            "pa-1",
+            b"partner secret",
+            json!({"iss": "https://partner", "exp": NOW - 30}),
+        );
+        let mut by_body = request("/verify", &[("X-Trust-Domain", "partner")]);
+        by_body.body = partner.into_bytes();
+        let (status, body, _) = respond(&policies, &by_body, NOW);
+        assert_eq!(
+            (status, &body["key_age"], &body["key_stale"]),
+            (200, &json!(0), &json!(false))
+        );
+        assert!(body["claims"].is_object());
+
+        // stale keys are still used while the refetch is pending, and refused
+        // past max_stale
+        policies.policies[1].fetched = NOW - 600;
+        let (status, body, _) = respond(&policies, &by_body, NOW);
+        assert_eq!(
+            (status, &body["key_age"], &body["key_stale"]),
+            (200, &json!(600), &json!(true))
+        );
+        policies.policies[1].fetched = NOW - 3_901;
+        let (status, body, _) = respond(&policies, &by_body, NOW);
+        assert_eq!(status, 503);
+        assert!(body["errors"][0]
+            .as_str()
+            .unwrap()
+            .starts_with("the keys were fetched 1 hour"));
+        // file keys have no age
+        let internal_request = request("/verify/internal", &[("authorization", &bearer)]);
+        let (_, body, _) = respond(&policies, &internal_request, NOW);
+        assert!(body.get("key_age").is_none());
+
+        // a script can add findings, or fail a token the policy passed
+        policies.policies[0].script = Some(
+            "if token.payload.exp - context.now < 300 { warn(\"short-lived\"); }"
+                .parse()
+                .unwrap(),
+        );
+        let (status, body, _) = respond(&policies, &internal_request, NOW);
+        assert_eq!((status, &body["findings"]), (200, &json!(["short-lived"])));
+        policies.policies[0].script = Some(
+            "if \"api://orders\" in token.payload.aud && context.policy == \"internal\" { deny(\"not for orders\"); }"
+                .parse()
+                .unwrap(),
+        );
+        let (status, body, _) = respond(&policies, &internal_request, NOW);
+        assert_eq!(
+            (status, &body["errors"], &body["valid"]),
+            (401, &json!(["not for orders"]), &json!(false))
+        );
+        // or allow one that failed only its claims, never a bad signature
+        policies.policies[0].script = Some(
+            "if context.failures.len() == 1 && context.failures[0].starts_with(\"expired at \") { allow(\"replay\"); }"
+                .parse()
+                .unwrap(),
+        );
+        let expired = |secret: &[u8]| {
+            let claims =
+                json!({"iss": "https://idp.internal", "aud": ["api://orders"], "exp": NOW - 60});
+            let bearer = format!("Bearer {}", token("HS256", "in-1", secret, claims));
+            respond(
+                &policies,
+                &request("/verify/internal", &[("authorization", &bearer)]),
+                NOW,
+            )
+        };
+        let (status, body, _) = expired(b"internal secret");
+        assert_eq!((status, &body["allowed"]), (200, &json!("replay")));
+        let (status, body, _) = expired(b"guessed");
+        assert_eq!(status, 401);
+        assert!(body.get("allowed").is_none());
+
+        let (status, _, policy) = respond(&policies, &request("/verify/other", &[]), NOW);
+        assert_eq!((status, policy), (404, None));
+        assert_eq!(
+            respond(&policies, &request("/verify/internal", &[]), NOW).0,
+            400
+        );
+    }
+
+    #[test]
+    fn required_claims_test() {
+        let fetch =
+            |_: &str, _: &[String]| Ok((jwks("k1", b"secret").into_bytes(), Duration::ZERO));
+        let policy = |extra: &str| {
+            let config =
+                crate::yaml::parse(&format!("name: a\njwks_url: http://idp/jwks\n{}", extra))
+                    .unwrap();
+            Policy::from_config(&config, Path::new("/etc/jwt-check"), &fetch).unwrap()
+        };
+        let token = |claims: Value| {
+            sign(
+                &json!({"alg": "HS256", "kid": "k1"}),
+                &claims,
+                &SigningKey::Hmac(b"secret".to_vec()),
+            )
+            .unwrap()
+        };
+        let strict = policy("");
+        assert_eq!(strict.required, ["exp"]);
+        assert!(strict.check(&token(json!({"exp": NOW + 60})), NOW).is_ok());
+        assert_eq!(
+            strict
+                .check(&token(json!({"sub": "a"})), NOW)
+                .unwrap_err()
+                .errors,
+            ["exp is missing"]
+        );
+        assert_eq!(
+            strict
+                .check(&token(json!({"exp": "1000"})), NOW)
+                .unwrap_err()
+                .errors,
+            ["exp is \"1000\", not a NumericDate"]
+        );
+        let relaxed = policy("required_claims: []\n");
+        assert!(relaxed.check(&token(json!({"sub": "a"})), NOW).is_ok());
+        assert_eq!(
+            policy("required_claims: [exp, sub]\n")
+                .check(&token(json!({"exp": NOW + 60})), NOW)
+                .unwrap_err()
+                .errors,
+            ["sub is missing"]
+        );
+    }
+
+    #[test]
+    fn traced_check_test() {
+        let fetch =
+            |_: &str, _: &[String]| Ok((jwks("k1", b"secret").into_bytes(), Duration::ZERO));
+        let config = crate::yaml::parse(
+            "policies:\n  - name: internal\n    issuers: [https://idp]\n    jwks_url: http://idp/jwks\n",
+        )
+        .unwrap();
+        let policies = Policies::from_config(&config, Path::new("/etc/jwt-check"), &fetch).unwrap();
+        let policy = &policies.policies[0];
+        let forged = sign(
+            &json!({"alg": "HS256", "kid": "k1"}),
+            &json!({"iss": "https://idp", "exp": NOW + 60}),
+            &SigningKey::Hmac(b"guessed".to_vec()),
+        )
+        .unwrap();
+        otlp::begin(None);
+        assert!(policy.check(&forged, NOW).is_err());
+        let spans = otlp::end("POST internal".to_string(), Vec::new(), None);
+        let steps: Vec<(&str, Option<&str>)> = spans
+            .iter()
+            .skip(1)
+            .map(|span| (span.name.as_str(), span.error.as_deref()))
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                ("decode", None),
+                ("claim validation", None),
+                ("key resolution", None),
+                ("signature check", Some("Error: Invalid signature")),
+            ]
+        );
+        otlp::begin(None);
+        assert!(policy.check("not a token", NOW).is_err());
+        let spans = otlp::end("POST internal".to_string(), Vec::new(), None);
+        assert_eq!(spans.len(), 2);
+        assert!(spans[1].error.is_some());
+        // untraced, the check records nothing
+        assert!(policy.check(&forged, NOW).is_err());
+        assert!(otlp::end("POST".to_string(), Vec::new(), None).is_empty());
+    }
+
+    #[test]
+    fn memory_limits_test() {
+        const REQUEST: &[u8] =
+            b"POST /verify/internal?x=1 HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabc";
+        let limits = Limits {
+            max_request: 128,
+            memory: 100,
+            ..Limits::default()
+        };
+        let budget = Budget::new(limits.memory);
+        let read = |bytes: &[u8], reservation: &mut Reservation| {
+            let mut reader = bytes;
+            read_request(&mut reader, &limits, reservation, Vec::new())
+        };
+        let mut first = budget.reservation();
+        let parsed = read(REQUEST, &mut first).unwrap();
+        assert_eq!(
+            (parsed.path.as_str(), parsed.body.as_slice()),
+            ("/verify/internal", &b"abc"[..])
+        );
+        assert_eq!(budget.used(), REQUEST.len());
+        // the first request still holds its memory, so the second goes over
+        assert_eq!(
+            read(REQUEST, &mut budget.reservation()).unwrap_err().status,
+            503
+        );
+        drop(first);
+        assert_eq!(budget.used(), 0);
+        let giant = format!(
+            "POST / HTTP/1.1\r\nContent-Length: 4096\r\n\r\n{}",
+            "a".repeat(4096)
+        );
+        assert_eq!(
+            read(giant.as_bytes(), &mut budget.reservation())
+                .unwrap_err()
+                .status,
+            413
+        );
+        let header = format!(
+            "GET / HTTP/1.1\r\nAuthorization: {}\r\n\r\n",
+            "a".repeat(200)
+        );
+        assert_eq!(
+            read(header.as_bytes(), &mut budget.reservation())
+                .unwrap_err()
+                .status,
+            413
+        );
+        assert_eq!(budget.used(), 0);
+        assert_eq!(budget.peak.load(Ordering::SeqCst), REQUEST.len());
+
+        let pool = Pool::default();
+        pool.give(Vec::with_capacity(1024));
+        pool.give(Vec::with_capacity(POOL_KEEP + 1));
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.take().capacity(), 1024);
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn connection_limits_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let policies: Shared = Arc::new(RwLock::new(Arc::new(Policies {
+            policies: Vec::new(),
+            files: Vec::new(),
+            tenancy: None,
+            access_log: LogProfile::default(),
+        })));
+        let limits = Limits {
+            connections: 1,
+            request_timeout: Duration::from_secs(1),
+            ..Limits::default()
+        };
+        let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let server = {
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || {
+                serve(
+                    listener,
+                    &policies,
+                    limits,
+                    None,
+                    &|| stop.load(Ordering::SeqCst),
+                    Duration::from_secs(5),
+                )
+            })
+        };
+        let response = |mut stream: TcpStream| {
+            let mut text = String::new();
+            stream.read_to_string(&mut text).unwrap();
+            text
+        };
+        // a client trickling an unfinished request, a byte at a time, holds
+        // the only connection...
+        let slow = TcpStream::connect(address).unwrap();
+        let accepted = Instant::now();
+        let mut trickle = slow.try_clone().unwrap();
+        std::thread::spawn(move || {
+            for byte in b"POST /verify HTTP/1.1\r\nHost: a\r\n".iter() {
+                if trickle.write_all(&[*byte]).is_err() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(25));
+            }
+        });
+        std::thread::sleep(Duration::from_millis(300));
+        let refused = response(TcpStream::connect(address).unwrap());
+        assert!(refused.starts_with("HTTP/1.1 503 "), "{}", refused);
+        assert!(refused.contains("Retry-After: 1\r\n"));
+        assert!(refused.contains("over the limit of 1 connections"));
+        // ...only until the request deadline
+        let timed_out = response(slow);
+        assert!(timed_out.starts_with("HTTP/1.1 408 "), "{}", timed_out);
+        assert!(timed_out.contains("request not received within 1s"));
+        assert!(accepted.elapsed() < Duration::from_secs(3));
+        stop.store(true, Ordering::SeqCst);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn reload_diff_test() {
+        let fetch = |url: &str, _: &[String]| {
+            let jwks = match url {
+                "http://idp/old" => jwks("k1", b"one"),
+                _ => jwks("k2", b"two"),
+            };
+            Ok((jwks.into_bytes(), Duration::ZERO))
+        };
+        let load = |text: &str| {
+            let config = crate::yaml::parse(text).unwrap();
+            Policies::from_config(&config, Path::new("/etc/jwt-check"), &fetch).unwrap()
+        };
+        let old = load("policies:\n  - name: internal\n    path: /verify/internal\n    issuers: [https://a]\n    jwks_url: http://idp/old\n  - name: legacy\n    jwks_url: http://idp/old\n");
+        let new = load("policies:\n  - name: internal\n    path: /verify/internal\n    issuers: [https://a, https://b]\n    algorithms: [ES256]\n    jwks_url: http://idp/new\n  - name: partner\n    header: {X-Trust-Domain: partner}\n    jwks_url: http://idp/old\n");
+        assert_eq!(
+            diff(&old, &new),
+            [
+                "policy legacy: removed",
+                "policy internal: issuers [\"https://a\"] -> [\"https://a\",\"https://b\"]",
+                "policy internal: algorithms [] -> [\"ES256\"]",
+                "policy internal: jwks_url \"http://idp/old\" -> \"http://idp/new\"",
+                "policy internal: keys [\"k1\"] -> [\"k2\"]",
+                "policy partner: added",
+            ]
+        );
+        assert!(diff(&new, &new).is_empty());
+        assert!(old.files.is_empty());
+    }
+
+    #[test]
+    fn tenancy_test() {
+        let fetch = |url: &str, _: &[String]| {
+            let jwks = match url {
+                "http://orders/jwks" => jwks("k1", b"orders secret"),
+                _ => jwks("k1", b"billing secret"),
+            };
+            Ok((jwks.into_bytes(), Duration::ZERO))
+        };
+        let load = |text: &str| {
+            let config = crate::yaml::parse(text).unwrap();
+            Policies::from_config(&config, Path::new("/etc/jwt-check"), &fetch)
+        };
+        let config = "tenant: {prefix: /tenants}\ntenants:\n  - name: orders\n    rate_limit: 2\n    policies:\n      - name: internal\n        path: /verify\n        jwks_url: http://orders/jwks\n  - name: billing\n    policies:\n      - name: internal\n        jwks_url: http://billing/jwks\n";
+        let policies = load(config).unwrap();
+        let tenancy = policies.tenancy.as_ref().unwrap();
+        let dumped = serde_json::to_value(tenancy).unwrap();
+        assert_eq!(dumped["identify"], json!({"prefix": "/tenants"}));
+        assert_eq!(serde_json::from_value::<Tenancy>(dumped).unwrap(), *tenancy);
+        let dumped = serde_json::to_value(&policies).unwrap();
+        assert_eq!(dumped["policies"][0]["tenant"], "orders");
+        // keys by kid only, since the JWKS holds the HMAC secret
+        assert_eq!(dumped["policies"][0]["keys"], json!(["k1"]));
+        assert_eq!(dumped["policies"][0]["required_claims"], json!(["exp"]));
+        assert!(dumped.get("files").is_none());
+        let token = sign(
+            &json!({"alg": "HS256", "kid": "k1"}),
+            &json!({"sub": "a", "exp": NOW + 60}),
+            &SigningKey::Hmac(b"orders secret".to_vec()),
+        )
+        .unwrap();
+        let bearer = format!("Bearer {}", token);
+        let ask = |path: &str| {
+            let (status, body, policy) = respond(
+                &policies,
+                &request(path, &[("Authorization", &bearer)]),
+                NOW,
+            );
+            (status, body, policy.map(Policy::label))
+        };
+        let (status, body, policy) = ask("/tenants/orders/verify");
+        assert_eq!(
+            (status, policy.as_deref(), &body["tenant"]),
+            (200, Some("orders/internal"), &json!("orders"))
+        );
+        // the same kid in another tenant is another key
+        let (status, _, policy) = ask("/tenants/billing/anything");
+        assert_eq!((status, policy.as_deref()), (401, Some("billing/internal")));
+        let (status, body, _) = ask("/tenants/orders/other");
+        assert_eq!(
+            (status, &body["errors"][0]),
+            (404, &json!("no policy for /other in tenant orders"))
+        );
+        assert_eq!(
+            ask("/tenants/shipping/verify").1["errors"][0],
+            "no tenant shipping"
+        );
+        assert_eq!(
+            ask("/verify").1["errors"][0],
+            "no tenant: expected a path under /tenants/TENANT"
+        );
+
+        let by_header = load("tenant: {header: X-Tenant}\ntenants:\n  - name: orders\n    policies:\n      - name: internal\n        jwks_url: http://orders/jwks\n").unwrap();
+        let headers = [("x-tenant", "orders"), ("Authorization", bearer.as_str())];
+        let (status, _, _) = respond(&by_header, &request("/verify", &headers), NOW);
+        assert_eq!(status, 200);
+        assert_eq!(
+            respond(&by_header, &request("/verify", &[]), NOW).1["errors"][0],
+            "no tenant: expected the X-Tenant header"
+        );
+        assert_eq!(
+            diff(&policies, &by_header),
+            [
+                "tenant {\"prefix\":\"/tenants\"} -> {\"header\":\"X-Tenant\"}",
+                "tenant billing: removed",
+                "tenant orders: rate_limit 2.0 -> null",
+                "policy billing/internal: removed",
+                "policy orders/internal: route {\"header\":null,\"path\":\"/verify\"} -> {\"header\":null,\"path\":null}",
+            ]
+        );
+        for (bad, error) in [
+            (
+                "tenants:\n  - name: orders\n",
+                "`tenants` needs `tenant`, saying how a request names its tenant",
+            ),
+            (
+                "tenant: {header: X-Tenant}\npolicies:\n  - name: a\n",
+                "with `tenant`, the policies go under `tenants`",
+            ),
+            (
+                "tenant: {header: X-Tenant}\ntenants:\n  - name: a\n    policies:\n      - name: p\n        jwks_url: http://a\n  - name: a\n",
+                "tenants: `a` is defined twice",
+            ),
+            (
+                "tenant: {header: X-Tenant}\ntenants:\n  - name: a\n    rate_limit: 0\n",
+                "tenant `a`: `rate_limit` must be a positive number of requests per second",
+            ),
+            (
+                "tenant: {header: X-Tenant, prefix: /tenants}\ntenants:\n  - name: a\n",
+                "`tenant` must be either {header: NAME} or {prefix: /PATH}",
+            ),
+            (
+                "tenant: {prefix: tenants}\ntenants:\n  - name: a\n",
+                "`tenant` must be either {header: NAME} or {prefix: /PATH}",
+            ),
+            (
+                "tenant: {header: X-Tenant}\ntenants: []\n",
+                "`tenant` needs a non-empty `tenants` list",
+            ),
+            (
+                "tenant: {header: X-Tenant}\ntenants:\n  - rate_limit: 1\n",
+                "tenants: every tenant needs a `name`",
+            ),
+            (
+                "tenant: {header: X-Tenant}\ntenants:\n  - name: a\n",
+                "tenant `a`: expected a non-empty `policies` list",
+            ),
+            (
+                "tenant: {header: X-Tenant}\ntenants:\n  - name: a\n    policies:\n      - name: p\n",
+                "tenant `a`: policy `p`: needs either `jwks` or `jwks_url`",
+            ),
+            (
+                "tenant: {header: X-Tenant}\ntenants:\n  - name: a\n    policies:\n      - name: p\n        jwks_url: http://a\n      - name: p\n        jwks_url: http://a\n",
+                "tenant `a`: policies: `p` is defined twice",
+            ),
+        ] {
+            assert!(
+                matches!(
+                    load(bad),
+                    Err(JWTError::ConfigError(e)) if e == error
+                ),
+                "{}",
+                bad
+            );
+        }
+
+        let limiter = Limiter::default();
+        let start = Instant::now();
+        let allowed: Vec<bool> = (0..3)
+            .map(|_| limiter.allow("orders", 2.0, start))
+            .collect();
+        assert_eq!(allowed, [true, true, false]);
+        assert!(limiter.allow("billing", 2.0, start));
+        assert!(limiter.allow("orders", 2.0, start + Duration::from_millis(500)));
+        assert!(!limiter.allow("orders", 2.0, start + Duration::from_millis(500)));
+
+        let state = State {
+            policies: Arc::new(RwLock::new(Arc::new(policies))),
+            limits: Limits::default(),
+            budget: Budget::new(1024),
+            pool: Pool::default(),
+            tally: Tally::default(),
+            limiter,
+            tracer: None,
+        };
+        state.tally.record("orders", 200);
+        state.tally.record("", 413);
+        let metrics = state.metrics();
+        assert!(metrics.contains("jwt_check_requests_total{tenant=\"orders\",status=\"200\"} 1\n"));
+        assert!(metrics.contains("jwt_check_requests_total{status=\"413\"} 1\n"));
+        assert!(
+            metrics.contains("jwt_check_key_age_seconds{tenant=\"billing\",policy=\"internal\"} ")
+        );
+        assert_eq!(state.tally.summary(), "answered 2 request(s): 1 200, 1 413");
+    }
+
+    #[test]
+    fn access_log_test() {
+        let fetch =
+            |_: &str, _: &[String]| Ok((jwks("k1", b"secret").into_bytes(), Duration::ZERO));
+        let load = |text: &str| {
+            let config = crate::yaml::parse(text).unwrap();
+            Policies::from_config(&config, Path::new("/etc/jwt-check"), &fetch)
+        };
+        let token = JWToken {
+            header: json!({"alg": "HS256", "kid": "k1"}),
+            payload: json!({"iss": "https://idp", "aud": ["api://orders"], "exp": NOW,
+                            "sub": "user-42", "email": "a@example.com"}),
+            signature: Vec::new(),
+        };
+        let policies = load("policies:\n  - name: internal\n    jwks_url: http://idp/jwks\n  - name: partner\n    jwks_url: http://partner/jwks\n    access_log:\n      claims: [header.kid]\n      hashed: [email]\n").unwrap();
+        let sub = &Hash::Sha256.digest(b"user-42")[..8];
+        let sub: String = sub.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(
+            policies.access_log.render(&token),
+            format!(
+                " iss=\"https://idp\" aud=[\"api://orders\"] exp={} sub#={}",
+                NOW, sub
+            )
+        );
+        let partner = policies.policies[1].access_log.as_ref().unwrap();
+        let rendered = partner.render(&token);
+        assert!(rendered.starts_with(" header.kid=\"k1\" email#="));
+        assert!(!rendered.contains("example.com") && !rendered.contains("user-42"));
+
+        std::env::set_var("JWT_CHECK_TEST_LOG_KEY", "pepper");
+        let keyed = load("access_log:\n  claims: []\n  hashed: [sub]\n  hash_key_env: JWT_CHECK_TEST_LOG_KEY\npolicies:\n  - name: internal\n    jwks_url: http://idp/jwks\n").unwrap();
+        let keyed_sub = keyed.access_log.render(&token);
+        assert!(keyed_sub.starts_with(" sub#=") && !keyed_sub.contains(&sub));
+        assert_eq!(
+            diff(&policies, &keyed)[0],
+            "access_log {\"claims\":[\"iss\",\"aud\",\"exp\"],\"hashed\":[\"sub\"],\"keyed\":false} -> {\"claims\":[],\"hashed\":[\"sub\"],\"keyed\":true}"
+        );
+        for (profile, error) in [
+            (
+                "  claims: [sub]\n  hashed: [sub]\n",
+                "access_log: `sub` is both logged and hashed",
+            ),
+            (
+                "  hash_key_env: JWT_CHECK_TEST_UNSET\n",
+                "access_log: `hash_key_env` JWT_CHECK_TEST_UNSET is not set",
+            ),
+            (
+                "  hash_key_env: [JWT_CHECK_TEST_LOG_KEY]\n",
+                "access_log: `hash_key_env` must be a name",
+            ),
+            (
+                "  claims: {sub: 1}\n",
+                "access_log: `claims` must be a list",
+            ),
+            (
+                "  hashed: [1]\n",
+                "access_log: `hashed` must be a list of strings",
+            ),
+        ] {
+            let bad = format!(
+                "access_log:\n{}policies:\n  - name: a\n    jwks_url: http://a\n",
+                profile
+            );
+            assert!(
+                matches!(
+                    load(&bad),
+                    Err(JWTError::ConfigError(e)) if e == error
+                ),
+                "{}",
+                bad
+            );
+        }
+        assert!(matches!(
+            load(
+                "access_log: sub\npolicies:\n  - name: a\n    jwks_url: http://a\n"
+            ),
+            Err(JWTError::ConfigError(e))
+                if e == "access_log: expected `claims`, `hashed` and `hash_key_env`"
+        ));
+        // a policy's own profile is named by the policy
+        assert!(matches!(
+            load("policies:\n  - name: a\n    jwks_url: http://a\n    access_log:\n      claims: [sub]\n      hashed: [sub]\n"),
+            Err(JWTError::ConfigError(e))
+                if e == "policy `a`: access_log: `sub` is both logged and hashed"
+        ));
+    }
+
+    #[test]
+    fn policy_config_errors_test() {
+        let fetch = |url: &str, _: &[String]| match url {
+            "http://idp/garbage" => Ok((b"not json".to_vec(), Duration::ZERO)),
+            _ => Ok((jwks("k1", b"secret").into_bytes(), Duration::ZERO)),
+        };
+        let error_of = |text: &str| {
+            let config = crate::yaml::parse(text).unwrap();
+            error_message!(
+                ConfigError,
+                Policies::from_config(&config, Path::new("/etc/jwt-check"), &fetch)
+            )
+        };
+        let policy = |rest: &str| format!("policies:\n  - name: a\n{}", rest);
+        assert_eq!(
+            error_of("name: a\n"),
+            "expected a non-empty `policies` list"
+        );
+        assert_eq!(
+            error_of("policies:\n  - path: /verify\n"),
+            "policies: every policy needs a `name`"
+        );
+        assert_eq!(
+            error_of(&policy("    path: verify\n    jwks_url: http://idp/jwks\n")),
+            "policy `a`: `path` must be a path such as /verify/internal"
+        );
+        assert_eq!(
+            error_of(&policy(
+                "    header: {X-Trust-Domain: 1}\n    jwks_url: http://idp/jwks\n"
+            )),
+            "policy `a`: the `header` value must be a string"
+        );
+        assert_eq!(
+            error_of(&policy(
+                "    header: partner\n    jwks_url: http://idp/jwks\n"
+            )),
+            "policy `a`: `header` must map one header name to its value"
+        );
+        assert_eq!(
+            error_of(&policy(
+                "    algorithms: [HS256, XX256]\n    jwks_url: http://idp/jwks\n"
+            )),
+            "policy `a`: unsupported algorithm `XX256`"
+        );
+        assert_eq!(
+            error_of(&policy("    issuers: [https://idp]\n")),
+            "policy `a`: needs either `jwks` or `jwks_url`"
+        );
+        assert_eq!(
+            error_of(&policy("    jwks: a.jwks\n    jwks_url: http://idp/jwks\n")),
+            "policy `a`: needs either `jwks` or `jwks_url`"
+        );
+        assert_eq!(
+            error_of(&policy("    jwks_url: http://idp/garbage\n")),
+            "policy `a`: the JWKS is not valid JSON: expected ident at line 1 column 2"
+        );
+        assert_eq!(
+            error_of(&policy("    leeway: [1]\n    jwks_url: http://idp/jwks\n")),
+            "policy `a`: `leeway` must be a duration such as 30s"
+        );
+        assert_eq!(
+            error_of("policies:\n  - name: a\n    jwks_url: http://idp/jwks\n  - name: a\n    jwks_url: http://idp/jwks\n"),
+            "policies: `a` is defined twice"
+        );
+    }
+
+    #[test]
+    fn script_config_errors_test() {
+        let base =
+            std::env::temp_dir().join(format!("jwt-check-serve-script-{}", std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        std::fs::write(base.join("bad.rhai"), "deny(\"no\"").unwrap();
+        let fetch =
+            |_: &str, _: &[String]| Ok((jwks("k1", b"secret").into_bytes(), Duration::ZERO));
+        let error_of = |script: &str| {
+            let config = crate::yaml::parse(&format!(
+                "policies:\n  - name: a\n    jwks_url: http://idp/jwks\n    script: {}\n",
+                script
+            ))
+            .unwrap();
+            error_message!(ConfigError, Policies::from_config(&config, &base, &fetch))
+        };
+        assert_eq!(
+            error_of("[a.rhai]"),
+            "policy `a`: `script` must be a file name"
+        );
+        assert!(error_of("missing.rhai").starts_with("policy `a`: cannot read missing.rhai: "));
+        assert_eq!(
+            error_of("bad.rhai"),
+            "policy `a`: bad.rhai: script: Expecting ')' to close the arguments list of this function call 'deny' (line 1, position 10)"
+        );
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn refresh_error_test() {
+        let fetch =
+            |_: &str, _: &[String]| Ok((jwks("k1", b"secret").into_bytes(), Duration::ZERO));
+        let config = crate::yaml::parse("tenant: {header: X-Tenant}\ntenants:\n  - name: orders\n    policies:\n      - name: internal\n        jwks_url: http://orders/jwks\n").unwrap();
+        let mut policies =
+            Policies::from_config(&config, Path::new("/etc/jwt-check"), &fetch).unwrap();
+        let policy = &mut policies.policies[0];
+        let keys = policy.keys.clone();
+        let garbage = |_: &str, _: &[String]| Ok((b"not json".to_vec(), Duration::ZERO));
+        assert!(matches!(
+            policy.refresh(&garbage),
+            Err(JWTError::ConfigError(e))
+                if e == "policy `orders/internal`: the JWKS is not valid JSON: expected ident at line 1 column 2"
+        ));
+        // the keys it had are kept
+        assert_eq!(policy.keys, keys);
+    }
+
+    #[test]
+    fn malformed_requests_test() {
+        let limits = Limits::default();
+        let budget = Budget::new(limits.memory);
+        let refusal_of = |bytes: &[u8]| {
+            let mut reader = bytes;
+            let refused = read_request(&mut reader, &limits, &mut budget.reservation(), Vec::new())
+                .unwrap_err();
+            (refused.status, refused.message)
+        };
+        let bad = |message: &str| (400, format!("bad request: {}", message));
+        assert_eq!(refusal_of(b"GET / HTTP/1.1\r\nHost"), bad("truncated"));
+        assert_eq!(refusal_of(b"GET /\r\n\r\n"), bad("malformed request line"));
+        assert_eq!(
+            refusal_of(b"GET / SPDY/3\r\n\r\n"),
+            bad("malformed request line")
+        );
+        assert_eq!(
+            refusal_of(b"GET / HTTP/1.1\r\nHost a\r\n\r\n"),
+            bad("malformed header")
+        );
+        assert_eq!(
+            refusal_of(b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n"),
+            bad("bad Content-Length")
+        );
+        assert_eq!(
+            refusal_of(b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nabc"),
+            bad("failed to fill whole buffer")
+        );
+        let crowded = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-Pad: a\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert_eq!(refusal_of(crowded.as_bytes()), bad("too many headers"));
+        assert_eq!(budget.used(), 0);
+    }
+}
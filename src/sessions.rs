@@ -0,0 +1,383 @@
+//! `--sessions`: a batch's tokens regrouped into the sessions they belong
+//! to, each a timeline of logins, refreshes and reissues.
+//!
+//! A session is the tokens sharing an issuer and `sid`, or an issuer and
+//! `sub` when there is no `sid`. Its tokens are ordered by `iat`: the first
+//! is the login, one with the same `jti` or `iat` as the token before it is
+//! a reissue, and any other is a refresh. Anomalies are flagged along the
+//! way: a token issued after the one before it expired, a later `auth_time`
+//! (a fresh login inside the session), and sessions of one subject whose
+//! spans overlap.
+
+use crate::{format_timestamp, humanize_seconds};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Login,
+    Refresh,
+    Reissue,
+}
+
+impl Step {
+    pub fn name(self) -> &'static str {
+        match self {
+            Step::Login => "login",
+            Step::Refresh => "refresh",
+            Step::Reissue => "reissue",
+        }
+    }
+}
+
+/// One token of a session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// The batch line the token came from.
+    pub line: usize,
+    pub iat: i64,
+    pub exp: Option<i64>,
+    pub step: Step,
+    pub anomalies: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub issuer: String,
+    pub subject: Option<String>,
+    /// `None` for the tokens of a subject that carry no `sid`.
+    pub sid: Option<String>,
+    pub events: Vec<Event>,
+    /// Anomalies of the session as a whole, such as overlapping another.
+    pub anomalies: Vec<String>,
+}
+
+impl Session {
+    fn label(&self) -> String {
+        match (&self.sid, &self.subject) {
+            (Some(sid), Some(sub)) => format!("sid {} (sub {})", sid, sub),
+            (Some(sid), None) => format!("sid {}", sid),
+            (None, Some(sub)) => format!("sub {}", sub),
+            (None, None) => "(no sid or sub)".to_string(),
+        }
+    }
+
+    fn start(&self) -> i64 {
+        self.events.first().map_or(0, |event| event.iat)
+    }
+
+    /// When the session's last token expires, or was issued if it never does.
+    fn end(&self) -> i64 {
+        self.events
+            .iter()
+            .map(|event| event.exp.unwrap_or(event.iat))
+            .max()
+            .unwrap_or_default()
+    }
+
+    pub fn anomaly_count(&self) -> usize {
+        self.anomalies.len()
+            + self
+                .events
+                .iter()
+                .map(|event| event.anomalies.len())
+                .sum::<usize>()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sessions {
+    /// By issuer, then subject and start.
+    pub sessions: Vec<Session>,
+    /// Tokens with no numeric `iat` or neither `sid` nor `sub`, left out.
+    pub left_out: usize,
+}
+
+fn text(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::String(text) => Some(text.clone()),
+        Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+/// Groups `payloads`, each with its batch line, into sessions. Their `iat`,
+/// `exp` and `auth_time` must already be in seconds.
+pub fn reconstruct(payloads: &[(usize, Value)]) -> Sessions {
+    type Key = (String, Option<String>, Option<String>);
+    let mut grouped: BTreeMap<Key, Vec<(usize, &Value, i64)>> = BTreeMap::new();
+    let mut left_out = 0;
+    for (line, payload) in payloads {
+        let sid = text(payload.get("sid"));
+        let sub = text(payload.get("sub"));
+        match payload.get("iat").and_then(Value::as_i64) {
+            Some(iat) if sid.is_some() || sub.is_some() => {
+                let iss = text(payload.get("iss")).unwrap_or_else(|| "(no iss)".to_string());
+                // a subject's tokens without a sid are one session, apart
+                // from its sessions that have one
+                grouped
+                    .entry((iss, sub, sid))
+                    .or_default()
+                    .push((*line, payload, iat));
+            }
+            _ => left_out += 1,
+        }
+    }
+
+    let mut sessions: Vec<Session> = grouped
+        .into_iter()
+        .map(|((issuer, subject, sid), mut tokens)| {
+            tokens.sort_by_key(|(line, _, iat)| (*iat, *line));
+            let mut events: Vec<Event> = Vec::with_capacity(tokens.len());
+            let mut previous: Option<(&Value, i64)> = None;
+            for (line, payload, iat) in tokens {
+                let exp = payload.get("exp").and_then(Value::as_i64);
+                let mut anomalies = Vec::new();
+                let step = match previous {
+                    None => Step::Login,
+                    Some((before, before_iat)) => {
+                        let jti = payload.get("jti").filter(|jti| !jti.is_null());
+                        if iat == before_iat || (jti.is_some() && jti == before.get("jti")) {
+                            Step::Reissue
+                        } else {
+                            Step::Refresh
+                        }
+                    }
+                };
+                if let Some((before, _)) = previous {
+                    if let Some(expired) = before.get("exp").and_then(Value::as_i64) {
+                        if iat > expired {
+                            anomalies.push(format!(
+                                "{} {} after the token before it expired",
+                                step.name(),
+                                humanize_seconds(iat.saturating_sub(expired))
+                            ));
+                        }
+                    }
+                    let auth_time =
+                        |payload: &Value| payload.get("auth_time").and_then(Value::as_i64);
+                    if let (Some(now), Some(then)) = (auth_time(payload), auth_time(before)) {
+                        if now > then {
+                            anomalies.push(format!(
+                                "auth_time moved to {}: a new login inside the session",
+                                format_timestamp(now)
+                            ));
+                        }
+                    }
+                }
+                events.push(Event {
+                    line,
+                    iat,
+                    exp,
+                    step,
+                    anomalies,
+                });
+                previous = Some((payload, iat));
+            }
+            Session {
+                issuer,
+                subject,
+                sid,
+                events,
+                anomalies: Vec::new(),
+            }
+        })
+        .collect();
+    sessions.sort_by(|a, b| {
+        (&a.issuer, &a.subject, a.start()).cmp(&(&b.issuer, &b.subject, b.start()))
+    });
+
+    // concurrent sessions: one subject's sids whose spans overlap
+    let concurrent: Vec<Vec<String>> = sessions
+        .iter()
+        .map(|session| {
+            sessions
+                .iter()
+                .filter(|other| {
+                    session.sid.is_some()
+                        && session.subject.is_some()
+                        && other.sid.is_some()
+                        && other.sid != session.sid
+                        && other.issuer == session.issuer
+                        && other.subject == session.subject
+                        && other.start() < session.end()
+                        && session.start() < other.end()
+                })
+                .filter_map(|other| other.sid.clone())
+                .collect()
+        })
+        .collect();
+    for (session, overlapping) in sessions.iter_mut().zip(concurrent) {
+        if !overlapping.is_empty() {
+            session.anomalies.push(format!(
+                "concurrent with session(s) {} of the same sub",
+                overlapping.join(", ")
+            ));
+        }
+    }
+    Sessions { sessions, left_out }
+}
+
+impl Sessions {
+    pub fn render_text(&self) -> String {
+        let anomalies: usize = self.sessions.iter().map(Session::anomaly_count).sum();
+        let mut out = format!(
+            "{} session(s), {} anomal{}",
+            self.sessions.len(),
+            anomalies,
+            if anomalies == 1 { "y" } else { "ies" }
+        );
+        if self.left_out > 0 {
+            out.push_str(&format!(
+                " ({} token(s) without iat, or without sid and sub, left out)",
+                self.left_out
+            ));
+        }
+        for session in &self.sessions {
+            out.push_str(&format!(
+                "\n{} {} from {} to {}",
+                session.issuer,
+                session.label(),
+                format_timestamp(session.start()),
+                format_timestamp(session.end())
+            ));
+            for anomaly in &session.anomalies {
+                out.push_str(&format!("\n  ! {}", anomaly));
+            }
+            for event in &session.events {
+                let expiry = event.exp.map_or_else(
+                    || "never expires".to_string(),
+                    |exp| format!("expires {}", format_timestamp(exp)),
+                );
+                out.push_str(&format!(
+                    "\n  {} {:<7} line {}, {}",
+                    format_timestamp(event.iat),
+                    event.step.name(),
+                    event.line,
+                    expiry
+                ));
+                for anomaly in &event.anomalies {
+                    out.push_str(&format!("\n    ! {}", anomaly));
+                }
+            }
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> Value {
+        serde_json::json!({
+            "left_out": self.left_out,
+            "sessions": self.sessions.iter().map(|session| serde_json::json!({
+                "iss": session.issuer,
+                "sub": session.subject,
+                "sid": session.sid,
+                "start": session.start(),
+                "end": session.end(),
+                "anomalies": session.anomalies,
+                "events": session.events.iter().map(|event| serde_json::json!({
+                    "line": event.line,
+                    "iat": event.iat,
+                    "exp": event.exp,
+                    "step": event.step.name(),
+                    "anomalies": event.anomalies,
+                })).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reconstruct_test() {
+        let idp = "https://idp";
+        let payloads: Vec<(usize, Value)> = vec![
+            json!({"iss": idp, "sub": "alice", "sid": "s1", "iat": 1_000, "exp": 1_300, "auth_time": 1_000}),
+            // a reissue of the same token, then a refresh in time
+            json!({"iss": idp, "sub": "alice", "sid": "s1", "iat": 1_000, "exp": 1_300}),
+            json!({"iss": idp, "sub": "alice", "sid": "s1", "iat": 1_200, "exp": 1_500, "auth_time": 1_000}),
+            // refreshed long after expiry, with a fresh auth_time
+            json!({"iss": idp, "sub": "alice", "sid": "s1", "iat": 5_000, "exp": 5_300, "auth_time": 4_990}),
+            // a second session of alice's at the same time
+            json!({"iss": idp, "sub": "alice", "sid": "s2", "iat": 1_100, "exp": 1_400}),
+            // bob's tokens carry no sid
+            json!({"iss": idp, "sub": "bob", "iat": 2_000, "jti": "a"}),
+            json!({"iss": idp, "sub": "bob", "iat": 2_010, "jti": "a"}),
+            json!({"iss": idp, "sub": "carol"}),
+            json!({"iss": idp, "iat": 3_000}),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(index, payload)| (index + 1, payload))
+        .collect();
+        let sessions = reconstruct(&payloads);
+        assert_eq!(sessions.left_out, 2);
+        assert_eq!(sessions.sessions.len(), 3);
+
+        let s1 = &sessions.sessions[0];
+        assert_eq!(s1.sid.as_deref(), Some("s1"));
+        let steps: Vec<Step> = s1.events.iter().map(|event| event.step).collect();
+        assert_eq!(
+            steps,
+            [Step::Login, Step::Reissue, Step::Refresh, Step::Refresh]
+        );
+        assert!(s1.events[2].anomalies.is_empty());
+        assert_eq!(
+            s1.events[3].anomalies,
+            [
+                "refresh 58 minutes after the token before it expired",
+                "auth_time moved to 1970-01-01T01:23:10Z: a new login inside the session",
+            ]
+        );
+        assert_eq!(
+            s1.anomalies,
+            ["concurrent with session(s) s2 of the same sub"]
+        );
+        assert_eq!(
+            sessions.sessions[1].anomalies,
+            ["concurrent with session(s) s1 of the same sub"]
+        );
+        assert_eq!(s1.anomaly_count(), 3);
+
+        // the same jti is a reissue even at another iat
+        let bob = &sessions.sessions[2];
+        assert_eq!(bob.sid, None);
+        assert_eq!(bob.events[1].step, Step::Reissue);
+        assert!(bob.anomalies.is_empty());
+
+        let text = sessions.render_text();
+        assert!(text.starts_with(
+            "3 session(s), 4 anomalies (2 token(s) without iat, or without sid and sub, left out)\n\
+             https://idp sid s1 (sub alice) from 1970-01-01T00:16:40Z to 1970-01-01T01:28:20Z\n\
+             \x20 ! concurrent with session(s) s2 of the same sub\n\
+             \x20 1970-01-01T00:16:40Z login   line 1, expires 1970-01-01T00:21:40Z"
+        ));
+        assert!(text.contains("https://idp sub bob from"));
+        assert!(text.contains("reissue line 7, never expires"));
+        let json = sessions.to_json();
+        assert_eq!(json["sessions"][0]["events"][1]["step"], "reissue");
+        assert_eq!(json["sessions"][2]["sid"], Value::Null);
+
+        let empty = reconstruct(&[]);
+        assert_eq!(empty.render_text(), "0 session(s), 0 anomalies");
+    }
+
+    #[test]
+    fn extreme_times_test() {
+        // exp and iat are the token's, so their distance may not fit in i64
+        let payloads = [
+            (1, json!({"sub": "alice", "iat": 0, "exp": i64::MIN})),
+            (2, json!({"sub": "alice", "iat": i64::MAX})),
+        ];
+        let sessions = reconstruct(&payloads);
+        let anomalies = &sessions.sessions[0].events[1].anomalies;
+        assert_eq!(
+            anomalies,
+            &["refresh 106751991167300 days after the token before it expired"]
+        );
+        sessions.render_text();
+    }
+}
@@ -0,0 +1,62 @@
+//! The signals long-running modes act on. `SIGHUP` asks `serve` to reload
+//! its policies. `SIGTERM` and `SIGINT` ask `serve` and `--batch` to stop
+//! once the work in hand is done, and a second one exits at once.
+//!
+//! The handlers only set flags, which the modes poll; elsewhere than Linux
+//! the signals keep their default behaviour.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static HANGUP: AtomicBool = AtomicBool::new(false);
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+
+#[cfg(target_os = "linux")]
+mod handlers {
+    use super::{HANGUP, TERMINATE};
+    use std::sync::atomic::Ordering;
+
+    extern "C" fn on_hangup(_: libc::c_int) {
+        HANGUP.store(true, Ordering::SeqCst);
+    }
+
+    extern "C" fn on_terminate(signal: libc::c_int) {
+        if TERMINATE.swap(true, Ordering::SeqCst) {
+            // asked twice: stop waiting for the work in hand
+            unsafe { libc::_exit(128 + signal) };
+        }
+    }
+
+    // the handlers only touch atomics and `_exit`, which are
+    // async-signal-safe
+    pub fn install(signal: libc::c_int, handler: extern "C" fn(libc::c_int)) {
+        unsafe { libc::signal(signal, handler as libc::sighandler_t) };
+    }
+
+    pub fn catch_hangup() {
+        install(libc::SIGHUP, on_hangup);
+    }
+
+    pub fn catch_terminate() {
+        install(libc::SIGTERM, on_terminate);
+        install(libc::SIGINT, on_terminate);
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use handlers::{catch_hangup, catch_terminate};
+
+#[cfg(not(target_os = "linux"))]
+pub fn catch_hangup() {}
+
+#[cfg(not(target_os = "linux"))]
+pub fn catch_terminate() {}
+
+/// Whether a `SIGHUP` arrived since the last call.
+pub fn hangup() -> bool {
+    HANGUP.swap(false, Ordering::SeqCst)
+}
+
+/// Whether a `SIGTERM` or `SIGINT` arrived.
+pub fn terminating() -> bool {
+    TERMINATE.load(Ordering::SeqCst)
+}
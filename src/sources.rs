@@ -0,0 +1,303 @@
+//! `--claim-sources`: claims gathered from every layer that carries them,
+//! each shown with the layer it came from instead of silently merged.
+//!
+//! Layers are given highest precedence first. A claim takes its value from
+//! the first layer that has it; the same claim in later layers is listed
+//! after it, marked as agreeing or conflicting.
+
+use serde_json::Value;
+
+/// Registered JOSE header parameters (RFC 7515, 7516, 7518, 7797, 8225,
+/// 8555). Other header members are claims replicated into the header, as
+/// RFC 7519 section 5.3 allows.
+const JOSE_PARAMETERS: &[&str] = &[
+    "alg", "enc", "zip", "jku", "jwk", "kid", "x5u", "x5c", "x5t", "x5t#S256", "typ", "cty",
+    "crit", "epk", "apu", "apv", "iv", "tag", "p2s", "p2c", "b64", "ppt", "url", "nonce",
+];
+
+/// Claims about the token rather than the user, which a UserInfo or
+/// introspection response has no reason to repeat.
+const TOKEN_CLAIMS: &[&str] = &[
+    "iss",
+    "aud",
+    "exp",
+    "nbf",
+    "iat",
+    "jti",
+    "nonce",
+    "auth_time",
+    "azp",
+    "acr",
+    "amr",
+    "sid",
+    "at_hash",
+    "c_hash",
+    "s_hash",
+    "cnf",
+    "typ",
+];
+
+/// One layer of claims: a name such as `payload` or `userinfo`, and a
+/// JSON object.
+pub struct Layer<'a> {
+    pub name: &'static str,
+    pub claims: &'a Value,
+}
+
+/// Where else a claim turned up, and whether it matches the value used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shadowed {
+    pub source: &'static str,
+    pub value: Value,
+    pub agrees: bool,
+}
+
+/// A claim, its value from the highest-precedence layer that has it, and
+/// the lower layers that also have it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourcedClaim {
+    pub name: String,
+    pub value: Value,
+    pub source: &'static str,
+    pub shadowed: Vec<Shadowed>,
+}
+
+impl SourcedClaim {
+    pub fn conflicts(&self) -> bool {
+        self.shadowed.iter().any(|s| !s.agrees)
+    }
+}
+
+/// The members of a JOSE header that are claims rather than parameters.
+pub fn header_claims(header: &Value) -> Value {
+    let claims = header
+        .as_object()
+        .map(|object| {
+            object
+                .iter()
+                .filter(|(name, _)| !JOSE_PARAMETERS.contains(&name.as_str()))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+    Value::Object(claims)
+}
+
+/// Combines `layers`, highest precedence first. Claims are listed in the
+/// order the layers first have them.
+pub fn combine(layers: &[Layer]) -> Vec<SourcedClaim> {
+    let mut combined: Vec<SourcedClaim> = Vec::new();
+    for layer in layers {
+        let object = match layer.claims.as_object() {
+            Some(object) => object,
+            None => continue,
+        };
+        for (name, value) in object {
+            match combined.iter_mut().find(|claim| &claim.name == name) {
+                Some(claim) => {
+                    let agrees = &claim.value == value;
+                    claim.shadowed.push(Shadowed {
+                        source: layer.name,
+                        value: value.clone(),
+                        agrees,
+                    });
+                }
+                None => combined.push(SourcedClaim {
+                    name: name.clone(),
+                    value: value.clone(),
+                    source: layer.name,
+                    shadowed: Vec::new(),
+                }),
+            }
+        }
+    }
+    combined
+}
+
+/// The user claims of the first layer that the layer named `name` lacks,
+/// such as an `email` in the ID token that userinfo doesn't return.
+pub fn missing_from(layers: &[Layer], name: &str) -> Vec<String> {
+    let (first, other) = match (
+        layers.first(),
+        layers.iter().find(|layer| layer.name == name),
+    ) {
+        (Some(first), Some(other)) => (first, other),
+        _ => return Vec::new(),
+    };
+    first
+        .claims
+        .as_object()
+        .map(|object| {
+            object
+                .keys()
+                .filter(|claim| !TOKEN_CLAIMS.contains(&claim.as_str()))
+                .filter(|claim| other.claims.get(claim.as_str()).is_none())
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The claims as a JSON document for `--format json`: the precedence of
+/// the layers, each claim with its source and the layers it shadows, and
+/// what userinfo is missing.
+pub fn to_json(layers: &[Layer], combined: &[SourcedClaim]) -> Value {
+    let precedence: Vec<&str> = layers.iter().map(|layer| layer.name).collect();
+    let claims: serde_json::Map<String, Value> = combined
+        .iter()
+        .map(|claim| {
+            let shadowed: Vec<Value> = claim
+                .shadowed
+                .iter()
+                .map(|s| serde_json::json!({"source": s.source, "value": s.value, "agrees": s.agrees}))
+                .collect();
+            let entry = serde_json::json!({
+                "value": claim.value,
+                "source": claim.source,
+                "conflict": claim.conflicts(),
+                "shadowed": shadowed,
+            });
+            (claim.name.clone(), entry)
+        })
+        .collect();
+    serde_json::json!({
+        "precedence": precedence,
+        "claims": claims,
+        "missing_in_userinfo": missing_from(layers, "userinfo"),
+    })
+}
+
+/// One line per claim followed by the lower layers that also have it.
+pub fn render_text(layers: &[Layer], combined: &[SourcedClaim]) -> String {
+    let precedence: Vec<&str> = layers.iter().map(|layer| layer.name).collect();
+    let mut out = format!("precedence: {}\n", precedence.join(" > "));
+    for claim in combined {
+        out.push_str(&format!(
+            "{}: {} (from {})\n",
+            claim.name, claim.value, claim.source
+        ));
+        for shadowed in &claim.shadowed {
+            out.push_str(&format!(
+                "  {} {}: {}\n",
+                if shadowed.agrees {
+                    "same in"
+                } else {
+                    "CONFLICT in"
+                },
+                shadowed.source,
+                shadowed.value
+            ));
+        }
+    }
+    let missing = missing_from(layers, "userinfo");
+    if !missing.is_empty() {
+        out.push_str(&format!("MISSING in userinfo: {}\n", missing.join(", ")));
+    }
+    let conflicts = combined.iter().filter(|claim| claim.conflicts()).count();
+    out.push_str(&format!("{} conflicting claim(s)", conflicts));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn missing_from_test() {
+        let payload = json!({"iss": "https://idp.example", "sub": "alice", "nonce": "n",
+                             "email": "alice@example.com", "name": "Alice"});
+        let userinfo = json!({"sub": "alice", "name": "Alice"});
+        let layers = [
+            Layer {
+                name: "payload",
+                claims: &payload,
+            },
+            Layer {
+                name: "userinfo",
+                claims: &userinfo,
+            },
+        ];
+        assert_eq!(missing_from(&layers, "userinfo"), ["email"]);
+        assert!(missing_from(&layers, "introspection").is_empty());
+    }
+
+    #[test]
+    fn combine_marks_conflicts_test() {
+        let payload = json!({"sub": "alice", "exp": 1900000000});
+        let header = header_claims(&json!({"alg": "dir", "enc": "A256GCM", "sub": "alice"}));
+        let userinfo = json!({"sub": "mallory", "email": "a@example.com"});
+        let combined = combine(&[
+            Layer {
+                name: "payload",
+                claims: &payload,
+            },
+            Layer {
+                name: "jwe header",
+                claims: &header,
+            },
+            Layer {
+                name: "userinfo",
+                claims: &userinfo,
+            },
+        ]);
+        let names: Vec<&str> = combined.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["exp", "sub", "email"]);
+        let sub = &combined[1];
+        assert_eq!(sub.source, "payload");
+        assert!(sub.conflicts());
+        assert_eq!(
+            sub.shadowed,
+            vec![
+                Shadowed {
+                    source: "jwe header",
+                    value: json!("alice"),
+                    agrees: true
+                },
+                Shadowed {
+                    source: "userinfo",
+                    value: json!("mallory"),
+                    agrees: false
+                },
+            ]
+        );
+        assert_eq!(combined[2].source, "userinfo");
+        assert!(!combined[2].conflicts());
+    }
+
+    #[test]
+    fn render_test() {
+        let payload = json!({"sub": "alice", "email": "alice@example.com", "nonce": "n"});
+        let userinfo = json!({"sub": "alice", "email": "alice@example.org"});
+        let layers = [
+            Layer {
+                name: "payload",
+                claims: &payload,
+            },
+            Layer {
+                name: "userinfo",
+                claims: &userinfo,
+            },
+        ];
+        let text = render_text(&layers, &combine(&layers));
+        assert!(text.contains("  CONFLICT in userinfo: \"alice@example.org\"\n"));
+        assert!(!text.contains("MISSING"));
+        let userinfo = json!({"sub": "alice"});
+        let layers = [
+            Layer {
+                name: "payload",
+                claims: &payload,
+            },
+            Layer {
+                name: "userinfo",
+                claims: &userinfo,
+            },
+        ];
+        let combined = combine(&layers);
+        let text = render_text(&layers, &combined);
+        assert!(text.ends_with("MISSING in userinfo: email\n0 conflicting claim(s)"));
+        let json = to_json(&layers, &combined);
+        assert_eq!(json["missing_in_userinfo"], json!(["email"]));
+        assert_eq!(json["precedence"], json!(["payload", "userinfo"]));
+    }
+}
@@ -0,0 +1,346 @@
+//! `verify_stream`: verifies a firehose of tokens, from a message queue or a
+//! log, on a bounded pool of threads.
+//!
+//! Tokens come from a futures `Stream` and results go out through one, so
+//! any async runtime can drive it; the signatures are checked on threads of
+//! its own, which wake the consuming task as results come back. Results
+//! come in input order. Tokens are only pulled from the input as results
+//! are taken, with at most `concurrency` of them being verified at once, so
+//! a slow consumer holds back a fast source rather than buffering it.
+
+use crate::keys::VerifyingKey;
+use crate::verify::{token_algorithm, Algorithm};
+use crate::{jwks, parse, verify, JWTError, JWToken};
+use futures_core::Stream;
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::JoinHandle;
+
+/// What tokens are verified against.
+#[derive(Debug, Clone)]
+pub enum Keys {
+    /// Every key is tried in turn.
+    Fixed(Vec<VerifyingKey>),
+    /// A JWKS, whose keys are picked by each token's `kid` and `alg`.
+    Jwks(Value),
+}
+
+impl Keys {
+    fn candidates(&self, token: &JWToken) -> Result<Vec<VerifyingKey>, JWTError> {
+        match self {
+            Keys::Fixed(keys) => Ok(keys.clone()),
+            Keys::Jwks(set) => {
+                let kid = token.header.get("kid").and_then(Value::as_str);
+                jwks::select_keys(set, kid, token_algorithm(token)?)
+            }
+        }
+    }
+}
+
+/// A token with a signature that checks out.
+#[derive(Debug, Clone, Serialize)]
+pub struct Verified {
+    pub alg: Algorithm,
+    pub token: JWToken,
+}
+
+/// The outcome for the input token at `index`.
+#[derive(Debug)]
+pub struct VerificationResult {
+    pub index: usize,
+    pub raw: String,
+    pub outcome: Result<Verified, JWTError>,
+}
+
+// One JSON line per token for a consumer to log or forward, with its keys
+// sorted: the `alg` and token when it verified, else the error as it would
+// be printed. The raw token is left out, as it is a credential.
+impl Serialize for VerificationResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let fields = if self.outcome.is_ok() { 3 } else { 2 };
+        let mut state = serializer.serialize_struct("VerificationResult", fields)?;
+        match &self.outcome {
+            Ok(verified) => {
+                state.serialize_field("alg", &verified.alg)?;
+                state.serialize_field("index", &self.index)?;
+                state.serialize_field("token", &verified.token)?;
+            }
+            Err(e) => {
+                state.serialize_field("error", &e.to_string())?;
+                state.serialize_field("index", &self.index)?;
+            }
+        }
+        state.end()
+    }
+}
+
+fn check(raw: &str, keys: &Keys) -> Result<Verified, JWTError> {
+    let token = parse(raw)?;
+    let mut last_error = JWTError::KeyError("no key to verify with".into());
+    for key in keys.candidates(&token)? {
+        match verify(raw, &token, &key) {
+            Ok(alg) => return Ok(Verified { alg, token }),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// The results of `verify_stream`, in the order tokens came in.
+pub struct VerifyStream<S> {
+    /// The tokens, until they run out.
+    input: Option<Pin<Box<S>>>,
+    jobs: Option<SyncSender<(usize, String)>>,
+    results: Receiver<VerificationResult>,
+    /// The task waiting on `results`, for the workers to wake.
+    waker: Arc<Mutex<Option<Waker>>>,
+    workers: Vec<JoinHandle<()>>,
+    concurrency: usize,
+    pulled: usize,
+    next: usize,
+    done: BTreeMap<usize, VerificationResult>,
+}
+
+/// Verifies every token of `tokens` against `keys`, `concurrency` at a time
+/// (at least one).
+pub fn verify_stream<S>(tokens: S, keys: Keys, concurrency: usize) -> VerifyStream<S>
+where
+    S: Stream<Item = String>,
+{
+    let concurrency = concurrency.max(1);
+    let (jobs, queue) = mpsc::sync_channel::<(usize, String)>(concurrency);
+    let queue = Arc::new(Mutex::new(queue));
+    let (sender, results): (Sender<VerificationResult>, _) = mpsc::channel();
+    let waker: Arc<Mutex<Option<Waker>>> = Arc::new(Mutex::new(None));
+    let keys = Arc::new(keys);
+    let workers = (0..concurrency)
+        .map(|_| {
+            let (queue, sender, keys) = (Arc::clone(&queue), sender.clone(), Arc::clone(&keys));
+            let waker = Arc::clone(&waker);
+            std::thread::spawn(move || loop {
+                let job = match queue.lock() {
+                    Ok(queue) => queue.recv(),
+                    Err(_) => return,
+                };
+                let (index, raw) = match job {
+                    Ok(job) => job,
+                    Err(_) => return,
+                };
+                let outcome = check(&raw, &keys);
+                let result = VerificationResult {
+                    index,
+                    raw,
+                    outcome,
+                };
+                if sender.send(result).is_err() {
+                    return;
+                }
+                if let Ok(waker) = waker.lock() {
+                    waker.iter().for_each(Waker::wake_by_ref);
+                }
+            })
+        })
+        .collect();
+    VerifyStream {
+        input: Some(Box::pin(tokens)),
+        jobs: Some(jobs),
+        results,
+        waker,
+        workers,
+        concurrency,
+        pulled: 0,
+        next: 0,
+        done: BTreeMap::new(),
+    }
+}
+
+impl<S: Stream<Item = String>> Stream for VerifyStream<S> {
+    type Item = VerificationResult;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<VerificationResult>> {
+        let this = self.get_mut();
+        // set before looking at `results`, so a result sent after the look
+        // wakes this task
+        if let Ok(mut waker) = this.waker.lock() {
+            *waker = Some(cx.waker().clone());
+        }
+        while this.pulled - this.next < this.concurrency {
+            let (input, jobs) = match (&mut this.input, &this.jobs) {
+                (Some(input), Some(jobs)) => (input, jobs),
+                _ => break,
+            };
+            let sent = match input.as_mut().poll_next(cx) {
+                Poll::Ready(Some(raw)) => jobs.send((this.pulled, raw)).is_ok(),
+                Poll::Ready(None) => false,
+                Poll::Pending => break,
+            };
+            if sent {
+                this.pulled += 1;
+            } else {
+                // no more input: the workers finish what they have and stop
+                this.input = None;
+                this.jobs = None;
+            }
+        }
+        while let Ok(result) = this.results.try_recv() {
+            this.done.insert(result.index, result);
+        }
+        match this.done.remove(&this.next) {
+            Some(result) => {
+                this.next += 1;
+                Poll::Ready(Some(result))
+            }
+            None if this.jobs.is_none() && this.next == this.pulled => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<I> Drop for VerifyStream<I> {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::SigningKey;
+    use crate::sign::sign;
+    use serde_json::json;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::task::Wake;
+    use std::thread::Thread;
+
+    /// An iterator as a stream that is always ready.
+    struct Iter<I>(I);
+
+    impl<I: Iterator + Unpin> Stream for Iter<I> {
+        type Item = I::Item;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<I::Item>> {
+            Poll::Ready(self.0.next())
+        }
+    }
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// The next item of `stream`, parking this thread until it is woken.
+    fn next<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match Pin::new(&mut *stream).poll_next(&mut cx) {
+                Poll::Ready(item) => return item,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    fn collect<S: Stream + Unpin>(mut stream: S) -> Vec<S::Item> {
+        std::iter::from_fn(|| next(&mut stream)).collect()
+    }
+
+    #[test]
+    fn verify_stream_test() {
+        let secret = b"your-256-bit-secret".to_vec();
+        let mint = |n: usize, secret: &[u8]| {
+            sign(
+                &json!({"alg": "HS256"}),
+                &json!({ "n": n }),
+                &SigningKey::Hmac(secret.to_vec()),
+            )
+            .unwrap()
+        };
+        let mut tokens: Vec<String> = (0..20).map(|n| mint(n, &secret)).collect();
+        tokens[7] = mint(7, b"another secret");
+        tokens[11] = "not a token".to_string();
+
+        let pulled = Rc::new(Cell::new(0));
+        let counted = {
+            let pulled = Rc::clone(&pulled);
+            tokens
+                .clone()
+                .into_iter()
+                .inspect(move |_| pulled.set(pulled.get() + 1))
+        };
+        let keys = Keys::Fixed(vec![VerifyingKey::Hmac(secret)]);
+        let mut stream = verify_stream(Iter(counted), keys, 4);
+        let first = next(&mut stream).unwrap();
+        assert_eq!(first.index, 0);
+        // bounded: no more than `concurrency` pulled ahead of the consumer
+        assert_eq!(pulled.get(), 4);
+
+        let results: Vec<VerificationResult> =
+            std::iter::once(first).chain(collect(stream)).collect();
+        assert_eq!(results.len(), 20);
+        for (n, result) in results.iter().enumerate() {
+            assert_eq!(result.index, n);
+            assert_eq!(result.raw, tokens[n]);
+            match n {
+                7 => assert!(matches!(
+                    result.outcome,
+                    Err(JWTError::InvalidSignatureError)
+                )),
+                11 => assert!(result.outcome.is_err()),
+                _ => {
+                    let verified = result.outcome.as_ref().unwrap();
+                    assert_eq!(verified.alg, Algorithm::HS256);
+                    assert_eq!(verified.token.payload["n"], n);
+                }
+            }
+        }
+        assert_eq!(
+            serde_json::to_value(&results[3]).unwrap(),
+            json!({"index": 3, "alg": "HS256", "token": results[3].outcome.as_ref().unwrap().token})
+        );
+        assert_eq!(
+            serde_json::to_value(&results[7]).unwrap(),
+            json!({"index": 7, "error": "Error: Invalid signature"})
+        );
+
+        let set = json!({"keys": [{"kty": "oct", "kid": "a", "k": "c2VjcmV0"}]});
+        let raw = sign(
+            &json!({"alg": "HS256", "kid": "b"}),
+            &json!({}),
+            &SigningKey::Hmac(b"secret".to_vec()),
+        )
+        .unwrap();
+        let results = collect(verify_stream(
+            Iter(vec![raw].into_iter()),
+            Keys::Jwks(set),
+            0,
+        ));
+        assert!(matches!(
+            &results[0].outcome,
+            Err(JWTError::KeyError(e)) if e == "no key in the JWKS matches kid `b` and alg HS256"
+        ));
+        let results = collect(verify_stream(
+            Iter(tokens.into_iter()),
+            Keys::Fixed(Vec::new()),
+            2,
+        ));
+        assert!(matches!(
+            &results[0].outcome,
+            Err(JWTError::KeyError(e)) if e == "no key to verify with"
+        ));
+        let empty = verify_stream(Iter(Vec::new().into_iter()), Keys::Fixed(Vec::new()), 2);
+        assert!(collect(empty).is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+//! `--log-sink`: where `serve` and `--batch` write their log lines, so
+//! their findings reach the system log with a priority alerting can match
+//! on.
+//!
+//! - `stderr`, the default, prints them as they are;
+//! - `syslog` sends them to `/dev/log` as RFC 3164 messages from the
+//!   `daemon` facility;
+//! - `journald` sends them to the systemd journal in its native protocol,
+//!   with `PRIORITY` and `SYSLOG_IDENTIFIER` fields.
+//!
+//! The socket is connected when the sink is chosen, before any sandboxing
+//! takes new sockets away. A line the sink cannot take is printed to stderr
+//! instead, so it is never lost.
+
+use crate::JWTError;
+use std::fmt;
+use std::sync::OnceLock;
+
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+pub const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+const IDENTIFIER: &str = "jwt-check";
+/// RFC 5424's `daemon`.
+const FACILITY: u8 = 3;
+
+/// The sink for the rest of the process, once `use_sink` picks one.
+static SINK: OnceLock<Connected> = OnceLock::new();
+
+/// Syslog severities, the ones the tool uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Err = 3,
+    Warning = 4,
+    Notice = 5,
+    Info = 6,
+}
+
+impl Priority {
+    /// The priority of a request answered with `status`: server faults are
+    /// errors, refused tokens warnings, and everything else information.
+    pub fn of_status(status: u16) -> Priority {
+        match status {
+            500..=599 => Priority::Err,
+            400..=499 => Priority::Warning,
+            _ => Priority::Info,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogSink {
+    Stderr,
+    Syslog,
+    Journald,
+}
+
+impl std::str::FromStr for LogSink {
+    type Err = JWTError;
+
+    fn from_str(name: &str) -> Result<LogSink, JWTError> {
+        match name {
+            "stderr" => Ok(LogSink::Stderr),
+            "syslog" => Ok(LogSink::Syslog),
+            "journald" => Ok(LogSink::Journald),
+            _ => Err(JWTError::InvalidArgumentError(
+                format!(
+                    "unknown log sink {}; expected stderr, syslog or journald",
+                    name
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LogSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogSink::Stderr => "stderr",
+            LogSink::Syslog => "syslog",
+            LogSink::Journald => "journald",
+        })
+    }
+}
+
+/// A sink with its socket, if it has one.
+#[derive(Debug)]
+pub struct Connected {
+    sink: LogSink,
+    #[cfg(unix)]
+    socket: Option<std::os::unix::net::UnixDatagram>,
+}
+
+impl Connected {
+    /// Connects `sink` to the socket at `path`; `stderr` has none.
+    #[cfg(unix)]
+    pub fn connect(sink: LogSink, path: &str) -> Result<Connected, JWTError> {
+        let socket = match sink {
+            LogSink::Stderr => None,
+            LogSink::Syslog | LogSink::Journald => {
+                let socket = std::os::unix::net::UnixDatagram::unbound()?;
+                socket.connect(path).map_err(|e| {
+                    JWTError::SinkError(crate::ErrorMessage::caused_by(
+                        format!("cannot reach {} at {}: {}", sink, path, e),
+                        e,
+                    ))
+                })?;
+                Some(socket)
+            }
+        };
+        Ok(Connected { sink, socket })
+    }
+
+    #[cfg(not(unix))]
+    pub fn connect(sink: LogSink, _: &str) -> Result<Connected, JWTError> {
+        match sink {
+            LogSink::Stderr => Ok(Connected { sink }),
+            _ => Err(JWTError::SinkError(
+                format!(
+                    "{} needs a Unix socket, which this platform doesn't have",
+                    sink
+                )
+                .into(),
+            )),
+        }
+    }
+
+    /// Sends one line, falling back to stderr.
+    pub fn send(&self, priority: Priority, message: &str) {
+        let entry = match self.sink {
+            LogSink::Stderr => None,
+            LogSink::Syslog => Some(syslog_message(priority, message, std::process::id())),
+            LogSink::Journald => Some(journal_entry(priority, message)),
+        };
+        #[cfg(unix)]
+        if let (Some(entry), Some(socket)) = (entry, &self.socket) {
+            if socket.send(&entry).is_ok() {
+                return;
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = entry;
+        eprintln!("{}", message);
+    }
+}
+
+/// `<PRI>TAG[PID]: MESSAGE`, as `/dev/log` takes it; the daemon adds the
+/// time and host.
+pub fn syslog_message(priority: Priority, message: &str, pid: u32) -> Vec<u8> {
+    format!(
+        "<{}>{}[{}]: {}",
+        FACILITY * 8 + priority as u8,
+        IDENTIFIER,
+        pid,
+        message.replace('\n', " ")
+    )
+    .into_bytes()
+}
+
+/// The journal's native protocol: `FIELD=value` lines, and for a value
+/// with a newline the field name, a newline, its length as 64-bit little
+/// endian and the bytes.
+pub fn journal_entry(priority: Priority, message: &str) -> Vec<u8> {
+    let mut entry = Vec::new();
+    let fields = [
+        ("PRIORITY", (priority as u8).to_string()),
+        ("SYSLOG_IDENTIFIER", IDENTIFIER.to_string()),
+        ("MESSAGE", message.to_string()),
+    ];
+    for (name, value) in fields {
+        entry.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+    entry
+}
+
+/// Sends every later `log` line to `sink`, at its usual socket.
+pub fn use_sink(sink: LogSink) -> Result<(), JWTError> {
+    let path = match sink {
+        LogSink::Journald => JOURNAL_SOCKET,
+        _ => SYSLOG_SOCKET,
+    };
+    let connected = Connected::connect(sink, path)?;
+    SINK.set(connected)
+        .map_err(|_| JWTError::InvalidArgumentError("the log sink is already chosen".into()))
+}
+
+/// One log line, to the chosen sink or else stderr.
+pub fn log(priority: Priority, message: &str) {
+    match SINK.get() {
+        Some(connected) => connected.send(priority, message),
+        None => eprintln!("{}", message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_test() {
+        assert_eq!(
+            syslog_message(Priority::Warning, "POST /verify 401 internal\nsub=x", 42),
+            b"<28>jwt-check[42]: POST /verify 401 internal sub=x".to_vec()
+        );
+        assert_eq!(
+            journal_entry(Priority::Err, "reload failed"),
+            b"PRIORITY=3\nSYSLOG_IDENTIFIER=jwt-check\nMESSAGE=reload failed\n".to_vec()
+        );
+        let entry = journal_entry(Priority::Info, "a\nb");
+        assert!(entry.ends_with(b"MESSAGE\n\x03\0\0\0\0\0\0\0a\nb\n"));
+        assert_eq!(Priority::of_status(503), Priority::Err);
+        assert_eq!(Priority::of_status(401), Priority::Warning);
+        assert_eq!(Priority::of_status(200), Priority::Info);
+        assert_eq!("journald".parse::<LogSink>().unwrap(), LogSink::Journald);
+        assert!(matches!(
+            "eventlog".parse::<LogSink>(),
+            Err(JWTError::InvalidArgumentError(e))
+                if e == "unknown log sink eventlog; expected stderr, syslog or journald"
+        ));
+    }
+
+    #[test]
+    fn use_sink_test() {
+        // stderr logs as if no sink were chosen, so choosing it here leaves
+        // the other tests' output where it was
+        use_sink(LogSink::Stderr).unwrap();
+        assert!(matches!(
+            use_sink(LogSink::Stderr),
+            Err(JWTError::InvalidArgumentError(e)) if e == "the log sink is already chosen"
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn connected_test() {
+        let dir = std::env::temp_dir().join(format!("jwt-check-syslog-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("log");
+        let _ = std::fs::remove_file(&path);
+        let server = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        let connected = Connected::connect(LogSink::Syslog, path.to_str().unwrap()).unwrap();
+        connected.send(Priority::Notice, "reload: policy internal: 2 key(s)");
+        let mut buffer = [0; 256];
+        let n = server.recv(&mut buffer).unwrap();
+        let expected = format!(
+            "<29>jwt-check[{}]: reload: policy internal: 2 key(s)",
+            std::process::id()
+        );
+        assert_eq!(buffer.get(..n).unwrap(), expected.as_bytes());
+        let missing = dir.join("missing");
+        let refused = Connected::connect(LogSink::Journald, missing.to_str().unwrap());
+        assert!(matches!(
+            refused,
+            Err(JWTError::SinkError(e)) if e == format!(
+                "cannot reach journald at {}: No such file or directory (os error 2)",
+                missing.display()
+            )
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
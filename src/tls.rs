@@ -0,0 +1,470 @@
+//! Decrypting captured TLS connections with an `SSLKEYLOGFILE`.
+//!
+//! Covers the AES-GCM cipher suites of TLS 1.2 (keys from the logged master
+//! secret) and TLS 1.3 (keys from the logged traffic secrets, including key
+//! updates). ChaCha20-Poly1305 and CBC suites are reported as unsupported.
+
+use crate::crypto::{gcm_decrypt, hmac, Hash};
+use std::collections::HashMap;
+
+const CONTENT_CHANGE_CIPHER_SPEC: u8 = 20;
+const CONTENT_HANDSHAKE: u8 = 22;
+const CONTENT_APPLICATION_DATA: u8 = 23;
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const HANDSHAKE_SERVER_HELLO: u8 = 2;
+const EXTENSION_SUPPORTED_VERSIONS: u16 = 43;
+const TAG_LEN: usize = 16;
+
+/// Secrets from an NSS key log, by label and client random.
+#[derive(Default)]
+pub struct KeyLog {
+    secrets: HashMap<(String, Vec<u8>), Vec<u8>>,
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(text.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+impl KeyLog {
+    /// Parses `LABEL <client random> <secret>` lines, skipping comments and
+    /// anything malformed.
+    pub fn parse(text: &str) -> KeyLog {
+        let mut secrets = HashMap::new();
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if let [label, random, secret] = fields.as_slice() {
+                if let (Some(random), Some(secret)) = (unhex(random), unhex(secret)) {
+                    secrets.insert((label.to_string(), random), secret);
+                }
+            }
+        }
+        KeyLog { secrets }
+    }
+
+    pub fn len(&self) -> usize {
+        self.secrets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.secrets.is_empty()
+    }
+
+    fn get(&self, label: &str, client_random: &[u8]) -> Option<&[u8]> {
+        self.secrets
+            .get(&(label.to_string(), client_random.to_vec()))
+            .map(Vec::as_slice)
+    }
+}
+
+/// A TLS record: content type, then the fragment. `header` is the five
+/// bytes the AEAD of TLS 1.3 authenticates.
+struct Record<'a> {
+    content_type: u8,
+    header: &'a [u8],
+    fragment: &'a [u8],
+}
+
+fn be16(bytes: &[u8]) -> Option<usize> {
+    match bytes {
+        [high, low, ..] => Some(usize::from(u16::from_be_bytes([*high, *low]))),
+        _ => None,
+    }
+}
+
+fn records(stream: &[u8]) -> Vec<Record<'_>> {
+    let mut out = Vec::new();
+    let mut rest = stream;
+    while let Some((header, body)) = rest.split_at_checked(5) {
+        let (content_type, len) = match (header.first(), header.get(3..).and_then(be16)) {
+            (Some(content_type), Some(len)) => (*content_type, len),
+            _ => break,
+        };
+        let (fragment, next) = match body.split_at_checked(len) {
+            Some(split) => split,
+            None => break,
+        };
+        out.push(Record {
+            content_type,
+            header,
+            fragment,
+        });
+        rest = next;
+    }
+    out
+}
+
+/// Whether a stream opens with a TLS handshake record.
+pub fn looks_like_tls(stream: &[u8]) -> bool {
+    matches!(stream.get(..3), Some([CONTENT_HANDSHAKE, 3, minor]) if *minor <= 4)
+}
+
+/// The plaintext handshake messages at the start of a stream, as
+/// (type, body), up to the first change_cipher_spec or encrypted record.
+fn plaintext_handshake(stream: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let mut data = Vec::new();
+    for record in records(stream) {
+        if record.content_type != CONTENT_HANDSHAKE {
+            break;
+        }
+        data.extend_from_slice(record.fragment);
+    }
+    let mut messages = Vec::new();
+    let mut rest = data.as_slice();
+    while let Some(([kind, a, b, c], body)) = rest.split_first_chunk::<4>() {
+        let len = usize::from(*a) << 16 | usize::from(*b) << 8 | usize::from(*c);
+        match body.split_at_checked(len) {
+            Some((message, next)) => {
+                messages.push((*kind, message.to_vec()));
+                rest = next;
+            }
+            None => break,
+        }
+    }
+    messages
+}
+
+struct ServerHello {
+    random: Vec<u8>,
+    cipher_suite: u16,
+    tls13: bool,
+}
+
+fn server_hello(body: &[u8]) -> Option<ServerHello> {
+    let random = body.get(2..34)?.to_vec();
+    let session_id_len = usize::from(*body.get(34)?);
+    let at = 35 + session_id_len;
+    let cipher_suite = be16(body.get(at..)?)? as u16;
+    // compression method, then optional extensions
+    let mut extensions = body.get(at + 5..).unwrap_or_default();
+    let mut tls13 = false;
+    while let Some((header, rest)) = extensions.split_at_checked(4) {
+        let kind = be16(header)? as u16;
+        let (data, next) = rest.split_at_checked(be16(header.get(2..)?)?)?;
+        if kind == EXTENSION_SUPPORTED_VERSIONS && data == [3, 4] {
+            tls13 = true;
+        }
+        extensions = next;
+    }
+    Some(ServerHello {
+        random,
+        cipher_suite,
+        tls13,
+    })
+}
+
+/// (key length, hash) of a supported AES-GCM suite.
+fn suite_params(cipher_suite: u16, tls13: bool) -> Option<(usize, Hash)> {
+    match (tls13, cipher_suite) {
+        (true, 0x1301) => Some((16, Hash::Sha256)),
+        (true, 0x1302) => Some((32, Hash::Sha384)),
+        (false, 0x009c) | (false, 0x009e) | (false, 0xc02b) | (false, 0xc02f) => {
+            Some((16, Hash::Sha256))
+        }
+        (false, 0x009d) | (false, 0x009f) | (false, 0xc02c) | (false, 0xc030) => {
+            Some((32, Hash::Sha384))
+        }
+        _ => None,
+    }
+}
+
+/// HKDF-Expand (RFC 5869).
+fn hkdf_expand(hash: Hash, prk: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len + hash.output_len());
+    let mut block = Vec::new();
+    let mut counter = 1u8;
+    while out.len() < len {
+        let mut input = block;
+        input.extend_from_slice(info);
+        input.push(counter);
+        block = hmac(hash, prk, &input);
+        out.extend_from_slice(&block);
+        counter = counter.wrapping_add(1);
+    }
+    out.truncate(len);
+    out
+}
+
+/// HKDF-Expand-Label with an empty context (RFC 8446 section 7.1).
+fn expand_label(hash: Hash, secret: &[u8], label: &str, len: usize) -> Vec<u8> {
+    let label = format!("tls13 {}", label);
+    let mut info = (len as u16).to_be_bytes().to_vec();
+    info.push(label.len() as u8);
+    info.extend_from_slice(label.as_bytes());
+    info.push(0);
+    hkdf_expand(hash, secret, &info, len)
+}
+
+/// The TLS 1.2 PRF, P_hash over `label || seed` (RFC 5246 section 5).
+fn prf(hash: Hash, secret: &[u8], label: &str, seed: &[u8], len: usize) -> Vec<u8> {
+    let mut label_seed = label.as_bytes().to_vec();
+    label_seed.extend_from_slice(seed);
+    let mut out = Vec::with_capacity(len + hash.output_len());
+    let mut a = hmac(hash, secret, &label_seed);
+    while out.len() < len {
+        let mut input = a.clone();
+        input.extend_from_slice(&label_seed);
+        out.extend_from_slice(&hmac(hash, secret, &input));
+        a = hmac(hash, secret, &a);
+    }
+    out.truncate(len);
+    out
+}
+
+fn nonce(iv: &[u8], seq: u64) -> Vec<u8> {
+    let mut nonce = iv.to_vec();
+    for (byte, s) in nonce.iter_mut().rev().zip(seq.to_be_bytes().iter().rev()) {
+        *byte ^= s;
+    }
+    nonce
+}
+
+fn open(key: &[u8], nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    let split = sealed.len().checked_sub(TAG_LEN)?;
+    let (ciphertext, tag) = sealed.split_at(split);
+    gcm_decrypt(key, nonce, aad, ciphertext, tag)
+}
+
+/// TLS 1.3: tries the handshake keys, then the application keys and each
+/// key update after them, moving on whenever a record fails to open under
+/// the current generation.
+fn decrypt_tls13(
+    stream: &[u8],
+    hash: Hash,
+    key_len: usize,
+    handshake: Option<&[u8]>,
+    application: Option<&[u8]>,
+) -> Vec<u8> {
+    let keys = |secret: &[u8]| {
+        (
+            expand_label(hash, secret, "key", key_len),
+            expand_label(hash, secret, "iv", 12),
+        )
+    };
+    let mut generations: Vec<Vec<u8>> = handshake.iter().map(|s| s.to_vec()).collect();
+    let first_application = generations.len();
+    if let Some(secret) = application {
+        generations.push(secret.to_vec());
+    }
+    let mut current = 0;
+    let mut seq = 0u64;
+    let mut plaintext = Vec::new();
+    for record in records(stream) {
+        if record.content_type != CONTENT_APPLICATION_DATA {
+            continue;
+        }
+        let mut opened = None;
+        let start = current;
+        for generation in start..start + 2 {
+            if generation == generations.len() && generation > first_application {
+                // a KeyUpdate may follow; derive the next traffic secret
+                if let Some(previous) = generations.last() {
+                    let next = expand_label(hash, previous, "traffic upd", hash.output_len());
+                    generations.push(next);
+                }
+            }
+            let secret = match generations.get(generation) {
+                Some(secret) => secret,
+                None => break,
+            };
+            let (key, iv) = keys(secret);
+            let attempt = if generation == current { seq } else { 0 };
+            if let Some(inner) = open(&key, &nonce(&iv, attempt), record.header, record.fragment) {
+                current = generation;
+                seq = attempt + 1;
+                opened = Some(inner);
+                break;
+            }
+        }
+        let mut inner = match opened {
+            Some(inner) => inner,
+            None => continue,
+        };
+        while inner.last() == Some(&0) {
+            inner.pop();
+        }
+        if inner.pop() == Some(CONTENT_APPLICATION_DATA) {
+            plaintext.extend_from_slice(&inner);
+        }
+    }
+    plaintext
+}
+
+/// TLS 1.2 AES-GCM: every record after change_cipher_spec is sealed, with
+/// an explicit 8-byte nonce after the 4-byte implicit salt.
+fn decrypt_tls12(stream: &[u8], key: &[u8], salt: &[u8]) -> Vec<u8> {
+    let mut encrypted = false;
+    let mut seq = 0u64;
+    let mut plaintext = Vec::new();
+    for record in records(stream) {
+        if record.content_type == CONTENT_CHANGE_CIPHER_SPEC {
+            encrypted = true;
+            continue;
+        }
+        if !encrypted {
+            continue;
+        }
+        let (explicit, sealed) = match (record.fragment.get(..8), record.fragment.get(8..)) {
+            (Some(explicit), Some(sealed)) if sealed.len() >= TAG_LEN => (explicit, sealed),
+            _ => continue,
+        };
+        let mut nonce = salt.to_vec();
+        nonce.extend_from_slice(explicit);
+        let mut aad = seq.to_be_bytes().to_vec();
+        aad.extend_from_slice(record.header.get(..3).unwrap_or_default());
+        aad.extend_from_slice(&((sealed.len() - TAG_LEN) as u16).to_be_bytes());
+        seq += 1;
+        if let Some(inner) = open(key, &nonce, &aad, sealed) {
+            if record.content_type == CONTENT_APPLICATION_DATA {
+                plaintext.extend_from_slice(&inner);
+            }
+        }
+    }
+    plaintext
+}
+
+/// A decrypted connection: the protocol version and the application data
+/// sent each way.
+pub struct Decrypted {
+    pub version: &'static str,
+    pub to_server: Vec<u8>,
+    pub to_client: Vec<u8>,
+}
+
+/// Decrypts both directions of a TLS connection, or explains why not.
+pub fn decrypt(keylog: &KeyLog, to_server: &[u8], to_client: &[u8]) -> Result<Decrypted, String> {
+    let client_random = plaintext_handshake(to_server)
+        .into_iter()
+        .find(|(kind, _)| *kind == HANDSHAKE_CLIENT_HELLO)
+        .and_then(|(_, body)| body.get(2..34).map(<[u8]>::to_vec))
+        .ok_or("no ClientHello captured")?;
+    let hello = plaintext_handshake(to_client)
+        .into_iter()
+        .find(|(kind, _)| *kind == HANDSHAKE_SERVER_HELLO)
+        .and_then(|(_, body)| server_hello(&body))
+        .ok_or("no ServerHello captured")?;
+    let (key_len, hash) = suite_params(hello.cipher_suite, hello.tls13)
+        .ok_or_else(|| format!("cipher suite {:#06x} is not supported", hello.cipher_suite))?;
+    if hello.tls13 {
+        let secret = |label: &str| keylog.get(label, &client_random);
+        if secret("CLIENT_TRAFFIC_SECRET_0").is_none()
+            && secret("SERVER_TRAFFIC_SECRET_0").is_none()
+        {
+            return Err(
+                "no TLS 1.3 traffic secrets for this connection in the key log".to_string(),
+            );
+        }
+        return Ok(Decrypted {
+            version: "TLS 1.3",
+            to_server: decrypt_tls13(
+                to_server,
+                hash,
+                key_len,
+                secret("CLIENT_HANDSHAKE_TRAFFIC_SECRET"),
+                secret("CLIENT_TRAFFIC_SECRET_0"),
+            ),
+            to_client: decrypt_tls13(
+                to_client,
+                hash,
+                key_len,
+                secret("SERVER_HANDSHAKE_TRAFFIC_SECRET"),
+                secret("SERVER_TRAFFIC_SECRET_0"),
+            ),
+        });
+    }
+    let master = keylog
+        .get("CLIENT_RANDOM", &client_random)
+        .ok_or("no master secret for this connection in the key log")?;
+    let mut seed = hello.random.clone();
+    seed.extend_from_slice(&client_random);
+    let block = prf(hash, master, "key expansion", &seed, 2 * key_len + 8);
+    let (client_key, rest) = block.split_at(key_len);
+    let (server_key, rest) = rest.split_at(key_len);
+    let (client_salt, server_salt) = rest.split_at(4);
+    Ok(Decrypted {
+        version: "TLS 1.2",
+        to_server: decrypt_tls12(to_server, client_key, client_salt),
+        to_client: decrypt_tls12(to_client, server_key, server_salt),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc8448_traffic_keys_test() {
+        // RFC 8448 section 3: the server handshake traffic secret and the
+        // key and IV derived from it
+        let secret =
+            unhex("b67b7d690cc16c4e75e54213cb2d37b4e9c912bcded9105d42befd59d391ad38").unwrap();
+        assert_eq!(
+            expand_label(Hash::Sha256, &secret, "key", 16),
+            unhex("3fce516009c21727d0f2e4e86ee403bc").unwrap()
+        );
+        assert_eq!(
+            expand_label(Hash::Sha256, &secret, "iv", 12),
+            unhex("5d313eb2671276ee13000b30").unwrap()
+        );
+        let log = KeyLog::parse("# comment\nCLIENT_RANDOM 0a0b 0c0d\nbroken line\n");
+        assert_eq!(log.len(), 1);
+        assert_eq!(
+            log.get("CLIENT_RANDOM", &[0x0a, 0x0b]),
+            Some(&[0x0c, 0x0d][..])
+        );
+    }
+
+    /// A plaintext handshake record holding one message.
+    fn handshake(kind: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![kind, 0];
+        message.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        message.extend_from_slice(body);
+        let mut record = vec![CONTENT_HANDSHAKE, 3, 3];
+        record.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        record.extend(message);
+        record
+    }
+
+    /// A ServerHello choosing `cipher_suite`, for TLS 1.3 when `tls13`.
+    fn server_hello_record(cipher_suite: u16, tls13: bool) -> Vec<u8> {
+        let mut body = vec![3, 3];
+        body.extend_from_slice(&[0x22; 32]);
+        body.push(0);
+        body.extend_from_slice(&cipher_suite.to_be_bytes());
+        body.push(0);
+        let versions: &[u8] = if tls13 { &[0, 0x2b, 0, 2, 3, 4] } else { &[] };
+        body.extend_from_slice(&(versions.len() as u16).to_be_bytes());
+        body.extend_from_slice(versions);
+        handshake(HANDSHAKE_SERVER_HELLO, &body)
+    }
+
+    #[test]
+    fn undecryptable_test() {
+        let mut client_hello = vec![3, 3];
+        client_hello.extend_from_slice(&[0x11; 32]);
+        let to_server = handshake(HANDSHAKE_CLIENT_HELLO, &client_hello);
+        let empty = KeyLog::parse("");
+        let error_of = |to_server: &[u8], to_client: &[u8]| {
+            error_message!(decrypt(&empty, to_server, to_client))
+        };
+        assert_eq!(error_of(b"", b""), "no ClientHello captured");
+        assert_eq!(error_of(&to_server, b""), "no ServerHello captured");
+        assert_eq!(
+            error_of(&to_server, &server_hello_record(0x1303, true)),
+            "cipher suite 0x1303 is not supported"
+        );
+        assert_eq!(
+            error_of(&to_server, &server_hello_record(0x1301, true)),
+            "no TLS 1.3 traffic secrets for this connection in the key log"
+        );
+        assert_eq!(
+            error_of(&to_server, &server_hello_record(0xc02f, false)),
+            "no master secret for this connection in the key log"
+        );
+    }
+}
@@ -0,0 +1,428 @@
+//! `--trust-dir`: Open Banking trust directories, for software statements
+//! and the tokens organizations sign with the keys those statements name.
+//!
+//! The UK Open Banking Directory and the Brazil Open Finance Directory both
+//! publish their keys as a keystore: the directory's own software statement
+//! signing keys at `/openbanking.jwks`, and each organization's keys under
+//! its `org_id` and `software_id`. The UK keystore has
+//! `/{org_id}/{software_id}.jwks` and Brazil's
+//! `/{org_id}/{software_id}/application.jwks`. `--trust-dir` takes a local
+//! mirror of either, laid out the same way.
+//!
+//! Verification then goes end to end. A software statement (SSA) is checked
+//! against the directory keys. A token an organization signed, such as a
+//! DCR request, request object or client assertion, is checked against the
+//! keys of the software its statement describes. That statement is the one
+//! in its `software_statement` claim, or the one given as
+//! `--software-statement`. Without either, the software is found by the
+//! token's `iss`, which is the `software_id` in client assertions and
+//! request objects.
+
+use crate::jwks::select_keys;
+use crate::keys::VerifyingKey;
+use crate::verify::{token_algorithm, verify};
+use crate::{parse, ErrorMessage, JWTError, JWToken};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// The directory's software statement signing keys, in both keystores.
+pub const DIRECTORY_KEYS: &str = "openbanking.jwks";
+
+fn trust_error(message: impl Into<ErrorMessage>) -> JWTError {
+    JWTError::TrustError(message.into())
+}
+
+/// A verified software statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoftwareStatement {
+    pub org_id: String,
+    pub software_id: String,
+    pub claims: Value,
+}
+
+impl SoftwareStatement {
+    fn from_claims(claims: &Value) -> Result<SoftwareStatement, JWTError> {
+        let text = |name: &str| {
+            claims
+                .get(name)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| trust_error(format!("the software statement has no `{}`", name)))
+        };
+        Ok(SoftwareStatement {
+            org_id: text("org_id")?,
+            software_id: text("software_id")?,
+            claims: claims.clone(),
+        })
+    }
+
+    /// `software_jwks_endpoint` (UK) or `software_jwks_uri` (Brazil).
+    fn jwks_uri(&self) -> Option<&str> {
+        ["software_jwks_endpoint", "software_jwks_uri"]
+            .iter()
+            .find_map(|name| self.claims.get(name).and_then(Value::as_str))
+    }
+}
+
+/// The signing keys the directory mirror holds for a token's organisation
+/// and software.
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub keys: Vec<VerifyingKey>,
+    /// The software statement the keys come from, unless the token is one.
+    pub statement: Option<SoftwareStatement>,
+    /// Which mirror entries were looked up on the way to the keys, printed
+    /// as the `trust:` lines.
+    pub steps: Vec<String>,
+}
+
+/// A local mirror of a directory keystore.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Directory {
+    root: PathBuf,
+    keys: Value,
+}
+
+fn read_jwks(path: &Path) -> Result<Value, JWTError> {
+    let bytes = std::fs::read(path).map_err(|e| {
+        trust_error(ErrorMessage::caused_by(
+            format!("cannot read {}: {}", path.display(), e),
+            e,
+        ))
+    })?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+impl Directory {
+    pub fn open(root: &Path) -> Result<Directory, JWTError> {
+        Ok(Directory {
+            root: root.to_path_buf(),
+            keys: read_jwks(&root.join(DIRECTORY_KEYS))?,
+        })
+    }
+
+    /// Whether the directory itself signed `token`: it names one of the
+    /// directory keys.
+    fn signed_by_directory(&self, token: &JWToken) -> bool {
+        let kid = token.header.get("kid").and_then(Value::as_str);
+        self.keys
+            .get("keys")
+            .and_then(Value::as_array)
+            .is_some_and(|keys| {
+                keys.iter()
+                    .any(|jwk| kid.is_some() && jwk.get("kid").and_then(Value::as_str) == kid)
+            })
+    }
+
+    /// Verifies a software statement against the directory keys.
+    pub fn verify_statement(&self, raw: &str) -> Result<SoftwareStatement, JWTError> {
+        let token = parse(raw)?;
+        let kid = token.header.get("kid").and_then(Value::as_str);
+        let keys = select_keys(&self.keys, kid, token_algorithm(&token)?)?;
+        if !keys.iter().any(|key| verify(raw, &token, key).is_ok()) {
+            return Err(trust_error(
+                "the software statement's signature does not verify with the directory keys"
+                    .to_string(),
+            ));
+        }
+        SoftwareStatement::from_claims(&token.payload)
+    }
+
+    /// Where the software's keys are: the path of its JWKS URI within the
+    /// mirror, else the UK or the Brazil keystore layout.
+    fn software_jwks(&self, statement: &SoftwareStatement) -> Result<PathBuf, JWTError> {
+        let mirrored = statement.jwks_uri().map(|uri| {
+            let path = uri.split_once("://").map_or(uri, |(_, rest)| rest);
+            let path = path
+                .find('/')
+                .map_or("", |at| path.get(at + 1..).unwrap_or_default());
+            self.root.join(path)
+        });
+        let org = self.root.join(&statement.org_id);
+        let candidates = mirrored.into_iter().chain([
+            org.join(format!("{}.jwks", statement.software_id)),
+            org.join(&statement.software_id).join("application.jwks"),
+        ]);
+        let mut tried = Vec::new();
+        for candidate in candidates {
+            if candidate.is_file() {
+                return Ok(candidate);
+            }
+            tried.push(candidate.display().to_string());
+        }
+        Err(trust_error(format!(
+            "no keys for software {} of organization {}; tried {}",
+            statement.software_id,
+            statement.org_id,
+            tried.join(", ")
+        )))
+    }
+
+    /// The organization whose software is `software_id`, found by looking
+    /// through the mirror.
+    fn find_software(&self, software_id: &str) -> Result<SoftwareStatement, JWTError> {
+        let entries = std::fs::read_dir(&self.root).map_err(|e| {
+            trust_error(ErrorMessage::caused_by(
+                format!("cannot list {}: {}", self.root.display(), e),
+                e,
+            ))
+        })?;
+        for entry in entries.flatten() {
+            let org = entry.path();
+            if org.join(format!("{}.jwks", software_id)).is_file()
+                || org.join(software_id).join("application.jwks").is_file()
+            {
+                return Ok(SoftwareStatement {
+                    org_id: entry.file_name().to_string_lossy().into_owned(),
+                    software_id: software_id.to_string(),
+                    claims: Value::Null,
+                });
+            }
+        }
+        Err(trust_error(format!(
+            "no software `{}` in {}; pass its software statement with --software-statement",
+            software_id,
+            self.root.display()
+        )))
+    }
+
+    /// The keys that should have signed `token`. `statement` is a software
+    /// statement given separately, as a compact JWT.
+    pub fn resolve(
+        &self,
+        token: &JWToken,
+        statement: Option<&str>,
+    ) -> Result<Resolution, JWTError> {
+        let alg = token_algorithm(token)?;
+        let kid = token.header.get("kid").and_then(Value::as_str);
+        if statement.is_none() && self.signed_by_directory(token) {
+            return Ok(Resolution {
+                keys: select_keys(&self.keys, kid, alg)?,
+                statement: None,
+                steps: vec![format!(
+                    "software statement signed by the directory ({})",
+                    self.root.join(DIRECTORY_KEYS).display()
+                )],
+            });
+        }
+        let mut steps = Vec::new();
+        let embedded = token
+            .payload
+            .get("software_statement")
+            .and_then(Value::as_str);
+        let software = match statement.or(embedded) {
+            Some(raw) => {
+                let verified = self.verify_statement(raw)?;
+                steps.push(format!(
+                    "software statement for software {} of organization {}: verified with the directory keys",
+                    verified.software_id, verified.org_id
+                ));
+                verified
+            }
+            None => {
+                let iss = token
+                    .payload
+                    .get("iss")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| {
+                        trust_error(
+                        "the token has no software statement and no iss to find its software by"
+                            .to_string(),
+                    )
+                    })?;
+                let found = self.find_software(iss)?;
+                steps.push(format!(
+                    "software {} of organization {} found by iss",
+                    found.software_id, found.org_id
+                ));
+                found
+            }
+        };
+        let path = self.software_jwks(&software)?;
+        let keys = select_keys(&read_jwks(&path)?, kid, alg)?;
+        steps.push(format!("signing keys from {}", path.display()));
+        Ok(Resolution {
+            keys,
+            statement: Some(software),
+            steps,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::SigningKey;
+    use crate::sign::sign;
+    use serde_json::json;
+
+    #[test]
+    fn resolves_keys_through_the_directory_test() {
+        let root =
+            std::env::temp_dir().join(format!("jwt-check-trust-test-{}", std::process::id()));
+        let oct = |kid: &str, secret: &[u8]| {
+            json!({"keys": [{"kty": "oct", "kid": kid,
+                "k": base64::encode_config(secret, base64::URL_SAFE_NO_PAD)}]})
+        };
+        let write = |path: &Path, jwks: &Value| {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, jwks.to_string()).unwrap();
+        };
+        write(
+            &root.join(DIRECTORY_KEYS),
+            &oct("directory-1", b"directory secret"),
+        );
+        // a UK-style and a Brazil-style organization
+        write(
+            &root.join("org-uk").join("sw-uk.jwks"),
+            &oct("uk-1", b"uk secret"),
+        );
+        write(
+            &root.join("org-br").join("sw-br").join("application.jwks"),
+            &oct("br-1", b"br secret"),
+        );
+        let signed = |kid: &str, secret: &[u8], claims: Value| {
+            sign(
+                &json!({"alg": "HS256", "kid": kid}),
+                &claims,
+                &SigningKey::Hmac(secret.to_vec()),
+            )
+            .unwrap()
+        };
+        let directory = Directory::open(&root).unwrap();
+
+        let ssa = signed(
+            "directory-1",
+            b"directory secret",
+            json!({"iss": "OpenBanking Ltd", "org_id": "org-br", "software_id": "sw-br",
+                   "software_jwks_uri": "https://keystore.example/org-br/sw-br/application.jwks"}),
+        );
+        let resolution = directory.resolve(&parse(&ssa).unwrap(), None).unwrap();
+        assert!(resolution.statement.is_none());
+        assert!(verify(&ssa, &parse(&ssa).unwrap(), &resolution.keys[0]).is_ok());
+
+        // a DCR request carrying the statement, signed with the software's key
+        let dcr = signed(
+            "br-1",
+            b"br secret",
+            json!({"software_statement": ssa, "redirect_uris": []}),
+        );
+        let resolution = directory.resolve(&parse(&dcr).unwrap(), None).unwrap();
+        assert_eq!(resolution.statement.unwrap().software_id, "sw-br");
+        assert!(verify(&dcr, &parse(&dcr).unwrap(), &resolution.keys[0]).is_ok());
+        assert_eq!(resolution.steps.len(), 2);
+
+        // a client assertion, found by iss
+        let assertion = signed(
+            "uk-1",
+            b"uk secret",
+            json!({"iss": "sw-uk", "sub": "sw-uk"}),
+        );
+        let resolution = directory
+            .resolve(&parse(&assertion).unwrap(), None)
+            .unwrap();
+        assert_eq!(
+            resolution.steps[0],
+            "software sw-uk of organization org-uk found by iss"
+        );
+
+        // a statement the directory didn't sign
+        let forged = signed(
+            "directory-1",
+            b"guessed",
+            json!({"org_id": "org-uk", "software_id": "sw-uk"}),
+        );
+        let request = signed("uk-1", b"uk secret", json!({"software_statement": forged}));
+        assert!(matches!(
+            directory.resolve(&parse(&request).unwrap(), None),
+            Err(JWTError::TrustError(e)) if e.contains("does not verify")
+        ));
+        let unknown = signed("x", b"x", json!({"iss": "sw-missing"}));
+        assert!(directory.resolve(&parse(&unknown).unwrap(), None).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn reports_each_trust_failure_test() {
+        let root = std::env::temp_dir().join(format!(
+            "jwt-check-trust-errors-test-{}",
+            std::process::id()
+        ));
+        let trust_error_of =
+            |result: Result<Resolution, JWTError>| error_message!(TrustError, result);
+        assert!(matches!(
+            Directory::open(&root),
+            Err(JWTError::TrustError(e)) if e.starts_with(&format!("cannot read {}", root.join(DIRECTORY_KEYS).display()))
+        ));
+        std::fs::create_dir_all(&root).unwrap();
+        let secret = base64::encode_config(b"directory secret", base64::URL_SAFE_NO_PAD);
+        std::fs::write(
+            root.join(DIRECTORY_KEYS),
+            json!({"keys": [{"kty": "oct", "kid": "directory-1", "k": secret}]}).to_string(),
+        )
+        .unwrap();
+        let directory = Directory::open(&root).unwrap();
+        let signed = |kid: &str, secret: &[u8], claims: Value| {
+            sign(
+                &json!({"alg": "HS256", "kid": kid}),
+                &claims,
+                &SigningKey::Hmac(secret.to_vec()),
+            )
+            .unwrap()
+        };
+        let resolve = |token: &str, statement: Option<&str>| {
+            directory.resolve(&parse(token).unwrap(), statement)
+        };
+
+        let forged = signed("directory-1", b"guessed", json!({}));
+        assert_eq!(
+            trust_error_of(resolve(&signed("x", b"x", json!({})), Some(&forged))),
+            "the software statement's signature does not verify with the directory keys"
+        );
+        let no_org = signed(
+            "directory-1",
+            b"directory secret",
+            json!({"software_id": "sw-1"}),
+        );
+        assert_eq!(
+            trust_error_of(resolve(&signed("x", b"x", json!({})), Some(&no_org))),
+            "the software statement has no `org_id`"
+        );
+        let statement = signed(
+            "directory-1",
+            b"directory secret",
+            json!({"org_id": "org-1", "software_id": "sw-1"}),
+        );
+        let org = root.join("org-1");
+        assert_eq!(
+            trust_error_of(resolve(&signed("x", b"x", json!({})), Some(&statement))),
+            format!(
+                "no keys for software sw-1 of organization org-1; tried {}, {}",
+                org.join("sw-1.jwks").display(),
+                org.join("sw-1").join("application.jwks").display()
+            )
+        );
+        assert_eq!(
+            trust_error_of(resolve(&signed("x", b"x", json!({"sub": "sw-1"})), None)),
+            "the token has no software statement and no iss to find its software by"
+        );
+        assert_eq!(
+            trust_error_of(resolve(&signed("x", b"x", json!({"iss": "sw-1"})), None)),
+            format!(
+                "no software `sw-1` in {}; pass its software statement with --software-statement",
+                root.display()
+            )
+        );
+        // the mirror removed after the directory keys were read
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(
+            trust_error_of(resolve(&signed("x", b"x", json!({"iss": "sw-1"})), None)),
+            format!(
+                "cannot list {}: No such file or directory (os error 2)",
+                root.display()
+            )
+        );
+        let listing = resolve(&signed("x", b"x", json!({"iss": "sw-1"})), None).unwrap_err();
+        assert!(std::error::Error::source(&listing).is_some_and(|e| e.is::<std::io::Error>()));
+    }
+}
@@ -0,0 +1,517 @@
+//! `jwt-check webhook verify`: checks a sample webhook delivery the way the
+//! sender's documentation says receivers should, so a backend can be tested
+//! against a captured payload before it goes live.
+//!
+//! - `github`: `X-Hub-Signature-256: sha256=<hex>`, HMAC-SHA256 of the body.
+//! - `stripe`: `Stripe-Signature: t=<unix>,v1=<hex>,...`, HMAC-SHA256 of
+//!   `{t}.{body}`; any `v1` may match, since Stripe sends one per secret
+//!   while a secret is rolled.
+//! - `slack`: `X-Slack-Signature: v0=<hex>` with `X-Slack-Request-Timestamp`,
+//!   HMAC-SHA256 of `v0:{timestamp}:{body}`.
+//! - `jws`: a detached JWS over the body (RFC 7515 Appendix F) in
+//!   `X-JWS-Signature`, checked with any key `jws verify` takes.
+//!
+//! Stripe and Slack sign a timestamp so a captured delivery can't be
+//! replayed later; one further than the tolerance from now is refused.
+
+use crate::crypto::{constant_time_eq, hmac, Hash};
+use crate::keys::VerifyingKey;
+use crate::{jws, ErrorMessage, JWTError};
+use std::fmt;
+use std::str::FromStr;
+
+/// The names `--preset` accepts.
+pub const PRESETS: [&str; 4] = ["github", "stripe", "slack", "jws"];
+
+/// How far a signed timestamp may be from now, as Stripe and Slack
+/// recommend.
+pub const DEFAULT_TOLERANCE: i64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Preset {
+    GitHub,
+    Stripe,
+    Slack,
+    Jws,
+}
+
+impl FromStr for Preset {
+    type Err = JWTError;
+
+    fn from_str(name: &str) -> Result<Preset, JWTError> {
+        match name {
+            "github" => Ok(Preset::GitHub),
+            "stripe" => Ok(Preset::Stripe),
+            "slack" => Ok(Preset::Slack),
+            "jws" => Ok(Preset::Jws),
+            other => Err(webhook_error(format!(
+                "unknown preset `{}`; expected one of {}",
+                other,
+                PRESETS.join(", ")
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Preset::GitHub => "github",
+            Preset::Stripe => "stripe",
+            Preset::Slack => "slack",
+            Preset::Jws => "jws",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Preset {
+    /// The request header the signature arrives in.
+    pub fn signature_header(self) -> &'static str {
+        match self {
+            Preset::GitHub => "X-Hub-Signature-256",
+            Preset::Stripe => "Stripe-Signature",
+            Preset::Slack => "X-Slack-Signature",
+            Preset::Jws => "X-JWS-Signature",
+        }
+    }
+
+    /// The request header a separately sent timestamp arrives in.
+    pub fn timestamp_header(self) -> Option<&'static str> {
+        match self {
+            Preset::Slack => Some("X-Slack-Request-Timestamp"),
+            _ => None,
+        }
+    }
+}
+
+/// One delivery: the raw body and the signature headers' values.
+#[derive(Debug, Clone, Copy)]
+pub struct Delivery<'a> {
+    pub body: &'a [u8],
+    pub signature: &'a str,
+    pub timestamp: Option<&'a str>,
+}
+
+/// A delivery whose signature checked out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verified {
+    /// How it was signed, e.g. `HMAC-SHA256` or `ES256`.
+    pub scheme: String,
+    /// The signed timestamp, for the presets that sign one.
+    pub signed_at: Option<i64>,
+}
+
+fn webhook_error(message: impl Into<ErrorMessage>) -> JWTError {
+    JWTError::WebhookError(message.into())
+}
+
+/// The value of header `name` in an HTTP header block, as captured with
+/// `curl -D` or copied from a delivery log; names match case-insensitively.
+pub fn header_value<'a>(headers: &'a str, name: &str) -> Option<&'a str> {
+    headers.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim())
+        } else {
+            None
+        }
+    })
+}
+
+fn hex_decode(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(text.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+fn timestamp(text: &str, now: i64, tolerance: i64) -> Result<i64, JWTError> {
+    let signed_at: i64 = text
+        .trim()
+        .parse()
+        .map_err(|_| webhook_error(format!("timestamp `{}` is not unix seconds", text)))?;
+    // the sender picks the timestamp, so it may be anywhere in i64
+    if now.abs_diff(signed_at) > tolerance.unsigned_abs() {
+        return Err(webhook_error(format!(
+            "signed at {}, {} s from now, beyond the {} s tolerance; a replayed delivery looks like this",
+            signed_at,
+            now.saturating_sub(signed_at),
+            tolerance
+        )));
+    }
+    Ok(signed_at)
+}
+
+/// Checks `delivery` as `preset` signs it. HMAC presets take a shared
+/// secret; `jws` takes any verification key.
+pub fn verify(
+    preset: Preset,
+    delivery: &Delivery,
+    key: &VerifyingKey,
+    now: i64,
+    tolerance: i64,
+) -> Result<Verified, JWTError> {
+    let signature = delivery.signature.trim();
+    let (message, signed_at, candidates) = match preset {
+        Preset::Jws => {
+            let (raw, token) = jws::attach(signature, delivery.body)?;
+            let alg = crate::verify::verify(&raw, &token, key)?;
+            return Ok(Verified {
+                scheme: alg.to_string(),
+                signed_at: None,
+            });
+        }
+        Preset::GitHub => {
+            if signature.starts_with("sha1=") {
+                return Err(webhook_error(
+                    "sha1 signatures (X-Hub-Signature) are not supported; use X-Hub-Signature-256",
+                ));
+            }
+            let hex = signature
+                .strip_prefix("sha256=")
+                .ok_or_else(|| webhook_error("X-Hub-Signature-256 must start with sha256="))?;
+            (delivery.body.to_vec(), None, vec![hex])
+        }
+        Preset::Stripe => {
+            let mut t = None;
+            let mut candidates = Vec::new();
+            for field in signature.split(',') {
+                match field.trim().split_once('=') {
+                    Some(("t", value)) => t = Some(value),
+                    Some(("v1", value)) => candidates.push(value),
+                    _ => {}
+                }
+            }
+            let t = t.ok_or_else(|| webhook_error("Stripe-Signature has no t= timestamp"))?;
+            if candidates.is_empty() {
+                return Err(webhook_error("Stripe-Signature has no v1= signature"));
+            }
+            let signed_at = timestamp(t, now, tolerance)?;
+            let mut message = format!("{}.", t).into_bytes();
+            message.extend_from_slice(delivery.body);
+            (message, Some(signed_at), candidates)
+        }
+        Preset::Slack => {
+            let t = delivery.timestamp.ok_or_else(|| {
+                webhook_error("slack signatures need the X-Slack-Request-Timestamp value")
+            })?;
+            let hex = signature
+                .strip_prefix("v0=")
+                .ok_or_else(|| webhook_error("X-Slack-Signature must start with v0="))?;
+            let signed_at = timestamp(t, now, tolerance)?;
+            let mut message = format!("v0:{}:", t.trim()).into_bytes();
+            message.extend_from_slice(delivery.body);
+            (message, Some(signed_at), vec![hex])
+        }
+    };
+    let secret = match key {
+        VerifyingKey::Hmac(secret) => secret,
+        other => {
+            return Err(webhook_error(format!(
+                "{} webhooks are signed with a shared secret, not a {}",
+                preset,
+                other.describe()
+            )))
+        }
+    };
+    let expected = hmac(Hash::Sha256, secret, &message);
+    let valid = candidates.iter().any(|hex| {
+        hex_decode(hex).is_some_and(|candidate| constant_time_eq(&candidate, &expected))
+    });
+    if !valid {
+        return Err(JWTError::InvalidSignatureError);
+    }
+    Ok(Verified {
+        scheme: "HMAC-SHA256".to_string(),
+        signed_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::SigningKey;
+    use serde_json::json;
+
+    fn delivery<'a>(body: &'a [u8], signature: &'a str) -> Delivery<'a> {
+        Delivery {
+            body,
+            signature,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn github_test() {
+        // GitHub's documented example
+        let secret = VerifyingKey::Hmac(b"It's a Secret to Everybody".to_vec());
+        let signature = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+        let verified = verify(
+            Preset::GitHub,
+            &delivery(b"Hello, World!", signature),
+            &secret,
+            0,
+            DEFAULT_TOLERANCE,
+        )
+        .unwrap();
+        assert_eq!(verified.scheme, "HMAC-SHA256");
+        assert!(matches!(
+            verify(
+                Preset::GitHub,
+                &delivery(b"Hello, World?", signature),
+                &secret,
+                0,
+                DEFAULT_TOLERANCE
+            ),
+            Err(JWTError::InvalidSignatureError)
+        ));
+        assert!(matches!(
+            verify(
+                Preset::GitHub,
+                &delivery(b"Hello, World!", "sha1=01"),
+                &secret,
+                0,
+                DEFAULT_TOLERANCE
+            ),
+            Err(JWTError::WebhookError(e))
+                if e == "sha1 signatures (X-Hub-Signature) are not supported; use X-Hub-Signature-256"
+        ));
+        assert!(matches!(
+            verify(
+                Preset::GitHub,
+                &delivery(b"Hello, World!", "757107ea"),
+                &secret,
+                0,
+                DEFAULT_TOLERANCE
+            ),
+            Err(JWTError::WebhookError(e)) if e == "X-Hub-Signature-256 must start with sha256="
+        ));
+        // a malformed hex digest is just a mismatch
+        assert!(matches!(
+            verify(
+                Preset::GitHub,
+                &delivery(b"Hello, World!", "sha256=zz"),
+                &secret,
+                0,
+                DEFAULT_TOLERANCE
+            ),
+            Err(JWTError::InvalidSignatureError)
+        ));
+    }
+
+    #[test]
+    fn stripe_test() {
+        let secret = VerifyingKey::Hmac(b"whsec_test".to_vec());
+        let body = br#"{"id":"evt_1"}"#;
+        let signature = "t=1700000000,v1=0000,v1=c89214b5b5da833daed6f0b8c5bb6bd58cea9022bd80ccc78230f3942d632925,v0=ab";
+        let verified = verify(
+            Preset::Stripe,
+            &delivery(body, signature),
+            &secret,
+            1_700_000_100,
+            DEFAULT_TOLERANCE,
+        )
+        .unwrap();
+        assert_eq!(verified.signed_at, Some(1_700_000_000));
+        // a delivery from too long ago, and one dated too far ahead
+        for (now, behind) in [(1_700_001_000, 1000), (1_699_999_000, -1000)] {
+            assert!(matches!(
+                verify(
+                    Preset::Stripe,
+                    &delivery(body, signature),
+                    &secret,
+                    now,
+                    DEFAULT_TOLERANCE
+                ),
+                Err(JWTError::WebhookError(e))
+                    if e == format!(
+                        "signed at 1700000000, {} s from now, beyond the 300 s tolerance; \
+                         a replayed delivery looks like this",
+                        behind
+                    )
+            ));
+        }
+        // as far from now as an i64 goes, without overflowing on the way
+        let oldest = format!("t={},v1=00", i64::MIN);
+        assert!(matches!(
+            verify(
+                Preset::Stripe,
+                &delivery(body, &oldest),
+                &secret,
+                1_700_000_000,
+                DEFAULT_TOLERANCE
+            ),
+            Err(JWTError::WebhookError(e))
+                if e == format!(
+                    "signed at {}, {} s from now, beyond the 300 s tolerance; \
+                     a replayed delivery looks like this",
+                    i64::MIN,
+                    i64::MAX
+                )
+        ));
+        assert!(matches!(
+            verify(
+                Preset::Stripe,
+                &delivery(body, "t=1700000001,v1=c89214b5b5da833daed6f0b8c5bb6bd58cea9022bd80ccc78230f3942d632925"),
+                &secret,
+                1_700_000_000,
+                DEFAULT_TOLERANCE
+            ),
+            Err(JWTError::InvalidSignatureError)
+        ));
+        assert!(matches!(
+            verify(
+                Preset::Stripe,
+                &delivery(body, "v1=00"),
+                &secret,
+                0,
+                DEFAULT_TOLERANCE
+            ),
+            Err(JWTError::WebhookError(e)) if e == "Stripe-Signature has no t= timestamp"
+        ));
+        assert!(matches!(
+            verify(
+                Preset::Stripe,
+                &delivery(body, "t=1700000000,v0=00"),
+                &secret,
+                0,
+                DEFAULT_TOLERANCE
+            ),
+            Err(JWTError::WebhookError(e)) if e == "Stripe-Signature has no v1= signature"
+        ));
+        assert!(matches!(
+            verify(
+                Preset::Stripe,
+                &delivery(body, "t=soon,v1=00"),
+                &secret,
+                0,
+                DEFAULT_TOLERANCE
+            ),
+            Err(JWTError::WebhookError(e)) if e == "timestamp `soon` is not unix seconds"
+        ));
+    }
+
+    #[test]
+    fn slack_test() {
+        // Slack's documented example
+        let secret = VerifyingKey::Hmac(b"8f742231b10e8888abcd99yyyzzz85a5".to_vec());
+        let body = b"token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+        let delivery = Delivery {
+            body,
+            signature: "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503",
+            timestamp: Some("1531420618"),
+        };
+        let verified = verify(Preset::Slack, &delivery, &secret, 1_531_420_618, 300).unwrap();
+        assert_eq!(verified.signed_at, Some(1_531_420_618));
+        let unstamped = Delivery {
+            timestamp: None,
+            ..delivery
+        };
+        assert!(matches!(
+            verify(
+                Preset::Slack,
+                &unstamped,
+                &secret,
+                1_531_420_618,
+                300
+            ),
+            Err(JWTError::WebhookError(e))
+                if e == "slack signatures need the X-Slack-Request-Timestamp value"
+        ));
+        let restamped = Delivery {
+            timestamp: Some("1531420619"),
+            ..delivery
+        };
+        assert!(matches!(
+            verify(Preset::Slack, &restamped, &secret, 1_531_420_618, 300),
+            Err(JWTError::InvalidSignatureError)
+        ));
+        let unprefixed = Delivery {
+            signature: "a2114d57",
+            ..delivery
+        };
+        assert!(matches!(
+            verify(
+                Preset::Slack,
+                &unprefixed,
+                &secret,
+                1_531_420_618,
+                300
+            ),
+            Err(JWTError::WebhookError(e)) if e == "X-Slack-Signature must start with v0="
+        ));
+    }
+
+    #[test]
+    fn jws_test() {
+        let body = br#"{"event":"payment.settled"}"#;
+        let signature = jws::sign(
+            &json!({"alg": "HS256"}),
+            body,
+            &SigningKey::Hmac(b"shared".to_vec()),
+            true,
+        )
+        .unwrap();
+        let secret = VerifyingKey::Hmac(b"shared".to_vec());
+        let verified = verify(
+            Preset::Jws,
+            &delivery(body, &signature),
+            &secret,
+            0,
+            DEFAULT_TOLERANCE,
+        )
+        .unwrap();
+        assert_eq!(verified.scheme, "HS256");
+        assert!(matches!(
+            verify(
+                Preset::Jws,
+                &delivery(b"{}", &signature),
+                &secret,
+                0,
+                DEFAULT_TOLERANCE
+            ),
+            Err(JWTError::InvalidSignatureError)
+        ));
+    }
+
+    #[test]
+    fn presets_test() {
+        for name in PRESETS {
+            assert_eq!(name.parse::<Preset>().unwrap().to_string(), name);
+        }
+        assert!(matches!(
+            "twilio".parse::<Preset>(),
+            Err(JWTError::WebhookError(e))
+                if e == "unknown preset `twilio`; expected one of github, stripe, slack, jws"
+        ));
+        let point = "03eb43d00387f80daaff0f29e365576a9b799f8d82153b9931dc343f8a5140e502";
+        let ec = crate::keys::parse_public_key_pem(point).unwrap();
+        assert!(matches!(
+            verify(
+                Preset::GitHub,
+                &delivery(b"", "sha256=00"),
+                &ec,
+                0,
+                DEFAULT_TOLERANCE
+            ),
+            Err(JWTError::WebhookError(e))
+                if e == "github webhooks are signed with a shared secret, not a EC secp256k1 public key"
+        ));
+        let headers = "POST /hook HTTP/1.1\r\nx-slack-signature: v0=ab\r\nX-Slack-Request-Timestamp: 1531420618\r\n";
+        assert_eq!(
+            header_value(headers, Preset::Slack.signature_header()),
+            Some("v0=ab")
+        );
+        assert_eq!(
+            header_value(headers, "X-Slack-Request-Timestamp"),
+            Some("1531420618")
+        );
+        assert_eq!(header_value(headers, "Stripe-Signature"), None);
+        assert_eq!(hex_decode("0aff"), Some(vec![0x0a, 0xff]));
+        assert_eq!(hex_decode("abc"), None);
+    }
+}
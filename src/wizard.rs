@@ -0,0 +1,190 @@
+//! `jwt-check wizard`: asks a few questions and turns the answers into an
+//! ordinary command line, so the flags can be learned from a worked example.
+
+use crate::JWTError;
+use jwt_check::parse;
+use std::io::{BufRead, Write};
+
+fn ended_early() -> JWTError {
+    JWTError::InvalidArgumentError("wizard input ended before all questions were answered".into())
+}
+
+/// Prints `question` and reads one trimmed line of answer.
+fn ask<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    question: &str,
+) -> Result<String, JWTError> {
+    write!(output, "{} ", question)?;
+    output.flush()?;
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Err(ended_early());
+    }
+    Ok(line.trim().to_string())
+}
+
+/// Asks until the answer is non-empty.
+fn ask_required<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    question: &str,
+) -> Result<String, JWTError> {
+    loop {
+        let answer = ask(input, output, question)?;
+        if !answer.is_empty() {
+            return Ok(answer);
+        }
+    }
+}
+
+/// Asks a yes/no question; an empty answer takes `default`.
+fn confirm<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    question: &str,
+    default: bool,
+) -> Result<bool, JWTError> {
+    let hint = if default { "[Y/n]" } else { "[y/N]" };
+    loop {
+        match ask(input, output, &format!("{} {}", question, hint))?
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+/// Runs the interview and returns the equivalent argument list, starting
+/// with the binary name, after printing it as a shell command.
+pub fn run<R: BufRead, W: Write>(mut input: R, mut output: W) -> Result<Vec<String>, JWTError> {
+    let input = &mut input;
+    let output = &mut output;
+    let token = ask_required(input, output, "Token to check:")?;
+    let mut args = vec![
+        "jwt-check".to_string(),
+        "--token".to_string(),
+        token.clone(),
+    ];
+
+    // suggest the key type the token's own header asks for
+    let alg = parse(&token).ok().and_then(|t| {
+        t.header
+            .get("alg")
+            .and_then(|a| a.as_str())
+            .map(str::to_string)
+    });
+    let suggested = match alg.as_deref() {
+        Some(alg) if alg.starts_with("HS") => "1",
+        Some(alg) if alg.starts_with(['R', 'P', 'E']) => "2",
+        _ => "3",
+    };
+    writeln!(output, "How is the token signed?")?;
+    writeln!(output, "  1) shared secret (HS256, HS384, HS512)")?;
+    writeln!(
+        output,
+        "  2) public key or certificate (RS*, PS*, ES256, ES384, ES256K)"
+    )?;
+    writeln!(output, "  3) don't check the signature")?;
+    let choice = loop {
+        let answer = ask(input, output, &format!("Choice [{}]:", suggested))?;
+        match answer.as_str() {
+            "" => break suggested.to_string(),
+            "1" | "2" | "3" => break answer,
+            _ => {}
+        }
+    };
+    match choice.as_str() {
+        "1" => {
+            let path = ask_required(input, output, "File holding the secret:")?;
+            args.extend(["--verify".into(), "--secret-file".into(), path]);
+        }
+        "2" => {
+            let path = ask_required(input, output, "PEM public key or certificate file:")?;
+            args.extend(["--verify".into(), "--key".into(), path]);
+        }
+        _ => {}
+    }
+
+    if confirm(input, output, "Check exp, nbf and iat?", true)? {
+        args.push("--validate".into());
+        for (flag, question) in [
+            ("--iss", "Required issuer (blank to skip):"),
+            ("--aud", "Required audience (blank to skip):"),
+        ] {
+            let answer = ask(input, output, question)?;
+            if !answer.is_empty() {
+                args.extend([flag.to_string(), answer]);
+            }
+        }
+    }
+
+    writeln!(output)?;
+    writeln!(output, "equivalent command:")?;
+    writeln!(
+        output,
+        "  {}",
+        args.iter()
+            .map(|a| shell_quote(a))
+            .collect::<Vec<_>>()
+            .join(" ")
+    )?;
+    writeln!(output)?;
+    Ok(args)
+}
+
+/// Single-quotes an argument unless it is made only of shell-safe characters.
+fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c));
+    if safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jwt_check::SAMPLE_TOKEN;
+
+    #[test]
+    fn answers_become_flags() {
+        let answers = format!(
+            "{}\n\nsecret.txt\n\nhttps://issuer.example\n\n",
+            SAMPLE_TOKEN
+        );
+        let mut transcript = Vec::new();
+        let args = run(answers.as_bytes(), &mut transcript).unwrap();
+        assert_eq!(
+            args[3..],
+            [
+                "--verify",
+                "--secret-file",
+                "secret.txt",
+                "--validate",
+                "--iss",
+                "https://issuer.example"
+            ]
+        );
+        let transcript = String::from_utf8(transcript).unwrap();
+        assert!(transcript.contains("Choice [1]:"));
+        assert!(transcript.contains("--secret-file secret.txt --validate"));
+        // cut short before the token, the key choice and the key file
+        for cut in [0, SAMPLE_TOKEN.len() + 1, SAMPLE_TOKEN.len() + 2] {
+            assert!(matches!(
+                run(&answers.as_bytes()[..cut], Vec::new()),
+                Err(JWTError::InvalidArgumentError(e))
+                    if e == "wizard input ended before all questions were answered"
+            ));
+        }
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+}
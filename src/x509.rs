@@ -0,0 +1,915 @@
+//! X.509 certificates behind a verification key: `--key` given a
+//! certificate, or the token's own `x5c` chain checked against
+//! `--x5c-roots`.
+//!
+//! A signature that matches is only half the answer when the key comes from
+//! a certificate. The certificate must also be within its validity period
+//! and allowed to sign: a `keyUsage` that leaves out `digitalSignature`, or
+//! an `extendedKeyUsage` without the one `--cert-eku` asks for, fails the
+//! token as a certificate problem rather than a bad signature.
+
+use crate::crypto::{ecdsa, rsa, Hash};
+use crate::format_timestamp;
+use crate::keys::{
+    der_expect, der_integer, der_read, parse_spki, VerifyingKey, TAG_BIT_STRING, TAG_OCTET_STRING,
+    TAG_OID, TAG_SEQUENCE,
+};
+use crate::revocation::Revocation;
+use crate::JWTError;
+use serde_json::Value;
+
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+const OID_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+const OID_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+const OID_AUTHORITY_INFO_ACCESS: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x01];
+const OID_OCSP: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01];
+/// The arcs shared by the PKCS #1 signature OIDs, the ecdsa-with-SHA2 ones
+/// and the SHA-2 digests; the last arc picks the hash.
+const OID_PKCS1: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01];
+const OID_ECDSA_WITH_SHA2: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03];
+const OID_SHA2: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02];
+const OID_RSASSA_PSS: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0a];
+const OID_MGF1: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x08];
+
+/// `keyUsage` bits, as the first two bytes of the BIT STRING.
+pub const DIGITAL_SIGNATURE: u16 = 0x8000;
+pub const KEY_CERT_SIGN: u16 = 0x0400;
+
+/// Extended key usages by the names OpenSSL uses for them.
+pub const EXTENDED_KEY_USAGES: [(&str, &str); 7] = [
+    ("serverAuth", "1.3.6.1.5.5.7.3.1"),
+    ("clientAuth", "1.3.6.1.5.5.7.3.2"),
+    ("codeSigning", "1.3.6.1.5.5.7.3.3"),
+    ("emailProtection", "1.3.6.1.5.5.7.3.4"),
+    ("timeStamping", "1.3.6.1.5.5.7.3.8"),
+    ("OCSPSigning", "1.3.6.1.5.5.7.3.9"),
+    ("anyExtendedKeyUsage", "2.5.29.37.0"),
+];
+
+fn invalid(what: &str) -> JWTError {
+    JWTError::CertificateError(what.to_string().into())
+}
+
+/// What a signing certificate must satisfy besides its validity period.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Policy {
+    /// `--cert-eku`: an extended key usage OID the leaf must list.
+    pub extended_key_usage: Option<String>,
+    /// `--crl`, `--ocsp-response` and `--ocsp`, for `x5c` chains.
+    pub revocation: Revocation,
+}
+
+impl Policy {
+    /// Reads `--cert-eku`, given as a name from [`EXTENDED_KEY_USAGES`] or
+    /// a dotted OID.
+    pub fn with_extended_key_usage(name: &str) -> Result<Policy, JWTError> {
+        let oid = match EXTENDED_KEY_USAGES.iter().find(|(known, _)| *known == name) {
+            Some((_, oid)) => oid.to_string(),
+            None if name.split('.').all(|arc| arc.parse::<u64>().is_ok()) => name.to_string(),
+            None => {
+                return Err(JWTError::InvalidArgumentError(
+                    format!(
+                        "`{}` is not an extended key usage; expected a dotted OID or one of {}",
+                        name,
+                        EXTENDED_KEY_USAGES
+                            .iter()
+                            .map(|(known, _)| *known)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                    .into(),
+                ))
+            }
+        };
+        Ok(Policy {
+            extended_key_usage: Some(oid),
+            ..Policy::default()
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Certificate {
+    pub der: Vec<u8>,
+    tbs: Vec<u8>,
+    /// The contents of the signature AlgorithmIdentifier: OID and parameters.
+    signature_algorithm: Vec<u8>,
+    signature: Vec<u8>,
+    pub serial: Vec<u8>,
+    /// The DER issuer and subject names, compared byte for byte.
+    pub issuer: Vec<u8>,
+    pub subject: Vec<u8>,
+    pub not_before: i64,
+    pub not_after: i64,
+    spki: Vec<u8>,
+    /// `None` when the certificate has no `keyUsage` extension.
+    pub key_usage: Option<u16>,
+    /// Dotted OIDs, or `None` without an `extendedKeyUsage` extension.
+    pub extended_key_usage: Option<Vec<String>>,
+    pub ca: bool,
+    /// The OCSP responder from `authorityInfoAccess`.
+    pub ocsp_url: Option<String>,
+    /// The extensions not read above, as dotted OIDs and DER values.
+    pub other_extensions: Vec<(String, Vec<u8>)>,
+}
+
+/// The whole TLV at the start of `input`, and what follows it.
+pub fn tlv(input: &[u8]) -> Result<(&[u8], &[u8]), JWTError> {
+    let (_, _, rest) = der_read(input)?;
+    Ok((
+        input.get(..input.len() - rest.len()).unwrap_or_default(),
+        rest,
+    ))
+}
+
+/// A DER OID in dotted form.
+pub fn oid_to_string(oid: &[u8]) -> String {
+    let mut arcs: Vec<u64> = Vec::new();
+    let mut value: u64 = 0;
+    for byte in oid {
+        value = (value << 7) | u64::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (value / 40).min(2);
+                arcs.push(first);
+                arcs.push(value - first * 40);
+            } else {
+                arcs.push(value);
+            }
+            value = 0;
+        }
+    }
+    arcs.iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // days-from-civil, after Howard Hinnant's date algorithms
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// A UTCTime or GeneralizedTime, in seconds since the epoch.
+pub fn parse_time(input: &[u8]) -> Result<(i64, &[u8]), JWTError> {
+    let (tag, contents, rest) = der_read(input)?;
+    let text = std::str::from_utf8(contents)?;
+    let digits = text
+        .strip_suffix('Z')
+        .filter(|d| d.bytes().all(|b| b.is_ascii_digit()))
+        .ok_or_else(|| invalid("certificate time is not in UTC"))?;
+    let number = |range: std::ops::Range<usize>| {
+        digits
+            .get(range)
+            .and_then(|part| part.parse::<i64>().ok())
+            .ok_or_else(|| invalid("truncated certificate time"))
+    };
+    let (year, offset) = match (tag, digits.len()) {
+        // RFC 5280: two-digit years from 50 are 19xx
+        (TAG_UTC_TIME, 12) => {
+            let year = number(0..2)?;
+            (if year >= 50 { 1900 + year } else { 2000 + year }, 2)
+        }
+        (TAG_GENERALIZED_TIME, 14) => (number(0..4)?, 4),
+        _ => return Err(invalid("unsupported certificate time")),
+    };
+    let at = |index: usize| number(offset + index * 2..offset + index * 2 + 2);
+    let days = days_from_civil(year, at(0)?, at(1)?);
+    Ok((days * 86_400 + at(2)? * 3_600 + at(3)? * 60 + at(4)?, rest))
+}
+
+impl Certificate {
+    pub fn from_der(der: &[u8]) -> Result<Certificate, JWTError> {
+        let (certificate, _) = der_expect(der, TAG_SEQUENCE)?;
+        let (tbs, rest) = tlv(certificate)?;
+        let (signature_algorithm, rest) = der_expect(rest, TAG_SEQUENCE)?;
+        let (signature, _) = der_expect(rest, TAG_BIT_STRING)?;
+        let signature = match signature.split_first() {
+            Some((0, signature)) => signature.to_vec(),
+            _ => return Err(invalid("certificate signature has unused bits")),
+        };
+
+        let (mut fields, _) = der_expect(tbs, TAG_SEQUENCE)?;
+        // optional [0] EXPLICIT version
+        if fields.first() == Some(&0xa0) {
+            fields = der_read(fields)?.2;
+        }
+        let (serial, rest) = der_expect(fields, 0x02)?;
+        let (_, _, rest) = der_read(rest)?;
+        let (issuer, rest) = tlv(rest)?;
+        let (validity, rest) = der_expect(rest, TAG_SEQUENCE)?;
+        let (not_before, after) = parse_time(validity)?;
+        let (not_after, _) = parse_time(after)?;
+        let (subject, rest) = tlv(rest)?;
+        let (spki, mut rest) = tlv(rest)?;
+
+        let mut parsed = Certificate {
+            der: der.to_vec(),
+            tbs: tbs.to_vec(),
+            signature_algorithm: signature_algorithm.to_vec(),
+            signature,
+            serial: serial.to_vec(),
+            issuer: issuer.to_vec(),
+            subject: subject.to_vec(),
+            not_before,
+            not_after,
+            spki: spki.to_vec(),
+            key_usage: None,
+            extended_key_usage: None,
+            ca: false,
+            ocsp_url: None,
+            other_extensions: Vec::new(),
+        };
+        while let Some(&tag) = rest.first() {
+            let (_, contents, after) = der_read(rest)?;
+            rest = after;
+            // issuerUniqueID [1] and subjectUniqueID [2] are skipped
+            if tag != 0xa3 {
+                continue;
+            }
+            let (mut extensions, _) = der_expect(contents, TAG_SEQUENCE)?;
+            while !extensions.is_empty() {
+                let (extension, after) = der_expect(extensions, TAG_SEQUENCE)?;
+                extensions = after;
+                parsed.read_extension(extension)?;
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn read_extension(&mut self, extension: &[u8]) -> Result<(), JWTError> {
+        let (oid, mut rest) = der_expect(extension, TAG_OID)?;
+        if rest.first() == Some(&TAG_BOOLEAN) {
+            rest = der_read(rest)?.2;
+        }
+        let (value, _) = der_expect(rest, TAG_OCTET_STRING)?;
+        match oid {
+            OID_KEY_USAGE => {
+                let (bits, _) = der_expect(value, TAG_BIT_STRING)?;
+                let byte = |index: usize| u16::from(bits.get(index).copied().unwrap_or(0));
+                self.key_usage = Some(byte(1) << 8 | byte(2));
+            }
+            OID_EXTENDED_KEY_USAGE => {
+                let (mut oids, _) = der_expect(value, TAG_SEQUENCE)?;
+                let mut usages = Vec::new();
+                while !oids.is_empty() {
+                    let (oid, after) = der_expect(oids, TAG_OID)?;
+                    usages.push(oid_to_string(oid));
+                    oids = after;
+                }
+                self.extended_key_usage = Some(usages);
+            }
+            OID_BASIC_CONSTRAINTS => {
+                let (constraints, _) = der_expect(value, TAG_SEQUENCE)?;
+                if let Ok((flag, _)) = der_expect(constraints, TAG_BOOLEAN) {
+                    self.ca = flag.first().is_some_and(|b| *b != 0);
+                }
+            }
+            OID_AUTHORITY_INFO_ACCESS => {
+                let (mut descriptions, _) = der_expect(value, TAG_SEQUENCE)?;
+                while !descriptions.is_empty() {
+                    let (description, after) = der_expect(descriptions, TAG_SEQUENCE)?;
+                    descriptions = after;
+                    let (method, location) = der_expect(description, TAG_OID)?;
+                    // uniformResourceIdentifier [6]
+                    if let (OID_OCSP, Ok((0x86, url, _))) = (method, der_read(location)) {
+                        self.ocsp_url = Some(String::from_utf8_lossy(url).into_owned());
+                    }
+                }
+            }
+            other => self
+                .other_extensions
+                .push((oid_to_string(other), value.to_vec())),
+        }
+        Ok(())
+    }
+
+    /// Every `CERTIFICATE` block of a PEM bundle, in order.
+    pub fn from_pem_bundle(text: &str) -> Result<Vec<Certificate>, JWTError> {
+        const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+        const END: &str = "-----END CERTIFICATE-----";
+        let mut certificates = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find(BEGIN) {
+            let body = rest.get(start + BEGIN.len()..).unwrap_or_default();
+            let end = body
+                .find(END)
+                .ok_or_else(|| invalid("PEM certificate has no END line"))?;
+            let base64: String = body
+                .get(..end)
+                .unwrap_or_default()
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect();
+            certificates.push(Certificate::from_der(&base64::decode(&base64)?)?);
+            rest = body.get(end + END.len()..).unwrap_or_default();
+        }
+        if certificates.is_empty() {
+            return Err(invalid("no PEM certificate found"));
+        }
+        Ok(certificates)
+    }
+
+    pub fn public_key(&self) -> Result<VerifyingKey, JWTError> {
+        parse_spki(&self.spki)
+    }
+
+    /// The subjectPublicKey bits, without the unused-bits byte, as OCSP
+    /// hashes them to identify an issuer.
+    pub fn public_key_bits(&self) -> Result<Vec<u8>, JWTError> {
+        let (fields, _) = der_expect(&self.spki, TAG_SEQUENCE)?;
+        let (_, _, rest) = der_read(fields)?;
+        let (bits, _) = der_expect(rest, TAG_BIT_STRING)?;
+        Ok(bits.get(1..).unwrap_or_default().to_vec())
+    }
+
+    /// The subject's common name, else the whole subject in hex.
+    pub fn name(&self) -> String {
+        common_name(&self.subject)
+            .unwrap_or_else(|| self.subject.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// The issuer's common name, else the whole issuer in hex.
+    pub fn issuer_name(&self) -> String {
+        common_name(&self.issuer)
+            .unwrap_or_else(|| self.issuer.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// Whether `issuer` issued this certificate: its subject is this one's
+    /// issuer, and its key verifies this one's signature.
+    pub fn issued_by(&self, issuer: &Certificate) -> Result<(), JWTError> {
+        if self.issuer != issuer.subject {
+            return Err(JWTError::CertificateError(
+                format!("`{}` was not issued by `{}`", self.name(), issuer.name()).into(),
+            ));
+        }
+        let valid = verify_signature(
+            &issuer.public_key()?,
+            &self.signature_algorithm,
+            &self.tbs,
+            &self.signature,
+        )?;
+        if valid {
+            Ok(())
+        } else {
+            Err(JWTError::CertificateError(
+                format!(
+                    "the signature on `{}` does not verify with the key of `{}`",
+                    self.name(),
+                    issuer.name()
+                )
+                .into(),
+            ))
+        }
+    }
+}
+
+enum Scheme {
+    Pkcs1(Hash),
+    Pss(Hash),
+    Ecdsa(Hash),
+}
+
+/// sha256/384/512, as the OID of a digest AlgorithmIdentifier.
+fn sha2(oid: &[u8]) -> Option<Hash> {
+    match oid.strip_prefix(OID_SHA2)? {
+        [0x01] => Some(Hash::Sha256),
+        [0x02] => Some(Hash::Sha384),
+        [0x03] => Some(Hash::Sha512),
+        _ => None,
+    }
+}
+
+/// The OID and parameters of the DER AlgorithmIdentifier `identifier`.
+fn algorithm_identifier(identifier: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (algorithm, _) = der_expect(identifier, TAG_SEQUENCE).ok()?;
+    der_expect(algorithm, TAG_OID).ok()
+}
+
+/// The hash of RSASSA-PSS-params (RFC 4055 section 3.1) that `verify_pss`
+/// checks: SHA-2, MGF1 with the same hash, a salt as long as the hash and
+/// the usual trailer. The SHA-1 defaults are not accepted.
+fn pss_hash(parameters: &[u8]) -> Option<Hash> {
+    let (mut fields, _) = der_expect(parameters, TAG_SEQUENCE).ok()?;
+    let (mut hash, mut mgf_hash, mut salt_len) = (None, None, 20);
+    while !fields.is_empty() {
+        let (tag, field, rest) = der_read(fields).ok()?;
+        fields = rest;
+        match tag {
+            0xa0 => hash = Some(sha2(algorithm_identifier(field)?.0)?),
+            0xa1 => match algorithm_identifier(field)? {
+                (OID_MGF1, mgf) => mgf_hash = Some(sha2(algorithm_identifier(mgf)?.0)?),
+                _ => return None,
+            },
+            0xa2 | 0xa3 => {
+                let (number, _) = der_expect(field, 0x02).ok()?;
+                let number = match number {
+                    [byte] => usize::from(*byte),
+                    _ => return None,
+                };
+                if tag == 0xa2 {
+                    salt_len = number;
+                } else if number != 1 {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+    let hash = hash?;
+    (mgf_hash == Some(hash) && salt_len == hash.output_len()).then_some(hash)
+}
+
+/// The scheme named by the contents of a signature AlgorithmIdentifier,
+/// matched on its whole OID.
+fn signature_scheme(algorithm: &[u8]) -> Result<Scheme, JWTError> {
+    let (oid, parameters) = der_expect(algorithm, TAG_OID)?;
+    let scheme = if let Some(suffix) = oid.strip_prefix(OID_PKCS1) {
+        match suffix {
+            [0x0b] => Some(Scheme::Pkcs1(Hash::Sha256)),
+            [0x0c] => Some(Scheme::Pkcs1(Hash::Sha384)),
+            [0x0d] => Some(Scheme::Pkcs1(Hash::Sha512)),
+            [0x0a] => pss_hash(parameters).map(Scheme::Pss),
+            _ => None,
+        }
+    } else {
+        match oid.strip_prefix(OID_ECDSA_WITH_SHA2) {
+            Some([0x02]) => Some(Scheme::Ecdsa(Hash::Sha256)),
+            Some([0x03]) => Some(Scheme::Ecdsa(Hash::Sha384)),
+            Some([0x04]) => Some(Scheme::Ecdsa(Hash::Sha512)),
+            _ => None,
+        }
+    };
+    scheme.ok_or_else(|| {
+        let what = if oid == OID_RSASSA_PSS {
+            "RSASSA-PSS parameters".to_string()
+        } else {
+            oid_to_string(oid)
+        };
+        invalid(&format!(
+            "unsupported certificate signature algorithm {}",
+            what
+        ))
+    })
+}
+
+/// Checks an X.509 `signature` over `message`: sha256/384/512WithRSAEncryption,
+/// RSASSA-PSS with SHA-2 or ecdsa-with-SHA256/384/512, as named by
+/// `algorithm`, the contents of the DER AlgorithmIdentifier. Any other
+/// algorithm, md5WithRSAEncryption and sha1WithRSAEncryption among them, is
+/// an error.
+pub fn verify_signature(
+    key: &VerifyingKey,
+    algorithm: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<bool, JWTError> {
+    match (key, signature_scheme(algorithm)?) {
+        (VerifyingKey::Rsa(key), Scheme::Pkcs1(hash)) => {
+            Ok(rsa::verify_pkcs1v15(key, hash, message, signature))
+        }
+        (VerifyingKey::Rsa(key), Scheme::Pss(hash)) => {
+            Ok(rsa::verify_pss(key, hash, message, signature))
+        }
+        (VerifyingKey::Ec(key), Scheme::Ecdsa(hash)) => {
+            let raw = ecdsa_signature_from_der(signature, key.curve.byte_len())?;
+            Ok(ecdsa::verify(key, hash, message, &raw))
+        }
+        _ => Err(invalid(
+            "the certificate signature algorithm does not match the issuer's key",
+        )),
+    }
+}
+
+fn common_name(name: &[u8]) -> Option<String> {
+    let (mut sets, _) = der_expect(name, TAG_SEQUENCE).ok()?;
+    while !sets.is_empty() {
+        let (set, after) = der_expect(sets, 0x31).ok()?;
+        sets = after;
+        let (attribute, _) = der_expect(set, TAG_SEQUENCE).ok()?;
+        let (oid, value) = der_expect(attribute, TAG_OID).ok()?;
+        if oid == OID_COMMON_NAME {
+            let (_, text, _) = der_read(value).ok()?;
+            return Some(String::from_utf8_lossy(text).into_owned());
+        }
+    }
+    None
+}
+
+/// A DER ECDSA signature as JOSE's raw `r || s`, each half `len` bytes.
+pub fn ecdsa_signature_from_der(der: &[u8], len: usize) -> Result<Vec<u8>, JWTError> {
+    let (sequence, _) = der_expect(der, TAG_SEQUENCE)?;
+    let (r, rest) = der_integer(sequence)?;
+    let (s, _) = der_integer(rest)?;
+    let mut raw = r.to_be_bytes(len);
+    raw.extend(s.to_be_bytes(len));
+    Ok(raw)
+}
+
+/// The token's `x5c` chain, leaf first, once each certificate is issued by
+/// the next and the last by one of `roots` (or is one of them). The root is
+/// returned as the chain's last certificate either way.
+pub fn x5c_chain(header: &Value, roots: &[Certificate]) -> Result<Vec<Certificate>, JWTError> {
+    let entries = header
+        .get("x5c")
+        .and_then(Value::as_array)
+        .filter(|entries| !entries.is_empty())
+        .ok_or_else(|| invalid("the token has no x5c header"))?;
+    let mut chain = entries
+        .iter()
+        .map(|entry| {
+            let text = entry
+                .as_str()
+                .ok_or_else(|| invalid("x5c entries must be base64 strings"))?;
+            // standard base64 with padding (RFC 7515 section 4.1.6)
+            Certificate::from_der(&base64::decode(text)?)
+        })
+        .collect::<Result<Vec<_>, JWTError>>()?;
+    for pair in chain.windows(2) {
+        if let [certificate, issuer] = pair {
+            certificate.issued_by(issuer)?;
+        }
+    }
+    let last = chain.last().ok_or_else(|| invalid("empty x5c"))?;
+    if roots.iter().any(|root| root.der == last.der) {
+        return Ok(chain);
+    }
+    if let Some(root) = roots.iter().find(|root| last.issued_by(root).is_ok()) {
+        chain.push(root.clone());
+        return Ok(chain);
+    }
+    Err(JWTError::CertificateError(
+        format!(
+            "x5c chain ends at `{}`, which was not issued by any of the {} trusted root(s)",
+            last.name(),
+            roots.len()
+        )
+        .into(),
+    ))
+}
+
+/// What is wrong with `chain` (leaf first) for signing at `now`: validity
+/// periods throughout, issuers that aren't CAs, and the leaf's key usages.
+/// `iat`, when known, tells a certificate that expired after the token was
+/// signed apart from one that had already expired.
+pub fn problems(chain: &[Certificate], now: i64, iat: Option<i64>, policy: &Policy) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (index, certificate) in chain.iter().enumerate() {
+        let name = certificate.name();
+        if now < certificate.not_before {
+            problems.push(format!(
+                "`{}` is not valid until {}",
+                name,
+                format_timestamp(certificate.not_before)
+            ));
+        }
+        if now > certificate.not_after {
+            let when = match iat {
+                Some(iat) if iat <= certificate.not_after => ", after the token was issued",
+                Some(_) => ", before the token was issued",
+                None => "",
+            };
+            problems.push(format!(
+                "`{}` expired at {}{}",
+                name,
+                format_timestamp(certificate.not_after),
+                when
+            ));
+        }
+        if index > 0 && !certificate.ca {
+            problems.push(format!("`{}` issues a certificate but is not a CA", name));
+        }
+    }
+    if let Some(leaf) = chain.first() {
+        let name = leaf.name();
+        if leaf
+            .key_usage
+            .is_some_and(|usage| usage & DIGITAL_SIGNATURE == 0)
+        {
+            problems.push(format!(
+                "`{}` has a keyUsage without digitalSignature, so it may not sign tokens",
+                name
+            ));
+        }
+        if let Some(required) = &policy.extended_key_usage {
+            let listed = leaf.extended_key_usage.as_deref().unwrap_or_default();
+            let any = EXTENDED_KEY_USAGES
+                .iter()
+                .find(|(known, _)| *known == "anyExtendedKeyUsage")
+                .map(|(_, oid)| *oid);
+            if !listed
+                .iter()
+                .any(|oid| oid == required || Some(oid.as_str()) == any)
+            {
+                let known = EXTENDED_KEY_USAGES
+                    .iter()
+                    .find(|(_, oid)| oid == required)
+                    .map_or(required.as_str(), |(known, _)| *known);
+                problems.push(format!(
+                    "`{}` does not list the extended key usage {}",
+                    name, known
+                ));
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: &str = "-----BEGIN CERTIFICATE-----
+MIIC+jCCAeKgAwIBAgIUN6EfvkzkElVNh9WbvwQdpT1yFI8wDQYJKoZIhvcNAQEL
+BQAwFDESMBAGA1UEAwwJVGVzdCBSb290MCAXDTI2MTAxNDEyNTAyNloYDzIxMjYw
+OTIwMTI1MDI2WjAUMRIwEAYDVQQDDAlUZXN0IFJvb3QwggEiMA0GCSqGSIb3DQEB
+AQUAA4IBDwAwggEKAoIBAQDf8LEauEbLnkbAP9I8JV2c4PoH2IjkL6nAG8PBbEFw
+tVgMYCZt8BiM9rjWtZ9m/VzHDSaQ8yw4wwg6Ig9GBAM+x4Sk7lukX0uTGNU2zx2r
+i+U0trYtxnkX0UKGQcOXpHrhy+t+t+KLYBiUqCCps0rzhWMuhRFakZcqpbgJE7b3
+do4l2bcTAxfnm+09SE+FREXUBdSqHrdyEWb1jxwSoLQYtRo9+OzBEL90sqKvXLAw
+MxGmQoIkYRrDZK9wiL4iIFsigfhVefDwSubiuqRg/xm9H7YILwN9RXKyetXA0cM6
+x+Bf/5ipbWCoYoE3pWFItWKRMCktg70G7tptMyE2D4SRAgMBAAGjQjBAMA8GA1Ud
+EwEB/wQFMAMBAf8wDgYDVR0PAQH/BAQDAgEGMB0GA1UdDgQWBBSw1cO6fRMsNuFg
+N4ilqIBuTLP9ijANBgkqhkiG9w0BAQsFAAOCAQEArnZRYJuwrhD4nwYng6Wa1G97
+R3fMyuBoN0TYY9mFe9O2TkAEejIgfY2rNtaAnTFDGK6UX/0Uzo8fdfHytnpIucOu
+ESmbSeff2WnjBMMuS7YXDtwq5Kp+tXvYnDpO+8RVbjNVqRKJydAR+7phM+0vjtSB
+fjh9xFT+PLA2s80BuDPTFkpsh++PVbq5Nn8mW98PZiobPXVWqofSYa/m0CLeCUNg
+qKhAiy7mURRwK+Xisz3hcQI8g0HW7C1ZCbwpeM0IhNYtYHSKLqaY1nN72rdPfLrW
+7TBkYxRm69AlAVUDUt7l+VRvVTjSNbRhnnLX7dnl1hiWo5Zmr0kTAOXZzSVdrA==
+-----END CERTIFICATE-----";
+    /// Issued by ROOT: keyUsage digitalSignature, extendedKeyUsage clientAuth.
+    const LEAF: &str = "MIICYTCCAUmgAwIBAgICEjQwDQYJKoZIhvcNAQELBQAwFDESMBAGA1UEAwwJVGVz\
+dCBSb290MCAXDTI2MTAxNDEyNTAyNloYDzIxMjYwOTIwMTI1MDI2WjAoMQ0wCwYD\
+VQQKDARBY21lMRcwFQYDVQQDDA5zaWduZXIuZXhhbXBsZTBZMBMGByqGSM49AgEG\
+CCqGSM49AwEHA0IABKqKbR810To749ofZY78rNhKOff11W5Ehe6i3mr+xDpeJe2C\
+vOZaq2dYGhZg4Mks1ImwTkPiFabNe5NpnjDeUNujcjBwMAkGA1UdEwQCMAAwDgYD\
+VR0PAQH/BAQDAgeAMBMGA1UdJQQMMAoGCCsGAQUFBwMCMB0GA1UdDgQWBBRgdz1y\
+GQFDFrWVuwPK1mJ40HJ1TzAfBgNVHSMEGDAWgBSw1cO6fRMsNuFgN4ilqIBuTLP9\
+ijANBgkqhkiG9w0BAQsFAAOCAQEAV6C7F4rVi24g5fVvFsIZVkdzDpFJmm5OUM9+\
+E5jSNOxVbyZjWyhO2BbMwZ3JWfpLwLrc/5f0qOlDKrMFi0vElsgy2X4DjRYSBTSU\
+ML6gYD4hHuu3dGItbBH1d0F99YVBsb4EvRtejJz44MdK9tPRUkFnE7FIv/aqDyvI\
+vJTAVPP8r1YNlQ4cIol10X3Xg0y0aUJ5OdytRkFv4rJ7BBjbDPX6MfSVMOSHBRLA\
+D60uho3KKaF5H03Rfoa8cQMMUxouunHFQSIcvaF74sLFWjOkmM4UgiJkgM4MA4eY\
+AD5FGjT+Y1tNeeukpWIx413P3ff2tqmCpyjWfxImNJVCHb3LTw==";
+    /// Self-signed with ECDSA, the same key as LEAF: keyUsage keyEncipherment.
+    const ENCRYPTION_ONLY: &str =
+        "MIIBaTCCAQ+gAwIBAgIUeYoSpDa2ENzPCw4eifET0ODQ4IEwCgYIKoZIzj0EAwIw\
+GjEYMBYGA1UEAwwPZW5jcnlwdGlvbiBvbmx5MCAXDTI2MTAxNDEyNTAyNloYDzIx\
+MjYwOTIwMTI1MDI2WjAaMRgwFgYDVQQDDA9lbmNyeXB0aW9uIG9ubHkwWTATBgcq\
+hkjOPQIBBggqhkjOPQMBBwNCAASqim0fNdE6O+PaH2WO/KzYSjn39dVuRIXuot5q\
+/sQ6XiXtgrzmWqtnWBoWYODJLNSJsE5D4hWmzXuTaZ4w3lDbozEwLzAOBgNVHQ8B\
+Af8EBAMCBSAwHQYDVR0OBBYEFGB3PXIZAUMWtZW7A8rWYnjQcnVPMAoGCCqGSM49\
+BAMCA0gAMEUCIQDRPEfSL/VmzEQJCno+HEsRRuMnmO3oGQdXG+dtcEHFfQIgO9Jl\
+vLqOArJT4hmw/i2BF0qrDHA/PVnNwK8CdNz2V/Y=";
+
+    /// Self-signed with md5WithRSAEncryption.
+    const MD5_SIGNED: &str = "MIIDDTCCAfWgAwIBAgIUatdSBAFk/uyPp/WYD+aAOBPkEFIwDQYJKoZIhvcNAQEE\
+BQAwFTETMBEGA1UEAwwKbWQ1IHNpZ25lZDAgFw0yNjEwMTQxNDI4NDdaGA8yMTI2\
+MDkyMDE0Mjg0N1owFTETMBEGA1UEAwwKbWQ1IHNpZ25lZDCCASIwDQYJKoZIhvcN\
+AQEBBQADggEPADCCAQoCggEBAJv+P2rRNDj1Y6QniS8oK1D+nGplVyH+4qYjB26x\
+MUjO+Zm+sePO9tJn1QOomLWIuOvFStp18wmvtzE++3uMbSmust/xEC0w9xT94juQ\
+fm0guYrJVhnfULZDgvhqvbO8csOL509IOvNlo94ZNyoFmQZRUcZl6tXbGGJrOKZI\
+601vPv/ZoG0YkrCJQ3rRf2d18BuEHpN3XFr7QzS8FDHnjH9e7R9ttvVh8+epzery\
+oqS5bFjRMR7CUu6IGnbZfg8dXwvmfbnumestbUJX4mIGGMwY9+tshuW5PZmQPdbY\
+C5gHjIVt1Qd0q09AyJuuBqQyRYVLmpsXE7q7W7hjBEklUXECAwEAAaNTMFEwHQYD\
+VR0OBBYEFD8E1RGcwtlcNkYo36N/cecxrYeWMB8GA1UdIwQYMBaAFD8E1RGcwtlc\
+NkYo36N/cecxrYeWMA8GA1UdEwEB/wQFMAMBAf8wDQYJKoZIhvcNAQEEBQADggEB\
+AIsXv9aJmkBeZs93jDbtPbYTopSo8r4A0eKFtMJGKfrULuiVgQwEvm5FqA8EehCF\
+eWGbeRSOfTEbMcIOQNpCVIN6O0W/trTkkGNcrec9LtoWIibodOwwBDm1Hr8Y5k5Q\
+0HzMcdfN1ISKuYoph86gDPeM6MNogiMl5ByhgkWMHhLeWhy4dD8pHId0DtFXXGWa\
+EJYFcL8e/Hk6d/6Ust94Aak4qHErGNDRIyE9/rnvRILaL6CulOxC/jwbgERMcxss\
+d7wf37+SVLGgRhTpkVJk4dqewSHA2Ic8FwURwLoMZE8kZHLtjjNKNNUutZIl9bPo\
+BfehX7ZG0H69oH4hgabg7zg=";
+    /// Self-signed with RSASSA-PSS: SHA-256, MGF1 with SHA-256, 32-byte salt.
+    const PSS_SIGNED: &str = "MIIDdTCCAimgAwIBAgIUa3CfCFdw49xONdLsak5ulfXoJw8wQQYJKoZIhvcNAQEK\
+MDSgDzANBglghkgBZQMEAgEFAKEcMBoGCSqGSIb3DQEBCDANBglghkgBZQMEAgEF\
+AKIDAgEgMBUxEzARBgNVBAMMCnBzcyBzaWduZWQwIBcNMjYxMDE0MTQyODQ3WhgP\
+MjEyNjA5MjAxNDI4NDdaMBUxEzARBgNVBAMMCnBzcyBzaWduZWQwggEiMA0GCSqG\
+SIb3DQEBAQUAA4IBDwAwggEKAoIBAQDbIz1JtluPlnJvZLxdohB/Oteiz1PzoA8B\
+4/T6IxU7rM6XUmmjU/lYbCN+jlNZt+iDQBEGZduAyhmSwRxm3b3TKlAhNXUaVKJc\
+J5Fx1028UNznoPY9GO9Ic+HKTJ37wb5/PfN5XNhnDoT4/IvnU0rglBlKiZ8IS6md\
+ofX5wQHlawbMgEFAD3E5PLgT0iJiJhFfkwf8JAapPVuK+tyoZdbHJmoTf7xF9d26\
+Se0qPrEnUlGSRg6O7VEj1ilnOXo4lZih9Em2CBQvG36j1tE5Uy3l3V3yGhBQmi4D\
+jS0jBCKMlZIZ3jqp+eUINworHm0SVDl+emz6J1JVHfZ7NGzogYbhAgMBAAGjUzBR\
+MB0GA1UdDgQWBBRaeUkgbQqE5nf2eYE0AOK/tMi30TAfBgNVHSMEGDAWgBRaeUkg\
+bQqE5nf2eYE0AOK/tMi30TAPBgNVHRMBAf8EBTADAQH/MEEGCSqGSIb3DQEBCjA0\
+oA8wDQYJYIZIAWUDBAIBBQChHDAaBgkqhkiG9w0BAQgwDQYJYIZIAWUDBAIBBQCi\
+AwIBIAOCAQEAU29Kht2jatcpTHAhOHOzSBOJh7sMRd+RadfSy8730b1114aAqCI9\
+evXy4LE0qmOcp6D97hcidW6HxmgADLBd2MLHWNZ8Rng0mzIUue8Ebf2qHVSCnNVg\
+yv21QsNo27eayuwAIxcczZNhOTz+rNoNLzMjStagSJbTSf9CMGSFnRNmSXgKoEeI\
+BjyuTRi9+bg0b+FPBx2719FU4TGer0hBuzdZ4rgfluo7IFOVmj+Q7EnC+8C+EV6n\
+u0VREIhMdcwQIpTv+XvxHWYUS6lRv7s88rJrNRf4aE2Eu9KrQzS57j7wOPXS0iJO\
+mCs8Xh7RUBknKThHeWVhEmrxgd4DUdGeXg==";
+
+    #[test]
+    fn certificate_checks_test() {
+        let roots = Certificate::from_pem_bundle(ROOT).unwrap();
+        let root = &roots[0];
+        assert!(root.ca);
+        assert_eq!(root.key_usage, Some(0x0600));
+        let header = serde_json::json!({"alg": "ES256", "x5c": [LEAF]});
+        let chain = x5c_chain(&header, &roots).unwrap();
+        // the trusted root that issued the last x5c certificate ends the chain
+        assert_eq!(chain.len(), 2);
+        assert_eq!(&chain[1], root);
+        let leaf = &chain[0];
+        assert_eq!(leaf.name(), "signer.example");
+        assert_eq!(leaf.serial, vec![0x12, 0x34]);
+        // notBefore is a UTCTime, notAfter (2126) a GeneralizedTime
+        assert_eq!(format_timestamp(leaf.not_before), "2026-10-14T12:50:26Z");
+        assert_eq!(format_timestamp(leaf.not_after), "2126-09-20T12:50:26Z");
+        assert_eq!(
+            leaf.extended_key_usage,
+            Some(vec!["1.3.6.1.5.5.7.3.2".to_string()])
+        );
+        assert!(matches!(leaf.public_key(), Ok(VerifyingKey::Ec(_))));
+
+        let now = leaf.not_before + 60;
+        let client = Policy::with_extended_key_usage("clientAuth").unwrap();
+        assert!(problems(&chain, now, None, &client).is_empty());
+        let server = Policy::with_extended_key_usage("serverAuth").unwrap();
+        assert_eq!(
+            problems(&chain, now, None, &server),
+            vec!["`signer.example` does not list the extended key usage serverAuth"]
+        );
+        let late = leaf.not_after + 1;
+        assert_eq!(
+            problems(&chain, late, Some(now), &Policy::default()),
+            vec![
+                "`signer.example` expired at 2126-09-20T12:50:26Z, after the token was issued",
+                "`Test Root` expired at 2126-09-20T12:50:26Z, after the token was issued"
+            ]
+        );
+        assert!(problems(&chain, 0, None, &Policy::default())[0].contains("not valid until"));
+
+        let header = serde_json::json!({"x5c": [ENCRYPTION_ONLY]});
+        assert!(matches!(
+            x5c_chain(&header, &roots),
+            Err(JWTError::CertificateError(e)) if e.contains("not issued by any")
+        ));
+        // self-signed, so it is its own trusted root
+        let own = vec![Certificate::from_der(&base64::decode(ENCRYPTION_ONLY).unwrap()).unwrap()];
+        let chain = x5c_chain(&header, &own).unwrap();
+        assert!(chain[0].issued_by(&own[0]).is_ok());
+        assert_eq!(
+            problems(&chain, now, None, &Policy::default()),
+            vec!["`encryption only` has a keyUsage without digitalSignature, so it may not sign tokens"]
+        );
+        assert!(Policy::with_extended_key_usage("nonsense").is_err());
+        assert_eq!(
+            oid_to_string(&[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01]),
+            "1.2.840.10045.2.1"
+        );
+    }
+
+    #[test]
+    fn signature_algorithms_test() {
+        let certificate =
+            |text: &str| Certificate::from_der(&base64::decode(text).unwrap()).unwrap();
+        let pss = certificate(PSS_SIGNED);
+        assert!(pss.issued_by(&pss).is_ok());
+        let md5 = certificate(MD5_SIGNED);
+        assert!(matches!(
+            md5.issued_by(&md5),
+            Err(JWTError::CertificateError(e))
+                if e == "unsupported certificate signature algorithm 1.2.840.113549.1.1.4"
+        ));
+        let key = pss.public_key().unwrap();
+        let sha256_with_rsa = [
+            0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b,
+        ];
+        // a PSS signature does not verify as PKCS #1 v1.5
+        assert!(!verify_signature(&key, &sha256_with_rsa, &pss.tbs, &pss.signature).unwrap());
+        // a longer OID that merely ends in the sha256WithRSAEncryption arc
+        let mut longer = sha256_with_rsa.to_vec();
+        longer.splice(1..2, [0x0a]);
+        longer.insert(10, 0x01);
+        assert!(verify_signature(&key, &longer, &pss.tbs, &pss.signature).is_err());
+        // RSASSA-PSS with the SHA-1 defaults (empty parameters)
+        let pss_defaults = [
+            0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0a, 0x30, 0x00,
+        ];
+        assert!(matches!(
+            verify_signature(&key, &pss_defaults, &pss.tbs, &pss.signature),
+            Err(JWTError::CertificateError(e)) if e.ends_with("RSASSA-PSS parameters")
+        ));
+        // the PSS certificate's own parameters, but a 20-byte salt
+        let mut short_salt = pss.signature_algorithm.clone();
+        let salt = short_salt
+            .windows(3)
+            .position(|w| w == [0x02, 0x01, 0x20])
+            .unwrap();
+        short_salt[salt + 2] = 20;
+        assert!(verify_signature(&key, &short_salt, &pss.tbs, &pss.signature).is_err());
+        let ecdsa_with_sha256 = [0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+        assert!(matches!(
+            verify_signature(&key, &ecdsa_with_sha256, &pss.tbs, &pss.signature),
+            Err(JWTError::CertificateError(e))
+                if e == "the certificate signature algorithm does not match the issuer's key"
+        ));
+    }
+
+    #[test]
+    fn malformed_certificates_test() {
+        let certificate_error_of =
+            |result: Result<Vec<Certificate>, JWTError>| error_message!(CertificateError, result);
+        let time = |tag: u8, text: &str| {
+            let mut der = vec![tag, text.len() as u8];
+            der.extend(text.as_bytes());
+            certificate_error_of(parse_time(&der).map(|_| Vec::new()))
+        };
+        assert_eq!(
+            time(TAG_UTC_TIME, "261014125026+0100"),
+            "certificate time is not in UTC"
+        );
+        assert_eq!(
+            time(TAG_UTC_TIME, "2610141250Z"),
+            "unsupported certificate time"
+        );
+        assert_eq!(
+            time(TAG_GENERALIZED_TIME, "261014125026Z"),
+            "unsupported certificate time"
+        );
+
+        let leaf_der = base64::decode(LEAF).unwrap();
+        let mut unused_bits = leaf_der.clone();
+        let at = unused_bits.len() - 257;
+        assert_eq!(unused_bits[at], 0);
+        unused_bits[at] = 1;
+        assert_eq!(
+            certificate_error_of(Certificate::from_der(&unused_bits).map(|c| vec![c])),
+            "certificate signature has unused bits"
+        );
+        assert_eq!(
+            certificate_error_of(Certificate::from_pem_bundle(
+                "-----BEGIN CERTIFICATE-----\nMIIC"
+            )),
+            "PEM certificate has no END line"
+        );
+        assert_eq!(
+            certificate_error_of(Certificate::from_pem_bundle("MIIC+jCCAeKgAwIBAgIU")),
+            "no PEM certificate found"
+        );
+
+        let roots = Certificate::from_pem_bundle(ROOT).unwrap();
+        let leaf = Certificate::from_der(&leaf_der).unwrap();
+        let encryption_only =
+            Certificate::from_der(&base64::decode(ENCRYPTION_ONLY).unwrap()).unwrap();
+        assert_eq!(
+            certificate_error_of(leaf.issued_by(&encryption_only).map(|_| Vec::new())),
+            "`signer.example` was not issued by `encryption only`"
+        );
+        let mut forged = leaf_der;
+        *forged.last_mut().unwrap() ^= 1;
+        let forged = Certificate::from_der(&forged).unwrap();
+        assert_eq!(
+            certificate_error_of(forged.issued_by(&roots[0]).map(|_| Vec::new())),
+            "the signature on `signer.example` does not verify with the key of `Test Root`"
+        );
+
+        let chain = |header: Value| certificate_error_of(x5c_chain(&header, &roots));
+        assert_eq!(
+            chain(serde_json::json!({"alg": "ES256"})),
+            "the token has no x5c header"
+        );
+        assert_eq!(
+            chain(serde_json::json!({"x5c": []})),
+            "the token has no x5c header"
+        );
+        assert_eq!(
+            chain(serde_json::json!({"x5c": [1]})),
+            "x5c entries must be base64 strings"
+        );
+        assert_eq!(
+            chain(serde_json::json!({"x5c": [ENCRYPTION_ONLY]})),
+            "x5c chain ends at `encryption only`, which was not issued by any of the 1 trusted root(s)"
+        );
+
+        let names: Vec<&str> = EXTENDED_KEY_USAGES.iter().map(|(name, _)| *name).collect();
+        assert!(matches!(
+            Policy::with_extended_key_usage("nonsense"),
+            Err(JWTError::InvalidArgumentError(e)) if e == format!(
+                "`nonsense` is not an extended key usage; expected a dotted OID or one of {}",
+                names.join(", ")
+            )
+        ));
+        assert_eq!(
+            Policy::with_extended_key_usage("1.3.6.1.5.5.7.3.4")
+                .unwrap()
+                .extended_key_usage
+                .as_deref(),
+            Some("1.3.6.1.5.5.7.3.4")
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Minimal YAML reader for the tool's configuration files.
+//!
+//! Supports block mappings and sequences, plain and quoted scalars, comments
+//! and single-line flow collections without nesting. Anchors, aliases and
+//! tags are rejected; multi-line scalars are not supported.
+
+use serde_json::{Map, Value};
+
+struct Line {
+    number: usize,
+    indent: usize,
+    text: String,
+}
+
+/// Parses a YAML document into a JSON value.
+pub fn parse(input: &str) -> Result<Value, String> {
+    let mut lines = Vec::new();
+    for (i, raw) in input.lines().enumerate() {
+        let text = strip_comment(raw).trim_end();
+        if text.trim().is_empty() || text == "---" {
+            continue;
+        }
+        let indent = text.len() - text.trim_start_matches(' ').len();
+        if text[indent..].starts_with('\t') {
+            return Err(format!(
+                "line {}: tabs are not allowed for indentation",
+                i + 1
+            ));
+        }
+        lines.push(Line {
+            number: i + 1,
+            indent,
+            text: text[indent..].to_string(),
+        });
+    }
+    let indent = match lines.first() {
+        Some(line) => line.indent,
+        None => return Ok(Value::Null),
+    };
+    let mut pos = 0;
+    let value = parse_block(&mut lines, &mut pos, indent)?;
+    match lines.get(pos) {
+        Some(line) => Err(format!("line {}: unexpected indentation", line.number)),
+        None => Ok(value),
+    }
+}
+
+fn is_sequence_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+fn parse_block(lines: &mut Vec<Line>, pos: &mut usize, indent: usize) -> Result<Value, String> {
+    match lines.get(*pos) {
+        Some(line) if is_sequence_item(&line.text) => parse_sequence(lines, pos, indent),
+        Some(_) => parse_mapping(lines, pos, indent),
+        None => Ok(Value::Null),
+    }
+}
+
+fn parse_sequence(lines: &mut Vec<Line>, pos: &mut usize, indent: usize) -> Result<Value, String> {
+    let mut items = Vec::new();
+    while let Some(line) = lines.get(*pos) {
+        if line.indent != indent || !is_sequence_item(&line.text) {
+            break;
+        }
+        let rest = line.text[1..].trim_start();
+        if rest.is_empty() {
+            *pos += 1;
+            items.push(parse_nested(lines, pos, indent)?);
+        } else if split_key(rest).is_some() {
+            // "- key: value" opens a mapping whose keys align with `key`
+            let offset = line.text.len() - rest.len();
+            let number = line.number;
+            let rest = rest.to_string();
+            if let Some(line) = lines.get_mut(*pos) {
+                *line = Line {
+                    number,
+                    indent: indent + offset,
+                    text: rest,
+                };
+            }
+            items.push(parse_mapping(lines, pos, indent + offset)?);
+        } else {
+            let value = scalar(rest).map_err(|e| format!("line {}: {}", line.number, e))?;
+            items.push(value);
+            *pos += 1;
+        }
+    }
+    Ok(Value::Array(items))
+}
+
+fn parse_mapping(lines: &mut Vec<Line>, pos: &mut usize, indent: usize) -> Result<Value, String> {
+    let mut map = Map::new();
+    while let Some(line) = lines.get(*pos) {
+        if line.indent < indent || is_sequence_item(&line.text) {
+            break;
+        }
+        if line.indent > indent {
+            return Err(format!("line {}: unexpected indentation", line.number));
+        }
+        let number = line.number;
+        let (key, rest) = split_key(&line.text)
+            .ok_or_else(|| format!("line {}: expected `key: value`", number))?;
+        let key = match scalar(key).map_err(|e| format!("line {}: {}", number, e))? {
+            Value::String(key) => key,
+            other => other.to_string(),
+        };
+        let value = if rest.is_empty() {
+            *pos += 1;
+            match lines.get(*pos) {
+                // block sequences may sit at the same indentation as their key
+                Some(next) if next.indent == indent && is_sequence_item(&next.text) => {
+                    parse_sequence(lines, pos, indent)?
+                }
+                _ => parse_nested(lines, pos, indent)?,
+            }
+        } else {
+            let value = scalar(rest).map_err(|e| format!("line {}: {}", number, e))?;
+            *pos += 1;
+            value
+        };
+        if map.insert(key.clone(), value).is_some() {
+            return Err(format!("line {}: duplicate key `{}`", number, key));
+        }
+    }
+    Ok(Value::Object(map))
+}
+
+fn parse_nested(lines: &mut Vec<Line>, pos: &mut usize, parent: usize) -> Result<Value, String> {
+    match lines.get(*pos) {
+        Some(next) if next.indent > parent => {
+            let indent = next.indent;
+            parse_block(lines, pos, indent)
+        }
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Splits `key: value` at the first colon followed by a space or end of line,
+/// ignoring colons inside quotes (so `http://x: y` keys work).
+fn split_key(text: &str) -> Option<(&str, &str)> {
+    let mut quote = None;
+    let bytes = text.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        match (quote, b) {
+            (None, b'"') | (None, b'\'') => quote = Some(b),
+            (Some(q), b) if q == b => quote = None,
+            (None, b':') if bytes.get(i + 1).is_none_or(|n| *n == b' ') => {
+                return Some((text[..i].trim(), text[i + 1..].trim()));
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = b' ';
+    for (i, &b) in line.as_bytes().iter().enumerate() {
+        match (quote, b) {
+            (None, b'"') | (None, b'\'') => quote = Some(b),
+            (Some(q), b) if q == b => quote = None,
+            (None, b'#') if previous == b' ' || previous == b'\t' => return &line[..i],
+            _ => (),
+        }
+        previous = b;
+    }
+    line
+}
+
+fn scalar(text: &str) -> Result<Value, String> {
+    let text = text.trim();
+    if text.starts_with('"') {
+        return serde_json::from_str(text).map_err(|e| format!("bad quoted string: {}", e));
+    }
+    if let Some(inner) = text.strip_prefix('\'') {
+        return match inner.strip_suffix('\'') {
+            Some(inner) => Ok(Value::String(inner.replace("''", "'"))),
+            None => Err("unterminated quoted string".to_string()),
+        };
+    }
+    if text.starts_with('&') || text.starts_with('*') || text.starts_with('!') {
+        return Err(format!(
+            "anchors, aliases and tags are not supported: `{}`",
+            text
+        ));
+    }
+    if text.starts_with('[') || text.starts_with('{') {
+        if let Ok(value) = serde_json::from_str(text) {
+            return Ok(value);
+        }
+        if let Some(inner) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            return inner
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(scalar)
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array);
+        }
+        if let Some(inner) = text.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+            let mut map = Map::new();
+            for entry in inner.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let (key, value) = split_key(entry)
+                    .ok_or_else(|| format!("expected `key: value` in `{}`", text))?;
+                map.insert(
+                    key.trim_matches(|c| c == '"' || c == '\'').to_string(),
+                    scalar(value)?,
+                );
+            }
+            return Ok(Value::Object(map));
+        }
+        return Err(format!("unsupported flow collection `{}`", text));
+    }
+    Ok(match text {
+        "" | "~" | "null" | "Null" | "NULL" => Value::Null,
+        "true" | "True" | "TRUE" => Value::Bool(true),
+        "false" | "False" | "FALSE" => Value::Bool(false),
+        _ => plain_number(text).unwrap_or_else(|| Value::String(text.to_string())),
+    })
+}
+
+fn plain_number(text: &str) -> Option<Value> {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    if !digits.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    if let Ok(n) = text.parse::<i64>() {
+        return Some(Value::from(n));
+    }
+    text.parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+        .map(Value::Number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_nested_documents() {
+        let doc = r#"
+# claim mapping
+user_id: sub
+profile:
+  name: "Jane # not a comment"
+  tags: [a, b]
+roles:
+  - admin
+  - from: realm_access.roles
+    type: array
+http://schemas/role: 'it''s'
+count: 3
+"#;
+        assert_eq!(
+            parse(doc).unwrap(),
+            json!({
+                "user_id": "sub",
+                "profile": {"name": "Jane # not a comment", "tags": ["a", "b"]},
+                "roles": ["admin", {"from": "realm_access.roles", "type": "array"}],
+                "http://schemas/role": "it's",
+                "count": 3,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_indentation() {
+        assert!(parse("a: 1\n   b: 2\n").is_err());
+        assert!(parse("a: 1\na: 2\n").is_err());
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        let cases = [
+            (
+                "a:\n\tb: 1\n",
+                "line 2: tabs are not allowed for indentation",
+            ),
+            ("a: 1\n  b: 2\n", "line 2: unexpected indentation"),
+            ("  a: 1\nb: 2\n", "line 2: unexpected indentation"),
+            ("a: 1\njust text\n", "line 2: expected `key: value`"),
+            ("a: 1\na: 2\n", "line 2: duplicate key `a`"),
+            ("a: 'open\n", "line 1: unterminated quoted string"),
+            (
+                "a: *base\n",
+                "line 1: anchors, aliases and tags are not supported: `*base`",
+            ),
+            (
+                "- !tag x\n",
+                "line 1: anchors, aliases and tags are not supported: `!tag x`",
+            ),
+            ("a: {b}\n", "line 1: expected `key: value` in `{b}`"),
+            ("a: [b\n", "line 1: unsupported flow collection `[b`"),
+        ];
+        for (doc, error) in cases {
+            assert_eq!(parse(doc).unwrap_err(), error, "{:?}", doc);
+        }
+        assert!(parse("a: \"open\n")
+            .unwrap_err()
+            .starts_with("line 1: bad quoted string: "));
+    }
+}